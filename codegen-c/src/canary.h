@@ -0,0 +1,82 @@
+/*
+ * Runtime support for C generated by the Canary compiler.
+ *
+ * Everything here is prefixed with `cy_` or `CY_`, which generated code
+ * never uses for names from the source.
+ */
+#ifndef CANARY_H
+#define CANARY_H
+
+#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#if defined(__GNUC__) || defined(__clang__)
+#define CY_NORETURN __attribute__((noreturn))
+#else
+#define CY_NORETURN
+#endif
+
+/*
+ * Canary integers wrap on overflow, which signed arithmetic in C must not
+ * do. These compute on `uint64_t`, where wrapping is defined, and convert
+ * the result back to `T`, which every two's complement compiler does by
+ * keeping the low bits.
+ */
+#define CY_ADD(T, a, b) ((T)((uint64_t)(a) + (uint64_t)(b)))
+#define CY_SUB(T, a, b) ((T)((uint64_t)(a) - (uint64_t)(b)))
+#define CY_MUL(T, a, b) ((T)((uint64_t)(a) * (uint64_t)(b)))
+#define CY_NEG(T, a) ((T)(0 - (uint64_t)(a)))
+
+#define CY_INFINITY (1.0 / 0.0)
+#define CY_NAN (0.0 / 0.0)
+
+/* Reports a runtime error at `at`, a `file:line:col` location, and exits. */
+CY_NORETURN static inline void cy_runtime_error(const char *at, const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "%s: runtime error: %s\n", at, message);
+    exit(EXIT_FAILURE);
+}
+
+/* The states of a global, which is set the first time it is used. */
+enum { CY_UNSET, CY_SETTING, CY_SET };
+
+/*
+ * Whether the global in `state` still has to be set, in which case it is
+ * marked as being set. Reading it while it is being set, at `at`, is a
+ * runtime error.
+ */
+static inline bool cy_begin_init(unsigned char *state, const char *at) {
+    if (*state == CY_SETTING) {
+        cy_runtime_error(at, "global read during its own initialization");
+    }
+    if (*state == CY_SET) {
+        return false;
+    }
+    *state = CY_SETTING;
+    return true;
+}
+
+/* Narrower integers divide as 64-bit ones and convert the result back. */
+static inline int64_t cy_div_i64(int64_t a, int64_t b, const char *at) {
+    if (b == 0) {
+        cy_runtime_error(at, "division by zero");
+    }
+    /* The one quotient that overflows wraps, like the other operators. */
+    if (b == -1) {
+        return CY_NEG(int64_t, a);
+    }
+    return a / b;
+}
+
+static inline uint64_t cy_div_u64(uint64_t a, uint64_t b, const char *at) {
+    if (b == 0) {
+        cy_runtime_error(at, "division by zero");
+    }
+    return a / b;
+}
+
+#endif
@@ -0,0 +1,1103 @@
+//! C backend.
+//!
+//! Lowers Canary's mid-level IR to portable C99, which any C compiler turns
+//! into a native binary, so whatever the IR passes did shows up in the
+//! generated code. Generated files include the small runtime header
+//! [`RUNTIME`], which must be next to them as `canary.h`.
+//!
+//! Structs are C structs, and enums are tagged unions: an `int32_t` tag and
+//! a union holding a struct of payload fields per variant. Pointers are C
+//! pointers, function values are function pointers with a typedef per
+//! signature, and strings are `const char *`. `printf` is the C one, its
+//! placeholders turned into the matching conversion specifiers.
+//!
+//! A function is a sequence of labelled statements, one label per block.
+//! SSA values become variables, assigned once where they are defined,
+//! except that constants, slots, globals and functions are written out
+//! where they are used. A jump assigns the parameters of its target and
+//! `goto`s it. Integer arithmetic goes through runtime macros that wrap
+//! instead of overflowing, and division through runtime functions that
+//! report division by zero.
+//!
+//! C has no tail calls, so functions that tail call each other are merged
+//! into one C function that starts with a `switch` on the member to run,
+//! and tail calls within it assign the callee's parameters and `goto` its
+//! entry. Each member is still a C function of its own that calls the
+//! merged one. Other tail calls, through function values or to functions
+//! outside the group, are ordinary calls, and unlike in the interpreter,
+//! ordinary calls nest as deep as the C stack allows.
+//!
+//! Like in the interpreter, each global is set the first time it is used,
+//! by a function of its own, `cy_init_<name>`, which reports reading the
+//! global while setting it as a runtime error. The C `main` returns the
+//! status the Canary `main` returns.
+
+mod types;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use ir::cfg::{TailGroup, reverse_postorder, tail_call_groups};
+use ir::{
+    BinOp, BlockId, Callee, Const, FuncId, Function, InstKind, Module, Signature, Target,
+    Terminator, Type, UnOp, Value,
+};
+use lexer::token::Span;
+use sema::format::{self, Piece, Spec};
+use sema::types::{FloatTy, IntTy};
+
+use types::ident;
+
+/// The runtime header, which generated files include as `canary.h`.
+pub const RUNTIME: &str = include_str!("canary.h");
+
+/// Lowers `module` to a C translation unit. Runtime errors name the file
+/// the module was lowered from. The module must be well formed.
+pub fn emit(module: &Module) -> String {
+    let mut unit = Unit {
+        module,
+        taken: HashSet::new(),
+        struct_names: Vec::new(),
+        field_names: Vec::new(),
+        enum_names: Vec::new(),
+        variant_names: Vec::new(),
+        function_names: Vec::new(),
+        global_names: Vec::new(),
+        groups: Vec::new(),
+        fn_types: HashMap::new(),
+        typedefs: String::new(),
+        functions: String::new(),
+        prototypes: String::new(),
+    };
+
+    for def in &module.structs {
+        let name = unit.unique(&def.name);
+        unit.struct_names.push(name);
+        let names = members(def.fields.iter().map(|f| f.name.as_str()));
+        unit.field_names.push(names);
+    }
+    for def in &module.enums {
+        let name = unit.unique(&def.name);
+        unit.enum_names.push(name);
+        let mut names = vec![members(def.variants.iter().map(|v| v.name.as_str()))];
+        for variant in &def.variants {
+            names.push(members(variant.fields.iter().map(|f| f.name.as_str())));
+        }
+        unit.variant_names.push(names);
+    }
+    for function in &module.functions {
+        let name = unit.unique(&function.name);
+        unit.function_names.push(name);
+    }
+    for global in &module.globals {
+        let name = unit.unique(&global.name);
+        unit.global_names.push(name);
+    }
+    unit.groups = tail_call_groups(module)
+        .into_iter()
+        .map(|tail| {
+            let merged = tail.is_merged().then(|| {
+                let first = &unit.function_names[tail.members[0].0 as usize];
+                unit.unique(&format!("{first}_group"))
+            });
+            Group { tail, merged }
+        })
+        .collect();
+
+    let types: Vec<Type> = (0..module.structs.len())
+        .map(|i| Type::Struct(ir::StructId(i as u32)))
+        .chain((0..module.enums.len()).map(|i| Type::Enum(ir::EnumId(i as u32))))
+        .collect();
+    let mut definitions = String::new();
+    let mut defined = HashSet::new();
+    for ty in &types {
+        unit.define(ty, &mut defined, &mut definitions);
+    }
+
+    let mut globals = String::new();
+    for (global, name) in module.globals.iter().zip(unit.global_names.clone()) {
+        if let Some(ty) = &global.ty {
+            let ty = unit.ty(ty);
+            writeln!(globals, "static {};", decl(&ty, &name)).unwrap();
+        }
+        writeln!(globals, "static unsigned char cy_state_{name};").unwrap();
+    }
+
+    for group in 0..unit.groups.len() {
+        unit.group(group);
+    }
+    for index in 0..module.globals.len() {
+        unit.init(index);
+    }
+    if let Some(main) = module.main {
+        unit.entry(main);
+    }
+
+    let mut out = format!(
+        "/* Generated by the Canary compiler from {}. */\n#include \"canary.h\"\n",
+        module.source.replace("*/", "*_/")
+    );
+    if !types.is_empty() {
+        out.push('\n');
+        for ty in &types {
+            let name = unit.ty(ty);
+            writeln!(out, "typedef struct {name} {name};").unwrap();
+        }
+    }
+    for section in [&unit.typedefs, &definitions, &globals, &unit.prototypes] {
+        if !section.is_empty() {
+            out.push('\n');
+            out.push_str(section);
+        }
+    }
+    out.push_str(&unit.functions);
+    out
+}
+
+/// C names for the members of a struct or union, in order.
+fn members<'n>(names: impl Iterator<Item = &'n str>) -> Vec<String> {
+    let mut taken = HashSet::new();
+    names.map(|name| unique(&mut taken, name)).collect()
+}
+
+/// `ident(name)`, with a number added if `taken` already has it.
+fn unique(taken: &mut HashSet<String>, name: &str) -> String {
+    let base = ident(name);
+    let mut candidate = base.clone();
+    let mut n = 1;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{base}_{n}");
+        n += 1;
+    }
+    candidate
+}
+
+/// `name` declared with the C type `ty`, which is always written in
+/// front of the name.
+fn decl(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{ty}{name}")
+    } else {
+        format!("{ty} {name}")
+    }
+}
+
+/// The lvalue that `pointer`, a C expression, points to.
+fn deref(pointer: &str) -> String {
+    match pointer.strip_prefix('&') {
+        Some(place) => place.to_string(),
+        None => format!("(*{pointer})"),
+    }
+}
+
+fn c_op(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+    }
+}
+
+struct Group {
+    tail: TailGroup,
+    /// The C function the members are merged into, if there are several.
+    merged: Option<String>,
+}
+
+/// The translation unit being written.
+struct Unit<'a> {
+    module: &'a Module,
+    /// Every name at file scope.
+    taken: HashSet<String>,
+    struct_names: Vec<String>,
+    /// The C name of each field of each struct.
+    field_names: Vec<Vec<String>>,
+    enum_names: Vec<String>,
+    /// For each enum, the union member of each variant, then the names of
+    /// the payload fields of each variant.
+    variant_names: Vec<Vec<Vec<String>>>,
+    function_names: Vec<String>,
+    global_names: Vec<String>,
+    groups: Vec<Group>,
+    /// The typedef of each function pointer type, by its C signature.
+    fn_types: HashMap<String, String>,
+    typedefs: String,
+    prototypes: String,
+    functions: String,
+}
+
+impl<'a> Unit<'a> {
+    fn unique(&mut self, name: &str) -> String {
+        unique(&mut self.taken, name)
+    }
+
+    /// The C type of `ty`, written in front of a declared name.
+    fn ty(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int(int) => types::int_name(*int).to_string(),
+            Type::Float(float) => types::float_name(*float).to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Str => "const char *".to_string(),
+            Type::Struct(id) => self.struct_names[id.0 as usize].clone(),
+            Type::Enum(id) => self.enum_names[id.0 as usize].clone(),
+            Type::Ptr(inner) => {
+                let inner = self.ty(inner);
+                decl(&inner, "*")
+            }
+            Type::Fn(sig) => self.fn_type(sig),
+        }
+    }
+
+    /// The C return type for `ret`.
+    fn ret(&mut self, ret: Option<&Type>) -> String {
+        match ret {
+            Some(ty) => self.ty(ty),
+            None => "void".to_string(),
+        }
+    }
+
+    /// The typedef for pointers to functions with signature `sig`.
+    fn fn_type(&mut self, sig: &Signature) -> String {
+        let ret = self.ret(sig.ret.as_ref());
+        let params = self.params(&sig.params).join(", ");
+        let signature = format!("{ret}({params})");
+        if let Some(name) = self.fn_types.get(&signature) {
+            return name.clone();
+        }
+        let name = format!("cy_fn_{}", self.fn_types.len());
+        writeln!(
+            self.typedefs,
+            "typedef {} (*{name})({params});",
+            ret.trim_end()
+        )
+        .unwrap();
+        self.fn_types.insert(signature, name.clone());
+        name
+    }
+
+    /// The C parameter types for `params`, `void` if there are none.
+    fn params(&mut self, params: &[Type]) -> Vec<String> {
+        if params.is_empty() {
+            return vec!["void".to_string()];
+        }
+        params.iter().map(|ty| self.ty(ty)).collect()
+    }
+
+    /// A string literal naming where `span` is, for runtime errors.
+    fn at(&self, span: Span) -> String {
+        types::string_literal(&format!(
+            "{}:{}:{}",
+            self.module.source, span.line, span.col
+        ))
+    }
+
+    /// Writes the definition of a struct or enum, after those it contains.
+    fn define(&mut self, ty: &Type, defined: &mut HashSet<Type>, out: &mut String) {
+        if !defined.insert(ty.clone()) {
+            return;
+        }
+        let module = self.module;
+        let fields: Vec<&Type> = match ty {
+            Type::Struct(id) => module.structs[id.0 as usize]
+                .fields
+                .iter()
+                .map(|f| &f.ty)
+                .collect(),
+            Type::Enum(id) => module.enums[id.0 as usize]
+                .variants
+                .iter()
+                .flat_map(|v| v.fields.iter().map(|f| &f.ty))
+                .collect(),
+            _ => unreachable!("{ty:?} has no definition"),
+        };
+        for field in fields {
+            if let Type::Struct(_) | Type::Enum(_) = field {
+                self.define(field, defined, out);
+            }
+        }
+
+        let name = self.ty(ty);
+        let mut body = String::new();
+        match ty {
+            Type::Struct(id) => {
+                let def = &module.structs[id.0 as usize];
+                let names = self.field_names[id.0 as usize].clone();
+                for (field, c_name) in def.fields.iter().zip(&names) {
+                    let ty = self.ty(&field.ty);
+                    writeln!(body, "    {};", decl(&ty, c_name)).unwrap();
+                }
+                // C does not allow empty structs.
+                if body.is_empty() {
+                    body.push_str("    char unused;\n");
+                }
+            }
+            Type::Enum(id) => {
+                body.push_str("    int32_t tag;\n");
+                let names = self.variant_names[id.0 as usize].clone();
+                let mut union = String::new();
+                for (index, variant) in module.enums[id.0 as usize].variants.iter().enumerate() {
+                    let mut payload = String::new();
+                    for (field, c_name) in variant.fields.iter().zip(&names[index + 1]) {
+                        let ty = self.ty(&field.ty);
+                        writeln!(payload, "            {};", decl(&ty, c_name)).unwrap();
+                    }
+                    if !payload.is_empty() {
+                        writeln!(
+                            union,
+                            "        struct {{\n{payload}        }} {};",
+                            names[0][index]
+                        )
+                        .unwrap();
+                    }
+                }
+                if !union.is_empty() {
+                    writeln!(body, "    union {{\n{union}    }} as;").unwrap();
+                }
+            }
+            _ => unreachable!(),
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        writeln!(out, "struct {name} {{\n{body}}};").unwrap();
+    }
+
+    /// Writes the C functions of a group: the function itself, or the
+    /// merged function and an entry point for each member.
+    fn group(&mut self, index: usize) {
+        let module = self.module;
+        let tail = self.groups[index].tail.clone();
+        let merged = self.groups[index].merged.clone();
+        let members = &tail.members;
+        let mut f = FnGen::new(self, tail.clone());
+
+        let mut params = Vec::new();
+        let mut param_types = Vec::new();
+        if merged.is_some() {
+            f.names.insert("entry".to_string());
+            params.push("int entry".to_string());
+            param_types.push("int".to_string());
+        }
+        for &id in members {
+            let names = f.name_member(module.function(id));
+            if merged.is_some() {
+                f.labels.insert(names.labels[0].clone());
+            }
+            f.names_of.push(names);
+        }
+        for (member, param, ty) in tail.params(module) {
+            let c_ty = f.unit.ty(ty);
+            params.push(decl(&c_ty, &f.names_of[member].values[param.0 as usize]));
+            param_types.push(c_ty);
+        }
+
+        if merged.is_some() {
+            f.line("switch (entry) {");
+            for i in 1..members.len() {
+                let label = f.names_of[i].labels[0].clone();
+                f.line(&format!("case {i}: goto {label};"));
+                f.used_labels.insert(label);
+            }
+            f.line("}");
+        }
+        for (i, &id) in members.iter().enumerate() {
+            f.function(i, module.function(id));
+        }
+
+        let ret = f.unit.ret(module.function(members[0]).sig.ret.as_ref());
+        let name = merged
+            .clone()
+            .unwrap_or_else(|| f.unit.function_names[members[0].0 as usize].clone());
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        let types = if param_types.is_empty() {
+            "void".to_string()
+        } else {
+            param_types.join(", ")
+        };
+        writeln!(f.unit.prototypes, "static {}({types});", decl(&ret, &name)).unwrap();
+        let header = format!("static {}({params})", decl(&ret, &name));
+        let names_of: Vec<Names> = f.names_of.drain(..).collect();
+        f.unused_labels(&names_of);
+        f.finish(&header);
+
+        let Some(merged) = merged else {
+            return;
+        };
+        for (i, &id) in members.iter().enumerate() {
+            let function = module.function(id);
+            let mut params = Vec::new();
+            let mut args = vec![i.to_string()];
+            for (member, param, ty) in tail.params(module) {
+                let name = &names_of[member].values[param.0 as usize];
+                if member == i {
+                    let c_ty = self.ty(ty);
+                    params.push(decl(&c_ty, name));
+                    args.push(name.clone());
+                } else {
+                    args.push(self.zero(ty));
+                }
+            }
+            let ret = self.ret(function.sig.ret.as_ref());
+            let name = self.function_names[id.0 as usize].clone();
+            let types = self.params(&function.sig.params).join(", ");
+            writeln!(self.prototypes, "static {}({types});", decl(&ret, &name)).unwrap();
+            let call = format!("{merged}({})", args.join(", "));
+            let body = match function.sig.ret {
+                Some(_) => format!("return {call};"),
+                None => format!("{call};"),
+            };
+            let params = if params.is_empty() {
+                "void".to_string()
+            } else {
+                params.join(", ")
+            };
+            writeln!(
+                self.functions,
+                "\nstatic {}({params}) {{\n    {body}\n}}",
+                decl(&ret, &name)
+            )
+            .unwrap();
+        }
+    }
+
+    /// `cy_init_<name>`, which sets global `index` unless it is set
+    /// already. `at` is where the global is used.
+    fn init(&mut self, index: usize) {
+        let global = &self.module.globals[index];
+        let name = &self.global_names[index];
+        let init = &self.function_names[global.init.0 as usize];
+        let set = match global.ty {
+            Some(_) => format!("{name} = {init}();"),
+            None => format!("{init}();"),
+        };
+        writeln!(
+            self.prototypes,
+            "static void cy_init_{name}(const char *at);"
+        )
+        .unwrap();
+        writeln!(
+            self.functions,
+            "\nstatic void cy_init_{name}(const char *at) {{\n    \
+             if (cy_begin_init(&cy_state_{name}, at)) {{\n        \
+             {set}\n        cy_state_{name} = CY_SET;\n    }}\n}}"
+        )
+        .unwrap();
+    }
+
+    /// The C `main`: runs the Canary `main` and returns its status.
+    fn entry(&mut self, main: FuncId) {
+        let name = &self.function_names[main.0 as usize];
+        let mut body = String::new();
+        match self.module.function(main).sig.ret {
+            Some(Type::Int(_)) => writeln!(body, "    return (int){name}();").unwrap(),
+            _ => writeln!(body, "    {name}();\n    return 0;").unwrap(),
+        }
+        writeln!(self.functions, "\nint main(void) {{\n{body}}}").unwrap();
+    }
+
+    /// A value of type `ty` with every bit zero.
+    fn zero(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Struct(_) | Type::Enum(_) => format!("({}){{0}}", self.ty(ty)),
+            Type::Bool => "false".to_string(),
+            _ => "0".to_string(),
+        }
+    }
+}
+
+/// The C names of the values and blocks of one member of a group.
+struct Names {
+    values: Vec<String>,
+    labels: Vec<String>,
+}
+
+/// Lowers one C function: a function, or the members of a group.
+struct FnGen<'u, 'a> {
+    unit: &'u mut Unit<'a>,
+    /// Names in use in the function, including every name at file scope,
+    /// which locals must not hide.
+    names: HashSet<String>,
+    labels: HashSet<String>,
+    /// The names of each member, in order.
+    names_of: Vec<Names>,
+    /// The group being lowered.
+    group: TailGroup,
+    /// The member being lowered.
+    member: usize,
+    /// C expressions for values that are the same wherever they are used:
+    /// constants, slots, globals and functions, and fields of those.
+    exprs: HashMap<Value, String>,
+    /// Values defined and used in one block only, declared where they are
+    /// defined; the others are declared at the top.
+    local: HashSet<Value>,
+    used_labels: HashSet<String>,
+    decls: String,
+    body: String,
+}
+
+impl<'u, 'a> FnGen<'u, 'a> {
+    fn new(unit: &'u mut Unit<'a>, group: TailGroup) -> Self {
+        let names = unit.taken.clone();
+        Self {
+            unit,
+            names,
+            labels: HashSet::new(),
+            names_of: Vec::new(),
+            group,
+            member: 0,
+            exprs: HashMap::new(),
+            local: HashSet::new(),
+            used_labels: HashSet::new(),
+            decls: String::new(),
+            body: String::new(),
+        }
+    }
+
+    /// Removes the labels of `names` that no `goto` goes to.
+    fn unused_labels(&mut self, names: &[Names]) {
+        for label in names.iter().flat_map(|names| &names.labels) {
+            if !self.used_labels.contains(label) {
+                self.body = self.body.replacen(&format!("{label}:;\n"), "", 1);
+            }
+        }
+    }
+
+    fn finish(self, header: &str) {
+        writeln!(
+            self.unit.functions,
+            "\n{header} {{\n{}{}}}",
+            self.decls, self.body
+        )
+        .unwrap();
+    }
+
+    /// Picks the names of the values and blocks of `id`.
+    fn name_member(&mut self, function: &Function) -> Names {
+        let values = (0..function.values.len())
+            .map(|n| unique(&mut self.names, &format!("v{n}")))
+            .collect();
+        let labels = (0..function.blocks.len())
+            .map(|n| unique(&mut self.labels, &format!("b{n}")))
+            .collect();
+        Names { values, labels }
+    }
+
+    /// Emits a statement.
+    fn line(&mut self, text: &str) {
+        writeln!(self.body, "    {text}").unwrap();
+    }
+
+    fn name(&self, value: Value) -> &str {
+        &self.names_of[self.member].values[value.0 as usize]
+    }
+
+    fn label(&self, block: BlockId) -> String {
+        self.names_of[self.member].labels[block.0 as usize].clone()
+    }
+
+    /// A C expression for `value`.
+    fn value(&self, value: Value) -> String {
+        match self.exprs.get(&value) {
+            Some(expr) => expr.clone(),
+            None => self.name(value).to_string(),
+        }
+    }
+
+    fn values(&self, values: &[Value]) -> Vec<String> {
+        values.iter().map(|v| self.value(*v)).collect()
+    }
+
+    /// Lowers the blocks of member `member`, `func`.
+    fn function(&mut self, member: usize, func: &'a Function) {
+        self.member = member;
+        self.exprs.clear();
+        self.local.clear();
+
+        // Where each value is defined and used decides where it is declared.
+        let order = reverse_postorder(func);
+        let mut defined_in = HashMap::new();
+        let mut used_in: HashMap<Value, HashSet<BlockId>> = HashMap::new();
+        for &block in &order {
+            let b = func.block(block);
+            for inst in &b.insts {
+                if let Some(result) = inst.result {
+                    defined_in.insert(result, block);
+                }
+                for operand in inst.kind.operands() {
+                    used_in.entry(operand).or_default().insert(block);
+                }
+            }
+            for operand in b.term.operands() {
+                used_in.entry(operand).or_default().insert(block);
+            }
+        }
+        for (&value, &block) in &defined_in {
+            let uses = used_in.get(&value);
+            if uses.is_none_or(|uses| uses.iter().all(|b| *b == block)) {
+                self.local.insert(value);
+            }
+        }
+        let entry_params = &func.blocks[0].params;
+        for (n, ty) in func.values.iter().enumerate() {
+            let value = Value(n as u32);
+            let used = used_in.contains_key(&value);
+            let declared = match defined_in.get(&value) {
+                Some(_) => used && !self.local.contains(&value),
+                // Parameters of the entry are the function's, and those of
+                // other blocks are assigned by every jump there.
+                None => !entry_params.contains(&value),
+            };
+            if declared && !self.is_stable(func, value) {
+                let c_ty = self.unit.ty(ty);
+                let name = self.name(value).to_string();
+                writeln!(self.decls, "    {};", decl(&c_ty, &name)).unwrap();
+            }
+        }
+
+        for (i, &block) in order.iter().enumerate() {
+            let label = self.label(block);
+            writeln!(self.body, "{label}:;").unwrap();
+            let b = func.block(block);
+            for inst in &b.insts {
+                self.inst(func, inst, &used_in);
+            }
+            let next = order.get(i + 1).copied();
+            self.terminator(func, &b.term, next);
+        }
+    }
+
+    /// Whether `value` is written out where it is used instead of being a
+    /// variable.
+    fn is_stable(&self, func: &Function, value: Value) -> bool {
+        func.blocks.iter().flat_map(|b| &b.insts).any(|inst| {
+            inst.result == Some(value)
+                && match &inst.kind {
+                    InstKind::Const(_)
+                    | InstKind::Func(_)
+                    | InstKind::Slot
+                    | InstKind::Global { .. } => true,
+                    InstKind::FieldPtr { ptr, .. } => self.is_stable(func, *ptr),
+                    _ => false,
+                }
+        })
+    }
+
+    fn inst(
+        &mut self,
+        func: &Function,
+        inst: &ir::Inst,
+        used_in: &HashMap<Value, HashSet<BlockId>>,
+    ) {
+        let used = inst.result.is_some_and(|r| used_in.contains_key(&r));
+        let ty = inst.result.map(|r| func.ty(r).clone());
+        let expr = match &inst.kind {
+            InstKind::Const(constant) => {
+                let text = match (constant, &ty) {
+                    (Const::Int(value), Some(Type::Int(int))) => types::int_literal(*int, *value),
+                    (Const::Float(value), Some(Type::Float(float))) => {
+                        types::float_literal(*float, *value)
+                    }
+                    (Const::Bool(value), _) => value.to_string(),
+                    (Const::Str(value), _) => types::string_literal(value),
+                    _ => unreachable!("{constant:?} of type {ty:?}"),
+                };
+                return self.stable(inst.result, text);
+            }
+            InstKind::Func(id) => {
+                let name = self.unit.function_names[id.0 as usize].clone();
+                return self.stable(inst.result, name);
+            }
+            InstKind::Slot => {
+                let Some(Type::Ptr(pointee)) = &ty else {
+                    unreachable!("slot of type {ty:?}");
+                };
+                let name = unique(&mut self.names, "slot");
+                let c_ty = self.unit.ty(pointee);
+                writeln!(self.decls, "    {};", decl(&c_ty, &name)).unwrap();
+                return self.stable(inst.result, format!("&{name}"));
+            }
+            InstKind::Global { id, span } => {
+                let name = self.unit.global_names[id.0 as usize].clone();
+                let at = self.unit.at(*span);
+                self.line(&format!("cy_init_{name}({at});"));
+                return self.stable(inst.result, format!("&{name}"));
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                let Type::Ptr(pointee) = func.ty(*ptr) else {
+                    unreachable!("field pointer through a non-pointer");
+                };
+                let Type::Struct(id) = **pointee else {
+                    unreachable!("field pointer into a non-struct");
+                };
+                let field = &self.unit.field_names[id.0 as usize][*index as usize];
+                let ptr_expr = self.value(*ptr);
+                let text = match ptr_expr.strip_prefix('&') {
+                    Some(place) => format!("&{place}.{field}"),
+                    None => format!("&{ptr_expr}->{field}"),
+                };
+                if self.exprs.contains_key(ptr) {
+                    return self.stable(inst.result, text);
+                }
+                text
+            }
+            InstKind::Unary { op, arg } => {
+                let arg = self.value(*arg);
+                match (op, &ty) {
+                    (UnOp::Neg, Some(Type::Float(_))) => format!("(-{arg})"),
+                    (UnOp::Neg, Some(ty)) => format!("CY_NEG({}, {arg})", self.unit.ty(ty)),
+                    (UnOp::Not, _) => format!("(!{arg})"),
+                    (UnOp::Neg, None) => unreachable!("negation without a result"),
+                }
+            }
+            InstKind::Binary { op, lhs, rhs, span } => self.binary(
+                *op,
+                func.ty(*lhs),
+                &self.value(*lhs),
+                &self.value(*rhs),
+                *span,
+            ),
+            InstKind::Call { callee, args, .. } => self.call(callee, args),
+            InstKind::Printf { format, args } => {
+                self.printf(func, format, args);
+                return;
+            }
+            InstKind::Struct(fields) => {
+                let Some(Type::Struct(id)) = &ty else {
+                    unreachable!("struct of type {ty:?}");
+                };
+                let names = &self.unit.field_names[id.0 as usize];
+                let inits: Vec<String> = fields
+                    .iter()
+                    .zip(names)
+                    .map(|(value, name)| format!(".{name} = {}", self.value(*value)))
+                    .collect();
+                let c_ty = self.unit.ty(ty.as_ref().unwrap());
+                if inits.is_empty() {
+                    format!("({c_ty}){{0}}")
+                } else {
+                    format!("({c_ty}){{ {} }}", inits.join(", "))
+                }
+            }
+            InstKind::Variant { index, fields } => {
+                let Some(Type::Enum(id)) = &ty else {
+                    unreachable!("variant of type {ty:?}");
+                };
+                let names = &self.unit.variant_names[id.0 as usize];
+                let variant = &names[0][*index as usize];
+                let inits: Vec<String> = fields
+                    .iter()
+                    .zip(&names[*index as usize + 1])
+                    .map(|(value, name)| format!(", .as.{variant}.{name} = {}", self.value(*value)))
+                    .collect();
+                let c_ty = self.unit.ty(ty.as_ref().unwrap());
+                format!("({c_ty}){{ .tag = {index}{} }}", inits.concat())
+            }
+            InstKind::Field { base, index } => {
+                let Type::Struct(id) = func.ty(*base) else {
+                    unreachable!("field of a non-struct");
+                };
+                let field = &self.unit.field_names[id.0 as usize][*index as usize];
+                format!("{}.{field}", self.value(*base))
+            }
+            InstKind::Tag(base) => format!("(uint32_t){}.tag", self.value(*base)),
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => {
+                let Type::Enum(id) = func.ty(*base) else {
+                    unreachable!("payload of a non-enum");
+                };
+                let names = &self.unit.variant_names[id.0 as usize];
+                format!(
+                    "{}.as.{}.{}",
+                    self.value(*base),
+                    names[0][*variant as usize],
+                    names[*variant as usize + 1][*field as usize]
+                )
+            }
+            InstKind::Load(ptr) => deref(&self.value(*ptr)),
+            InstKind::Store { ptr, value } => {
+                let line = format!("{} = {};", deref(&self.value(*ptr)), self.value(*value));
+                self.line(&line);
+                return;
+            }
+        };
+
+        match inst.result {
+            Some(result) if used => {
+                let name = self.name(result).to_string();
+                if self.local.contains(&result) {
+                    let c_ty = self.unit.ty(ty.as_ref().unwrap());
+                    self.line(&format!("{} = {expr};", decl(&c_ty, &name)));
+                } else {
+                    self.line(&format!("{name} = {expr};"));
+                }
+            }
+            // Results nobody uses are only computed for their effects.
+            _ if inst.kind.is_pure() => {}
+            _ => self.line(&format!("{expr};")),
+        }
+    }
+
+    /// Records `text` as the expression for `result`.
+    fn stable(&mut self, result: Option<Value>, text: String) {
+        if let Some(result) = result {
+            self.exprs.insert(result, text);
+        }
+    }
+
+    /// `l op r` for operands of type `ty`.
+    fn binary(&mut self, op: BinOp, ty: &Type, l: &str, r: &str, span: Span) -> String {
+        let symbol = c_op(op);
+        if op.is_comparison() {
+            return match ty {
+                Type::Str => format!("(strcmp({l}, {r}) {symbol} 0)"),
+                _ => format!("({l} {symbol} {r})"),
+            };
+        }
+        let c_ty = self.unit.ty(ty);
+        match ty {
+            Type::Float(FloatTy::F64) => format!("({l} {symbol} {r})"),
+            // Rounds away the extra precision C may compute with.
+            Type::Float(_) => format!("(float)({l} {symbol} {r})"),
+            _ => match op {
+                BinOp::Add => format!("CY_ADD({c_ty}, {l}, {r})"),
+                BinOp::Sub => format!("CY_SUB({c_ty}, {l}, {r})"),
+                BinOp::Mul => format!("CY_MUL({c_ty}, {l}, {r})"),
+                BinOp::Div => {
+                    let int = match ty {
+                        Type::Int(int) => *int,
+                        _ => IntTy::I32,
+                    };
+                    let div = if int.is_signed() {
+                        "cy_div_i64"
+                    } else {
+                        "cy_div_u64"
+                    };
+                    let at = self.unit.at(span);
+                    let call = format!("{div}({l}, {r}, {at})");
+                    if int.bits() == 64 {
+                        call
+                    } else {
+                        format!("(({c_ty}){call})")
+                    }
+                }
+                _ => unreachable!("{op:?} is not arithmetic"),
+            },
+        }
+    }
+
+    fn call(&self, callee: &Callee, args: &[Value]) -> String {
+        let callee = match callee {
+            Callee::Direct(id) => self.unit.function_names[id.0 as usize].clone(),
+            Callee::Indirect(value) => self.value(*value),
+        };
+        format!("{callee}({})", self.values(args).join(", "))
+    }
+
+    fn terminator(&mut self, func: &Function, term: &Terminator, next: Option<BlockId>) {
+        match term {
+            Terminator::Jump(target) => {
+                for line in self.jump(func, target, next) {
+                    self.line(&line);
+                }
+            }
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let cond = self.value(*cond);
+                let then = self.jump(func, then, None);
+                match then.as_slice() {
+                    [line] => self.line(&format!("if ({cond}) {line}")),
+                    lines => {
+                        self.line(&format!("if ({cond}) {{"));
+                        for line in lines {
+                            self.line(&format!("    {line}"));
+                        }
+                        self.line("}");
+                    }
+                }
+                for line in self.jump(func, otherwise, next) {
+                    self.line(&line);
+                }
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                let int = match func.ty(*value) {
+                    Type::Int(int) => *int,
+                    _ => IntTy::I32,
+                };
+                let value = self.value(*value);
+                self.line(&format!("switch ({value}) {{"));
+                let arms = cases
+                    .iter()
+                    .map(|(n, target)| (format!("case {}:", types::int_literal(int, *n)), target))
+                    .chain([("default:".to_string(), default)]);
+                for (label, target) in arms {
+                    match self.jump(func, target, None).as_slice() {
+                        [line] => self.line(&format!("{label} {line}")),
+                        lines => {
+                            self.line(&format!("{label} {{"));
+                            for line in lines {
+                                self.line(&format!("    {line}"));
+                            }
+                            self.line("}");
+                        }
+                    }
+                }
+                self.line("}");
+            }
+            Terminator::Return(Some(value)) => {
+                let value = self.value(*value);
+                self.line(&format!("return {value};"));
+            }
+            Terminator::Return(None) => self.line("return;"),
+            Terminator::TailCall { callee, args } => {
+                if let Callee::Direct(id) = callee
+                    && let Some(member) = self.group.index_of(*id)
+                {
+                    let callee = self.unit.module.function(*id);
+                    let params: Vec<String> = callee.blocks[0]
+                        .params
+                        .iter()
+                        .map(|p| self.names_of[member].values[p.0 as usize].clone())
+                        .collect();
+                    let label = self.names_of[member].labels[0].clone();
+                    let values = self.values(args);
+                    for line in self.assign(func, &params, &values, args) {
+                        self.line(&line);
+                    }
+                    self.line(&format!("goto {label};"));
+                    self.used_labels.insert(label);
+                    return;
+                }
+                let call = self.call(callee, args);
+                match func.sig.ret {
+                    Some(_) => self.line(&format!("return {call};")),
+                    None => {
+                        self.line(&format!("{call};"));
+                        self.line("return;");
+                    }
+                }
+            }
+            Terminator::Trap { message, span } => {
+                let at = self.unit.at(*span);
+                let message = types::string_literal(message);
+                self.line(&format!("cy_runtime_error({at}, {message});"));
+            }
+            Terminator::Unreachable => self.line("abort();"),
+        }
+    }
+
+    /// The statements of a jump to `target`: they assign its parameters,
+    /// then `goto` it unless it is `next`, the block that follows.
+    fn jump(&mut self, func: &Function, target: &Target, next: Option<BlockId>) -> Vec<String> {
+        let params: Vec<String> = func
+            .block(target.block)
+            .params
+            .iter()
+            .map(|p| self.name(*p).to_string())
+            .collect();
+        let values = self.values(&target.args);
+        let mut lines = self.assign(func, &params, &values, &target.args);
+        if next != Some(target.block) {
+            let label = self.label(target.block);
+            lines.push(format!("goto {label};"));
+            self.used_labels.insert(label);
+        }
+        lines
+    }
+
+    /// Assignments of `values` to the variables `params`, all at once: a
+    /// value that an earlier assignment would change is copied first.
+    fn assign(
+        &mut self,
+        func: &Function,
+        params: &[String],
+        values: &[String],
+        args: &[Value],
+    ) -> Vec<String> {
+        let mut copies = Vec::new();
+        let mut assigns = Vec::new();
+        let mut assigned = HashSet::new();
+        for ((param, value), arg) in params.iter().zip(values).zip(args) {
+            if param == value {
+                continue;
+            }
+            let value = if assigned.contains(value) {
+                let copy = unique(&mut self.names, "arg");
+                let c_ty = self.unit.ty(func.ty(*arg));
+                copies.push(format!("{} = {value};", decl(&c_ty, &copy)));
+                copy
+            } else {
+                value.clone()
+            };
+            assigned.insert(param.clone());
+            assigns.push(format!("{param} = {value};"));
+        }
+        copies.extend(assigns);
+        copies
+    }
+
+    /// Lowers `printf` to the C one.
+    fn printf(&mut self, func: &Function, format: &str, args: &[Value]) {
+        let pieces = format::parse(format).expect("format string was checked");
+
+        // Literal text, then the `<inttypes.h>` macros that end it.
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut tys = args.iter().map(|arg| func.ty(*arg));
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(literal) => {
+                    text.push_str(&literal.replace('%', "%%"));
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let ty = tys.next().expect("format arguments were checked");
+            match (spec, ty) {
+                (Spec::Str, _) => text.push_str("%s"),
+                (Spec::Float, _) => text.push_str("%f"),
+                (Spec::Int, ty) => {
+                    let int = match ty {
+                        Type::Int(int) => *int,
+                        _ => IntTy::I32,
+                    };
+                    text.push('%');
+                    parts.push(types::string_literal(&text));
+                    parts.push(types::int_format(int).to_string());
+                    text.clear();
+                }
+            }
+        }
+        if !text.is_empty() || parts.is_empty() {
+            parts.push(types::string_literal(&text));
+        }
+
+        let mut call = format!("printf({}", parts.join(" "));
+        for value in self.values(args) {
+            call.push_str(", ");
+            call.push_str(&value);
+        }
+        call.push_str(");");
+        self.line(&call);
+    }
+}
@@ -0,0 +1,265 @@
+//! C names, types and literals.
+
+use sema::types::{FloatTy, IntTy};
+
+/// Names generated code may not use for anything from the source: C
+/// keywords, and what the headers included by the runtime declare that a
+/// program could plausibly clash with.
+const RESERVED: &[&str] = &[
+    // Keywords.
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "_Bool",
+    "_Complex",
+    "_Imaginary",
+    // Macros.
+    "bool",
+    "true",
+    "false",
+    "NULL",
+    "EOF",
+    "stdin",
+    "stdout",
+    "stderr",
+    "errno",
+    "EXIT_SUCCESS",
+    "EXIT_FAILURE",
+    "RAND_MAX",
+    "BUFSIZ",
+    // Functions.
+    "main",
+    "abort",
+    "abs",
+    "atexit",
+    "atof",
+    "atoi",
+    "atol",
+    "bsearch",
+    "calloc",
+    "clearerr",
+    "div",
+    "exit",
+    "fclose",
+    "feof",
+    "ferror",
+    "fflush",
+    "fgetc",
+    "fgets",
+    "fopen",
+    "fprintf",
+    "fputc",
+    "fputs",
+    "fread",
+    "free",
+    "freopen",
+    "fscanf",
+    "fseek",
+    "ftell",
+    "fwrite",
+    "getc",
+    "getchar",
+    "getenv",
+    "labs",
+    "ldiv",
+    "malloc",
+    "memchr",
+    "memcmp",
+    "memcpy",
+    "memmove",
+    "memset",
+    "perror",
+    "printf",
+    "putc",
+    "putchar",
+    "puts",
+    "qsort",
+    "rand",
+    "realloc",
+    "remove",
+    "rename",
+    "rewind",
+    "scanf",
+    "setbuf",
+    "snprintf",
+    "sprintf",
+    "srand",
+    "sscanf",
+    "strcat",
+    "strchr",
+    "strcmp",
+    "strcpy",
+    "strerror",
+    "strlen",
+    "strncmp",
+    "strncpy",
+    "strrchr",
+    "strstr",
+    "strtod",
+    "strtok",
+    "strtol",
+    "strtoul",
+    "system",
+    "tmpfile",
+    "ungetc",
+];
+
+/// `name` as a C identifier that cannot clash with C itself or with the
+/// runtime. Characters C does not allow, such as the `#` of macro hygiene
+/// or the `.` of method names, become `_`.
+pub fn ident(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    let lower = ident.to_ascii_lowercase();
+    if RESERVED.contains(&ident.as_str())
+        || lower.starts_with("cy_")
+        || ident.starts_with("__")
+        || ident.starts_with('_') && ident[1..].starts_with(|c: char| c.is_ascii_uppercase())
+        || ident.ends_with("_t")
+        || ["INT", "UINT", "PRI", "SCN"]
+            .iter()
+            .any(|p| ident.starts_with(p))
+    {
+        ident.push('_');
+    }
+    ident
+}
+
+pub fn int_name(int: IntTy) -> &'static str {
+    match int {
+        IntTy::I8 => "int8_t",
+        IntTy::I16 => "int16_t",
+        IntTy::I32 => "int32_t",
+        IntTy::I64 => "int64_t",
+        IntTy::U8 => "uint8_t",
+        IntTy::U16 => "uint16_t",
+        IntTy::U32 => "uint32_t",
+        IntTy::U64 => "uint64_t",
+    }
+}
+
+/// C has no 16-bit floats; `f16` is computed as `float`.
+pub fn float_name(float: FloatTy) -> &'static str {
+    match float {
+        FloatTy::F64 => "double",
+        _ => "float",
+    }
+}
+
+/// The `<inttypes.h>` macro that prints `int` with `printf`.
+pub fn int_format(int: IntTy) -> &'static str {
+    match int {
+        IntTy::I8 => "PRId8",
+        IntTy::I16 => "PRId16",
+        IntTy::I32 => "PRId32",
+        IntTy::I64 => "PRId64",
+        IntTy::U8 => "PRIu8",
+        IntTy::U16 => "PRIu16",
+        IntTy::U32 => "PRIu32",
+        IntTy::U64 => "PRIu64",
+    }
+}
+
+/// An integer constant of type `int`, stored like `ir::Const::Int`. The
+/// most negative value of a type has no literal of its own.
+pub fn int_literal(int: IntTy, value: i64) -> String {
+    if !int.is_signed() {
+        let value = value as u64;
+        return match int {
+            IntTy::U64 => format!("UINT64_C({value})"),
+            IntTy::U32 => format!("{value}u"),
+            _ => value.to_string(),
+        };
+    }
+    match int {
+        IntTy::I64 if value == i64::MIN => "INT64_MIN".to_string(),
+        IntTy::I64 => format!("INT64_C({value})"),
+        IntTy::I32 if value == i32::MIN as i64 => "INT32_MIN".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// A float constant of type `float`. Rust prints the shortest decimal that
+/// reads back as the same value, which is a valid C literal.
+pub fn float_literal(float: FloatTy, value: f64) -> String {
+    let single = float != FloatTy::F64;
+    let value = if single { value as f32 as f64 } else { value };
+    if value.is_nan() {
+        return "CY_NAN".to_string();
+    }
+    if value.is_infinite() {
+        let sign = if value < 0.0 { "-" } else { "" };
+        return format!("({sign}CY_INFINITY)");
+    }
+    let mut text = if single {
+        format!("{:?}", value as f32)
+    } else {
+        format!("{value:?}")
+    };
+    if single {
+        text.push('f');
+    }
+    if value < 0.0 {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+/// A C string literal holding the bytes of `value`. Anything but printable
+/// ASCII is an octal escape, which is always three digits so the next
+/// character cannot be taken for part of it.
+pub fn string_literal(value: &str) -> String {
+    let mut out = String::from("\"");
+    let mut bytes = value.bytes().peekable();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            // `??` starts a trigraph.
+            b'?' if bytes.peek() == Some(&b'?') => out.push_str("\\?"),
+            0x20..0x7f => out.push(b as char),
+            _ => out.push_str(&format!("\\{b:03o}")),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,1279 @@
+//! .NET CIL backend.
+//!
+//! Lowers Canary's mid-level IR to ILAsm, the text form of ECMA-335 Common
+//! Intermediate Language, which `ilasm` assembles into an assembly for the
+//! .NET runtime.
+//!
+//! SSA values and block parameters are locals, or arguments for the
+//! parameters of the entry block, so the evaluation stack is empty between
+//! blocks; constants, functions and the addresses of slots and globals are
+//! pushed where they are used instead. A jump pushes its arguments and
+//! stores them into the parameters of the block it goes to.
+//!
+//! Canary structs are value types, so copying one is what the evaluation
+//! stack does anyway. Enums are abstract classes with an `int32` tag, one
+//! nested sealed subclass per variant holding its payload; reading the
+//! payload casts to the variant. Pointers are managed pointers (`T&`)
+//! taken with `ldloca`, `ldsflda` and `ldflda`, so taking the address of
+//! a slot needs no boxing. The runtime does not allow managed pointers in
+//! fields, so pointers stored in structs, enums or globals produce IL it
+//! rejects. Function values are method pointers from `ldftn`, called with
+//! `calli`.
+//!
+//! Functions that tail call each other are merged into one method,
+//! `'<first>.group'`, that starts with a `switch` on the member to run;
+//! tail calls within it overwrite the arguments with `starg` and `br` to
+//! the callee, and each member keeps a method that calls the merged one.
+//! This works whatever the arguments are, unlike the `tail.` prefix, which
+//! the runtime only honours when no argument points into the frame being
+//! dropped. Other tail calls still use `tail.` when they can.
+//!
+//! Everything else lives in one static class named after the module:
+//! functions are static methods, globals are static fields, and `canary.entry` is the assembly's entry point around the
+//! Canary `main`. `printf` writes each piece with `System.Console.Write`,
+//! and runtime errors are written to `System.Console.Error` before exiting.
+//!
+//! Globals are initialized the first time they are used, like in the
+//! interpreter: every use calls `'canary.init.<name>'`, which runs the
+//! initializer unless a static state field says it already has, and reports
+//! a global read during its own initialization.
+
+mod types;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use ir::cfg::{TailGroup, reverse_postorder, tail_call_groups};
+use ir::{
+    BinOp, BlockId, Callee, Const, EnumId, FuncId, Function, InstKind, Signature, StructId, Target,
+    Terminator, Type, UnOp, Value,
+};
+use sema::format::{self, Piece, Spec};
+use sema::types::{FloatTy, IntTy};
+
+use types::quote;
+
+const OBJECT: &str = "[System.Runtime]System.Object";
+const VALUE_TYPE: &str = "[System.Runtime]System.ValueType";
+const CONSOLE: &str = "[System.Console]System.Console";
+
+/// Lowers `module` to an ILAsm module for an assembly called `assembly`,
+/// which also names the class holding the functions and globals. Runtime
+/// errors name the file the module was lowered from. The module must be
+/// well formed.
+pub fn emit(module: &ir::Module, assembly: &str) -> String {
+    let mut unit = Module {
+        module,
+        out: String::new(),
+        class: String::new(),
+        struct_names: Vec::new(),
+        enum_names: Vec::new(),
+        method_names: Vec::new(),
+        global_names: Vec::new(),
+        state_names: Vec::new(),
+        init_names: Vec::new(),
+        groups: Vec::new(),
+        members: String::new(),
+    };
+
+    let mut taken = HashSet::new();
+    let mut unique = |name: &str| {
+        let mut candidate = name.to_string();
+        let mut n = 1;
+        while !taken.insert(candidate.clone()) {
+            candidate = format!("{name}_{n}");
+            n += 1;
+        }
+        quote(&candidate)
+    };
+    unit.class = unique(assembly);
+    unit.struct_names = module.structs.iter().map(|s| unique(&s.name)).collect();
+    unit.enum_names = module.enums.iter().map(|e| unique(&e.name)).collect();
+
+    let mut taken = HashSet::from(["canary.entry".to_string()]);
+    let mut unique = |name: &str| {
+        let mut candidate = name.to_string();
+        let mut n = 1;
+        while !taken.insert(candidate.clone()) {
+            candidate = format!("{name}_{n}");
+            n += 1;
+        }
+        candidate
+    };
+    let names: Vec<String> = module.functions.iter().map(|f| unique(&f.name)).collect();
+    unit.method_names = names.iter().map(|name| quote(name)).collect();
+    unit.groups = tail_call_groups(module)
+        .into_iter()
+        .map(|tail| {
+            let first = &names[tail.members[0].0 as usize];
+            let merged = tail
+                .is_merged()
+                .then(|| quote(&unique(&format!("{first}.group"))));
+            Group { tail, merged }
+        })
+        .collect();
+    unit.init_names = module
+        .globals
+        .iter()
+        .map(|g| quote(&unique(&format!("canary.init.{}", g.name))))
+        .collect();
+    unit.global_names = module.globals.iter().map(|g| quote(&g.name)).collect();
+    unit.state_names = module
+        .globals
+        .iter()
+        .map(|g| quote(&format!("canary.state.{}", g.name)))
+        .collect();
+
+    unit.header(assembly);
+    for id in 0..module.structs.len() {
+        unit.structure(StructId(id as u32));
+    }
+    for id in 0..module.enums.len() {
+        unit.enumeration(EnumId(id as u32));
+    }
+
+    for (global, name) in module.globals.iter().zip(&unit.global_names) {
+        if let Some(ty) = &global.ty {
+            let ty = unit.ty(ty);
+            writeln!(unit.members, "  .field public static {ty} {name}").unwrap();
+        }
+    }
+    for name in &unit.state_names {
+        writeln!(unit.members, "  .field private static uint8 {name}").unwrap();
+    }
+    for index in 0..unit.groups.len() {
+        unit.group(index);
+    }
+    for index in 0..module.globals.len() {
+        unit.init(index);
+    }
+    if let Some(main) = module.main {
+        unit.entry(main);
+    }
+
+    writeln!(
+        unit.out,
+        "\n.class public abstract sealed auto ansi {} extends {OBJECT}\n{{\n{}}}",
+        unit.class, unit.members
+    )
+    .unwrap();
+    unit.out
+}
+
+struct Group {
+    tail: TailGroup,
+    /// The quoted name of the method the members are merged into, if there
+    /// are several.
+    merged: Option<String>,
+}
+
+/// The ILAsm module being written.
+struct Module<'a> {
+    module: &'a ir::Module,
+    out: String,
+    /// The static class holding functions and globals.
+    class: String,
+    /// Quoted class name of each struct and enum.
+    struct_names: Vec<String>,
+    enum_names: Vec<String>,
+    method_names: Vec<String>,
+    global_names: Vec<String>,
+    /// The quoted names of the field holding the state of each global's
+    /// initialization and of the method that runs it; see `Module::init`.
+    state_names: Vec<String>,
+    init_names: Vec<String>,
+    groups: Vec<Group>,
+    /// Fields and methods of the static class.
+    members: String,
+}
+
+impl<'a> Module<'a> {
+    fn header(&mut self, assembly: &str) {
+        for reference in ["System.Runtime", "System.Console"] {
+            writeln!(
+                self.out,
+                ".assembly extern {reference}\n{{\n  .publickeytoken = (B0 3F 5F 7F 11 D5 0A 3A)\n  .ver 8:0:0:0\n}}"
+            )
+            .unwrap();
+        }
+        writeln!(
+            self.out,
+            ".assembly {}\n{{\n  .ver 0:0:0:0\n}}\n.module {}",
+            quote(assembly),
+            quote(&format!("{assembly}.dll"))
+        )
+        .unwrap();
+    }
+
+    /// The ILAsm type of `ty`.
+    fn ty(&self, ty: &Type) -> String {
+        match ty {
+            Type::Int(int) => types::int_name(*int).to_string(),
+            Type::Float(float) => types::float_name(*float).to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Str => "string".to_string(),
+            Type::Struct(id) => format!("valuetype {}", self.struct_names[id.0 as usize]),
+            Type::Enum(id) => format!("class {}", self.enum_names[id.0 as usize]),
+            Type::Ptr(inner) => format!("{}&", self.ty(inner)),
+            Type::Fn(sig) => format!("method {}", self.signature(sig, "*")),
+        }
+    }
+
+    /// The ILAsm return type for `ret`.
+    fn ret(&self, ret: Option<&Type>) -> String {
+        match ret {
+            Some(ty) => self.ty(ty),
+            None => "void".to_string(),
+        }
+    }
+
+    /// `ret name(params)`, the shape of method references and pointers.
+    fn signature(&self, sig: &Signature, name: &str) -> String {
+        let params: Vec<String> = sig.params.iter().map(|ty| self.ty(ty)).collect();
+        format!(
+            "{} {name}({})",
+            self.ret(sig.ret.as_ref()),
+            params.join(", ")
+        )
+    }
+
+    /// How `call` and `ldftn` refer to a function.
+    fn method(&self, id: FuncId) -> String {
+        let name = format!("{}::{}", self.class, self.method_names[id.0 as usize]);
+        self.signature(&self.module.function(id).sig, &name)
+    }
+
+    /// How `ldsfld` and friends refer to a global.
+    fn global(&self, id: ir::GlobalId) -> String {
+        let ty = self.module.globals[id.0 as usize]
+            .ty
+            .as_ref()
+            .expect("only globals with a value are referred to");
+        format!(
+            "{} {}::{}",
+            self.ty(ty),
+            self.class,
+            self.global_names[id.0 as usize]
+        )
+    }
+
+    /// How `ldfld` and friends refer to a field.
+    fn field_ref(&self, ty: &Type, class: &str, name: &str) -> String {
+        format!("{} {class}::{}", self.ty(ty), quote(name))
+    }
+
+    fn struct_field(&self, id: StructId, index: u32) -> String {
+        let field = &self.module.structs[id.0 as usize].fields[index as usize];
+        self.field_ref(&field.ty, &self.struct_names[id.0 as usize], &field.name)
+    }
+
+    /// The class of a variant, nested in the class of its enum.
+    fn variant_class(&self, id: EnumId, index: u32) -> String {
+        let variant = &self.module.enums[id.0 as usize].variants[index as usize];
+        format!(
+            "{}/{}",
+            self.enum_names[id.0 as usize],
+            quote(&variant.name)
+        )
+    }
+
+    /// The name of a payload field. Those of tuple variants are numbers,
+    /// which ILAsm does not take as names.
+    fn variant_field_name(&self, id: EnumId, index: u32, field: u32) -> String {
+        let variant = &self.module.enums[id.0 as usize].variants[index as usize];
+        let name = &variant.fields[field as usize].name;
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("_{name}")
+        } else {
+            name.clone()
+        }
+    }
+
+    fn variant_field(&self, id: EnumId, index: u32, field: u32) -> String {
+        let variant = &self.module.enums[id.0 as usize].variants[index as usize];
+        let ty = &variant.fields[field as usize].ty;
+        let class = self.variant_class(id, index);
+        self.field_ref(ty, &class, &self.variant_field_name(id, index, field))
+    }
+
+    fn tag_field(&self, id: EnumId) -> String {
+        format!("int32 {}::'tag'", self.enum_names[id.0 as usize])
+    }
+
+    /// A struct is a sealed value type with a field for each of its fields.
+    fn structure(&mut self, id: StructId) {
+        let def = &self.module.structs[id.0 as usize];
+        let mut fields = String::new();
+        for field in &def.fields {
+            let ty = self.ty(&field.ty);
+            writeln!(fields, "  .field public {ty} {}", quote(&field.name)).unwrap();
+        }
+        writeln!(
+            self.out,
+            "\n.class public sequential ansi sealed beforefieldinit {} extends {VALUE_TYPE}\n{{\n{fields}}}",
+            self.struct_names[id.0 as usize]
+        )
+        .unwrap();
+    }
+
+    /// An enum is an abstract class holding the tag, with a nested class
+    /// for each variant. Variants are immutable: matching only reads them.
+    fn enumeration(&mut self, id: EnumId) {
+        let def = &self.module.enums[id.0 as usize];
+        let name = &self.enum_names[id.0 as usize];
+        let tag = self.tag_field(id);
+        let mut body = String::new();
+        writeln!(body, "  .field public initonly int32 'tag'").unwrap();
+        writeln!(
+            body,
+            "\n  .method family hidebysig specialname rtspecialname instance void .ctor(int32 'tag') cil managed\n  {{\n    .maxstack 2\n    ldarg.0\n    call instance void {OBJECT}::.ctor()\n    ldarg.0\n    ldarg.1\n    stfld {tag}\n    ret\n  }}"
+        )
+        .unwrap();
+
+        for (index, variant) in def.variants.iter().enumerate() {
+            let index = index as u32;
+            let mut fields = String::new();
+            let mut params = Vec::new();
+            let mut stores = String::new();
+            for (field, def) in variant.fields.iter().enumerate() {
+                let field = field as u32;
+                let ty = self.ty(&def.ty);
+                let field_name = quote(&self.variant_field_name(id, index, field));
+                writeln!(fields, "    .field public initonly {ty} {field_name}").unwrap();
+                params.push(format!("{ty} {field_name}"));
+                writeln!(
+                    stores,
+                    "      ldarg.0\n      {}\n      stfld {}",
+                    ldarg(field as usize + 1),
+                    self.variant_field(id, index, field)
+                )
+                .unwrap();
+            }
+            writeln!(
+                body,
+                "\n  .class nested public sealed auto ansi {} extends {name}\n  {{\n{fields}    .method public hidebysig specialname rtspecialname instance void .ctor({}) cil managed\n    {{\n      .maxstack 2\n      ldarg.0\n      {}\n      call instance void {name}::.ctor(int32)\n{stores}      ret\n    }}\n  }}",
+                quote(&variant.name),
+                params.join(", "),
+                types::ldc_i4(index as i32),
+            )
+            .unwrap();
+        }
+        writeln!(
+            self.out,
+            "\n.class public abstract auto ansi {name} extends {OBJECT}\n{{\n{body}}}"
+        )
+        .unwrap();
+    }
+
+    /// Writes the methods of a group: the function itself, or the merged
+    /// method, which takes the index of the member to run and then the
+    /// parameters of every member, and an entry point for each member.
+    fn group(&mut self, index: usize) {
+        let module = self.module;
+        let tail = self.groups[index].tail.clone();
+        let merged = self.groups[index].merged.clone();
+        let members = &tail.members;
+        let mut f = FnGen::new(self);
+        f.group = tail.clone();
+
+        let mut params = Vec::new();
+        if merged.is_some() {
+            let name = f.fresh_name("entry");
+            params.push(format!("int32 {name}"));
+        }
+        for &id in members {
+            let names = f.name_member(module.function(id));
+            f.members.push(names);
+        }
+        for (member, param, ty) in tail.params(module) {
+            let ty = f.module.ty(ty);
+            params.push(format!(
+                "{ty} {}",
+                f.members[member].values[param.0 as usize]
+            ));
+        }
+
+        if merged.is_some() {
+            let labels: Vec<String> = f.members.iter().map(|m| m.labels[0].clone()).collect();
+            f.ins(&ldarg(0), 0, 1);
+            f.ins(&format!("switch ({})", labels.join(", ")), 1, 0);
+        }
+        for (i, &id) in members.iter().enumerate() {
+            f.function(i, module.function(id));
+        }
+
+        let ret = f.module.ret(module.function(members[0]).sig.ret.as_ref());
+        let name = merged
+            .clone()
+            .unwrap_or_else(|| f.module.method_names[members[0].0 as usize].clone());
+        let header = format!(
+            ".method public hidebysig static {ret} {name}({}) cil managed",
+            params.join(", ")
+        );
+        f.finish(&header, "");
+
+        if let Some(merged) = merged {
+            for index in 0..members.len() {
+                self.entry_point(&tail, index, &merged);
+            }
+        }
+    }
+
+    /// The method of member `index` of a merged group, which calls the
+    /// merged method with its own arguments and zeroed locals for the rest.
+    fn entry_point(&mut self, group: &TailGroup, index: usize, merged: &str) {
+        let module = self.module;
+        let id = group.members[index];
+        let function = module.function(id);
+        let mut f = FnGen::new(self);
+        let mut params = Vec::new();
+        let mut types = vec!["int32".to_string()];
+        f.ins(&types::ldc_i4(index as i32), 0, 1);
+        for (member, param, ty) in group.params(module) {
+            types.push(f.module.ty(ty));
+            if member == index {
+                let name = f.fresh_name(&format!("v{}", param.0));
+                params.push(format!("{} {name}", f.module.ty(ty)));
+                f.ins(&format!("ldarg {name}"), 0, 1);
+            } else {
+                let name = f.local("unused", ty);
+                f.ins(&format!("ldloc {name}"), 0, 1);
+            }
+        }
+        let ret = f.module.ret(function.sig.ret.as_ref());
+        let pushes = u32::from(function.sig.ret.is_some());
+        let call = format!(
+            "call {ret} {}::{merged}({})",
+            f.module.class,
+            types.join(", ")
+        );
+        f.ins(&call, types.len() as u32, pushes);
+        f.ins("ret", pushes, 0);
+        let name = &f.module.method_names[id.0 as usize];
+        let header = format!(
+            ".method public hidebysig static {ret} {name}({}) cil managed",
+            params.join(", ")
+        );
+        f.finish(&header, "");
+    }
+
+    /// The method that initializes global `index` the first time it is
+    /// used, given where as `file:line:col`. Its state field is 0 before
+    /// that, 1 while its initializer runs and 2 after.
+    fn init(&mut self, index: usize) {
+        let module = self.module;
+        let global = &module.globals[index];
+        let state = format!("uint8 {}::{}", self.class, self.state_names[index]);
+        let name = self.init_names[index].clone();
+        let mut f = FnGen::new(self);
+        let begin = f.fresh_label("begin");
+        let done = f.fresh_label("done");
+        f.ins(&format!("ldsfld {state}"), 0, 1);
+        f.ins("ldc.i4.2", 0, 1);
+        f.ins(&format!("beq {done}"), 2, 0);
+        f.ins(&format!("ldsfld {state}"), 0, 1);
+        f.ins(&format!("brfalse {begin}"), 1, 0);
+        f.fail(|f| {
+            f.ins("ldarg.0", 0, 1);
+            let text = ": runtime error: global read during its own initialization";
+            f.ins(&format!("ldstr {}", types::string_literal(text)), 0, 1);
+            f.ins(
+                "call string [System.Runtime]System.String::Concat(string, string)",
+                2,
+                1,
+            );
+        });
+
+        f.label(&begin);
+        f.ins("ldc.i4.1", 0, 1);
+        f.ins(&format!("stsfld {state}"), 1, 0);
+        let pushes = u32::from(global.ty.is_some());
+        let call = format!("call {}", f.module.method(global.init));
+        f.ins(&call, 0, pushes);
+        if global.ty.is_some() {
+            let field = f.module.global(ir::GlobalId(index as u32));
+            f.ins(&format!("stsfld {field}"), 1, 0);
+        }
+        f.ins("ldc.i4.2", 0, 1);
+        f.ins(&format!("stsfld {state}"), 1, 0);
+        f.label(&done);
+        f.ins("ret", 0, 0);
+        f.finish(
+            &format!(".method private hidebysig static void {name}(string 'at') cil managed"),
+            "",
+        );
+    }
+
+    /// The entry point: runs the Canary `main` and returns its status.
+    fn entry(&mut self, main: FuncId) {
+        let ret = self.module.function(main).sig.ret.clone();
+        let mut f = FnGen::new(self);
+        let call = format!("call {}", f.module.method(main));
+        match &ret {
+            Some(Type::Int(int)) => {
+                f.ins(&call, 0, 1);
+                if int.bits() == 64 {
+                    f.ins("conv.i4", 1, 1);
+                }
+            }
+            ty => {
+                f.ins(&call, 0, u32::from(ty.is_some()));
+                if ty.is_some() {
+                    f.ins("pop", 1, 0);
+                }
+                f.ins("ldc.i4.0", 0, 1);
+            }
+        }
+        f.ins("ret", 1, 0);
+        f.finish(
+            ".method public hidebysig static int32 'canary.entry'() cil managed",
+            "    .entrypoint\n",
+        );
+    }
+}
+
+/// The shortest `ldarg` form for argument `index`.
+fn ldarg(index: usize) -> String {
+    match index {
+        0..=3 => format!("ldarg.{index}"),
+        _ => format!("ldarg.s {index}"),
+    }
+}
+
+/// How a value is pushed.
+#[derive(Clone, PartialEq)]
+enum Operand {
+    /// By one instruction: `ldloc` or `ldarg` for values in a local or an
+    /// argument, or the constant or function itself.
+    Push(String),
+    /// By taking the address of a place.
+    Address(Place),
+}
+
+/// Somewhere a pointer can point.
+#[derive(Clone, PartialEq)]
+enum Place {
+    Local(String),
+    /// A static field, by reference.
+    Static(String),
+    /// A field of the struct at another place, by reference.
+    Field(Box<Place>, String),
+}
+
+/// The ILAsm names of the values and blocks of one member of a group.
+struct Names {
+    values: Vec<String>,
+    labels: Vec<String>,
+}
+
+/// Lowers one method body, tracking the depth of the evaluation stack for
+/// `.maxstack`.
+struct FnGen<'m, 'a> {
+    module: &'m mut Module<'a>,
+    /// The names of each member, in order.
+    members: Vec<Names>,
+    /// The group being lowered.
+    group: TailGroup,
+    /// The member being lowered.
+    member: usize,
+    /// How each value of the member is pushed, once it is defined.
+    operands: HashMap<Value, Operand>,
+    /// The values the member reads.
+    used: HashSet<Value>,
+    /// `.locals` entries.
+    locals: Vec<String>,
+    names: HashSet<String>,
+    body: String,
+    depth: u32,
+    max_depth: u32,
+}
+
+impl<'m, 'a> FnGen<'m, 'a> {
+    fn new(module: &'m mut Module<'a>) -> Self {
+        Self {
+            module,
+            members: Vec::new(),
+            group: TailGroup::default(),
+            member: 0,
+            operands: HashMap::new(),
+            used: HashSet::new(),
+            locals: Vec::new(),
+            names: HashSet::new(),
+            body: String::new(),
+            depth: 0,
+            max_depth: 0,
+        }
+    }
+
+    fn finish(self, header: &str, directives: &str) {
+        let mut out = String::new();
+        if !self.module.members.is_empty() {
+            out.push('\n');
+        }
+        writeln!(
+            out,
+            "  {header}\n  {{\n{directives}    .maxstack {}",
+            self.max_depth
+        )
+        .unwrap();
+        if !self.locals.is_empty() {
+            writeln!(out, "    .locals init ({})", self.locals.join(", ")).unwrap();
+        }
+        out.push_str(&self.body);
+        out.push_str("  }\n");
+        self.module.members.push_str(&out);
+    }
+
+    /// A quoted local or parameter name based on `hint` that is unique in
+    /// this method.
+    fn fresh_name(&mut self, hint: &str) -> String {
+        let mut candidate = hint.to_string();
+        let mut n = 1;
+        while !self.names.insert(candidate.clone()) {
+            candidate = format!("{hint}_{n}");
+            n += 1;
+        }
+        quote(&candidate)
+    }
+
+    /// A new local of type `ty`.
+    fn local(&mut self, hint: &str, ty: &Type) -> String {
+        let name = self.fresh_name(hint);
+        self.declare(&name, ty);
+        name
+    }
+
+    /// Adds the local `name` of type `ty` to `.locals`.
+    fn declare(&mut self, name: &str, ty: &Type) {
+        let ty = self.module.ty(ty);
+        self.locals.push(format!("{ty} {name}"));
+    }
+
+    /// Picks the names of the values and blocks of `function`.
+    fn name_member(&mut self, function: &Function) -> Names {
+        let values = (0..function.values.len())
+            .map(|n| self.fresh_name(&format!("v{n}")))
+            .collect();
+        // Labels are not quoted.
+        let labels = (0..function.blocks.len())
+            .map(|n| {
+                let label = self.fresh_name(&format!("b{n}"));
+                label.trim_matches('\'').to_string()
+            })
+            .collect();
+        Names { values, labels }
+    }
+
+    /// Emits `text`, which pops `pops` values and pushes `pushes`.
+    fn ins(&mut self, text: &str, pops: u32, pushes: u32) {
+        writeln!(self.body, "    {text}").unwrap();
+        self.depth = self.depth - pops + pushes;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.body, "  {label}:").unwrap();
+    }
+
+    fn name(&self, value: Value) -> String {
+        self.members[self.member].values[value.0 as usize].clone()
+    }
+
+    fn block_label(&self, block: BlockId) -> String {
+        self.members[self.member].labels[block.0 as usize].clone()
+    }
+
+    /// Lowers the blocks of member `member`, `func`.
+    fn function(&mut self, member: usize, func: &Function) {
+        self.member = member;
+        self.operands.clear();
+        self.used.clear();
+        for block in &func.blocks {
+            for inst in &block.insts {
+                self.used.extend(inst.kind.operands());
+            }
+            self.used.extend(block.term.operands());
+        }
+        for &param in &func.blocks[0].params {
+            let name = self.name(param);
+            self.operands
+                .insert(param, Operand::Push(format!("ldarg {name}")));
+        }
+        for block in &func.blocks[1..] {
+            for &param in &block.params {
+                let name = self.name(param);
+                self.declare(&name, func.ty(param));
+                self.operands
+                    .insert(param, Operand::Push(format!("ldloc {name}")));
+            }
+        }
+
+        let order = reverse_postorder(func);
+        for (i, &block) in order.iter().enumerate() {
+            let label = self.block_label(block);
+            self.label(&label);
+            let b = func.block(block);
+            for inst in &b.insts {
+                self.inst(func, inst);
+            }
+            self.terminator(func, &b.term, order.get(i + 1).copied());
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        match self.operands[&value].clone() {
+            Operand::Push(text) => self.ins(&text, 0, 1),
+            Operand::Address(place) => self.address(&place),
+        }
+    }
+
+    /// Pushes the address of `place`.
+    fn address(&mut self, place: &Place) {
+        match place {
+            Place::Local(name) => self.ins(&format!("ldloca {name}"), 0, 1),
+            Place::Static(field) => self.ins(&format!("ldsflda {field}"), 0, 1),
+            Place::Field(base, field) => {
+                self.address(base);
+                self.ins(&format!("ldflda {field}"), 1, 1);
+            }
+        }
+    }
+
+    /// Pushes the value of type `ty` that `ptr` points to.
+    fn load(&mut self, ptr: Value, ty: &Type) {
+        match self.operands[&ptr].clone() {
+            Operand::Address(Place::Local(name)) => self.ins(&format!("ldloc {name}"), 0, 1),
+            Operand::Address(Place::Static(field)) => {
+                self.ins(&format!("ldsfld {field}"), 0, 1);
+            }
+            Operand::Address(Place::Field(base, field)) => {
+                self.address(&base);
+                self.ins(&format!("ldfld {field}"), 1, 1);
+            }
+            Operand::Push(text) => {
+                self.ins(&text, 0, 1);
+                match types::ldind(ty) {
+                    Some(suffix) => self.ins(&format!("ldind.{suffix}"), 1, 1),
+                    None => {
+                        let ty = self.module.ty(ty);
+                        self.ins(&format!("ldobj {ty}"), 1, 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores `value`, of type `ty`, where `ptr` points.
+    fn store(&mut self, ptr: Value, value: Value, ty: &Type) {
+        match self.operands[&ptr].clone() {
+            Operand::Address(Place::Local(name)) => {
+                self.push(value);
+                self.ins(&format!("stloc {name}"), 1, 0);
+            }
+            Operand::Address(Place::Static(field)) => {
+                self.push(value);
+                self.ins(&format!("stsfld {field}"), 1, 0);
+            }
+            Operand::Address(Place::Field(base, field)) => {
+                self.address(&base);
+                self.push(value);
+                self.ins(&format!("stfld {field}"), 2, 0);
+            }
+            Operand::Push(text) => {
+                self.ins(&text, 0, 1);
+                self.push(value);
+                match types::stind(ty) {
+                    Some(suffix) => self.ins(&format!("stind.{suffix}"), 2, 0),
+                    None => {
+                        let ty = self.module.ty(ty);
+                        self.ins(&format!("stobj {ty}"), 2, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores the value on the stack as `result`, of type `ty`, or drops it
+    /// if nothing reads it.
+    fn define(&mut self, result: Option<Value>, ty: Option<&Type>) {
+        match (result, ty) {
+            (Some(result), Some(ty)) if self.used.contains(&result) => {
+                let name = self.name(result);
+                self.declare(&name, ty);
+                self.ins(&format!("stloc {name}"), 1, 0);
+                self.operands
+                    .insert(result, Operand::Push(format!("ldloc {name}")));
+            }
+            (Some(_), Some(_)) => self.ins("pop", 1, 0),
+            _ => {}
+        }
+    }
+
+    fn inst(&mut self, func: &Function, inst: &ir::Inst) {
+        let ty = inst.result.map(|r| func.ty(r));
+        let unused = inst.result.is_none_or(|r| !self.used.contains(&r));
+        let alias = |f: &mut Self, operand: Operand| {
+            if let Some(result) = inst.result {
+                f.operands.insert(result, operand);
+            }
+        };
+        match &inst.kind {
+            InstKind::Const(constant) => {
+                let text = match (constant, ty) {
+                    (Const::Int(value), Some(ty)) => types::int_constant(ty, *value),
+                    (Const::Float(value), Some(ty)) => types::float_constant(ty, *value),
+                    (Const::Bool(value), _) => types::ldc_i4(i32::from(*value)),
+                    (Const::Str(value), _) => format!("ldstr {}", types::string_literal(value)),
+                    _ => unreachable!("{constant:?} without a type"),
+                };
+                alias(self, Operand::Push(text));
+            }
+            InstKind::Func(id) => {
+                let method = self.module.method(*id);
+                alias(self, Operand::Push(format!("ldftn {method}")));
+            }
+            InstKind::Global { id, span } => {
+                let at = format!("{}:{}:{}", self.module.module.source, span.line, span.col);
+                self.ins(&format!("ldstr {}", types::string_literal(&at)), 0, 1);
+                let init = &self.module.init_names[id.0 as usize];
+                let call = format!("call void {}::{init}(string)", self.module.class);
+                self.ins(&call, 1, 0);
+                let place = Place::Static(self.module.global(*id));
+                alias(self, Operand::Address(place));
+            }
+            InstKind::Slot => {
+                let Some(Type::Ptr(pointee)) = ty else {
+                    unreachable!("slot of type {ty:?}");
+                };
+                let name = self.local("slot", pointee);
+                alias(self, Operand::Address(Place::Local(name)));
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                let Type::Ptr(pointee) = func.ty(*ptr) else {
+                    unreachable!("field pointer through a non-pointer");
+                };
+                let Type::Struct(id) = **pointee else {
+                    unreachable!("field pointer into a non-struct");
+                };
+                let field = self.module.struct_field(id, *index);
+                match self.operands[ptr].clone() {
+                    Operand::Address(place) => {
+                        let place = Place::Field(Box::new(place), field);
+                        alias(self, Operand::Address(place));
+                    }
+                    Operand::Push(text) => {
+                        self.ins(&text, 0, 1);
+                        self.ins(&format!("ldflda {field}"), 1, 1);
+                        self.define(inst.result, ty);
+                    }
+                }
+            }
+            // Results nobody uses are only computed for their effects.
+            _ if unused && inst.kind.is_pure() => {}
+            InstKind::Unary { op, arg } => {
+                self.push(*arg);
+                match op {
+                    UnOp::Neg => {
+                        self.ins("neg", 1, 1);
+                        self.wrap(ty.expect("negation without a result"));
+                    }
+                    UnOp::Not => {
+                        self.ins("ldc.i4.0", 0, 1);
+                        self.ins("ceq", 2, 1);
+                    }
+                }
+                self.define(inst.result, ty);
+            }
+            InstKind::Binary { op, lhs, rhs, .. } => {
+                self.push(*lhs);
+                self.push(*rhs);
+                self.binary(*op, func.ty(*lhs));
+                self.define(inst.result, ty);
+            }
+            InstKind::Call { callee, args, .. } => {
+                self.call(func, callee, args, false);
+                self.define(inst.result, ty);
+            }
+            InstKind::Printf { format, args } => self.printf(func, format, args),
+            InstKind::Struct(fields) => {
+                // Built in its local a field at a time.
+                let Some(ty @ Type::Struct(id)) = ty else {
+                    unreachable!("struct of type {ty:?}");
+                };
+                let name = self.name(inst.result.expect("struct without a result"));
+                self.declare(&name, ty);
+                if fields.is_empty() {
+                    self.ins(&format!("ldloca {name}"), 0, 1);
+                    let c_ty = self.module.ty(ty);
+                    self.ins(&format!("initobj {c_ty}"), 1, 0);
+                }
+                for (index, field) in fields.iter().enumerate() {
+                    self.ins(&format!("ldloca {name}"), 0, 1);
+                    self.push(*field);
+                    let field = self.module.struct_field(*id, index as u32);
+                    self.ins(&format!("stfld {field}"), 2, 0);
+                }
+                alias(self, Operand::Push(format!("ldloc {name}")));
+            }
+            InstKind::Variant { index, fields } => {
+                let Some(Type::Enum(id)) = ty else {
+                    unreachable!("variant of type {ty:?}");
+                };
+                let mut params = Vec::new();
+                for field in fields {
+                    self.push(*field);
+                    params.push(self.module.ty(func.ty(*field)));
+                }
+                let class = self.module.variant_class(*id, *index);
+                let count = params.len() as u32;
+                let ctor = format!("newobj instance void {class}::.ctor({})", params.join(", "));
+                self.ins(&ctor, count, 1);
+                self.define(inst.result, ty);
+            }
+            InstKind::Field { base, index } => {
+                let Type::Struct(id) = func.ty(*base) else {
+                    unreachable!("field of a non-struct");
+                };
+                self.push(*base);
+                let field = self.module.struct_field(*id, *index);
+                self.ins(&format!("ldfld {field}"), 1, 1);
+                self.define(inst.result, ty);
+            }
+            InstKind::Tag(base) => {
+                let Type::Enum(id) = func.ty(*base) else {
+                    unreachable!("tag of a non-enum");
+                };
+                self.push(*base);
+                let tag = self.module.tag_field(*id);
+                self.ins(&format!("ldfld {tag}"), 1, 1);
+                self.define(inst.result, ty);
+            }
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => {
+                let Type::Enum(id) = func.ty(*base) else {
+                    unreachable!("payload of a non-enum");
+                };
+                self.push(*base);
+                let class = self.module.variant_class(*id, *variant);
+                self.ins(&format!("castclass {class}"), 1, 1);
+                let field = self.module.variant_field(*id, *variant, *field);
+                self.ins(&format!("ldfld {field}"), 1, 1);
+                self.define(inst.result, ty);
+            }
+            InstKind::Load(ptr) => {
+                self.load(*ptr, ty.expect("load without a result"));
+                self.define(inst.result, ty);
+            }
+            InstKind::Store { ptr, value } => self.store(*ptr, *value, func.ty(*value)),
+        }
+    }
+
+    fn wrap(&mut self, ty: &Type) {
+        if let Some(conv) = types::wrap(ty) {
+            self.ins(conv, 1, 1);
+        }
+    }
+
+    /// Replaces the two operands of type `ty` on the stack with the result
+    /// of `op`.
+    fn binary(&mut self, op: BinOp, ty: &Type) {
+        if !op.is_comparison() {
+            let instruction = match op {
+                BinOp::Add => "add",
+                BinOp::Sub => "sub",
+                BinOp::Mul => "mul",
+                BinOp::Div if types::is_unsigned(ty) => "div.un",
+                BinOp::Div => "div",
+                _ => unreachable!("{op:?} is not arithmetic"),
+            };
+            self.ins(instruction, 2, 1);
+            self.wrap(ty);
+            return;
+        }
+
+        if let Type::Str = ty {
+            let method = if op == BinOp::Eq {
+                "op_Equality"
+            } else {
+                "op_Inequality"
+            };
+            let call = format!("call bool [System.Runtime]System.String::{method}(string, string)");
+            self.ins(&call, 2, 1);
+            return;
+        }
+
+        // Unordered floats compare like unsigned integers in `.un`
+        // comparisons, so negating one gives the ordered comparison.
+        let un = types::is_unsigned(ty) || matches!(ty, Type::Float(_));
+        let (cmp, negate) = match op {
+            BinOp::Eq => ("ceq", false),
+            BinOp::Ne => ("ceq", true),
+            BinOp::Lt if types::is_unsigned(ty) => ("clt.un", false),
+            BinOp::Gt if types::is_unsigned(ty) => ("cgt.un", false),
+            BinOp::Lt => ("clt", false),
+            BinOp::Gt => ("cgt", false),
+            BinOp::Le if un => ("cgt.un", true),
+            BinOp::Ge if un => ("clt.un", true),
+            BinOp::Le => ("cgt", true),
+            BinOp::Ge => ("clt", true),
+            _ => unreachable!(),
+        };
+        self.ins(cmp, 2, 1);
+        if negate {
+            self.ins("ldc.i4.0", 0, 1);
+            self.ins("ceq", 2, 1);
+        }
+    }
+
+    /// Pushes the arguments and calls `callee`, with the `tail.` prefix if
+    /// `tail` is set.
+    fn call(&mut self, func: &Function, callee: &Callee, args: &[Value], tail: bool) {
+        let count = args.len() as u32;
+        for &arg in args {
+            self.push(arg);
+        }
+        match callee {
+            Callee::Direct(id) => {
+                let sig = &self.module.module.function(*id).sig;
+                let pushes = u32::from(sig.ret.is_some());
+                let method = self.module.method(*id);
+                if tail {
+                    self.ins("tail.", 0, 0);
+                }
+                self.ins(&format!("call {method}"), count, pushes);
+            }
+            Callee::Indirect(value) => {
+                let Type::Fn(sig) = func.ty(*value) else {
+                    unreachable!("call of a non-function");
+                };
+                let pushes = u32::from(sig.ret.is_some());
+                let signature = self.module.signature(sig, "");
+                self.push(*value);
+                if tail {
+                    self.ins("tail.", 0, 0);
+                }
+                self.ins(&format!("calli {signature}"), count + 1, pushes);
+            }
+        }
+    }
+
+    fn terminator(&mut self, func: &Function, term: &Terminator, next: Option<BlockId>) {
+        match term {
+            Terminator::Jump(target) => self.goto(func, target, next),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.push(*cond);
+                if otherwise.args.is_empty() {
+                    let label = self.block_label(otherwise.block);
+                    self.ins(&format!("brfalse {label}"), 1, 0);
+                    self.goto(func, then, next);
+                } else if then.args.is_empty() {
+                    let label = self.block_label(then.block);
+                    self.ins(&format!("brtrue {label}"), 1, 0);
+                    self.goto(func, otherwise, next);
+                } else {
+                    let label = self.fresh_label("else");
+                    self.ins(&format!("brfalse {label}"), 1, 0);
+                    self.goto(func, then, None);
+                    self.label(&label);
+                    self.goto(func, otherwise, next);
+                }
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                let ty = func.ty(*value).clone();
+                let mut edges = Vec::new();
+                for (n, target) in cases {
+                    self.push(*value);
+                    self.ins(&types::int_constant(&ty, *n), 0, 1);
+                    let label = if target.args.is_empty() {
+                        self.block_label(target.block)
+                    } else {
+                        let label = self.fresh_label("case");
+                        edges.push((label.clone(), target));
+                        label
+                    };
+                    self.ins(&format!("beq {label}"), 2, 0);
+                }
+                let next = if edges.is_empty() { next } else { None };
+                self.goto(func, default, next);
+                for (label, target) in edges {
+                    self.label(&label);
+                    self.goto(func, target, None);
+                }
+            }
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.push(*value);
+                }
+                self.ins("ret", u32::from(value.is_some()), 0);
+            }
+            Terminator::TailCall { callee, args } => {
+                if let Callee::Direct(id) = callee
+                    && let Some(member) = self.group.index_of(*id)
+                {
+                    // A tail call within the group reuses the frame.
+                    let callee = self.module.module.function(*id);
+                    let params: Vec<String> = callee.blocks[0]
+                        .params
+                        .iter()
+                        .map(|p| self.members[member].values[p.0 as usize].clone())
+                        .collect();
+                    for &arg in args {
+                        self.push(arg);
+                    }
+                    for param in params.iter().rev() {
+                        self.ins(&format!("starg {param}"), 1, 0);
+                    }
+                    let label = self.members[member].labels[0].clone();
+                    self.ins(&format!("br {label}"), 0, 0);
+                    return;
+                }
+                let tail = self.can_tail_call(func, args);
+                self.call(func, callee, args, tail);
+                self.ins("ret", u32::from(func.sig.ret.is_some()), 0);
+            }
+            Terminator::Trap { message, span } => {
+                let text = format!(
+                    "{}:{}:{}: runtime error: {message}",
+                    self.module.module.source, span.line, span.col
+                );
+                self.fail(|f| f.ins(&format!("ldstr {}", types::string_literal(&text)), 0, 1));
+            }
+            Terminator::Unreachable => self.unreachable(),
+        }
+    }
+
+    /// Writes the message `text` pushes to `Console.Error` and exits with
+    /// status 1.
+    fn fail(&mut self, text: impl FnOnce(&mut Self)) {
+        self.ins(
+            "call class [System.Runtime]System.IO.TextWriter [System.Console]System.Console::get_Error()",
+            0,
+            1,
+        );
+        text(self);
+        self.ins(
+            "callvirt instance void [System.Runtime]System.IO.TextWriter::WriteLine(string)",
+            2,
+            0,
+        );
+        self.ins("ldc.i4.1", 0, 1);
+        self.ins(
+            "call void [System.Runtime]System.Environment::Exit(int32)",
+            1,
+            0,
+        );
+        self.unreachable();
+    }
+
+    /// Ends a block control never leaves.
+    fn unreachable(&mut self) {
+        self.ins("ldnull", 0, 1);
+        self.ins("throw", 1, 0);
+    }
+
+    /// A label for code that is not a block of its own.
+    fn fresh_label(&mut self, hint: &str) -> String {
+        self.fresh_name(hint).trim_matches('\'').to_string()
+    }
+
+    /// Pushes the arguments of `target`, stores them to its parameters, and
+    /// branches there unless it is `next`, the block that follows.
+    fn goto(&mut self, func: &Function, target: &Target, next: Option<BlockId>) {
+        let params = &func.block(target.block).params;
+        // Every argument is read before any parameter is written.
+        let moves: Vec<(Value, Value)> = params
+            .iter()
+            .zip(&target.args)
+            .filter(|(param, arg)| param != arg)
+            .map(|(param, arg)| (*param, *arg))
+            .collect();
+        for &(_, arg) in &moves {
+            self.push(arg);
+        }
+        let store = if target.block == BlockId(0) {
+            "starg"
+        } else {
+            "stloc"
+        };
+        for &(param, _) in moves.iter().rev() {
+            let name = self.name(param);
+            self.ins(&format!("{store} {name}"), 1, 0);
+        }
+        if next != Some(target.block) {
+            let label = self.block_label(target.block);
+            self.ins(&format!("br {label}"), 0, 0);
+        }
+    }
+
+    /// Whether a call in tail position may use `tail.`: it must pass no
+    /// pointers, which may point into the frame being dropped.
+    fn can_tail_call(&self, func: &Function, args: &[Value]) -> bool {
+        !args.iter().any(|arg| matches!(func.ty(*arg), Type::Ptr(_)))
+    }
+
+    /// Lowers `printf` to a `Console.Write` for each piece of the format
+    /// string.
+    fn printf(&mut self, func: &Function, format: &str, args: &[Value]) {
+        let pieces = format::parse(format).expect("format string was checked");
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    self.ins(&format!("ldstr {}", types::string_literal(&text)), 0, 1);
+                    self.write("string");
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = *args.next().expect("format arguments were checked");
+            self.push(arg);
+            match (spec, func.ty(arg)) {
+                (Spec::Str, _) => self.write("string"),
+                (Spec::Int, Type::Int(int)) => self.write(match int {
+                    IntTy::U32 => "uint32",
+                    IntTy::I64 => "int64",
+                    IntTy::U64 => "uint64",
+                    _ => "int32",
+                }),
+                (Spec::Float, Type::Float(float)) => {
+                    // Six decimals, like C's `%f`, whatever the culture.
+                    if *float != FloatTy::F64 {
+                        self.ins("conv.r8", 1, 1);
+                    }
+                    let double = self.local("double", &Type::Float(FloatTy::F64));
+                    self.ins(&format!("stloc {double}"), 1, 0);
+                    self.ins(&format!("ldloca {double}"), 0, 1);
+                    self.ins("ldstr \"F6\"", 0, 1);
+                    self.ins(
+                        "call class [System.Runtime]System.Globalization.CultureInfo [System.Runtime]System.Globalization.CultureInfo::get_InvariantCulture()",
+                        0,
+                        1,
+                    );
+                    self.ins(
+                        "call instance string [System.Runtime]System.Double::ToString(string, class [System.Runtime]System.IFormatProvider)",
+                        3,
+                        1,
+                    );
+                    self.write("string");
+                }
+                (spec, ty) => unreachable!("{} cannot print {ty:?}", spec.as_str()),
+            }
+        }
+    }
+
+    /// Prints the value on the stack, of ILAsm type `ty`.
+    fn write(&mut self, ty: &str) {
+        self.ins(&format!("call void {CONSOLE}::Write({ty})"), 1, 0);
+    }
+}
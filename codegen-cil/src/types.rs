@@ -0,0 +1,148 @@
+//! ILAsm names and the instructions that go with each kind of value.
+
+use ir::Type;
+use sema::types::{FloatTy, IntTy};
+
+/// `name` as an ILAsm identifier. Everything from the source is quoted, so
+/// names that happen to be ILAsm keywords or contain the `#` of macro
+/// hygiene need no special cases.
+pub fn quote(name: &str) -> String {
+    format!("'{}'", name.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+pub fn int_name(int: IntTy) -> &'static str {
+    match int {
+        IntTy::I8 => "int8",
+        IntTy::I16 => "int16",
+        IntTy::I32 => "int32",
+        IntTy::I64 => "int64",
+        IntTy::U8 => "uint8",
+        IntTy::U16 => "uint16",
+        IntTy::U32 => "uint32",
+        IntTy::U64 => "uint64",
+    }
+}
+
+/// The CLI has no 16-bit floats; `f16` is computed as `float32`.
+pub fn float_name(float: FloatTy) -> &'static str {
+    match float {
+        FloatTy::F64 => "float64",
+        _ => "float32",
+    }
+}
+
+pub fn is_unsigned(ty: &Type) -> bool {
+    matches!(ty, Type::Int(int) if !int.is_signed())
+}
+
+/// The conversion that brings the result of arithmetic back into the range
+/// of `ty`: the evaluation stack computes in 32 bits at least, which is how
+/// narrow integers wrap, and `float32` results may carry extra precision.
+pub fn wrap(ty: &Type) -> Option<&'static str> {
+    Some(match ty {
+        Type::Int(IntTy::I8) => "conv.i1",
+        Type::Int(IntTy::I16) => "conv.i2",
+        Type::Int(IntTy::U8) => "conv.u1",
+        Type::Int(IntTy::U16) => "conv.u2",
+        Type::Float(FloatTy::F16 | FloatTy::F32) => "conv.r4",
+        _ => return None,
+    })
+}
+
+/// The `ldind` suffix for `ty`, or `None` for value types, which take
+/// `ldobj`.
+pub fn ldind(ty: &Type) -> Option<&'static str> {
+    Some(match ty {
+        Type::Int(IntTy::I8) => "i1",
+        Type::Int(IntTy::U8) | Type::Bool => "u1",
+        Type::Int(IntTy::I16) => "i2",
+        Type::Int(IntTy::U16) => "u2",
+        Type::Int(IntTy::I32) => "i4",
+        Type::Int(IntTy::U32) => "u4",
+        Type::Int(IntTy::I64 | IntTy::U64) => "i8",
+        Type::Float(FloatTy::F64) => "r8",
+        Type::Float(_) => "r4",
+        Type::Ptr(_) | Type::Fn(_) => "i",
+        Type::Struct(_) => return None,
+        Type::Str | Type::Enum(_) => "ref",
+    })
+}
+
+/// The `stind` suffix for `ty`, or `None` for value types, which take
+/// `stobj`.
+pub fn stind(ty: &Type) -> Option<&'static str> {
+    Some(match ty {
+        Type::Int(IntTy::I8 | IntTy::U8) | Type::Bool => "i1",
+        Type::Int(IntTy::I16 | IntTy::U16) => "i2",
+        Type::Int(IntTy::I32 | IntTy::U32) => "i4",
+        Type::Int(IntTy::I64 | IntTy::U64) => "i8",
+        Type::Float(FloatTy::F64) => "r8",
+        Type::Float(_) => "r4",
+        Type::Ptr(_) | Type::Fn(_) => "i",
+        Type::Struct(_) => return None,
+        Type::Str | Type::Enum(_) => "ref",
+    })
+}
+
+/// `ldc` for an integer constant of type `ty`, stored as the IR stores
+/// integers. 32-bit constants are pushed by their bits.
+pub fn int_constant(ty: &Type, value: i64) -> String {
+    match ty {
+        Type::Int(int) if int.bits() == 64 => format!("ldc.i8 {value}"),
+        _ => ldc_i4(value as i32),
+    }
+}
+
+/// The shortest `ldc.i4` form for `value`.
+pub fn ldc_i4(value: i32) -> String {
+    match value {
+        -1 => "ldc.i4.m1".to_string(),
+        0..=8 => format!("ldc.i4.{value}"),
+        -128..=127 => format!("ldc.i4.s {value}"),
+        _ => format!("ldc.i4 {value}"),
+    }
+}
+
+/// `ldc` for a float constant. Values without an exact short decimal form
+/// are written as their bits.
+pub fn float_constant(ty: &Type, value: f64) -> String {
+    let plain = |text: &str| !text.contains(['e', 'i', 'N']);
+    if let Type::Float(FloatTy::F64) = ty {
+        let text = format!("{value:?}");
+        if plain(&text) {
+            format!("ldc.r8 {text}")
+        } else {
+            format!("ldc.r8 float64(0x{:016X})", value.to_bits())
+        }
+    } else {
+        let value = value as f32;
+        let text = format!("{value:?}");
+        if plain(&text) {
+            format!("ldc.r4 {text}")
+        } else {
+            format!("ldc.r4 float32(0x{:08X})", value.to_bits())
+        }
+    }
+}
+
+/// A string literal for `ldstr`. Text outside printable ASCII is written as
+/// its UTF-16 bytes, so no escaping rules are involved.
+pub fn string_literal(value: &str) -> String {
+    let simple = value
+        .bytes()
+        .all(|b| (0x20..0x7f).contains(&b) || b == b'\n' || b == b'\t');
+    if simple {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t");
+        return format!("\"{escaped}\"");
+    }
+    let bytes: Vec<String> = value
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .map(|b| format!("{b:02X}"))
+        .collect();
+    format!("bytearray ({})", bytes.join(" "))
+}
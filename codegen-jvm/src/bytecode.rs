@@ -0,0 +1,478 @@
+//! An assembler for method bodies that tracks the operand stack, so it can
+//! compute `max_stack` and the stack map frame at every branch target.
+//!
+//! Frames always list the same locals: every local is given a value in a
+//! prologue before anything else runs, so each one has its declared type
+//! everywhere in the method. Only the stack differs between frames.
+//!
+//! Code after a `goto`, `return` or `athrow` is dropped until a label that
+//! is jumped to, so there is never dead code needing a frame of its own.
+
+use crate::classfile::{Code, ConstantPool, Frame, VType};
+use crate::descriptor::{self, Kind};
+
+pub mod op {
+    pub const ACONST_NULL: u8 = 0x01;
+    pub const ICONST_0: u8 = 0x03;
+    pub const LCONST_0: u8 = 0x09;
+    pub const FCONST_0: u8 = 0x0b;
+    pub const DCONST_0: u8 = 0x0e;
+    pub const BIPUSH: u8 = 0x10;
+    pub const SIPUSH: u8 = 0x11;
+    pub const LDC: u8 = 0x12;
+    pub const LDC_W: u8 = 0x13;
+    pub const LDC2_W: u8 = 0x14;
+    pub const ILOAD: u8 = 0x15;
+    pub const LLOAD: u8 = 0x16;
+    pub const FLOAD: u8 = 0x17;
+    pub const DLOAD: u8 = 0x18;
+    pub const ALOAD: u8 = 0x19;
+    pub const IALOAD: u8 = 0x2e;
+    pub const LALOAD: u8 = 0x2f;
+    pub const FALOAD: u8 = 0x30;
+    pub const DALOAD: u8 = 0x31;
+    pub const AALOAD: u8 = 0x32;
+    pub const BALOAD: u8 = 0x33;
+    pub const ISTORE: u8 = 0x36;
+    pub const LSTORE: u8 = 0x37;
+    pub const FSTORE: u8 = 0x38;
+    pub const DSTORE: u8 = 0x39;
+    pub const ASTORE: u8 = 0x3a;
+    pub const IASTORE: u8 = 0x4f;
+    pub const LASTORE: u8 = 0x50;
+    pub const FASTORE: u8 = 0x51;
+    pub const DASTORE: u8 = 0x52;
+    pub const AASTORE: u8 = 0x53;
+    pub const BASTORE: u8 = 0x54;
+    pub const POP: u8 = 0x57;
+    pub const POP2: u8 = 0x58;
+    pub const DUP: u8 = 0x59;
+    pub const DUP_X1: u8 = 0x5a;
+    pub const DUP_X2: u8 = 0x5b;
+    pub const DUP2: u8 = 0x5c;
+    pub const DUP2_X1: u8 = 0x5d;
+    pub const DUP2_X2: u8 = 0x5e;
+    pub const SWAP: u8 = 0x5f;
+    pub const IADD: u8 = 0x60;
+    pub const LADD: u8 = 0x61;
+    pub const FADD: u8 = 0x62;
+    pub const DADD: u8 = 0x63;
+    pub const ISUB: u8 = 0x64;
+    pub const LSUB: u8 = 0x65;
+    pub const FSUB: u8 = 0x66;
+    pub const DSUB: u8 = 0x67;
+    pub const IMUL: u8 = 0x68;
+    pub const LMUL: u8 = 0x69;
+    pub const FMUL: u8 = 0x6a;
+    pub const DMUL: u8 = 0x6b;
+    pub const IDIV: u8 = 0x6c;
+    pub const LDIV: u8 = 0x6d;
+    pub const FDIV: u8 = 0x6e;
+    pub const DDIV: u8 = 0x6f;
+    pub const INEG: u8 = 0x74;
+    pub const LNEG: u8 = 0x75;
+    pub const FNEG: u8 = 0x76;
+    pub const DNEG: u8 = 0x77;
+    pub const IAND: u8 = 0x7e;
+    pub const IXOR: u8 = 0x82;
+    pub const I2L: u8 = 0x85;
+    pub const L2I: u8 = 0x88;
+    pub const F2D: u8 = 0x8d;
+    pub const D2F: u8 = 0x90;
+    pub const I2B: u8 = 0x91;
+    pub const I2C: u8 = 0x92;
+    pub const I2S: u8 = 0x93;
+    pub const LCMP: u8 = 0x94;
+    pub const FCMPL: u8 = 0x95;
+    pub const FCMPG: u8 = 0x96;
+    pub const DCMPL: u8 = 0x97;
+    pub const DCMPG: u8 = 0x98;
+    pub const IFEQ: u8 = 0x99;
+    pub const IFNE: u8 = 0x9a;
+    pub const IFLT: u8 = 0x9b;
+    pub const IFGE: u8 = 0x9c;
+    pub const IFGT: u8 = 0x9d;
+    pub const IFLE: u8 = 0x9e;
+    pub const IF_ICMPEQ: u8 = 0x9f;
+    pub const IF_ICMPNE: u8 = 0xa0;
+    pub const IF_ICMPLT: u8 = 0xa1;
+    pub const IF_ICMPGE: u8 = 0xa2;
+    pub const IF_ICMPGT: u8 = 0xa3;
+    pub const IF_ICMPLE: u8 = 0xa4;
+    pub const GOTO: u8 = 0xa7;
+    pub const IRETURN: u8 = 0xac;
+    pub const LRETURN: u8 = 0xad;
+    pub const FRETURN: u8 = 0xae;
+    pub const DRETURN: u8 = 0xaf;
+    pub const ARETURN: u8 = 0xb0;
+    pub const RETURN: u8 = 0xb1;
+    pub const GETSTATIC: u8 = 0xb2;
+    pub const PUTSTATIC: u8 = 0xb3;
+    pub const GETFIELD: u8 = 0xb4;
+    pub const PUTFIELD: u8 = 0xb5;
+    pub const INVOKEVIRTUAL: u8 = 0xb6;
+    pub const INVOKESPECIAL: u8 = 0xb7;
+    pub const INVOKESTATIC: u8 = 0xb8;
+    pub const NEW: u8 = 0xbb;
+    pub const NEWARRAY: u8 = 0xbc;
+    pub const ANEWARRAY: u8 = 0xbd;
+    pub const ATHROW: u8 = 0xbf;
+    pub const CHECKCAST: u8 = 0xc0;
+    pub const INSTANCEOF: u8 = 0xc1;
+    pub const WIDE: u8 = 0xc4;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Debug, Default)]
+struct LabelState {
+    offset: Option<usize>,
+    /// The stack on arrival, from the first jump or from falling through.
+    stack: Option<Vec<VType>>,
+    targeted: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Assembler {
+    bytes: Vec<u8>,
+    stack: Vec<VType>,
+    max_stack: u16,
+    /// Types of the locals, one entry per local (wide ones take two slots).
+    locals: Vec<VType>,
+    next_slot: u16,
+    labels: Vec<LabelState>,
+    /// Branch instructions waiting for their target: the offset of the
+    /// instruction and of its 16-bit operand.
+    fixups: Vec<(usize, usize, Label)>,
+    unreachable: bool,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the next local slot. Parameters come first, in order.
+    pub fn local(&mut self, ty: VType) -> u16 {
+        let slot = self.next_slot;
+        self.next_slot += ty.size();
+        self.locals.push(ty);
+        slot
+    }
+
+    /// Every local declared so far, as `(slot, type)`.
+    pub fn locals(&self) -> Vec<(u16, VType)> {
+        let mut slot = 0;
+        self.locals
+            .iter()
+            .map(|ty| {
+                let entry = (slot, ty.clone());
+                slot += ty.size();
+                entry
+            })
+            .collect()
+    }
+
+    /// Emits an instruction that pops `pops` values and pushes `pushes`.
+    pub fn emit(&mut self, bytes: &[u8], pops: usize, pushes: &[VType]) {
+        if self.unreachable {
+            return;
+        }
+        self.bytes.extend(bytes);
+        assert!(self.stack.len() >= pops, "stack underflow at {:?}", bytes);
+        self.stack.truncate(self.stack.len() - pops);
+        self.stack.extend(pushes.iter().cloned());
+        let depth: u16 = self.stack.iter().map(VType::size).sum();
+        self.max_stack = self.max_stack.max(depth);
+        if matches!(
+            bytes[0],
+            op::IRETURN
+                | op::LRETURN
+                | op::FRETURN
+                | op::DRETURN
+                | op::ARETURN
+                | op::RETURN
+                | op::ATHROW
+        ) {
+            self.unreachable = true;
+        }
+    }
+
+    /// An instruction with a one-byte operand.
+    pub fn emit_u8(&mut self, opcode: u8, operand: u8, pops: usize, pushes: &[VType]) {
+        self.emit(&[opcode, operand], pops, pushes);
+    }
+
+    /// An instruction with a two-byte operand, such as a constant pool
+    /// index.
+    pub fn emit_u16(&mut self, opcode: u8, operand: u16, pops: usize, pushes: &[VType]) {
+        let [hi, lo] = operand.to_be_bytes();
+        self.emit(&[opcode, hi, lo], pops, pushes);
+    }
+
+    /// An instruction without operands.
+    pub fn op(&mut self, opcode: u8, pops: usize, pushes: &[VType]) {
+        self.emit(&[opcode], pops, pushes);
+    }
+
+    /// Duplicates the value on top of the stack.
+    pub fn dup(&mut self) {
+        let Some(top) = self.stack.last().cloned() else {
+            return;
+        };
+        let opcode = if top.size() == 2 { op::DUP2 } else { op::DUP };
+        self.op(opcode, 1, &[top.clone(), top]);
+    }
+
+    /// Discards the value on top of the stack.
+    pub fn pop(&mut self) {
+        let Some(top) = self.stack.last() else {
+            return;
+        };
+        let opcode = if top.size() == 2 { op::POP2 } else { op::POP };
+        self.op(opcode, 1, &[]);
+    }
+
+    /// Loads local `slot`, which holds a value of type `descriptor`.
+    pub fn load(&mut self, descriptor: &str, slot: u16) {
+        let opcode = Kind::of(descriptor).load();
+        self.local_op(opcode, slot, 0, &[VType::of(descriptor)]);
+    }
+
+    /// Stores the value on top of the stack in local `slot`.
+    pub fn store(&mut self, descriptor: &str, slot: u16) {
+        let opcode = Kind::of(descriptor).store();
+        self.local_op(opcode, slot, 1, &[]);
+    }
+
+    fn local_op(&mut self, opcode: u8, slot: u16, pops: usize, pushes: &[VType]) {
+        match u8::try_from(slot) {
+            Ok(slot) => self.emit(&[opcode, slot], pops, pushes),
+            Err(_) => {
+                let [hi, lo] = slot.to_be_bytes();
+                self.emit(&[op::WIDE, opcode, hi, lo], pops, pushes);
+            }
+        }
+    }
+
+    pub fn push_int(&mut self, pool: &mut ConstantPool, value: i32) {
+        match value {
+            -1..=5 => self.op((op::ICONST_0 as i32 + value) as u8, 0, &[VType::Int]),
+            -128..=127 => self.emit_u8(op::BIPUSH, value as u8, 0, &[VType::Int]),
+            -32768..=32767 => self.emit_u16(op::SIPUSH, value as u16, 0, &[VType::Int]),
+            _ => {
+                let index = pool.integer(value);
+                self.ldc(index, VType::Int);
+            }
+        }
+    }
+
+    pub fn push_long(&mut self, pool: &mut ConstantPool, value: i64) {
+        match value {
+            0 | 1 => self.op(op::LCONST_0 + value as u8, 0, &[VType::Long]),
+            _ => {
+                let index = pool.long(value);
+                self.emit_u16(op::LDC2_W, index, 0, &[VType::Long]);
+            }
+        }
+    }
+
+    pub fn push_float(&mut self, pool: &mut ConstantPool, value: f32) {
+        if value.to_bits() == 0 {
+            self.op(op::FCONST_0, 0, &[VType::Float]);
+        } else {
+            let index = pool.float(value);
+            self.ldc(index, VType::Float);
+        }
+    }
+
+    pub fn push_double(&mut self, pool: &mut ConstantPool, value: f64) {
+        if value.to_bits() == 0 {
+            self.op(op::DCONST_0, 0, &[VType::Double]);
+        } else {
+            let index = pool.double(value);
+            self.emit_u16(op::LDC2_W, index, 0, &[VType::Double]);
+        }
+    }
+
+    /// Pushes the default value of a local of type `descriptor`: zero,
+    /// `false` or `null`.
+    pub fn push_default(&mut self, descriptor: &str) {
+        let (opcode, ty) = match Kind::of(descriptor) {
+            Kind::Int => (op::ICONST_0, VType::Int),
+            Kind::Long => (op::LCONST_0, VType::Long),
+            Kind::Float => (op::FCONST_0, VType::Float),
+            Kind::Double => (op::DCONST_0, VType::Double),
+            Kind::Reference => (op::ACONST_NULL, VType::Null),
+        };
+        self.op(opcode, 0, &[ty]);
+    }
+
+    /// `ldc` of a one-slot constant.
+    pub fn ldc(&mut self, index: u16, ty: VType) {
+        match u8::try_from(index) {
+            Ok(index) => self.emit_u8(op::LDC, index, 0, &[ty]),
+            Err(_) => self.emit_u16(op::LDC_W, index, 0, &[ty]),
+        }
+    }
+
+    /// `invokestatic`, `invokevirtual` or `invokespecial`.
+    pub fn invoke(
+        &mut self,
+        pool: &mut ConstantPool,
+        opcode: u8,
+        class: &str,
+        name: &str,
+        descriptor: &str,
+    ) {
+        let (params, ret) = descriptor::parse_method(descriptor).expect("bad method descriptor");
+        let receiver = usize::from(opcode != op::INVOKESTATIC);
+        let pushes: Vec<VType> = ret.map(VType::of).into_iter().collect();
+        let index = pool.method(class, name, descriptor);
+        self.emit_u16(opcode, index, params.len() + receiver, &pushes);
+    }
+
+    /// `getstatic`, `putstatic`, `getfield` or `putfield`.
+    pub fn field(
+        &mut self,
+        pool: &mut ConstantPool,
+        opcode: u8,
+        class: &str,
+        name: &str,
+        descriptor: &str,
+    ) {
+        let index = pool.field(class, name, descriptor);
+        let ty = VType::of(descriptor);
+        match opcode {
+            op::GETSTATIC => self.emit_u16(opcode, index, 0, &[ty]),
+            op::PUTSTATIC => self.emit_u16(opcode, index, 1, &[]),
+            op::GETFIELD => self.emit_u16(opcode, index, 1, &[ty]),
+            _ => self.emit_u16(opcode, index, 2, &[]),
+        }
+    }
+
+    /// `new`, `checkcast` or `instanceof` of `class`.
+    pub fn class_op(&mut self, pool: &mut ConstantPool, opcode: u8, class: &str) {
+        let index = pool.class(class);
+        match opcode {
+            op::INSTANCEOF => self.emit_u16(opcode, index, 1, &[VType::Int]),
+            op::NEW => self.emit_u16(opcode, index, 0, &[VType::Object(class.to_string())]),
+            _ => self.emit_u16(opcode, index, 1, &[VType::Object(class.to_string())]),
+        }
+    }
+
+    /// A new array with one element of type `descriptor`, which is left
+    /// on the stack.
+    pub fn new_cell(&mut self, pool: &mut ConstantPool, descriptor: &str) {
+        let cell = VType::Object(format!("[{descriptor}"));
+        self.op(op::ICONST_0 + 1, 0, &[VType::Int]);
+        match descriptor::array_type(descriptor) {
+            Some(atype) => self.emit_u8(op::NEWARRAY, atype, 1, &[cell]),
+            None => {
+                let index = pool.class(descriptor::class_name(descriptor));
+                self.emit_u16(op::ANEWARRAY, index, 1, &[cell]);
+            }
+        }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(LabelState::default());
+        Label(self.labels.len() - 1)
+    }
+
+    /// A conditional or unconditional jump to `target`, popping `pops`
+    /// values first.
+    pub fn branch(&mut self, opcode: u8, target: Label, pops: usize) {
+        if self.unreachable {
+            return;
+        }
+        let at = self.bytes.len();
+        self.emit(&[opcode, 0, 0], pops, &[]);
+        self.fixups.push((at, at + 1, target));
+
+        let stack = self.stack.clone();
+        let state = &mut self.labels[target.0];
+        state.targeted = true;
+        match &state.stack {
+            Some(expected) => debug_assert_eq!(*expected, stack, "stack mismatch at a jump"),
+            None => state.stack = Some(stack),
+        }
+        if opcode == op::GOTO {
+            self.unreachable = true;
+        }
+    }
+
+    /// Places `label` here. Code after an unconditional jump becomes
+    /// reachable again if anything jumps to the label.
+    pub fn place(&mut self, label: Label) {
+        let state = &mut self.labels[label.0];
+        state.offset = Some(self.bytes.len());
+        match (&state.stack, self.unreachable) {
+            (Some(stack), true) => {
+                self.stack = stack.clone();
+                self.unreachable = false;
+            }
+            (None, false) => state.stack = Some(self.stack.clone()),
+            _ => {}
+        }
+    }
+
+    /// Places `label` at the start of a block, which begins with an empty
+    /// stack and is reachable even if the jumps to it come later.
+    pub fn place_block(&mut self, label: Label) {
+        self.labels[label.0].stack.get_or_insert_with(Vec::new);
+        self.stack.clear();
+        self.unreachable = false;
+        self.place(label);
+    }
+
+    /// Resolves jumps and computes the frames. `prologue` runs first: code
+    /// without branches that gives every local its initial value.
+    pub fn finish(mut self, prologue: Assembler) -> Code {
+        for (at, operand, label) in std::mem::take(&mut self.fixups) {
+            let target = self.labels[label.0]
+                .offset
+                .expect("jump to a label never placed");
+            let delta = (target as isize - at as isize) as i16;
+            self.bytes[operand..operand + 2].copy_from_slice(&delta.to_be_bytes());
+        }
+
+        let shift = prologue.bytes.len();
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut targets: Vec<(usize, Vec<VType>)> = self
+            .labels
+            .iter()
+            .filter(|l| l.targeted)
+            .map(|l| {
+                (
+                    l.offset.unwrap() + shift,
+                    l.stack.clone().unwrap_or_default(),
+                )
+            })
+            .collect();
+        targets.sort_by_key(|(offset, _)| *offset);
+        for (offset, stack) in targets {
+            if frames
+                .last()
+                .is_some_and(|f| usize::from(f.offset) == offset)
+            {
+                continue;
+            }
+            frames.push(Frame {
+                offset: offset as u16,
+                locals: self.locals.clone(),
+                stack,
+            });
+        }
+
+        let mut bytes = prologue.bytes;
+        bytes.extend(self.bytes);
+        Code {
+            max_stack: self.max_stack.max(prologue.max_stack),
+            max_locals: self.next_slot,
+            bytes,
+            frames,
+        }
+    }
+}
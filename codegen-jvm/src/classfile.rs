@@ -0,0 +1,393 @@
+//! The class file format (JVMS chapter 4), as far as this backend uses it.
+
+use std::collections::HashMap;
+
+/// Class files are written for Java 17, the first version with sealed
+/// classes, which is what Canary enums become.
+pub const MAJOR_VERSION: u16 = 61;
+
+pub const ACC_PUBLIC: u16 = 0x0001;
+pub const ACC_STATIC: u16 = 0x0008;
+pub const ACC_FINAL: u16 = 0x0010;
+pub const ACC_SUPER: u16 = 0x0020;
+pub const ACC_ABSTRACT: u16 = 0x0400;
+
+/// `reference_kind` of a method handle to a static method.
+pub const REF_INVOKE_STATIC: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Constant {
+    Utf8(String),
+    Integer(i32),
+    /// By bits, so every constant can be compared and hashed.
+    Float(u32),
+    Long(i64),
+    Double(u64),
+    Class(u16),
+    String(u16),
+    Fieldref(u16, u16),
+    Methodref(u16, u16),
+    NameAndType(u16, u16),
+    MethodHandle(u8, u16),
+}
+
+impl Constant {
+    pub fn tag(&self) -> u8 {
+        match self {
+            Constant::Utf8(_) => 1,
+            Constant::Integer(_) => 3,
+            Constant::Float(_) => 4,
+            Constant::Long(_) => 5,
+            Constant::Double(_) => 6,
+            Constant::Class(_) => 7,
+            Constant::String(_) => 8,
+            Constant::Fieldref(..) => 9,
+            Constant::Methodref(..) => 10,
+            Constant::NameAndType(..) => 12,
+            Constant::MethodHandle(..) => 15,
+        }
+    }
+
+    /// Longs and doubles take two entries of the pool.
+    pub fn is_wide(&self) -> bool {
+        matches!(self, Constant::Long(_) | Constant::Double(_))
+    }
+}
+
+/// A constant pool that hands out each constant's index, adding it on
+/// first use.
+#[derive(Debug, Default)]
+pub struct ConstantPool {
+    /// Entry `i` has index `i + 1`; the entry after a wide constant is
+    /// `None`.
+    entries: Vec<Option<Constant>>,
+    indices: HashMap<Constant, u16>,
+}
+
+impl ConstantPool {
+    fn add(&mut self, constant: Constant) -> u16 {
+        if let Some(&index) = self.indices.get(&constant) {
+            return index;
+        }
+        let index = self.entries.len() as u16 + 1;
+        let wide = constant.is_wide();
+        self.entries.push(Some(constant.clone()));
+        if wide {
+            self.entries.push(None);
+        }
+        self.indices.insert(constant, index);
+        index
+    }
+
+    pub fn utf8(&mut self, text: &str) -> u16 {
+        self.add(Constant::Utf8(text.to_string()))
+    }
+
+    pub fn class(&mut self, name: &str) -> u16 {
+        let name = self.utf8(name);
+        self.add(Constant::Class(name))
+    }
+
+    pub fn string(&mut self, text: &str) -> u16 {
+        let text = self.utf8(text);
+        self.add(Constant::String(text))
+    }
+
+    pub fn integer(&mut self, value: i32) -> u16 {
+        self.add(Constant::Integer(value))
+    }
+
+    pub fn float(&mut self, value: f32) -> u16 {
+        self.add(Constant::Float(value.to_bits()))
+    }
+
+    pub fn long(&mut self, value: i64) -> u16 {
+        self.add(Constant::Long(value))
+    }
+
+    pub fn double(&mut self, value: f64) -> u16 {
+        self.add(Constant::Double(value.to_bits()))
+    }
+
+    fn name_and_type(&mut self, name: &str, descriptor: &str) -> u16 {
+        let name = self.utf8(name);
+        let descriptor = self.utf8(descriptor);
+        self.add(Constant::NameAndType(name, descriptor))
+    }
+
+    pub fn field(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let class = self.class(class);
+        let nat = self.name_and_type(name, descriptor);
+        self.add(Constant::Fieldref(class, nat))
+    }
+
+    pub fn method(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let class = self.class(class);
+        let nat = self.name_and_type(name, descriptor);
+        self.add(Constant::Methodref(class, nat))
+    }
+
+    pub fn static_handle(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        let method = self.method(class, name, descriptor);
+        self.add(Constant::MethodHandle(REF_INVOKE_STATIC, method))
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        put_u16(out, self.entries.len() as u16 + 1);
+        for constant in self.entries.iter().flatten() {
+            out.push(constant.tag());
+            match constant {
+                Constant::Utf8(text) => {
+                    let bytes = modified_utf8(text);
+                    put_u16(out, bytes.len() as u16);
+                    out.extend(bytes);
+                }
+                Constant::Integer(value) => out.extend(value.to_be_bytes()),
+                Constant::Float(bits) => out.extend(bits.to_be_bytes()),
+                Constant::Long(value) => out.extend(value.to_be_bytes()),
+                Constant::Double(bits) => out.extend(bits.to_be_bytes()),
+                Constant::Class(index) | Constant::String(index) => put_u16(out, *index),
+                Constant::Fieldref(a, b)
+                | Constant::Methodref(a, b)
+                | Constant::NameAndType(a, b) => {
+                    put_u16(out, *a);
+                    put_u16(out, *b);
+                }
+                Constant::MethodHandle(kind, index) => {
+                    out.push(*kind);
+                    put_u16(out, *index);
+                }
+            }
+        }
+    }
+}
+
+/// The JVM's variant of UTF-8: NUL takes two bytes and characters outside
+/// the BMP are written as two encoded surrogates.
+pub fn modified_utf8(text: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for unit in text.encode_utf16() {
+        match unit {
+            0x0001..=0x007f => out.push(unit as u8),
+            0x0000 | 0x0080..=0x07ff => {
+                out.push(0xc0 | (unit >> 6) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                out.push(0xe0 | (unit >> 12) as u8);
+                out.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    out
+}
+
+pub fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend(value.to_be_bytes());
+}
+
+pub fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_be_bytes());
+}
+
+/// A verification type in a stack map frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VType {
+    Int,
+    Float,
+    Long,
+    Double,
+    Null,
+    /// A class by internal name, or an array by descriptor.
+    Object(String),
+}
+
+impl VType {
+    /// The type of a value with the field descriptor `descriptor`.
+    pub fn of(descriptor: &str) -> VType {
+        match descriptor.as_bytes()[0] {
+            b'Z' | b'B' | b'C' | b'S' | b'I' => VType::Int,
+            b'F' => VType::Float,
+            b'J' => VType::Long,
+            b'D' => VType::Double,
+            b'L' => VType::Object(descriptor[1..descriptor.len() - 1].to_string()),
+            _ => VType::Object(descriptor.to_string()),
+        }
+    }
+
+    /// Stack and local slots taken.
+    pub fn size(&self) -> u16 {
+        match self {
+            VType::Long | VType::Double => 2,
+            _ => 1,
+        }
+    }
+
+    fn write(&self, pool: &mut ConstantPool, out: &mut Vec<u8>) {
+        match self {
+            VType::Int => out.push(1),
+            VType::Float => out.push(2),
+            VType::Double => out.push(3),
+            VType::Long => out.push(4),
+            VType::Null => out.push(5),
+            VType::Object(name) => {
+                out.push(7);
+                let class = pool.class(name);
+                put_u16(out, class);
+            }
+        }
+    }
+}
+
+/// The stack map frame at `offset`. Every frame is written as a
+/// `full_frame`, which is larger than needed but always valid.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub offset: u16,
+    pub locals: Vec<VType>,
+    pub stack: Vec<VType>,
+}
+
+#[derive(Debug, Default)]
+pub struct Code {
+    pub max_stack: u16,
+    pub max_locals: u16,
+    pub bytes: Vec<u8>,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub access: u16,
+    pub name: String,
+    pub descriptor: String,
+}
+
+#[derive(Debug)]
+pub struct Method {
+    pub access: u16,
+    pub name: String,
+    pub descriptor: String,
+    pub code: Code,
+}
+
+#[derive(Debug)]
+pub struct ClassBuilder {
+    pub pool: ConstantPool,
+    pub access: u16,
+    pub name: String,
+    pub super_name: String,
+    pub fields: Vec<Field>,
+    pub methods: Vec<Method>,
+    /// Set for sealed classes.
+    pub permitted: Vec<String>,
+}
+
+impl ClassBuilder {
+    pub fn new(access: u16, name: &str, super_name: &str) -> Self {
+        Self {
+            pool: ConstantPool::default(),
+            access,
+            name: name.to_string(),
+            super_name: super_name.to_string(),
+            fields: Vec::new(),
+            methods: Vec::new(),
+            permitted: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let pool = &mut self.pool;
+        let mut body = Vec::new();
+        put_u16(&mut body, self.access);
+        put_u16(&mut body, pool.class(&self.name));
+        put_u16(&mut body, pool.class(&self.super_name));
+        put_u16(&mut body, 0);
+
+        put_u16(&mut body, self.fields.len() as u16);
+        for field in &self.fields {
+            put_u16(&mut body, field.access);
+            put_u16(&mut body, pool.utf8(&field.name));
+            put_u16(&mut body, pool.utf8(&field.descriptor));
+            put_u16(&mut body, 0);
+        }
+
+        put_u16(&mut body, self.methods.len() as u16);
+        for method in &self.methods {
+            put_u16(&mut body, method.access);
+            put_u16(&mut body, pool.utf8(&method.name));
+            put_u16(&mut body, pool.utf8(&method.descriptor));
+            if method.access & ACC_ABSTRACT != 0 {
+                put_u16(&mut body, 0);
+                continue;
+            }
+            put_u16(&mut body, 1);
+            write_code(pool, &method.code, &mut body);
+        }
+
+        if self.permitted.is_empty() {
+            put_u16(&mut body, 0);
+        } else {
+            put_u16(&mut body, 1);
+            put_u16(&mut body, pool.utf8("PermittedSubclasses"));
+            put_u32(&mut body, 2 + 2 * self.permitted.len() as u32);
+            put_u16(&mut body, self.permitted.len() as u16);
+            for name in &self.permitted {
+                put_u16(&mut body, pool.class(name));
+            }
+        }
+
+        let mut out = Vec::new();
+        put_u32(&mut out, 0xcafe_babe);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, MAJOR_VERSION);
+        pool.write(&mut out);
+        out.extend(body);
+        out
+    }
+}
+
+fn write_code(pool: &mut ConstantPool, code: &Code, out: &mut Vec<u8>) {
+    let mut attributes = Vec::new();
+    let mut count = 0;
+    if !code.frames.is_empty() {
+        count += 1;
+        let mut table = Vec::new();
+        put_u16(&mut table, code.frames.len() as u16);
+        let mut previous: Option<u16> = None;
+        for frame in &code.frames {
+            let delta = match previous {
+                None => frame.offset,
+                Some(previous) => frame.offset - previous - 1,
+            };
+            previous = Some(frame.offset);
+            table.push(255);
+            put_u16(&mut table, delta);
+            put_u16(&mut table, frame.locals.len() as u16);
+            for local in &frame.locals {
+                local.write(pool, &mut table);
+            }
+            put_u16(&mut table, frame.stack.len() as u16);
+            for item in &frame.stack {
+                item.write(pool, &mut table);
+            }
+        }
+        put_u16(&mut attributes, pool.utf8("StackMapTable"));
+        put_u32(&mut attributes, table.len() as u32);
+        attributes.extend(table);
+    }
+
+    put_u16(out, pool.utf8("Code"));
+    put_u32(
+        out,
+        (2 + 2 + 4 + code.bytes.len() + 2 + 2 + attributes.len()) as u32,
+    );
+    put_u16(out, code.max_stack);
+    put_u16(out, code.max_locals);
+    put_u32(out, code.bytes.len() as u32);
+    out.extend(&code.bytes);
+    put_u16(out, 0);
+    put_u16(out, count);
+    out.extend(attributes);
+}
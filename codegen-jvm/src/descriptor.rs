@@ -0,0 +1,137 @@
+//! Descriptors and the instructions that go with each kind of value.
+
+use crate::bytecode::op;
+
+/// How the JVM computes with a value: `boolean`, `byte` and friends are
+/// all `int`s on the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int,
+    Long,
+    Float,
+    Double,
+    Reference,
+}
+
+impl Kind {
+    pub fn of(descriptor: &str) -> Kind {
+        match descriptor.as_bytes()[0] {
+            b'J' => Kind::Long,
+            b'F' => Kind::Float,
+            b'D' => Kind::Double,
+            b'L' | b'[' => Kind::Reference,
+            _ => Kind::Int,
+        }
+    }
+
+    pub fn load(self) -> u8 {
+        match self {
+            Kind::Int => op::ILOAD,
+            Kind::Long => op::LLOAD,
+            Kind::Float => op::FLOAD,
+            Kind::Double => op::DLOAD,
+            Kind::Reference => op::ALOAD,
+        }
+    }
+
+    pub fn store(self) -> u8 {
+        match self {
+            Kind::Int => op::ISTORE,
+            Kind::Long => op::LSTORE,
+            Kind::Float => op::FSTORE,
+            Kind::Double => op::DSTORE,
+            Kind::Reference => op::ASTORE,
+        }
+    }
+
+    pub fn ret(self) -> u8 {
+        match self {
+            Kind::Int => op::IRETURN,
+            Kind::Long => op::LRETURN,
+            Kind::Float => op::FRETURN,
+            Kind::Double => op::DRETURN,
+            Kind::Reference => op::ARETURN,
+        }
+    }
+}
+
+/// `xaload` for an array with elements of type `descriptor`.
+pub fn array_load(descriptor: &str) -> u8 {
+    match descriptor {
+        "Z" => op::BALOAD,
+        _ => match Kind::of(descriptor) {
+            Kind::Int => op::IALOAD,
+            Kind::Long => op::LALOAD,
+            Kind::Float => op::FALOAD,
+            Kind::Double => op::DALOAD,
+            Kind::Reference => op::AALOAD,
+        },
+    }
+}
+
+/// `xastore` for an array with elements of type `descriptor`.
+pub fn array_store(descriptor: &str) -> u8 {
+    match descriptor {
+        "Z" => op::BASTORE,
+        _ => match Kind::of(descriptor) {
+            Kind::Int => op::IASTORE,
+            Kind::Long => op::LASTORE,
+            Kind::Float => op::FASTORE,
+            Kind::Double => op::DASTORE,
+            Kind::Reference => op::AASTORE,
+        },
+    }
+}
+
+/// The `atype` operand of `newarray`, or `None` for arrays of references,
+/// which take `anewarray`.
+pub fn array_type(descriptor: &str) -> Option<u8> {
+    Some(match descriptor {
+        "Z" => 4,
+        "F" => 6,
+        "D" => 7,
+        "I" => 10,
+        "J" => 11,
+        _ => return None,
+    })
+}
+
+/// The class named by a reference type's descriptor, as `anewarray`,
+/// `checkcast` and the constant pool want it.
+pub fn class_name(descriptor: &str) -> &str {
+    match descriptor.strip_prefix('L') {
+        Some(name) => name.strip_suffix(';').unwrap_or(name),
+        None => descriptor,
+    }
+}
+
+/// Splits a method descriptor into its parameter descriptors and its
+/// return descriptor, which is `None` for `V`.
+pub fn parse_method(descriptor: &str) -> Option<(Vec<&str>, Option<&str>)> {
+    let rest = descriptor.strip_prefix('(')?;
+    let close = rest.find(')')?;
+    let (mut params, ret) = (&rest[..close], &rest[close + 1..]);
+    let mut list = Vec::new();
+    while !params.is_empty() {
+        let len = field_len(params)?;
+        list.push(&params[..len]);
+        params = &params[len..];
+    }
+    let ret = match ret {
+        "V" => None,
+        ret if field_len(ret) == Some(ret.len()) => Some(ret),
+        _ => return None,
+    };
+    Some((list, ret))
+}
+
+/// The length of the field descriptor at the start of `text`.
+fn field_len(text: &str) -> Option<usize> {
+    let dims = text.bytes().take_while(|&b| b == b'[').count();
+    let len = match text.as_bytes().get(dims)? {
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' => 1,
+        b'L' => text[dims..].find(';')? + 1,
+        _ => return None,
+    };
+    Some(dims + len)
+}
@@ -0,0 +1,805 @@
+//! Reads class files back: [`dump`] checks one and disassembles it.
+//!
+//! The checks are the structural part of the JVM's verifier: the constant
+//! pool is well-formed, every instruction is known and decodes, branches
+//! land on instructions, every branch target and every instruction after an
+//! unconditional jump has a stack map frame, and the operand stack depth
+//! agrees with the frames and stays within `max_stack`. Types are not
+//! checked beyond that; running the classes on a real JVM does that.
+
+use std::fmt::Write;
+
+use crate::bytecode::op;
+use crate::classfile::{ACC_ABSTRACT, ACC_FINAL, ACC_PUBLIC, ACC_STATIC, ACC_SUPER, Constant};
+use crate::descriptor::{self, Kind};
+
+/// Checks the class file `bytes` and prints it like `javap -c`.
+pub fn dump(bytes: &[u8]) -> Result<String, String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.u32()? != 0xcafe_babe {
+        return Err("not a class file".to_string());
+    }
+    let minor = r.u16()?;
+    let major = r.u16()?;
+    let pool = Pool::read(&mut r)?;
+
+    let mut out = String::new();
+    let access = r.u16()?;
+    let this = pool.class(r.u16()?)?;
+    let super_name = pool.class(r.u16()?)?;
+    writeln!(
+        out,
+        "{}class {this} extends {super_name} (version {major}.{minor})",
+        flags(access)
+    )
+    .unwrap();
+    if r.u16()? != 0 {
+        return Err("interfaces are not supported".to_string());
+    }
+
+    let field_count = r.u16()?;
+    for _ in 0..field_count {
+        let access = r.u16()?;
+        let name = pool.utf8(r.u16()?)?;
+        let descriptor = pool.utf8(r.u16()?)?;
+        if descriptor::parse_method(&format!("(){descriptor}")).is_none() {
+            return Err(format!(
+                "field `{name}` has a bad descriptor `{descriptor}`"
+            ));
+        }
+        writeln!(out, "  {}{name}: {descriptor}", flags(access)).unwrap();
+        skip_attributes(&mut r)?;
+    }
+
+    let method_count = r.u16()?;
+    for _ in 0..method_count {
+        let access = r.u16()?;
+        let name = pool.utf8(r.u16()?)?;
+        let descriptor = pool.utf8(r.u16()?)?;
+        writeln!(out, "\n  {}{name}{descriptor}", flags(access)).unwrap();
+        let method = MethodInfo {
+            name,
+            descriptor,
+            is_static: access & ACC_STATIC != 0,
+        };
+        let mut code = false;
+        for _ in 0..r.u16()? {
+            let name = pool.utf8(r.u16()?)?;
+            let len = r.u32()? as usize;
+            let body = r.take(len)?;
+            match name {
+                "Code" => {
+                    code = true;
+                    let mut r = Reader {
+                        bytes: body,
+                        pos: 0,
+                    };
+                    dump_code(&mut r, &pool, &method, &mut out)
+                        .map_err(|e| format!("in `{}{}`: {e}", method.name, method.descriptor))?;
+                }
+                name => writeln!(out, "    attribute {name}").unwrap(),
+            }
+        }
+        if !code && access & ACC_ABSTRACT == 0 {
+            return Err(format!("method `{name}` has no code"));
+        }
+    }
+
+    for _ in 0..r.u16()? {
+        let name = pool.utf8(r.u16()?)?;
+        let len = r.u32()? as usize;
+        let body = r.take(len)?;
+        match name {
+            "PermittedSubclasses" => {
+                let mut r = Reader {
+                    bytes: body,
+                    pos: 0,
+                };
+                let mut permitted = Vec::new();
+                for _ in 0..r.u16()? {
+                    permitted.push(pool.class(r.u16()?)?);
+                }
+                writeln!(out, "\n  permits {}", permitted.join(", ")).unwrap();
+            }
+            name => writeln!(out, "\n  attribute {name}").unwrap(),
+        }
+    }
+    if r.pos != bytes.len() {
+        return Err("trailing bytes after the class".to_string());
+    }
+    Ok(out)
+}
+
+/// The access flags, each followed by a space.
+fn flags(access: u16) -> String {
+    let mut out = String::new();
+    for (flag, name) in [
+        (ACC_PUBLIC, "public"),
+        (ACC_STATIC, "static"),
+        (ACC_FINAL, "final"),
+        (ACC_SUPER, "super"),
+        (ACC_ABSTRACT, "abstract"),
+    ] {
+        if access & flag != 0 {
+            out.push_str(name);
+            out.push(' ');
+        }
+    }
+    out
+}
+
+fn skip_attributes(r: &mut Reader) -> Result<(), String> {
+    for _ in 0..r.u16()? {
+        r.u16()?;
+        let len = r.u32()? as usize;
+        r.take(len)?;
+    }
+    Ok(())
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], String> {
+        let end = self.pos + len;
+        let bytes = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| format!("unexpected end of data at byte {}", self.pos))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+struct Pool {
+    /// Entry `i` has index `i`; index 0 and the entries after wide
+    /// constants are `None`.
+    entries: Vec<Option<Constant>>,
+}
+
+impl Pool {
+    fn read(r: &mut Reader) -> Result<Pool, String> {
+        let count = r.u16()?;
+        let mut entries = vec![None];
+        while entries.len() < usize::from(count) {
+            let constant = match r.u8()? {
+                1 => {
+                    let len = r.u16()?;
+                    Constant::Utf8(decode_utf8(r.take(len.into())?)?)
+                }
+                3 => Constant::Integer(r.u32()? as i32),
+                4 => Constant::Float(r.u32()?),
+                5 => Constant::Long((u64::from(r.u32()?) << 32 | u64::from(r.u32()?)) as i64),
+                6 => Constant::Double(u64::from(r.u32()?) << 32 | u64::from(r.u32()?)),
+                7 => Constant::Class(r.u16()?),
+                8 => Constant::String(r.u16()?),
+                9 => Constant::Fieldref(r.u16()?, r.u16()?),
+                10 => Constant::Methodref(r.u16()?, r.u16()?),
+                12 => Constant::NameAndType(r.u16()?, r.u16()?),
+                15 => Constant::MethodHandle(r.u8()?, r.u16()?),
+                tag => return Err(format!("unsupported constant tag {tag}")),
+            };
+            let wide = constant.is_wide();
+            entries.push(Some(constant));
+            if wide {
+                entries.push(None);
+            }
+        }
+        if entries.len() != usize::from(count) {
+            return Err("a wide constant overruns the constant pool".to_string());
+        }
+
+        let pool = Pool { entries };
+        for i in 1..pool.entries.len() as u16 {
+            pool.check(i)?;
+        }
+        Ok(pool)
+    }
+
+    fn get(&self, index: u16) -> Result<&Constant, String> {
+        self.entries
+            .get(usize::from(index))
+            .and_then(Option::as_ref)
+            .ok_or_else(|| format!("bad constant pool index {index}"))
+    }
+
+    /// Checks that the entries `index` refers to have the right kinds.
+    fn check(&self, index: u16) -> Result<(), String> {
+        let Some(constant) = &self.entries[usize::from(index)] else {
+            return Ok(());
+        };
+        match *constant {
+            Constant::Class(name) | Constant::String(name) => {
+                self.utf8(name)?;
+            }
+            Constant::Fieldref(class, nat) | Constant::Methodref(class, nat) => {
+                self.class(class)?;
+                let (_, descriptor) = self.name_and_type(nat)?;
+                let is_method = descriptor.starts_with('(');
+                if is_method != matches!(constant, Constant::Methodref(..)) {
+                    return Err(format!("constant {index} has a bad descriptor"));
+                }
+            }
+            Constant::NameAndType(name, descriptor) => {
+                self.utf8(name)?;
+                self.utf8(descriptor)?;
+            }
+            Constant::MethodHandle(kind, method) => {
+                if !(1..=9).contains(&kind) {
+                    return Err(format!("constant {index} has a bad handle kind {kind}"));
+                }
+                self.member(method)?;
+            }
+            Constant::Utf8(_)
+            | Constant::Integer(_)
+            | Constant::Float(_)
+            | Constant::Long(_)
+            | Constant::Double(_) => {}
+        }
+        Ok(())
+    }
+
+    fn utf8(&self, index: u16) -> Result<&str, String> {
+        match self.get(index)? {
+            Constant::Utf8(text) => Ok(text),
+            _ => Err(format!("constant {index} is not a Utf8")),
+        }
+    }
+
+    fn class(&self, index: u16) -> Result<&str, String> {
+        match self.get(index)? {
+            Constant::Class(name) => self.utf8(*name),
+            _ => Err(format!("constant {index} is not a Class")),
+        }
+    }
+
+    fn name_and_type(&self, index: u16) -> Result<(&str, &str), String> {
+        match self.get(index)? {
+            Constant::NameAndType(name, descriptor) => {
+                Ok((self.utf8(*name)?, self.utf8(*descriptor)?))
+            }
+            _ => Err(format!("constant {index} is not a NameAndType")),
+        }
+    }
+
+    /// A field or method reference, as `(class, name, descriptor)`.
+    fn member(&self, index: u16) -> Result<(&str, &str, &str), String> {
+        match self.get(index)? {
+            Constant::Fieldref(class, nat) | Constant::Methodref(class, nat) => {
+                let (name, descriptor) = self.name_and_type(*nat)?;
+                Ok((self.class(*class)?, name, descriptor))
+            }
+            _ => Err(format!("constant {index} is not a member reference")),
+        }
+    }
+
+    /// A loadable constant, as printed after `ldc`, with the stack slots it
+    /// takes.
+    fn loadable(&self, index: u16) -> Result<(String, u16), String> {
+        Ok(match self.get(index)? {
+            Constant::Integer(value) => (value.to_string(), 1),
+            Constant::Float(bits) => (format!("{:?}f", f32::from_bits(*bits)), 1),
+            Constant::Long(value) => (format!("{value}L"), 2),
+            Constant::Double(bits) => (format!("{:?}d", f64::from_bits(*bits)), 2),
+            Constant::String(text) => (format!("{:?}", self.utf8(*text)?), 1),
+            Constant::Class(name) => (format!("class {}", self.utf8(*name)?), 1),
+            Constant::MethodHandle(_, method) => {
+                let (class, name, descriptor) = self.member(*method)?;
+                (format!("handle {class}.{name}:{descriptor}"), 1)
+            }
+            _ => return Err(format!("constant {index} cannot be loaded")),
+        })
+    }
+}
+
+/// Decodes the JVM's modified UTF-8.
+fn decode_utf8(bytes: &[u8]) -> Result<String, String> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    let bad = || "malformed modified UTF-8".to_string();
+    while i < bytes.len() {
+        let b = bytes[i];
+        let (unit, len) = match b {
+            0x01..=0x7f => (u16::from(b), 1),
+            0xc0..=0xdf => {
+                let b1 = *bytes.get(i + 1).ok_or_else(bad)?;
+                ((u16::from(b) & 0x1f) << 6 | (u16::from(b1) & 0x3f), 2)
+            }
+            0xe0..=0xef => {
+                let b1 = *bytes.get(i + 1).ok_or_else(bad)?;
+                let b2 = *bytes.get(i + 2).ok_or_else(bad)?;
+                (
+                    (u16::from(b) & 0x0f) << 12
+                        | (u16::from(b1) & 0x3f) << 6
+                        | (u16::from(b2) & 0x3f),
+                    3,
+                )
+            }
+            _ => return Err(bad()),
+        };
+        units.push(unit);
+        i += len;
+    }
+    String::from_utf16(&units).map_err(|_| bad())
+}
+
+struct MethodInfo<'p> {
+    name: &'p str,
+    descriptor: &'p str,
+    is_static: bool,
+}
+
+/// A stack map frame: the types of the locals and of the stack, printed.
+struct Frame {
+    offset: usize,
+    locals: Vec<String>,
+    locals_size: usize,
+    stack: Vec<String>,
+    stack_size: usize,
+}
+
+/// How an instruction's operands are encoded.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    None,
+    /// A signed byte, as for `bipush`.
+    Byte,
+    /// A signed short, as for `sipush`.
+    Short,
+    /// A local variable index of one byte, or two after `wide`.
+    Local,
+    /// A constant pool index of one byte, for `ldc`.
+    Constant8,
+    Constant16,
+    /// A branch offset of two bytes.
+    Branch,
+    /// The element type of `newarray`.
+    ArrayType,
+}
+
+/// The stack slots an instruction pops and pushes, or `None` where the
+/// constant pool decides.
+type Effect = Option<(u16, u16)>;
+
+/// What is known about an opcode: its mnemonic, its operands and its
+/// effect on the stack.
+fn instruction(opcode: u8) -> Option<(&'static str, Operand, Effect)> {
+    use Operand::*;
+    let fixed = |pops, pushes| Some((pops, pushes));
+    Some(match opcode {
+        op::ACONST_NULL => ("aconst_null", None, fixed(0, 1)),
+        0x02 => ("iconst_m1", None, fixed(0, 1)),
+        0x03 => ("iconst_0", None, fixed(0, 1)),
+        0x04 => ("iconst_1", None, fixed(0, 1)),
+        0x05 => ("iconst_2", None, fixed(0, 1)),
+        0x06 => ("iconst_3", None, fixed(0, 1)),
+        0x07 => ("iconst_4", None, fixed(0, 1)),
+        0x08 => ("iconst_5", None, fixed(0, 1)),
+        0x09 => ("lconst_0", None, fixed(0, 2)),
+        0x0a => ("lconst_1", None, fixed(0, 2)),
+        op::FCONST_0 => ("fconst_0", None, fixed(0, 1)),
+        op::DCONST_0 => ("dconst_0", None, fixed(0, 2)),
+        op::BIPUSH => ("bipush", Byte, fixed(0, 1)),
+        op::SIPUSH => ("sipush", Short, fixed(0, 1)),
+        op::LDC => ("ldc", Constant8, Option::None),
+        op::LDC_W => ("ldc_w", Constant16, Option::None),
+        op::LDC2_W => ("ldc2_w", Constant16, Option::None),
+        op::ILOAD => ("iload", Local, fixed(0, 1)),
+        op::LLOAD => ("lload", Local, fixed(0, 2)),
+        op::FLOAD => ("fload", Local, fixed(0, 1)),
+        op::DLOAD => ("dload", Local, fixed(0, 2)),
+        op::ALOAD => ("aload", Local, fixed(0, 1)),
+        op::IALOAD => ("iaload", None, fixed(2, 1)),
+        op::LALOAD => ("laload", None, fixed(2, 2)),
+        op::FALOAD => ("faload", None, fixed(2, 1)),
+        op::DALOAD => ("daload", None, fixed(2, 2)),
+        op::AALOAD => ("aaload", None, fixed(2, 1)),
+        op::BALOAD => ("baload", None, fixed(2, 1)),
+        op::ISTORE => ("istore", Local, fixed(1, 0)),
+        op::LSTORE => ("lstore", Local, fixed(2, 0)),
+        op::FSTORE => ("fstore", Local, fixed(1, 0)),
+        op::DSTORE => ("dstore", Local, fixed(2, 0)),
+        op::ASTORE => ("astore", Local, fixed(1, 0)),
+        op::IASTORE => ("iastore", None, fixed(3, 0)),
+        op::LASTORE => ("lastore", None, fixed(4, 0)),
+        op::FASTORE => ("fastore", None, fixed(3, 0)),
+        op::DASTORE => ("dastore", None, fixed(4, 0)),
+        op::AASTORE => ("aastore", None, fixed(3, 0)),
+        op::BASTORE => ("bastore", None, fixed(3, 0)),
+        op::POP => ("pop", None, fixed(1, 0)),
+        op::POP2 => ("pop2", None, fixed(2, 0)),
+        op::DUP => ("dup", None, fixed(1, 2)),
+        op::DUP_X1 => ("dup_x1", None, fixed(2, 3)),
+        op::DUP_X2 => ("dup_x2", None, fixed(3, 4)),
+        op::DUP2 => ("dup2", None, fixed(2, 4)),
+        op::DUP2_X1 => ("dup2_x1", None, fixed(3, 5)),
+        op::DUP2_X2 => ("dup2_x2", None, fixed(4, 6)),
+        op::SWAP => ("swap", None, fixed(2, 2)),
+        op::IADD => ("iadd", None, fixed(2, 1)),
+        op::LADD => ("ladd", None, fixed(4, 2)),
+        op::FADD => ("fadd", None, fixed(2, 1)),
+        op::DADD => ("dadd", None, fixed(4, 2)),
+        op::ISUB => ("isub", None, fixed(2, 1)),
+        op::LSUB => ("lsub", None, fixed(4, 2)),
+        op::FSUB => ("fsub", None, fixed(2, 1)),
+        op::DSUB => ("dsub", None, fixed(4, 2)),
+        op::IMUL => ("imul", None, fixed(2, 1)),
+        op::LMUL => ("lmul", None, fixed(4, 2)),
+        op::FMUL => ("fmul", None, fixed(2, 1)),
+        op::DMUL => ("dmul", None, fixed(4, 2)),
+        op::IDIV => ("idiv", None, fixed(2, 1)),
+        op::LDIV => ("ldiv", None, fixed(4, 2)),
+        op::FDIV => ("fdiv", None, fixed(2, 1)),
+        op::DDIV => ("ddiv", None, fixed(4, 2)),
+        op::INEG => ("ineg", None, fixed(1, 1)),
+        op::LNEG => ("lneg", None, fixed(2, 2)),
+        op::FNEG => ("fneg", None, fixed(1, 1)),
+        op::DNEG => ("dneg", None, fixed(2, 2)),
+        op::IAND => ("iand", None, fixed(2, 1)),
+        op::IXOR => ("ixor", None, fixed(2, 1)),
+        op::I2L => ("i2l", None, fixed(1, 2)),
+        op::L2I => ("l2i", None, fixed(2, 1)),
+        op::F2D => ("f2d", None, fixed(1, 2)),
+        op::D2F => ("d2f", None, fixed(2, 1)),
+        op::I2B => ("i2b", None, fixed(1, 1)),
+        op::I2C => ("i2c", None, fixed(1, 1)),
+        op::I2S => ("i2s", None, fixed(1, 1)),
+        op::LCMP => ("lcmp", None, fixed(4, 1)),
+        op::FCMPL => ("fcmpl", None, fixed(2, 1)),
+        op::FCMPG => ("fcmpg", None, fixed(2, 1)),
+        op::DCMPL => ("dcmpl", None, fixed(4, 1)),
+        op::DCMPG => ("dcmpg", None, fixed(4, 1)),
+        op::IFEQ => ("ifeq", Branch, fixed(1, 0)),
+        op::IFNE => ("ifne", Branch, fixed(1, 0)),
+        op::IFLT => ("iflt", Branch, fixed(1, 0)),
+        op::IFGE => ("ifge", Branch, fixed(1, 0)),
+        op::IFGT => ("ifgt", Branch, fixed(1, 0)),
+        op::IFLE => ("ifle", Branch, fixed(1, 0)),
+        op::IF_ICMPEQ => ("if_icmpeq", Branch, fixed(2, 0)),
+        op::IF_ICMPNE => ("if_icmpne", Branch, fixed(2, 0)),
+        op::IF_ICMPLT => ("if_icmplt", Branch, fixed(2, 0)),
+        op::IF_ICMPGE => ("if_icmpge", Branch, fixed(2, 0)),
+        op::IF_ICMPGT => ("if_icmpgt", Branch, fixed(2, 0)),
+        op::IF_ICMPLE => ("if_icmple", Branch, fixed(2, 0)),
+        op::GOTO => ("goto", Branch, fixed(0, 0)),
+        op::IRETURN => ("ireturn", None, fixed(1, 0)),
+        op::LRETURN => ("lreturn", None, fixed(2, 0)),
+        op::FRETURN => ("freturn", None, fixed(1, 0)),
+        op::DRETURN => ("dreturn", None, fixed(2, 0)),
+        op::ARETURN => ("areturn", None, fixed(1, 0)),
+        op::RETURN => ("return", None, fixed(0, 0)),
+        op::GETSTATIC => ("getstatic", Constant16, Option::None),
+        op::PUTSTATIC => ("putstatic", Constant16, Option::None),
+        op::GETFIELD => ("getfield", Constant16, Option::None),
+        op::PUTFIELD => ("putfield", Constant16, Option::None),
+        op::INVOKEVIRTUAL => ("invokevirtual", Constant16, Option::None),
+        op::INVOKESPECIAL => ("invokespecial", Constant16, Option::None),
+        op::INVOKESTATIC => ("invokestatic", Constant16, Option::None),
+        op::NEW => ("new", Constant16, fixed(0, 1)),
+        op::NEWARRAY => ("newarray", ArrayType, fixed(1, 1)),
+        op::ANEWARRAY => ("anewarray", Constant16, fixed(1, 1)),
+        op::ATHROW => ("athrow", None, fixed(1, 0)),
+        op::CHECKCAST => ("checkcast", Constant16, fixed(1, 1)),
+        op::INSTANCEOF => ("instanceof", Constant16, fixed(1, 1)),
+        _ => return Option::None,
+    })
+}
+
+/// Stack slots taken by a value of the field type `descriptor`.
+fn slots(descriptor: &str) -> u16 {
+    match Kind::of(descriptor) {
+        Kind::Long | Kind::Double => 2,
+        _ => 1,
+    }
+}
+
+fn dump_code(
+    r: &mut Reader,
+    pool: &Pool,
+    method: &MethodInfo,
+    out: &mut String,
+) -> Result<(), String> {
+    let max_stack = r.u16()?;
+    let max_locals = r.u16()?;
+    let len = r.u32()? as usize;
+    let code = r.take(len)?;
+    if r.u16()? != 0 {
+        return Err("exception tables are not supported".to_string());
+    }
+    let mut frames = Vec::new();
+    for _ in 0..r.u16()? {
+        let name = pool.utf8(r.u16()?)?;
+        let len = r.u32()? as usize;
+        let body = r.take(len)?;
+        if name == "StackMapTable" {
+            let mut r = Reader {
+                bytes: body,
+                pos: 0,
+            };
+            frames = read_frames(&mut r, pool)?;
+        }
+    }
+    writeln!(out, "    stack={max_stack}, locals={max_locals}").unwrap();
+
+    let (params, ret) = descriptor::parse_method(method.descriptor)
+        .ok_or_else(|| format!("bad method descriptor `{}`", method.descriptor))?;
+    let param_slots: u16 =
+        u16::from(!method.is_static) + params.iter().map(|p| slots(p)).sum::<u16>();
+    if param_slots > max_locals {
+        return Err("the parameters do not fit in max_locals".to_string());
+    }
+    let expected_return = match ret {
+        None => op::RETURN,
+        Some(ret) => Kind::of(ret).ret(),
+    };
+
+    // Decode everything first, so branch targets can be checked.
+    let mut instructions = Vec::new();
+    let mut at = 0;
+    while at < code.len() {
+        let start = at;
+        let mut opcode = code[at];
+        at += 1;
+        let wide = opcode == op::WIDE;
+        if wide {
+            opcode = *code.get(at).ok_or("`wide` at the end of the code")?;
+            at += 1;
+        }
+        let (name, operand, effect) = instruction(opcode)
+            .ok_or_else(|| format!("unknown opcode {opcode:#04x} at {start}"))?;
+        let size = match operand {
+            Operand::None => 0,
+            Operand::Local if wide => 2,
+            Operand::Byte | Operand::Local | Operand::Constant8 | Operand::ArrayType => 1,
+            Operand::Short | Operand::Constant16 | Operand::Branch => 2,
+        };
+        if wide && operand != Operand::Local {
+            return Err(format!("`wide` before `{name}` at {start}"));
+        }
+        let bytes = code
+            .get(at..at + size)
+            .ok_or_else(|| format!("`{name}` at {start} runs past the end of the code"))?;
+        let value: i64 = match (operand, size) {
+            (Operand::None, _) => 0,
+            (Operand::Byte, _) => i64::from(bytes[0] as i8),
+            (_, 1) => i64::from(bytes[0]),
+            (Operand::Short | Operand::Branch, _) => {
+                i64::from(i16::from_be_bytes([bytes[0], bytes[1]]))
+            }
+            _ => i64::from(u16::from_be_bytes([bytes[0], bytes[1]])),
+        };
+        at += size;
+        instructions.push((start, opcode, name, operand, effect, value));
+    }
+    let starts: Vec<usize> = instructions.iter().map(|i| i.0).collect();
+    let frame_at = |offset: usize| frames.iter().find(|f: &&Frame| f.offset == offset);
+    for frame in &frames {
+        if starts.binary_search(&frame.offset).is_err() {
+            return Err(format!(
+                "stack map frame at {} is not at an instruction",
+                frame.offset
+            ));
+        }
+        if frame.locals_size > usize::from(max_locals) {
+            return Err(format!(
+                "stack map frame at {} has too many locals",
+                frame.offset
+            ));
+        }
+    }
+
+    // Follow the stack depth through the code in order. `None` means the
+    // previous instruction never falls through.
+    let mut depth: Option<usize> = Some(0);
+    for &(start, opcode, name, operand, effect, value) in &instructions {
+        if let Some(frame) = frame_at(start) {
+            writeln!(
+                out,
+                "      frame: locals [{}], stack [{}]",
+                frame.locals.join(", "),
+                frame.stack.join(", ")
+            )
+            .unwrap();
+            if depth.is_some_and(|d| d != frame.stack_size) {
+                return Err(format!("stack depth at {start} disagrees with its frame"));
+            }
+            depth = Some(frame.stack_size);
+        }
+        let Some(current) = depth else {
+            return Err(format!(
+                "no stack map frame at {start}, after an unconditional jump"
+            ));
+        };
+
+        let mut text = name.to_string();
+        let (pops, pushes) = match operand {
+            Operand::None | Operand::Byte | Operand::Short => {
+                if operand != Operand::None {
+                    write!(text, " {value}").unwrap();
+                }
+                effect.unwrap()
+            }
+            Operand::Local => {
+                let slot = value as u16;
+                // `lload`, `dstore` and the like take two slots.
+                let width = if name.starts_with(['l', 'd']) { 2 } else { 1 };
+                if slot + width > max_locals {
+                    return Err(format!(
+                        "`{name}` at {start} uses local {slot}, past max_locals"
+                    ));
+                }
+                write!(text, " {slot}").unwrap();
+                effect.unwrap()
+            }
+            Operand::ArrayType => {
+                let element = match value {
+                    4 => "boolean",
+                    5 => "char",
+                    6 => "float",
+                    7 => "double",
+                    8 => "byte",
+                    9 => "short",
+                    10 => "int",
+                    11 => "long",
+                    _ => return Err(format!("bad array type {value} at {start}")),
+                };
+                write!(text, " {element}").unwrap();
+                effect.unwrap()
+            }
+            Operand::Branch => {
+                let target = start as i64 + value;
+                if target < 0 || starts.binary_search(&(target as usize)).is_err() {
+                    return Err(format!(
+                        "`{name}` at {start} jumps into the middle of nowhere"
+                    ));
+                }
+                write!(text, " {target}").unwrap();
+                effect.unwrap()
+            }
+            Operand::Constant8 | Operand::Constant16 => {
+                let index = value as u16;
+                match opcode {
+                    op::LDC | op::LDC_W | op::LDC2_W => {
+                        let (constant, size) = pool.loadable(index)?;
+                        if (size == 2) != (opcode == op::LDC2_W) {
+                            return Err(format!(
+                                "`{name}` at {start} loads a constant of the wrong size"
+                            ));
+                        }
+                        write!(text, " {constant}").unwrap();
+                        (0, size)
+                    }
+                    op::GETSTATIC | op::PUTSTATIC | op::GETFIELD | op::PUTFIELD => {
+                        let (class, field, descriptor) = pool.member(index)?;
+                        if descriptor.starts_with('(') {
+                            return Err(format!("`{name}` at {start} refers to a method"));
+                        }
+                        write!(text, " {class}.{field}:{descriptor}").unwrap();
+                        let size = slots(descriptor);
+                        match opcode {
+                            op::GETSTATIC => (0, size),
+                            op::PUTSTATIC => (size, 0),
+                            op::GETFIELD => (1, size),
+                            _ => (1 + size, 0),
+                        }
+                    }
+                    op::INVOKEVIRTUAL | op::INVOKESPECIAL | op::INVOKESTATIC => {
+                        let (class, callee, descriptor) = pool.member(index)?;
+                        let (params, ret) = descriptor::parse_method(descriptor)
+                            .ok_or_else(|| format!("`{name}` at {start} refers to a field"))?;
+                        write!(text, " {class}.{callee}:{descriptor}").unwrap();
+                        let receiver = u16::from(opcode != op::INVOKESTATIC);
+                        let pops = receiver + params.iter().map(|p| slots(p)).sum::<u16>();
+                        (pops, ret.map_or(0, slots))
+                    }
+                    _ => {
+                        write!(text, " {}", pool.class(index)?).unwrap();
+                        effect.unwrap()
+                    }
+                }
+            }
+        };
+        writeln!(out, "      {start}: {text}").unwrap();
+
+        let (pops, pushes) = (usize::from(pops), usize::from(pushes));
+        if pops > current {
+            return Err(format!(
+                "`{name}` at {start} pops more than is on the stack"
+            ));
+        }
+        let after = current - pops + pushes;
+        if after > usize::from(max_stack) {
+            return Err(format!("`{name}` at {start} goes past max_stack"));
+        }
+        if operand == Operand::Branch {
+            let target = (start as i64 + value) as usize;
+            match frame_at(target) {
+                Some(frame) if frame.stack_size == current - pops => {}
+                Some(_) => {
+                    return Err(format!(
+                        "stack depth at `{name}` at {start} disagrees with the frame at {target}"
+                    ));
+                }
+                None => return Err(format!("no stack map frame at {target}, a branch target")),
+            }
+        }
+
+        let is_return = matches!(
+            opcode,
+            op::IRETURN | op::LRETURN | op::FRETURN | op::DRETURN | op::ARETURN | op::RETURN
+        );
+        if is_return && opcode != expected_return {
+            return Err(format!("`{name}` at {start} in a method returning {ret:?}"));
+        }
+        depth = if is_return || matches!(opcode, op::GOTO | op::ATHROW) {
+            None
+        } else {
+            Some(after)
+        };
+    }
+    if depth.is_some() {
+        return Err("control falls off the end of the code".to_string());
+    }
+    Ok(())
+}
+
+fn read_frames(r: &mut Reader, pool: &Pool) -> Result<Vec<Frame>, String> {
+    let mut frames: Vec<Frame> = Vec::new();
+    for _ in 0..r.u16()? {
+        let kind = r.u8()?;
+        if kind != 255 {
+            return Err(format!("unsupported stack map frame type {kind}"));
+        }
+        let delta = usize::from(r.u16()?);
+        let offset = match frames.last() {
+            Some(previous) => previous.offset + delta + 1,
+            None => delta,
+        };
+        let (locals, locals_size) = read_types(r, pool)?;
+        let (stack, stack_size) = read_types(r, pool)?;
+        frames.push(Frame {
+            offset,
+            locals,
+            locals_size,
+            stack,
+            stack_size,
+        });
+    }
+    Ok(frames)
+}
+
+/// A list of verification types, printed, with the slots they take.
+fn read_types(r: &mut Reader, pool: &Pool) -> Result<(Vec<String>, usize), String> {
+    let mut types = Vec::new();
+    let mut size = 0;
+    for _ in 0..r.u16()? {
+        let (text, slots) = match r.u8()? {
+            0 => ("top".to_string(), 1),
+            1 => ("int".to_string(), 1),
+            2 => ("float".to_string(), 1),
+            3 => ("double".to_string(), 2),
+            4 => ("long".to_string(), 2),
+            5 => ("null".to_string(), 1),
+            7 => (pool.class(r.u16()?)?.to_string(), 1),
+            tag => return Err(format!("unsupported verification type {tag}")),
+        };
+        types.push(text);
+        size += slots;
+    }
+    Ok((types, size))
+}
@@ -0,0 +1,1858 @@
+//! JVM backend.
+//!
+//! Writes Java class files directly, constant pool, descriptors, stack map
+//! frames and all, so no assembler or JDK is involved. [`dump`] reads them
+//! back, checks them and prints them like `javap -c`, which is what the
+//! tests compare.
+//!
+//! Lowers Canary's mid-level IR. Functions and globals become static
+//! methods and fields of one main class, named after the source file. The
+//! Canary `main` is renamed `canary$main`; the JVM's `public static void
+//! main(String[])` calls it, flushes `System.out` and exits with its
+//! status. SSA values and block parameters are locals, or parameters for
+//! those of the entry block, and a jump stores its arguments in the
+//! parameters of the block it goes to.
+//!
+//! Structs are final classes with a public field per field. The objects of
+//! struct values are never modified once built, so they are shared freely;
+//! memory holding a struct is an object of its own, which loading copies
+//! and storing updates in place, so pointers into it stay valid. Enums are
+//! sealed abstract classes holding the index of the variant in `tag`, with
+//! a final subclass per variant.
+//!
+//! A pointer to a struct is the object itself. Any other pointer is a
+//! one-element array. Slots, globals and struct fields are only kept in one
+//! when a pointer to them is used for more than loading and storing: other
+//! slots are locals, and loads and stores through a pointer to them use the
+//! local, static field or instance field directly. Fields are kept in one
+//! for every struct of their type.
+//!
+//! Integers of up to 32 bits are `int`s and 64-bit ones `long`s; narrower
+//! types are truncated after each operation and unsigned ones use the JDK's
+//! unsigned helpers. `f16` is computed as `f32`. Traps print the runtime
+//! error to `System.err` and exit.
+//!
+//! Globals are initialized the first time they are used, like in the
+//! interpreter: every use calls `canary$init$<name>`, which runs the
+//! initializer unless a static state field says it already has, and reports
+//! a global read during its own initialization.
+//!
+//! The JVM never reuses a frame for a call, so functions that tail call
+//! each other are compiled into one static method, `<first>$group`, which
+//! takes the index of the member to start in and the parameters of all of
+//! them, and a tail call within it stores the arguments in the callee's
+//! parameter slots and jumps with `goto`. Each member keeps a method of its
+//! own that calls the merged one, for ordinary calls and method handles.
+
+mod bytecode;
+mod classfile;
+mod descriptor;
+mod dump;
+
+use std::collections::{HashMap, HashSet};
+
+use ir::cfg::{TailGroup, reverse_postorder, tail_call_groups};
+use ir::{
+    BinOp, BlockId, Callee, Const, EnumId, FuncId, Function, GlobalId, InstKind, Signature,
+    StructId, Target, Terminator, Type, UnOp, Value,
+};
+use sema::format::{self, Piece, Spec};
+use sema::types::{FloatTy, IntTy};
+
+use bytecode::{Assembler, Label, op};
+use classfile::*;
+use descriptor::Kind;
+
+pub use dump::dump;
+
+/// A class file ready to be written to `<name>.class`.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+const OBJECT: &str = "java/lang/Object";
+const STRING: &str = "Ljava/lang/String;";
+const METHOD_HANDLE: &str = "java/lang/invoke/MethodHandle";
+const BUILDER: &str = "java/lang/StringBuilder";
+
+/// Lowers `module` to class files, the first of which is `main_class`.
+/// Runtime errors name the file the module was lowered from. The module
+/// must be well formed.
+pub fn emit(module: &ir::Module, main_class: &str) -> Vec<Class> {
+    let main_class: String = main_class
+        .chars()
+        .map(|c| if ".;[/".contains(c) { '_' } else { c })
+        .collect();
+    let mut unit = Module {
+        module,
+        main_class: main_class.clone(),
+        struct_names: Vec::new(),
+        enum_names: Vec::new(),
+        method_names: Vec::new(),
+        global_names: Vec::new(),
+        state_names: Vec::new(),
+        init_names: Vec::new(),
+        groups: Vec::new(),
+        boxed: HashSet::new(),
+        cells: HashSet::new(),
+    };
+
+    let mut taken = HashSet::from([main_class.clone()]);
+    for def in &module.structs {
+        unit.struct_names.push(unique(&mut taken, &def.name));
+    }
+    for def in &module.enums {
+        unit.enum_names.push(unique(&mut taken, &def.name));
+    }
+
+    let mut taken = HashSet::new();
+    for (i, function) in module.functions.iter().enumerate() {
+        let name = if module.main == Some(FuncId(i as u32)) {
+            "canary$main"
+        } else {
+            &function.name
+        };
+        unit.method_names
+            .push(unique(&mut taken, &name.replace('.', "$")));
+    }
+    unit.groups = tail_call_groups(module)
+        .into_iter()
+        .map(|tail| {
+            let merged = tail.is_merged().then(|| {
+                let first = &unit.method_names[tail.members[0].0 as usize];
+                unique(&mut taken, &format!("{first}$group"))
+            });
+            Group { tail, merged }
+        })
+        .collect();
+    for global in &module.globals {
+        let name = format!("canary$init${}", global.name.replace('.', "$"));
+        unit.init_names.push(unique(&mut taken, &name));
+    }
+    let mut taken = HashSet::new();
+    for global in &module.globals {
+        unit.global_names.push(unique(&mut taken, &global.name));
+    }
+    for global in &module.globals {
+        let name = format!("canary$state${}", global.name);
+        unit.state_names.push(unique(&mut taken, &name));
+    }
+
+    // Globals and fields that need a cell; see `Module::boxed`.
+    for function in &module.functions {
+        let escaping = escaping(function);
+        for inst in function.blocks.iter().flat_map(|b| &b.insts) {
+            let Some(result) = inst.result else {
+                continue;
+            };
+            if !escaping.contains(&result) {
+                continue;
+            }
+            match &inst.kind {
+                InstKind::Global { id, .. } => {
+                    if let Some(ty) = &module.globals[id.0 as usize].ty
+                        && !matches!(ty, Type::Struct(_))
+                    {
+                        unit.boxed.insert(*id);
+                    }
+                }
+                InstKind::FieldPtr { ptr, index } => {
+                    if let Type::Ptr(pointee) = function.ty(*ptr)
+                        && let Type::Struct(id) = **pointee
+                        && !matches!(unit.field_ty(id, *index), Type::Struct(_))
+                    {
+                        unit.cells.insert((id, *index));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut classes = vec![unit.main()];
+    for id in 0..module.structs.len() {
+        classes.push(unit.struct_class(StructId(id as u32)));
+    }
+    for id in 0..module.enums.len() {
+        classes.extend(unit.enum_classes(EnumId(id as u32)));
+    }
+    classes
+}
+
+/// A name based on `name` that is not in `taken` yet.
+fn unique(taken: &mut HashSet<String>, name: &str) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{name}${n}");
+        n += 1;
+    }
+    candidate
+}
+
+/// The values of `function` used for more than the pointer that a load,
+/// a store or a field pointer goes through.
+fn escaping(function: &Function) -> HashSet<Value> {
+    let mut escaping = HashSet::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            match &inst.kind {
+                InstKind::Load(_) | InstKind::FieldPtr { .. } => {}
+                InstKind::Store { value, .. } => {
+                    escaping.insert(*value);
+                }
+                kind => escaping.extend(kind.operands()),
+            }
+        }
+        escaping.extend(block.term.operands());
+    }
+    escaping
+}
+
+struct Group {
+    tail: TailGroup,
+    /// The method the members are merged into, if there are several.
+    merged: Option<String>,
+}
+
+struct Module<'a> {
+    module: &'a ir::Module,
+    main_class: String,
+    /// Class of each struct and enum.
+    struct_names: Vec<String>,
+    enum_names: Vec<String>,
+    method_names: Vec<String>,
+    global_names: Vec<String>,
+    /// The field holding the state of each global's initialization, and
+    /// the method that runs it; see `FnGen::init`.
+    state_names: Vec<String>,
+    init_names: Vec<String>,
+    groups: Vec<Group>,
+    /// Globals other than structs kept in a one-element array, because a
+    /// pointer to them is used for more than loading and storing.
+    boxed: HashSet<GlobalId>,
+    /// Struct fields kept in a one-element array for the same reason, by
+    /// struct and index.
+    cells: HashSet<(StructId, u32)>,
+}
+
+impl Module<'_> {
+    /// The field descriptor of `ty`.
+    fn descriptor(&self, ty: &Type) -> String {
+        match ty {
+            Type::Int(int) if int.bits() == 64 => "J".to_string(),
+            Type::Int(_) => "I".to_string(),
+            Type::Float(FloatTy::F64) => "D".to_string(),
+            Type::Float(_) => "F".to_string(),
+            Type::Bool => "Z".to_string(),
+            Type::Str => STRING.to_string(),
+            Type::Struct(id) => format!("L{};", self.struct_names[id.0 as usize]),
+            Type::Enum(id) => format!("L{};", self.enum_names[id.0 as usize]),
+            Type::Ptr(inner) => match **inner {
+                Type::Struct(_) => self.descriptor(inner),
+                _ => format!("[{}", self.descriptor(inner)),
+            },
+            Type::Fn(_) => format!("L{METHOD_HANDLE};"),
+        }
+    }
+
+    fn method_descriptor(&self, params: &[Type], ret: Option<&Type>) -> String {
+        let params: String = params.iter().map(|p| self.descriptor(p)).collect();
+        let ret = ret.map_or_else(|| "V".to_string(), |ty| self.descriptor(ty));
+        format!("({params}){ret}")
+    }
+
+    fn signature(&self, sig: &Signature) -> String {
+        self.method_descriptor(&sig.params, sig.ret.as_ref())
+    }
+
+    fn class_of(&self, ty: &Type) -> &str {
+        match ty {
+            Type::Struct(id) => &self.struct_names[id.0 as usize],
+            Type::Enum(id) => &self.enum_names[id.0 as usize],
+            _ => unreachable!("{ty:?} has no class"),
+        }
+    }
+
+    fn field_ty(&self, id: StructId, index: u32) -> &Type {
+        &self.module.structs[id.0 as usize].fields[index as usize].ty
+    }
+
+    fn variant_class(&self, id: EnumId, index: u32) -> String {
+        let variant = &self.module.enums[id.0 as usize].variants[index as usize];
+        format!("{}${}", self.enum_names[id.0 as usize], variant.name)
+    }
+
+    /// The Java name of a payload field: tuple payloads name theirs by
+    /// position, which Java names cannot start with.
+    fn payload_field(&self, id: EnumId, index: u32, field: u32) -> String {
+        let variant = &self.module.enums[id.0 as usize].variants[index as usize];
+        let name = &variant.fields[field as usize].name;
+        if name.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("_{name}")
+        } else {
+            name.clone()
+        }
+    }
+
+    /// The descriptor of the static field of global `id`: a cell if it is
+    /// boxed.
+    fn global_descriptor(&self, id: GlobalId) -> Option<String> {
+        let ty = self.module.globals[id.0 as usize].ty.as_ref()?;
+        let descriptor = self.descriptor(ty);
+        Some(match self.boxed.contains(&id) {
+            true => format!("[{descriptor}"),
+            false => descriptor,
+        })
+    }
+
+    /// The main class: every function and global.
+    fn main(&self) -> Class {
+        let module = self.module;
+        let mut class =
+            ClassBuilder::new(ACC_PUBLIC | ACC_FINAL | ACC_SUPER, &self.main_class, OBJECT);
+
+        for name in &self.state_names {
+            class.fields.push(Field {
+                access: ACC_STATIC,
+                name: name.clone(),
+                descriptor: "I".to_string(),
+            });
+        }
+        for (i, name) in self.global_names.iter().enumerate() {
+            let Some(descriptor) = self.global_descriptor(GlobalId(i as u32)) else {
+                continue;
+            };
+            class.fields.push(Field {
+                access: ACC_PUBLIC | ACC_STATIC,
+                name: name.clone(),
+                descriptor,
+            });
+        }
+
+        for group in &self.groups {
+            let method = FnGen::new(self, &mut class.pool).group(group);
+            class.methods.push(method);
+            if group.merged.is_some() {
+                for index in 0..group.tail.members.len() {
+                    let method = self.entry_point(&mut class.pool, group, index);
+                    class.methods.push(method);
+                }
+            }
+        }
+        for index in 0..module.globals.len() {
+            let method = FnGen::new(self, &mut class.pool).init(index);
+            class.methods.push(method);
+        }
+        if let Some(main) = module.main {
+            let method = self.entry(&mut class.pool, main);
+            class.methods.push(method);
+        }
+
+        Class {
+            name: self.main_class.clone(),
+            bytes: class.finish(),
+        }
+    }
+
+    /// The method of member `index` of a merged group, which calls the
+    /// merged method with its own arguments and placeholders for the rest.
+    fn entry_point(&self, pool: &mut ConstantPool, group: &Group, index: usize) -> Method {
+        let id = group.tail.members[index];
+        let function = self.module.function(id);
+        let mut code = Assembler::new();
+        let mut own = function
+            .sig
+            .params
+            .iter()
+            .map(|ty| self.descriptor(ty))
+            .map(|descriptor| (code.local(VType::of(&descriptor)), descriptor))
+            .collect::<Vec<_>>()
+            .into_iter();
+        code.push_int(pool, index as i32);
+        let mut params = vec![Type::Int(IntTy::I32)];
+        for (member, _, ty) in group.tail.params(self.module) {
+            params.push(ty.clone());
+            if member == index {
+                let (slot, descriptor) = own.next().expect("parameter of the member");
+                code.load(&descriptor, slot);
+            } else {
+                code.push_default(&self.descriptor(ty));
+            }
+        }
+        let merged = group
+            .merged
+            .as_ref()
+            .expect("entry point of a lone function");
+        let descriptor = self.method_descriptor(&params, function.sig.ret.as_ref());
+        code.invoke(
+            pool,
+            op::INVOKESTATIC,
+            &self.main_class,
+            merged,
+            &descriptor,
+        );
+        match &function.sig.ret {
+            Some(ty) => code.op(Kind::of(&self.descriptor(ty)).ret(), 1, &[]),
+            None => code.op(op::RETURN, 0, &[]),
+        }
+        Method {
+            access: ACC_PUBLIC | ACC_STATIC,
+            name: self.method_names[id.0 as usize].clone(),
+            descriptor: self.signature(&function.sig),
+            code: code.finish(Assembler::new()),
+        }
+    }
+
+    /// `main(String[])`: runs the Canary `main`, then exits with its status.
+    fn entry(&self, pool: &mut ConstantPool, main: FuncId) -> Method {
+        let function = self.module.function(main);
+        let descriptor = self.signature(&function.sig);
+        let mut code = Assembler::new();
+        code.local(VType::Object("[Ljava/lang/String;".to_string()));
+        let name = &self.method_names[main.0 as usize];
+        code.invoke(pool, op::INVOKESTATIC, &self.main_class, name, &descriptor);
+        let status = match function.sig.ret.as_ref().map(|ty| self.descriptor(ty)) {
+            Some(d) if d == "I" => true,
+            Some(d) if d == "J" => {
+                code.op(op::L2I, 1, &[VType::Int]);
+                true
+            }
+            Some(_) => {
+                code.pop();
+                false
+            }
+            None => false,
+        };
+        let out = "Ljava/io/PrintStream;";
+        code.field(pool, op::GETSTATIC, "java/lang/System", "out", out);
+        code.invoke(
+            pool,
+            op::INVOKEVIRTUAL,
+            "java/io/PrintStream",
+            "flush",
+            "()V",
+        );
+        if status {
+            code.invoke(pool, op::INVOKESTATIC, "java/lang/System", "exit", "(I)V");
+        }
+        code.op(op::RETURN, 0, &[]);
+        Method {
+            access: ACC_PUBLIC | ACC_STATIC,
+            name: "main".to_string(),
+            descriptor: "([Ljava/lang/String;)V".to_string(),
+            code: code.finish(Assembler::new()),
+        }
+    }
+
+    /// The fields of a struct or variant class, as `(name, descriptor)`.
+    fn fields(&self, names: Vec<String>, types: Vec<&Type>) -> Vec<(String, String)> {
+        names
+            .into_iter()
+            .zip(types)
+            .map(|(name, ty)| (name, self.descriptor(ty)))
+            .collect()
+    }
+
+    fn struct_class(&self, id: StructId) -> Class {
+        let def = &self.module.structs[id.0 as usize];
+        let name = &self.struct_names[id.0 as usize];
+        let fields = self.fields(
+            def.fields.iter().map(|f| f.name.clone()).collect(),
+            def.fields.iter().map(|f| &f.ty).collect(),
+        );
+        let nested: Vec<bool> = def
+            .fields
+            .iter()
+            .map(|f| matches!(f.ty, Type::Struct(_)))
+            .collect();
+        let cells: Vec<bool> = (0..def.fields.len())
+            .map(|index| self.cells.contains(&(id, index as u32)))
+            .collect();
+        let mut class = ClassBuilder::new(ACC_PUBLIC | ACC_FINAL | ACC_SUPER, name, OBJECT);
+        data_class(&mut class, OBJECT, None, name, &fields, &cells);
+        let this = format!("L{name};");
+
+        // `copy()`: a copy that shares nothing mutable with this one.
+        let pool = &mut class.pool;
+        let mut code = Assembler::new();
+        code.local(VType::Object(name.clone()));
+        for (((field, descriptor), nested), cell) in fields.iter().zip(&nested).zip(&cells) {
+            code.load(&this, 0);
+            if *cell {
+                let array = format!("[{descriptor}");
+                code.field(pool, op::GETFIELD, name, field, &array);
+                code.push_int(pool, 0);
+                let opcode = descriptor::array_load(descriptor);
+                code.op(opcode, 2, &[VType::of(descriptor)]);
+                continue;
+            }
+            code.field(pool, op::GETFIELD, name, field, descriptor);
+            if *nested {
+                let class = descriptor::class_name(descriptor);
+                code.invoke(
+                    pool,
+                    op::INVOKEVIRTUAL,
+                    class,
+                    "copy",
+                    &format!("(){descriptor}"),
+                );
+            }
+        }
+        let of = format!(
+            "({}){this}",
+            fields.iter().map(|f| f.1.as_str()).collect::<String>()
+        );
+        code.invoke(pool, op::INVOKESTATIC, name, "of", &of);
+        code.op(op::ARETURN, 1, &[]);
+        class.methods.push(Method {
+            access: ACC_PUBLIC,
+            name: "copy".to_string(),
+            descriptor: format!("(){this}"),
+            code: code.finish(Assembler::new()),
+        });
+
+        // `set(other)`: assignment, which updates nested structs and cells
+        // in place too, since pointers may point into them.
+        let pool = &mut class.pool;
+        let mut code = Assembler::new();
+        code.local(VType::Object(name.clone()));
+        code.local(VType::Object(name.clone()));
+        for (((field, descriptor), nested), cell) in fields.iter().zip(&nested).zip(&cells) {
+            code.load(&this, 0);
+            if *cell {
+                let array = format!("[{descriptor}");
+                code.field(pool, op::GETFIELD, name, field, &array);
+                code.push_int(pool, 0);
+                code.load(&this, 1);
+                code.field(pool, op::GETFIELD, name, field, &array);
+                code.push_int(pool, 0);
+                let opcode = descriptor::array_load(descriptor);
+                code.op(opcode, 2, &[VType::of(descriptor)]);
+                code.op(descriptor::array_store(descriptor), 3, &[]);
+            } else if *nested {
+                code.field(pool, op::GETFIELD, name, field, descriptor);
+                code.load(&this, 1);
+                code.field(pool, op::GETFIELD, name, field, descriptor);
+                let class = descriptor::class_name(descriptor);
+                code.invoke(
+                    pool,
+                    op::INVOKEVIRTUAL,
+                    class,
+                    "set",
+                    &format!("({descriptor})V"),
+                );
+            } else {
+                code.load(&this, 1);
+                code.field(pool, op::GETFIELD, name, field, descriptor);
+                code.field(pool, op::PUTFIELD, name, field, descriptor);
+            }
+        }
+        code.op(op::RETURN, 0, &[]);
+        class.methods.push(Method {
+            access: ACC_PUBLIC,
+            name: "set".to_string(),
+            descriptor: format!("({this})V"),
+            code: code.finish(Assembler::new()),
+        });
+
+        Class {
+            name: name.clone(),
+            bytes: class.finish(),
+        }
+    }
+
+    /// The sealed base class of an enum, which holds the tag, then a class
+    /// per variant.
+    fn enum_classes(&self, id: EnumId) -> Vec<Class> {
+        let def = &self.module.enums[id.0 as usize];
+        let name = &self.enum_names[id.0 as usize];
+        let mut base = ClassBuilder::new(ACC_PUBLIC | ACC_SUPER | ACC_ABSTRACT, name, OBJECT);
+        base.fields.push(Field {
+            access: ACC_PUBLIC | ACC_FINAL,
+            name: "tag".to_string(),
+            descriptor: "I".to_string(),
+        });
+        let pool = &mut base.pool;
+        let mut code = Assembler::new();
+        code.local(VType::Object(name.clone()));
+        code.local(VType::Int);
+        code.load(&format!("L{name};"), 0);
+        code.invoke(pool, op::INVOKESPECIAL, OBJECT, "<init>", "()V");
+        code.load(&format!("L{name};"), 0);
+        code.load("I", 1);
+        code.field(pool, op::PUTFIELD, name, "tag", "I");
+        code.op(op::RETURN, 0, &[]);
+        base.methods.push(Method {
+            access: ACC_PUBLIC,
+            name: "<init>".to_string(),
+            descriptor: "(I)V".to_string(),
+            code: code.finish(Assembler::new()),
+        });
+
+        let mut variants = Vec::new();
+        for (index, variant) in def.variants.iter().enumerate() {
+            let index = index as u32;
+            let variant_name = self.variant_class(id, index);
+            base.permitted.push(variant_name.clone());
+            let fields = self.fields(
+                (0..variant.fields.len())
+                    .map(|field| self.payload_field(id, index, field as u32))
+                    .collect(),
+                variant.fields.iter().map(|f| &f.ty).collect(),
+            );
+            let mut class =
+                ClassBuilder::new(ACC_PUBLIC | ACC_FINAL | ACC_SUPER, &variant_name, name);
+            let cells = vec![false; fields.len()];
+            data_class(&mut class, name, Some(index), name, &fields, &cells);
+            variants.push(Class {
+                name: variant_name,
+                bytes: class.finish(),
+            });
+        }
+
+        let mut classes = vec![Class {
+            name: name.clone(),
+            bytes: base.finish(),
+        }];
+        classes.extend(variants);
+        classes
+    }
+}
+
+/// Adds `fields`, a constructor taking all of them and a static `of` that
+/// constructs one and returns it as a `returns`. Code builds values with
+/// `of`, so no uninitialized object is ever live across a branch. The
+/// constructor passes `tag`, if any, to the superclass's. The fields marked
+/// in `cells` are stored in a one-element array, which `of` creates.
+fn data_class(
+    class: &mut ClassBuilder,
+    super_name: &str,
+    tag: Option<u32>,
+    returns: &str,
+    fields: &[(String, String)],
+    cells: &[bool],
+) {
+    let name = class.name.clone();
+    let this = format!("L{name};");
+    let stored: Vec<String> = fields
+        .iter()
+        .zip(cells)
+        .map(|((_, descriptor), cell)| match cell {
+            true => format!("[{descriptor}"),
+            false => descriptor.clone(),
+        })
+        .collect();
+    let init = format!("({})V", stored.concat());
+    for ((field, _), descriptor) in fields.iter().zip(&stored) {
+        class.fields.push(Field {
+            access: ACC_PUBLIC,
+            name: field.clone(),
+            descriptor: descriptor.clone(),
+        });
+    }
+
+    let pool = &mut class.pool;
+    let mut code = Assembler::new();
+    code.local(VType::Object(name.clone()));
+    let slots: Vec<u16> = stored.iter().map(|d| code.local(VType::of(d))).collect();
+    code.load(&this, 0);
+    match tag {
+        Some(tag) => {
+            code.push_int(pool, tag as i32);
+            code.invoke(pool, op::INVOKESPECIAL, super_name, "<init>", "(I)V");
+        }
+        None => code.invoke(pool, op::INVOKESPECIAL, super_name, "<init>", "()V"),
+    }
+    for (((field, _), descriptor), slot) in fields.iter().zip(&stored).zip(&slots) {
+        code.load(&this, 0);
+        code.load(descriptor, *slot);
+        code.field(pool, op::PUTFIELD, &name, field, descriptor);
+    }
+    code.op(op::RETURN, 0, &[]);
+    class.methods.push(Method {
+        access: ACC_PUBLIC,
+        name: "<init>".to_string(),
+        descriptor: init.clone(),
+        code: code.finish(Assembler::new()),
+    });
+
+    let mut code = Assembler::new();
+    let slots: Vec<u16> = fields.iter().map(|f| code.local(VType::of(&f.1))).collect();
+    code.class_op(pool, op::NEW, &name);
+    code.dup();
+    for (((_, descriptor), slot), cell) in fields.iter().zip(&slots).zip(cells) {
+        if *cell {
+            code.new_cell(pool, descriptor);
+            code.dup();
+            code.push_int(pool, 0);
+            code.load(descriptor, *slot);
+            code.op(descriptor::array_store(descriptor), 3, &[]);
+        } else {
+            code.load(descriptor, *slot);
+        }
+    }
+    code.invoke(pool, op::INVOKESPECIAL, &name, "<init>", &init);
+    code.op(op::ARETURN, 1, &[]);
+    let params: String = fields.iter().map(|f| f.1.as_str()).collect();
+    class.methods.push(Method {
+        access: ACC_PUBLIC | ACC_STATIC,
+        name: "of".to_string(),
+        descriptor: format!("({params})L{returns};"),
+        code: code.finish(Assembler::new()),
+    });
+}
+
+/// How a value is pushed.
+#[derive(Debug, Clone)]
+enum Operand {
+    /// From a local or parameter: the slot and its descriptor.
+    Local(u16, String),
+    /// A constant, with its descriptor.
+    Const(Const, String),
+    /// A method handle.
+    Func(FuncId),
+    /// A pointer to a struct or a cell held by a static field, which is
+    /// read where it is used.
+    Static(String, String),
+    /// A pointer that is only loaded from and stored to, which is not a
+    /// value of its own.
+    Place(Place),
+}
+
+/// Where a pointer that is not a value of its own points.
+#[derive(Debug, Clone)]
+enum Place {
+    Local(u16, String),
+    Static(String, String),
+    /// An instance field of the struct `object` points to.
+    Field {
+        object: Value,
+        class: String,
+        name: String,
+        descriptor: String,
+    },
+}
+
+/// The labels and parameter slots of one member of a group.
+struct Member {
+    labels: Vec<Label>,
+    params: Vec<(u16, String)>,
+}
+
+/// Lowers one method body.
+struct FnGen<'m, 'a> {
+    module: &'m Module<'a>,
+    pool: &'m mut ConstantPool,
+    code: Assembler,
+    params: usize,
+    /// The group being lowered.
+    group: TailGroup,
+    members: Vec<Member>,
+    /// How each value of the function being lowered is pushed, once it is
+    /// defined.
+    operands: HashMap<Value, Operand>,
+    /// The values the function reads.
+    used: HashSet<Value>,
+    escaping: HashSet<Value>,
+}
+
+impl<'m, 'a> FnGen<'m, 'a> {
+    fn new(module: &'m Module<'a>, pool: &'m mut ConstantPool) -> Self {
+        Self {
+            module,
+            pool,
+            code: Assembler::new(),
+            params: 0,
+            group: TailGroup::default(),
+            members: Vec::new(),
+            operands: HashMap::new(),
+            used: HashSet::new(),
+            escaping: HashSet::new(),
+        }
+    }
+
+    fn descriptor(&self, ty: &Type) -> String {
+        self.module.descriptor(ty)
+    }
+
+    /// Gives every local other than the parameters its default value, so
+    /// the frames can all list the same locals.
+    fn finish(self) -> Code {
+        let mut prologue = Assembler::new();
+        for (slot, ty) in self.code.locals().into_iter().skip(self.params) {
+            let descriptor = match &ty {
+                VType::Int => "I".to_string(),
+                VType::Long => "J".to_string(),
+                VType::Float => "F".to_string(),
+                VType::Double => "D".to_string(),
+                _ => format!("L{OBJECT};"),
+            };
+            prologue.push_default(&descriptor);
+            prologue.store(&descriptor, slot);
+        }
+        self.code.finish(prologue)
+    }
+
+    /// The method of a group: the function itself, or the merged method,
+    /// which takes the index of the member to run and then the parameters
+    /// of every member.
+    fn group(mut self, group: &Group) -> Method {
+        let module = self.module.module;
+        let members = &group.tail.members;
+        self.group = group.tail.clone();
+        let mut param_types = Vec::new();
+        if group.merged.is_some() {
+            self.code.local(VType::Int);
+            param_types.push(Type::Int(IntTy::I32));
+        }
+        for &id in members {
+            let blocks = &module.function(id).blocks;
+            let labels = blocks.iter().map(|_| self.code.new_label()).collect();
+            let params = Vec::new();
+            self.members.push(Member { labels, params });
+        }
+        for (member, _, ty) in group.tail.params(module) {
+            param_types.push(ty.clone());
+            let descriptor = self.descriptor(ty);
+            let slot = self.code.local(VType::of(&descriptor));
+            self.members[member].params.push((slot, descriptor));
+        }
+        self.params = self.code.locals().len();
+
+        if group.merged.is_some() {
+            for i in 1..members.len() {
+                self.code.load("I", 0);
+                self.code.push_int(self.pool, i as i32);
+                self.code
+                    .branch(op::IF_ICMPEQ, self.members[i].labels[0], 2);
+            }
+        }
+        for (i, &id) in members.iter().enumerate() {
+            self.function(i, module.function(id));
+        }
+
+        let first = module.function(members[0]);
+        let name = group
+            .merged
+            .clone()
+            .unwrap_or_else(|| self.module.method_names[members[0].0 as usize].clone());
+        let descriptor = self
+            .module
+            .method_descriptor(&param_types, first.sig.ret.as_ref());
+        Method {
+            access: ACC_PUBLIC | ACC_STATIC,
+            name,
+            descriptor,
+            code: self.finish(),
+        }
+    }
+
+    /// The method that initializes global `index` the first time it is
+    /// used, given where as `file:line:col`. Its state field is 0 before
+    /// that, 1 while its initializer runs and 2 after.
+    fn init(mut self, index: usize) -> Method {
+        let module = self.module.module;
+        let class = self.module.main_class.clone();
+        let global = &module.globals[index];
+        let id = GlobalId(index as u32);
+        let state = self.module.state_names[index].clone();
+        let at = self.code.local(VType::of(STRING));
+        self.params = self.code.locals().len();
+        let begin = self.code.new_label();
+        let done = self.code.new_label();
+
+        self.code
+            .field(self.pool, op::GETSTATIC, &class, &state, "I");
+        self.code.push_int(self.pool, 2);
+        self.code.branch(op::IF_ICMPEQ, done, 2);
+        self.code
+            .field(self.pool, op::GETSTATIC, &class, &state, "I");
+        self.code.branch(op::IFEQ, begin, 1);
+        self.fail(|f| {
+            f.code.load(STRING, at);
+            let text = ": runtime error: global read during its own initialization";
+            let index = f.pool.string(text);
+            f.code.ldc(index, VType::of(STRING));
+            f.code.invoke(
+                f.pool,
+                op::INVOKEVIRTUAL,
+                "java/lang/String",
+                "concat",
+                &format!("({STRING}){STRING}"),
+            );
+        });
+
+        self.code.place(begin);
+        self.code.push_int(self.pool, 1);
+        self.code
+            .field(self.pool, op::PUTSTATIC, &class, &state, "I");
+        let init = &self.module.method_names[global.init.0 as usize];
+        let descriptor = self.module.signature(&module.function(global.init).sig);
+        if let Some(ty) = &global.ty {
+            let value = self.descriptor(ty);
+            let boxed = self.module.boxed.contains(&id);
+            if boxed {
+                self.code.new_cell(self.pool, &value);
+                self.code.dup();
+                self.code.push_int(self.pool, 0);
+            }
+            self.code
+                .invoke(self.pool, op::INVOKESTATIC, &class, init, &descriptor);
+            // The value may be shared with other values; the global is
+            // memory of its own.
+            if let Type::Struct(_) = ty {
+                self.copy(ty);
+            }
+            if boxed {
+                self.code.op(descriptor::array_store(&value), 3, &[]);
+            }
+            let name = &self.module.global_names[index];
+            let stored = self.module.global_descriptor(id).expect("global of a type");
+            self.code
+                .field(self.pool, op::PUTSTATIC, &class, name, &stored);
+        } else {
+            self.code
+                .invoke(self.pool, op::INVOKESTATIC, &class, init, &descriptor);
+        }
+        self.code.push_int(self.pool, 2);
+        self.code
+            .field(self.pool, op::PUTSTATIC, &class, &state, "I");
+        self.code.place(done);
+        self.code.op(op::RETURN, 0, &[]);
+        Method {
+            access: ACC_STATIC,
+            name: self.module.init_names[index].clone(),
+            descriptor: format!("({STRING})V"),
+            code: self.finish(),
+        }
+    }
+
+    /// Lowers the blocks of member `member`, `func`.
+    fn function(&mut self, member: usize, func: &Function) {
+        self.operands.clear();
+        self.used.clear();
+        for block in &func.blocks {
+            for inst in &block.insts {
+                self.used.extend(inst.kind.operands());
+            }
+            self.used.extend(block.term.operands());
+        }
+        self.escaping = escaping(func);
+
+        let params = self.members[member].params.clone();
+        for (&param, (slot, descriptor)) in func.blocks[0].params.iter().zip(params) {
+            self.operands
+                .insert(param, Operand::Local(slot, descriptor));
+        }
+        for block in &func.blocks[1..] {
+            for &param in &block.params {
+                let descriptor = self.descriptor(func.ty(param));
+                let slot = self.code.local(VType::of(&descriptor));
+                self.operands
+                    .insert(param, Operand::Local(slot, descriptor));
+            }
+        }
+
+        let order = reverse_postorder(func);
+        for (i, &block) in order.iter().enumerate() {
+            self.code
+                .place_block(self.members[member].labels[block.0 as usize]);
+            let b = func.block(block);
+            for inst in &b.insts {
+                self.inst(func, inst);
+            }
+            let next = order.get(i + 1).copied();
+            self.terminator(member, func, &b.term, next);
+        }
+    }
+
+    /// Converts the `int` on top of the stack to `ty`, which is how
+    /// integer overflow wraps.
+    fn wrap(&mut self, ty: &Type) {
+        match ty {
+            Type::Int(IntTy::I8) => self.code.op(op::I2B, 1, &[VType::Int]),
+            Type::Int(IntTy::I16) => self.code.op(op::I2S, 1, &[VType::Int]),
+            Type::Int(IntTy::U16) => self.code.op(op::I2C, 1, &[VType::Int]),
+            Type::Int(IntTy::U8) => {
+                self.code.push_int(self.pool, 0xff);
+                self.code.op(op::IAND, 2, &[VType::Int]);
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        match self.operands[&value].clone() {
+            Operand::Local(slot, descriptor) => self.code.load(&descriptor, slot),
+            Operand::Const(constant, descriptor) => match constant {
+                Const::Int(n) if descriptor == "J" => self.code.push_long(self.pool, n),
+                Const::Int(n) => self.code.push_int(self.pool, n as i32),
+                Const::Float(x) if descriptor == "F" => {
+                    self.code.push_float(self.pool, x as f32);
+                }
+                Const::Float(x) => self.code.push_double(self.pool, x),
+                Const::Bool(b) => self.code.push_int(self.pool, i32::from(b)),
+                Const::Str(s) => {
+                    let index = self.pool.string(&s);
+                    self.code.ldc(index, VType::of(STRING));
+                }
+            },
+            Operand::Func(id) => {
+                let sig = &self.module.module.function(id).sig;
+                let descriptor = self.module.signature(sig);
+                let name = &self.module.method_names[id.0 as usize];
+                let index = self
+                    .pool
+                    .static_handle(&self.module.main_class, name, &descriptor);
+                self.code
+                    .ldc(index, VType::Object(METHOD_HANDLE.to_string()));
+            }
+            Operand::Static(name, descriptor) => {
+                let class = self.module.main_class.clone();
+                self.code
+                    .field(self.pool, op::GETSTATIC, &class, &name, &descriptor);
+            }
+            Operand::Place(place) => unreachable!("{value:?} points to {place:?}"),
+        }
+    }
+
+    /// Stores the value on the stack as `result`, of type `ty`, or drops it
+    /// if nothing reads it.
+    fn define(&mut self, result: Option<Value>, ty: &Type) {
+        match result {
+            Some(result) if self.used.contains(&result) => {
+                let descriptor = self.descriptor(ty);
+                let slot = self.code.local(VType::of(&descriptor));
+                self.code.store(&descriptor, slot);
+                self.operands
+                    .insert(result, Operand::Local(slot, descriptor));
+            }
+            _ => self.code.pop(),
+        }
+    }
+
+    /// Pushes the value of type `ty` that `ptr` points to.
+    fn load(&mut self, ptr: Value, ty: &Type) {
+        match self.operands[&ptr].clone() {
+            Operand::Place(Place::Local(slot, descriptor)) => self.code.load(&descriptor, slot),
+            Operand::Place(Place::Static(name, descriptor)) => {
+                let class = self.module.main_class.clone();
+                self.code
+                    .field(self.pool, op::GETSTATIC, &class, &name, &descriptor);
+            }
+            Operand::Place(Place::Field {
+                object,
+                class,
+                name,
+                descriptor,
+            }) => {
+                self.push(object);
+                self.code
+                    .field(self.pool, op::GETFIELD, &class, &name, &descriptor);
+            }
+            _ => {
+                self.push(ptr);
+                if let Type::Struct(_) = ty {
+                    self.copy(ty);
+                } else {
+                    let descriptor = self.descriptor(ty);
+                    self.code.push_int(self.pool, 0);
+                    let opcode = descriptor::array_load(&descriptor);
+                    self.code.op(opcode, 2, &[VType::of(&descriptor)]);
+                }
+            }
+        }
+    }
+
+    /// Stores `value`, of type `ty`, where `ptr` points.
+    fn store(&mut self, ptr: Value, value: Value, ty: &Type) {
+        match self.operands[&ptr].clone() {
+            Operand::Place(Place::Local(slot, descriptor)) => {
+                self.push(value);
+                self.code.store(&descriptor, slot);
+            }
+            Operand::Place(Place::Static(name, descriptor)) => {
+                self.push(value);
+                let class = self.module.main_class.clone();
+                self.code
+                    .field(self.pool, op::PUTSTATIC, &class, &name, &descriptor);
+            }
+            Operand::Place(Place::Field {
+                object,
+                class,
+                name,
+                descriptor,
+            }) => {
+                self.push(object);
+                self.push(value);
+                self.code
+                    .field(self.pool, op::PUTFIELD, &class, &name, &descriptor);
+            }
+            _ => {
+                self.push(ptr);
+                if let Type::Struct(_) = ty {
+                    self.push(value);
+                    let class = self.module.class_of(ty).to_string();
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKEVIRTUAL,
+                        &class,
+                        "set",
+                        &format!("(L{class};)V"),
+                    );
+                } else {
+                    self.code.push_int(self.pool, 0);
+                    self.push(value);
+                    let descriptor = self.descriptor(ty);
+                    self.code.op(descriptor::array_store(&descriptor), 3, &[]);
+                }
+            }
+        }
+    }
+
+    /// Copies the struct on top of the stack.
+    fn copy(&mut self, ty: &Type) {
+        let class = self.module.class_of(ty).to_string();
+        self.code.invoke(
+            self.pool,
+            op::INVOKEVIRTUAL,
+            &class,
+            "copy",
+            &format!("()L{class};"),
+        );
+    }
+
+    /// Pushes a new struct of type `ty` to store into, with every field
+    /// zero, `false` or `null`.
+    fn zero(&mut self, ty: &Type) {
+        let Type::Struct(id) = ty else {
+            unreachable!("zero of a non-struct");
+        };
+        let fields = &self.module.module.structs[id.0 as usize].fields;
+        for field in fields {
+            match &field.ty {
+                Type::Struct(_) => self.zero(&field.ty),
+                ty => self.code.push_default(&self.descriptor(ty)),
+            }
+        }
+        let class = self.module.class_of(ty).to_string();
+        let params: String = fields.iter().map(|f| self.descriptor(&f.ty)).collect();
+        self.code.invoke(
+            self.pool,
+            op::INVOKESTATIC,
+            &class,
+            "of",
+            &format!("({params})L{class};"),
+        );
+    }
+
+    fn inst(&mut self, func: &Function, inst: &ir::Inst) {
+        let result = inst.result;
+        let ty = result.map(|r| func.ty(r));
+        let unused = result.is_none_or(|r| !self.used.contains(&r));
+        let alias = |f: &mut Self, operand: Operand| {
+            if let Some(result) = result {
+                f.operands.insert(result, operand);
+            }
+        };
+        match &inst.kind {
+            InstKind::Const(constant) => {
+                let descriptor = self.descriptor(ty.expect("constant without a result"));
+                alias(self, Operand::Const(constant.clone(), descriptor));
+            }
+            InstKind::Func(id) => alias(self, Operand::Func(*id)),
+            InstKind::Global { id, span } => {
+                let at = format!("{}:{}:{}", self.module.module.source, span.line, span.col);
+                let index = self.pool.string(&at);
+                self.code.ldc(index, VType::of(STRING));
+                let class = self.module.main_class.clone();
+                let init = &self.module.init_names[id.0 as usize];
+                self.code.invoke(
+                    self.pool,
+                    op::INVOKESTATIC,
+                    &class,
+                    init,
+                    &format!("({STRING})V"),
+                );
+                let name = self.module.global_names[id.0 as usize].clone();
+                let pointee = self.module.module.globals[id.0 as usize].ty.as_ref();
+                let pointee = pointee.expect("pointer to a global without a value");
+                let descriptor = self
+                    .module
+                    .global_descriptor(*id)
+                    .expect("global of a type");
+                if self.module.boxed.contains(id) || matches!(pointee, Type::Struct(_)) {
+                    alias(self, Operand::Static(name, descriptor));
+                } else {
+                    alias(self, Operand::Place(Place::Static(name, descriptor)));
+                }
+            }
+            InstKind::Slot => {
+                let Some(Type::Ptr(pointee)) = ty else {
+                    unreachable!("slot of type {ty:?}");
+                };
+                let result = result.expect("slot without a result");
+                if let Type::Struct(_) = **pointee {
+                    self.zero(pointee);
+                    self.define(Some(result), pointee);
+                } else if self.escaping.contains(&result) {
+                    let descriptor = self.descriptor(pointee);
+                    self.code.new_cell(self.pool, &descriptor);
+                    self.define(Some(result), ty.unwrap());
+                } else {
+                    let descriptor = self.descriptor(pointee);
+                    let slot = self.code.local(VType::of(&descriptor));
+                    alias(self, Operand::Place(Place::Local(slot, descriptor)));
+                }
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                let Type::Ptr(pointee) = func.ty(*ptr) else {
+                    unreachable!("field pointer through a non-pointer");
+                };
+                let Type::Struct(id) = **pointee else {
+                    unreachable!("field pointer into a non-struct");
+                };
+                let field = &self.module.module.structs[id.0 as usize].fields[*index as usize];
+                let class = self.module.struct_names[id.0 as usize].clone();
+                let descriptor = self.descriptor(&field.ty);
+                if self.module.cells.contains(&(id, *index)) {
+                    self.push(*ptr);
+                    let cell = format!("[{descriptor}");
+                    self.code
+                        .field(self.pool, op::GETFIELD, &class, &field.name, &cell);
+                    self.define(result, ty.unwrap());
+                } else if let Type::Struct(_) = field.ty {
+                    self.push(*ptr);
+                    self.code
+                        .field(self.pool, op::GETFIELD, &class, &field.name, &descriptor);
+                    self.define(result, ty.unwrap());
+                } else {
+                    let place = Place::Field {
+                        object: *ptr,
+                        class,
+                        name: field.name.clone(),
+                        descriptor,
+                    };
+                    alias(self, Operand::Place(place));
+                }
+            }
+            // Results nobody uses are only computed for their effects.
+            _ if unused && inst.kind.is_pure() => {}
+            InstKind::Unary { op, arg } => {
+                let ty = ty.expect("unary operation without a result");
+                self.push(*arg);
+                match op {
+                    UnOp::Neg => {
+                        let (opcode, vtype) = match Kind::of(&self.descriptor(ty)) {
+                            Kind::Long => (op::LNEG, VType::Long),
+                            Kind::Float => (op::FNEG, VType::Float),
+                            Kind::Double => (op::DNEG, VType::Double),
+                            _ => (op::INEG, VType::Int),
+                        };
+                        self.code.op(opcode, 1, &[vtype]);
+                        self.wrap(ty);
+                    }
+                    UnOp::Not => {
+                        self.code.push_int(self.pool, 1);
+                        self.code.op(op::IXOR, 2, &[VType::Int]);
+                    }
+                }
+                self.define(result, ty);
+            }
+            InstKind::Binary { op, lhs, rhs, .. } => {
+                self.push(*lhs);
+                self.push(*rhs);
+                let operand = func.ty(*lhs);
+                if op.is_comparison() {
+                    self.compare(*op, operand);
+                } else {
+                    self.arith(*op, operand);
+                }
+                self.define(result, ty.expect("binary operation without a result"));
+            }
+            InstKind::Call { callee, args, .. } => {
+                let ret = self.call(func, callee, args);
+                if let Some(ret) = ret {
+                    self.define(result, &ret);
+                }
+            }
+            InstKind::Printf { format, args } => self.printf(func, format, args),
+            InstKind::Struct(fields) => {
+                let ty = ty.expect("struct without a result");
+                for field in fields {
+                    self.push(*field);
+                }
+                let class = self.module.class_of(ty).to_string();
+                let params: String = fields
+                    .iter()
+                    .map(|f| self.descriptor(func.ty(*f)))
+                    .collect();
+                let descriptor = format!("({params})L{class};");
+                self.code
+                    .invoke(self.pool, op::INVOKESTATIC, &class, "of", &descriptor);
+                self.define(result, ty);
+            }
+            InstKind::Variant { index, fields } => {
+                let Some(ty @ Type::Enum(id)) = ty else {
+                    unreachable!("variant of type {ty:?}");
+                };
+                for field in fields {
+                    self.push(*field);
+                }
+                let class = self.module.variant_class(*id, *index);
+                let base = self.module.class_of(ty);
+                let params: String = fields
+                    .iter()
+                    .map(|f| self.descriptor(func.ty(*f)))
+                    .collect();
+                let descriptor = format!("({params})L{base};");
+                self.code
+                    .invoke(self.pool, op::INVOKESTATIC, &class, "of", &descriptor);
+                self.define(result, ty);
+            }
+            InstKind::Field { base, index } => {
+                let Type::Struct(id) = *func.ty(*base) else {
+                    unreachable!("field of a non-struct");
+                };
+                let field = &self.module.module.structs[id.0 as usize].fields[*index as usize];
+                let class = self.module.struct_names[id.0 as usize].clone();
+                let descriptor = self.descriptor(&field.ty);
+                self.push(*base);
+                if self.module.cells.contains(&(id, *index)) {
+                    let cell = format!("[{descriptor}");
+                    self.code
+                        .field(self.pool, op::GETFIELD, &class, &field.name, &cell);
+                    self.code.push_int(self.pool, 0);
+                    let opcode = descriptor::array_load(&descriptor);
+                    self.code.op(opcode, 2, &[VType::of(&descriptor)]);
+                } else {
+                    self.code
+                        .field(self.pool, op::GETFIELD, &class, &field.name, &descriptor);
+                }
+                self.define(result, &field.ty);
+            }
+            InstKind::Tag(base) => {
+                self.push(*base);
+                let class = self.module.class_of(func.ty(*base)).to_string();
+                self.code.field(self.pool, op::GETFIELD, &class, "tag", "I");
+                self.define(result, ty.expect("tag without a result"));
+            }
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => {
+                let Type::Enum(id) = *func.ty(*base) else {
+                    unreachable!("payload of a non-enum");
+                };
+                let ty = ty.expect("payload without a result");
+                let class = self.module.variant_class(id, *variant);
+                let name = self.module.payload_field(id, *variant, *field);
+                let descriptor = self.descriptor(ty);
+                self.push(*base);
+                self.code.class_op(self.pool, op::CHECKCAST, &class);
+                self.code
+                    .field(self.pool, op::GETFIELD, &class, &name, &descriptor);
+                self.define(result, ty);
+            }
+            InstKind::Load(ptr) => {
+                let ty = ty.expect("load without a result");
+                self.load(*ptr, ty);
+                self.define(result, ty);
+            }
+            InstKind::Store { ptr, value } => self.store(*ptr, *value, func.ty(*value)),
+        }
+    }
+
+    /// Compares the two values of type `ty` on the stack, leaving 1 if
+    /// `op` holds and 0 if not.
+    fn compare(&mut self, op: BinOp, ty: &Type) {
+        if let Type::Str = ty {
+            self.code.invoke(
+                self.pool,
+                op::INVOKEVIRTUAL,
+                "java/lang/String",
+                "equals",
+                &format!("(L{OBJECT};)Z"),
+            );
+            if op == BinOp::Ne {
+                self.code.push_int(self.pool, 1);
+                self.code.op(op::IXOR, 2, &[VType::Int]);
+            }
+            return;
+        }
+
+        // Reduce everything to comparing an `int` with zero, or two `int`s.
+        let descriptor = self.descriptor(ty);
+        let unsigned = matches!(ty, Type::Int(int) if !int.is_signed());
+        let two_ints = match Kind::of(&descriptor) {
+            Kind::Int if unsigned && *ty == Type::Int(IntTy::U32) => {
+                self.code.invoke(
+                    self.pool,
+                    op::INVOKESTATIC,
+                    "java/lang/Integer",
+                    "compareUnsigned",
+                    "(II)I",
+                );
+                false
+            }
+            Kind::Int => true,
+            Kind::Long if unsigned => {
+                self.code.invoke(
+                    self.pool,
+                    op::INVOKESTATIC,
+                    "java/lang/Long",
+                    "compareUnsigned",
+                    "(JJ)I",
+                );
+                false
+            }
+            Kind::Long => {
+                self.code.op(op::LCMP, 2, &[VType::Int]);
+                false
+            }
+            kind => {
+                // NaN compares false with everything but `!=`.
+                let double = kind == Kind::Double;
+                let opcode = match (op, double) {
+                    (BinOp::Lt | BinOp::Le, false) => op::FCMPG,
+                    (BinOp::Lt | BinOp::Le, true) => op::DCMPG,
+                    (_, false) => op::FCMPL,
+                    (_, true) => op::DCMPL,
+                };
+                self.code.op(opcode, 2, &[VType::Int]);
+                false
+            }
+        };
+        let (opcode, pops) = match (op, two_ints) {
+            (BinOp::Eq, true) => (op::IF_ICMPEQ, 2),
+            (BinOp::Ne, true) => (op::IF_ICMPNE, 2),
+            (BinOp::Lt, true) => (op::IF_ICMPLT, 2),
+            (BinOp::Le, true) => (op::IF_ICMPLE, 2),
+            (BinOp::Gt, true) => (op::IF_ICMPGT, 2),
+            (BinOp::Ge, true) => (op::IF_ICMPGE, 2),
+            (BinOp::Eq, false) => (op::IFEQ, 1),
+            (BinOp::Ne, false) => (op::IFNE, 1),
+            (BinOp::Lt, false) => (op::IFLT, 1),
+            (BinOp::Le, false) => (op::IFLE, 1),
+            (BinOp::Gt, false) => (op::IFGT, 1),
+            (BinOp::Ge, false) => (op::IFGE, 1),
+            _ => unreachable!("{op:?} is not a comparison"),
+        };
+        let (yes, join) = (self.code.new_label(), self.code.new_label());
+        self.code.branch(opcode, yes, pops);
+        self.code.push_int(self.pool, 0);
+        self.code.branch(op::GOTO, join, 0);
+        self.code.place(yes);
+        self.code.push_int(self.pool, 1);
+        self.code.place(join);
+    }
+
+    /// Combines the two values of type `ty` on top of the stack.
+    fn arith(&mut self, op: BinOp, ty: &Type) {
+        let descriptor = self.descriptor(ty);
+        let kind = Kind::of(&descriptor);
+        let vtype = VType::of(&descriptor);
+        let helper = match (op, ty) {
+            (BinOp::Div, Type::Int(IntTy::U32)) => Some(("java/lang/Integer", "(II)I")),
+            (BinOp::Div, Type::Int(IntTy::U64)) => Some(("java/lang/Long", "(JJ)J")),
+            _ => None,
+        };
+        if let Some((class, descriptor)) = helper {
+            self.code.invoke(
+                self.pool,
+                op::INVOKESTATIC,
+                class,
+                "divideUnsigned",
+                descriptor,
+            );
+            return;
+        }
+        let base = match op {
+            BinOp::Add => op::IADD,
+            BinOp::Sub => op::ISUB,
+            BinOp::Mul => op::IMUL,
+            BinOp::Div => op::IDIV,
+            _ => unreachable!("{op:?} is not arithmetic"),
+        };
+        // The opcodes for each operation go `int`, `long`, `float`, `double`.
+        let offset = match kind {
+            Kind::Int => 0,
+            Kind::Long => 1,
+            Kind::Float => 2,
+            Kind::Double => 3,
+            Kind::Reference => unreachable!("arithmetic on references"),
+        };
+        self.code.op(base + offset, 2, &[vtype]);
+        self.wrap(ty);
+    }
+
+    /// Calls `callee`, returning the type of what it leaves on the stack.
+    fn call(&mut self, func: &Function, callee: &Callee, args: &[Value]) -> Option<Type> {
+        match callee {
+            Callee::Direct(id) => {
+                for &arg in args {
+                    self.push(arg);
+                }
+                let sig = &self.module.module.function(*id).sig;
+                let descriptor = self.module.signature(sig);
+                let class = self.module.main_class.clone();
+                let name = &self.module.method_names[id.0 as usize];
+                self.code
+                    .invoke(self.pool, op::INVOKESTATIC, &class, name, &descriptor);
+                sig.ret.clone()
+            }
+            Callee::Indirect(value) => {
+                let Type::Fn(sig) = func.ty(*value) else {
+                    unreachable!("call of a non-function");
+                };
+                self.push(*value);
+                for &arg in args {
+                    self.push(arg);
+                }
+                let descriptor = self.module.signature(sig);
+                self.code.invoke(
+                    self.pool,
+                    op::INVOKEVIRTUAL,
+                    METHOD_HANDLE,
+                    "invokeExact",
+                    &descriptor,
+                );
+                sig.ret.clone()
+            }
+        }
+    }
+
+    fn terminator(
+        &mut self,
+        member: usize,
+        func: &Function,
+        term: &Terminator,
+        next: Option<BlockId>,
+    ) {
+        let label = |f: &Self, block: BlockId| f.members[member].labels[block.0 as usize];
+        match term {
+            Terminator::Jump(target) => self.goto(member, func, target, next),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.push(*cond);
+                if otherwise.args.is_empty() {
+                    self.code.branch(op::IFEQ, label(self, otherwise.block), 1);
+                    self.goto(member, func, then, next);
+                } else if then.args.is_empty() {
+                    self.code.branch(op::IFNE, label(self, then.block), 1);
+                    self.goto(member, func, otherwise, next);
+                } else {
+                    let edge = self.code.new_label();
+                    self.code.branch(op::IFEQ, edge, 1);
+                    self.goto(member, func, then, None);
+                    self.code.place(edge);
+                    self.goto(member, func, otherwise, next);
+                }
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                let long = self.descriptor(func.ty(*value)) == "J";
+                let mut edges = Vec::new();
+                for (n, target) in cases {
+                    let to = if target.args.is_empty() {
+                        label(self, target.block)
+                    } else {
+                        let edge = self.code.new_label();
+                        edges.push((edge, target));
+                        edge
+                    };
+                    self.push(*value);
+                    if long {
+                        self.code.push_long(self.pool, *n);
+                        self.code.op(op::LCMP, 2, &[VType::Int]);
+                        self.code.branch(op::IFEQ, to, 1);
+                    } else {
+                        self.code.push_int(self.pool, *n as i32);
+                        self.code.branch(op::IF_ICMPEQ, to, 2);
+                    }
+                }
+                let next = if edges.is_empty() { next } else { None };
+                self.goto(member, func, default, next);
+                for (edge, target) in edges {
+                    self.code.place(edge);
+                    self.goto(member, func, target, None);
+                }
+            }
+            Terminator::Return(value) => match value {
+                Some(value) => {
+                    self.push(*value);
+                    let descriptor = self.descriptor(func.ty(*value));
+                    self.code.op(Kind::of(&descriptor).ret(), 1, &[]);
+                }
+                None => self.code.op(op::RETURN, 0, &[]),
+            },
+            Terminator::TailCall { callee, args } => {
+                if let Callee::Direct(id) = callee
+                    && let Some(callee) = self.group.index_of(*id)
+                {
+                    // A tail call within the group reuses the frame.
+                    for &arg in args {
+                        self.push(arg);
+                    }
+                    let params = self.members[callee].params.clone();
+                    for (slot, descriptor) in params.iter().rev() {
+                        self.code.store(descriptor, *slot);
+                    }
+                    let entry = self.members[callee].labels[0];
+                    self.code.branch(op::GOTO, entry, 0);
+                    return;
+                }
+                match self.call(func, callee, args) {
+                    Some(ret) => {
+                        let descriptor = self.descriptor(&ret);
+                        self.code.op(Kind::of(&descriptor).ret(), 1, &[]);
+                    }
+                    None => self.code.op(op::RETURN, 0, &[]),
+                }
+            }
+            Terminator::Trap { message, span } => {
+                let text = format!(
+                    "{}:{}:{}: runtime error: {message}",
+                    self.module.module.source, span.line, span.col
+                );
+                self.fail(|f| {
+                    let index = f.pool.string(&text);
+                    f.code.ldc(index, VType::of(STRING));
+                });
+            }
+            Terminator::Unreachable => self.unreachable(),
+        }
+    }
+
+    /// Flushes `System.out`, prints the message `text` pushes to
+    /// `System.err` and exits with status 1.
+    fn fail(&mut self, text: impl FnOnce(&mut Self)) {
+        let stream = "Ljava/io/PrintStream;";
+        let print = "java/io/PrintStream";
+        let system = "java/lang/System";
+        self.code
+            .field(self.pool, op::GETSTATIC, system, "out", stream);
+        self.code
+            .invoke(self.pool, op::INVOKEVIRTUAL, print, "flush", "()V");
+        self.code
+            .field(self.pool, op::GETSTATIC, system, "err", stream);
+        text(self);
+        self.code.invoke(
+            self.pool,
+            op::INVOKEVIRTUAL,
+            print,
+            "println",
+            &format!("({STRING})V"),
+        );
+        self.code.push_int(self.pool, 1);
+        self.code
+            .invoke(self.pool, op::INVOKESTATIC, system, "exit", "(I)V");
+        self.unreachable();
+    }
+
+    /// Ends a block control never leaves.
+    fn unreachable(&mut self) {
+        self.code.op(op::ACONST_NULL, 0, &[VType::Null]);
+        self.code.op(op::ATHROW, 1, &[]);
+    }
+
+    /// Pushes the arguments of `target`, stores them to its parameters, and
+    /// jumps there unless it is `next`, the block that follows.
+    fn goto(&mut self, member: usize, func: &Function, target: &Target, next: Option<BlockId>) {
+        let params = &func.block(target.block).params;
+        // Every argument is read before any parameter is written.
+        let moves: Vec<(Value, Value)> = params
+            .iter()
+            .zip(&target.args)
+            .filter(|(param, arg)| param != arg)
+            .map(|(param, arg)| (*param, *arg))
+            .collect();
+        for &(_, arg) in &moves {
+            self.push(arg);
+        }
+        for &(param, _) in moves.iter().rev() {
+            let Operand::Local(slot, descriptor) = self.operands[&param].clone() else {
+                unreachable!("block parameter {param:?} is not a local");
+            };
+            self.code.store(&descriptor, slot);
+        }
+        if next != Some(target.block) {
+            let label = self.members[member].labels[target.block.0 as usize];
+            self.code.branch(op::GOTO, label, 0);
+        }
+    }
+
+    /// Lowers `printf` to building the text with a `StringBuilder` and
+    /// printing it to `System.out`.
+    fn printf(&mut self, func: &Function, format: &str, args: &[Value]) {
+        let pieces = format::parse(format).expect("format string was checked");
+
+        let out = "Ljava/io/PrintStream;";
+        self.code
+            .field(self.pool, op::GETSTATIC, "java/lang/System", "out", out);
+        self.code.class_op(self.pool, op::NEW, BUILDER);
+        self.code.dup();
+        self.code
+            .invoke(self.pool, op::INVOKESPECIAL, BUILDER, "<init>", "()V");
+        let append = |f: &mut Self, descriptor: &str| {
+            f.code.invoke(
+                f.pool,
+                op::INVOKEVIRTUAL,
+                BUILDER,
+                "append",
+                &format!("({descriptor})L{BUILDER};"),
+            );
+        };
+
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    let index = self.pool.string(&text);
+                    self.code.ldc(index, VType::of(STRING));
+                    append(self, STRING);
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = *args.next().expect("format arguments were checked");
+            let ty = func.ty(arg);
+            let descriptor = self.descriptor(ty);
+            self.push(arg);
+            match (spec, ty) {
+                (Spec::Str, _) => append(self, STRING),
+                (Spec::Int, Type::Int(IntTy::U32)) => {
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKESTATIC,
+                        "java/lang/Integer",
+                        "toUnsignedString",
+                        &format!("(I){STRING}"),
+                    );
+                    append(self, STRING);
+                }
+                (Spec::Int, Type::Int(IntTy::U64)) => {
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKESTATIC,
+                        "java/lang/Long",
+                        "toUnsignedString",
+                        &format!("(J){STRING}"),
+                    );
+                    append(self, STRING);
+                }
+                (Spec::Int, _) => append(self, &descriptor),
+                (Spec::Float, _) => {
+                    // Like Rust's `{:.6}`: NaN and the infinities by name,
+                    // the sign of negative numbers even when they round to
+                    // zero, and otherwise the exact value of the double,
+                    // rounded to six decimals.
+                    if descriptor == "F" {
+                        self.code.op(op::F2D, 1, &[VType::Double]);
+                    }
+                    let double = self.code.local(VType::Double);
+                    self.code.store("D", double);
+                    let nan = self.code.new_label();
+                    let positive = self.code.new_label();
+                    let finite = self.code.new_label();
+                    let done = self.code.new_label();
+                    let boxed = "java/lang/Double";
+
+                    self.code.load("D", double);
+                    self.code.load("D", double);
+                    self.code.op(op::DCMPL, 2, &[VType::Int]);
+                    self.code.branch(op::IFNE, nan, 1);
+                    self.code.load("D", double);
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKESTATIC,
+                        boxed,
+                        "doubleToRawLongBits",
+                        "(D)J",
+                    );
+                    self.code.push_long(self.pool, 0);
+                    self.code.op(op::LCMP, 2, &[VType::Int]);
+                    self.code.branch(op::IFGE, positive, 1);
+                    let index = self.pool.string("-");
+                    self.code.ldc(index, VType::of(STRING));
+                    append(self, STRING);
+                    self.code.load("D", double);
+                    self.code.op(op::DNEG, 1, &[VType::Double]);
+                    self.code.store("D", double);
+                    self.code.place(positive);
+
+                    self.code.load("D", double);
+                    self.code
+                        .invoke(self.pool, op::INVOKESTATIC, boxed, "isInfinite", "(D)Z");
+                    self.code.branch(op::IFEQ, finite, 1);
+                    let index = self.pool.string("inf");
+                    self.code.ldc(index, VType::of(STRING));
+                    self.code.branch(op::GOTO, done, 0);
+
+                    self.code.place(finite);
+                    let decimal = "java/math/BigDecimal";
+                    self.code.class_op(self.pool, op::NEW, decimal);
+                    self.code.dup();
+                    self.code.load("D", double);
+                    self.code
+                        .invoke(self.pool, op::INVOKESPECIAL, decimal, "<init>", "(D)V");
+                    self.code.push_int(self.pool, 6);
+                    let rounding = "java/math/RoundingMode";
+                    self.code.field(
+                        self.pool,
+                        op::GETSTATIC,
+                        rounding,
+                        "HALF_EVEN",
+                        &format!("L{rounding};"),
+                    );
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKEVIRTUAL,
+                        decimal,
+                        "setScale",
+                        &format!("(IL{rounding};)L{decimal};"),
+                    );
+                    self.code.invoke(
+                        self.pool,
+                        op::INVOKEVIRTUAL,
+                        decimal,
+                        "toPlainString",
+                        &format!("(){STRING}"),
+                    );
+                    self.code.branch(op::GOTO, done, 0);
+
+                    self.code.place(nan);
+                    let index = self.pool.string("NaN");
+                    self.code.ldc(index, VType::of(STRING));
+                    self.code.place(done);
+                    append(self, STRING);
+                }
+            }
+        }
+
+        self.code.invoke(
+            self.pool,
+            op::INVOKEVIRTUAL,
+            BUILDER,
+            "toString",
+            &format!("(){STRING}"),
+        );
+        self.code.invoke(
+            self.pool,
+            op::INVOKEVIRTUAL,
+            "java/io/PrintStream",
+            "print",
+            &format!("({STRING})V"),
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Memory layout of Canary values, following the C rules QBE uses for
+//! aggregate types: every field is aligned to its own alignment and the
+//! size is rounded up to the largest one.
+//!
+//! Enums are a `w` tag holding the variant index, followed by the payload
+//! of the active variant. Every variant's payload starts at the same offset.
+
+use ir::{EnumId, Field, Module, StructId, Type};
+use sema::types::{FloatTy, IntTy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u64,
+    pub align: u64,
+}
+
+impl Layout {
+    const fn scalar(size: u64) -> Self {
+        Self { size, align: size }
+    }
+}
+
+fn align_to(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+pub fn layout(module: &Module, ty: &Type) -> Layout {
+    match ty {
+        Type::Int(int) => Layout::scalar(u64::from(int.bits() / 8)),
+        Type::Float(FloatTy::F64) => Layout::scalar(8),
+        // `f16` is widened to `s`, QBE's only smaller float type.
+        Type::Float(_) => Layout::scalar(4),
+        Type::Bool => Layout::scalar(1),
+        Type::Str | Type::Ptr(_) | Type::Fn(_) => Layout::scalar(8),
+        Type::Struct(id) => fields_layout(module, &module.structs[id.0 as usize].fields).1,
+        Type::Enum(id) => enum_layout(module, *id).1,
+    }
+}
+
+/// The offset of each field laid out one after another, and the layout of
+/// the whole.
+pub fn fields_layout(module: &Module, fields: &[Field]) -> (Vec<u64>, Layout) {
+    let mut offsets = Vec::with_capacity(fields.len());
+    let mut size = 0;
+    let mut align = 1;
+    for field in fields {
+        let field = layout(module, &field.ty);
+        size = align_to(size, field.align);
+        offsets.push(size);
+        size += field.size;
+        align = align.max(field.align);
+    }
+    let size = align_to(size, align);
+    (offsets, Layout { size, align })
+}
+
+/// The offset of the payload of every variant of the enum `id`, and the
+/// layout of the enum.
+pub fn enum_layout(module: &Module, id: EnumId) -> (u64, Layout) {
+    let payloads: Vec<Layout> = module.enums[id.0 as usize]
+        .variants
+        .iter()
+        .map(|v| fields_layout(module, &v.fields).1)
+        .collect();
+    let align = payloads.iter().map(|l| l.align).fold(4, u64::max);
+    let offset = align_to(4, align);
+    let size = payloads.iter().map(|l| offset + l.size).fold(4, u64::max);
+    (
+        offset,
+        Layout {
+            size: align_to(size, align),
+            align,
+        },
+    )
+}
+
+/// Offsets of the payload fields of `variant`, relative to the enum.
+pub fn variant_offsets(module: &Module, id: EnumId, variant: u32) -> Vec<u64> {
+    let (base, _) = enum_layout(module, id);
+    let fields = &module.enums[id.0 as usize].variants[variant as usize].fields;
+    let (offsets, _) = fields_layout(module, fields);
+    offsets.into_iter().map(|o| base + o).collect()
+}
+
+/// Offsets of the fields of the struct `id`.
+pub fn field_offsets(module: &Module, id: StructId) -> Vec<u64> {
+    fields_layout(module, &module.structs[id.0 as usize].fields).0
+}
+
+/// Whether values of `ty` live in memory and are handled by address.
+pub fn is_aggregate(ty: &Type) -> bool {
+    matches!(ty, Type::Struct(_) | Type::Enum(_))
+}
+
+/// QBE's base type for a value held in a temporary: `w`, `l`, `s` or `d`.
+/// Aggregates are held as `l` pointers.
+pub fn base(ty: &Type) -> char {
+    match ty {
+        Type::Int(int) if int.bits() <= 32 => 'w',
+        Type::Int(_) => 'l',
+        Type::Bool => 'w',
+        Type::Float(FloatTy::F64) => 'd',
+        Type::Float(_) => 's',
+        Type::Str | Type::Ptr(_) | Type::Fn(_) => 'l',
+        Type::Struct(_) | Type::Enum(_) => 'l',
+    }
+}
+
+/// The suffix of the load and store instructions for a scalar: `storeb`,
+/// `loadub` and so on.
+pub fn memory(ty: &Type) -> (&'static str, &'static str) {
+    match ty {
+        Type::Int(IntTy::I8) => ("b", "sb"),
+        Type::Int(IntTy::U8) | Type::Bool => ("b", "ub"),
+        Type::Int(IntTy::I16) => ("h", "sh"),
+        Type::Int(IntTy::U16) => ("h", "uh"),
+        Type::Int(IntTy::I32 | IntTy::U32) => ("w", "w"),
+        Type::Float(FloatTy::F64) => ("d", "d"),
+        Type::Float(_) => ("s", "s"),
+        _ => ("l", "l"),
+    }
+}
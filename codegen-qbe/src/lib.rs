@@ -0,0 +1,1312 @@
+//! QBE IL backend.
+//!
+//! Lowers Canary's mid-level IR to the text form of QBE's intermediate
+//! language (<https://c9x.me/compile/doc/il.html>), which `qbe` compiles to
+//! assembly for `cc` to link against libc.
+//!
+//! SSA values become QBE temporaries and blocks become QBE blocks. Instead
+//! of phi instructions, a jump copies its arguments to the parameters of
+//! the block it goes to, and QBE's own SSA construction turns the copies
+//! back into phis. Structs and enums are handled by address: an aggregate
+//! value is a pointer to memory that does not change while the value is
+//! in use, so loading or storing one copies it with `blit`, and each
+//! aggregate block parameter has memory of its own that jumps copy their
+//! argument into. Structs are declared as QBE aggregate types so they can
+//! be passed and returned by value; enums, whose layout is in [`layout`],
+//! are opaque types of the right size.
+//!
+//! QBE has no tail calls, so they are jumps within one QBE function.
+//! Functions that tail-call each other become one QBE function that takes
+//! the parameters of every member and an index saying where to start;
+//! each member is also a QBE function of its own that calls the merged one
+//! with its index. Other tail calls, through function values or to
+//! functions outside the group, are ordinary calls.
+//!
+//! Globals are zero-initialized data, each filled in the first time it is
+//! used by a function of its own, `$canary.init.<name>`, like in the
+//! interpreter. The exported `$main` is a C entry point around the Canary
+//! `main`. Runtime errors go through `$canary.error`, which prints them like
+//! the C runtime does and exits, and integer division through
+//! `$canary.div.i64` and `$canary.div.u64`, which report division by zero
+//! and wrap the one quotient that overflows, like the C runtime's.
+
+mod layout;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use ir::cfg::{TailGroup, reverse_postorder, tail_call_groups};
+use ir::{
+    BinOp, BlockId, Callee, Const, FuncId, Function, InstKind, Target, Terminator, Type, UnOp,
+    Value,
+};
+use lexer::token::Span;
+use sema::format::{self, Piece, Spec};
+use sema::types::{FloatTy, IntTy};
+
+use layout::{base, is_aggregate, layout, memory};
+
+/// Symbols the generated code defines or calls besides the functions and
+/// globals of the module.
+const RESERVED: &[&str] = &[
+    "main",
+    "canary.begin_init",
+    "canary.error",
+    "canary.div.i64",
+    "canary.div.u64",
+    "printf",
+    "dprintf",
+    "strcmp",
+    "fflush",
+    "exit",
+];
+
+/// Lowers `module` to a QBE IL module. Runtime errors name the file the
+/// module was lowered from. The module must be well formed.
+pub fn emit(module: &ir::Module) -> String {
+    let mut unit = Module {
+        module,
+        types: String::new(),
+        data: String::new(),
+        functions: String::new(),
+        type_names: HashMap::new(),
+        func_names: Vec::new(),
+        global_names: Vec::new(),
+        init_names: Vec::new(),
+        state_names: Vec::new(),
+        used: RESERVED.iter().map(|s| s.to_string()).collect(),
+        strings: HashMap::new(),
+        errors: false,
+        divides: false,
+    };
+
+    for (i, function) in module.functions.iter().enumerate() {
+        let name = if module.main == Some(FuncId(i as u32)) {
+            unit.unique("canary.main")
+        } else {
+            unit.unique(&function.name)
+        };
+        unit.func_names.push(name);
+    }
+    for global in &module.globals {
+        let name = unit.unique(&global.name);
+        if let Some(ty) = &global.ty {
+            let layout = layout(module, ty);
+            writeln!(
+                unit.data,
+                "data ${name} = align {} {{ z {} }}",
+                layout.align,
+                layout.size.max(1)
+            )
+            .unwrap();
+        }
+        unit.global_names.push(name);
+    }
+    for name in unit.global_names.clone() {
+        let init = unit.unique(&format!("canary.init.{name}"));
+        unit.init_names.push(init);
+        let state = unit.unique(&format!("canary.state.{name}"));
+        writeln!(unit.data, "data ${state} = {{ b 0 }}").unwrap();
+        unit.state_names.push(state);
+    }
+
+    for group in tail_call_groups(module) {
+        unit.group(&group);
+    }
+    for index in 0..module.globals.len() {
+        unit.init(index);
+    }
+    if !module.globals.is_empty() {
+        unit.begin_init();
+    }
+    if let Some(main) = module.main {
+        unit.entry(main);
+    }
+    if unit.divides {
+        unit.divide();
+    }
+    if unit.errors {
+        unit.error();
+    }
+
+    let mut out = String::new();
+    for section in [&unit.types, &unit.data, &unit.functions] {
+        if !section.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(section);
+        }
+    }
+    out
+}
+
+/// The QBE module being written.
+struct Module<'a> {
+    module: &'a ir::Module,
+    types: String,
+    data: String,
+    functions: String,
+    /// QBE aggregate type of each struct and enum type.
+    type_names: HashMap<Type, String>,
+    func_names: Vec<String>,
+    global_names: Vec<String>,
+    /// The function that sets each global on first use, and the byte
+    /// saying how far it got: 0 before, 1 while and 2 after.
+    init_names: Vec<String>,
+    state_names: Vec<String>,
+    /// Every global symbol, to keep them apart.
+    used: HashSet<String>,
+    /// Data symbol of each string constant.
+    strings: HashMap<String, String>,
+    /// Whether anything reports a runtime error, so `$canary.error` is
+    /// needed.
+    errors: bool,
+    /// Whether anything divides integers, so the division helpers are
+    /// needed.
+    divides: bool,
+}
+
+impl<'a> Module<'a> {
+    /// A global symbol based on `name` that is not taken yet.
+    fn unique(&mut self, name: &str) -> String {
+        let name = sanitize(name);
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while !self.used.insert(candidate.clone()) {
+            candidate = format!("{name}.{n}");
+            n += 1;
+        }
+        candidate
+    }
+
+    /// A NUL-terminated string constant.
+    fn string(&mut self, value: &str) -> String {
+        if let Some(name) = self.strings.get(value) {
+            return format!("${name}");
+        }
+        let name = self.unique(&format!("str.{}", self.strings.len()));
+
+        // Printable characters go in a quoted string, everything else as a
+        // byte, so no escaping rules are involved.
+        let mut items = Vec::new();
+        let mut run = String::new();
+        for byte in value.bytes() {
+            if (0x20..0x7f).contains(&byte) && byte != b'"' && byte != b'\\' {
+                run.push(byte as char);
+            } else {
+                if !run.is_empty() {
+                    items.push(format!("b \"{}\"", std::mem::take(&mut run)));
+                }
+                items.push(format!("b {byte}"));
+            }
+        }
+        if !run.is_empty() {
+            items.push(format!("b \"{run}\""));
+        }
+        items.push("b 0".to_string());
+
+        writeln!(self.data, "data ${name} = {{ {} }}", items.join(", ")).unwrap();
+        self.strings.insert(value.to_string(), name.clone());
+        format!("${name}")
+    }
+
+    /// A string naming where `span` is, for runtime errors.
+    fn at(&mut self, span: Span) -> String {
+        let at = format!("{}:{}:{}", self.module.source, span.line, span.col);
+        self.string(&at)
+    }
+
+    /// The QBE aggregate type of `ty`, declaring it on first use.
+    fn aggregate(&mut self, ty: &Type) -> String {
+        if let Some(name) = self.type_names.get(ty) {
+            return format!(":{name}");
+        }
+        let module = self.module;
+        let name = match ty {
+            Type::Struct(id) => sanitize(&module.structs[id.0 as usize].name),
+            Type::Enum(id) => sanitize(&module.enums[id.0 as usize].name),
+            _ => unreachable!("{ty:?} is not an aggregate"),
+        };
+        let taken: HashSet<&String> = self.type_names.values().collect();
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while taken.contains(&candidate) {
+            candidate = format!("{name}.{n}");
+            n += 1;
+        }
+        let name = candidate;
+        self.type_names.insert(ty.clone(), name.clone());
+
+        let body = match ty {
+            Type::Enum(_) => {
+                let layout = layout(module, ty);
+                format!("align {} {{ {} }}", layout.align, layout.size)
+            }
+            Type::Struct(id) => {
+                let fields: Vec<String> = module.structs[id.0 as usize]
+                    .fields
+                    .iter()
+                    .map(|f| self.field_type(&f.ty))
+                    .collect();
+                if fields.is_empty() {
+                    // QBE has no empty aggregates.
+                    "{ b }".to_string()
+                } else {
+                    format!("{{ {} }}", fields.join(", "))
+                }
+            }
+            _ => unreachable!(),
+        };
+        writeln!(self.types, "type :{name} = {body}").unwrap();
+        format!(":{name}")
+    }
+
+    fn field_type(&mut self, ty: &Type) -> String {
+        if is_aggregate(ty) {
+            return self.aggregate(ty);
+        }
+        memory(ty).0.to_string()
+    }
+
+    /// How a value of `ty` is passed to or returned from a function.
+    fn abi(&mut self, ty: &Type) -> String {
+        if is_aggregate(ty) {
+            self.aggregate(ty)
+        } else {
+            base(ty).to_string()
+        }
+    }
+
+    fn ret(&mut self, ret: Option<&Type>) -> Option<String> {
+        ret.map(|ty| self.abi(ty))
+    }
+
+    /// Writes the QBE functions of a group of functions that tail-call
+    /// each other: the function itself, or the merged function and an
+    /// entry point for each member.
+    fn group(&mut self, group: &TailGroup) {
+        let module = self.module;
+        let members = &group.members;
+        let merged = group.is_merged().then(|| {
+            let first = self.func_names[members[0].0 as usize].clone();
+            self.unique(&format!("{first}.group"))
+        });
+        let mut f = FnGen::new(self);
+        f.group = group.clone();
+        let mut params = Vec::new();
+        if merged.is_some() {
+            f.names.insert("entry".to_string());
+            params.push("w %entry".to_string());
+        }
+        for &id in members {
+            let function = module.function(id);
+            let reentered = function
+                .blocks
+                .iter()
+                .any(|b| b.term.targets().iter().any(|t| t.block == BlockId(0)))
+                || members
+                    .iter()
+                    .any(|&other| tail_calls(module.function(other), id));
+            let names = f.name_member(function, reentered);
+            f.members.push(names);
+        }
+        for (i, param, ty) in group.params(module) {
+            let abi = f.module.abi(ty);
+            let name = f.members[i].values[param.0 as usize].clone();
+            if is_aggregate(ty) && f.members[i].reentered {
+                // The argument is copied to the parameter's own memory.
+                let incoming = format!("%{}", f.fresh_name(&format!("{}.in", &name[1..])));
+                params.push(format!("{abi} {incoming}"));
+                f.store(ty, &incoming, &name);
+            } else {
+                params.push(format!("{abi} {name}"));
+            }
+        }
+
+        if merged.is_some() {
+            for i in 1..members.len() {
+                let test = f.assign('w', format!("ceqw %entry, {i}"));
+                let next = f.new_label("dispatch");
+                let label = f.members[i].labels[0].clone();
+                f.ins(format!("jnz {test}, {label}, {next}"));
+                f.label(&next);
+            }
+        }
+        for (i, &id) in members.iter().enumerate() {
+            f.function(i, module.function(id));
+        }
+
+        let ret = f.module.ret(module.function(members[0]).sig.ret.as_ref());
+        let name = merged
+            .clone()
+            .unwrap_or_else(|| f.module.func_names[members[0].0 as usize].clone());
+        f.finish(&signature(ret.as_deref(), &name, &params));
+
+        let Some(merged) = merged else {
+            return;
+        };
+        for i in 0..members.len() {
+            self.entry_point(group, i, &merged);
+        }
+    }
+
+    /// The QBE function of `id`, a member of a merged function, which calls
+    /// the merged function with its own arguments and stand-ins for those
+    /// of the other members.
+    fn entry_point(&mut self, group: &TailGroup, index: usize, merged: &str) {
+        let module = self.module;
+        let id = group.members[index];
+        let mut f = FnGen::new(self);
+        let mut params = Vec::new();
+        let mut args = vec![format!("w {index}")];
+        for (member, param, ty) in group.params(module) {
+            let abi = f.module.abi(ty);
+            let value = if member == index {
+                let temp = format!("%{}", f.fresh_name(&format!("v{}", param.0)));
+                params.push(format!("{abi} {temp}"));
+                temp
+            } else if is_aggregate(ty) {
+                // Aggregates are passed by address, so the stand-in
+                // needs memory, but nothing reads it.
+                f.alloc("unused", ty)
+            } else {
+                zero(ty)
+            };
+            args.push(format!("{abi} {value}"));
+        }
+
+        let ret = f.module.ret(module.function(id).sig.ret.as_ref());
+        let call = format!("call ${merged}({})", args.join(", "));
+        match &ret {
+            Some(abi) => {
+                let result = f.temp();
+                f.ins(format!("{result} ={abi} {call}"));
+                f.ins(format!("ret {result}"));
+            }
+            None => {
+                f.ins(call);
+                f.ins("ret".to_string());
+            }
+        }
+        let name = f.module.func_names[id.0 as usize].clone();
+        f.finish(&signature(ret.as_deref(), &name, &params));
+    }
+
+    /// `$canary.init.<name>`, which stores the value of global `index`
+    /// unless it is stored already. `%at` is where the global is used.
+    fn init(&mut self, index: usize) {
+        let module = self.module;
+        let global = &module.globals[index];
+        let name = self.global_names[index].clone();
+        let state = self.state_names[index].clone();
+        let signature = format!("function ${}(l %at)", self.init_names[index]);
+        let mut f = FnGen::new(self);
+        let begin = f.assign('w', format!("call $canary.begin_init(l ${state}, l %at)"));
+        let (set, done) = (f.new_label("set"), f.new_label("done"));
+        f.ins(format!("jnz {begin}, {set}, {done}"));
+        f.label(&set);
+        let init = &f.module.func_names[global.init.0 as usize];
+        let call = format!("call ${init}()");
+        match &global.ty {
+            Some(ty) => {
+                let abi = f.module.abi(ty);
+                let value = f.temp();
+                f.ins(format!("{value} ={abi} {call}"));
+                f.store(ty, &value, &format!("${name}"));
+            }
+            None => f.ins(call),
+        }
+        f.ins(format!("storeb 2, ${state}"));
+        f.label(&done);
+        f.ins("ret".to_string());
+        f.finish(&signature);
+    }
+
+    /// `$canary.begin_init`, which tells whether the global whose state is
+    /// at `%state` still has to be set, and marks it as being set if so.
+    /// Reading it while it is being set, at `%at`, is a runtime error.
+    fn begin_init(&mut self) {
+        let message = self.string("global read during its own initialization");
+        self.errors = true;
+        writeln!(
+            self.functions,
+            "\nfunction w $canary.begin_init(l %state, l %at) {{\n@start\n\
+             \t%s =w loadub %state\n\
+             \t%setting =w ceqw %s, 1\n\
+             \tjnz %setting, @cycle, @check\n\
+             @cycle\n\
+             \tcall $canary.error(l %at, l {message})\n\
+             \thlt\n\
+             @check\n\
+             \tjnz %s, @set, @unset\n\
+             @unset\n\
+             \tstoreb 1, %state\n\
+             \tret 1\n\
+             @set\n\
+             \tret 0\n}}"
+        )
+        .unwrap();
+    }
+
+    /// `$canary.div.i64` and `$canary.div.u64`, which divide like the C
+    /// runtime's `cy_div_i64` and `cy_div_u64`: dividing by zero is a
+    /// runtime error at `%at`, and the one quotient that overflows wraps.
+    /// Narrower integers divide as 64-bit ones.
+    fn divide(&mut self) {
+        let message = self.string("division by zero");
+        self.errors = true;
+        for (name, instruction) in [("i64", "div"), ("u64", "udiv")] {
+            // Only the signed quotient of the smallest integer and -1
+            // overflows, and negating wraps it the same way.
+            let negate = match name {
+                "i64" => {
+                    "\t%minus =w ceql %b, -1\n\
+                          \tjnz %minus, @negate, @divide\n\
+                          @negate\n\
+                          \t%negated =l neg %a\n\
+                          \tret %negated\n\
+                          @divide\n"
+                }
+                _ => "",
+            };
+            writeln!(
+                self.functions,
+                "\nfunction l $canary.div.{name}(l %a, l %b, l %at) {{\n@start\n\
+                 \t%zero =w ceql %b, 0\n\
+                 \tjnz %zero, @error, @nonzero\n\
+                 @error\n\
+                 \tcall $canary.error(l %at, l {message})\n\
+                 \thlt\n\
+                 @nonzero\n\
+                 {negate}\
+                 \t%quotient =l {instruction} %a, %b\n\
+                 \tret %quotient\n}}"
+            )
+            .unwrap();
+        }
+    }
+
+    /// The C `main`: runs the Canary `main` and returns its status.
+    fn entry(&mut self, main: FuncId) {
+        let name = &self.func_names[main.0 as usize];
+        let mut body = String::from("@start\n");
+        match self.module.function(main).sig.ret.as_ref().map(base) {
+            Some(class @ ('w' | 'l')) => {
+                writeln!(body, "\t%status ={class} call ${name}()").unwrap();
+                body.push_str("\tret %status\n");
+            }
+            _ => {
+                writeln!(body, "\tcall ${name}()").unwrap();
+                body.push_str("\tret 0\n");
+            }
+        }
+        writeln!(self.functions, "\nexport function w $main() {{\n{body}}}").unwrap();
+    }
+
+    /// `$canary.error`, which reports a runtime error at `at`, a
+    /// `file:line:col` location, after what the program printed, and exits.
+    fn error(&mut self) {
+        let format = self.string("%s: runtime error: %s\n");
+        writeln!(
+            self.functions,
+            "\nfunction $canary.error(l %at, l %message) {{\n@start\n\
+             \tcall $fflush(l 0)\n\
+             \tcall $dprintf(w 2, l {format}, ..., l %at, l %message)\n\
+             \tcall $exit(w 1)\n\
+             \thlt\n}}"
+        )
+        .unwrap();
+    }
+}
+
+/// `name` with every character QBE does not allow in names replaced.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Whether `function` tail-calls `id` directly.
+fn tail_calls(function: &Function, id: FuncId) -> bool {
+    function.blocks.iter().any(|block| match &block.term {
+        Terminator::TailCall {
+            callee: Callee::Direct(callee),
+            ..
+        } => *callee == id,
+        _ => false,
+    })
+}
+
+/// The header of a QBE function returning `ret`, if anything.
+fn signature(ret: Option<&str>, name: &str, params: &[String]) -> String {
+    match ret {
+        Some(ret) => format!("function {ret} ${name}({})", params.join(", ")),
+        None => format!("function ${name}({})", params.join(", ")),
+    }
+}
+
+/// A scalar of type `ty` with every bit zero.
+fn zero(ty: &Type) -> String {
+    match base(ty) {
+        'd' => "d_0".to_string(),
+        's' => "s_0".to_string(),
+        _ => "0".to_string(),
+    }
+}
+
+/// The QBE names of the values and blocks of one member of a group.
+struct Names {
+    values: Vec<String>,
+    labels: Vec<String>,
+    /// Whether tail calls jump to the entry.
+    reentered: bool,
+}
+
+/// Lowers one QBE function: a function, the members of a group, or one of
+/// the functions around them.
+struct FnGen<'m, 'a> {
+    module: &'m mut Module<'a>,
+    /// Stack slots, all allocated in the start block.
+    allocs: String,
+    body: String,
+    names: HashSet<String>,
+    /// The names of each member, in order.
+    members: Vec<Names>,
+    /// The group being lowered.
+    group: TailGroup,
+    /// The member being lowered.
+    member: usize,
+    /// Operands for values that are not temporaries of their own:
+    /// constants, functions, and the memory of slots, globals and
+    /// aggregates.
+    operands: HashMap<Value, String>,
+    /// The value whose memory each aggregate value points into, for those
+    /// that are part of another.
+    roots: HashMap<Value, Value>,
+    /// The memory a jump copies an aggregate argument to first, by the
+    /// parameter it is for, when the argument may overlap the parameter.
+    staging: HashMap<String, String>,
+}
+
+impl<'m, 'a> FnGen<'m, 'a> {
+    fn new(module: &'m mut Module<'a>) -> Self {
+        Self {
+            module,
+            allocs: String::new(),
+            body: String::new(),
+            names: HashSet::new(),
+            members: Vec::new(),
+            group: TailGroup::default(),
+            member: 0,
+            operands: HashMap::new(),
+            roots: HashMap::new(),
+            staging: HashMap::new(),
+        }
+    }
+
+    fn program(&self) -> &'a ir::Module {
+        self.module.module
+    }
+
+    fn finish(self, signature: &str) {
+        let mut out = String::new();
+        if !self.module.functions.is_empty() {
+            out.push('\n');
+        }
+        writeln!(out, "{signature} {{\n@start").unwrap();
+        out.push_str(&self.allocs);
+        out.push_str(&self.body);
+        out.push_str("}\n");
+        self.module.functions.push_str(&out);
+    }
+
+    /// A temporary or label name based on `hint` that is unique in this
+    /// function.
+    fn fresh_name(&mut self, hint: &str) -> String {
+        let hint = sanitize(hint);
+        let mut candidate = hint.clone();
+        let mut n = 1;
+        while !self.names.insert(candidate.clone()) {
+            candidate = format!("{hint}.{n}");
+            n += 1;
+        }
+        candidate
+    }
+
+    fn temp(&mut self) -> String {
+        format!("%{}", self.fresh_name("t"))
+    }
+
+    fn new_label(&mut self, hint: &str) -> String {
+        format!("@{}", self.fresh_name(hint))
+    }
+
+    fn ins(&mut self, line: String) {
+        writeln!(self.body, "\t{line}").unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.body, "{label}").unwrap();
+    }
+
+    /// `%t =class rhs`.
+    fn assign(&mut self, class: char, rhs: String) -> String {
+        self.assign_to(None, class, rhs)
+    }
+
+    /// `dest =class rhs`, or a new temporary if there is no `dest`.
+    fn assign_to(&mut self, dest: Option<String>, class: char, rhs: String) -> String {
+        let dest = dest.unwrap_or_else(|| self.temp());
+        self.ins(format!("{dest} ={class} {rhs}"));
+        dest
+    }
+
+    /// Stack memory for a value of `ty`.
+    fn alloc(&mut self, hint: &str, ty: &Type) -> String {
+        let slot = format!("%{}", self.fresh_name(hint));
+        self.alloc_as(&slot, ty);
+        slot
+    }
+
+    /// Stack memory for a value of `ty`, whose address is `slot`.
+    fn alloc_as(&mut self, slot: &str, ty: &Type) {
+        let layout = layout(self.program(), ty);
+        let op = match layout.align {
+            ..=4 => "alloc4",
+            8 => "alloc8",
+            _ => "alloc16",
+        };
+        writeln!(self.allocs, "\t{slot} =l {op} {}", layout.size.max(1)).unwrap();
+    }
+
+    fn load(&mut self, dest: Option<String>, ty: &Type, address: &str) -> String {
+        let (_, load) = memory(ty);
+        self.assign_to(dest, base(ty), format!("load{load} {address}"))
+    }
+
+    fn store(&mut self, ty: &Type, value: &str, address: &str) {
+        self.ins(self.store_line(ty, value, address));
+    }
+
+    /// The instruction that stores `value` at `address`, copying it if it is
+    /// an aggregate.
+    fn store_line(&self, ty: &Type, value: &str, address: &str) -> String {
+        if is_aggregate(ty) {
+            let size = layout(self.program(), ty).size;
+            return format!("blit {value}, {address}, {size}");
+        }
+        let (store, _) = memory(ty);
+        format!("store{store} {value}, {address}")
+    }
+
+    /// `dest =class rhs` for arithmetic on `ty`. The result of `w`
+    /// arithmetic on a type narrower than 32 bits is extended again, which
+    /// is how integer overflow wraps.
+    fn arith(&mut self, dest: Option<String>, ty: &Type, rhs: String) -> String {
+        let ext = match ty {
+            Type::Int(int) if int.bits() < 32 => {
+                let sign = if int.is_signed() { 's' } else { 'u' };
+                let size = if int.bits() == 8 { 'b' } else { 'h' };
+                format!("ext{sign}{size}")
+            }
+            _ => return self.assign_to(dest, base(ty), rhs),
+        };
+        let value = self.assign('w', rhs);
+        self.assign_to(dest, 'w', format!("{ext} {value}"))
+    }
+
+    /// Picks the names of the values and blocks of `function`, and gives
+    /// memory to the aggregate parameters of every block jumped to: all
+    /// but the entry, and the entry too if it is `reentered`.
+    fn name_member(&mut self, function: &Function, reentered: bool) -> Names {
+        let values: Vec<String> = (0..function.values.len())
+            .map(|n| format!("%{}", self.fresh_name(&format!("v{n}"))))
+            .collect();
+        let labels = (0..function.blocks.len())
+            .map(|n| self.new_label(&format!("b{n}")))
+            .collect();
+        let jumped = if reentered { 0 } else { 1 };
+        for block in &function.blocks[jumped..] {
+            for &param in &block.params {
+                let ty = function.ty(param);
+                if is_aggregate(ty) {
+                    self.alloc_as(&values[param.0 as usize], ty);
+                }
+            }
+        }
+        Names {
+            values,
+            labels,
+            reentered,
+        }
+    }
+
+    /// The operand for `value`.
+    fn operand(&self, value: Value) -> String {
+        match self.operands.get(&value) {
+            Some(operand) => operand.clone(),
+            None => self.members[self.member].values[value.0 as usize].clone(),
+        }
+    }
+
+    fn name(&self, value: Value) -> String {
+        self.members[self.member].values[value.0 as usize].clone()
+    }
+
+    fn block_label(&self, block: BlockId) -> String {
+        self.members[self.member].labels[block.0 as usize].clone()
+    }
+
+    /// Lowers the blocks of member `member`, `func`.
+    fn function(&mut self, member: usize, func: &Function) {
+        self.member = member;
+        self.operands.clear();
+        self.roots.clear();
+        let order = reverse_postorder(func);
+        for (i, &block) in order.iter().enumerate() {
+            let label = self.block_label(block);
+            self.label(&label);
+            let b = func.block(block);
+            for inst in &b.insts {
+                self.inst(func, inst);
+            }
+            self.terminator(func, &b.term, order.get(i + 1).copied());
+        }
+    }
+
+    fn inst(&mut self, func: &Function, inst: &ir::Inst) {
+        let ty = inst.result.map(|r| func.ty(r));
+        let result = inst.result.map(|r| self.name(r));
+        let alias = |f: &mut Self, operand: String| {
+            if let Some(r) = inst.result {
+                f.operands.insert(r, operand);
+            }
+        };
+        match &inst.kind {
+            InstKind::Const(constant) => {
+                let text = match (constant, ty) {
+                    (Const::Int(value), _) => value.to_string(),
+                    (Const::Float(value), Some(Type::Float(FloatTy::F64))) => {
+                        format!("d_{value:?}")
+                    }
+                    (Const::Float(value), _) => format!("s_{value:?}"),
+                    (Const::Bool(value), _) => u8::from(*value).to_string(),
+                    (Const::Str(value), _) => self.module.string(value),
+                };
+                alias(self, text);
+            }
+            InstKind::Func(id) => {
+                let name = format!("${}", self.module.func_names[id.0 as usize]);
+                alias(self, name);
+            }
+            InstKind::Global { id, span } => {
+                let init = self.module.init_names[id.0 as usize].clone();
+                let at = self.module.at(*span);
+                self.ins(format!("call ${init}(l {at})"));
+                let name = format!("${}", self.module.global_names[id.0 as usize]);
+                alias(self, name);
+            }
+            InstKind::Slot => {
+                let Some(Type::Ptr(pointee)) = ty else {
+                    unreachable!("slot of type {ty:?}");
+                };
+                let slot = self.alloc("slot", pointee);
+                alias(self, slot);
+            }
+            InstKind::Unary { op, arg } => {
+                let arg = self.operand(*arg);
+                let ty = ty.expect("unary operation without a result");
+                match op {
+                    UnOp::Neg if matches!(base(ty), 's' | 'd') => {
+                        self.assign_to(result, base(ty), format!("neg {arg}"))
+                    }
+                    UnOp::Neg => self.arith(result, ty, format!("sub 0, {arg}")),
+                    UnOp::Not => self.assign_to(result, 'w', format!("ceqw {arg}, 0")),
+                };
+            }
+            InstKind::Binary { op, lhs, rhs, span } => {
+                let left = self.operand(*lhs);
+                let right = self.operand(*rhs);
+                if *op == BinOp::Div
+                    && let Type::Int(int) = func.ty(*lhs)
+                {
+                    self.divide(result, *int, left, right, *span);
+                } else {
+                    self.binary(result, *op, func.ty(*lhs), left, right);
+                }
+            }
+            InstKind::Call { callee, args, .. } => {
+                let call = self.call(func, callee, args);
+                match (ty, result) {
+                    (Some(ty), Some(result)) => {
+                        let abi = self.module.abi(ty);
+                        self.ins(format!("{result} ={abi} {call}"));
+                    }
+                    _ => self.ins(call),
+                }
+            }
+            InstKind::Printf { format, args } => self.printf(func, format, args),
+            InstKind::Struct(fields) => {
+                let Some(ty @ Type::Struct(id)) = ty else {
+                    unreachable!("struct of type {ty:?}");
+                };
+                let slot = self.alloc("struct", ty);
+                let offsets = layout::field_offsets(self.program(), *id);
+                for (field, offset) in fields.iter().zip(offsets) {
+                    let value = self.operand(*field);
+                    let address = self.offset(&slot, offset);
+                    self.store(func.ty(*field), &value, &address);
+                }
+                alias(self, slot);
+            }
+            InstKind::Variant { index, fields } => {
+                let Some(ty @ Type::Enum(id)) = ty else {
+                    unreachable!("variant of type {ty:?}");
+                };
+                let slot = self.alloc("variant", ty);
+                self.ins(format!("storew {index}, {slot}"));
+                let offsets = layout::variant_offsets(self.program(), *id, *index);
+                for (field, offset) in fields.iter().zip(offsets) {
+                    let value = self.operand(*field);
+                    let address = self.offset(&slot, offset);
+                    self.store(func.ty(*field), &value, &address);
+                }
+                alias(self, slot);
+            }
+            InstKind::Field { base, index } => {
+                let Type::Struct(id) = func.ty(*base) else {
+                    unreachable!("field of a non-struct");
+                };
+                let offset = layout::field_offsets(self.program(), *id)[*index as usize];
+                let value = self.part(result, *base, offset, ty.expect("field without a result"));
+                if let Some(r) = inst.result {
+                    self.part_of(r, *base, ty);
+                }
+                alias(self, value);
+            }
+            InstKind::Tag(base) => {
+                let base = self.operand(*base);
+                self.assign_to(result, 'w', format!("loadw {base}"));
+            }
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => {
+                let Type::Enum(id) = func.ty(*base) else {
+                    unreachable!("payload of a non-enum");
+                };
+                let offset =
+                    layout::variant_offsets(self.program(), *id, *variant)[*field as usize];
+                let value = self.part(result, *base, offset, ty.expect("payload without a result"));
+                if let Some(r) = inst.result {
+                    self.part_of(r, *base, ty);
+                }
+                alias(self, value);
+            }
+            InstKind::Load(ptr) => {
+                let ty = ty.expect("load without a result");
+                let address = self.operand(*ptr);
+                let value = if is_aggregate(ty) {
+                    // A copy, since the memory may be stored to while the
+                    // value is in use.
+                    let copy = self.alloc("load", ty);
+                    self.store(ty, &address, &copy);
+                    copy
+                } else {
+                    self.load(result, ty, &address)
+                };
+                alias(self, value);
+            }
+            InstKind::Store { ptr, value } => {
+                let address = self.operand(*ptr);
+                let stored = self.operand(*value);
+                self.store(func.ty(*value), &stored, &address);
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                let Type::Ptr(pointee) = func.ty(*ptr) else {
+                    unreachable!("field pointer through a non-pointer");
+                };
+                let Type::Struct(id) = **pointee else {
+                    unreachable!("field pointer into a non-struct");
+                };
+                let offset = layout::field_offsets(self.program(), id)[*index as usize];
+                let address = self.operand(*ptr);
+                let value = self.offset(&address, offset);
+                alias(self, value);
+            }
+        }
+    }
+
+    /// The part of the aggregate `base` at `offset`, of type `ty`: its
+    /// address if it is an aggregate too, and its value otherwise.
+    fn part(&mut self, dest: Option<String>, base: Value, offset: u64, ty: &Type) -> String {
+        let base = self.operand(base);
+        let address = self.offset(&base, offset);
+        if is_aggregate(ty) {
+            address
+        } else {
+            self.load(dest, ty, &address)
+        }
+    }
+
+    /// Records that `value`, of type `ty`, points into the memory of `base`
+    /// if it is an aggregate.
+    fn part_of(&mut self, value: Value, base: Value, ty: Option<&Type>) {
+        if ty.is_some_and(is_aggregate) {
+            let root = self.roots.get(&base).copied().unwrap_or(base);
+            self.roots.insert(value, root);
+        }
+    }
+
+    fn offset(&mut self, address: &str, offset: u64) -> String {
+        if offset == 0 {
+            return address.to_string();
+        }
+        self.assign('l', format!("add {address}, {offset}"))
+    }
+
+    /// `dest = left / right` for integers of type `int`, through the
+    /// runtime helpers.
+    fn divide(
+        &mut self,
+        dest: Option<String>,
+        int: IntTy,
+        left: String,
+        right: String,
+        span: Span,
+    ) {
+        let (helper, ext) = match int.is_signed() {
+            true => ("i64", "extsw"),
+            false => ("u64", "extuw"),
+        };
+        let (left, right) = match int.bits() {
+            64 => (left, right),
+            _ => (
+                self.assign('l', format!("{ext} {left}")),
+                self.assign('l', format!("{ext} {right}")),
+            ),
+        };
+        let at = self.module.at(span);
+        self.module.divides = true;
+        let call = format!("call $canary.div.{helper}(l {left}, l {right}, l {at})");
+        self.arith(dest, &Type::Int(int), call);
+    }
+
+    /// `dest = left op right` for operands of type `ty`.
+    fn binary(&mut self, dest: Option<String>, op: BinOp, ty: &Type, left: String, right: String) {
+        if !op.is_comparison() {
+            let instruction = match op {
+                BinOp::Add => "add",
+                BinOp::Sub => "sub",
+                BinOp::Mul => "mul",
+                // Integers divide through `divide`.
+                BinOp::Div => "div",
+                _ => unreachable!("{op:?} is not arithmetic"),
+            };
+            self.arith(dest, ty, format!("{instruction} {left}, {right}"));
+            return;
+        }
+
+        let (class, left, right) = match ty {
+            Type::Str => {
+                let order = self.assign('w', format!("call $strcmp(l {left}, l {right})"));
+                ('w', order, "0".to_string())
+            }
+            _ => (base(ty), left, right),
+        };
+        let float = matches!(class, 's' | 'd');
+        let unsigned = matches!(ty, Type::Int(int) if !int.is_signed());
+        let cmp = match op {
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt if float => "lt",
+            BinOp::Le if float => "le",
+            BinOp::Gt if float => "gt",
+            BinOp::Ge if float => "ge",
+            BinOp::Lt if unsigned => "ult",
+            BinOp::Le if unsigned => "ule",
+            BinOp::Gt if unsigned => "ugt",
+            BinOp::Ge if unsigned => "uge",
+            BinOp::Lt => "slt",
+            BinOp::Le => "sle",
+            BinOp::Gt => "sgt",
+            BinOp::Ge => "sge",
+            _ => unreachable!(),
+        };
+        self.assign_to(dest, 'w', format!("c{cmp}{class} {left}, {right}"));
+    }
+
+    /// `call` and its arguments, without the result.
+    fn call(&mut self, func: &Function, callee: &Callee, args: &[Value]) -> String {
+        let target = match callee {
+            Callee::Direct(id) => format!("${}", self.module.func_names[id.0 as usize]),
+            Callee::Indirect(value) => self.operand(*value),
+        };
+        let mut list = Vec::with_capacity(args.len());
+        for &arg in args {
+            let abi = self.module.abi(func.ty(arg));
+            list.push(format!("{abi} {}", self.operand(arg)));
+        }
+        format!("call {target}({})", list.join(", "))
+    }
+
+    fn terminator(&mut self, func: &Function, term: &Terminator, next: Option<BlockId>) {
+        match term {
+            Terminator::Jump(target) => {
+                for line in self.jump(func, target, next) {
+                    self.ins(line);
+                }
+            }
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let cond = self.operand(*cond);
+                let mut edges = Vec::new();
+                let then = self.edge(func, then, &mut edges);
+                let otherwise = self.edge(func, otherwise, &mut edges);
+                self.ins(format!("jnz {cond}, {then}, {otherwise}"));
+                self.edges(edges);
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                let class = base(func.ty(*value));
+                let value = self.operand(*value);
+                let mut edges = Vec::new();
+                for (i, (n, target)) in cases.iter().enumerate() {
+                    let test = self.assign('w', format!("ceq{class} {value}, {n}"));
+                    let case = self.edge(func, target, &mut edges);
+                    // The last case goes to the default when it fails.
+                    let otherwise = if i + 1 == cases.len() {
+                        self.edge(func, default, &mut edges)
+                    } else {
+                        self.new_label("next")
+                    };
+                    self.ins(format!("jnz {test}, {case}, {otherwise}"));
+                    if i + 1 < cases.len() {
+                        self.label(&otherwise);
+                    }
+                }
+                if cases.is_empty() {
+                    for line in self.jump(func, default, next) {
+                        self.ins(line);
+                    }
+                }
+                self.edges(edges);
+            }
+            Terminator::Return(Some(value)) => {
+                let value = self.operand(*value);
+                self.ins(format!("ret {value}"));
+            }
+            Terminator::Return(None) => self.ins("ret".to_string()),
+            Terminator::TailCall { callee, args } => {
+                if let Callee::Direct(id) = callee
+                    && let Some(member) = self.group.index_of(*id)
+                {
+                    let callee = self.program().function(*id);
+                    let params: Vec<String> = callee.blocks[0]
+                        .params
+                        .iter()
+                        .map(|p| self.members[member].values[p.0 as usize].clone())
+                        .collect();
+                    // Only the caller's own entry block can overlap.
+                    let overlap = (member == self.member).then_some(BlockId(0));
+                    let mut lines = self.copy(func, overlap, &params, args);
+                    lines.push(format!("jmp {}", self.members[member].labels[0]));
+                    for line in lines {
+                        self.ins(line);
+                    }
+                    return;
+                }
+                let call = self.call(func, callee, args);
+                match &func.sig.ret {
+                    Some(ty) => {
+                        let abi = self.module.abi(ty);
+                        let result = self.temp();
+                        self.ins(format!("{result} ={abi} {call}"));
+                        self.ins(format!("ret {result}"));
+                    }
+                    None => {
+                        self.ins(call);
+                        self.ins("ret".to_string());
+                    }
+                }
+            }
+            Terminator::Trap { message, span } => {
+                let at = self.module.at(*span);
+                let message = self.module.string(message);
+                self.module.errors = true;
+                self.ins(format!("call $canary.error(l {at}, l {message})"));
+                self.ins("hlt".to_string());
+            }
+            Terminator::Unreachable => self.ins("hlt".to_string()),
+        }
+    }
+
+    /// The label a conditional jump to `target` goes to: the block itself,
+    /// or a block of its own that passes the arguments first.
+    fn edge(
+        &mut self,
+        func: &Function,
+        target: &Target,
+        edges: &mut Vec<(String, Vec<String>)>,
+    ) -> String {
+        if target.args.is_empty() {
+            return self.block_label(target.block);
+        }
+        let label = self.new_label("edge");
+        let lines = self.jump(func, target, None);
+        edges.push((label.clone(), lines));
+        label
+    }
+
+    fn edges(&mut self, edges: Vec<(String, Vec<String>)>) {
+        for (label, lines) in edges {
+            self.label(&label);
+            for line in lines {
+                self.ins(line);
+            }
+        }
+    }
+
+    /// The instructions of a jump to `target`: they copy the arguments to
+    /// its parameters, then `jmp` there unless it is `next`, the block that
+    /// follows.
+    fn jump(&mut self, func: &Function, target: &Target, next: Option<BlockId>) -> Vec<String> {
+        let params: Vec<String> = func
+            .block(target.block)
+            .params
+            .iter()
+            .map(|p| self.name(*p))
+            .collect();
+        let mut lines = self.copy(func, Some(target.block), &params, &target.args);
+        if next != Some(target.block) {
+            lines.push(format!("jmp {}", self.block_label(target.block)));
+        }
+        lines
+    }
+
+    /// Copies `args` to the parameters named `params`, all at once. A
+    /// scalar that an earlier copy would change is copied first, and so is
+    /// an aggregate that may point into the memory of a parameter of
+    /// `overlap`, a block of the function being lowered.
+    fn copy(
+        &mut self,
+        func: &Function,
+        overlap: Option<BlockId>,
+        params: &[String],
+        args: &[Value],
+    ) -> Vec<String> {
+        let overlapping: Vec<Value> =
+            overlap.map_or_else(Vec::new, |b| func.block(b).params.clone());
+        let mut copies = Vec::new();
+        let mut assigns = Vec::new();
+        let mut assigned = HashSet::new();
+        for (param, &arg) in params.iter().zip(args) {
+            let ty = func.ty(arg);
+            let value = self.operand(arg);
+            if *param == value {
+                continue;
+            }
+            if is_aggregate(ty) {
+                let root = self.roots.get(&arg).copied().unwrap_or(arg);
+                let value = if overlapping.contains(&root) {
+                    let staging = match self.staging.get(param) {
+                        Some(staging) => staging.clone(),
+                        None => {
+                            let staging = self.alloc(&format!("{}.next", &param[1..]), ty);
+                            self.staging.insert(param.clone(), staging.clone());
+                            staging
+                        }
+                    };
+                    copies.push(self.store_line(ty, &value, &staging));
+                    staging
+                } else {
+                    value
+                };
+                assigns.push(self.store_line(ty, &value, param));
+                continue;
+            }
+            let class = base(ty);
+            let value = if assigned.contains(&value) {
+                let copy = self.temp();
+                copies.push(format!("{copy} ={class} copy {value}"));
+                copy
+            } else {
+                value
+            };
+            assigned.insert(param.clone());
+            assigns.push(format!("{param} ={class} copy {value}"));
+        }
+        copies.extend(assigns);
+        copies
+    }
+
+    /// Lowers `printf` to a call to C's `printf`, translating each
+    /// placeholder into the matching conversion.
+    fn printf(&mut self, func: &Function, format: &str, args: &[Value]) {
+        let pieces = format::parse(format).expect("format string was checked");
+
+        let mut c_format = String::new();
+        let mut list = Vec::new();
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    c_format.push_str(&text.replace('%', "%%"));
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = *args.next().expect("format arguments were checked");
+            let value = self.operand(arg);
+            match (spec, func.ty(arg)) {
+                (Spec::Str, _) => {
+                    c_format.push_str("%s");
+                    list.push(format!("l {value}"));
+                }
+                (Spec::Float, Type::Float(FloatTy::F64)) => {
+                    c_format.push_str("%f");
+                    list.push(format!("d {value}"));
+                }
+                (Spec::Float, _) => {
+                    // Variadic C functions take floats as doubles.
+                    let double = self.assign('d', format!("exts {value}"));
+                    c_format.push_str("%f");
+                    list.push(format!("d {double}"));
+                }
+                (Spec::Int, ty) => {
+                    let (long, conversion) = match ty {
+                        Type::Int(int) => {
+                            (int.bits() == 64, if int.is_signed() { 'd' } else { 'u' })
+                        }
+                        _ => (false, 'd'),
+                    };
+                    let long = if long { "l" } else { "" };
+                    write!(c_format, "%{long}{conversion}").unwrap();
+                    list.push(format!("{} {value}", base(ty)));
+                }
+            }
+        }
+
+        let c_format = self.module.string(&c_format);
+        let mut call = format!("call $printf(l {c_format}, ...");
+        for arg in list {
+            write!(call, ", {arg}").unwrap();
+        }
+        call.push(')');
+        self.ins(call);
+    }
+}
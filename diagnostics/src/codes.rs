@@ -0,0 +1,66 @@
+//! Error codes shown as `error[E0001]` and reported in JSON output.
+//!
+//! Codes are never reused; retired codes stay listed here.
+
+// Lexing and parsing
+pub const UNEXPECTED_TOKEN: &str = "E0001";
+pub const UNEXPECTED_EOF: &str = "E0002";
+pub const INVALID_CHARACTER: &str = "E0003";
+pub const UNTERMINATED_STRING: &str = "E0004";
+pub const UNCLOSED_COMMENT: &str = "E0005";
+pub const INVALID_ASSIGN_TARGET: &str = "E0006";
+pub const INVALID_NUMBER: &str = "E0052";
+
+// Macro expansion
+pub const UNKNOWN_MACRO: &str = "E0007";
+pub const MACRO_ARG_COUNT: &str = "E0008";
+pub const MACRO_RECURSION_LIMIT: &str = "E0009";
+
+// Name resolution
+pub const UNDEFINED_NAME: &str = "E0010";
+pub const DUPLICATE_NAME: &str = "E0011";
+
+// Type checking
+pub const MISMATCHED_TYPES: &str = "E0020";
+pub const UNKNOWN_TYPE: &str = "E0021";
+pub const UNKNOWN_FIELD: &str = "E0022";
+pub const ARG_COUNT: &str = "E0023";
+pub const NOT_CALLABLE: &str = "E0024";
+pub const MISSING_FIELD: &str = "E0025";
+pub const CANNOT_INFER: &str = "E0026";
+pub const NON_EXHAUSTIVE: &str = "E0027";
+pub const INVALID_OPERAND: &str = "E0028";
+pub const MISSING_METHOD: &str = "E0029";
+pub const LITERAL_OUT_OF_RANGE: &str = "E0030";
+pub const INVALID_ESCAPE: &str = "E0031";
+pub const CYCLIC_TYPE: &str = "E0032";
+pub const NOT_A_VALUE: &str = "E0033";
+pub const CAPTURED_LOCAL: &str = "E0034";
+pub const MISPLACED_ITEM: &str = "E0035";
+pub const DUPLICATE_FIELD: &str = "E0036";
+
+// Mutability
+pub const ASSIGN_TO_IMMUTABLE: &str = "E0040";
+pub const CONST_TO_MUT: &str = "E0041";
+pub const NON_CONST_TYPE: &str = "E0042";
+pub const WRITE_THROUGH_REF: &str = "E0053";
+pub const MUT_REF_TO_IMMUTABLE: &str = "E0054";
+
+// Tail calls
+pub const NOT_TAIL_CALL: &str = "E0043";
+pub const INDIRECT_TAIL_CALL: &str = "E0056";
+
+// printf format strings
+pub const INVALID_FORMAT: &str = "E0044";
+pub const FORMAT_ARG_COUNT: &str = "E0045";
+pub const FORMAT_ARG_TYPE: &str = "E0046";
+
+// Constant evaluation
+pub const CONST_CYCLE: &str = "E0047";
+pub const CONST_OVERFLOW: &str = "E0048";
+pub const CONST_DIVIDE_BY_ZERO: &str = "E0049";
+pub const CONST_STACK_OVERFLOW: &str = "E0055";
+
+// Running
+pub const NO_MAIN: &str = "E0050";
+pub const RUNTIME_ERROR: &str = "E0051";
@@ -0,0 +1,226 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+use serde::Serialize;
+
+use crate::source::SourceMap;
+use crate::{Diagnostic, Label, Severity};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+/// Prints diagnostics in the format selected on the command line.
+///
+/// Human-readable output goes to stderr, JSON goes to stdout with one
+/// object per line so tools can stream it.
+pub struct Emitter<'a> {
+    pub format: MessageFormat,
+    pub color: bool,
+    sources: &'a SourceMap,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(format: MessageFormat, sources: &'a SourceMap) -> Self {
+        Self {
+            format,
+            color: std::io::stderr().is_terminal(),
+            sources,
+        }
+    }
+
+    pub fn emit(&self, diag: &Diagnostic) {
+        match self.format {
+            MessageFormat::Human => eprint!("{}", render_human(diag, self.sources, self.color)),
+            MessageFormat::Json => println!("{}", render_json(diag, self.sources)),
+        }
+    }
+
+    /// Emits every diagnostic and returns how many of them were errors.
+    pub fn emit_all<'d>(&self, diags: impl IntoIterator<Item = &'d Diagnostic>) -> usize {
+        let mut errors = 0;
+        for diag in diags {
+            if diag.is_error() {
+                errors += 1;
+            }
+            self.emit(diag);
+        }
+        errors
+    }
+}
+
+fn severity_color(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => utils::ERROR,
+        Severity::Warning => utils::WARNING,
+        Severity::Note => utils::INFO,
+    }
+}
+
+/// Renders `diag` with the offending source lines and carets underneath:
+///
+/// ```text
+/// error[E0001]: expected `;` after expression, found `}`
+///  --> tests/hello.cy:2:26
+///   |
+/// 2 |   printf("Hello, World!")
+///   |                          ^ expected `;`
+/// ```
+pub fn render_human(diag: &Diagnostic, sources: &SourceMap, color: bool) -> String {
+    let (paint, reset) = if color {
+        (severity_color(diag.severity), utils::RESET)
+    } else {
+        ("", "")
+    };
+    let secondary = if color { utils::INFO } else { "" };
+
+    let mut out = String::new();
+    let _ = write!(out, "{paint}{}", diag.severity.as_str());
+    if let Some(code) = diag.code {
+        let _ = write!(out, "[{code}]");
+    }
+    let _ = writeln!(out, "{reset}: {}", diag.message);
+
+    let mut labels: Vec<&Label> = diag
+        .labels
+        .iter()
+        .filter(|l| sources.get(l.span.file_id).is_some())
+        .collect();
+    labels.sort_by_key(|l| (l.span.file_id, l.span.start, !l.primary));
+
+    let gutter = labels
+        .iter()
+        .map(|l| l.span.line.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let pad = " ".repeat(gutter);
+
+    if let Some(first) = diag
+        .labels
+        .iter()
+        .find(|l| l.primary)
+        .or(labels.first().copied())
+        && let Some(file) = sources.get(first.span.file_id)
+    {
+        let _ = writeln!(
+            out,
+            "{pad}--> {}:{}:{}",
+            file.name, first.span.line, first.span.col
+        );
+    }
+
+    if !labels.is_empty() {
+        let _ = writeln!(out, "{pad} |");
+    }
+
+    let mut previous: Option<(usize, usize)> = None;
+    for label in &labels {
+        let file = sources.get(label.span.file_id).unwrap();
+        let line_no = file.line_of(label.span.start).min(file.line_count());
+        let text = file.line(line_no);
+        let line_start = file.line_start(line_no);
+
+        if previous != Some((label.span.file_id, line_no)) {
+            if let Some((_, prev_line)) = previous
+                && line_no > prev_line + 1
+            {
+                let _ = writeln!(out, "{pad} ...");
+            }
+            let _ = writeln!(out, "{line_no:>gutter$} | {text}");
+        }
+        previous = Some((label.span.file_id, line_no));
+
+        let before = &file.content[line_start..label.span.start.max(line_start)];
+        let indent: String = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let line_end = line_start + text.len();
+        let width = file
+            .content
+            .get(label.span.start..label.span.end.min(line_end))
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+        let (mark, mark_color) = if label.primary {
+            ('^', paint)
+        } else {
+            ('-', secondary)
+        };
+        let underline: String = std::iter::repeat_n(mark, width).collect();
+        let message = if label.message.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", label.message)
+        };
+        let _ = writeln!(
+            out,
+            "{pad} | {indent}{mark_color}{underline}{message}{reset}"
+        );
+    }
+
+    if !diag.notes.is_empty() || diag.help.is_some() {
+        if !labels.is_empty() {
+            let _ = writeln!(out, "{pad} |");
+        }
+        for note in &diag.notes {
+            let _ = writeln!(out, "{pad} = note: {note}");
+        }
+        if let Some(help) = &diag.help {
+            let _ = writeln!(out, "{pad} = help: {help}");
+        }
+    }
+    out.push('\n');
+
+    out
+}
+
+#[derive(Serialize)]
+struct JsonLabel<'a> {
+    file: Option<&'a str>,
+    start: usize,
+    end: usize,
+    line: usize,
+    col: usize,
+    message: &'a str,
+    primary: bool,
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    severity: &'static str,
+    code: Option<&'static str>,
+    message: &'a str,
+    labels: Vec<JsonLabel<'a>>,
+    notes: &'a [String],
+    help: Option<&'a str>,
+    rendered: String,
+}
+
+/// Renders `diag` as a single-line JSON object.
+pub fn render_json(diag: &Diagnostic, sources: &SourceMap) -> String {
+    let json = JsonDiagnostic {
+        severity: diag.severity.as_str(),
+        code: diag.code,
+        message: &diag.message,
+        labels: diag
+            .labels
+            .iter()
+            .map(|l| JsonLabel {
+                file: sources.get(l.span.file_id).map(|f| f.name.as_str()),
+                start: l.span.start,
+                end: l.span.end,
+                line: l.span.line,
+                col: l.span.col,
+                message: &l.message,
+                primary: l.primary,
+            })
+            .collect(),
+        notes: &diag.notes,
+        help: diag.help.as_deref(),
+        rendered: render_human(diag, sources, false),
+    };
+    serde_json::to_string(&json).unwrap_or_default()
+}
@@ -0,0 +1,79 @@
+use lexer::token::Span;
+
+pub struct SourceFile {
+    pub name: String,
+    pub content: String,
+    /// Byte offset of the start of every line.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: String, content: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            name,
+            content,
+            line_starts,
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the 1-based line containing `offset`.
+    pub fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+
+    /// Returns the text of the 1-based `line` without its line terminator.
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.content.len(), |next| next - 1);
+        self.content[start..end].trim_end_matches('\r')
+    }
+
+    /// Returns the byte offset of the start of the 1-based `line`.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line - 1]
+    }
+}
+
+/// Every file taking part in a compilation, indexed by `Span::file_id`.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Registers a file and returns the id to give to its lexer.
+    pub fn add(&mut self, name: impl Into<String>, content: impl Into<String>) -> usize {
+        self.files
+            .push(SourceFile::new(name.into(), content.into()));
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file_id: usize) -> Option<&SourceFile> {
+        self.files.get(file_id)
+    }
+
+    pub fn content(&self, file_id: usize) -> &str {
+        &self.files[file_id].content
+    }
+
+    pub fn snippet(&self, span: Span) -> Option<&str> {
+        self.get(span.file_id)?.content.get(span.start..span.end)
+    }
+}
@@ -0,0 +1,420 @@
+//! A tree-walking interpreter over the typed AST.
+//!
+//! This is the reference semantics for Canary while the compiled backends
+//! are built: `canary run` executes `main` here. Calls marked as tail calls
+//! by `sema::tail` reuse the caller's frame, so recursion in tail position
+//! runs in constant stack space like it must in every backend.
+
+mod value;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::Span;
+use sema::format::{self, Piece, Spec};
+use sema::resolve::DeclId;
+use sema::tast::*;
+use sema::types::Ty;
+
+use value::{Place, Value, round, wrap};
+
+/// Non-tail calls deeper than this are reported instead of overflowing the
+/// interpreter's own stack.
+const MAX_DEPTH: usize = 10_000;
+
+/// Stack size of the interpreter thread. Each Canary call takes a few Rust
+/// frames, large ones in debug builds, so the default is far too small for
+/// `MAX_DEPTH`. Only the pages actually used are committed.
+const STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Runs the `main` function of `program`, writing its output to `out`, and
+/// returns its exit status: the value `main` returns, or 0 for `void`.
+pub fn run(program: &TProgram, out: &mut (dyn Write + Send)) -> Result<i32, Diagnostic> {
+    let Some(main) = program.main else {
+        return Err(Diagnostic::error("no `main` function")
+            .with_code(codes::NO_MAIN)
+            .with_help("add `const main : fn() -> void = { ... };`"));
+    };
+
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("interpreter".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let mut interp = Interpreter {
+                    program,
+                    out,
+                    globals: HashMap::new(),
+                    depth: 0,
+                };
+                let status = match interp.call(main, Vec::new(), program.function(main).span)? {
+                    Value::Int(status) => status as i32,
+                    _ => 0,
+                };
+                interp.out.flush().map_err(|e| {
+                    Diagnostic::error(format!("cannot write output: {e}"))
+                        .with_code(codes::RUNTIME_ERROR)
+                })?;
+                Ok(status)
+            })
+            .expect("failed to spawn the interpreter thread")
+            .join()
+            .expect("the interpreter panicked")
+    })
+}
+
+enum Global {
+    InProgress,
+    Done(Place),
+}
+
+/// Why evaluation stopped before producing a value.
+enum Unwind {
+    Return(Value),
+    /// A call in tail position, performed by the function being left.
+    TailCall(FuncId, Vec<Value>),
+    Error(Diagnostic),
+}
+
+impl From<Diagnostic> for Unwind {
+    fn from(diag: Diagnostic) -> Self {
+        Unwind::Error(diag)
+    }
+}
+
+type Eval<T = Value> = Result<T, Unwind>;
+
+struct Interpreter<'a> {
+    program: &'a TProgram,
+    out: &'a mut (dyn Write + Send),
+    globals: HashMap<DeclId, Global>,
+    depth: usize,
+}
+
+/// Locals of one call, or of one global initializer.
+type Frame = HashMap<DeclId, Place>;
+
+fn runtime_error(span: Span, message: impl Into<String>) -> Diagnostic {
+    let message = message.into();
+    Diagnostic::error(format!("runtime error: {message}"))
+        .with_code(codes::RUNTIME_ERROR)
+        .with_primary(span, message)
+}
+
+impl<'a> Interpreter<'a> {
+    /// Calls `func` and runs any tail calls it makes in the same loop.
+    fn call(&mut self, func: FuncId, args: Vec<Value>, span: Span) -> Result<Value, Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            return Err(runtime_error(span, "stack overflow")
+                .with_note(format!("more than {MAX_DEPTH} nested calls"))
+                .with_help("make the recursive call a tail call"));
+        }
+        self.depth += 1;
+
+        let (mut func, mut args) = (func, args);
+        let result = loop {
+            let function = self.program.function(func);
+            let mut frame: Frame = function
+                .params
+                .iter()
+                .zip(args)
+                .map(|(id, value)| (*id, Place::new(value)))
+                .collect();
+            match self.eval(&function.body, &mut frame) {
+                Ok(value) | Err(Unwind::Return(value)) => break Ok(value),
+                Err(Unwind::TailCall(next, next_args)) => (func, args) = (next, next_args),
+                Err(Unwind::Error(diag)) => break Err(diag),
+            }
+        };
+
+        self.depth -= 1;
+        result
+    }
+
+    fn global(&mut self, id: DeclId, span: Span) -> Eval<Place> {
+        match self.globals.get(&id) {
+            Some(Global::Done(place)) => return Ok(place.clone()),
+            Some(Global::InProgress) => {
+                return Err(
+                    runtime_error(span, "global read during its own initialization").into(),
+                );
+            }
+            None => {}
+        }
+
+        // Globals are initialized on first use, so their order in the
+        // source does not matter.
+        let global = self.program.global(id).expect("unknown global");
+        self.globals.insert(id, Global::InProgress);
+        let value = match self.eval(&global.value, &mut Frame::new()) {
+            Ok(value) | Err(Unwind::Return(value)) => value,
+            Err(unwind) => return Err(unwind),
+        };
+        let place = Place::new(value);
+        self.globals.insert(id, Global::Done(place.clone()));
+        Ok(place)
+    }
+
+    fn eval(&mut self, expr: &TExpr, frame: &mut Frame) -> Eval {
+        Ok(match &expr.kind {
+            TExprKind::Int(value) => match &expr.ty {
+                Ty::Int(int) => Value::Int(wrap(*value as i64, *int)),
+                _ => Value::Int(*value as i64),
+            },
+            TExprKind::Float(value) => Value::Float(round(*value, &expr.ty)),
+            TExprKind::Bool(value) => Value::Bool(*value),
+            TExprKind::Str(value) => Value::Str(Rc::from(value.as_str())),
+            TExprKind::Local(_) | TExprKind::Global(_) | TExprKind::Field { .. } => {
+                self.place(expr, frame)?.get()
+            }
+            TExprKind::Function(func) => Value::Function(*func),
+            TExprKind::Builtin(_) => unreachable!("builtins are only called"),
+            TExprKind::Unary { op, expr: inner } => match op {
+                UnOp::Neg => match self.eval(inner, frame)? {
+                    Value::Int(v) => match &expr.ty {
+                        Ty::Int(int) => Value::Int(wrap(v.wrapping_neg(), *int)),
+                        _ => Value::Int(v.wrapping_neg()),
+                    },
+                    Value::Float(v) => Value::Float(-v),
+                    other => unreachable!("cannot negate {other:?}"),
+                },
+                UnOp::Not => Value::Bool(!self.eval(inner, frame)?.bool()),
+                UnOp::Ref => Value::Ref(self.place(inner, frame)?),
+                UnOp::Deref => self.place(expr, frame)?.get(),
+            },
+            TExprKind::Binary { op, lhs, rhs } => self.binary(*op, lhs, rhs, expr, frame)?,
+            TExprKind::Assign { op, target, value } => {
+                let place = self.place(target, frame)?;
+                let value = self.eval(value, frame)?;
+                let value = match op {
+                    Some(op) => self.arith(*op, place.get(), value, &target.ty, expr.span)?,
+                    None => value,
+                };
+                place.set(value);
+                Value::Void
+            }
+            TExprKind::Call {
+                callee, args, tail, ..
+            } => {
+                if let TExprKind::Builtin(Builtin::Printf) = callee.kind {
+                    return self.printf(args, expr.span, frame);
+                }
+                let Value::Function(func) = self.eval(callee, frame)? else {
+                    unreachable!("callee is not a function");
+                };
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(arg, frame)?);
+                }
+                if *tail {
+                    return Err(Unwind::TailCall(func, values));
+                }
+                self.call(func, values, expr.span)?
+            }
+            TExprKind::Struct { fields, .. } => {
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields {
+                    values.push(self.eval(field, frame)?);
+                }
+                Value::Struct(values)
+            }
+            TExprKind::Variant { index, fields, .. } => {
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields {
+                    values.push(self.eval(field, frame)?);
+                }
+                Value::Variant(*index, values)
+            }
+            TExprKind::Block(block) => {
+                for stmt in &block.stmts {
+                    match stmt {
+                        TStmt::Let { id, value } => {
+                            let value = self.eval(value, frame)?;
+                            frame.insert(*id, Place::new(value));
+                        }
+                        TStmt::Expr(value) => {
+                            self.eval(value, frame)?;
+                        }
+                    }
+                }
+                match &block.tail {
+                    Some(tail) => self.eval(tail, frame)?,
+                    None => Value::Void,
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => {
+                let value = self.eval(scrutinee, frame)?;
+                for arm in arms {
+                    if self.matches(&arm.pattern, &value, frame) {
+                        return self.eval(&arm.body, frame);
+                    }
+                }
+                return Err(runtime_error(expr.span, "no switch arm matched").into());
+            }
+            TExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, frame)?,
+                    None => Value::Void,
+                };
+                return Err(Unwind::Return(value));
+            }
+            TExprKind::Error => unreachable!("programs with errors are not run"),
+        })
+    }
+
+    /// Evaluates `expr` as a location. Expressions that are not places are
+    /// evaluated into a fresh temporary, which is what `&f(x)` refers to.
+    fn place(&mut self, expr: &TExpr, frame: &mut Frame) -> Eval<Place> {
+        match &expr.kind {
+            TExprKind::Local(id) => Ok(frame.get(id).expect("unbound local").clone()),
+            TExprKind::Global(id) => self.global(*id, expr.span),
+            TExprKind::Field { base, index } => Ok(self.place(base, frame)?.field(*index)),
+            TExprKind::Unary {
+                op: UnOp::Deref,
+                expr,
+            } => match self.eval(expr, frame)? {
+                Value::Ref(place) => Ok(place),
+                other => unreachable!("cannot dereference {other:?}"),
+            },
+            _ => Ok(Place::new(self.eval(expr, frame)?)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        lhs: &TExpr,
+        rhs: &TExpr,
+        expr: &TExpr,
+        frame: &mut Frame,
+    ) -> Eval {
+        // `&&` and `||` short-circuit.
+        match op {
+            BinOp::And => {
+                return Ok(Value::Bool(
+                    self.eval(lhs, frame)?.bool() && self.eval(rhs, frame)?.bool(),
+                ));
+            }
+            BinOp::Or => {
+                return Ok(Value::Bool(
+                    self.eval(lhs, frame)?.bool() || self.eval(rhs, frame)?.bool(),
+                ));
+            }
+            _ => {}
+        }
+
+        let a = self.eval(lhs, frame)?;
+        let b = self.eval(rhs, frame)?;
+        if !op.is_comparison() {
+            return Ok(self.arith(op, a, b, &expr.ty, expr.span)?);
+        }
+
+        let ordering = match (&a, &b) {
+            (Value::Int(x), Value::Int(y)) => match &lhs.ty {
+                Ty::Int(int) if !int.is_signed() => (*x as u64).partial_cmp(&(*y as u64)),
+                _ => x.partial_cmp(y),
+            },
+            (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+            (Value::Bool(x), Value::Bool(y)) => x.partial_cmp(y),
+            (Value::Str(x), Value::Str(y)) => x.partial_cmp(y),
+            _ => unreachable!("cannot compare {a:?} and {b:?}"),
+        };
+        Ok(Value::Bool(op.compare(ordering)))
+    }
+
+    fn arith(
+        &mut self,
+        op: BinOp,
+        a: Value,
+        b: Value,
+        ty: &Ty,
+        span: Span,
+    ) -> Result<Value, Diagnostic> {
+        match (a, b) {
+            (Value::Int(x), Value::Int(y)) => {
+                let Ty::Int(int) = ty else {
+                    unreachable!("integer arithmetic of type {ty:?}");
+                };
+                let value = match op {
+                    BinOp::Add => x.wrapping_add(y),
+                    BinOp::Sub => x.wrapping_sub(y),
+                    BinOp::Mul => x.wrapping_mul(y),
+                    BinOp::Div if y == 0 => return Err(runtime_error(span, "division by zero")),
+                    BinOp::Div if int.is_signed() => x.wrapping_div(y),
+                    BinOp::Div => ((x as u64) / (y as u64)) as i64,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Value::Int(wrap(value, *int)))
+            }
+            (Value::Float(x), Value::Float(y)) => {
+                let value = match op {
+                    BinOp::Add => x + y,
+                    BinOp::Sub => x - y,
+                    BinOp::Mul => x * y,
+                    BinOp::Div => x / y,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Value::Float(round(value, ty)))
+            }
+            (a, b) => unreachable!("cannot apply {op:?} to {a:?} and {b:?}"),
+        }
+    }
+
+    fn matches(&mut self, pattern: &TPattern, value: &Value, frame: &mut Frame) -> bool {
+        match (pattern, value) {
+            (TPattern::Wildcard, _) => true,
+            (TPattern::Int(expected), Value::Int(v)) => *expected as i64 == *v,
+            (TPattern::Bool(expected), Value::Bool(v)) => expected == v,
+            (TPattern::Str(expected), Value::Str(v)) => expected.as_str() == &**v,
+            (TPattern::Variant { index, bindings }, Value::Variant(tag, fields)) => {
+                if index != tag {
+                    return false;
+                }
+                for (id, field) in bindings {
+                    frame.insert(*id, Place::new(fields[*field].clone()));
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Prints the format string `args[0]`, replacing each placeholder with
+    /// the next argument as described in [`sema::format`].
+    fn printf(&mut self, args: &[TExpr], span: Span, frame: &mut Frame) -> Eval {
+        let (format, args) = args.split_first().expect("printf without a format string");
+        let TExprKind::Str(format) = &format.kind else {
+            unreachable!("format string is not a literal");
+        };
+        let pieces = format::parse(format).expect("format string was checked");
+
+        let mut output = String::new();
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    output.push_str(&text);
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = args.next().expect("format arguments were checked");
+            match (spec, self.eval(arg, frame)?) {
+                (Spec::Str, Value::Str(s)) => output.push_str(&s),
+                (Spec::Int, Value::Int(v)) => match &arg.ty {
+                    Ty::Int(int) if !int.is_signed() => output.push_str(&(v as u64).to_string()),
+                    _ => output.push_str(&v.to_string()),
+                },
+                (Spec::Float, Value::Float(v)) => output.push_str(&format!("{v:.6}")),
+                (spec, value) => unreachable!("{} cannot print {value:?}", spec.as_str()),
+            }
+        }
+
+        self.out
+            .write_all(output.as_bytes())
+            .map_err(|e| runtime_error(span, format!("cannot write output: {e}")))?;
+        Ok(Value::Void)
+    }
+}
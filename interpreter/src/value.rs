@@ -0,0 +1,107 @@
+//! Runtime values.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use sema::tast::FuncId;
+use sema::types::{IntTy, Ty};
+
+/// A variable's storage. References point into cells, so `&x` sees later
+/// assignments to `x`.
+pub type Cell = Rc<RefCell<Value>>;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// Every integer type, stored as its bits; see [`wrap`].
+    Int(i64),
+    /// Every float type; `f16` and `f32` are rounded on each operation.
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Void,
+    Struct(Vec<Value>),
+    Variant(usize, Vec<Value>),
+    Ref(Place),
+    Function(FuncId),
+}
+
+/// A location that can be read, written or referenced: a cell, or a field
+/// nested in the struct or variant stored there.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub cell: Cell,
+    pub path: Vec<usize>,
+}
+
+impl Place {
+    pub fn new(value: Value) -> Self {
+        Self {
+            cell: Rc::new(RefCell::new(value)),
+            path: Vec::new(),
+        }
+    }
+
+    pub fn get(&self) -> Value {
+        let cell = self.cell.borrow();
+        let mut value = &*cell;
+        for &index in &self.path {
+            value = match value {
+                Value::Struct(fields) | Value::Variant(_, fields) => &fields[index],
+                _ => unreachable!("field path into a non-aggregate"),
+            };
+        }
+        value.clone()
+    }
+
+    pub fn set(&self, new: Value) {
+        let mut cell = self.cell.borrow_mut();
+        let mut value = &mut *cell;
+        for &index in &self.path {
+            value = match value {
+                Value::Struct(fields) | Value::Variant(_, fields) => &mut fields[index],
+                _ => unreachable!("field path into a non-aggregate"),
+            };
+        }
+        *value = new;
+    }
+
+    pub fn field(&self, index: usize) -> Place {
+        let mut path = self.path.clone();
+        path.push(index);
+        Place {
+            cell: self.cell.clone(),
+            path,
+        }
+    }
+}
+
+impl Value {
+    pub fn bool(&self) -> bool {
+        match self {
+            Value::Bool(v) => *v,
+            other => unreachable!("expected a bool, found {other:?}"),
+        }
+    }
+}
+
+/// Truncates `value` to the width of `ty`, sign-extending signed types.
+/// Integer arithmetic wraps on overflow.
+pub fn wrap(value: i64, ty: IntTy) -> i64 {
+    let unused = 64 - ty.bits();
+    if unused == 0 {
+        value
+    } else if ty.is_signed() {
+        (value << unused) >> unused
+    } else {
+        ((value as u64) << unused >> unused) as i64
+    }
+}
+
+/// Rounds `value` to the precision of `ty`.
+pub fn round(value: f64, ty: &Ty) -> f64 {
+    match ty {
+        Ty::Float(sema::types::FloatTy::F64) => value,
+        // There is no stable `f16`; `f32` is the closest we can round to.
+        _ => value as f32 as f64,
+    }
+}
@@ -0,0 +1,260 @@
+//! Control-flow graph queries shared by lowering, the verifier, passes
+//! and backends.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::module::*;
+
+/// The blocks reachable from the entry, each before the blocks it jumps
+/// to unless that jump closes a cycle. The first target of a branch comes
+/// before the second, so printed functions read top to bottom.
+pub fn reverse_postorder(func: &Function) -> Vec<BlockId> {
+    let mut visited = vec![false; func.blocks.len()];
+    let mut order = Vec::new();
+    // Iterative, since chains of blocks can be as long as the source.
+    let mut stack = vec![(BlockId(0), false)];
+    while let Some((block, done)) = stack.pop() {
+        if done {
+            order.push(block);
+            continue;
+        }
+        if std::mem::replace(&mut visited[block.0 as usize], true) {
+            continue;
+        }
+        stack.push((block, true));
+        for target in func.block(block).term.targets() {
+            if !visited[target.block.0 as usize] {
+                stack.push((target.block, false));
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// The blocks that jump to each block, each listed once.
+pub fn predecessors(func: &Function) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); func.blocks.len()];
+    for (i, block) in func.blocks.iter().enumerate() {
+        for target in block.term.targets() {
+            let list: &mut Vec<BlockId> = &mut preds[target.block.0 as usize];
+            if !list.contains(&BlockId(i as u32)) {
+                list.push(BlockId(i as u32));
+            }
+        }
+    }
+    preds
+}
+
+/// The immediate dominator of every block, as in Cooper, Harvey and
+/// Kennedy's "A Simple, Fast Dominance Algorithm". The entry and blocks
+/// that cannot be reached have none.
+pub fn dominators(func: &Function) -> Vec<Option<BlockId>> {
+    let order = reverse_postorder(func);
+    let position: HashMap<BlockId, usize> =
+        order.iter().enumerate().map(|(i, b)| (*b, i)).collect();
+    let preds = predecessors(func);
+    let mut idom: Vec<Option<BlockId>> = vec![None; func.blocks.len()];
+    idom[0] = Some(BlockId(0));
+
+    let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+        while a != b {
+            while position[&a] > position[&b] {
+                a = idom[a.0 as usize].expect("processed block");
+            }
+            while position[&b] > position[&a] {
+                b = idom[b.0 as usize].expect("processed block");
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().skip(1) {
+            let mut new = None;
+            for &pred in &preds[block.0 as usize] {
+                if idom[pred.0 as usize].is_none() {
+                    continue;
+                }
+                new = Some(match new {
+                    None => pred,
+                    Some(other) => intersect(&idom, pred, other),
+                });
+            }
+            if new.is_some() && idom[block.0 as usize] != new {
+                idom[block.0 as usize] = new;
+                changed = true;
+            }
+        }
+    }
+    idom[0] = None;
+    idom
+}
+
+/// Whether every path from the entry to `b` goes through `a`.
+pub fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        match idom[b.0 as usize] {
+            Some(parent) => b = parent,
+            None => return false,
+        }
+    }
+}
+
+/// Drops the blocks that cannot be reached and the values nothing defines
+/// any more, then numbers blocks in reverse postorder and values in the
+/// order they are defined.
+pub fn compact(func: &mut Function) {
+    let order = reverse_postorder(func);
+    let mut block_ids = vec![None; func.blocks.len()];
+    for (new, old) in order.iter().enumerate() {
+        block_ids[old.0 as usize] = Some(BlockId(new as u32));
+    }
+    let mut old_blocks: Vec<Option<Block>> = std::mem::take(&mut func.blocks)
+        .into_iter()
+        .map(Some)
+        .collect();
+    func.blocks = order
+        .iter()
+        .map(|old| {
+            old_blocks[old.0 as usize]
+                .take()
+                .expect("block listed once")
+        })
+        .collect();
+
+    let mut value_ids = HashMap::new();
+    let mut values = Vec::new();
+    let mut define = |value: &mut Value, types: &[Type]| {
+        values.push(types[value.0 as usize].clone());
+        let new = Value(values.len() as u32 - 1);
+        value_ids.insert(*value, new);
+        *value = new;
+    };
+    for block in &mut func.blocks {
+        for param in &mut block.params {
+            define(param, &func.values);
+        }
+        for inst in &mut block.insts {
+            if let Some(result) = &mut inst.result {
+                define(result, &func.values);
+            }
+        }
+    }
+    func.values = values;
+
+    let rename = &mut |value: &mut Value| {
+        *value = *value_ids.get(value).expect("value used but not defined");
+    };
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            inst.kind.operands_mut(rename);
+        }
+        block.term.operands_mut(rename);
+        for target in block.term.targets_mut() {
+            target.block = block_ids[target.block.0 as usize].expect("reachable target");
+        }
+    }
+}
+
+/// Functions that reach each other through direct tail calls. A backend
+/// without tail calls across functions merges a group of several into one
+/// function, which takes the index of the member to start in, then the
+/// parameters of every member, so that those calls can jump.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TailGroup {
+    /// In the order of the module, so the first is the one the group is
+    /// named after.
+    pub members: Vec<FuncId>,
+}
+
+impl TailGroup {
+    pub fn is_merged(&self) -> bool {
+        self.members.len() > 1
+    }
+
+    /// The index of `id` in the group, if it is a member.
+    pub fn index_of(&self, id: FuncId) -> Option<usize> {
+        self.members.iter().position(|&member| member == id)
+    }
+
+    /// The parameters of every member in the order the merged function
+    /// takes them after the index: the index of the member each belongs
+    /// to, the parameter and its type.
+    pub fn params<'m>(&self, module: &'m Module) -> Vec<(usize, Value, &'m Type)> {
+        let mut params = Vec::new();
+        for (i, &id) in self.members.iter().enumerate() {
+            let func = module.function(id);
+            for &param in &func.blocks[0].params {
+                params.push((i, param, func.ty(param)));
+            }
+        }
+        params
+    }
+}
+
+/// Groups the functions of `module` that reach each other through direct
+/// tail calls. Every function is in exactly one group, most of them alone,
+/// and the members of a group have the same return type.
+pub fn tail_call_groups(module: &Module) -> Vec<TailGroup> {
+    let count = module.functions.len();
+    let edges: Vec<Vec<usize>> = module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, func)| {
+            func.blocks
+                .iter()
+                .filter_map(|block| match &block.term {
+                    Terminator::TailCall {
+                        callee: Callee::Direct(id),
+                        ..
+                    } if id.0 as usize != i
+                        // Members share the return type of the merged function.
+                        && module.function(*id).sig.ret == func.sig.ret =>
+                    {
+                        Some(id.0 as usize)
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let reach: Vec<HashSet<usize>> = (0..count)
+        .map(|start| {
+            let mut seen = HashSet::new();
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                for &next in &edges[i] {
+                    if seen.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            seen
+        })
+        .collect();
+
+    let mut grouped = vec![false; count];
+    let mut groups = Vec::new();
+    for i in 0..count {
+        if grouped[i] {
+            continue;
+        }
+        let members: Vec<FuncId> = (i..count)
+            .filter(|&j| j == i || reach[i].contains(&j) && reach[j].contains(&i))
+            .map(|j| FuncId(j as u32))
+            .collect();
+        for member in &members {
+            grouped[member.0 as usize] = true;
+        }
+        groups.push(TailGroup { members });
+    }
+    groups
+}
@@ -0,0 +1,229 @@
+//! Dead code elimination.
+//!
+//! Removes what cannot affect the program: pure instructions whose result
+//! is unused, block parameters nothing reads, blocks that cannot be
+//! reached, such as the arms of a switch folded to a jump, and functions
+//! that are not `pub` and that nothing calls or refers to. A block that is
+//! only jumped to from one other block is merged into it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg;
+use crate::module::*;
+
+pub fn dce(module: &mut Module) -> bool {
+    let mut changed = remove_functions(module);
+    for func in &mut module.functions {
+        let before = func.clone();
+        cfg::compact(func);
+        while remove_unused(func) {}
+        merge_blocks(func);
+        cfg::compact(func);
+        changed |= *func != before;
+    }
+    changed
+}
+
+/// Removes one round of unused instructions and parameters, and says
+/// whether there were any.
+fn remove_unused(func: &mut Function) -> bool {
+    let mut used = HashSet::new();
+    for block in &func.blocks {
+        for inst in &block.insts {
+            used.extend(inst.kind.operands());
+        }
+        // Arguments only count if their parameter is used, see below.
+        match &block.term {
+            Terminator::Jump(_) => {}
+            term => {
+                let mut term = term.clone();
+                for target in term.targets_mut() {
+                    target.args.clear();
+                }
+                used.extend(term.operands());
+            }
+        }
+    }
+    // A parameter is used if it is read, or passed on to a parameter that
+    // is; the entry's parameters are the function's.
+    let mut live: Vec<Vec<bool>> = func
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(b, block)| {
+            block
+                .params
+                .iter()
+                .map(|p| b == 0 || used.contains(p))
+                .collect()
+        })
+        .collect();
+    loop {
+        let mut grew = false;
+        for block in &func.blocks {
+            for target in block.term.targets() {
+                for (i, arg) in target.args.iter().enumerate() {
+                    if live[target.block.0 as usize][i] && used.insert(*arg) {
+                        grew = true;
+                    }
+                }
+            }
+        }
+        for (b, block) in func.blocks.iter().enumerate() {
+            for (i, param) in block.params.iter().enumerate() {
+                if !live[b][i] && used.contains(param) {
+                    live[b][i] = true;
+                    grew = true;
+                }
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+
+    let mut changed = false;
+    for (b, block) in func.blocks.iter_mut().enumerate() {
+        let before = block.insts.len() + block.params.len();
+        block.insts.retain(|inst| match inst.result {
+            Some(result) => used.contains(&result) || !inst.kind.is_pure(),
+            None => true,
+        });
+        let mut i = 0;
+        block.params.retain(|_| {
+            i += 1;
+            live[b][i - 1]
+        });
+        for target in block.term.targets_mut() {
+            let mut i = 0;
+            target.args.retain(|_| {
+                i += 1;
+                live[target.block.0 as usize][i - 1]
+            });
+        }
+        changed |= block.insts.len() + block.params.len() != before;
+    }
+    changed
+}
+
+/// Appends each block to the block that jumps to it, if no other block
+/// does, passing the arguments of the jump for its parameters.
+fn merge_blocks(func: &mut Function) {
+    let preds = cfg::predecessors(func);
+    let mut args = HashMap::new();
+    for a in 0..func.blocks.len() {
+        while let Terminator::Jump(target) = &func.blocks[a].term {
+            let b = target.block.0 as usize;
+            if b == 0 || b == a || preds[b].len() != 1 {
+                break;
+            }
+            let target = target.clone();
+            let dead = Block {
+                params: Vec::new(),
+                insts: Vec::new(),
+                term: Terminator::Unreachable,
+            };
+            let merged = std::mem::replace(&mut func.blocks[b], dead);
+            args.extend(merged.params.into_iter().zip(target.args));
+            func.blocks[a].insts.extend(merged.insts);
+            func.blocks[a].term = merged.term;
+        }
+    }
+    if args.is_empty() {
+        return;
+    }
+    // A parameter may be passed another merged parameter.
+    let resolve = &mut |value: &mut Value| {
+        while let Some(arg) = args.get(value) {
+            *value = *arg;
+        }
+    };
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            inst.kind.operands_mut(resolve);
+        }
+        block.term.operands_mut(resolve);
+    }
+}
+
+/// Removes the functions that are not `pub` and cannot be reached from
+/// `main` or the initializer of a global.
+fn remove_functions(module: &mut Module) -> bool {
+    let mut reached = vec![false; module.functions.len()];
+    let mut stack: Vec<FuncId> = module.globals.iter().map(|g| g.init).collect();
+    stack.extend(module.main);
+    for (i, func) in module.functions.iter().enumerate() {
+        if func.public {
+            stack.push(FuncId(i as u32));
+        }
+    }
+    while let Some(id) = stack.pop() {
+        if std::mem::replace(&mut reached[id.0 as usize], true) {
+            continue;
+        }
+        for block in &module.function(id).blocks {
+            for inst in &block.insts {
+                match &inst.kind {
+                    InstKind::Func(callee)
+                    | InstKind::Call {
+                        callee: Callee::Direct(callee),
+                        ..
+                    } => stack.push(*callee),
+                    _ => {}
+                }
+            }
+            if let Terminator::TailCall {
+                callee: Callee::Direct(callee),
+                ..
+            } = &block.term
+            {
+                stack.push(*callee);
+            }
+        }
+    }
+    if reached.iter().all(|r| *r) {
+        return false;
+    }
+
+    let mut ids = Vec::with_capacity(reached.len());
+    let mut next = 0;
+    for r in &reached {
+        ids.push(FuncId(next));
+        next += *r as u32;
+    }
+    let mut i = 0;
+    module.functions.retain(|_| {
+        i += 1;
+        reached[i - 1]
+    });
+
+    let renumber = |id: &mut FuncId| *id = ids[id.0 as usize];
+    for global in &mut module.globals {
+        renumber(&mut global.init);
+    }
+    if let Some(main) = &mut module.main {
+        renumber(main);
+    }
+    for func in &mut module.functions {
+        for block in &mut func.blocks {
+            for inst in &mut block.insts {
+                match &mut inst.kind {
+                    InstKind::Func(callee)
+                    | InstKind::Call {
+                        callee: Callee::Direct(callee),
+                        ..
+                    } => renumber(callee),
+                    _ => {}
+                }
+            }
+            if let Terminator::TailCall {
+                callee: Callee::Direct(callee),
+                ..
+            } = &mut block.term
+            {
+                renumber(callee);
+            }
+        }
+    }
+    true
+}
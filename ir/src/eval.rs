@@ -0,0 +1,393 @@
+//! Runs a module directly, with the interpreter's semantics: globals are
+//! initialized on first use, non-tail calls nest at most `MAX_DEPTH` deep
+//! and tail calls replace the caller's frame. Golden tests compare what it
+//! prints with the interpreter, so a lowering or pass that changes the
+//! meaning of a program shows up there.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::Span;
+use sema::format::{self, Piece, Spec};
+
+use crate::module::*;
+
+/// Non-tail calls deeper than this are reported like the interpreter does.
+const MAX_DEPTH: usize = 10_000;
+
+/// See `interpreter::STACK_SIZE`.
+const STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Runs the `main` function of `module`, writing its output to `out`, and
+/// returns its exit status: the value `main` returns, or 0 for none.
+pub fn run(module: &Module, out: &mut (dyn Write + Send)) -> Result<i32, Diagnostic> {
+    let Some(main) = module.main else {
+        return Err(Diagnostic::error("no `main` function").with_code(codes::NO_MAIN));
+    };
+
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("ir".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || {
+                let mut machine = Machine {
+                    module,
+                    out,
+                    globals: vec![GlobalState::Uninit; module.globals.len()],
+                    depth: 0,
+                };
+                let status = match machine.call(main, Vec::new(), Span::default())? {
+                    Some(Val::Int(status)) => status as i32,
+                    _ => 0,
+                };
+                machine.out.flush().map_err(|e| {
+                    Diagnostic::error(format!("cannot write output: {e}"))
+                        .with_code(codes::RUNTIME_ERROR)
+                })?;
+                Ok(status)
+            })
+            .expect("failed to spawn the IR thread")
+            .join()
+            .expect("the IR evaluator panicked")
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Val {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Struct(Vec<Val>),
+    Variant(u32, Vec<Val>),
+    Ptr(Place),
+    Func(FuncId),
+    /// What a slot holds before its first store.
+    Uninit,
+}
+
+type Cell = Rc<RefCell<Val>>;
+
+/// A slot or global, or a field nested in the struct stored there.
+#[derive(Debug, Clone)]
+struct Place {
+    cell: Cell,
+    path: Vec<u32>,
+}
+
+impl Place {
+    fn new(value: Val) -> Self {
+        Self {
+            cell: Rc::new(RefCell::new(value)),
+            path: Vec::new(),
+        }
+    }
+
+    fn get(&self) -> Val {
+        let mut value = &*self.cell.borrow();
+        for index in &self.path {
+            let Val::Struct(fields) = value else {
+                unreachable!("field of {value:?}");
+            };
+            value = &fields[*index as usize];
+        }
+        value.clone()
+    }
+
+    fn set(&self, new: Val) {
+        let mut cell = self.cell.borrow_mut();
+        let mut value = &mut *cell;
+        for index in &self.path {
+            let Val::Struct(fields) = value else {
+                unreachable!("field of {value:?}");
+            };
+            value = &mut fields[*index as usize];
+        }
+        *value = new;
+    }
+}
+
+#[derive(Debug, Clone)]
+enum GlobalState {
+    Uninit,
+    InProgress,
+    Done(Place),
+}
+
+struct Machine<'a> {
+    module: &'a Module,
+    out: &'a mut (dyn Write + Send),
+    globals: Vec<GlobalState>,
+    depth: usize,
+}
+
+fn runtime_error(span: Span, message: impl Into<String>) -> Diagnostic {
+    let message = message.into();
+    Diagnostic::error(format!("runtime error: {message}"))
+        .with_code(codes::RUNTIME_ERROR)
+        .with_primary(span, message)
+}
+
+impl Machine<'_> {
+    /// Calls `func` as a non-tail call.
+    fn call(
+        &mut self,
+        func: FuncId,
+        args: Vec<Val>,
+        span: Span,
+    ) -> Result<Option<Val>, Diagnostic> {
+        if self.depth == MAX_DEPTH {
+            return Err(runtime_error(span, "stack overflow")
+                .with_note(format!("more than {MAX_DEPTH} nested calls")));
+        }
+        self.depth += 1;
+        let result = self.invoke(func, args);
+        self.depth -= 1;
+        result
+    }
+
+    /// Runs `func` and any tail calls it makes.
+    fn invoke(&mut self, func: FuncId, args: Vec<Val>) -> Result<Option<Val>, Diagnostic> {
+        let (mut func, mut args) = (func, args);
+        'call: loop {
+            let function = self.module.function(func);
+            let mut values: HashMap<Value, Val> = HashMap::new();
+            let mut block = function.block(BlockId(0));
+            for (param, arg) in block.params.iter().zip(args) {
+                values.insert(*param, arg);
+            }
+            loop {
+                for inst in &block.insts {
+                    let value = self.inst(&inst.kind, function, &values)?;
+                    if let (Some(result), Some(value)) = (inst.result, value) {
+                        values.insert(result, value);
+                    }
+                }
+                let get = |value: &Value| values[value].clone();
+                let target = match &block.term {
+                    Terminator::Jump(target) => target,
+                    Terminator::Branch {
+                        cond,
+                        then,
+                        otherwise,
+                    } => match get(cond) {
+                        Val::Bool(true) => then,
+                        _ => otherwise,
+                    },
+                    Terminator::Switch {
+                        value,
+                        cases,
+                        default,
+                    } => {
+                        let Val::Int(value) = get(value) else {
+                            unreachable!("switch on a value that is not an integer");
+                        };
+                        cases
+                            .iter()
+                            .find(|(case, _)| *case == value)
+                            .map_or(default, |(_, target)| target)
+                    }
+                    Terminator::Return(value) => return Ok(value.as_ref().map(get)),
+                    Terminator::TailCall { callee, args: next } => {
+                        func = self.callee(callee, &values);
+                        args = next.iter().map(get).collect();
+                        continue 'call;
+                    }
+                    Terminator::Trap { message, span } => {
+                        return Err(runtime_error(*span, message.clone()));
+                    }
+                    Terminator::Unreachable => unreachable!("reached `unreachable`"),
+                };
+                let args: Vec<_> = target.args.iter().map(get).collect();
+                block = function.block(target.block);
+                for (param, arg) in block.params.iter().zip(args) {
+                    values.insert(*param, arg);
+                }
+            }
+        }
+    }
+
+    fn callee(&self, callee: &Callee, values: &HashMap<Value, Val>) -> FuncId {
+        match callee {
+            Callee::Direct(id) => *id,
+            Callee::Indirect(value) => match &values[value] {
+                Val::Func(id) => *id,
+                other => unreachable!("cannot call {other:?}"),
+            },
+        }
+    }
+
+    fn global(&mut self, id: GlobalId, span: Span) -> Result<Option<Place>, Diagnostic> {
+        match &self.globals[id.0 as usize] {
+            GlobalState::Done(place) => return Ok(Some(place.clone())),
+            GlobalState::InProgress => {
+                return Err(runtime_error(
+                    span,
+                    "global read during its own initialization",
+                ));
+            }
+            GlobalState::Uninit => {}
+        }
+        self.globals[id.0 as usize] = GlobalState::InProgress;
+        let global = &self.module.globals[id.0 as usize];
+        let value = self.invoke(global.init, Vec::new())?;
+        let place = Place::new(value.unwrap_or(Val::Uninit));
+        self.globals[id.0 as usize] = GlobalState::Done(place.clone());
+        Ok(Some(place).filter(|_| global.ty.is_some()))
+    }
+
+    fn inst(
+        &mut self,
+        kind: &InstKind,
+        function: &Function,
+        values: &HashMap<Value, Val>,
+    ) -> Result<Option<Val>, Diagnostic> {
+        let get = |value: &Value| values[value].clone();
+        let all = |list: &[Value]| list.iter().map(get).collect::<Vec<_>>();
+        Ok(Some(match kind {
+            InstKind::Const(value) => match value {
+                Const::Int(v) => Val::Int(*v),
+                Const::Float(v) => Val::Float(*v),
+                Const::Bool(v) => Val::Bool(*v),
+                Const::Str(v) => Val::Str(Rc::from(v.as_str())),
+            },
+            InstKind::Unary { op, arg } => match (op, get(arg), function.ty(*arg)) {
+                (UnOp::Neg, Val::Int(v), Type::Int(int)) => Val::Int(wrap(v.wrapping_neg(), *int)),
+                (UnOp::Neg, Val::Float(v), _) => Val::Float(-v),
+                (UnOp::Not, Val::Bool(v), _) => Val::Bool(!v),
+                (op, value, _) => unreachable!("cannot apply {op:?} to {value:?}"),
+            },
+            InstKind::Binary { op, lhs, rhs, span } => {
+                self.binary(*op, get(lhs), get(rhs), function.ty(*lhs), *span)?
+            }
+            InstKind::Func(id) => Val::Func(*id),
+            InstKind::Call { callee, args, span } => {
+                let func = self.callee(callee, values);
+                return self.call(func, all(args), *span);
+            }
+            InstKind::Printf { format, args } => {
+                self.printf(format, args, function, values)?;
+                return Ok(None);
+            }
+            InstKind::Struct(fields) => Val::Struct(all(fields)),
+            InstKind::Variant { index, fields } => Val::Variant(*index, all(fields)),
+            InstKind::Field { base, index } => match get(base) {
+                Val::Struct(mut fields) => fields.swap_remove(*index as usize),
+                other => unreachable!("field of {other:?}"),
+            },
+            InstKind::Tag(base) => match get(base) {
+                Val::Variant(tag, _) => Val::Int(tag as i64),
+                other => unreachable!("tag of {other:?}"),
+            },
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => match get(base) {
+                Val::Variant(tag, mut fields) if tag == *variant => {
+                    fields.swap_remove(*field as usize)
+                }
+                other => unreachable!("payload of variant {variant} from {other:?}"),
+            },
+            InstKind::Slot => Val::Ptr(Place::new(Val::Uninit)),
+            InstKind::Load(ptr) => match get(ptr) {
+                Val::Ptr(place) => place.get(),
+                other => unreachable!("load from {other:?}"),
+            },
+            InstKind::Store { ptr, value } => {
+                let Val::Ptr(place) = get(ptr) else {
+                    unreachable!("store to a value that is not a pointer");
+                };
+                place.set(get(value));
+                return Ok(None);
+            }
+            InstKind::FieldPtr { ptr, index } => match get(ptr) {
+                Val::Ptr(mut place) => {
+                    place.path.push(*index);
+                    Val::Ptr(place)
+                }
+                other => unreachable!("field pointer into {other:?}"),
+            },
+            InstKind::Global { id, span } => return Ok(self.global(*id, *span)?.map(Val::Ptr)),
+        }))
+    }
+
+    fn binary(&self, op: BinOp, a: Val, b: Val, ty: &Type, span: Span) -> Result<Val, Diagnostic> {
+        if op.is_comparison() {
+            let ordering = match (&a, &b) {
+                (Val::Int(x), Val::Int(y)) => match ty {
+                    Type::Int(int) if !int.is_signed() => (*x as u64).partial_cmp(&(*y as u64)),
+                    _ => x.partial_cmp(y),
+                },
+                (Val::Float(x), Val::Float(y)) => x.partial_cmp(y),
+                (Val::Bool(x), Val::Bool(y)) => x.partial_cmp(y),
+                (Val::Str(x), Val::Str(y)) => x.partial_cmp(y),
+                _ => unreachable!("cannot compare {a:?} and {b:?}"),
+            };
+            return Ok(Val::Bool(op.compare(ordering)));
+        }
+
+        match (a, b, ty) {
+            (Val::Int(x), Val::Int(y), Type::Int(int)) => {
+                let value = match op {
+                    BinOp::Add => x.wrapping_add(y),
+                    BinOp::Sub => x.wrapping_sub(y),
+                    BinOp::Mul => x.wrapping_mul(y),
+                    BinOp::Div if y == 0 => return Err(runtime_error(span, "division by zero")),
+                    BinOp::Div if int.is_signed() => x.wrapping_div(y),
+                    BinOp::Div => ((x as u64) / (y as u64)) as i64,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Val::Int(wrap(value, *int)))
+            }
+            (Val::Float(x), Val::Float(y), Type::Float(float)) => {
+                let value = match op {
+                    BinOp::Add => x + y,
+                    BinOp::Sub => x - y,
+                    BinOp::Mul => x * y,
+                    BinOp::Div => x / y,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Val::Float(round(value, *float)))
+            }
+            (a, b, _) => unreachable!("cannot apply {op:?} to {a:?} and {b:?}"),
+        }
+    }
+
+    fn printf(
+        &mut self,
+        format: &str,
+        args: &[Value],
+        function: &Function,
+        values: &HashMap<Value, Val>,
+    ) -> Result<(), Diagnostic> {
+        let pieces = format::parse(format).expect("format string was checked");
+        let mut output = String::new();
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    output.push_str(&text);
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = args.next().expect("format arguments were checked");
+            match (spec, &values[arg]) {
+                (Spec::Str, Val::Str(s)) => output.push_str(s),
+                (Spec::Int, Val::Int(v)) => match function.ty(*arg) {
+                    Type::Int(int) if !int.is_signed() => output.push_str(&(*v as u64).to_string()),
+                    _ => output.push_str(&v.to_string()),
+                },
+                (Spec::Float, Val::Float(v)) => output.push_str(&format!("{v:.6}")),
+                (spec, value) => unreachable!("{} cannot print {value:?}", spec.as_str()),
+            }
+        }
+        self.out
+            .write_all(output.as_bytes())
+            .map_err(|e| runtime_error(Span::default(), format!("cannot write output: {e}")))
+    }
+}
@@ -0,0 +1,319 @@
+//! Constant folding and propagation.
+//!
+//! Instructions whose operands are constants become constants, computed
+//! like [`crate::run`] computes them; a division by zero is left to fail at
+//! run time. Branches and switches on constants become jumps. Values are
+//! also forwarded where the IR makes a copy of them plain: a field of a
+//! struct built in the function, the tag and payload of a variant built in
+//! it, arithmetic with 0 or 1, and a block parameter that is passed the
+//! same value by every jump.
+
+use std::collections::HashMap;
+
+use crate::cfg;
+use crate::module::*;
+
+pub fn fold(module: &mut Module) -> bool {
+    let mut changed = false;
+    for func in &mut module.functions {
+        if fold_function(func) {
+            cfg::compact(func);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn fold_function(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut round = Folder {
+            defs: definitions(func),
+            copies: HashMap::new(),
+            changed: false,
+        };
+        round.instructions(func);
+        round.terminators(func);
+        round.params(func);
+        round.forward(func);
+        if !round.changed {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// The instruction that defines each value that is not a parameter.
+fn definitions(func: &Function) -> HashMap<Value, InstKind> {
+    func.blocks
+        .iter()
+        .flat_map(|b| &b.insts)
+        .filter_map(|inst| Some((inst.result?, inst.kind.clone())))
+        .collect()
+}
+
+struct Folder {
+    defs: HashMap<Value, InstKind>,
+    /// Values to use instead of others.
+    copies: HashMap<Value, Value>,
+    changed: bool,
+}
+
+impl Folder {
+    fn constant(&self, value: Value) -> Option<&Const> {
+        match self.defs.get(&value) {
+            Some(InstKind::Const(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// What `value` stands for after the copies found so far.
+    fn resolve(&self, mut value: Value) -> Value {
+        while let Some(copy) = self.copies.get(&value) {
+            value = *copy;
+        }
+        value
+    }
+
+    fn copy(&mut self, value: Value, to: Value) {
+        self.copies.insert(value, self.resolve(to));
+        self.changed = true;
+    }
+
+    /// Folds every instruction it can, dropping those whose result is a
+    /// copy of another value.
+    fn instructions(&mut self, func: &mut Function) {
+        for b in 0..func.blocks.len() {
+            let insts = std::mem::take(&mut func.blocks[b].insts);
+            let mut kept = Vec::with_capacity(insts.len());
+            for mut inst in insts {
+                inst.kind.operands_mut(&mut |v| *v = self.resolve(*v));
+                let fold = inst
+                    .result
+                    .and_then(|result| self.simplify(&inst.kind, func.ty(result), func));
+                match (fold, inst.result) {
+                    (Some(Fold::Const(c)), Some(result)) => {
+                        self.defs.insert(result, InstKind::Const(c.clone()));
+                        inst.kind = InstKind::Const(c);
+                        self.changed = true;
+                    }
+                    (Some(Fold::Copy(value)), Some(result)) => {
+                        self.copy(result, value);
+                        continue;
+                    }
+                    _ => {}
+                }
+                kept.push(inst);
+            }
+            func.blocks[b].insts = kept;
+        }
+    }
+
+    /// What `kind`, producing a `ty`, can be replaced by.
+    fn simplify(&self, kind: &InstKind, ty: &Type, func: &Function) -> Option<Fold> {
+        match kind {
+            InstKind::Unary { op, arg } => {
+                let value = match (op, self.constant(*arg)?, ty) {
+                    (UnOp::Neg, Const::Int(v), Type::Int(int)) => {
+                        Const::Int(wrap(v.wrapping_neg(), *int))
+                    }
+                    (UnOp::Neg, Const::Float(v), _) => Const::Float(-v),
+                    (UnOp::Not, Const::Bool(v), _) => Const::Bool(!v),
+                    _ => return None,
+                };
+                Some(Fold::Const(value))
+            }
+            InstKind::Binary { op, lhs, rhs, .. } => {
+                let operand_ty = func.ty(*lhs);
+                match (self.constant(*lhs), self.constant(*rhs)) {
+                    (Some(a), Some(b)) => binary(*op, a, b, operand_ty).map(Fold::Const),
+                    // `x + 0`, `x - 0`, `x * 1` and `x / 1`.
+                    (_, Some(Const::Int(0))) if matches!(op, BinOp::Add | BinOp::Sub) => {
+                        Some(Fold::Copy(*lhs))
+                    }
+                    (_, Some(Const::Int(1))) if matches!(op, BinOp::Mul | BinOp::Div) => {
+                        Some(Fold::Copy(*lhs))
+                    }
+                    // `0 + x` and `1 * x`.
+                    (Some(Const::Int(0)), _) if *op == BinOp::Add => Some(Fold::Copy(*rhs)),
+                    (Some(Const::Int(1)), _) if *op == BinOp::Mul => Some(Fold::Copy(*rhs)),
+                    _ => None,
+                }
+            }
+            InstKind::Field { base, index } => match self.defs.get(base) {
+                Some(InstKind::Struct(fields)) => Some(Fold::Copy(fields[*index as usize])),
+                _ => None,
+            },
+            InstKind::Tag(base) => match self.defs.get(base) {
+                Some(InstKind::Variant { index, .. }) => {
+                    Some(Fold::Const(Const::Int(*index as i64)))
+                }
+                _ => None,
+            },
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => match self.defs.get(base) {
+                Some(InstKind::Variant { index, fields }) if index == variant => {
+                    Some(Fold::Copy(fields[*field as usize]))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Turns branches and switches that can only go one way into jumps.
+    fn terminators(&mut self, func: &mut Function) {
+        for block in &mut func.blocks {
+            block.term.operands_mut(&mut |v| *v = self.resolve(*v));
+            let jump = match &block.term {
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                } => match self.constant(*cond) {
+                    Some(Const::Bool(true)) => then.clone(),
+                    Some(Const::Bool(false)) => otherwise.clone(),
+                    _ if then == otherwise => then.clone(),
+                    _ => continue,
+                },
+                Terminator::Switch {
+                    value,
+                    cases,
+                    default,
+                } => match self.constant(*value) {
+                    Some(Const::Int(v)) => cases
+                        .iter()
+                        .find(|(case, _)| case == v)
+                        .map_or(default, |(_, target)| target)
+                        .clone(),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            block.term = Terminator::Jump(jump);
+            self.changed = true;
+        }
+    }
+
+    /// Replaces the parameters that every jump passes the same value, and
+    /// drops them.
+    fn params(&mut self, func: &mut Function) {
+        let order = cfg::reverse_postorder(func);
+        let mut reachable = vec![false; func.blocks.len()];
+        for block in &order {
+            reachable[block.0 as usize] = true;
+        }
+
+        // The arguments each block is passed, by parameter.
+        let mut incoming: Vec<Vec<Vec<Value>>> = func
+            .blocks
+            .iter()
+            .map(|b| vec![Vec::new(); b.params.len()])
+            .collect();
+        for block in &order {
+            for target in func.block(*block).term.targets() {
+                for (i, arg) in target.args.iter().enumerate() {
+                    incoming[target.block.0 as usize][i].push(self.resolve(*arg));
+                }
+            }
+        }
+
+        let mut removed: Vec<Vec<bool>> = Vec::with_capacity(func.blocks.len());
+        for (b, block) in func.blocks.iter().enumerate() {
+            let mut remove = vec![false; block.params.len()];
+            // The entry's parameters are the function's.
+            if b != 0 && reachable[b] {
+                for (i, param) in block.params.iter().enumerate() {
+                    let mut args = incoming[b][i].iter().filter(|a| *a != param);
+                    let Some(first) = args.next() else {
+                        continue;
+                    };
+                    if args.all(|a| a == first) && self.resolve(*first) != *param {
+                        self.copy(*param, *first);
+                        remove[i] = true;
+                    }
+                }
+            }
+            removed.push(remove);
+        }
+
+        for (b, block) in func.blocks.iter_mut().enumerate() {
+            let mut i = 0;
+            block.params.retain(|_| {
+                i += 1;
+                !removed[b][i - 1]
+            });
+            for target in block.term.targets_mut() {
+                let mut i = 0;
+                target.args.retain(|_| {
+                    i += 1;
+                    !removed[target.block.0 as usize][i - 1]
+                });
+            }
+        }
+    }
+
+    /// Rewrites every use of a value that has a copy.
+    fn forward(&self, func: &mut Function) {
+        for block in &mut func.blocks {
+            for inst in &mut block.insts {
+                inst.kind.operands_mut(&mut |v| *v = self.resolve(*v));
+            }
+            block.term.operands_mut(&mut |v| *v = self.resolve(*v));
+        }
+    }
+}
+
+enum Fold {
+    Const(Const),
+    /// The result is this other value.
+    Copy(Value),
+}
+
+/// `a op b` for operands of type `ty`, unless it fails at run time.
+fn binary(op: BinOp, a: &Const, b: &Const, ty: &Type) -> Option<Const> {
+    if op.is_comparison() {
+        let ordering = match (a, b) {
+            (Const::Int(x), Const::Int(y)) => match ty {
+                Type::Int(int) if !int.is_signed() => (*x as u64).partial_cmp(&(*y as u64)),
+                _ => x.partial_cmp(y),
+            },
+            (Const::Float(x), Const::Float(y)) => x.partial_cmp(y),
+            (Const::Bool(x), Const::Bool(y)) => x.partial_cmp(y),
+            (Const::Str(x), Const::Str(y)) => x.partial_cmp(y),
+            _ => return None,
+        };
+        return Some(Const::Bool(op.compare(ordering)));
+    }
+
+    match (a, b, ty) {
+        (Const::Int(x), Const::Int(y), Type::Int(int)) => {
+            let value = match op {
+                BinOp::Add => x.wrapping_add(*y),
+                BinOp::Sub => x.wrapping_sub(*y),
+                BinOp::Mul => x.wrapping_mul(*y),
+                BinOp::Div if *y == 0 => return None,
+                BinOp::Div if int.is_signed() => x.wrapping_div(*y),
+                BinOp::Div => ((*x as u64) / (*y as u64)) as i64,
+                _ => return None,
+            };
+            Some(Const::Int(wrap(value, *int)))
+        }
+        (Const::Float(x), Const::Float(y), Type::Float(float)) => {
+            let value = match op {
+                BinOp::Add => x + y,
+                BinOp::Sub => x - y,
+                BinOp::Mul => x * y,
+                BinOp::Div => x / y,
+                _ => return None,
+            };
+            // Infinities and NaN have no literal.
+            let value = round(value, *float);
+            value.is_finite().then_some(Const::Float(value))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,168 @@
+//! Inlining of small functions.
+//!
+//! A direct call to a function of at most [`MAX_INSTS`] instructions is
+//! replaced by a copy of its blocks: the calling block jumps to the copy of
+//! the entry with the arguments, and each `ret` jumps to a new block that
+//! holds the rest of the calling block and takes the result as parameter.
+//!
+//! Functions that tail call, call themselves, or have slots are not
+//! inlined: a tail call would have to become a call that grows the stack,
+//! and slots only exist in a function's entry block.
+
+use std::collections::HashMap;
+
+use crate::cfg;
+use crate::module::*;
+
+/// Functions with more instructions than this are not inlined.
+const MAX_INSTS: usize = 12;
+
+pub fn inline(module: &mut Module) -> bool {
+    let mut changed = false;
+    for id in bottom_up(module) {
+        let mut work: Vec<BlockId> = (0..module.function(id).blocks.len() as u32)
+            .map(BlockId)
+            .rev()
+            .collect();
+        let mut inlined = false;
+        while let Some(block) = work.pop() {
+            let site = module
+                .function(id)
+                .block(block)
+                .insts
+                .iter()
+                .position(|inst| {
+                    matches!(
+                        inst.kind,
+                        InstKind::Call { callee: Callee::Direct(callee), .. }
+                            if callee != id && is_inlinable(module.function(callee), callee)
+                    )
+                });
+            let Some(site) = site else {
+                continue;
+            };
+            let InstKind::Call {
+                callee: Callee::Direct(callee),
+                ..
+            } = module.function(id).block(block).insts[site].kind
+            else {
+                unreachable!();
+            };
+            let callee = module.function(callee).clone();
+            let func = &mut module.functions[id.0 as usize];
+            // The rest of the block may hold more calls.
+            work.push(splice(func, block, site, &callee));
+            inlined = true;
+        }
+        if inlined {
+            cfg::compact(&mut module.functions[id.0 as usize]);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Every function, each after the functions it calls unless they call it
+/// back, so that callees are inlined into before their bodies are copied.
+/// Copies are not inlined into again, so functions that call each other
+/// are inlined once.
+fn bottom_up(module: &Module) -> Vec<FuncId> {
+    fn visit(module: &Module, id: FuncId, seen: &mut [bool], order: &mut Vec<FuncId>) {
+        if std::mem::replace(&mut seen[id.0 as usize], true) {
+            return;
+        }
+        for block in &module.function(id).blocks {
+            for inst in &block.insts {
+                if let InstKind::Call {
+                    callee: Callee::Direct(callee),
+                    ..
+                } = inst.kind
+                {
+                    visit(module, callee, seen, order);
+                }
+            }
+        }
+        order.push(id);
+    }
+    let mut seen = vec![false; module.functions.len()];
+    let mut order = Vec::with_capacity(seen.len());
+    for i in 0..module.functions.len() {
+        visit(module, FuncId(i as u32), &mut seen, &mut order);
+    }
+    order
+}
+
+fn is_inlinable(func: &Function, id: FuncId) -> bool {
+    let insts: usize = func.blocks.iter().map(|b| b.insts.len()).sum();
+    insts <= MAX_INSTS
+        && func.blocks.iter().all(|block| {
+            !matches!(block.term, Terminator::TailCall { .. })
+                && block.insts.iter().all(|inst| match &inst.kind {
+                    InstKind::Slot => false,
+                    InstKind::Call {
+                        callee: Callee::Direct(callee),
+                        ..
+                    } => *callee != id,
+                    _ => true,
+                })
+        })
+}
+
+/// Replaces the call at `site` in `block` with a copy of `callee`, and
+/// returns the block that now holds the rest of `block`.
+fn splice(func: &mut Function, block: BlockId, site: usize, callee: &Function) -> BlockId {
+    let first = func.blocks.len() as u32;
+    let rest = BlockId(first + callee.blocks.len() as u32);
+
+    let caller = &mut func.blocks[block.0 as usize];
+    let mut after = caller.insts.split_off(site);
+    let call = after.remove(0);
+    let InstKind::Call { args, .. } = call.kind else {
+        unreachable!("not a call");
+    };
+    let term = std::mem::replace(
+        &mut caller.term,
+        Terminator::Jump(Target {
+            block: BlockId(first),
+            args,
+        }),
+    );
+
+    let mut values = HashMap::new();
+    for (i, ty) in callee.values.iter().enumerate() {
+        values.insert(Value(i as u32), func.value(ty.clone()));
+    }
+    let value = |v: &mut Value| *v = values[v];
+    for source in &callee.blocks {
+        let mut block = source.clone();
+        block.params.iter_mut().for_each(value);
+        for inst in &mut block.insts {
+            inst.kind.operands_mut(&mut { value });
+            if let Some(result) = &mut inst.result {
+                value(result);
+            }
+        }
+        block.term.operands_mut(&mut { value });
+        for target in block.term.targets_mut() {
+            target.block = BlockId(first + target.block.0);
+        }
+        if let Terminator::Return(result) = &block.term {
+            block.term = Terminator::Jump(Target {
+                block: rest,
+                args: result
+                    .iter()
+                    .copied()
+                    .filter(|_| call.result.is_some())
+                    .collect(),
+            });
+        }
+        func.blocks.push(block);
+    }
+
+    func.blocks.push(Block {
+        params: call.result.into_iter().collect(),
+        insts: after,
+        term,
+    });
+    rest
+}
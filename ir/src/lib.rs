@@ -0,0 +1,35 @@
+//! Canary's mid-level IR, which sits between the typed AST and the
+//! backends so that optimizations and code generators share one input.
+//!
+//! A [`Module`] holds struct and enum layouts, globals and functions. A
+//! function is a control-flow graph of basic blocks over SSA values:
+//! every value is defined once, by a block parameter or an instruction,
+//! and jumps pass the parameters of the block they go to instead of using
+//! phi nodes. Every value has a [`Type`]. Blocks end in a [`Terminator`],
+//! among them tail calls, which every backend must compile without growing
+//! the stack.
+//!
+//! [`lower`] builds a module from the typed AST, [`verify`] checks one is
+//! well formed, [`print`] and [`parse`] convert it to and from text for
+//! golden tests, and [`run`] executes it with the interpreter's semantics.
+//! [`opt`] holds the optimization passes and the levels that select them.
+
+pub mod cfg;
+mod dce;
+mod eval;
+mod fold;
+mod inline;
+mod lower;
+mod module;
+pub mod opt;
+mod parse;
+mod print;
+mod tailrec;
+mod verify;
+
+pub use eval::run;
+pub use lower::lower;
+pub use module::*;
+pub use parse::parse;
+pub use print::print;
+pub use verify::verify;
@@ -0,0 +1,891 @@
+//! Lowers the typed AST to the IR.
+//!
+//! Locals become SSA values as they are lowered, following Braun et al.'s
+//! "Simple and Efficient Construction of SSA Form": reading a local looks
+//! for its latest definition in the current block, then in the blocks that
+//! jump there, adding a block parameter where those disagree. Canary has no
+//! loops, so every predecessor of a block is known before code goes into
+//! it. Locals that need an address, because they are referenced with `&`
+//! or assigned through a field, live in slots instead.
+
+use std::collections::{HashMap, HashSet};
+
+use lexer::token::Span;
+use sema::resolve::DeclId;
+use sema::tast::{self, Builtin, TArm, TExpr, TExprKind, TLocal, TPattern, TProgram, TStmt};
+use sema::types::{FieldDef, FloatTy, FnTy, IntTy, Ty};
+
+use crate::cfg;
+use crate::module::*;
+
+/// Lowers `program`, which must have checked without errors. `source`
+/// names the file it came from.
+pub fn lower(program: &TProgram, source: &str) -> Module {
+    let mut lowerer = Lowerer {
+        program,
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        fields: HashMap::new(),
+        globals: HashMap::new(),
+    };
+    let mut module = Module {
+        source: source.to_string(),
+        ..Module::default()
+    };
+
+    let mut structs: Vec<_> = program.structs.keys().copied().collect();
+    structs.sort();
+    let mut enums: Vec<_> = program.enums.keys().copied().collect();
+    enums.sort();
+    for (i, id) in structs.iter().enumerate() {
+        lowerer.structs.insert(*id, StructId(i as u32));
+    }
+    for (i, id) in enums.iter().enumerate() {
+        lowerer.enums.insert(*id, EnumId(i as u32));
+    }
+    for id in &structs {
+        let def = &program.structs[id];
+        let (fields, indices) = lowerer.fields(&def.fields);
+        lowerer.fields.insert(*id, vec![indices]);
+        module.structs.push(StructLayout {
+            name: def.name.clone(),
+            fields,
+        });
+    }
+    for id in &enums {
+        let def = &program.enums[id];
+        let mut variants = Vec::new();
+        let mut indices = Vec::new();
+        for variant in &def.variants {
+            let (fields, index) = lowerer.fields(&variant.fields);
+            indices.push(index);
+            variants.push(VariantLayout {
+                name: variant.name.clone(),
+                fields,
+            });
+        }
+        lowerer.fields.insert(*id, indices);
+        module.enums.push(EnumLayout {
+            name: def.name.clone(),
+            variants,
+        });
+    }
+
+    let mut names = HashSet::new();
+    let mut unique = |name: String| {
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while !names.insert(candidate.clone()) {
+            candidate = format!("{name}.{n}");
+            n += 1;
+        }
+        candidate
+    };
+    let function_names: Vec<_> = program
+        .functions
+        .iter()
+        .map(|f| unique(f.name.clone()))
+        .collect();
+
+    let functions = program.functions.len() as u32;
+    let order = program.init_order();
+    for (i, id) in order.iter().enumerate() {
+        let global = program.global(*id).expect("ordered global exists");
+        lowerer.globals.insert(*id, GlobalId(i as u32));
+        module.globals.push(Global {
+            name: global.name.clone(),
+            ty: lowerer.ty(&global.ty),
+            init: FuncId(functions + i as u32),
+        });
+    }
+
+    for (function, name) in program.functions.iter().zip(function_names) {
+        let sig = lowerer.sig(&function.ty);
+        let mut f = FnLower::new(&lowerer, name, sig, &function.locals, &function.body);
+        for id in &function.params {
+            let value = f.local_ty(*id).map(|ty| f.param(BlockId(0), ty));
+            f.bind(*id, value);
+        }
+        f.func.public = function.public;
+        module.functions.push(f.finish(&function.body));
+    }
+    for id in &order {
+        let global = program.global(*id).expect("ordered global exists");
+        let sig = Signature {
+            params: Vec::new(),
+            ret: lowerer.ty(&global.ty),
+        };
+        let name = unique(format!("{}.init", global.name));
+        let f = FnLower::new(&lowerer, name, sig, &global.locals, &global.value);
+        module.functions.push(f.finish(&global.value));
+    }
+    module.main = program.main.map(|id| FuncId(id.0));
+    module
+}
+
+struct Lowerer<'p> {
+    program: &'p TProgram,
+    structs: HashMap<DeclId, StructId>,
+    enums: HashMap<DeclId, EnumId>,
+    /// Where each source field of a struct, or of each variant of an enum,
+    /// is in the layout. Fields without a value have no place.
+    fields: HashMap<DeclId, Vec<Vec<Option<u32>>>>,
+    globals: HashMap<DeclId, GlobalId>,
+}
+
+impl Lowerer<'_> {
+    fn ty(&self, ty: &Ty) -> Option<Type> {
+        Some(match ty {
+            Ty::Int(int) => Type::Int(*int),
+            Ty::IntVar(_) => Type::Int(IntTy::I32),
+            Ty::Float(float) => Type::Float(*float),
+            Ty::FloatVar(_) => Type::Float(FloatTy::F64),
+            Ty::Bool => Type::Bool,
+            Ty::Str => Type::Str,
+            Ty::Struct(id) => Type::Struct(self.structs[id]),
+            Ty::Enum(id) => Type::Enum(self.enums[id]),
+            Ty::Ref(inner, _) => Type::Ptr(Box::new(self.ty(inner)?)),
+            Ty::Fn(func) => Type::Fn(Box::new(self.sig(func))),
+            // Tuples only appear flattened into enum payloads.
+            Ty::Void | Ty::Never | Ty::Tuple(_) | Ty::Error => return None,
+        })
+    }
+
+    fn sig(&self, func: &FnTy) -> Signature {
+        Signature {
+            params: func.params.iter().filter_map(|ty| self.ty(ty)).collect(),
+            ret: self.ty(&func.ret),
+        }
+    }
+
+    fn fields(&self, defs: &[FieldDef]) -> (Vec<Field>, Vec<Option<u32>>) {
+        let mut fields = Vec::new();
+        let mut indices = Vec::new();
+        for def in defs {
+            match self.ty(&def.ty) {
+                Some(ty) => {
+                    indices.push(Some(fields.len() as u32));
+                    fields.push(Field {
+                        name: def.name.clone(),
+                        ty,
+                    });
+                }
+                None => indices.push(None),
+            }
+        }
+        (fields, indices)
+    }
+
+    /// Where field `index` of struct `id`, or of variant `variant` of enum
+    /// `id`, is in the layout.
+    fn field(&self, id: DeclId, variant: usize, index: usize) -> Option<u32> {
+        self.fields[&id][variant][index]
+    }
+}
+
+/// Locals that need a slot: those referenced with `&` and those assigned
+/// through a field, which both need the address of their storage.
+fn slot_locals(body: &TExpr) -> HashSet<DeclId> {
+    fn root(expr: &TExpr) -> Option<DeclId> {
+        match &expr.kind {
+            TExprKind::Local(id) => Some(*id),
+            TExprKind::Field { base, .. } => root(base),
+            _ => None,
+        }
+    }
+
+    let mut slots = HashSet::new();
+    body.walk(&mut |e| match &e.kind {
+        TExprKind::Unary {
+            op: tast::UnOp::Ref,
+            expr,
+        } => slots.extend(root(expr)),
+        TExprKind::Assign { target, .. } if matches!(target.kind, TExprKind::Field { .. }) => {
+            slots.extend(root(target));
+        }
+        _ => {}
+    });
+    slots
+}
+
+/// Control does not reach the end of the expression being lowered, so
+/// neither does it reach whatever comes after it.
+struct Diverges;
+
+/// The value of a lowered expression, `None` for those without one.
+type Lower<T = Option<Value>> = Result<T, Diverges>;
+
+/// Where an assignment or `&` goes.
+enum Place {
+    Ptr(Value),
+    /// A local kept in SSA values.
+    Local(DeclId),
+    /// Somewhere without a value, such as a `void` global.
+    None,
+}
+
+struct FnLower<'l, 'p> {
+    lowerer: &'l Lowerer<'p>,
+    func: Function,
+    locals: &'p HashMap<DeclId, TLocal>,
+    current: BlockId,
+    preds: Vec<Vec<BlockId>>,
+    /// The latest value of each SSA local, per block.
+    defs: Vec<HashMap<DeclId, Value>>,
+    /// Locals that live in slots, see [`slot_locals`].
+    in_slots: HashSet<DeclId>,
+    slots: HashMap<DeclId, Value>,
+    /// How many slots start the entry block.
+    entry_slots: usize,
+}
+
+impl<'l, 'p> FnLower<'l, 'p> {
+    fn new(
+        lowerer: &'l Lowerer<'p>,
+        name: String,
+        sig: Signature,
+        locals: &'p HashMap<DeclId, TLocal>,
+        body: &TExpr,
+    ) -> Self {
+        let mut f = Self {
+            lowerer,
+            func: Function {
+                name,
+                public: false,
+                sig,
+                blocks: Vec::new(),
+                values: Vec::new(),
+            },
+            locals,
+            current: BlockId(0),
+            preds: Vec::new(),
+            defs: Vec::new(),
+            in_slots: slot_locals(body),
+            slots: HashMap::new(),
+            entry_slots: 0,
+        };
+        f.block();
+        f
+    }
+
+    fn finish(mut self, body: &TExpr) -> Function {
+        if let Ok(value) = self.expr(body) {
+            let value = value.filter(|_| self.func.sig.ret.is_some());
+            self.terminate(Terminator::Return(value));
+        }
+        cfg::compact(&mut self.func);
+        self.func
+    }
+
+    fn local_ty(&self, id: DeclId) -> Option<Type> {
+        self.lowerer.ty(&self.locals[&id].ty)
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.func.blocks.push(Block {
+            params: Vec::new(),
+            insts: Vec::new(),
+            term: Terminator::Unreachable,
+        });
+        self.preds.push(Vec::new());
+        self.defs.push(HashMap::new());
+        BlockId(self.func.blocks.len() as u32 - 1)
+    }
+
+    fn param(&mut self, block: BlockId, ty: Type) -> Value {
+        let value = self.func.value(ty);
+        self.func.blocks[block.0 as usize].params.push(value);
+        value
+    }
+
+    fn emit(&mut self, kind: InstKind, ty: Option<Type>) -> Option<Value> {
+        let result = ty.map(|ty| self.func.value(ty));
+        self.func.blocks[self.current.0 as usize]
+            .insts
+            .push(Inst { result, kind });
+        result
+    }
+
+    fn value(&mut self, kind: InstKind, ty: Type) -> Value {
+        self.emit(kind, Some(ty)).expect("typed instruction")
+    }
+
+    fn int(&mut self, int: IntTy, value: i64) -> Value {
+        self.value(
+            InstKind::Const(Const::Int(wrap(value, int))),
+            Type::Int(int),
+        )
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        for target in term.targets() {
+            let preds = &mut self.preds[target.block.0 as usize];
+            if !preds.contains(&self.current) {
+                preds.push(self.current);
+            }
+        }
+        self.func.blocks[self.current.0 as usize].term = term;
+    }
+
+    fn jump(&mut self, block: BlockId, args: Vec<Value>) {
+        self.terminate(Terminator::Jump(Target { block, args }));
+    }
+
+    /// A new slot holding values of `ty`, at the start of the entry block.
+    fn slot(&mut self, ty: Type) -> Value {
+        let value = self.func.value(Type::Ptr(Box::new(ty)));
+        let inst = Inst {
+            result: Some(value),
+            kind: InstKind::Slot,
+        };
+        self.func.blocks[0].insts.insert(self.entry_slots, inst);
+        self.entry_slots += 1;
+        value
+    }
+
+    /// Gives the new local `id` its first value.
+    fn bind(&mut self, id: DeclId, value: Option<Value>) {
+        let Some(value) = value else {
+            return;
+        };
+        if self.in_slots.contains(&id) {
+            let ty = self.func.ty(value).clone();
+            let ptr = self.slot(ty);
+            self.emit(InstKind::Store { ptr, value }, None);
+            self.slots.insert(id, ptr);
+        } else {
+            self.defs[self.current.0 as usize].insert(id, value);
+        }
+    }
+
+    /// The value of the SSA local `id` at the end of `block`.
+    fn read(&mut self, id: DeclId, block: BlockId) -> Option<Value> {
+        let ty = self.local_ty(id)?;
+        if let Some(value) = self.defs[block.0 as usize].get(&id) {
+            return Some(*value);
+        }
+        let preds = self.preds[block.0 as usize].clone();
+        let values: Vec<_> = preds
+            .iter()
+            .map(|pred| self.read(id, *pred).expect("local defined on every path"))
+            .collect();
+        let value = match values.first() {
+            None => unreachable!("local read before it is defined"),
+            Some(first) if values.iter().all(|v| v == first) => *first,
+            Some(_) => {
+                let param = self.param(block, ty);
+                for (pred, value) in preds.iter().zip(values) {
+                    for target in self.func.blocks[pred.0 as usize].term.targets_mut() {
+                        if target.block == block {
+                            target.args.push(value);
+                        }
+                    }
+                }
+                param
+            }
+        };
+        self.defs[block.0 as usize].insert(id, value);
+        Some(value)
+    }
+
+    fn global(&mut self, id: DeclId, span: Span) -> Option<Value> {
+        let global = self.lowerer.globals[&id];
+        let ty = self.lowerer.ty(&self.lowerer.program.global(id)?.ty);
+        self.emit(
+            InstKind::Global { id: global, span },
+            ty.map(|ty| Type::Ptr(Box::new(ty))),
+        )
+    }
+
+    fn load(&mut self, ptr: Value) -> Value {
+        let Type::Ptr(ty) = self.func.ty(ptr).clone() else {
+            unreachable!("load from a value that is not a pointer");
+        };
+        self.value(InstKind::Load(ptr), *ty)
+    }
+
+    /// Lowers an expression that has a value.
+    fn operand(&mut self, expr: &TExpr) -> Lower<Value> {
+        Ok(self.expr(expr)?.expect("operand has a value"))
+    }
+
+    fn expr(&mut self, expr: &TExpr) -> Lower {
+        let ty = self.lowerer.ty(&expr.ty);
+        Ok(match &expr.kind {
+            TExprKind::Int(value) => {
+                let Some(Type::Int(int)) = ty else {
+                    unreachable!("integer literal of type {:?}", expr.ty);
+                };
+                Some(self.int(int, *value as i64))
+            }
+            TExprKind::Float(value) => {
+                let Some(Type::Float(float)) = ty else {
+                    unreachable!("float literal of type {:?}", expr.ty);
+                };
+                let value = Const::Float(round(*value, float));
+                Some(self.value(InstKind::Const(value), Type::Float(float)))
+            }
+            TExprKind::Bool(value) => {
+                Some(self.value(InstKind::Const(Const::Bool(*value)), Type::Bool))
+            }
+            TExprKind::Str(value) => {
+                Some(self.value(InstKind::Const(Const::Str(value.clone())), Type::Str))
+            }
+            TExprKind::Local(id) => match self.slots.get(id) {
+                Some(ptr) => Some(self.load(*ptr)),
+                None => self.read(*id, self.current),
+            },
+            TExprKind::Global(id) => self.global(*id, expr.span).map(|ptr| self.load(ptr)),
+            TExprKind::Function(id) => self.emit(InstKind::Func(FuncId(id.0)), ty),
+            TExprKind::Builtin(_) => unreachable!("builtins are only called"),
+            TExprKind::Unary { op, expr: inner } => match op {
+                tast::UnOp::Neg | tast::UnOp::Not => {
+                    let arg = self.operand(inner)?;
+                    let op = match op {
+                        tast::UnOp::Neg => UnOp::Neg,
+                        _ => UnOp::Not,
+                    };
+                    self.emit(InstKind::Unary { op, arg }, ty)
+                }
+                tast::UnOp::Ref => match self.place(inner)? {
+                    Place::Ptr(ptr) => Some(ptr),
+                    Place::Local(_) => unreachable!("referenced local without a slot"),
+                    Place::None => None,
+                },
+                tast::UnOp::Deref => {
+                    let ptr = self.expr(inner)?;
+                    ptr.filter(|_| ty.is_some()).map(|ptr| self.load(ptr))
+                }
+            },
+            TExprKind::Binary { op, lhs, rhs } => self.binary(*op, lhs, rhs, expr)?,
+            TExprKind::Assign { op, target, value } => {
+                let place = self.place(target)?;
+                let Some(value) = self.expr(value)? else {
+                    return Ok(None);
+                };
+                let value = match op {
+                    Some(op) => {
+                        let old = self.load_place(&place).expect("assigned place has a value");
+                        let op = arith(*op);
+                        let kind = InstKind::Binary {
+                            op,
+                            lhs: old,
+                            rhs: value,
+                            span: expr.span,
+                        };
+                        let ty = self.func.ty(old).clone();
+                        self.value(kind, ty)
+                    }
+                    None => value,
+                };
+                match place {
+                    Place::Ptr(ptr) => {
+                        self.emit(InstKind::Store { ptr, value }, None);
+                    }
+                    Place::Local(id) => {
+                        self.defs[self.current.0 as usize].insert(id, value);
+                    }
+                    Place::None => {}
+                }
+                None
+            }
+            TExprKind::Call {
+                callee, args, tail, ..
+            } => {
+                return self.call(callee, args, *tail, expr);
+            }
+            TExprKind::Field { base, index } => {
+                let base_value = self.expr(base)?;
+                let Ty::Struct(id) = base.ty else {
+                    unreachable!("field of {:?}", base.ty);
+                };
+                match (base_value, self.lowerer.field(id, 0, *index), ty) {
+                    (Some(base), Some(index), Some(ty)) => {
+                        Some(self.value(InstKind::Field { base, index }, ty))
+                    }
+                    _ => None,
+                }
+            }
+            TExprKind::Struct { fields, .. } => {
+                let fields = self.operands(fields)?;
+                self.emit(InstKind::Struct(fields), ty)
+            }
+            TExprKind::Variant { index, fields, .. } => {
+                let fields = self.operands(fields)?;
+                let kind = InstKind::Variant {
+                    index: *index as u32,
+                    fields,
+                };
+                self.emit(kind, ty)
+            }
+            TExprKind::Block(block) => {
+                for stmt in &block.stmts {
+                    match stmt {
+                        TStmt::Let { id, value } => {
+                            let value = self.expr(value)?;
+                            self.bind(*id, value);
+                        }
+                        TStmt::Expr(value) => {
+                            self.expr(value)?;
+                        }
+                    }
+                }
+                match &block.tail {
+                    Some(tail) => self.expr(tail)?,
+                    None => None,
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => self.switch(scrutinee, arms, expr)?,
+            TExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value)?,
+                    None => None,
+                };
+                let value = value.filter(|_| self.func.sig.ret.is_some());
+                self.terminate(Terminator::Return(value));
+                return Err(Diverges);
+            }
+            TExprKind::Error => unreachable!("programs with errors are not lowered"),
+        })
+    }
+
+    /// Lowers expressions left to right, keeping the values of those that
+    /// have one.
+    fn operands(&mut self, exprs: &[TExpr]) -> Lower<Vec<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            values.extend(self.expr(expr)?);
+        }
+        Ok(values)
+    }
+
+    /// Evaluates `expr` as a location. Expressions that are not places are
+    /// stored in a new slot, which is what `&f(x)` refers to.
+    fn place(&mut self, expr: &TExpr) -> Lower<Place> {
+        if self.lowerer.ty(&expr.ty).is_none() {
+            self.expr(expr)?;
+            return Ok(Place::None);
+        }
+        Ok(match &expr.kind {
+            TExprKind::Local(id) => match self.slots.get(id) {
+                Some(ptr) => Place::Ptr(*ptr),
+                None => Place::Local(*id),
+            },
+            TExprKind::Global(id) => match self.global(*id, expr.span) {
+                Some(ptr) => Place::Ptr(ptr),
+                None => Place::None,
+            },
+            TExprKind::Field { base, index } => {
+                let Ty::Struct(id) = base.ty else {
+                    unreachable!("field of {:?}", base.ty);
+                };
+                let ptr = match self.place(base)? {
+                    Place::Ptr(ptr) => ptr,
+                    Place::Local(_) => unreachable!("field of a local without a slot"),
+                    Place::None => return Ok(Place::None),
+                };
+                let Some(index) = self.lowerer.field(id, 0, *index) else {
+                    return Ok(Place::None);
+                };
+                let ty = self.lowerer.ty(&expr.ty).expect("field has a value");
+                let kind = InstKind::FieldPtr { ptr, index };
+                Place::Ptr(self.value(kind, Type::Ptr(Box::new(ty))))
+            }
+            TExprKind::Unary {
+                op: tast::UnOp::Deref,
+                expr: inner,
+            } => Place::Ptr(self.operand(inner)?),
+            _ => {
+                let value = self.operand(expr)?;
+                let ty = self.func.ty(value).clone();
+                let ptr = self.slot(ty);
+                self.emit(InstKind::Store { ptr, value }, None);
+                Place::Ptr(ptr)
+            }
+        })
+    }
+
+    fn load_place(&mut self, place: &Place) -> Option<Value> {
+        match place {
+            Place::Ptr(ptr) => Some(self.load(*ptr)),
+            Place::Local(id) => self.read(*id, self.current),
+            Place::None => None,
+        }
+    }
+
+    fn binary(&mut self, op: tast::BinOp, lhs: &TExpr, rhs: &TExpr, expr: &TExpr) -> Lower {
+        if let tast::BinOp::And | tast::BinOp::Or = op {
+            // `&&` and `||` short-circuit: the right side only runs when the
+            // left does not decide the result.
+            let lhs = self.operand(lhs)?;
+            let short = op == tast::BinOp::Or;
+            let short = self.value(InstKind::Const(Const::Bool(short)), Type::Bool);
+            let right = self.block();
+            let join = self.block();
+            let result = self.param(join, Type::Bool);
+            let (then, otherwise) = if op == tast::BinOp::And {
+                (right, join)
+            } else {
+                (join, right)
+            };
+            let args = |block| {
+                if block == join {
+                    vec![short]
+                } else {
+                    Vec::new()
+                }
+            };
+            self.terminate(Terminator::Branch {
+                cond: lhs,
+                then: Target {
+                    block: then,
+                    args: args(then),
+                },
+                otherwise: Target {
+                    block: otherwise,
+                    args: args(otherwise),
+                },
+            });
+            self.current = right;
+            if let Ok(rhs) = self.operand(rhs) {
+                self.jump(join, vec![rhs]);
+            }
+            self.current = join;
+            return Ok(Some(result));
+        }
+
+        let lhs = self.operand(lhs)?;
+        let rhs = self.operand(rhs)?;
+        let kind = InstKind::Binary {
+            op: arith(op),
+            lhs,
+            rhs,
+            span: expr.span,
+        };
+        Ok(self.emit(kind, self.lowerer.ty(&expr.ty)))
+    }
+
+    fn call(&mut self, callee: &TExpr, args: &[TExpr], tail: bool, expr: &TExpr) -> Lower {
+        if let TExprKind::Builtin(Builtin::Printf) = callee.kind {
+            let (format, args) = args.split_first().expect("printf without a format string");
+            let TExprKind::Str(format) = &format.kind else {
+                unreachable!("format string is not a literal");
+            };
+            let args = self.operands(args)?;
+            let format = format.clone();
+            self.emit(InstKind::Printf { format, args }, None);
+            return Ok(None);
+        }
+
+        let callee = match callee.kind {
+            TExprKind::Function(id) => Callee::Direct(FuncId(id.0)),
+            _ => Callee::Indirect(self.operand(callee)?),
+        };
+        let args = self.operands(args)?;
+        if tail {
+            self.terminate(Terminator::TailCall { callee, args });
+            return Err(Diverges);
+        }
+        let kind = InstKind::Call {
+            callee,
+            args,
+            span: expr.span,
+        };
+        let result = self.emit(kind, self.lowerer.ty(&expr.ty));
+        if expr.ty == Ty::Never {
+            self.terminate(Terminator::Unreachable);
+            return Err(Diverges);
+        }
+        Ok(result)
+    }
+
+    fn switch(&mut self, scrutinee: &TExpr, arms: &[TArm], expr: &TExpr) -> Lower {
+        let value = self.operand(scrutinee)?;
+        let join = self.block();
+        let result = self.lowerer.ty(&expr.ty).map(|ty| self.param(join, ty));
+
+        // Only the first arm that can match a value is ever taken.
+        let mut live = Vec::new();
+        let mut seen = Vec::new();
+        for arm in arms {
+            let key = match &arm.pattern {
+                TPattern::Int(v) => Some(Const::Int(*v as i64)),
+                TPattern::Bool(v) => Some(Const::Bool(*v)),
+                TPattern::Str(v) => Some(Const::Str(v.clone())),
+                TPattern::Variant { index, .. } => Some(Const::Int(*index as i64)),
+                TPattern::Wildcard => None,
+            };
+            let Some(key) = key else {
+                live.push(arm);
+                break;
+            };
+            if !seen.contains(&key) {
+                seen.push(key);
+                live.push(arm);
+            }
+        }
+        let blocks: Vec<_> = live.iter().map(|_| self.block()).collect();
+        let wildcard = live
+            .iter()
+            .zip(&blocks)
+            .find(|(arm, _)| matches!(arm.pattern, TPattern::Wildcard))
+            .map(|(_, block)| *block);
+        let mut trap = None;
+        let mut fallback = |f: &mut Self| {
+            wildcard.unwrap_or_else(|| {
+                *trap.get_or_insert_with(|| {
+                    let block = f.block();
+                    f.func.blocks[block.0 as usize].term = Terminator::Trap {
+                        message: "no switch arm matched".to_string(),
+                        span: expr.span,
+                    };
+                    block
+                })
+            })
+        };
+        let to = |block| Target {
+            block,
+            args: Vec::new(),
+        };
+
+        match &scrutinee.ty {
+            Ty::Bool => {
+                let find = |want: bool| {
+                    live.iter()
+                        .zip(&blocks)
+                        .find_map(|(arm, block)| match arm.pattern {
+                            TPattern::Bool(v) if v != want => None,
+                            _ => Some(*block),
+                        })
+                };
+                let then = find(true).unwrap_or_else(|| fallback(self));
+                let otherwise = find(false).unwrap_or_else(|| fallback(self));
+                if then == otherwise {
+                    self.jump(then, Vec::new());
+                } else {
+                    self.terminate(Terminator::Branch {
+                        cond: value,
+                        then: to(then),
+                        otherwise: to(otherwise),
+                    });
+                }
+            }
+            Ty::Str => {
+                // Compares with each string in turn. The last comparison
+                // goes on to the wildcard arm, or to the trap.
+                let tests: Vec<_> = live
+                    .iter()
+                    .zip(&blocks)
+                    .filter_map(|(arm, block)| match &arm.pattern {
+                        TPattern::Str(text) => Some((text, *block)),
+                        _ => None,
+                    })
+                    .collect();
+                if tests.is_empty() {
+                    let block = fallback(self);
+                    self.jump(block, Vec::new());
+                }
+                let count = tests.len();
+                for (i, (text, block)) in tests.into_iter().enumerate() {
+                    let text = self.value(InstKind::Const(Const::Str(text.clone())), Type::Str);
+                    let kind = InstKind::Binary {
+                        op: BinOp::Eq,
+                        lhs: value,
+                        rhs: text,
+                        span: expr.span,
+                    };
+                    let cond = self.value(kind, Type::Bool);
+                    let next = match i + 1 < count {
+                        true => self.block(),
+                        false => fallback(self),
+                    };
+                    self.terminate(Terminator::Branch {
+                        cond,
+                        then: to(block),
+                        otherwise: to(next),
+                    });
+                    self.current = next;
+                }
+            }
+            _ => {
+                let (on, int) = match self.func.ty(value) {
+                    Type::Int(int) => (value, Some(*int)),
+                    _ => (
+                        self.value(InstKind::Tag(value), Type::Int(IntTy::U32)),
+                        None,
+                    ),
+                };
+                let mut cases = Vec::new();
+                for (arm, block) in live.iter().zip(&blocks) {
+                    let case = match &arm.pattern {
+                        TPattern::Int(v) => wrap(*v as i64, int.unwrap_or(IntTy::I64)),
+                        TPattern::Variant { index, .. } => *index as i64,
+                        _ => continue,
+                    };
+                    cases.push((case, to(*block)));
+                }
+                let default = to(fallback(self));
+                self.terminate(Terminator::Switch {
+                    value: on,
+                    cases,
+                    default,
+                });
+            }
+        }
+
+        let mut reached = false;
+        for (arm, block) in live.iter().zip(blocks) {
+            self.current = block;
+            if let TPattern::Variant { index, bindings } = &arm.pattern {
+                let Ty::Enum(id) = scrutinee.ty else {
+                    unreachable!("variant pattern on {:?}", scrutinee.ty);
+                };
+                for (local, field) in bindings {
+                    let payload = match self.lowerer.field(id, *index, *field) {
+                        Some(field) => {
+                            let ty = self.local_ty(*local).expect("payload field has a value");
+                            let kind = InstKind::Payload {
+                                base: value,
+                                variant: *index as u32,
+                                field,
+                            };
+                            Some(self.value(kind, ty))
+                        }
+                        None => None,
+                    };
+                    self.bind(*local, payload);
+                }
+            }
+            if let Ok(body) = self.expr(&arm.body) {
+                let args = match result {
+                    Some(_) => vec![body.expect("arm has a value")],
+                    None => Vec::new(),
+                };
+                self.jump(join, args);
+                reached = true;
+            }
+        }
+
+        if !reached {
+            return Err(Diverges);
+        }
+        self.current = join;
+        Ok(result)
+    }
+}
+
+fn arith(op: tast::BinOp) -> BinOp {
+    match op {
+        tast::BinOp::Add => BinOp::Add,
+        tast::BinOp::Sub => BinOp::Sub,
+        tast::BinOp::Mul => BinOp::Mul,
+        tast::BinOp::Div => BinOp::Div,
+        tast::BinOp::Eq => BinOp::Eq,
+        tast::BinOp::Ne => BinOp::Ne,
+        tast::BinOp::Lt => BinOp::Lt,
+        tast::BinOp::Le => BinOp::Le,
+        tast::BinOp::Gt => BinOp::Gt,
+        tast::BinOp::Ge => BinOp::Ge,
+        tast::BinOp::And | tast::BinOp::Or => unreachable!("`{}` short-circuits", op.as_str()),
+    }
+}
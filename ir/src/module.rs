@@ -0,0 +1,505 @@
+//! The data structures of the IR.
+
+use std::cmp::Ordering;
+
+use lexer::token::Span;
+use sema::tast;
+use sema::types::{FloatTy, IntTy};
+
+/// Index of a [`Function`] in [`Module::functions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FuncId(pub u32);
+
+/// Index of a [`StructLayout`] in [`Module::structs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StructId(pub u32);
+
+/// Index of an [`EnumLayout`] in [`Module::enums`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EnumId(pub u32);
+
+/// Index of a [`Global`] in [`Module::globals`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GlobalId(pub u32);
+
+/// An SSA value of one function: a block parameter or the result of an
+/// instruction, defined exactly once. Its type is in [`Function::values`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Value(pub u32);
+
+/// Index of a [`Block`] in [`Function::blocks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub u32);
+
+/// The type of a value. Nothing of type `void` or `never` exists at run
+/// time, so those have no `Type`: they are left out of parameters, fields
+/// and results, and a function that returns neither has no return type.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int(IntTy),
+    Float(FloatTy),
+    Bool,
+    Str,
+    Struct(StructId),
+    Enum(EnumId),
+    /// A reference to a slot, a global, or a field inside one.
+    Ptr(Box<Type>),
+    Fn(Box<Signature>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLayout {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantLayout {
+    pub name: String,
+    /// The payload, flattened like [`sema::types::VariantDef::fields`].
+    pub fields: Vec<Field>,
+}
+
+/// A tagged union: the tag is the index of the variant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumLayout {
+    pub name: String,
+    pub variants: Vec<VariantLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    /// `None` for globals without a value, which are only initialized for
+    /// the effects of `init`.
+    pub ty: Option<Type>,
+    /// The function without parameters that computes the initial value.
+    /// Backends may call it on first use or ahead of time, in the order
+    /// the globals are listed.
+    pub init: FuncId,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    /// The source file the module was lowered from.
+    pub source: String,
+    pub structs: Vec<StructLayout>,
+    pub enums: Vec<EnumLayout>,
+    /// Every global, each after the globals its initializer refers to.
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+    pub main: Option<FuncId>,
+}
+
+impl Module {
+    pub fn function(&self, id: FuncId) -> &Function {
+        &self.functions[id.0 as usize]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// Unique in the module; methods are qualified as `Type.method`.
+    pub name: String,
+    /// Declared `pub` in the source, so kept even if nothing calls it.
+    pub public: bool,
+    pub sig: Signature,
+    /// The first block is the entry, whose parameters are the function's.
+    pub blocks: Vec<Block>,
+    /// The type of each value, by index.
+    pub values: Vec<Type>,
+}
+
+impl Function {
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn ty(&self, value: Value) -> &Type {
+        &self.values[value.0 as usize]
+    }
+
+    /// A new value of type `ty`, to be defined by the caller.
+    pub fn value(&mut self, ty: Type) -> Value {
+        self.values.push(ty);
+        Value(self.values.len() as u32 - 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    /// Values passed in by every jump here, instead of phi nodes.
+    pub params: Vec<Value>,
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    /// `None` for instructions that produce nothing, such as `store` and
+    /// calls to functions without a return type.
+    pub result: Option<Value>,
+    pub kind: InstKind,
+}
+
+/// An integer constant, stored like the interpreter stores integers:
+/// sign-extended for signed types and zero-extended for unsigned ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Int(i64),
+    /// Already rounded to the precision of its type.
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+/// `value` truncated to `int` and extended back to 64 bits, which is how
+/// [`Const::Int`] and every integer at run time is stored.
+pub fn wrap(value: i64, int: IntTy) -> i64 {
+    let unused = 64 - int.bits();
+    if unused == 0 {
+        value
+    } else if int.is_signed() {
+        (value << unused) >> unused
+    } else {
+        ((value as u64) << unused >> unused) as i64
+    }
+}
+
+/// `value` rounded to the precision of `float`. There is no stable `f16`;
+/// `f32` is the closest we can round to.
+pub fn round(value: f64, float: FloatTy) -> f64 {
+    match float {
+        FloatTy::F64 => value,
+        _ => value as f32 as f64,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+/// Arithmetic wraps around, except that integer `Div` by zero is a runtime
+/// error. Comparisons produce a `bool`; strings compare by content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        )
+    }
+
+    /// Whether the comparison holds of operands ordered by `ordering`,
+    /// which is `None` if they are unordered, as in the source language.
+    pub fn compare(self, ordering: Option<Ordering>) -> bool {
+        let op = match self {
+            BinOp::Eq => tast::BinOp::Eq,
+            BinOp::Ne => tast::BinOp::Ne,
+            BinOp::Lt => tast::BinOp::Lt,
+            BinOp::Le => tast::BinOp::Le,
+            BinOp::Gt => tast::BinOp::Gt,
+            BinOp::Ge => tast::BinOp::Ge,
+            _ => unreachable!("{self:?} is not a comparison"),
+        };
+        op.compare(ordering)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "ne",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Direct(FuncId),
+    /// A value of `Fn` type.
+    Indirect(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstKind {
+    Const(Const),
+    Unary {
+        op: UnOp,
+        arg: Value,
+    },
+    /// `span` locates the runtime error of a division by zero.
+    Binary {
+        op: BinOp,
+        lhs: Value,
+        rhs: Value,
+        span: Span,
+    },
+    /// The function as a value.
+    Func(FuncId),
+    /// `span` locates the runtime error of a stack overflow.
+    Call {
+        callee: Callee,
+        args: Vec<Value>,
+        span: Span,
+    },
+    /// Prints `format`, a checked `sema::format` string, with `args` for
+    /// its placeholders.
+    Printf {
+        format: String,
+        args: Vec<Value>,
+    },
+    /// A struct of the result type with these fields.
+    Struct(Vec<Value>),
+    /// Variant `index` of the result type with this payload.
+    Variant {
+        index: u32,
+        fields: Vec<Value>,
+    },
+    /// Field `index` of a struct value.
+    Field {
+        base: Value,
+        index: u32,
+    },
+    /// The index of the variant an enum value holds, as a `u32`.
+    Tag(Value),
+    /// Payload field `field` of an enum value holding variant `variant`.
+    Payload {
+        base: Value,
+        variant: u32,
+        field: u32,
+    },
+    /// New storage for a value of the pointee of the result type, which
+    /// lives as long as the function's frame. Always in the entry block.
+    Slot,
+    Load(Value),
+    Store {
+        ptr: Value,
+        value: Value,
+    },
+    /// A pointer to field `index` of the struct `ptr` points to.
+    FieldPtr {
+        ptr: Value,
+        index: u32,
+    },
+    /// A pointer to the global. `span` locates the runtime error of a
+    /// global read while it is being initialized.
+    Global {
+        id: GlobalId,
+        span: Span,
+    },
+}
+
+impl InstKind {
+    /// Whether the instruction can be removed when its result is unused:
+    /// it has no effect and cannot fail.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            InstKind::Binary { op, .. } => *op != BinOp::Div,
+            InstKind::Call { .. }
+            | InstKind::Printf { .. }
+            | InstKind::Store { .. }
+            | InstKind::Global { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// Every value the instruction reads, in order.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            InstKind::Const(_) | InstKind::Func(_) | InstKind::Slot | InstKind::Global { .. } => {
+                Vec::new()
+            }
+            InstKind::Unary { arg, .. } => vec![*arg],
+            InstKind::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            InstKind::Call { callee, args, .. } => {
+                let mut operands = Vec::new();
+                if let Callee::Indirect(value) = callee {
+                    operands.push(*value);
+                }
+                operands.extend(args);
+                operands
+            }
+            InstKind::Printf { args, .. }
+            | InstKind::Struct(args)
+            | InstKind::Variant { fields: args, .. } => args.clone(),
+            InstKind::Field { base, .. }
+            | InstKind::Tag(base)
+            | InstKind::Payload { base, .. }
+            | InstKind::Load(base)
+            | InstKind::FieldPtr { ptr: base, .. } => vec![*base],
+            InstKind::Store { ptr, value } => vec![*ptr, *value],
+        }
+    }
+
+    /// Calls `f` on every value the instruction reads, to rewrite them.
+    pub fn operands_mut(&mut self, f: &mut impl FnMut(&mut Value)) {
+        match self {
+            InstKind::Const(_) | InstKind::Func(_) | InstKind::Slot | InstKind::Global { .. } => {}
+            InstKind::Unary { arg, .. } => f(arg),
+            InstKind::Binary { lhs, rhs, .. } => {
+                f(lhs);
+                f(rhs);
+            }
+            InstKind::Call { callee, args, .. } => {
+                if let Callee::Indirect(value) = callee {
+                    f(value);
+                }
+                args.iter_mut().for_each(f);
+            }
+            InstKind::Printf { args, .. }
+            | InstKind::Struct(args)
+            | InstKind::Variant { fields: args, .. } => args.iter_mut().for_each(f),
+            InstKind::Field { base, .. }
+            | InstKind::Tag(base)
+            | InstKind::Payload { base, .. }
+            | InstKind::Load(base)
+            | InstKind::FieldPtr { ptr: base, .. } => f(base),
+            InstKind::Store { ptr, value } => {
+                f(ptr);
+                f(value);
+            }
+        }
+    }
+}
+
+/// A jump to `block`, passing `args` for its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub block: BlockId,
+    pub args: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(Target),
+    Branch {
+        cond: Value,
+        then: Target,
+        otherwise: Target,
+    },
+    /// Jumps to the case whose integer equals `value`, or to `default`.
+    Switch {
+        value: Value,
+        cases: Vec<(i64, Target)>,
+        default: Target,
+    },
+    Return(Option<Value>),
+    /// Calls and returns what the callee returns, replacing the caller's
+    /// frame: a chain of tail calls runs in constant stack space.
+    TailCall {
+        callee: Callee,
+        args: Vec<Value>,
+    },
+    /// Stops the program with the runtime error `message`.
+    Trap {
+        message: String,
+        span: Span,
+    },
+    /// Control never gets here.
+    Unreachable,
+}
+
+impl Terminator {
+    /// The blocks control can go to next.
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Switch { cases, default, .. } => {
+                cases.iter().map(|(_, t)| t).chain([default]).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            Terminator::Switch { cases, default, .. } => {
+                cases.iter_mut().map(|(_, t)| t).chain([default]).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every value the terminator reads, block arguments included.
+    pub fn operands(&self) -> Vec<Value> {
+        let mut operands = match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Switch { value, .. } => vec![*value],
+            Terminator::Return(value) => value.iter().copied().collect(),
+            Terminator::TailCall { callee, args } => {
+                let mut operands = Vec::new();
+                if let Callee::Indirect(value) = callee {
+                    operands.push(*value);
+                }
+                operands.extend(args);
+                operands
+            }
+            _ => Vec::new(),
+        };
+        for target in self.targets() {
+            operands.extend(&target.args);
+        }
+        operands
+    }
+
+    /// Calls `f` on every value the terminator reads, to rewrite them.
+    pub fn operands_mut(&mut self, f: &mut impl FnMut(&mut Value)) {
+        match self {
+            Terminator::Branch { cond: value, .. }
+            | Terminator::Switch { value, .. }
+            | Terminator::Return(Some(value)) => f(value),
+            Terminator::TailCall { callee, args } => {
+                if let Callee::Indirect(value) = callee {
+                    f(value);
+                }
+                args.iter_mut().for_each(&mut *f);
+            }
+            _ => {}
+        }
+        for target in self.targets_mut() {
+            target.args.iter_mut().for_each(&mut *f);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! The optimization passes and the order they run in at each level.
+//!
+//! Every pass takes a well-formed module to a well-formed module with the
+//! same behavior, runtime errors included, except that a program may use
+//! fewer stack frames: inlined calls and loops made of tail recursion no
+//! longer count towards a stack overflow.
+
+use crate::module::Module;
+use crate::print::print;
+use crate::{dce, fold, inline, tailrec};
+
+/// How hard to optimize, as selected by `-O0`, `-O1` and `-O2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// The module as lowered.
+    #[default]
+    O0,
+    /// Cheap passes that only make the module smaller.
+    O1,
+    /// Also inlines small functions, and cleans up after that.
+    O2,
+}
+
+/// A transformation of a whole module.
+pub struct Pass {
+    /// How the pass is shown in IR dumps.
+    pub name: &'static str,
+    /// Runs the pass and says whether it changed anything.
+    pub run: fn(&mut Module) -> bool,
+}
+
+/// Folds instructions and branches whose operands are constants, and
+/// forwards values through block parameters, fields and payloads.
+pub const FOLD: Pass = Pass {
+    name: "fold",
+    run: fold::fold,
+};
+
+/// Removes unused instructions and block parameters, blocks that cannot
+/// be reached, and functions that are neither `pub` nor used.
+pub const DCE: Pass = Pass {
+    name: "dce",
+    run: dce::dce,
+};
+
+/// Replaces calls to small functions with their bodies.
+pub const INLINE: Pass = Pass {
+    name: "inline",
+    run: inline::inline,
+};
+
+/// Turns tail calls of functions to themselves into loops.
+pub const TAILREC: Pass = Pass {
+    name: "tailrec",
+    run: tailrec::tailrec,
+};
+
+impl OptLevel {
+    /// The passes run at this level, in order.
+    pub fn passes(self) -> &'static [Pass] {
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 => &[FOLD, DCE, TAILREC],
+            OptLevel::O2 => &[FOLD, DCE, TAILREC, INLINE, FOLD, DCE],
+        }
+    }
+}
+
+/// Runs the passes of `level` on `module`.
+pub fn optimize(module: &mut Module, level: OptLevel) {
+    optimize_with(module, level, |_, _| {});
+}
+
+/// Like [`optimize`], calling `changed` after each pass that changed the
+/// module, for example to dump it.
+pub fn optimize_with(
+    module: &mut Module,
+    level: OptLevel,
+    mut changed: impl FnMut(&Pass, &Module),
+) {
+    for pass in level.passes() {
+        if (pass.run)(module) {
+            changed(pass, module);
+        }
+    }
+}
+
+/// Optimizes `module` at `level` and returns it printed before the first
+/// pass and after each pass that changed it, each under a `;` comment
+/// naming the pass.
+pub fn dump(module: &mut Module, level: OptLevel) -> String {
+    let mut out = format!("; before optimization\n{}", print(module));
+    optimize_with(module, level, |pass, module| {
+        out.push_str(&format!("\n; after {}\n{}", pass.name, print(module)));
+    });
+    out
+}
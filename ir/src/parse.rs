@@ -0,0 +1,912 @@
+//! Reads the textual form written by [`crate::print`].
+//!
+//! Spans come back with only their line and column, which is all the text
+//! keeps of them.
+
+use std::collections::HashMap;
+
+use lexer::token::Span;
+use sema::types::{IntTy, Ty};
+
+use crate::module::*;
+
+/// Parses a module, or says on which line and why it cannot.
+pub fn parse(text: &str) -> Result<Module, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        structs: HashMap::new(),
+        enums: HashMap::new(),
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+    };
+    parser.declare()?;
+    parser.module()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    /// `@name`
+    Name(String),
+    /// `%n`
+    Value(u32),
+    /// An integer or float, as written.
+    Num(String),
+    Str(String),
+    Punct(&'static str),
+    Eof,
+}
+
+impl Tok {
+    fn describe(&self) -> String {
+        match self {
+            Tok::Ident(name) => format!("`{name}`"),
+            Tok::Name(name) => format!("`@{name}`"),
+            Tok::Value(n) => format!("`%{n}`"),
+            Tok::Num(n) => format!("`{n}`"),
+            Tok::Str(_) => "a string".to_string(),
+            Tok::Punct(p) => format!("`{p}`"),
+            Tok::Eof => "the end of the text".to_string(),
+        }
+    }
+}
+
+/// Characters that end a name or an identifier.
+fn ends_name(c: char) -> bool {
+    c.is_whitespace() || "(){},:=;&\"%@".contains(c)
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Tok, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        let take_while = |chars: &mut std::iter::Peekable<std::str::Chars>,
+                          f: &dyn Fn(char) -> bool| {
+            let mut s = String::new();
+            while let Some(&c) = chars.peek() {
+                if !f(c) {
+                    break;
+                }
+                s.push(c);
+                chars.next();
+            }
+            s
+        };
+        let tok = match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+                continue;
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            ';' => {
+                take_while(&mut chars, &|c| c != '\n');
+                continue;
+            }
+            '@' => {
+                chars.next();
+                let name = take_while(&mut chars, &|c| !ends_name(c));
+                if name.is_empty() {
+                    return Err(format!("line {line}: expected a name after `@`"));
+                }
+                Tok::Name(name)
+            }
+            '%' => {
+                chars.next();
+                let digits = take_while(&mut chars, &|c| c.is_ascii_digit());
+                let n = digits
+                    .parse()
+                    .map_err(|_| format!("line {line}: expected a value number after `%`"))?;
+                Tok::Value(n)
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None | Some('\n') => {
+                            return Err(format!("line {line}: unterminated string"));
+                        }
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('"') => s.push('"'),
+                            Some('\\') => s.push('\\'),
+                            Some('u') => {
+                                let code = take_while(&mut chars, &|c| c != '}');
+                                chars.next();
+                                let c = code
+                                    .strip_prefix('{')
+                                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| format!("line {line}: invalid `\\u` escape"))?;
+                                s.push(c);
+                            }
+                            _ => return Err(format!("line {line}: invalid escape")),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                Tok::Str(s)
+            }
+            '-' => {
+                chars.next();
+                if chars.peek() == Some(&'>') {
+                    chars.next();
+                    Tok::Punct("->")
+                } else {
+                    let rest = take_while(&mut chars, &|c| !ends_name(c));
+                    Tok::Num(format!("-{rest}"))
+                }
+            }
+            c if c.is_ascii_digit() => Tok::Num(take_while(&mut chars, &|c| !ends_name(c))),
+            '(' | ')' | '{' | '}' | ',' | ':' | '=' | '&' => {
+                chars.next();
+                Tok::Punct(match c {
+                    '(' => "(",
+                    ')' => ")",
+                    '{' => "{",
+                    '}' => "}",
+                    ',' => ",",
+                    ':' => ":",
+                    '=' => "=",
+                    _ => "&",
+                })
+            }
+            _ => {
+                let ident = take_while(&mut chars, &|c| !ends_name(c));
+                if ident.is_empty() {
+                    return Err(format!("line {line}: unexpected `{c}`"));
+                }
+                Tok::Ident(ident)
+            }
+        };
+        tokens.push((tok, line));
+    }
+    tokens.push((Tok::Eof, line));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Tok, usize)>,
+    pos: usize,
+    structs: HashMap<String, StructId>,
+    enums: HashMap<String, EnumId>,
+    functions: HashMap<String, FuncId>,
+    globals: HashMap<String, GlobalId>,
+}
+
+type Parse<T> = Result<T, String>;
+
+impl Parser {
+    /// The token at `pos`; past the end, the final `Eof`.
+    fn at(&self, pos: usize) -> &(Tok, usize) {
+        &self.tokens[pos.min(self.tokens.len() - 1)]
+    }
+
+    fn peek(&self) -> &Tok {
+        &self.at(self.pos).0
+    }
+
+    fn next(&mut self) -> Tok {
+        self.pos += 1;
+        self.at(self.pos - 1).0.clone()
+    }
+
+    fn error<T>(&self, expected: &str) -> Parse<T> {
+        let (tok, line) = self.at(self.pos);
+        Err(format!(
+            "line {line}: expected {expected}, found {}",
+            tok.describe()
+        ))
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Tok::Punct(p) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Parse<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            self.error(&format!("`{punct}`"))
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(k) if k == keyword)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Parse<()> {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("`{keyword}`"))
+        }
+    }
+
+    fn ident(&mut self) -> Parse<String> {
+        match self.next() {
+            Tok::Ident(name) | Tok::Num(name) => Ok(name),
+            _ => {
+                self.pos -= 1;
+                self.error("a name")
+            }
+        }
+    }
+
+    fn name(&mut self) -> Parse<String> {
+        match self.next() {
+            Tok::Name(name) => Ok(name),
+            _ => {
+                self.pos -= 1;
+                self.error("an `@` name")
+            }
+        }
+    }
+
+    fn value(&mut self) -> Parse<Value> {
+        match self.next() {
+            Tok::Value(n) => Ok(Value(n)),
+            _ => {
+                self.pos -= 1;
+                self.error("a value")
+            }
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Parse<T> {
+        match self.next() {
+            Tok::Num(n) => match n.parse() {
+                Ok(n) => Ok(n),
+                Err(_) => {
+                    self.pos -= 1;
+                    self.error("a number in range")
+                }
+            },
+            _ => {
+                self.pos -= 1;
+                self.error("a number")
+            }
+        }
+    }
+
+    /// Gives every type, global and function its id up front, since they
+    /// can be used before they are defined.
+    fn declare(&mut self) -> Parse<()> {
+        let mut depth = 0;
+        let (mut structs, mut enums, mut functions, mut globals) = (0, 0, 0, 0);
+        for i in 0..self.tokens.len() {
+            match &self.tokens[i].0 {
+                Tok::Punct("{") => depth += 1,
+                Tok::Punct("}") => depth -= 1,
+                Tok::Ident(keyword) if depth == 0 => {
+                    let name = match &self.tokens[i + 1].0 {
+                        Tok::Ident(name) | Tok::Name(name) => name.clone(),
+                        _ => continue,
+                    };
+                    let duplicate = match keyword.as_str() {
+                        "struct" => {
+                            structs += 1;
+                            self.structs
+                                .insert(name.clone(), StructId(structs - 1))
+                                .is_some()
+                        }
+                        "enum" => {
+                            enums += 1;
+                            self.enums.insert(name.clone(), EnumId(enums - 1)).is_some()
+                        }
+                        "fn" => {
+                            functions += 1;
+                            self.functions
+                                .insert(name.clone(), FuncId(functions - 1))
+                                .is_some()
+                        }
+                        "global" => {
+                            globals += 1;
+                            self.globals
+                                .insert(name.clone(), GlobalId(globals - 1))
+                                .is_some()
+                        }
+                        _ => false,
+                    };
+                    if duplicate {
+                        return Err(format!(
+                            "line {}: `{name}` is defined twice",
+                            self.tokens[i].1
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn module(&mut self) -> Parse<Module> {
+        let mut module = Module::default();
+        self.keyword("source")?;
+        module.source = match self.next() {
+            Tok::Str(source) => source,
+            _ => {
+                self.pos -= 1;
+                return self.error("the source file name");
+            }
+        };
+        loop {
+            match self.next() {
+                Tok::Eof => break,
+                Tok::Ident(keyword) => match keyword.as_str() {
+                    "struct" => {
+                        let name = self.ident()?;
+                        let fields = self.fields()?;
+                        module.structs.push(StructLayout { name, fields });
+                    }
+                    "enum" => {
+                        let name = self.ident()?;
+                        let mut variants = Vec::new();
+                        self.expect("{")?;
+                        while !self.eat("}") {
+                            let name = self.ident()?;
+                            let fields = match self.peek() {
+                                Tok::Punct("{") => self.fields()?,
+                                _ => Vec::new(),
+                            };
+                            variants.push(VariantLayout { name, fields });
+                            if !self.eat(",") {
+                                self.expect("}")?;
+                                break;
+                            }
+                        }
+                        module.enums.push(EnumLayout { name, variants });
+                    }
+                    "global" => {
+                        let name = self.name()?;
+                        self.expect(":")?;
+                        let ty = self.ret_ty()?;
+                        self.expect("=")?;
+                        let init = self.function_id()?;
+                        module.globals.push(Global { name, ty, init });
+                    }
+                    "main" => module.main = Some(self.function_id()?),
+                    "fn" => {
+                        let function = self.function(false)?;
+                        module.functions.push(function);
+                    }
+                    "pub" => {
+                        self.keyword("fn")?;
+                        let function = self.function(true)?;
+                        module.functions.push(function);
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("`struct`, `enum`, `global`, `main`, `fn` or `pub fn`");
+                    }
+                },
+                _ => {
+                    self.pos -= 1;
+                    return self.error("`struct`, `enum`, `global`, `main`, `fn` or `pub fn`");
+                }
+            }
+        }
+        Ok(module)
+    }
+
+    fn fields(&mut self) -> Parse<Vec<Field>> {
+        let mut fields = Vec::new();
+        self.expect("{")?;
+        while !self.eat("}") {
+            let name = self.ident()?;
+            self.expect(":")?;
+            let ty = self.ty()?;
+            fields.push(Field { name, ty });
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(fields)
+    }
+
+    fn function_id(&mut self) -> Parse<FuncId> {
+        let name = self.name()?;
+        match self.functions.get(&name) {
+            Some(id) => Ok(*id),
+            None => {
+                self.pos -= 1;
+                self.error("a function")
+            }
+        }
+    }
+
+    fn ty(&mut self) -> Parse<Type> {
+        match self.ret_ty()? {
+            Some(ty) => Ok(ty),
+            None => {
+                self.pos -= 1;
+                self.error("a type with values")
+            }
+        }
+    }
+
+    /// A type, or `void` for none.
+    fn ret_ty(&mut self) -> Parse<Option<Type>> {
+        if self.eat("&") {
+            return Ok(Some(Type::Ptr(Box::new(self.ty()?))));
+        }
+        let name = self.ident()?;
+        if name == "void" {
+            return Ok(None);
+        }
+        if name == "fn" {
+            return Ok(Some(Type::Fn(Box::new(self.signature()?))));
+        }
+        let ty = match Ty::primitive(&name) {
+            Some(Ty::Int(int)) => Type::Int(int),
+            Some(Ty::Float(float)) => Type::Float(float),
+            Some(Ty::Bool) => Type::Bool,
+            Some(Ty::Str) => Type::Str,
+            _ => match (self.structs.get(&name), self.enums.get(&name)) {
+                (Some(id), _) => Type::Struct(*id),
+                (_, Some(id)) => Type::Enum(*id),
+                _ => {
+                    self.pos -= 1;
+                    return self.error("a type");
+                }
+            },
+        };
+        Ok(Some(ty))
+    }
+
+    fn signature(&mut self) -> Parse<Signature> {
+        let mut params = Vec::new();
+        self.expect("(")?;
+        while !self.eat(")") {
+            params.push(self.ty()?);
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        self.expect("->")?;
+        let ret = self.ret_ty()?;
+        Ok(Signature { params, ret })
+    }
+
+    fn function(&mut self, public: bool) -> Parse<Function> {
+        let name = self.name()?;
+        let sig = self.signature()?;
+        let mut func = FnParser {
+            func: Function {
+                name,
+                public,
+                sig,
+                blocks: Vec::new(),
+                values: Vec::new(),
+            },
+            types: HashMap::new(),
+            blocks: HashMap::new(),
+        };
+        self.expect("{")?;
+        while !self.eat("}") {
+            self.block(&mut func)?;
+        }
+        func.finish(self)
+    }
+
+    /// A label, `b` and the block's index.
+    fn label(&mut self) -> Parse<BlockId> {
+        if let Tok::Ident(label) = self.peek()
+            && let Some(n) = label.strip_prefix('b').and_then(|n| n.parse().ok())
+        {
+            self.pos += 1;
+            return Ok(BlockId(n));
+        }
+        self.error("a block label")
+    }
+
+    fn block(&mut self, func: &mut FnParser) -> Parse<()> {
+        let id = self.label()?;
+        let mut params = Vec::new();
+        if self.eat("(") {
+            while !self.eat(")") {
+                let value = self.value()?;
+                self.expect(":")?;
+                let ty = self.ty()?;
+                func.define(self, value, ty)?;
+                params.push(value);
+                if !self.eat(",") {
+                    self.expect(")")?;
+                    break;
+                }
+            }
+        }
+        self.expect(":")?;
+
+        let mut insts = Vec::new();
+        let term = loop {
+            if let Some(term) = self.terminator(func)? {
+                break term;
+            }
+            insts.push(self.inst(func)?);
+        };
+        let block = Block {
+            params,
+            insts,
+            term,
+        };
+        if func.blocks.insert(id, block).is_some() {
+            return Err(format!(
+                "line {}: `b{}` is defined twice",
+                self.line(),
+                id.0
+            ));
+        }
+        Ok(())
+    }
+
+    fn line(&self) -> usize {
+        self.at(self.pos.saturating_sub(1)).1
+    }
+
+    /// ` at line:col`, if present.
+    fn span(&mut self) -> Parse<Span> {
+        if !self.is_keyword("at") {
+            return Ok(Span::default());
+        }
+        self.pos += 1;
+        let line = self.number()?;
+        self.expect(":")?;
+        let col = self.number()?;
+        Ok(Span {
+            line,
+            col,
+            ..Span::default()
+        })
+    }
+
+    fn values(&mut self, close: &str) -> Parse<Vec<Value>> {
+        let mut values = Vec::new();
+        while !self.eat(close) {
+            values.push(self.value()?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    fn callee(&mut self) -> Parse<(Callee, Vec<Value>)> {
+        let callee = match self.peek() {
+            Tok::Value(_) => Callee::Indirect(self.value()?),
+            _ => Callee::Direct(self.function_id()?),
+        };
+        self.expect("(")?;
+        Ok((callee, self.values(")")?))
+    }
+
+    fn target(&mut self) -> Parse<Target> {
+        let block = self.label()?;
+        let args = match self.eat("(") {
+            true => self.values(")")?,
+            false => Vec::new(),
+        };
+        Ok(Target { block, args })
+    }
+
+    fn inst(&mut self, func: &mut FnParser) -> Parse<Inst> {
+        let mut result = None;
+        if let Tok::Value(_) = self.peek() {
+            let value = self.value()?;
+            self.expect(":")?;
+            let ty = self.ty()?;
+            self.expect("=")?;
+            func.define(self, value, ty.clone())?;
+            result = Some((value, ty));
+        }
+        let op = self.ident()?;
+        let kind = match op.as_str() {
+            "const" => {
+                let value = match (self.next(), result.as_ref().map(|r| &r.1)) {
+                    (Tok::Ident(b), Some(Type::Bool)) if b == "true" || b == "false" => {
+                        Const::Bool(b == "true")
+                    }
+                    (Tok::Str(s), Some(Type::Str)) => Const::Str(s),
+                    (Tok::Num(n), Some(Type::Int(int))) => match n.parse::<i128>() {
+                        Ok(v) if v >= i64::MIN as i128 && v <= u64::MAX as i128 => {
+                            Const::Int(wrap(v as i64, *int))
+                        }
+                        _ => {
+                            self.pos -= 1;
+                            return self.error("an integer in range");
+                        }
+                    },
+                    (Tok::Num(n) | Tok::Ident(n), Some(Type::Float(float))) => {
+                        let value = match n.as_str() {
+                            "nan" => f64::NAN,
+                            "inf" => f64::INFINITY,
+                            "-inf" => f64::NEG_INFINITY,
+                            n => match n.parse() {
+                                Ok(v) => v,
+                                Err(_) => {
+                                    self.pos -= 1;
+                                    return self.error("a float");
+                                }
+                            },
+                        };
+                        Const::Float(round(value, *float))
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("a constant of the result type");
+                    }
+                };
+                InstKind::Const(value)
+            }
+            "neg" | "not" => {
+                let op = match op.as_str() {
+                    "neg" => UnOp::Neg,
+                    _ => UnOp::Not,
+                };
+                InstKind::Unary {
+                    op,
+                    arg: self.value()?,
+                }
+            }
+            "add" | "sub" | "mul" | "div" | "eq" | "ne" | "lt" | "le" | "gt" | "ge" => {
+                let op = match op.as_str() {
+                    "add" => BinOp::Add,
+                    "sub" => BinOp::Sub,
+                    "mul" => BinOp::Mul,
+                    "div" => BinOp::Div,
+                    "eq" => BinOp::Eq,
+                    "ne" => BinOp::Ne,
+                    "lt" => BinOp::Lt,
+                    "le" => BinOp::Le,
+                    "gt" => BinOp::Gt,
+                    _ => BinOp::Ge,
+                };
+                let lhs = self.value()?;
+                self.expect(",")?;
+                let rhs = self.value()?;
+                let span = self.span()?;
+                InstKind::Binary { op, lhs, rhs, span }
+            }
+            "func" => InstKind::Func(self.function_id()?),
+            "call" => {
+                let (callee, args) = self.callee()?;
+                let span = self.span()?;
+                InstKind::Call { callee, args, span }
+            }
+            "printf" => {
+                let format = match self.next() {
+                    Tok::Str(format) => format,
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("a format string");
+                    }
+                };
+                let mut args = Vec::new();
+                while self.eat(",") {
+                    args.push(self.value()?);
+                }
+                InstKind::Printf { format, args }
+            }
+            "struct" => {
+                self.expect("(")?;
+                InstKind::Struct(self.values(")")?)
+            }
+            "variant" => {
+                let index = self.number()?;
+                self.expect("(")?;
+                let fields = self.values(")")?;
+                InstKind::Variant { index, fields }
+            }
+            "field" => {
+                let base = self.value()?;
+                self.expect(",")?;
+                InstKind::Field {
+                    base,
+                    index: self.number()?,
+                }
+            }
+            "tag" => InstKind::Tag(self.value()?),
+            "payload" => {
+                let base = self.value()?;
+                self.expect(",")?;
+                let variant = self.number()?;
+                self.expect(",")?;
+                let field = self.number()?;
+                InstKind::Payload {
+                    base,
+                    variant,
+                    field,
+                }
+            }
+            "slot" => InstKind::Slot,
+            "load" => InstKind::Load(self.value()?),
+            "store" => {
+                let ptr = self.value()?;
+                self.expect(",")?;
+                InstKind::Store {
+                    ptr,
+                    value: self.value()?,
+                }
+            }
+            "fieldptr" => {
+                let ptr = self.value()?;
+                self.expect(",")?;
+                InstKind::FieldPtr {
+                    ptr,
+                    index: self.number()?,
+                }
+            }
+            "global" => {
+                let name = self.name()?;
+                let Some(id) = self.globals.get(&name).copied() else {
+                    self.pos -= 1;
+                    return self.error("a global");
+                };
+                InstKind::Global {
+                    id,
+                    span: self.span()?,
+                }
+            }
+            _ => {
+                self.pos -= 1;
+                return self.error("an instruction");
+            }
+        };
+        Ok(Inst {
+            result: result.map(|(value, _)| value),
+            kind,
+        })
+    }
+
+    /// A terminator, or `None` if the next line is an instruction.
+    fn terminator(&mut self, func: &FnParser) -> Parse<Option<Terminator>> {
+        let Tok::Ident(keyword) = self.peek() else {
+            return Ok(None);
+        };
+        let keyword = keyword.clone();
+        let term = match keyword.as_str() {
+            "jump" => {
+                self.pos += 1;
+                Terminator::Jump(self.target()?)
+            }
+            "branch" => {
+                self.pos += 1;
+                let cond = self.value()?;
+                self.expect(",")?;
+                let then = self.target()?;
+                self.expect(",")?;
+                let otherwise = self.target()?;
+                Terminator::Branch {
+                    cond,
+                    then,
+                    otherwise,
+                }
+            }
+            "switch" => {
+                self.pos += 1;
+                let value = self.value()?;
+                let int = match func.types.get(&value) {
+                    Some(Type::Int(int)) => *int,
+                    _ => IntTy::I64,
+                };
+                let mut cases = Vec::new();
+                loop {
+                    self.expect(",")?;
+                    if self.is_keyword("default") {
+                        self.pos += 1;
+                        self.expect(":")?;
+                        break;
+                    }
+                    let case: i128 = self.number()?;
+                    self.expect(":")?;
+                    cases.push((wrap(case as i64, int), self.target()?));
+                }
+                Terminator::Switch {
+                    value,
+                    cases,
+                    default: self.target()?,
+                }
+            }
+            "ret" => {
+                self.pos += 1;
+                match self.peek() {
+                    Tok::Value(_) => Terminator::Return(Some(self.value()?)),
+                    _ => Terminator::Return(None),
+                }
+            }
+            "tail" => {
+                self.pos += 1;
+                let (callee, args) = self.callee()?;
+                Terminator::TailCall { callee, args }
+            }
+            "trap" => {
+                self.pos += 1;
+                let message = match self.next() {
+                    Tok::Str(message) => message,
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("a message");
+                    }
+                };
+                Terminator::Trap {
+                    message,
+                    span: self.span()?,
+                }
+            }
+            "unreachable" => {
+                self.pos += 1;
+                Terminator::Unreachable
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(term))
+    }
+}
+
+struct FnParser {
+    func: Function,
+    types: HashMap<Value, Type>,
+    blocks: HashMap<BlockId, Block>,
+}
+
+impl FnParser {
+    fn define(&mut self, parser: &Parser, value: Value, ty: Type) -> Parse<()> {
+        if self.types.insert(value, ty).is_some() {
+            return Err(format!(
+                "line {}: `%{}` is defined twice",
+                parser.line(),
+                value.0
+            ));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, parser: &Parser) -> Parse<Function> {
+        for i in 0..self.blocks.len() as u32 {
+            match self.blocks.remove(&BlockId(i)) {
+                Some(block) => self.func.blocks.push(block),
+                None => {
+                    return Err(format!(
+                        "line {}: `@{}` has no block `b{i}`",
+                        parser.line(),
+                        self.func.name
+                    ));
+                }
+            }
+        }
+        let count = self.types.keys().map(|v| v.0 + 1).max().unwrap_or(0);
+        for i in 0..count {
+            match self.types.remove(&Value(i)) {
+                Some(ty) => self.func.values.push(ty),
+                None => {
+                    return Err(format!(
+                        "line {}: `@{}` has no value `%{i}`",
+                        parser.line(),
+                        self.func.name
+                    ));
+                }
+            }
+        }
+        Ok(self.func)
+    }
+}
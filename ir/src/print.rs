@@ -0,0 +1,312 @@
+//! The textual form of the IR, which [`crate::parse`] reads back.
+//!
+//! ```text
+//! source "example.cy"
+//!
+//! struct Point { x: i64, y: i64 }
+//! global @origin: Point = @origin.init
+//! main @main
+//!
+//! fn @norm(Point) -> i64 {
+//! b0(%0: Point):
+//!     %1: i64 = field %0, 0
+//!     %2: i64 = field %0, 1
+//!     %3: bool = lt %1, %2
+//!     branch %3, b1, b2(%1)
+//! b1:
+//!     jump b2(%2)
+//! b2(%4: i64):
+//!     ret %4
+//! }
+//! ```
+//!
+//! Every value is written with its type where it is defined, so nothing
+//! has to be inferred when reading it back. Spans are kept as `at
+//! line:col` on the instructions that can fail at run time.
+
+use std::fmt::Write;
+
+use lexer::token::Span;
+use sema::types::IntTy;
+
+use crate::module::*;
+
+pub fn print(module: &Module) -> String {
+    let mut out = String::new();
+    writeln!(out, "source {}", string(&module.source)).unwrap();
+
+    if !module.structs.is_empty() || !module.enums.is_empty() {
+        out.push('\n');
+    }
+    for layout in &module.structs {
+        writeln!(
+            out,
+            "struct {} {}",
+            layout.name,
+            fields(module, &layout.fields)
+        )
+        .unwrap();
+    }
+    for layout in &module.enums {
+        let variants: Vec<_> = layout
+            .variants
+            .iter()
+            .map(|v| match v.fields.is_empty() {
+                true => v.name.clone(),
+                false => format!("{} {}", v.name, fields(module, &v.fields)),
+            })
+            .collect();
+        writeln!(out, "enum {} {{ {} }}", layout.name, variants.join(", ")).unwrap();
+    }
+
+    if !module.globals.is_empty() || module.main.is_some() {
+        out.push('\n');
+    }
+    for global in &module.globals {
+        let ty = match &global.ty {
+            Some(ty) => ty_name(module, ty),
+            None => "void".to_string(),
+        };
+        let init = &module.function(global.init).name;
+        writeln!(out, "global @{}: {ty} = @{init}", global.name).unwrap();
+    }
+    if let Some(main) = module.main {
+        writeln!(out, "main @{}", module.function(main).name).unwrap();
+    }
+
+    for func in &module.functions {
+        out.push('\n');
+        function(module, func, &mut out);
+    }
+    out
+}
+
+fn function(module: &Module, func: &Function, out: &mut String) {
+    let keyword = if func.public { "pub fn" } else { "fn" };
+    writeln!(
+        out,
+        "{keyword} @{}{} {{",
+        func.name,
+        signature(module, &func.sig)
+    )
+    .unwrap();
+    for (i, block) in func.blocks.iter().enumerate() {
+        write!(out, "b{i}").unwrap();
+        if !block.params.is_empty() {
+            let params: Vec<_> = block
+                .params
+                .iter()
+                .map(|p| format!("%{}: {}", p.0, ty_name(module, func.ty(*p))))
+                .collect();
+            write!(out, "({})", params.join(", ")).unwrap();
+        }
+        out.push_str(":\n");
+        for inst in &block.insts {
+            out.push_str("    ");
+            if let Some(result) = inst.result {
+                write!(
+                    out,
+                    "%{}: {} = ",
+                    result.0,
+                    ty_name(module, func.ty(result))
+                )
+                .unwrap();
+            }
+            match (&inst.kind, inst.result.map(|r| func.ty(r))) {
+                (InstKind::Const(Const::Int(v)), Some(Type::Int(int))) => {
+                    writeln!(out, "const {}", int_text(*v, *int)).unwrap();
+                }
+                (kind, _) => writeln!(out, "{}", inst_text(module, kind)).unwrap(),
+            }
+        }
+        writeln!(out, "    {}", terminator(module, func, &block.term)).unwrap();
+    }
+    out.push_str("}\n");
+}
+
+fn inst_text(module: &Module, kind: &InstKind) -> String {
+    match kind {
+        InstKind::Const(value) => format!("const {}", constant(value)),
+        InstKind::Unary { op, arg } => {
+            let op = match op {
+                UnOp::Neg => "neg",
+                UnOp::Not => "not",
+            };
+            format!("{op} %{}", arg.0)
+        }
+        InstKind::Binary { op, lhs, rhs, span } => {
+            let at = match op {
+                BinOp::Div => at(*span),
+                _ => String::new(),
+            };
+            format!("{} %{}, %{}{at}", op.as_str(), lhs.0, rhs.0)
+        }
+        InstKind::Func(id) => format!("func @{}", module.function(*id).name),
+        InstKind::Call { callee, args, span } => {
+            format!("call {}{}", call(module, callee, args), at(*span))
+        }
+        InstKind::Printf { format, args } => {
+            let mut text = format!("printf {}", string(format));
+            for arg in args {
+                write!(text, ", %{}", arg.0).unwrap();
+            }
+            text
+        }
+        InstKind::Struct(fields) => format!("struct({})", values(fields)),
+        InstKind::Variant { index, fields } => format!("variant {index}({})", values(fields)),
+        InstKind::Field { base, index } => format!("field %{}, {index}", base.0),
+        InstKind::Tag(base) => format!("tag %{}", base.0),
+        InstKind::Payload {
+            base,
+            variant,
+            field,
+        } => format!("payload %{}, {variant}, {field}", base.0),
+        InstKind::Slot => "slot".to_string(),
+        InstKind::Load(ptr) => format!("load %{}", ptr.0),
+        InstKind::Store { ptr, value } => format!("store %{}, %{}", ptr.0, value.0),
+        InstKind::FieldPtr { ptr, index } => format!("fieldptr %{}, {index}", ptr.0),
+        InstKind::Global { id, span } => {
+            format!(
+                "global @{}{}",
+                module.globals[id.0 as usize].name,
+                at(*span)
+            )
+        }
+    }
+}
+
+fn terminator(module: &Module, func: &Function, term: &Terminator) -> String {
+    match term {
+        Terminator::Jump(to) => format!("jump {}", target(to)),
+        Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        } => format!(
+            "branch %{}, {}, {}",
+            cond.0,
+            target(then),
+            target(otherwise)
+        ),
+        Terminator::Switch {
+            value,
+            cases,
+            default,
+        } => {
+            let int = match func.ty(*value) {
+                Type::Int(int) => *int,
+                _ => IntTy::I64,
+            };
+            let mut text = format!("switch %{}", value.0);
+            for (case, to) in cases {
+                write!(text, ", {}: {}", int_text(*case, int), target(to)).unwrap();
+            }
+            write!(text, ", default: {}", target(default)).unwrap();
+            text
+        }
+        Terminator::Return(Some(value)) => format!("ret %{}", value.0),
+        Terminator::Return(None) => "ret".to_string(),
+        Terminator::TailCall { callee, args } => format!("tail {}", call(module, callee, args)),
+        Terminator::Trap { message, span } => format!("trap {}{}", string(message), at(*span)),
+        Terminator::Unreachable => "unreachable".to_string(),
+    }
+}
+
+fn call(module: &Module, callee: &Callee, args: &[Value]) -> String {
+    let callee = match callee {
+        Callee::Direct(id) => format!("@{}", module.function(*id).name),
+        Callee::Indirect(value) => format!("%{}", value.0),
+    };
+    format!("{callee}({})", values(args))
+}
+
+fn target(target: &Target) -> String {
+    match target.args.is_empty() {
+        true => format!("b{}", target.block.0),
+        false => format!("b{}({})", target.block.0, values(&target.args)),
+    }
+}
+
+fn values(values: &[Value]) -> String {
+    let values: Vec<_> = values.iter().map(|v| format!("%{}", v.0)).collect();
+    values.join(", ")
+}
+
+fn at(span: Span) -> String {
+    format!(" at {}:{}", span.line, span.col)
+}
+
+fn fields(module: &Module, fields: &[Field]) -> String {
+    if fields.is_empty() {
+        return "{}".to_string();
+    }
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|f| format!("{}: {}", f.name, ty_name(module, &f.ty)))
+        .collect();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+fn signature(module: &Module, sig: &Signature) -> String {
+    let params: Vec<_> = sig.params.iter().map(|p| ty_name(module, p)).collect();
+    let ret = match &sig.ret {
+        Some(ret) => ty_name(module, ret),
+        None => "void".to_string(),
+    };
+    format!("({}) -> {ret}", params.join(", "))
+}
+
+/// The type as written in the text.
+pub fn ty_name(module: &Module, ty: &Type) -> String {
+    match ty {
+        Type::Int(int) => int.as_str().to_string(),
+        Type::Float(float) => float.as_str().to_string(),
+        Type::Bool => "bool".to_string(),
+        Type::Str => "str".to_string(),
+        Type::Struct(id) => module.structs[id.0 as usize].name.clone(),
+        Type::Enum(id) => module.enums[id.0 as usize].name.clone(),
+        Type::Ptr(inner) => format!("&{}", ty_name(module, inner)),
+        Type::Fn(sig) => format!("fn{}", signature(module, sig)),
+    }
+}
+
+/// An integer as its type reads it: unsigned types never print negative.
+fn int_text(value: i64, int: IntTy) -> String {
+    match int.is_signed() {
+        true => value.to_string(),
+        false => (value as u64).to_string(),
+    }
+}
+
+fn constant(value: &Const) -> String {
+    match value {
+        Const::Int(v) => v.to_string(),
+        Const::Float(v) if v.is_nan() => "nan".to_string(),
+        Const::Float(v) if v.is_infinite() => match *v > 0.0 {
+            true => "inf".to_string(),
+            false => "-inf".to_string(),
+        },
+        Const::Float(v) => format!("{v:?}"),
+        Const::Bool(v) => v.to_string(),
+        Const::Str(v) => string(v),
+    }
+}
+
+/// A double-quoted string in which only `"`, `\` and control characters
+/// are escaped.
+fn string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
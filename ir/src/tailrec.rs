@@ -0,0 +1,70 @@
+//! Tail recursion to loops.
+//!
+//! A function that tail calls itself gets a loop header: the entry jumps
+//! to it with the parameters, and each such tail call jumps back to it with
+//! the arguments instead, so the recursion no longer depends on how a code
+//! generator implements tail calls.
+//!
+//! Functions with slots are left alone, since a slot in the entry would be
+//! shared by every iteration while a reference to it may still be alive.
+
+use crate::cfg;
+use crate::module::*;
+
+pub fn tailrec(module: &mut Module) -> bool {
+    let mut changed = false;
+    for (i, func) in module.functions.iter_mut().enumerate() {
+        let id = FuncId(i as u32);
+        let recursive = func.blocks.iter().any(|b| is_self_call(&b.term, id));
+        let has_slots = func.blocks[0]
+            .insts
+            .iter()
+            .any(|inst| inst.kind == InstKind::Slot);
+        if recursive && !has_slots {
+            make_loop(func, id);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn is_self_call(term: &Terminator, id: FuncId) -> bool {
+    matches!(term, Terminator::TailCall { callee: Callee::Direct(callee), .. } if *callee == id)
+}
+
+fn make_loop(func: &mut Function, id: FuncId) {
+    // The old entry becomes the header, keeping its parameters so that
+    // every use of them stays valid.
+    let header = BlockId(func.blocks.len() as u32);
+    let params: Vec<Value> = func.blocks[0]
+        .params
+        .iter()
+        .map(|p| func.ty(*p).clone())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|ty| func.value(ty))
+        .collect();
+    let entry = Block {
+        params: params.clone(),
+        insts: Vec::new(),
+        term: Terminator::Jump(Target {
+            block: header,
+            args: params,
+        }),
+    };
+    let old = std::mem::replace(&mut func.blocks[0], entry);
+    func.blocks.push(old);
+
+    for block in &mut func.blocks {
+        if is_self_call(&block.term, id) {
+            let Terminator::TailCall { args, .. } = &mut block.term else {
+                unreachable!();
+            };
+            block.term = Terminator::Jump(Target {
+                block: header,
+                args: std::mem::take(args),
+            });
+        }
+    }
+    cfg::compact(func);
+}
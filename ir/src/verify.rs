@@ -0,0 +1,422 @@
+//! Checks that a module is well formed: every value is defined once before
+//! it is used, and every instruction, jump and call gets the types it
+//! expects. Lowering and every pass must produce modules that pass.
+
+use std::collections::HashMap;
+
+use sema::format::{self, Piece};
+use sema::types::IntTy;
+
+use crate::cfg;
+use crate::module::*;
+use crate::print::ty_name;
+
+/// Says what is wrong with the first function that is malformed.
+pub fn verify(module: &Module) -> Result<(), String> {
+    for (i, global) in module.globals.iter().enumerate() {
+        let Some(init) = module.functions.get(global.init.0 as usize) else {
+            return Err(format!("global {i} has no initializer"));
+        };
+        if !init.sig.params.is_empty() || init.sig.ret != global.ty {
+            return Err(format!(
+                "@{} does not initialize @{}",
+                init.name, global.name
+            ));
+        }
+    }
+    if let Some(main) = module.main
+        && module.functions.get(main.0 as usize).is_none()
+    {
+        return Err("`main` is not a function".to_string());
+    }
+    for func in &module.functions {
+        Verifier { module, func }
+            .function()
+            .map_err(|e| format!("@{}: {e}", func.name))?;
+    }
+    Ok(())
+}
+
+struct Verifier<'m> {
+    module: &'m Module,
+    func: &'m Function,
+}
+
+/// Where a value is defined: its block, and its position there, with the
+/// block's parameters before every instruction.
+type Site = (BlockId, usize);
+
+impl Verifier<'_> {
+    fn function(&self) -> Result<(), String> {
+        let func = self.func;
+        let Some(entry) = func.blocks.first() else {
+            return Err("no entry block".to_string());
+        };
+        let params: Vec<_> = entry.params.iter().map(|p| func.ty(*p).clone()).collect();
+        if params != func.sig.params {
+            return Err("the entry block's parameters differ from the signature".to_string());
+        }
+
+        let mut sites: HashMap<Value, Site> = HashMap::new();
+        for (b, block) in func.blocks.iter().enumerate() {
+            let b = BlockId(b as u32);
+            let results = block.insts.iter().filter_map(|inst| inst.result);
+            for (i, value) in block.params.iter().copied().chain(results).enumerate() {
+                if value.0 as usize >= func.values.len() {
+                    return Err(format!("%{} has no type", value.0));
+                }
+                if sites.insert(value, (b, i)).is_some() {
+                    return Err(format!("%{} is defined twice", value.0));
+                }
+            }
+        }
+
+        let idom = cfg::dominators(func);
+        let reachable = cfg::reverse_postorder(func);
+        for &b in &reachable {
+            let block = func.block(b);
+            let here = |i| (b, block.params.len() + i);
+            for (i, inst) in block.insts.iter().enumerate() {
+                for operand in inst.kind.operands() {
+                    self.dominates(&sites, &idom, operand, here(i))?;
+                }
+                self.inst(inst, b)
+                    .map_err(|e| format!("b{}: {} {e}", b.0, inst_name(&inst.kind)))?;
+            }
+            for operand in block.term.operands() {
+                self.dominates(&sites, &idom, operand, here(block.insts.len()))?;
+            }
+            self.terminator(&block.term)
+                .map_err(|e| format!("b{}: {e}", b.0))?;
+        }
+        Ok(())
+    }
+
+    /// Whether `value` is defined on every path to `site`, before it.
+    fn dominates(
+        &self,
+        sites: &HashMap<Value, Site>,
+        idom: &[Option<BlockId>],
+        value: Value,
+        site: Site,
+    ) -> Result<(), String> {
+        let Some(&(block, position)) = sites.get(&value) else {
+            return Err(format!("b{}: %{} is never defined", site.0.0, value.0));
+        };
+        let before = match block == site.0 {
+            true => position < site.1,
+            false => cfg::dominates(idom, block, site.0),
+        };
+        if !before {
+            return Err(format!(
+                "b{}: %{} is used before it is defined",
+                site.0.0, value.0
+            ));
+        }
+        Ok(())
+    }
+
+    fn ty(&self, value: Value) -> &Type {
+        self.func.ty(value)
+    }
+
+    fn name(&self, ty: &Type) -> String {
+        ty_name(self.module, ty)
+    }
+
+    fn expect(&self, value: Value, ty: &Type) -> Result<(), String> {
+        if self.ty(value) != ty {
+            return Err(format!(
+                "expects %{} to be `{}`, not `{}`",
+                value.0,
+                self.name(ty),
+                self.name(self.ty(value))
+            ));
+        }
+        Ok(())
+    }
+
+    fn pointee(&self, ptr: Value) -> Result<&Type, String> {
+        match self.ty(ptr) {
+            Type::Ptr(inner) => Ok(inner),
+            ty => Err(format!("expects a pointer, not `{}`", self.name(ty))),
+        }
+    }
+
+    fn layout(&self, ty: &Type) -> Result<&StructLayout, String> {
+        match ty {
+            Type::Struct(id) if (id.0 as usize) < self.module.structs.len() => {
+                Ok(&self.module.structs[id.0 as usize])
+            }
+            ty => Err(format!("expects a struct, not `{}`", self.name(ty))),
+        }
+    }
+
+    fn variant(&self, ty: &Type, index: u32) -> Result<&VariantLayout, String> {
+        let Type::Enum(id) = ty else {
+            return Err(format!("expects an enum, not `{}`", self.name(ty)));
+        };
+        let layout = &self.module.enums[id.0 as usize];
+        layout
+            .variants
+            .get(index as usize)
+            .ok_or_else(|| format!("`{}` has no variant {index}", layout.name))
+    }
+
+    fn fields(&self, values: &[Value], fields: &[Field]) -> Result<(), String> {
+        if values.len() != fields.len() {
+            return Err(format!(
+                "expects {} fields, not {}",
+                fields.len(),
+                values.len()
+            ));
+        }
+        for (value, field) in values.iter().zip(fields) {
+            self.expect(*value, &field.ty)?;
+        }
+        Ok(())
+    }
+
+    fn signature(&self, callee: &Callee) -> Result<Signature, String> {
+        match callee {
+            Callee::Direct(id) => match self.module.functions.get(id.0 as usize) {
+                Some(func) => Ok(func.sig.clone()),
+                None => Err(format!("calls missing function {}", id.0)),
+            },
+            Callee::Indirect(value) => match self.ty(*value) {
+                Type::Fn(sig) => Ok((**sig).clone()),
+                ty => Err(format!("calls `{}`", self.name(ty))),
+            },
+        }
+    }
+
+    fn args(&self, sig: &Signature, args: &[Value]) -> Result<(), String> {
+        if args.len() != sig.params.len() {
+            return Err(format!(
+                "passes {} arguments for {}",
+                args.len(),
+                sig.params.len()
+            ));
+        }
+        for (arg, param) in args.iter().zip(&sig.params) {
+            self.expect(*arg, param)?;
+        }
+        Ok(())
+    }
+
+    fn inst(&self, inst: &Inst, block: BlockId) -> Result<(), String> {
+        // What the instruction produces, if anything.
+        let produces: Option<Type> = match &inst.kind {
+            InstKind::Const(value) => {
+                let ty = inst.result.map(|r| self.ty(r));
+                let fits = matches!(
+                    (value, ty),
+                    (Const::Int(_), Some(Type::Int(_)))
+                        | (Const::Float(_), Some(Type::Float(_)))
+                        | (Const::Bool(_), Some(Type::Bool))
+                        | (Const::Str(_), Some(Type::Str))
+                );
+                if !fits {
+                    return Err("has the wrong type".to_string());
+                }
+                ty.cloned()
+            }
+            InstKind::Unary { op, arg } => {
+                let ty = self.ty(*arg);
+                let fits = match op {
+                    UnOp::Neg => matches!(ty, Type::Int(_) | Type::Float(_)),
+                    UnOp::Not => *ty == Type::Bool,
+                };
+                if !fits {
+                    return Err(format!("cannot take `{}`", self.name(ty)));
+                }
+                Some(ty.clone())
+            }
+            InstKind::Binary { op, lhs, rhs, .. } => {
+                let ty = self.ty(*lhs);
+                self.expect(*rhs, ty)?;
+                let fits = match op.is_comparison() {
+                    true => matches!(ty, Type::Int(_) | Type::Float(_) | Type::Bool | Type::Str),
+                    false => matches!(ty, Type::Int(_) | Type::Float(_)),
+                };
+                if !fits {
+                    return Err(format!("cannot take `{}`", self.name(ty)));
+                }
+                Some(match op.is_comparison() {
+                    true => Type::Bool,
+                    false => ty.clone(),
+                })
+            }
+            InstKind::Func(id) => match self.module.functions.get(id.0 as usize) {
+                Some(func) => Some(Type::Fn(Box::new(func.sig.clone()))),
+                None => return Err(format!("names missing function {}", id.0)),
+            },
+            InstKind::Call { callee, args, .. } => {
+                let sig = self.signature(callee)?;
+                self.args(&sig, args)?;
+                sig.ret
+            }
+            InstKind::Printf { format, args } => {
+                let Ok(pieces) = format::parse(format) else {
+                    return Err("has an invalid format string".to_string());
+                };
+                let placeholders = pieces
+                    .iter()
+                    .filter(|p| matches!(p.piece, Piece::Arg(_)))
+                    .count();
+                if placeholders != args.len() {
+                    return Err(format!("has {} arguments for {placeholders}", args.len()));
+                }
+                None
+            }
+            InstKind::Struct(values) => {
+                let ty = inst.result.map(|r| self.ty(r)).ok_or("has no result")?;
+                self.fields(values, &self.layout(ty)?.fields)?;
+                Some(ty.clone())
+            }
+            InstKind::Variant { index, fields } => {
+                let ty = inst.result.map(|r| self.ty(r)).ok_or("has no result")?;
+                self.fields(fields, &self.variant(ty, *index)?.fields)?;
+                Some(ty.clone())
+            }
+            InstKind::Field { base, index } => {
+                let layout = self.layout(self.ty(*base))?;
+                match layout.fields.get(*index as usize) {
+                    Some(field) => Some(field.ty.clone()),
+                    None => return Err(format!("`{}` has no field {index}", layout.name)),
+                }
+            }
+            InstKind::Tag(base) => {
+                self.variant(self.ty(*base), 0)?;
+                Some(Type::Int(IntTy::U32))
+            }
+            InstKind::Payload {
+                base,
+                variant,
+                field,
+            } => {
+                let variant = self.variant(self.ty(*base), *variant)?;
+                match variant.fields.get(*field as usize) {
+                    Some(field) => Some(field.ty.clone()),
+                    None => return Err(format!("`{}` has no field {field}", variant.name)),
+                }
+            }
+            InstKind::Slot => {
+                if block != BlockId(0) {
+                    return Err("is not in the entry block".to_string());
+                }
+                let ty = inst.result.map(|r| self.ty(r)).ok_or("has no result")?;
+                if !matches!(ty, Type::Ptr(_)) {
+                    return Err("does not produce a pointer".to_string());
+                }
+                Some(ty.clone())
+            }
+            InstKind::Load(ptr) => Some(self.pointee(*ptr)?.clone()),
+            InstKind::Store { ptr, value } => {
+                self.expect(*value, self.pointee(*ptr)?)?;
+                None
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                let layout = self.layout(self.pointee(*ptr)?)?;
+                match layout.fields.get(*index as usize) {
+                    Some(field) => Some(Type::Ptr(Box::new(field.ty.clone()))),
+                    None => return Err(format!("`{}` has no field {index}", layout.name)),
+                }
+            }
+            InstKind::Global { id, .. } => match self.module.globals.get(id.0 as usize) {
+                Some(global) => global.ty.clone().map(|ty| Type::Ptr(Box::new(ty))),
+                None => return Err(format!("names missing global {}", id.0)),
+            },
+        };
+
+        let result = inst.result.map(|r| self.ty(r));
+        if result != produces.as_ref() {
+            let show = |ty: Option<&Type>| match ty {
+                Some(ty) => format!("`{}`", self.name(ty)),
+                None => "nothing".to_string(),
+            };
+            return Err(format!(
+                "produces {}, not {}",
+                show(produces.as_ref()),
+                show(result)
+            ));
+        }
+        Ok(())
+    }
+
+    fn terminator(&self, term: &Terminator) -> Result<(), String> {
+        for target in term.targets() {
+            let Some(block) = self.func.blocks.get(target.block.0 as usize) else {
+                return Err(format!("jumps to missing block b{}", target.block.0));
+            };
+            if target.args.len() != block.params.len() {
+                return Err(format!(
+                    "passes {} arguments to b{}, which takes {}",
+                    target.args.len(),
+                    target.block.0,
+                    block.params.len()
+                ));
+            }
+            for (arg, param) in target.args.iter().zip(&block.params) {
+                self.expect(*arg, self.ty(*param))?;
+            }
+        }
+        match term {
+            Terminator::Branch { cond, .. } => self.expect(*cond, &Type::Bool),
+            Terminator::Switch { value, .. } => match self.ty(*value) {
+                Type::Int(_) => Ok(()),
+                ty => Err(format!("switches on `{}`", self.name(ty))),
+            },
+            Terminator::Return(value) => {
+                match (value, &self.func.sig.ret) {
+                    (Some(value), Some(ret)) => self.expect(*value, ret)?,
+                    (None, None) => {}
+                    _ => return Err("returns the wrong type".to_string()),
+                }
+                Ok(())
+            }
+            Terminator::TailCall { callee, args } => {
+                let sig = self.signature(callee)?;
+                self.args(&sig, args)?;
+                if sig.ret != self.func.sig.ret {
+                    return Err("tail calls a function with another return type".to_string());
+                }
+                Ok(())
+            }
+            Terminator::Jump(_) | Terminator::Trap { .. } | Terminator::Unreachable => Ok(()),
+        }
+    }
+}
+
+fn inst_name(kind: &InstKind) -> &'static str {
+    match kind {
+        InstKind::Binary { op, .. } => match op {
+            BinOp::Add => "`add`",
+            BinOp::Sub => "`sub`",
+            BinOp::Mul => "`mul`",
+            BinOp::Div => "`div`",
+            BinOp::Eq => "`eq`",
+            BinOp::Ne => "`ne`",
+            BinOp::Lt => "`lt`",
+            BinOp::Le => "`le`",
+            BinOp::Gt => "`gt`",
+            BinOp::Ge => "`ge`",
+        },
+        InstKind::Const(_) => "`const`",
+        InstKind::Unary { op: UnOp::Neg, .. } => "`neg`",
+        InstKind::Unary { op: UnOp::Not, .. } => "`not`",
+        InstKind::Func(_) => "`func`",
+        InstKind::Call { .. } => "`call`",
+        InstKind::Printf { .. } => "`printf`",
+        InstKind::Struct(_) => "`struct`",
+        InstKind::Variant { .. } => "`variant`",
+        InstKind::Field { .. } => "`field`",
+        InstKind::Tag(_) => "`tag`",
+        InstKind::Payload { .. } => "`payload`",
+        InstKind::Slot => "`slot`",
+        InstKind::Load(_) => "`load`",
+        InstKind::Store { .. } => "`store`",
+        InstKind::FieldPtr { .. } => "`fieldptr`",
+        InstKind::Global { .. } => "`global`",
+    }
+}
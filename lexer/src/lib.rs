@@ -4,28 +4,52 @@ use std::str::Chars;
 
 pub mod token;
 
-use crate::token::{Location, Token, TokenType};
-use utils::error;
+use crate::token::{FloatLit, IntLit, Span, Token, TokenType};
+
+/// Message carried by a [`TokenType::Error`] for a string missing its closing quote.
+pub const UNTERMINATED_STRING: &str = "unterminated string literal";
+/// Message carried by a [`TokenType::Error`] for a `/*` comment missing its `*/`.
+pub const UNCLOSED_COMMENT: &str = "unclosed block comment";
+/// Start of the message carried by a [`TokenType::Error`] for a malformed
+/// number literal; what is wrong with it follows after `": "`.
+pub const INVALID_NUMBER: &str = "invalid number literal";
+
+const INT_SUFFIXES: [&str; 8] = ["i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64"];
+const FLOAT_SUFFIXES: [&str; 3] = ["f16", "f32", "f64"];
 
 #[derive(Clone)]
 pub struct Lexer<'a> {
     content: Peekable<Chars<'a>>,
+    file_id: usize,
+    offset: usize,
     line: usize,
     col: usize,
+    unclosed_comment: Option<Span>,
+    emitted_eof: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(content: &'a String) -> Self {
+    pub fn new(content: &'a str) -> Self {
+        Self::with_file_id(content, 0)
+    }
+
+    /// Creates a lexer whose token spans refer to `file_id`.
+    pub fn with_file_id(content: &'a str, file_id: usize) -> Self {
         Self {
             content: content.chars().peekable(),
+            file_id,
+            offset: 0,
             line: 1,
             col: 1,
+            unclosed_comment: None,
+            emitted_eof: false,
         }
     }
 
     fn next_char(&mut self) -> Option<char> {
         let next = self.content.next();
         if let Some(c) = next {
+            self.offset += c.len_utf8();
             if c == '\n' {
                 self.line += 1;
                 self.col = 1;
@@ -40,19 +64,35 @@ impl<'a> Lexer<'a> {
         self.content.peek()
     }
 
+    fn span_here(&self) -> Span {
+        Span {
+            file_id: self.file_id,
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            col: self.col,
+            expansion: 0,
+        }
+    }
+
     pub fn next_token(&mut self) -> anyhow::Result<Token> {
         self.skip_whitespace_and_comments();
 
-        let loc = Location {
-            line: self.line,
-            col: self.col,
-        };
+        if let Some(mut span) = self.unclosed_comment.take() {
+            span.end = self.offset;
+            return Ok(Token {
+                kind: TokenType::Error(UNCLOSED_COMMENT.to_string()),
+                span,
+            });
+        }
+
+        let mut span = self.span_here();
         let c = match self.peek() {
             Some(c) => *c,
             None => {
                 return Ok(Token {
                     kind: TokenType::EOF,
-                    loc,
+                    span,
                 });
             }
         };
@@ -155,10 +195,22 @@ impl<'a> Lexer<'a> {
                 if self.peek() == Some(&'>') {
                     self.next_char();
                     TokenType::Pipe
+                } else if self.peek() == Some(&'|') {
+                    self.next_char();
+                    TokenType::OrOr
                 } else {
                     TokenType::VertBar
                 }
             }
+            '&' => {
+                self.next_char();
+                if self.peek() == Some(&'&') {
+                    self.next_char();
+                    TokenType::AndAnd
+                } else {
+                    TokenType::Amp
+                }
+            }
 
             ';' => {
                 self.next_char();
@@ -174,7 +226,12 @@ impl<'a> Lexer<'a> {
             }
             '!' => {
                 self.next_char();
-                TokenType::Bang
+                if self.peek() == Some(&'=') {
+                    self.next_char();
+                    TokenType::BangEql
+                } else {
+                    TokenType::Bang
+                }
             }
             '#' => {
                 self.next_char();
@@ -209,21 +266,22 @@ impl<'a> Lexer<'a> {
                 let ident_str = self.read_ident()?;
                 TokenType::from(ident_str.as_str())
             }
-            _ if c.is_numeric() => {
-                let number_str = self.read_number()?;
-                TokenType::Number(number_str)
-            }
-            '"' => {
-                let string_str = self.read_string()?;
-                TokenType::String(string_str)
-            }
+            _ if c.is_ascii_digit() => match self.read_number() {
+                Ok(kind) => kind,
+                Err(e) => TokenType::Error(format!("{INVALID_NUMBER}: {e}")),
+            },
+            '"' => match self.read_string() {
+                Ok(string_str) => TokenType::String(string_str),
+                Err(e) => TokenType::Error(e.to_string()),
+            },
             _ => {
                 self.next_char();
                 TokenType::Invalid(c)
             }
         };
 
-        Ok(Token { kind, loc })
+        span.end = self.offset;
+        Ok(Token { kind, span })
     }
 
     fn skip_whitespace(&mut self) {
@@ -244,16 +302,137 @@ impl<'a> Lexer<'a> {
         Ok(res)
     }
 
-    fn read_number(&mut self) -> anyhow::Result<String> {
-        let mut res = String::with_capacity(10);
+    /// Reads an integer or float literal: digits with `_` separators in
+    /// base 10, or in base 16, 2 or 8 after `0x`, `0b` or `0o`, then for
+    /// base 10 an optional fraction and exponent, then an optional type
+    /// suffix. A suffix naming a float type makes a float.
+    fn read_number(&mut self) -> anyhow::Result<TokenType> {
+        let mut text = String::with_capacity(10);
+        let mut radix = 10;
+        if self.peek() == Some(&'0') {
+            let prefix = self.content.clone().nth(1);
+            radix = match prefix {
+                Some('x') => 16,
+                Some('b') => 2,
+                Some('o') => 8,
+                _ => 10,
+            };
+            if radix != 10 {
+                text.push(self.next_char().unwrap());
+                text.push(self.next_char().unwrap());
+            }
+        }
+
+        let mut digits = String::new();
+        self.read_digits(radix, &mut text, &mut digits);
+        let mut float = false;
+        if radix == 10 {
+            // `1.` is `1` followed by a dot, as in `1..2`.
+            if self.peek() == Some(&'.')
+                && self
+                    .content
+                    .clone()
+                    .nth(1)
+                    .is_some_and(|c| c.is_ascii_digit())
+            {
+                float = true;
+                text.push(self.next_char().unwrap());
+                digits.push('.');
+                self.read_digits(10, &mut text, &mut digits);
+            }
+            if matches!(self.peek(), Some('e' | 'E')) {
+                let mut ahead = self.content.clone();
+                ahead.next();
+                let sign = ahead.next_if(|c| matches!(c, '+' | '-'));
+                if ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+                    float = true;
+                    text.push(self.next_char().unwrap());
+                    digits.push('e');
+                    if let Some(sign) = sign {
+                        text.push(self.next_char().unwrap());
+                        digits.push(sign);
+                    }
+                    self.read_digits(10, &mut text, &mut digits);
+                }
+            }
+        }
+
+        let mut suffix = String::new();
         while let Some(&c) = self.peek() {
-            if c.is_numeric() || c == '_' {
-                res.push(self.next_char().unwrap());
+            if c.is_alphanumeric() || c == '_' {
+                suffix.push(self.next_char().unwrap());
             } else {
                 break;
             }
         }
-        Ok(res)
+        text.push_str(&suffix);
+
+        let base = match radix {
+            16 => "a hexadecimal",
+            2 => "a binary",
+            8 => "an octal",
+            _ => "a decimal",
+        };
+        if digits.is_empty() {
+            anyhow::bail!("no digits after `{}`", &text[..2]);
+        }
+        if let Some(digit) = digits
+            .chars()
+            .find(|c| !c.is_digit(radix) && c.is_ascii_digit())
+        {
+            anyhow::bail!("`{digit}` is not {base} digit");
+        }
+        let suffix = match suffix.as_str() {
+            "" => None,
+            s if FLOAT_SUFFIXES.contains(&s) => {
+                if radix != 10 {
+                    anyhow::bail!("{base} literal cannot be a float");
+                }
+                float = true;
+                Some(suffix)
+            }
+            "e" | "E" if radix == 10 => anyhow::bail!("no digits in the exponent"),
+            s if INT_SUFFIXES.contains(&s) => {
+                if float {
+                    anyhow::bail!("a float literal cannot have type `{s}`");
+                }
+                Some(suffix)
+            }
+            s => anyhow::bail!("unknown suffix `{s}`"),
+        };
+
+        if float {
+            let value = digits.parse().expect("float syntax checked while reading");
+            return Ok(TokenType::Float(FloatLit {
+                text,
+                value,
+                suffix,
+            }));
+        }
+        let value = digits.chars().try_fold(0u64, |value, c| {
+            value
+                .checked_mul(radix as u64)?
+                .checked_add(c.to_digit(radix)? as u64)
+        });
+        Ok(TokenType::Int(IntLit {
+            text,
+            value,
+            suffix,
+        }))
+    }
+
+    /// Reads digits and `_` separators into `text`, and the digits alone
+    /// into `digits`. In base 10 or less this takes every decimal digit, so
+    /// that `0b12` is reported as a bad digit rather than read as `0b1`.
+    fn read_digits(&mut self, radix: u32, text: &mut String, digits: &mut String) {
+        while let Some(&c) = self.peek() {
+            if c.is_ascii_digit() || c.is_digit(radix) {
+                digits.push(c);
+            } else if c != '_' {
+                break;
+            }
+            text.push(self.next_char().unwrap());
+        }
     }
 
     fn read_string(&mut self) -> anyhow::Result<String> {
@@ -264,20 +443,20 @@ impl<'a> Lexer<'a> {
                 res.push(self.next_char().unwrap());
             } else {
                 self.next_char();
-                break;
+                return Ok(res);
             }
         }
-        Ok(res)
+        anyhow::bail!(UNTERMINATED_STRING)
     }
 
     fn skip_whitespace_and_comments(&mut self) {
         loop {
             let mut skipped_something = false;
-            let initial_len = self.content.clone().count();
+            let initial_offset = self.offset;
 
             self.skip_whitespace();
 
-            if self.content.clone().count() < initial_len {
+            if self.offset > initial_offset {
                 skipped_something = true;
             }
 
@@ -310,20 +489,19 @@ impl<'a> Lexer<'a> {
                 }
                 // Multi-line comment /* */
                 Some(&'*') => {
+                    let start = self.span_here();
                     self.next_char();
                     self.next_char();
                     let mut found_end = false;
                     while let Some(c) = self.next_char() {
-                        if c == '*' {
-                            if self.peek() == Some(&'/') {
-                                self.next_char();
-                                found_end = true;
-                                break;
-                            }
+                        if c == '*' && self.peek() == Some(&'/') {
+                            self.next_char();
+                            found_end = true;
+                            break;
                         }
                     }
                     if !found_end {
-                        error!("Found unclosed multiline comment");
+                        self.unclosed_comment = Some(start);
                     }
                     skipped = true;
                 }
@@ -337,13 +515,24 @@ impl<'a> Lexer<'a> {
 impl Iterator for Lexer<'_> {
     type Item = Token;
 
+    /// Yields every token followed by a single `EOF` token, then `None`.
     fn next(&mut self) -> Option<Self::Item> {
+        if self.emitted_eof {
+            return None;
+        }
         match self.next_token() {
-            Ok(t) if t.kind == TokenType::EOF => None, // EOF
-            Ok(t) => Some(t),
+            Ok(t) => {
+                if t.kind == TokenType::EOF {
+                    self.emitted_eof = true;
+                }
+                Some(t)
+            }
             Err(e) => {
-                error!("{e}");
-                None
+                self.emitted_eof = true;
+                Some(Token {
+                    kind: TokenType::Error(e.to_string()),
+                    span: self.span_here(),
+                })
             }
         }
     }
@@ -1,15 +1,111 @@
 #![allow(dead_code)]
 
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Default)]
-pub struct Location {
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A region of source text.
+///
+/// `start` and `end` are byte offsets into the file identified by `file_id`
+/// (`end` is exclusive). `line` and `col` are 1-based and point at `start`.
+///
+/// `expansion` is `0` for code written in the source. Tokens produced by a
+/// macro expansion keep the span of the macro body they came from but get
+/// the id of that expansion, so every expanded node has a distinct span.
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone, Copy, Default, Hash)]
+pub struct Span {
+    pub file_id: usize,
+    pub start: usize,
+    pub end: usize,
     pub line: usize,
     pub col: usize,
+    pub expansion: u32,
+}
+
+impl Span {
+    /// Returns a span starting at `self` and ending where `other` ends.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone)]
 pub struct Token {
     pub kind: TokenType,
-    pub loc: Location,
+    pub span: Span,
+}
+
+/// An integer literal such as `42`, `0xFF` or `1_000u64`.
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone)]
+pub struct IntLit {
+    /// The literal as written.
+    pub text: String,
+    /// `None` if it does not fit in 64 bits.
+    pub value: Option<u64>,
+    /// The type named after the digits, such as `u8`.
+    pub suffix: Option<String>,
+}
+
+impl fmt::Debug for IntLit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{value}{}", self.suffix.as_deref().unwrap_or("")),
+            None => f.write_str(&self.text),
+        }
+    }
+}
+
+/// A float literal such as `3.14`, `1e9` or `2f32`.
+#[derive(Clone)]
+pub struct FloatLit {
+    /// The literal as written.
+    pub text: String,
+    /// The nearest `f64`, infinite if the literal is too large for one.
+    pub value: f64,
+    /// The type named after the digits, such as `f32`.
+    pub suffix: Option<String>,
+}
+
+// The value follows from the text, which unlike an `f64` is totally ordered.
+impl PartialEq for FloatLit {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for FloatLit {}
+
+impl PartialOrd for FloatLit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloatLit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.text.cmp(&other.text)
+    }
+}
+
+impl fmt::Debug for FloatLit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}{}",
+            self.value,
+            self.suffix.as_deref().unwrap_or("")
+        )
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone)]
@@ -26,6 +122,9 @@ pub enum TokenType {
     LessEql,       // <=
     GreaterEql,    // >=
     DoubleEql,     // ==
+    BangEql,       // !=
+    AndAnd,        // &&
+    OrOr,          // ||
     RightArrow,    // ->
     FatRightArrow, // =>
     Pipe,          // |>
@@ -41,6 +140,7 @@ pub enum TokenType {
     Less,      // <
     Greater,   // >
     VertBar,   // |
+    Amp,       // &
     Dot,       // .
     Comma,     // ,
     Question,  // ?
@@ -78,7 +178,8 @@ pub enum TokenType {
 
     // Literals
     Ident(String),
-    Number(String),
+    Int(IntLit),
+    Float(FloatLit),
     String(String),
     Invalid(char),
     EOF,
@@ -101,6 +202,9 @@ impl TokenType {
             LessEql => "<=",
             GreaterEql => ">=",
             DoubleEql => "==",
+            BangEql => "!=",
+            AndAnd => "&&",
+            OrOr => "||",
             RightArrow => "->",
             FatRightArrow => "=>",
             Pipe => "|>",
@@ -111,9 +215,10 @@ impl TokenType {
             Minus => "-",
             Star => "*",
             Div => "/",
-            Less => ">",
-            Greater => "<",
+            Less => "<",
+            Greater => ">",
             VertBar => "|",
+            Amp => "&",
             Dot => ".",
             Comma => ",",
             Question => "?",
@@ -145,11 +250,12 @@ impl TokenType {
             Continue => "Continue",
             Unreachable => "Unreachable",
             Ident(_) => "Ident",
-            Number(_) => "Number",
+            Int(_) => "Int",
+            Float(_) => "Float",
             String(_) => "String",
             Invalid(_) => "Invalid",
             EOF => "EOF",
-            Error(_) => unreachable!(),
+            Error(_) => "Error",
         }
     }
 }
@@ -0,0 +1,322 @@
+//! Macro expansion.
+//!
+//! Runs after [`Parser::program`](crate::Parser::program) and before any
+//! later phase: every `name!(...)` is replaced by the body of the macro
+//! `name` with its parameters substituted, and the result is parsed as an
+//! expression. Macros are declared at the top level and are visible in the
+//! whole program, including in bodies of other macros.
+//!
+//! Expansion is hygienic for identifiers the body introduces itself. Names
+//! bound by `let`, `mut`, `const` or `static` and by `|x|` captures in the
+//! body are renamed to `name#N`, where `N` is the id of the expansion, so
+//! they can neither capture nor shadow identifiers at the call site.
+//! Arguments are substituted after renaming and keep referring to the
+//! caller's names.
+
+use std::collections::{HashMap, HashSet};
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::{Span, Token, TokenType};
+
+use crate::Parser;
+use crate::node::*;
+
+/// How deep macros may expand into other macros before we give up.
+pub const RECURSION_LIMIT: usize = 64;
+
+struct MacroDef {
+    params: Vec<Token>,
+    body: Vec<Token>,
+    span: Span,
+}
+
+struct Expander {
+    macros: HashMap<String, MacroDef>,
+    /// Id of the last expansion; `Span::expansion` of the tokens it produced.
+    expansions: u32,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Expands every macro invocation in `program` in place and returns the
+/// errors found. Invocations that fail to expand are left untouched.
+pub fn expand(program: &mut Program) -> Vec<Diagnostic> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        expansions: 0,
+        diagnostics: Vec::new(),
+    };
+
+    for node in &program.0 {
+        if let Node::Stmt(Stmt::Decl(decl)) = node
+            && let Decl::Macro(mac) = decl.as_ref()
+            && let TokenType::Ident(name) = &mac.name.kind
+        {
+            expander.macros.insert(
+                name.clone(),
+                MacroDef {
+                    params: mac.params.clone(),
+                    body: mac.body.clone(),
+                    span: mac.name.span,
+                },
+            );
+        }
+    }
+
+    for node in &mut program.0 {
+        expander.node(node, 0);
+    }
+
+    expander.diagnostics
+}
+
+impl Expander {
+    fn node(&mut self, node: &mut Node, depth: usize) {
+        match node {
+            Node::Stmt(Stmt::Decl(decl)) => self.decl(decl, depth),
+            Node::Stmt(Stmt::ExprStmt(expr)) | Node::Expr(expr) => self.expr(expr, depth),
+            Node::Error(_) | Node::EOF => {}
+        }
+    }
+
+    fn decl(&mut self, decl: &mut Decl, depth: usize) {
+        match decl {
+            Decl::Variable(var) => self.expr(&mut var.expr, depth),
+            Decl::Function(func) => self.expr(&mut func.body, depth),
+            Decl::Interface(interface) => {
+                for method in &mut interface.methods {
+                    if let Some(body) = &mut method.body {
+                        self.expr(body, depth);
+                    }
+                }
+            }
+            Decl::Impl(imp) => {
+                for node in &mut imp.members.nodes {
+                    self.node(node, depth);
+                }
+            }
+            Decl::Struct(_) | Decl::Enum(_) | Decl::Macro(_) | Decl::Derive(_) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr, depth: usize) {
+        match expr {
+            Expr::Atom(_) => {}
+            Expr::BinOp(op) => {
+                self.expr(&mut op.lhs, depth);
+                self.expr(&mut op.rhs, depth);
+            }
+            Expr::UnaryOp(op) => self.expr(&mut op.expr, depth),
+            Expr::Assign(assign) => {
+                self.expr(&mut assign.target, depth);
+                self.expr(&mut assign.value, depth);
+            }
+            Expr::Pipe(pipe) => {
+                self.expr(&mut pipe.lhs, depth);
+                self.expr(&mut pipe.rhs, depth);
+            }
+            Expr::Field(field) => self.expr(&mut field.base, depth),
+            Expr::Block(block) => {
+                for node in &mut block.nodes {
+                    self.node(node, depth);
+                }
+            }
+            Expr::Call(call) => {
+                self.expr(&mut call.callee, depth);
+                for arg in &mut call.args {
+                    self.expr(arg, depth);
+                }
+            }
+            Expr::StructLiteral(lit) => {
+                for (_, value) in &mut lit.fields {
+                    self.expr(value, depth);
+                }
+            }
+            Expr::Switch(switch) => {
+                self.expr(&mut switch.scrutinee, depth);
+                for arm in &mut switch.arms {
+                    self.expr(&mut arm.body, depth);
+                }
+            }
+            Expr::Return(ret) => {
+                if let Some(value) = &mut ret.value {
+                    self.expr(value, depth);
+                }
+            }
+            Expr::Variant(variant) => match &mut variant.payload {
+                VariantArgs::None => {}
+                VariantArgs::Args(args) => {
+                    for arg in args {
+                        self.expr(arg, depth);
+                    }
+                }
+                VariantArgs::Fields(fields) => {
+                    for (_, value) in fields {
+                        self.expr(value, depth);
+                    }
+                }
+            },
+            Expr::MacroCall(call) => {
+                if let Some(mut expanded) = self.expand_call(call, depth) {
+                    // The expansion may itself contain invocations.
+                    self.expr(&mut expanded, depth + 1);
+                    *expr = expanded;
+                }
+            }
+        }
+    }
+
+    /// Substitutes and parses a single invocation.
+    fn expand_call(&mut self, call: &MacroCall, depth: usize) -> Option<Expr> {
+        let TokenType::Ident(name) = &call.name.kind else {
+            return None;
+        };
+
+        let Some(mac) = self.macros.get(name) else {
+            self.diagnostics.push(
+                Diagnostic::error(format!("cannot find macro `{name}`"))
+                    .with_code(codes::UNKNOWN_MACRO)
+                    .with_primary(call.name.span, "not found")
+                    .with_help(format!(
+                        "declare it with `const {name} : macro(...) = {{ ... }};`"
+                    )),
+            );
+            return None;
+        };
+
+        if depth >= RECURSION_LIMIT {
+            self.diagnostics.push(
+                Diagnostic::error(format!("recursion limit reached while expanding `{name}!`"))
+                    .with_code(codes::MACRO_RECURSION_LIMIT)
+                    .with_primary(call.span, "expanded too many times")
+                    .with_note(format!(
+                        "macros may expand into other macros at most {RECURSION_LIMIT} levels deep"
+                    )),
+            );
+            return None;
+        }
+
+        if mac.params.len() != call.args.len() {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            self.diagnostics.push(
+                Diagnostic::error(format!(
+                    "macro `{name}` takes {} argument{} but {} {} supplied",
+                    mac.params.len(),
+                    plural(mac.params.len()),
+                    call.args.len(),
+                    if call.args.len() == 1 { "was" } else { "were" },
+                ))
+                .with_code(codes::MACRO_ARG_COUNT)
+                .with_primary(call.span, "wrong number of arguments")
+                .with_secondary(mac.span, "macro defined here"),
+            );
+            return None;
+        }
+
+        self.expansions += 1;
+        let tokens = substitute(mac, &call.args, self.expansions, call.span);
+
+        let (expr, diags) = Parser::from_tokens(tokens).expression();
+        self.diagnostics.extend(
+            diags
+                .into_iter()
+                .map(|d| d.with_secondary(call.span, "in this macro invocation")),
+        );
+        expr
+    }
+}
+
+/// Builds the token stream of one expansion: the body with its own bindings
+/// renamed and every parameter replaced by the matching argument.
+fn substitute(mac: &MacroDef, args: &[Vec<Token>], expansion: u32, call: Span) -> Vec<Token> {
+    let params: HashMap<&str, &[Token]> = mac
+        .params
+        .iter()
+        .zip(args)
+        .filter_map(|(param, arg)| match &param.kind {
+            TokenType::Ident(name) => Some((name.as_str(), arg.as_slice())),
+            _ => None,
+        })
+        .collect();
+    let introduced = introduced_names(&mac.body);
+
+    let mut tokens = Vec::new();
+    let mut prev: Option<&TokenType> = None;
+    for token in &mac.body {
+        let after_dot = prev == Some(&TokenType::Dot);
+        prev = Some(&token.kind);
+
+        if let TokenType::Ident(name) = &token.kind
+            && !after_dot
+        {
+            if let Some(arg) = params.get(name.as_str()) {
+                // Keep multi-token arguments together: `twice!(1 + 2)`
+                // must not expand to `1 + 2 * 2`.
+                if arg.len() > 1 {
+                    let span = arg[0].span.to(arg[arg.len() - 1].span);
+                    tokens.push(Token {
+                        kind: TokenType::OParen,
+                        span,
+                    });
+                    tokens.extend(arg.iter().cloned());
+                    tokens.push(Token {
+                        kind: TokenType::CParen,
+                        span,
+                    });
+                } else {
+                    tokens.extend(arg.iter().cloned());
+                }
+                continue;
+            }
+            if introduced.contains(name.as_str()) {
+                tokens.push(Token {
+                    kind: TokenType::Ident(format!("{name}#{expansion}")),
+                    span: Span {
+                        expansion,
+                        ..token.span
+                    },
+                });
+                continue;
+            }
+        }
+
+        tokens.push(Token {
+            kind: token.kind.clone(),
+            span: Span {
+                expansion,
+                ..token.span
+            },
+        });
+    }
+
+    tokens.push(Token {
+        kind: TokenType::EOF,
+        span: call,
+    });
+    tokens
+}
+
+/// Names bound by the body itself: `let x`, `mut x`, `const x`, `static x` and `|x|`.
+fn introduced_names(body: &[Token]) -> HashSet<&str> {
+    let mut names = HashSet::new();
+    for window in body.windows(2) {
+        if let [keyword, name] = window
+            && matches!(
+                keyword.kind,
+                TokenType::Let | TokenType::Mut | TokenType::Const | TokenType::Static
+            )
+            && let TokenType::Ident(name) = &name.kind
+        {
+            names.insert(name.as_str());
+        }
+    }
+    for window in body.windows(3) {
+        if let [open, name, close] = window
+            && open.kind == TokenType::VertBar
+            && close.kind == TokenType::VertBar
+            && let TokenType::Ident(name) = &name.kind
+        {
+            names.insert(name.as_str());
+        }
+    }
+    names
+}
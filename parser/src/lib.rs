@@ -1,42 +1,159 @@
 #![allow(dead_code, unused_imports)]
 use std::iter::Peekable;
 
-use anyhow::bail;
-
-use lexer::Lexer;
-use lexer::token::{Token, TokenType};
+use diagnostics::{Diagnostic, codes};
+use lexer::token::{Span, Token, TokenType};
+use lexer::{INVALID_NUMBER, Lexer, UNCLOSED_COMMENT, UNTERMINATED_STRING};
 use utils::*;
 
+pub mod expand;
 pub mod node;
 
 use node::*;
 
+pub type PResult<T> = Result<T, Diagnostic>;
+
+/// Where the parser gets its tokens from: source text, or tokens produced
+/// by a macro expansion.
+#[derive(Clone)]
+enum TokenSource<'a> {
+    Lexer(Lexer<'a>),
+    Tokens(std::vec::IntoIter<Token>),
+}
+
+impl Iterator for TokenSource<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TokenSource::Lexer(lexer) => lexer.next(),
+            TokenSource::Tokens(tokens) => tokens.next(),
+        }
+    }
+}
+
 pub struct Parser<'a> {
-    lexer: Peekable<Lexer<'a>>,
+    lexer: Peekable<TokenSource<'a>>,
+    /// Span of the most recently consumed token.
+    prev_span: Span,
+    /// Errors recovered from so far.
+    diagnostics: Vec<Diagnostic>,
+    /// Number of `{` consumed and not yet closed.
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         Self {
-            lexer: lexer.peekable(),
+            lexer: TokenSource::Lexer(lexer).peekable(),
+            prev_span: Span::default(),
+            diagnostics: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Creates a parser over already lexed tokens, e.g. a macro expansion.
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        Self {
+            lexer: TokenSource::Tokens(tokens.into_iter()).peekable(),
+            prev_span: Span::default(),
+            diagnostics: Vec::new(),
+            depth: 0,
         }
     }
 
+    /// Parses a single expression spanning all of the input. The expression
+    /// is only returned if no errors were found, including recovered ones.
+    pub fn expression(&mut self) -> (Option<Expr>, Vec<Diagnostic>) {
+        let expr = self.parse_expr(0).and_then(|expr| {
+            let end = self.peek_token();
+            if end.kind != TokenType::EOF {
+                return Err(self.unexpected(&end, "expected end of input", "unexpected token"));
+            }
+            Ok(expr)
+        });
+        match expr {
+            Ok(expr) if self.diagnostics.is_empty() => (Some(expr), Vec::new()),
+            Ok(_) => (None, std::mem::take(&mut self.diagnostics)),
+            Err(diag) => {
+                self.diagnostics.push(diag);
+                (None, std::mem::take(&mut self.diagnostics))
+            }
+        }
+    }
+
+    /// Parses the whole input.
+    ///
+    /// Parsing does not stop at the first error: each statement that fails to
+    /// parse is replaced by a `Node::Error` and the parser resumes at the next
+    /// statement. The returned program is therefore complete only if no
+    /// diagnostics were returned alongside it.
     #[allow(irrefutable_let_patterns)]
-    pub fn program(&mut self) -> anyhow::Result<Program> {
+    pub fn program(&mut self) -> (Program, Vec<Diagnostic>) {
         let mut program = Program::new();
 
-        while let x = self.next()? {
+        while let x = self.next_recovering() {
             if x == Node::EOF {
                 break;
             }
             program.0.push(x);
         }
 
-        Ok(program)
+        (program, std::mem::take(&mut self.diagnostics))
+    }
+
+    /// Parses the next statement. On failure the error is recorded, input is
+    /// skipped up to a likely statement boundary and a `Node::Error` covering
+    /// the skipped source is returned instead.
+    fn next_recovering(&mut self) -> Node {
+        let start = self.peek_token().span;
+        let before = self.prev_span;
+        let depth = self.depth;
+        match self.next() {
+            Ok(node) => node,
+            Err(diag) => {
+                self.diagnostics.push(diag);
+                self.synchronize(depth);
+                if self.prev_span == before {
+                    // Nothing was consumed; skip the offending token so we
+                    // cannot get stuck on it.
+                    self.bump();
+                }
+                Node::Error(start.to(self.prev_span))
+            }
+        }
+    }
+
+    /// Skips tokens until just after a `;`, or until a `}` closing the
+    /// enclosing block or a keyword that starts a declaration, counting only
+    /// those at `depth`, the nesting of the statement that failed. The
+    /// error may have been found inside `{ ... }` groups of the statement,
+    /// which are skipped up to their end.
+    fn synchronize(&mut self, depth: usize) {
+        loop {
+            match self.peek_token().kind {
+                TokenType::EOF => return,
+                TokenType::SemiColon if self.depth == depth => {
+                    self.bump();
+                    return;
+                }
+                TokenType::CBrack if self.depth <= depth => return,
+                TokenType::Const
+                | TokenType::Let
+                | TokenType::Mut
+                | TokenType::Static
+                | TokenType::Pub
+                    if self.depth == depth =>
+                {
+                    return;
+                }
+                _ => {}
+            }
+            self.bump();
+        }
     }
 
-    fn next(&mut self) -> anyhow::Result<Node> {
+    fn next(&mut self) -> PResult<Node> {
         let peeked_kind = self.lexer.peek().map(|t| &t.kind);
 
         match peeked_kind {
@@ -44,46 +161,56 @@ impl<'a> Parser<'a> {
             Some(TokenType::Const)
             | Some(TokenType::Let)
             | Some(TokenType::Mut)
-            | Some(TokenType::Pub) => Ok(Node::Stmt(Stmt::Decl(self.parse_decl()?))),
+            | Some(TokenType::Static)
+            | Some(TokenType::Pub) => Ok(Node::Stmt(Stmt::Decl(Box::new(self.parse_decl()?)))),
             Some(TokenType::Ident(_)) => {
-                if self
-                    .lexer
-                    .clone()
-                    .nth(1)
-                    .map_or(false, |t| t.kind == TokenType::PlusEql)
+                if self.peek_nth(1) == Some(TokenType::PlusEql)
+                    && self.peek_nth(2) == Some(TokenType::Impl)
                 {
-                    Ok(Node::Stmt(Stmt::Decl(self.parse_impl_decl()?)))
+                    Ok(Node::Stmt(Stmt::Decl(Box::new(self.parse_impl_decl()?))))
+                } else if self.peek_nth(1) == Some(TokenType::PlusEql)
+                    && matches!(self.peek_nth(2), Some(TokenType::Ident(_)))
+                    && self.peek_nth(3) == Some(TokenType::OBrack)
+                {
+                    Ok(Node::Stmt(Stmt::Decl(Box::new(self.parse_derive_decl()?))))
                 } else {
-                    // Assume it's an expression statement
-                    let expr = self.parse_expr(0)?;
-                    // Expect semicolon after expression statement
-                    self.expect_and_consume(
-                        TokenType::SemiColon,
-                        "Expected semicolon after expression",
-                    )?;
-                    Ok(Node::Stmt(Stmt::ExprStmt(expr)))
-                }
-            }
-            Some(TokenType::OBrack) | Some(TokenType::CBrack) => {
-                self.lexer.next(); // Consume the brace
+                    self.parse_expr_stmt()
+                }
+            }
+            Some(TokenType::CBrack) | Some(TokenType::SemiColon) => {
+                self.bump(); // Consume the brace or empty statement
                 self.next() // Recurse to get the next actual node
             }
-            Some(_) => {
-                // Assume it's an expression statement
-                let expr = self.parse_expr(0)?;
-                // Expect semicolon after expression statement
-                self.expect_and_consume(
-                    TokenType::SemiColon,
-                    "Expected semicolon after expression",
-                )?;
+            Some(_) => self.parse_expr_stmt(),
+            _ => Ok(Node::EOF), // Handle EOF when peek() returns None
+        }
+    }
+
+    /// Parses an expression statement.
+    ///
+    /// An expression directly followed by the `}` closing its block is the
+    /// block's value and becomes a `Node::Expr`. Otherwise a `;` is required,
+    /// except after block-like expressions (`{ ... }` and `switch`).
+    fn parse_expr_stmt(&mut self) -> PResult<Node> {
+        let expr = self.parse_expr(0)?;
+        match self.lexer.peek().map(|t| &t.kind) {
+            Some(TokenType::SemiColon) => {
+                self.bump();
+                Ok(Node::Stmt(Stmt::ExprStmt(expr)))
+            }
+            Some(TokenType::CBrack) => Ok(Node::Expr(expr)),
+            _ if is_block_like(&expr) => Ok(Node::Stmt(Stmt::ExprStmt(expr))),
+            _ => {
+                self.expect_semicolon("expected `;` after expression")?;
                 Ok(Node::Stmt(Stmt::ExprStmt(expr)))
             }
-            _ => Ok(Node::EOF), // Handle EOF when peek() returns None
         }
     }
 
-    fn parse_expr(&mut self, precedence: u8) -> anyhow::Result<Expr> {
-        let mut lhs = self.parse_atom()?;
+    /// Parses an expression whose infix operators all bind tighter than
+    /// `precedence` (see `get_precedence`).
+    fn parse_expr(&mut self, precedence: u8) -> PResult<Expr> {
+        let mut lhs = self.parse_prefix()?;
 
         while let Some(op) = self.lexer.peek() {
             let op_precedence = get_precedence(&op.kind);
@@ -91,227 +218,864 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let op = self.lexer.next().unwrap();
-            let rhs = self.parse_expr(op_precedence)?;
-
-            lhs = Expr::BinOp(Box::new(BinOp { op, lhs, rhs }));
+            let op = self.bump();
+            // Assignment is right-associative: `a = b = c` is `a = (b = c)`.
+            let rhs_precedence = if is_assignment(&op.kind) {
+                op_precedence - 1
+            } else {
+                op_precedence
+            };
+            let rhs = self.parse_expr(rhs_precedence)?;
+            let span = lhs.span().to(rhs.span());
+
+            lhs = match op.kind {
+                ref kind if is_assignment(kind) => {
+                    check_assign_target(&lhs, &op)?;
+                    Expr::Assign(Box::new(Assign {
+                        op,
+                        target: lhs,
+                        value: rhs,
+                        span,
+                    }))
+                }
+                TokenType::Pipe => Expr::Pipe(Box::new(Pipe { lhs, rhs, span })),
+                _ => Expr::BinOp(Box::new(BinOp { op, lhs, rhs, span })),
+            };
         }
 
         Ok(lhs)
     }
 
-    fn parse_atom(&mut self) -> anyhow::Result<Expr> {
-        let token = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected token, found none"))?;
+    /// Parses prefix operators, then an atom followed by any calls and
+    /// member accesses.
+    fn parse_prefix(&mut self) -> PResult<Expr> {
+        match self.lexer.peek().map(|t| &t.kind) {
+            Some(TokenType::Minus)
+            | Some(TokenType::Bang)
+            | Some(TokenType::Amp)
+            | Some(TokenType::Star) => {
+                let op = self.bump();
+                let mutable = op.kind == TokenType::Amp && self.eat_mut();
+                let expr = self.parse_expr(PREFIX_PRECEDENCE)?;
+                let span = op.span.to(expr.span());
+                Ok(Expr::UnaryOp(Box::new(UnaryOp {
+                    op,
+                    mutable,
+                    expr,
+                    span,
+                })))
+            }
+            Some(TokenType::Pound) => self.parse_annotation(),
+            _ => {
+                let atom = self.parse_atom()?;
+                // `{ ... }` and `switch` never take postfix operators, so a
+                // switch arm body like `{ ... }` can be followed by `.Variant`.
+                if is_block_like(&atom) {
+                    Ok(atom)
+                } else {
+                    self.parse_postfix(atom)
+                }
+            }
+        }
+    }
+
+    fn parse_postfix(&mut self, mut expr: Expr) -> PResult<Expr> {
+        loop {
+            match self.lexer.peek().map(|t| t.kind.clone()) {
+                Some(TokenType::OParen) => {
+                    expr = self.parse_function_call(expr)?;
+                }
+                Some(TokenType::Dot) if matches!(self.peek_nth(1), Some(TokenType::Ident(_))) => {
+                    self.bump(); // Consume `.`
+                    let name = self.bump();
+                    let span = expr.span().to(name.span);
+                    expr = Expr::Field(Box::new(Field {
+                        base: expr,
+                        name,
+                        span,
+                    }));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_atom(&mut self) -> PResult<Expr> {
+        // A `}` here closes a block around the expression, so leave it for
+        // that block to consume.
+        let next = self.peek_token();
+        if next.kind == TokenType::CBrack {
+            return Err(self.unexpected(&next, "expected expression", "expected expression"));
+        }
+        let token = self.bump();
 
         match token.kind {
-            TokenType::Number(_) => Ok(Expr::Atom(token)),
+            TokenType::Int(_) | TokenType::Float(_) => Ok(Expr::Atom(token)),
             TokenType::String(_) => Ok(Expr::Atom(token)), // Added for string literals
             TokenType::OParen => self.parse_paren(),
-            TokenType::OBrack => self.parse_block_expr(),
+            TokenType::OBrack => self.parse_block_expr(token),
+            TokenType::Dot => match self.lexer.peek().map(|t| &t.kind) {
+                Some(TokenType::OBrack) => self.parse_struct_literal(token),
+                Some(TokenType::Ident(_)) => self.parse_variant_literal(token),
+                _ => {
+                    let next = self.bump();
+                    Err(self
+                        .unexpected(&next, "expected `{` or variant name after `.`", "expected `{`")
+                        .with_help(
+                            "struct literals are written `.{ .field = value }`, enum variants `.Variant`",
+                        ))
+                }
+            },
+            TokenType::Ident(_)
+                if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Bang)
+                    && self.peek_nth(1) == Some(TokenType::OParen) =>
+            {
+                self.parse_macro_call(token)
+            }
+            // Calls on identifiers are handled by `parse_postfix`
+            TokenType::Ident(_) => Ok(Expr::Atom(token)),
+            TokenType::Switch => self.parse_switch(token),
+            TokenType::Return => {
+                let value = match self.lexer.peek().map(|t| &t.kind) {
+                    Some(TokenType::SemiColon)
+                    | Some(TokenType::Comma)
+                    | Some(TokenType::CBrack)
+                    | Some(TokenType::CParen)
+                    | Some(TokenType::EOF) => None,
+                    _ => Some(self.parse_expr(0)?),
+                };
+                let span = value
+                    .as_ref()
+                    .map_or(token.span, |v| token.span.to(v.span()));
+                Ok(Expr::Return(Box::new(Return { value, span })))
+            }
+            _ => Err(self.unexpected(&token, "expected expression", "expected expression")),
+        }
+    }
+
+    fn parse_switch(&mut self, switch_token: Token) -> PResult<Expr> {
+        // `switch` was already consumed by `parse_atom`.
+        let scrutinee = self.parse_expr(0)?;
+        let open = self.expect_and_consume(TokenType::OBrack, "expected `{` after switch value")?;
+
+        let mut arms = Vec::new();
+        while self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind != TokenType::CBrack && t.kind != TokenType::EOF)
+        {
+            let pattern = self.parse_pattern()?;
+            self.expect_and_consume(TokenType::FatRightArrow, "expected `=>` after pattern")?;
+            let body = self.parse_expr(0)?;
+            let span = pattern.span().to(body.span());
+            let block_body = is_block_like(&body);
+            arms.push(SwitchArm {
+                pattern,
+                body,
+                span,
+            });
+
+            match self.lexer.peek().map(|t| &t.kind) {
+                Some(TokenType::Comma) => {
+                    self.bump();
+                }
+                Some(TokenType::CBrack) => break,
+                // Arms with a block body don't need a trailing comma
+                _ if block_body => {}
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `}` after switch arm",
+                        "expected `,` or `}`",
+                    ));
+                }
+            }
+        }
+
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
+
+        Ok(Expr::Switch(Box::new(Switch {
+            scrutinee,
+            arms,
+            span: switch_token.span.to(close.span),
+        })))
+    }
+
+    fn parse_pattern(&mut self) -> PResult<Pattern> {
+        let token = self.bump();
+        match &token.kind {
+            TokenType::String(_) | TokenType::Int(_) | TokenType::Float(_) => {
+                Ok(Pattern::Literal(token))
+            }
+            TokenType::Ident(name) if name == "_" => Ok(Pattern::Wildcard(token)),
             TokenType::Dot => {
-                info!("Current token in parse_atom: Dot");
-                info!("After Dot, peeked: {:?}", self.lexer.peek());
-                if self
+                let name = self.expect_ident("expected variant name after `.`")?;
+                let capture = if self
                     .lexer
                     .peek()
-                    .map_or(false, |t| t.kind == TokenType::OBrack)
+                    .is_some_and(|t| t.kind == TokenType::Colon)
                 {
-                    self.parse_struct_literal()
+                    self.bump(); // Consume `:`
+                    Some(self.parse_capture()?)
                 } else {
-                    bail!("Unhandled token in parse_atom: Dot (not a struct literal)")
+                    None
+                };
+                let end = capture.as_ref().map_or(name.span, |c| c.span());
+                Ok(Pattern::Variant {
+                    span: token.span.to(end),
+                    name,
+                    capture,
+                })
+            }
+            _ => Err(self
+                .unexpected(&token, "expected pattern", "expected pattern")
+                .with_help("patterns are literals, `.Variant`, `.Variant : |x|` or `_`")),
+        }
+    }
+
+    fn parse_capture(&mut self) -> PResult<Capture> {
+        let open = self.expect_and_consume(TokenType::VertBar, "expected `|` to start capture")?;
+
+        let capture = if self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind == TokenType::OBrack)
+        {
+            let obrack = self.bump();
+            let mut fields = Vec::new();
+            while self
+                .lexer
+                .peek()
+                .is_some_and(|t| t.kind != TokenType::CBrack)
+            {
+                fields.push(self.expect_ident("expected field name in capture")?);
+                match self.lexer.peek().map(|t| &t.kind) {
+                    Some(TokenType::Comma) => {
+                        self.bump();
+                    }
+                    Some(TokenType::CBrack) => break,
+                    _ => {
+                        let found = self.peek_token();
+                        return Err(self.unexpected(
+                            &found,
+                            "expected `,` or `}` after captured field",
+                            "expected `,` or `}`",
+                        ));
+                    }
                 }
             }
-            TokenType::Ident(_) => {
-                // If the next token is '(', it's a function call
-                if self
-                    .lexer
-                    .peek()
-                    .map_or(false, |t| t.kind == TokenType::OParen)
-                {
-                    self.parse_function_call(token) // Pass the identifier as the callee
-                } else {
-                    // Otherwise, it's just an identifier atom
-                    Ok(Expr::Atom(token))
+            let cbrack = self.expect_closing(TokenType::CBrack, &obrack)?;
+            Capture::Destructure(fields, obrack.span.to(cbrack.span))
+        } else {
+            Capture::Binding(self.expect_ident("expected name to bind")?)
+        };
+
+        self.expect_closing(TokenType::VertBar, &open)?;
+        Ok(capture)
+    }
+
+    /// Parses `macro(params) = { body }` after the `:` of a declaration.
+    /// The body is kept as raw tokens and parsed once expanded.
+    fn parse_macro_definition(
+        &mut self,
+        start: Span,
+        storage: Storage,
+        name: Token,
+    ) -> PResult<Macro> {
+        // consume `macro`
+        self.expect_and_consume(TokenType::Macro, "expected `macro`")?;
+
+        // The parameter list is optional: `const name : macro = { ... };`
+        let mut params = Vec::new();
+        if self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind == TokenType::OParen)
+        {
+            params = self.parse_macro_params()?;
+        }
+
+        self.expect_and_consume(TokenType::Eql, "expected `=` before macro body")?;
+        let body = match self.lexer.peek().map(|t| &t.kind) {
+            Some(TokenType::OBrack) | Some(TokenType::OParen) => self.parse_token_tree()?,
+            _ => {
+                let found = self.peek_token();
+                return Err(self
+                    .unexpected(&found, "expected macro body", "expected `{` or `(`")
+                    .with_help("macro bodies are delimited: `= { ... }` or `= ( ... )`"));
+            }
+        };
+        let end = body.last().map_or(start, |t| t.span);
+
+        Ok(Macro {
+            storage,
+            name,
+            params,
+            body,
+            span: start.to(end),
+        })
+    }
+
+    /// Parses `(a, b, ...)`, the parameter names of a macro.
+    fn parse_macro_params(&mut self) -> PResult<Vec<Token>> {
+        let open = self.bump();
+        let mut params = Vec::new();
+        while self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind != TokenType::CParen)
+        {
+            params.push(self.expect_ident("expected macro parameter name")?);
+            match self.lexer.peek().map(|t| &t.kind) {
+                Some(TokenType::Comma) => {
+                    self.bump();
+                }
+                Some(TokenType::CParen) => break,
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `)` after macro parameter",
+                        "expected `,` or `)`",
+                    ));
+                }
+            }
+        }
+        self.expect_closing(TokenType::CParen, &open)?;
+        Ok(params)
+    }
+
+    /// Parses `name!(arg, ...)` where each argument is kept as raw tokens.
+    fn parse_macro_call(&mut self, name: Token) -> PResult<Expr> {
+        self.expect_and_consume(TokenType::Bang, "expected `!`")?;
+        let open = self.expect_and_consume(TokenType::OParen, "expected `(` after `!`")?;
+
+        let mut args = Vec::new();
+        let mut current = Vec::new();
+        loop {
+            match self.lexer.peek().map(|t| &t.kind) {
+                Some(TokenType::CParen) => break,
+                Some(TokenType::Comma) => {
+                    self.bump();
+                    args.push(std::mem::take(&mut current));
                 }
+                Some(TokenType::OParen) | Some(TokenType::OBrack) | Some(TokenType::OSquare) => {
+                    current.extend(self.parse_token_tree()?);
+                }
+                Some(TokenType::EOF) | None => break,
+                _ => current.push(self.bump()),
             }
-            t => bail!("Unhandled token in parse_atom: {:?}", t),
         }
+        if !current.is_empty() || !args.is_empty() {
+            args.push(current);
+        }
+        let close = self.expect_closing(TokenType::CParen, &open)?;
+
+        Ok(Expr::MacroCall(MacroCall {
+            span: name.span.to(close.span),
+            name,
+            args,
+        }))
     }
 
-    fn parse_paren(&mut self) -> anyhow::Result<Expr> {
-        // Consume `(`.
-        self.lexer.next();
+    /// Consumes a delimited group such as `{ ... }` or `( ... )`, including
+    /// nested groups, and returns its tokens with the delimiters.
+    fn parse_token_tree(&mut self) -> PResult<Vec<Token>> {
+        let open = self.bump();
+        let mut stack = vec![open.clone()];
+        let mut tokens = vec![open];
+        while let Some(top) = stack.last() {
+            let closing = match top.kind {
+                TokenType::OParen => TokenType::CParen,
+                TokenType::OBrack => TokenType::CBrack,
+                _ => TokenType::CSquare,
+            };
+            let token = self.peek_token();
+            match token.kind {
+                TokenType::EOF => {
+                    let open = top.clone();
+                    return Err(self
+                        .unexpected(
+                            &token,
+                            &format!("expected {}", describe(&closing)),
+                            &format!("expected {}", describe(&closing)),
+                        )
+                        .with_secondary(open.span, "unclosed delimiter"));
+                }
+                TokenType::OParen | TokenType::OBrack | TokenType::OSquare => {
+                    stack.push(token);
+                }
+                ref kind if *kind == closing => {
+                    stack.pop();
+                }
+                TokenType::CParen | TokenType::CBrack | TokenType::CSquare => {
+                    let open = top.clone();
+                    return Err(self
+                        .unexpected(
+                            &token,
+                            "mismatched closing delimiter",
+                            &format!("expected {}", describe(&closing)),
+                        )
+                        .with_secondary(open.span, "unclosed delimiter"));
+                }
+                _ => {}
+            }
+            tokens.push(self.bump());
+        }
+        Ok(tokens)
+    }
+
+    fn parse_paren(&mut self) -> PResult<Expr> {
+        // `(` was already consumed by `parse_atom`.
         let expr = self.parse_expr(0)?;
         // Consume `)`.
-        self.expect_and_consume(TokenType::CParen, "Expected closing parenthesis")?;
+        self.expect_and_consume(TokenType::CParen, "expected closing parenthesis")?;
         Ok(expr)
     }
 
-    fn parse_block_expr(&mut self) -> anyhow::Result<Expr> {
-        // Consume `{`.
-        self.lexer.next();
+    fn parse_block_expr(&mut self, open: Token) -> PResult<Expr> {
+        // `{` was already consumed by `parse_atom`.
         let mut nodes = Vec::new();
         while let Some(token) = self.lexer.peek() {
-            if token.kind == TokenType::CBrack {
+            if token.kind == TokenType::CBrack || token.kind == TokenType::EOF {
                 break;
             }
-            nodes.push(self.next()?);
+            nodes.push(self.next_recovering());
         }
         // Consume `}`.
-        self.expect_and_consume(TokenType::CBrack, "Expected closing brace")?;
-        Ok(Expr::Block(Block(nodes)))
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
+        Ok(Expr::Block(Block {
+            nodes,
+            span: open.span.to(close.span),
+        }))
     }
 
-    fn parse_type(&mut self) -> anyhow::Result<Type> {
-        let type_token = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected type, found none"))?;
+    fn parse_type(&mut self) -> PResult<Type> {
+        let type_token = self.bump();
         match type_token.kind {
             TokenType::Ident(_) => Ok(Type::Identifier(type_token)),
+            TokenType::Amp => {
+                let mutable = self.eat_mut();
+                let inner = self.parse_type()?;
+                let span = type_token.span.to(inner.span());
+                Ok(Type::Ref(Box::new(inner), mutable, span))
+            }
+            TokenType::Fn => {
+                let open = self.expect_and_consume(TokenType::OParen, "expected `(` after `fn`")?;
+                let mut params = Vec::new();
+                while self
+                    .lexer
+                    .peek()
+                    .is_some_and(|t| t.kind != TokenType::CParen)
+                {
+                    params.push(self.parse_type()?);
+
+                    match self.lexer.peek().map(|t| &t.kind) {
+                        Some(TokenType::Comma) => {
+                            self.bump(); // Consume comma
+                        }
+                        Some(TokenType::CParen) => break,
+                        _ => {
+                            let found = self.peek_token();
+                            return Err(self.unexpected(
+                                &found,
+                                "expected `,` or `)` after parameter type",
+                                "expected `,` or `)`",
+                            ));
+                        }
+                    }
+                }
+                self.expect_closing(TokenType::CParen, &open)?;
+                self.expect_and_consume(
+                    TokenType::RightArrow,
+                    "expected `->` after parameter types",
+                )
+                .map_err(|d| {
+                    d.with_help("functions without a result return `void`: `fn() -> void`")
+                })?;
+                let ret = self.parse_type()?;
+                let span = type_token.span.to(ret.span());
+                Ok(Type::Fn(params, Box::new(ret), span))
+            }
             TokenType::OParen => {
                 let mut elements = Vec::new();
                 while self
                     .lexer
                     .peek()
-                    .map_or(false, |t| t.kind != TokenType::CParen)
+                    .is_some_and(|t| t.kind != TokenType::CParen)
                 {
                     elements.push(Box::new(self.parse_type()?));
 
                     match self.lexer.peek().map(|t| &t.kind) {
                         Some(TokenType::Comma) => {
-                            self.lexer.next(); // Consume comma
+                            self.bump(); // Consume comma
                         }
                         Some(TokenType::CParen) => {
                             // Closing parenthesis, break loop
                             break;
                         }
-                        t => bail!("Expected ',' or ')' after parameter, found {:?}", t),
+                        _ => {
+                            let found = self.peek_token();
+                            return Err(self.unexpected(
+                                &found,
+                                "expected `,` or `)` after tuple element",
+                                "expected `,` or `)`",
+                            ));
+                        }
                     }
                 }
 
-                self.expect_and_consume(TokenType::CParen, "Expected ')'")?;
+                let close = self.expect_closing(TokenType::CParen, &type_token)?;
 
-                Ok(Type::Touple(elements))
+                Ok(Type::Touple(elements, type_token.span.to(close.span)))
             }
-            _ => bail!("Expected identifier for type, found {:?}", type_token.kind),
+            _ => Err(self.unexpected(&type_token, "expected type", "expected type")),
         }
     }
 
-    fn parse_parameter(&mut self) -> anyhow::Result<Parameter> {
-        let name = self
+    fn parse_parameter(&mut self) -> PResult<Parameter> {
+        // `self`, `&self` and `&mut self` receivers carry their own type
+        let self_at = match self.peek_nth(1) {
+            Some(TokenType::Mut) => 2,
+            _ => 1,
+        };
+        if let Some(TokenType::Amp) = self.lexer.peek().map(|t| &t.kind)
+            && self.peek_nth(self_at) == Some(TokenType::Ident("self".to_string()))
+        {
+            let amp = self.bump();
+            let mutable = self.eat_mut();
+            let name = self.bump();
+            let span = amp.span.to(name.span);
+            let self_ty = Type::Identifier(self_type(&name));
+            return Ok(Parameter {
+                param_type: Type::Ref(Box::new(self_ty), mutable, span),
+                name,
+                span,
+            });
+        }
+        if self
             .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected parameter name, found none"))?;
-        match name.kind {
-            TokenType::Ident(_) => {}
-            _ => bail!(
-                "Expected identifier for parameter name, found {:?}",
-                name.kind
-            ),
+            .peek()
+            .is_some_and(|t| t.kind == TokenType::Ident("self".to_string()))
+            && self.peek_nth(1) != Some(TokenType::Colon)
+        {
+            let name = self.bump();
+            return Ok(Parameter {
+                param_type: Type::Identifier(self_type(&name)),
+                span: name.span,
+                name,
+            });
         }
 
+        let name = self.expect_ident("expected parameter name")?;
+
         // Consume ':'
-        self.expect_and_consume(TokenType::Colon, "Expected ':' after parameter name")?;
+        self.expect_and_consume(TokenType::Colon, "expected `:` after parameter name")?;
 
         let param_type = self.parse_type()?;
+        let span = name.span.to(param_type.span());
 
-        Ok(Parameter { name, param_type })
+        Ok(Parameter {
+            name,
+            param_type,
+            span,
+        })
     }
 
-    fn parse_struct_definition(&mut self, name: Token) -> anyhow::Result<Struct> {
+    fn parse_struct_definition(
+        &mut self,
+        start: Span,
+        storage: Storage,
+        name: Token,
+    ) -> PResult<Struct> {
         // consume `struct`
-        self.expect_and_consume(TokenType::Struct, "Expected 'struct'")?;
+        self.expect_and_consume(TokenType::Struct, "expected `struct`")?;
 
         // consume `=`
-        self.expect_and_consume(TokenType::Eql, "Expected '='")?;
+        self.expect_and_consume(TokenType::Eql, "expected `=` after `struct`")?;
+
+        let (members, close) = self.parse_struct_members()?;
+
+        Ok(Struct {
+            storage,
+            name,
+            members,
+            span: start.to(close.span),
+        })
+    }
 
+    /// Parses `{ name: type, ... }` and returns the members together with
+    /// the closing `}`.
+    fn parse_struct_members(&mut self) -> PResult<(Vec<Parameter>, Token)> {
         // consume '{'
-        self.expect_and_consume(TokenType::OBrack, "Expected '}")?;
+        let open =
+            self.expect_and_consume(TokenType::OBrack, "expected `{` to open struct body")?;
 
         let mut members = Vec::new();
         while self
             .lexer
             .peek()
-            .map_or(false, |t| t.kind != TokenType::CBrack)
+            .is_some_and(|t| t.kind != TokenType::CBrack)
         {
             members.push(self.parse_parameter()?);
 
             match self.lexer.peek().map(|t| &t.kind) {
                 Some(TokenType::Comma) => {
-                    self.lexer.next();
+                    self.bump();
                 }
                 Some(TokenType::CBrack) => {
                     break;
                 }
-                t => bail!("Expected ',' or ')' after parameter, found {:?}", t),
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `}` after struct member",
+                        "expected `,` or `}`",
+                    ));
+                }
             }
         }
 
-        self.expect_and_consume(TokenType::CBrack, "Expected '}'")?;
-        // self.expect_and_consume(TokenType::SemiColon, "Expected ';'")?;
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
 
-        Ok(Struct {
-            name: name,
-            members: members,
+        Ok((members, close))
+    }
+
+    fn parse_enum_definition(
+        &mut self,
+        start: Span,
+        storage: Storage,
+        name: Token,
+    ) -> PResult<Enum> {
+        // consume `enum`
+        self.expect_and_consume(TokenType::Enum, "expected `enum`")?;
+
+        // `=` is optional: both `enum { ... }` and `enum = { ... }` are accepted
+        if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Eql) {
+            self.bump();
+        }
+
+        let open = self.expect_and_consume(TokenType::OBrack, "expected `{` to open enum body")?;
+
+        let mut variants = Vec::new();
+        while self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind != TokenType::CBrack && t.kind != TokenType::EOF)
+        {
+            variants.push(self.parse_variant()?);
+
+            match self.lexer.peek().map(|t| &t.kind) {
+                Some(TokenType::Comma) => {
+                    self.bump();
+                }
+                Some(TokenType::CBrack) => break,
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `}` after enum variant",
+                        "expected `,` or `}`",
+                    ));
+                }
+            }
+        }
+
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
+
+        Ok(Enum {
+            storage,
+            name,
+            variants,
+            span: start.to(close.span),
+        })
+    }
+
+    fn parse_variant(&mut self) -> PResult<Variant> {
+        let name = self.expect_ident("expected variant name")?;
+
+        if self.lexer.peek().is_none_or(|t| t.kind != TokenType::Colon) {
+            return Ok(Variant {
+                span: name.span,
+                name,
+                payload: VariantPayload::None,
+            });
+        }
+        self.bump(); // Consume `:`
+
+        let (payload, end) = match self.lexer.peek().map(|t| &t.kind) {
+            Some(TokenType::Struct) => {
+                self.bump();
+                if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Eql) {
+                    self.bump();
+                }
+                let (members, close) = self.parse_struct_members()?;
+                (VariantPayload::Struct(members), close.span)
+            }
+            _ => match self.parse_type()? {
+                Type::Touple(elements, span) => (
+                    VariantPayload::Touple(elements.into_iter().map(|t| *t).collect()),
+                    span,
+                ),
+                ty => {
+                    let span = ty.span();
+                    (VariantPayload::Type(ty), span)
+                }
+            },
+        };
+
+        Ok(Variant {
+            span: name.span.to(end),
+            name,
+            payload,
         })
     }
 
     fn parse_function_definition(
         &mut self,
         visibility: Option<Token>,
-        prot: Token,
+        storage: Storage,
         name: Token,
-    ) -> anyhow::Result<Function> {
+    ) -> PResult<Function> {
+        let (params, return_type) = self.parse_fn_signature()?;
+
+        // Consume `=`
+        self.expect_and_consume(TokenType::Eql, "expected `=` before function body")?;
+
+        // Parse function body (block expression)
+        let body = self.parse_expr(0)?;
+        let span = visibility
+            .as_ref()
+            .map_or(storage.span(), |v| v.span)
+            .to(body.span());
+
+        Ok(Function {
+            visibility,
+            storage,
+            name,
+            params,
+            return_type,
+            body,
+            span,
+        })
+    }
+
+    /// Parses `fn(params) -> type`.
+    fn parse_fn_signature(&mut self) -> PResult<(Vec<Parameter>, Type)> {
         // Consume `fn`
-        self.expect_and_consume(TokenType::Fn, "Expected 'fn'")?;
+        self.expect_and_consume(TokenType::Fn, "expected `fn`")?;
 
         // Consume `(`
-        self.expect_and_consume(TokenType::OParen, "Expected '('")?;
+        let open = self.expect_and_consume(TokenType::OParen, "expected `(` after `fn`")?;
 
         let mut params = Vec::new();
         // Parse parameters until `)`
         while self
             .lexer
             .peek()
-            .map_or(false, |t| t.kind != TokenType::CParen)
+            .is_some_and(|t| t.kind != TokenType::CParen)
         {
             params.push(self.parse_parameter()?);
 
             // Check for comma or closing parenthesis
             match self.lexer.peek().map(|t| &t.kind) {
                 Some(TokenType::Comma) => {
-                    self.lexer.next(); // Consume comma
+                    self.bump(); // Consume comma
                 }
                 Some(TokenType::CParen) => {
                     // Closing parenthesis, break loop
                     break;
                 }
-                _ => bail!("Expected ',' or ')' after parameter"),
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `)` after parameter",
+                        "expected `,` or `)`",
+                    ));
+                }
             }
         }
 
         // Consume `)`
-        self.expect_and_consume(TokenType::CParen, "Expected ')'")?;
+        self.expect_closing(TokenType::CParen, &open)?;
 
         // Consume `->`
-        self.expect_and_consume(TokenType::RightArrow, "Expected '->'")?;
+        self.expect_and_consume(TokenType::RightArrow, "expected `->` after parameter list")
+            .map_err(|d| d.with_help("functions without a result return `void`: `fn() -> void`"))?;
 
         // Parse return type
         let return_type = self.parse_type()?;
 
-        // Consume `=`
-        self.expect_and_consume(TokenType::Eql, "Expected '='")?;
+        Ok((params, return_type))
+    }
 
-        // Parse function body (block expression)
-        let body = self.parse_expr(0)?;
+    fn parse_interface_definition(
+        &mut self,
+        start: Span,
+        storage: Storage,
+        name: Token,
+    ) -> PResult<Interface> {
+        // consume `interface`
+        self.expect_and_consume(TokenType::Interface, "expected `interface`")?;
 
-        Ok(Function {
+        // consume `=`
+        self.expect_and_consume(TokenType::Eql, "expected `=` after `interface`")?;
+
+        let open =
+            self.expect_and_consume(TokenType::OBrack, "expected `{` to open interface body")?;
+
+        let mut methods = Vec::new();
+        while self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind != TokenType::CBrack && t.kind != TokenType::EOF)
+        {
+            methods.push(self.parse_interface_method()?);
+        }
+
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
+
+        Ok(Interface {
+            storage,
+            name,
+            methods,
+            span: start.to(close.span),
+        })
+    }
+
+    /// Parses `[pub] const name : fn(...) -> type [= body];` inside an
+    /// interface. Methods without a body must be provided by every type
+    /// deriving the interface.
+    fn parse_interface_method(&mut self) -> PResult<InterfaceMethod> {
+        let visibility = if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Pub) {
+            Some(self.bump())
+        } else {
+            None
+        };
+        let prot = self
+            .expect_and_consume(TokenType::Const, "expected `const` before interface method")
+            .map_err(|d| d.with_note("interfaces may only contain `const` function members"))?;
+        let name = self.expect_ident("expected method name")?;
+        self.expect_and_consume(TokenType::Colon, "expected `:` after method name")?;
+        let (params, return_type) = self.parse_fn_signature()?;
+
+        let body = if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Eql) {
+            self.bump();
+            Some(self.parse_expr(0)?)
+        } else {
+            None
+        };
+        let semi = self.expect_semicolon("expected `;` after interface method")?;
+
+        Ok(InterfaceMethod {
+            span: visibility
+                .as_ref()
+                .map_or(prot.span, |v| v.span)
+                .to(semi.span),
             visibility,
-            prot,
             name,
             params,
             return_type,
@@ -319,271 +1083,621 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_function_call(&mut self, callee: Token) -> anyhow::Result<Expr> {
+    fn parse_function_call(&mut self, callee: Expr) -> PResult<Expr> {
+        let (args, close) = self.parse_args()?;
+        let span = callee.span().to(close.span);
+
+        Ok(Expr::Call(Call {
+            callee: Box::new(callee),
+            args,
+            tail: None,
+            span,
+        }))
+    }
+
+    /// Parses `#tail call(...)`, which asks the compiler to prove that the
+    /// call is in tail position.
+    fn parse_annotation(&mut self) -> PResult<Expr> {
+        let pound = self.bump();
+        let name = self.bump();
+        if name.kind != TokenType::Ident("tail".to_string()) {
+            return Err(self
+                .unexpected(
+                    &name,
+                    "expected annotation name after `#`",
+                    "expected `tail`",
+                )
+                .with_help("the only annotation is `#tail`, written before a call"));
+        }
+        let span = pound.span.to(name.span);
+
+        match self.parse_prefix()? {
+            Expr::Call(mut call) => {
+                call.tail = Some(span);
+                call.span = span.to(call.span);
+                Ok(Expr::Call(call))
+            }
+            other => Err(Diagnostic::error("`#tail` must be followed by a call")
+                .with_code(codes::UNEXPECTED_TOKEN)
+                .with_primary(other.span(), "not a call")
+                .with_secondary(span, "annotation here")),
+        }
+    }
+
+    /// Parses `(arg, ...)` and returns the arguments together with the
+    /// closing `)`.
+    fn parse_args(&mut self) -> PResult<(Vec<Expr>, Token)> {
         // Consume `(`
-        self.expect_and_consume(TokenType::OParen, "Expected '('")?;
+        let open = self.expect_and_consume(TokenType::OParen, "expected `(`")?;
 
         let mut args = Vec::new();
         // Parse arguments until `)`
         while self
             .lexer
             .peek()
-            .map_or(false, |t| t.kind != TokenType::CParen)
+            .is_some_and(|t| t.kind != TokenType::CParen)
         {
             args.push(self.parse_expr(0)?);
 
             // Check for comma or closing parenthesis
             match self.lexer.peek().map(|t| &t.kind) {
                 Some(TokenType::Comma) => {
-                    self.lexer.next(); // Consume comma
+                    self.bump(); // Consume comma
                 }
                 Some(TokenType::CParen) => {
                     // Closing parenthesis, break loop
                     break;
                 }
-                _ => bail!("Expected ',' or ')' after argument"),
+                _ => {
+                    let found = self.peek_token();
+                    return Err(self.unexpected(
+                        &found,
+                        "expected `,` or `)` after argument",
+                        "expected `,` or `)`",
+                    ));
+                }
             }
         }
 
         // Consume `)`
-        self.expect_and_consume(TokenType::CParen, "Expected ')'")?;
+        let close = self.expect_closing(TokenType::CParen, &open)?;
+
+        Ok((args, close))
+    }
 
-        Ok(Expr::Call(Call { callee, args }))
+    fn parse_struct_literal(&mut self, dot_token: Token) -> PResult<Expr> {
+        // `.` was already consumed by `parse_atom`.
+        let (fields, cbrack_token) = self.parse_field_inits()?;
+
+        Ok(Expr::StructLiteral(StructLiteral {
+            fields,
+            span: dot_token.span.to(cbrack_token.span),
+        }))
     }
 
-    fn parse_struct_literal(&mut self) -> anyhow::Result<Expr> {
-        info!("Entering parse_struct_literal");
-        // Consume `.`
-        let dot_token = self.expect_and_consume(TokenType::Dot, "Expected '.'")?;
-        info!("Consumed: {:?}", dot_token.kind);
+    /// Parses `{ .name = value, ... }` and returns the fields together with
+    /// the closing `}`.
+    fn parse_field_inits(&mut self) -> PResult<(Vec<(Token, Expr)>, Token)> {
         // Consume `{`
-        let obrack_token = self.expect_and_consume(TokenType::OBrack, "Expected '{'")?;
-        info!("Consumed: {:?}", obrack_token.kind);
+        let obrack_token = self.expect_and_consume(TokenType::OBrack, "expected `{`")?;
 
         let mut fields = Vec::new();
         loop {
-            info!(
-                "Looping in parse_struct_literal. Peeked: {:?}",
-                self.lexer.peek()
-            );
             // If the next token is '}', we're done
             if self
                 .lexer
                 .peek()
-                .map_or(false, |t| t.kind == TokenType::CBrack)
+                .is_some_and(|t| t.kind == TokenType::CBrack)
             {
-                info!("Breaking loop: CBrack found");
                 break;
             }
 
             // Consume `.` for field name
-            let field_dot_token =
-                self.expect_and_consume(TokenType::Dot, "Expected '.' for struct field")?;
-            info!("Consumed: {:?}", field_dot_token.kind);
+            self.expect_and_consume(TokenType::Dot, "expected `.` before struct field name")
+                .map_err(|d| d.with_help("fields are initialized with `.name = value`"))?;
             // Consume field name
-            let field_name = self
-                .lexer
-                .next()
-                .ok_or(anyhow::anyhow!("Expected field name, found none"))?;
-            info!("Consumed: {:?}", field_name.kind);
-            match field_name.kind {
-                TokenType::Ident(_) => {} // Correctly matches Ident
-                _ => bail!(
-                    "Expected identifier for field name, found {:?}",
-                    field_name.kind
-                ),
-            }
+            let field_name = self.expect_ident("expected field name")?;
 
             // Consume `=
-            let eql_token =
-                self.expect_and_consume(TokenType::Eql, "Expected '=' after field name")?;
-            info!("Consumed: {:?}\n", eql_token.kind);
+            self.expect_and_consume(TokenType::Eql, "expected `=` after field name")?;
 
             // Parse field value
             let field_value = self.parse_expr(0)?;
-            info!("Parsed field value: {:?}\n", field_value);
 
             fields.push((field_name, field_value));
 
             // Check for comma
-            info!("Checking for comma. Peeked: {:?}\n", self.lexer.peek());
             if self
                 .lexer
                 .peek()
-                .map_or(false, |t| t.kind == TokenType::Comma)
+                .is_some_and(|t| t.kind == TokenType::Comma)
             {
-                let comma_token = self.lexer.next().unwrap(); // Consume comma
-                info!("Consumed: {:?}\n", comma_token.kind);
+                self.bump(); // Consume comma
             } else if self
                 .lexer
                 .peek()
-                .map_or(false, |t| t.kind == TokenType::CBrack)
+                .is_some_and(|t| t.kind == TokenType::CBrack)
             {
                 // If no comma, and it's a closing brace, break
-                info!("Breaking loop: CBrack found after field\n");
                 break;
             } else {
-                bail!("Expected ',' or '}}' after struct field");
+                let found = self.peek_token();
+                return Err(self.unexpected(
+                    &found,
+                    "expected `,` or `}` after struct field",
+                    "expected `,` or `}`",
+                ));
             }
         }
 
         // Consume `}`
-        let cbrack_token = self.expect_and_consume(TokenType::CBrack, "Expected '}'")?;
-        info!("Consumed: {:?}\n", cbrack_token.kind);
+        let cbrack_token = self.expect_closing(TokenType::CBrack, &obrack_token)?;
 
-        Ok(Expr::StructLiteral(StructLiteral { fields }))
+        Ok((fields, cbrack_token))
     }
 
-    fn parse_decl(&mut self) -> anyhow::Result<Decl> {
-        let visibility = if self
+    /// Parses the rest of `.Variant`, `.Variant(args)` or
+    /// `.Variant { .field = value }` after the leading `.`.
+    fn parse_variant_literal(&mut self, dot_token: Token) -> PResult<Expr> {
+        let name = self.expect_ident("expected variant name after `.`")?;
+        let (payload, end) = match self.lexer.peek().map(|t| &t.kind) {
+            Some(TokenType::OParen) => {
+                let (args, close) = self.parse_args()?;
+                (VariantArgs::Args(args), close.span)
+            }
+            Some(TokenType::OBrack) => {
+                let (fields, close) = self.parse_field_inits()?;
+                (VariantArgs::Fields(fields), close.span)
+            }
+            _ => (VariantArgs::None, name.span),
+        };
+
+        Ok(Expr::Variant(VariantLiteral {
+            name,
+            payload,
+            span: dot_token.span.to(end),
+        }))
+    }
+
+    /// Parses the storage class of a declaration: `let`, `mut`, `const`,
+    /// `static`, `static mut` or `static const`.
+    fn parse_storage(&mut self) -> PResult<Storage> {
+        let static_kw = if self
             .lexer
             .peek()
-            .map_or(false, |t| t.kind == TokenType::Pub)
+            .is_some_and(|t| t.kind == TokenType::Static)
         {
-            Some(self.lexer.next().unwrap())
+            Some(self.bump())
         } else {
             None
         };
 
-        // Will be Const|Let|Mut
-        let prot = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected token, found none"))?;
+        let next = self.peek_token();
+        let qualifier = match next.kind {
+            TokenType::Let if static_kw.is_some() => {
+                // Unambiguous, so report it and carry on as a bare `static`.
+                self.diagnostics.push(
+                    Diagnostic::error("`let` cannot follow `static`")
+                        .with_code(codes::UNEXPECTED_TOKEN)
+                        .with_primary(next.span, "remove `let`")
+                        .with_help("a bare `static` is already immutable"),
+                );
+                self.bump();
+                None
+            }
+            TokenType::Const | TokenType::Mut => Some(self.bump()),
+            TokenType::Let if static_kw.is_none() => Some(self.bump()),
+            _ if static_kw.is_some() => None,
+            _ => {
+                return Err(self.unexpected(
+                    &next,
+                    "expected `const`, `let`, `mut` or `static` after `pub`",
+                    "expected declaration keyword",
+                ));
+            }
+        };
 
-        let name = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected token, found none"))?;
+        Ok(Storage {
+            static_kw,
+            qualifier,
+        })
+    }
 
-        match name.kind {
-            TokenType::Ident(_) => {}
-            t => bail!("Expected Ident, found {:?}", t),
-        }
+    fn parse_decl(&mut self) -> PResult<Decl> {
+        let visibility = if self.lexer.peek().is_some_and(|t| t.kind == TokenType::Pub) {
+            Some(self.bump())
+        } else {
+            None
+        };
+
+        let storage = self.parse_storage()?;
+        let name = self.expect_ident("expected name after declaration keyword")?;
+
+        let start = visibility.as_ref().map_or(storage.span(), |v| v.span);
 
         // Check for type hint or function definition
-        let next_token_kind = self
-            .lexer
-            .peek()
-            .ok_or(anyhow::anyhow!("Expected token, found none"))?
-            .kind
-            .clone();
+        let next_token_kind = self.lexer.peek().map(|t| t.kind.clone());
 
         let decl = match next_token_kind {
-            TokenType::Colon => {
-                self.lexer.next(); // Consume ':'
-                let peeked_kind = self
-                    .lexer
-                    .peek()
-                    .ok_or(anyhow::anyhow!("Expected token after ':', found none"))?
-                    .kind
-                    .clone();
+            Some(TokenType::Colon) => {
+                self.bump(); // Consume ':'
+                let peeked_kind = self.lexer.peek().map(|t| t.kind.clone());
                 match peeked_kind {
-                    TokenType::Fn => {
-                        Decl::Function(self.parse_function_definition(visibility, prot, name)?)
+                    Some(TokenType::Fn) if !self.at_fn_type() => {
+                        Decl::Function(self.parse_function_definition(visibility, storage, name)?)
+                    }
+                    Some(TokenType::Struct) => {
+                        Decl::Struct(self.parse_struct_definition(start, storage, name)?)
+                    }
+                    Some(TokenType::Enum) => {
+                        Decl::Enum(self.parse_enum_definition(start, storage, name)?)
+                    }
+                    Some(TokenType::Interface) => {
+                        Decl::Interface(self.parse_interface_definition(start, storage, name)?)
+                    }
+                    Some(TokenType::Macro) => {
+                        Decl::Macro(self.parse_macro_definition(start, storage, name)?)
                     }
-                    TokenType::Struct => Decl::Struct(self.parse_struct_definition(name)?),
                     _ => {
                         // It's a variable declaration with a type hint
                         let type_hint = Some(self.parse_type()?);
-                        // Consume `:=` or `=`
-                        self.expect_and_consume(TokenType::Assign, "Expected Assign")?;
+                        // Consume `=`
+                        self.expect_and_consume(TokenType::Eql, "expected `=` after type")
+                            .map_err(|d| {
+                                d.with_help("write `name : type = value` or `name := value`")
+                            })?;
                         let expr = self.parse_expr(0)?;
+                        let span = start.to(expr.span());
                         Decl::Variable(Variable {
                             visibility,
-                            prot,
+                            storage,
                             name,
                             type_hint,
                             expr,
+                            span,
                         })
                     }
                 }
             }
-            TokenType::Assign | TokenType::Eql => {
+            Some(TokenType::Assign) | Some(TokenType::Eql) => {
                 // It's a variable declaration without a type hint
-                self.lexer.next(); // Consume `:=` or `=`
+                self.bump(); // Consume `:=` or `=`
                 let expr = self.parse_expr(0)?;
+                let span = start.to(expr.span());
                 Decl::Variable(Variable {
                     visibility,
-                    prot,
+                    storage,
                     name,
                     type_hint: None,
                     expr,
+                    span,
                 })
             }
-            _ => bail!("Expected ':' or '=', found {:?}", next_token_kind),
+            _ => {
+                let found = self.peek_token();
+                return Err(self.unexpected(
+                    &found,
+                    "expected `:` or `:=` after declaration name",
+                    "expected `:` or `:=`",
+                ));
+            }
         };
 
         // expect `;`
-        self.expect_and_consume(TokenType::SemiColon, "Expected Semicolon")?;
+        self.expect_semicolon("expected `;` after declaration")?;
 
         Ok(decl)
     }
 
-    fn parse_impl_decl(&mut self) -> anyhow::Result<Decl> {
-        let name = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("Expected identifier for impl, found none"))?;
-        match name.kind {
-            TokenType::Ident(_) => {}
-            t => bail!("Expected Ident for impl name, found {:?}", t),
-        }
+    fn parse_impl_decl(&mut self) -> PResult<Decl> {
+        let name = self.expect_ident("expected type name before `+=`")?;
 
-        self.expect_and_consume(TokenType::PlusEql, "Expected '+='")?;
-        self.expect_and_consume(TokenType::Impl, "Expected 'impl'")?;
-        self.expect_and_consume(TokenType::OBrack, "Expected '{'")?;
+        self.expect_and_consume(TokenType::PlusEql, "expected `+=`")?;
+        self.expect_and_consume(TokenType::Impl, "expected `impl` after `+=`")?;
+        let open = self.expect_and_consume(TokenType::OBrack, "expected `{` after `impl`")?;
 
         let mut members = Vec::new();
         while self
             .lexer
             .peek()
-            .map_or(false, |t| t.kind != TokenType::CBrack)
+            .is_some_and(|t| t.kind != TokenType::CBrack && t.kind != TokenType::EOF)
         {
-            members.push(self.next()?);
+            members.push(self.next_recovering());
         }
 
-        self.expect_and_consume(TokenType::CBrack, "Expected '}'")?;
-        self.expect_and_consume(TokenType::SemiColon, "Expected ';'")?;
+        let close = self.expect_closing(TokenType::CBrack, &open)?;
+        self.expect_semicolon("expected `;` after impl block")?;
 
         Ok(Decl::Impl(Impl {
+            span: name.span.to(close.span),
             name,
-            members: Block(members),
+            members: Block {
+                nodes: members,
+                span: open.span.to(close.span),
+            },
+        }))
+    }
+
+    /// Parses `Type += Interface{};`, declaring that `Type` conforms to
+    /// `Interface`.
+    fn parse_derive_decl(&mut self) -> PResult<Decl> {
+        let target = self.expect_ident("expected type name before `+=`")?;
+        self.expect_and_consume(TokenType::PlusEql, "expected `+=`")?;
+        let interface = self.expect_ident("expected interface name after `+=`")?;
+        let open =
+            self.expect_and_consume(TokenType::OBrack, "expected `{` after interface name")?;
+        let close = self
+            .expect_closing(TokenType::CBrack, &open)
+            .map_err(|d| d.with_help("derives take no arguments: `Type += Interface{};`"))?;
+        self.expect_semicolon("expected `;` after derive")?;
+
+        Ok(Decl::Derive(Derive {
+            span: target.span.to(close.span),
+            target,
+            interface,
         }))
     }
 
     // Helper methods for token expectation and consumption
-    fn expect(&mut self, expected_kind: TokenType, msg: &str) -> anyhow::Result<()> {
-        let peeked_kind = self
-            .lexer
-            .peek()
-            .ok_or(anyhow::anyhow!("{}, found none", msg))?;
-        if peeked_kind.kind == expected_kind {
+
+    /// Consumes the next token. Past the end of input this keeps returning
+    /// an `EOF` token positioned after the last real token.
+    fn bump(&mut self) -> Token {
+        match self.lexer.next() {
+            Some(token) => {
+                self.prev_span = token.span;
+                match token.kind {
+                    TokenType::OBrack => self.depth += 1,
+                    TokenType::CBrack => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+                token
+            }
+            None => Token {
+                kind: TokenType::EOF,
+                span: self.end_of_prev(),
+            },
+        }
+    }
+
+    /// An empty span directly after the previously consumed token.
+    fn end_of_prev(&self) -> Span {
+        Span {
+            start: self.prev_span.end,
+            col: self.prev_span.col + self.prev_span.len(),
+            ..self.prev_span
+        }
+    }
+
+    /// Returns the kind of the token `n` positions ahead (`0` is the next one).
+    fn peek_nth(&self, n: usize) -> Option<TokenType> {
+        self.lexer.clone().nth(n).map(|t| t.kind)
+    }
+
+    /// Whether the `fn` ahead starts a function type rather than a
+    /// function definition: definitions name their parameters, so
+    /// `fn(u32) -> u32` is a type and `fn(n: u32) -> u32` and `fn() -> u32`
+    /// are definitions.
+    fn at_fn_type(&self) -> bool {
+        let is_self = |kind: Option<TokenType>| kind == Some(TokenType::Ident("self".to_string()));
+        match self.peek_nth(2) {
+            Some(TokenType::CParen) => false,
+            Some(TokenType::Amp) => {
+                !(is_self(self.peek_nth(3))
+                    || self.peek_nth(3) == Some(TokenType::Mut) && is_self(self.peek_nth(4)))
+            }
+            first => !(is_self(first) || self.peek_nth(3) == Some(TokenType::Colon)),
+        }
+    }
+
+    /// Consumes a `mut` after `&`, returning whether there was one.
+    fn eat_mut(&mut self) -> bool {
+        let found = self.lexer.peek().is_some_and(|t| t.kind == TokenType::Mut);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    /// Returns a copy of the next token without consuming it.
+    fn peek_token(&mut self) -> Token {
+        match self.lexer.peek() {
+            Some(token) => token.clone(),
+            None => Token {
+                kind: TokenType::EOF,
+                span: self.end_of_prev(),
+            },
+        }
+    }
+
+    fn expect(&mut self, expected_kind: TokenType, msg: &str) -> PResult<()> {
+        let token = self.peek_token();
+        if token.kind == expected_kind {
             Ok(())
         } else {
-            bail!("{}, found {:?}", msg, peeked_kind.kind)
+            let label = format!("expected {}", describe(&expected_kind));
+            Err(self.unexpected(&token, msg, &label))
         }
     }
 
-    fn expect_and_consume(&mut self, expected_kind: TokenType, msg: &str) -> anyhow::Result<Token> {
-        info!("expect_and_consume: Expected {:?}", expected_kind);
-        let token = self
-            .lexer
-            .next()
-            .ok_or(anyhow::anyhow!("{}, found none", msg))?;
-        info!("expect_and_consume: Consumed {:?}", token.kind);
+    fn expect_and_consume(&mut self, expected_kind: TokenType, msg: &str) -> PResult<Token> {
+        let token = self.bump();
         if token.kind == expected_kind {
             Ok(token)
         } else {
-            bail!("{}, found {:?}", msg, token.kind)
+            let label = format!("expected {}", describe(&expected_kind));
+            Err(self.unexpected(&token, msg, &label))
+        }
+    }
+
+    /// Like `expect_and_consume`, but points back at the unclosed delimiter.
+    fn expect_closing(&mut self, expected_kind: TokenType, open: &Token) -> PResult<Token> {
+        let msg = format!("expected {}", describe(&expected_kind));
+        self.expect_and_consume(expected_kind, &msg)
+            .map_err(|d| d.with_secondary(open.span, "unclosed delimiter"))
+    }
+
+    /// Consumes a `;`. When it is missing the error points just past the
+    /// previous token, where the `;` belongs.
+    fn expect_semicolon(&mut self, msg: &str) -> PResult<Token> {
+        if self
+            .lexer
+            .peek()
+            .is_some_and(|t| t.kind == TokenType::SemiColon)
+        {
+            return Ok(self.bump());
+        }
+        let found = self.peek_token();
+        match found.kind {
+            TokenType::Invalid(_) | TokenType::Error(_) => {
+                Err(self.unexpected(&found, msg, "expected `;`"))
+            }
+            _ => Err(
+                Diagnostic::error(format!("{msg}, found {}", describe(&found.kind)))
+                    .with_code(codes::UNEXPECTED_TOKEN)
+                    .with_primary(self.end_of_prev(), "expected `;`")
+                    .with_secondary(found.span, "unexpected token")
+                    .with_help("add `;` here"),
+            ),
         }
     }
+
+    fn expect_ident(&mut self, msg: &str) -> PResult<Token> {
+        let token = self.bump();
+        match token.kind {
+            TokenType::Ident(_) => Ok(token),
+            _ => Err(self.unexpected(&token, msg, "expected identifier")),
+        }
+    }
+
+    /// Builds the diagnostic for finding `token` where something else was
+    /// expected. Lexer errors and invalid characters get their own codes.
+    fn unexpected(&self, token: &Token, msg: &str, label: &str) -> Diagnostic {
+        match &token.kind {
+            TokenType::EOF => Diagnostic::error(format!("{msg}, found end of file"))
+                .with_code(codes::UNEXPECTED_EOF)
+                .with_primary(token.span, label),
+            TokenType::Invalid(c) => Diagnostic::error(format!("invalid character `{c}`"))
+                .with_code(codes::INVALID_CHARACTER)
+                .with_primary(token.span, "not valid in Canary source"),
+            TokenType::Error(e) if e == UNTERMINATED_STRING => Diagnostic::error(e.clone())
+                .with_code(codes::UNTERMINATED_STRING)
+                .with_primary(token.span, "string starts here")
+                .with_help("add a closing `\"`"),
+            TokenType::Error(e) if e == UNCLOSED_COMMENT => Diagnostic::error(e.clone())
+                .with_code(codes::UNCLOSED_COMMENT)
+                .with_primary(token.span, "comment starts here")
+                .with_help("close the comment with `*/`"),
+            TokenType::Error(e) if e.starts_with(INVALID_NUMBER) => {
+                Diagnostic::error(INVALID_NUMBER)
+                    .with_code(codes::INVALID_NUMBER)
+                    .with_primary(token.span, &e[INVALID_NUMBER.len() + 2..])
+            }
+            TokenType::Error(e) => Diagnostic::error(e.clone()).with_primary(token.span, ""),
+            kind => Diagnostic::error(format!("{msg}, found {}", describe(kind)))
+                .with_code(codes::UNEXPECTED_TOKEN)
+                .with_primary(token.span, label),
+        }
+    }
+}
+
+/// Describes a token the way it should appear in an error message.
+fn describe(kind: &TokenType) -> String {
+    match kind {
+        TokenType::Ident(name) => format!("identifier `{name}`"),
+        TokenType::Int(n) => format!("number `{}`", n.text),
+        TokenType::Float(n) => format!("number `{}`", n.text),
+        TokenType::String(_) => "string literal".to_string(),
+        TokenType::Invalid(c) => format!("`{c}`"),
+        TokenType::EOF => "end of file".to_string(),
+        TokenType::Error(e) => e.clone(),
+        TokenType::Const
+        | TokenType::Let
+        | TokenType::Mut
+        | TokenType::Static
+        | TokenType::Struct
+        | TokenType::Enum
+        | TokenType::Macro
+        | TokenType::Impl
+        | TokenType::Interface
+        | TokenType::Priv
+        | TokenType::Pub
+        | TokenType::Override
+        | TokenType::Fn
+        | TokenType::Defer
+        | TokenType::If
+        | TokenType::Else
+        | TokenType::Switch
+        | TokenType::Return
+        | TokenType::Break
+        | TokenType::Continue
+        | TokenType::Unreachable => {
+            format!("keyword `{}`", kind.clone().into_str().to_lowercase())
+        }
+        _ => format!("`{}`", kind.clone().into_str()),
+    }
 }
 
+/// The implicit `Self` type of a `self` receiver, positioned at `name`.
+fn self_type(name: &Token) -> Token {
+    Token {
+        kind: TokenType::Ident("Self".to_string()),
+        span: name.span,
+    }
+}
+
+/// Binding power of prefix `-`, `!`, `&` and `*`: tighter than every infix
+/// operator, looser than calls and member access.
+const PREFIX_PRECEDENCE: u8 = 9;
+
+/// Binding power of infix operators, lowest first. `0` means the token is
+/// not an infix operator.
 fn get_precedence(token: &TokenType) -> u8 {
     match token {
-        TokenType::Plus | TokenType::Minus => 1,
-        TokenType::Star | TokenType::Div => 2,
+        TokenType::Eql
+        | TokenType::PlusEql
+        | TokenType::MinusEql
+        | TokenType::StarEql
+        | TokenType::DivEql => 1,
+        TokenType::Pipe => 2,
+        TokenType::OrOr => 3,
+        TokenType::AndAnd => 4,
+        TokenType::DoubleEql | TokenType::BangEql => 5,
+        TokenType::Less | TokenType::LessEql | TokenType::Greater | TokenType::GreaterEql => 6,
+        TokenType::Plus | TokenType::Minus => 7,
+        TokenType::Star | TokenType::Div => 8,
         _ => 0,
     }
 }
+
+/// Expressions ending in a `}` that may stand as statements without `;`.
+fn is_block_like(expr: &Expr) -> bool {
+    matches!(expr, Expr::Block(_) | Expr::Switch(_))
+}
+
+fn is_assignment(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Eql
+            | TokenType::PlusEql
+            | TokenType::MinusEql
+            | TokenType::StarEql
+            | TokenType::DivEql
+    )
+}
+
+/// Only names, fields and dereferences can be assigned to.
+fn check_assign_target(target: &Expr, op: &Token) -> PResult<()> {
+    match target {
+        Expr::Atom(Token {
+            kind: TokenType::Ident(_),
+            ..
+        })
+        | Expr::Field(_) => Ok(()),
+        Expr::UnaryOp(unop) if unop.op.kind == TokenType::Star => Ok(()),
+        _ => Err(Diagnostic::error("invalid left-hand side of assignment")
+            .with_code(codes::INVALID_ASSIGN_TARGET)
+            .with_primary(target.span(), "cannot assign to this expression")
+            .with_secondary(op.span, "assignment here")),
+    }
+}
@@ -1,3 +1,4 @@
+use lexer::token::Span;
 use lexer::token::Token;
 use lexer::token::TokenType;
 use std::fmt::{self, Debug};
@@ -10,6 +11,12 @@ impl Program {
     }
 }
 
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Debug for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for node in &self.0 {
@@ -23,14 +30,28 @@ impl Debug for Program {
 pub enum Node {
     Stmt(Stmt),
     Expr(Expr),
+    /// Placeholder for a statement that failed to parse.
+    Error(Span),
     EOF,
 }
 
+impl Node {
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Stmt(stmt) => stmt.span(),
+            Node::Expr(expr) => expr.span(),
+            Node::Error(span) => *span,
+            Node::EOF => Span::default(),
+        }
+    }
+}
+
 impl Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Node::Stmt(stmt) => write!(f, "{:#?}", stmt),
             Node::Expr(expr) => write!(f, "{:#?}", expr),
+            Node::Error(_) => write!(f, "Error"),
             Node::EOF => write!(f, "EOF"),
         }
     }
@@ -38,10 +59,19 @@ impl Debug for Node {
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub enum Stmt {
-    Decl(Decl),
+    Decl(Box<Decl>),
     ExprStmt(Expr), // Added for expression statements
 }
 
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Decl(decl) => decl.span(),
+            Stmt::ExprStmt(expr) => expr.span(),
+        }
+    }
+}
+
 impl Debug for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -55,10 +85,37 @@ impl Debug for Stmt {
 pub enum Expr {
     Atom(Token),
     BinOp(Box<BinOp>),
-    UnaryOp,
+    UnaryOp(Box<UnaryOp>),
+    Assign(Box<Assign>),
+    Pipe(Box<Pipe>),
+    Field(Box<Field>),
     Block(Block),
     Call(Call),
     StructLiteral(StructLiteral),
+    Switch(Box<Switch>),
+    Return(Box<Return>),
+    Variant(VariantLiteral),
+    MacroCall(MacroCall),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Atom(token) => token.span,
+            Expr::BinOp(binop) => binop.span,
+            Expr::UnaryOp(unop) => unop.span,
+            Expr::Assign(assign) => assign.span,
+            Expr::Pipe(pipe) => pipe.span,
+            Expr::Field(field) => field.span,
+            Expr::Block(block) => block.span,
+            Expr::Call(call) => call.span,
+            Expr::StructLiteral(sl) => sl.span,
+            Expr::Switch(switch) => switch.span,
+            Expr::Return(ret) => ret.span,
+            Expr::Variant(variant) => variant.span,
+            Expr::MacroCall(call) => call.span,
+        }
+    }
 }
 
 impl Debug for Expr {
@@ -66,10 +123,17 @@ impl Debug for Expr {
         match self {
             Expr::Atom(token) => write!(f, "{:?}", token.kind),
             Expr::BinOp(binop) => write!(f, "{:#?}", binop),
-            Expr::UnaryOp => write!(f, "UnaryOp"),
+            Expr::UnaryOp(unop) => write!(f, "{:#?}", unop),
+            Expr::Assign(assign) => write!(f, "{:#?}", assign),
+            Expr::Pipe(pipe) => write!(f, "{:#?}", pipe),
+            Expr::Field(field) => write!(f, "{:#?}", field),
             Expr::Block(block) => write!(f, "{:#?}", block),
             Expr::Call(call) => write!(f, "{:#?}", call),
             Expr::StructLiteral(sl) => write!(f, "{:#?}", sl),
+            Expr::Switch(switch) => write!(f, "{:#?}", switch),
+            Expr::Return(ret) => write!(f, "{:#?}", ret),
+            Expr::Variant(variant) => write!(f, "{:#?}", variant),
+            Expr::MacroCall(call) => write!(f, "{:#?}", call),
         }
     }
 }
@@ -77,13 +141,14 @@ impl Debug for Expr {
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct StructLiteral {
     pub fields: Vec<(Token, Expr)>,
+    pub span: Span,
 }
 
 impl Debug for StructLiteral {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, ".{{\n")?;
+        writeln!(f, ".{{")?;
         for (name, expr) in &self.fields {
-            write!(f, "        .{:?} = {:#?}\n", name.kind, expr)?;
+            writeln!(f, "        .{:?} = {:#?}", name.kind, expr)?;
         }
         write!(f, "    }}")
     }
@@ -94,6 +159,7 @@ pub struct BinOp {
     pub op: Token,
     pub lhs: Expr,
     pub rhs: Expr,
+    pub span: Span,
 }
 
 impl Debug for BinOp {
@@ -102,15 +168,262 @@ impl Debug for BinOp {
     }
 }
 
+/// A prefix operator: `-x`, `!x`, `&x`, `&mut x` or `*x`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnaryOp {
+    pub op: Token,
+    /// Whether `&` is followed by `mut`.
+    pub mutable: bool,
+    pub expr: Expr,
+    pub span: Span,
+}
+
+impl Debug for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.mutable {
+            write!(f, "({:?} mut {:#?})", self.op.kind, self.expr)
+        } else {
+            write!(f, "({:?} {:#?})", self.op.kind, self.expr)
+        }
+    }
+}
+
+/// `target = value`, or a compound assignment such as `target += value`
+/// when `op` is not `Eql`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Assign {
+    pub op: Token,
+    pub target: Expr,
+    pub value: Expr,
+    pub span: Span,
+}
+
+impl Debug for Assign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:#?} {:?} {:#?})",
+            self.target, self.op.kind, self.value
+        )
+    }
+}
+
+/// `lhs |> rhs`: passes `lhs` as the first argument of the call `rhs`.
+/// `x |> f` is `f(x)` and `x |> f(y)` is `f(x, y)`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pipe {
+    pub lhs: Expr,
+    pub rhs: Expr,
+    pub span: Span,
+}
+
+impl Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:#?} |> {:#?})", self.lhs, self.rhs)
+    }
+}
+
+/// Member access: `base.name`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Field {
+    pub base: Expr,
+    pub name: Token,
+    pub span: Span,
+}
+
+impl Debug for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:#?}.{:?})", self.base, self.name.kind)
+    }
+}
+
+/// An enum variant written with a leading dot, whose enum is known from
+/// context: `.AllDone`, `.SwimLaps(8)` or `.Meditate { .times = 6 }`.
+///
+/// Qualified constructors such as `Activity.SwimLaps(8)` parse as a `Call`
+/// on a `Field`; name resolution tells them apart from method calls.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct VariantLiteral {
+    pub name: Token,
+    pub payload: VariantArgs,
+    pub span: Span,
+}
+
+impl Debug for VariantLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, ".{:?}", self.name.kind)?;
+        match &self.payload {
+            VariantArgs::None => Ok(()),
+            VariantArgs::Args(args) => write!(f, "({:#?})", args),
+            VariantArgs::Fields(fields) => {
+                writeln!(f, " {{")?;
+                for (name, expr) in fields {
+                    writeln!(f, "        .{:?} = {:#?}", name.kind, expr)?;
+                }
+                write!(f, "    }}")
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum VariantArgs {
+    None,
+    Args(Vec<Expr>),
+    Fields(Vec<(Token, Expr)>),
+}
+
+/// `switch (scrutinee) { pattern => body, ... }`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Switch {
+    pub scrutinee: Expr,
+    pub arms: Vec<SwitchArm>,
+    pub span: Span,
+}
+
+impl Debug for Switch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "(switch {:#?} {{", self.scrutinee)?;
+        for arm in &self.arms {
+            writeln!(f, "    {:#?}", arm)?;
+        }
+        write!(f, "}})")
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct SwitchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    pub span: Span,
+}
+
+impl Debug for SwitchArm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} => {:#?}", self.pattern, self.body)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pattern {
+    /// A string or number literal, compared for equality.
+    Literal(Token),
+    /// An enum tag such as `.SwimLaps`, optionally binding its payload with
+    /// `.SwimLaps : |laps|`.
+    Variant {
+        name: Token,
+        capture: Option<Capture>,
+        span: Span,
+    },
+    /// `_`, matching anything.
+    Wildcard(Token),
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Literal(t) | Pattern::Wildcard(t) => t.span,
+            Pattern::Variant { span, .. } => *span,
+        }
+    }
+}
+
+impl Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Literal(t) => write!(f, "{:?}", t.kind),
+            Pattern::Wildcard(_) => write!(f, "_"),
+            Pattern::Variant {
+                name,
+                capture: None,
+                ..
+            } => write!(f, ".{:?}", name.kind),
+            Pattern::Variant {
+                name,
+                capture: Some(capture),
+                ..
+            } => write!(f, ".{:?} : {:?}", name.kind, capture),
+        }
+    }
+}
+
+/// The payload binding of a variant pattern.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capture {
+    /// `|laps|` binds the whole payload.
+    Binding(Token),
+    /// `|{times, seconds}|` binds fields of a struct payload by name.
+    Destructure(Vec<Token>, Span),
+}
+
+impl Capture {
+    pub fn span(&self) -> Span {
+        match self {
+            Capture::Binding(t) => t.span,
+            Capture::Destructure(_, span) => *span,
+        }
+    }
+}
+
+impl Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capture::Binding(t) => write!(f, "|{:?}|", t.kind),
+            Capture::Destructure(fields, _) => {
+                write!(f, "|{{")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}", field.kind)?;
+                }
+                write!(f, "}}|")
+            }
+        }
+    }
+}
+
+/// `return` or `return value`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Return {
+    pub value: Option<Expr>,
+    pub span: Span,
+}
+
+impl Debug for Return {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "(Return {:#?})", value),
+            None => write!(f, "Return"),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub enum Decl {
     Variable(Variable),
     Function(Function),
     Struct(Struct),
-    Enum,
-    Interface,
-    Macro,
+    Enum(Enum),
+    Interface(Interface),
+    Macro(Macro),
     Impl(Impl),
+    Derive(Derive),
+}
+
+impl Decl {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Variable(e) => e.span,
+            Self::Function(e) => e.span,
+            Self::Struct(e) => e.span,
+            Self::Enum(e) => e.span,
+            Self::Interface(e) => e.span,
+            Self::Macro(e) => e.span,
+            Self::Impl(e) => e.span,
+            Self::Derive(e) => e.span,
+        }
+    }
 }
 
 impl Debug for Decl {
@@ -119,8 +432,11 @@ impl Debug for Decl {
             Self::Variable(e) => write!(f, "{:#?}", e),
             Self::Function(e) => write!(f, "{:#?}", e),
             Self::Struct(e) => write!(f, "{:#?}", e),
+            Self::Enum(e) => write!(f, "{:#?}", e),
+            Self::Interface(e) => write!(f, "{:#?}", e),
+            Self::Macro(e) => write!(f, "{:#?}", e),
             Self::Impl(e) => write!(f, "{:#?}", e),
-            _ => write!(f, "Unhandled decl"),
+            Self::Derive(e) => write!(f, "{:#?}", e),
         }
     }
 }
@@ -129,6 +445,7 @@ impl Debug for Decl {
 pub struct Impl {
     pub name: Token,
     pub members: Block,
+    pub span: Span,
 }
 
 impl Debug for Impl {
@@ -137,13 +454,184 @@ impl Debug for Impl {
     }
 }
 
+/// `const name : macro(params) = { body };`
+///
+/// The body is a token tree; it is parsed only after `expand` substitutes
+/// the arguments of an invocation for `params`.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Macro {
+    pub storage: Storage,
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Token>,
+    pub span: Span,
+}
+
+impl Debug for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:?} : macro(", self.name.kind)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}", param.kind)?;
+        }
+        write!(f, ") = {} tokens)", self.body.len())
+    }
+}
+
+/// `name!(arg, ...)`, replaced by its expansion before later phases run.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacroCall {
+    pub name: Token,
+    pub args: Vec<Vec<Token>>,
+    pub span: Span,
+}
+
+impl Debug for MacroCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:?}!(", self.name.kind)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let kinds: Vec<_> = arg.iter().map(|t| &t.kind).collect();
+            write!(f, "{:?}", kinds)?;
+        }
+        write!(f, "))")
+    }
+}
+
+/// `Target += Interface{};`
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Derive {
+    pub target: Token,
+    pub interface: Token,
+    pub span: Span,
+}
+
+impl Debug for Derive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:?} += {:?}{{}})",
+            self.target.kind, self.interface.kind
+        )
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Interface {
+    pub storage: Storage,
+    pub name: Token,
+    pub methods: Vec<InterfaceMethod>,
+    pub span: Span,
+}
+
+impl Debug for Interface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "({:?} : interface = {{", self.name.kind)?;
+        for method in &self.methods {
+            writeln!(f, "    {:#?}", method)?;
+        }
+        write!(f, "}})")
+    }
+}
+
+/// A method an interface requires. `body` is the default implementation,
+/// if the interface provides one.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct InterfaceMethod {
+    pub visibility: Option<Token>,
+    pub name: Token,
+    pub params: Vec<Parameter>,
+    pub return_type: Type,
+    pub body: Option<Expr>,
+    pub span: Span,
+}
+
+impl Debug for InterfaceMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({:?} {:?} : fn({:#?}) -> {:#?}",
+            self.visibility
+                .as_ref()
+                .map_or(&TokenType::EOF, |t| &t.kind),
+            self.name.kind,
+            self.params,
+            self.return_type,
+        )?;
+        match &self.body {
+            Some(body) => write!(f, " = {:#?})", body),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// How a declared value is stored: `let`, `mut` and `const`, each of which
+/// (`let` being implied) may be prefixed with `static`.
+///
+/// Only the syntax is recorded here; the rules attached to each class are
+/// checked during semantic analysis.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Storage {
+    /// `static`, for values that live for the whole program.
+    pub static_kw: Option<Token>,
+    /// `let`, `mut` or `const`. Only absent for a bare `static`.
+    pub qualifier: Option<Token>,
+}
+
+impl Storage {
+    pub fn is_static(&self) -> bool {
+        self.static_kw.is_some()
+    }
+
+    pub fn mutability(&self) -> Mutability {
+        match self.qualifier.as_ref().map(|t| &t.kind) {
+            Some(TokenType::Mut) => Mutability::Mutable,
+            Some(TokenType::Const) => Mutability::Const,
+            _ => Mutability::Immutable,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match (&self.static_kw, &self.qualifier) {
+            (Some(s), Some(q)) => s.span.to(q.span),
+            (Some(t), None) | (None, Some(t)) => t.span,
+            (None, None) => Span::default(),
+        }
+    }
+}
+
+impl Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.static_kw, &self.qualifier) {
+            (Some(s), Some(q)) => write!(f, "{:?} {:?}", s.kind, q.kind),
+            (Some(t), None) | (None, Some(t)) => write!(f, "{:?}", t.kind),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Mutability {
+    /// `let` and bare `static`: read-only, but castable to `mut`.
+    Immutable,
+    /// `mut` and `static mut`.
+    Mutable,
+    /// `const` and `static const`: never castable to `mut`.
+    Const,
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Variable {
     pub visibility: Option<Token>,
-    pub prot: Token,
+    pub storage: Storage,
     pub name: Token,
     pub type_hint: Option<Type>,
     pub expr: Expr,
+    pub span: Span,
 }
 
 impl Debug for Variable {
@@ -155,7 +643,7 @@ impl Debug for Variable {
                 self.visibility
                     .as_ref()
                     .map_or(&TokenType::EOF, |t| &t.kind),
-                self.prot.kind,
+                self.storage,
                 self.name.kind,
                 type_hint,
                 self.expr
@@ -167,7 +655,7 @@ impl Debug for Variable {
                 self.visibility
                     .as_ref()
                     .map_or(&TokenType::EOF, |t| &t.kind),
-                self.prot.kind,
+                self.storage,
                 self.name.kind,
                 self.expr
             )
@@ -177,8 +665,10 @@ impl Debug for Variable {
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Struct {
+    pub storage: Storage,
     pub name: Token,
     pub members: Vec<Parameter>,
+    pub span: Span,
 }
 
 impl Debug for Struct {
@@ -187,23 +677,96 @@ impl Debug for Struct {
     }
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Enum {
+    pub storage: Storage,
+    pub name: Token,
+    pub variants: Vec<Variant>,
+    pub span: Span,
+}
+
+impl Debug for Enum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({:?} : enum = {:?})", self.name.kind, self.variants)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Variant {
+    pub name: Token,
+    pub payload: VariantPayload,
+    pub span: Span,
+}
+
+impl Debug for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.payload {
+            VariantPayload::None => write!(f, "{:?}", self.name.kind),
+            VariantPayload::Type(t) => write!(f, "{:?}: {:?}", self.name.kind, t),
+            VariantPayload::Touple(elements) => {
+                write!(f, "{:?}: ( ", self.name.kind)?;
+                for element in elements {
+                    write!(f, "{:?} ", element)?;
+                }
+                write!(f, ")")
+            }
+            VariantPayload::Struct(members) => {
+                write!(f, "{:?}: struct = {:?}", self.name.kind, members)
+            }
+        }
+    }
+}
+
+/// What a variant carries: nothing (`AllDone`), a single type
+/// (`Todo: str`), a tuple (`SwimLaps: (u8, u8)`) or an anonymous struct
+/// (`Meditate: struct = { times: u8 }`).
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum VariantPayload {
+    None,
+    Type(Type),
+    Touple(Vec<Type>),
+    Struct(Vec<Parameter>),
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub enum Type {
     Identifier(Token),
-    Touple(Vec<Box<Type>>),
+    Touple(Vec<Box<Type>>, Span),
+    /// `&T`, or `&mut T` if the flag is set.
+    Ref(Box<Type>, bool, Span),
+    /// `fn(T, U) -> R`, the type of function values.
+    Fn(Vec<Type>, Box<Type>, Span),
+}
+
+impl Type {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Identifier(t) => t.span,
+            Self::Touple(_, span) | Self::Ref(_, _, span) | Self::Fn(_, _, span) => *span,
+        }
+    }
 }
 
 impl Debug for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Identifier(t) => write!(f, "{:?}", t.kind)?,
-            Self::Touple(t) => {
+            Self::Touple(t, _) => {
                 write!(f, "( ")?;
                 for element in t {
                     write!(f, "{:?} ", element)?;
                 }
                 write!(f, ")")?;
             }
+            Self::Ref(t, false, _) => write!(f, "&{:?}", t)?,
+            Self::Ref(t, true, _) => write!(f, "&mut {:?}", t)?,
+            Self::Fn(params, ret, _) => {
+                write!(f, "fn( ")?;
+                for param in params {
+                    write!(f, "{:?} ", param)?;
+                }
+                write!(f, ") -> {:?}", ret)?;
+            }
         }
         Ok(())
     }
@@ -212,11 +775,12 @@ impl Debug for Type {
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Function {
     pub visibility: Option<Token>,
-    pub prot: Token,
+    pub storage: Storage,
     pub name: Token,
     pub params: Vec<Parameter>,
     pub return_type: Type,
     pub body: Expr,
+    pub span: Span,
 }
 
 impl Debug for Function {
@@ -227,7 +791,7 @@ impl Debug for Function {
             self.visibility
                 .as_ref()
                 .map_or(&TokenType::EOF, |t| &t.kind),
-            self.prot.kind,
+            self.storage,
             self.name.kind,
             self.params,
             self.return_type,
@@ -240,6 +804,7 @@ impl Debug for Function {
 pub struct Parameter {
     pub name: Token,
     pub param_type: Type,
+    pub span: Span,
 }
 
 impl Debug for Parameter {
@@ -249,13 +814,16 @@ impl Debug for Parameter {
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
-pub struct Block(pub Vec<Node>);
+pub struct Block {
+    pub nodes: Vec<Node>,
+    pub span: Span,
+}
 
 impl Debug for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{{\n")?;
-        for node in &self.0 {
-            write!(f, "    {:#?}\n", node)?;
+        writeln!(f, "{{")?;
+        for node in &self.nodes {
+            writeln!(f, "    {:#?}", node)?;
         }
         write!(f, "}}")
     }
@@ -263,12 +831,19 @@ impl Debug for Block {
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Call {
-    pub callee: Token,
+    pub callee: Box<Expr>,
     pub args: Vec<Expr>,
+    /// The `#tail` annotation, if the call has one.
+    pub tail: Option<Span>,
+    pub span: Span,
 }
 
 impl Debug for Call {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({:?}({:#?}))", self.callee.kind, self.args)
+        if self.tail.is_some() {
+            write!(f, "(#tail {:#?}({:#?}))", self.callee, self.args)
+        } else {
+            write!(f, "({:#?}({:#?}))", self.callee, self.args)
+        }
     }
 }
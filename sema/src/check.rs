@@ -0,0 +1,2364 @@
+//! Type checking.
+//!
+//! Lowers the resolved AST into the typed AST of [`crate::tast`]. Checking
+//! is bidirectional: the expected type flows down into literals, blocks and
+//! switch arms, which is what lets `.{ ... }` and `.Variant` literals find
+//! their type from an annotation. Integer literals without a known type
+//! are left to [`crate::infer`], which unifies them with their uses and
+//! defaults whatever is left once the item is checked.
+//!
+//! Items are processed in phases so that order in the source never
+//! matters: type definitions, then function signatures and methods, then
+//! globals (checked on demand when one refers to another), then bodies.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::ops::Range;
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::{FloatLit, IntLit, Span, Token, TokenType};
+use parser::node::*;
+
+use crate::format::{self, Spec};
+use crate::infer::InferCtx;
+use crate::resolve::{DeclId, Resolution, SymbolKind};
+use crate::tast::BinOp;
+use crate::tast::*;
+use crate::types::*;
+
+/// Type-checks `program`. The typed program is only complete if no errors
+/// were returned.
+pub fn check(program: &Program, res: &Resolution) -> (TProgram, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        res,
+        out: TProgram::default(),
+        diagnostics: Vec::new(),
+        types: HashMap::new(),
+        interfaces: HashMap::new(),
+        funcs: HashMap::new(),
+        public: HashSet::new(),
+        sigs: Vec::new(),
+        pending: Vec::new(),
+        bodies: Vec::new(),
+        methods: HashMap::new(),
+        globals: HashMap::new(),
+        global_values: HashMap::new(),
+        global_order: Vec::new(),
+        frame: Frame::default(),
+        infer: InferCtx::default(),
+        literals: Vec::new(),
+    };
+
+    checker.collect_types(&program.0);
+    checker.collect_functions(&program.0);
+    checker.collect_globals(&program.0);
+    for id in checker.global_order.clone() {
+        checker.check_global(id);
+    }
+    checker.check_functions();
+
+    checker.finish(&program.0)
+}
+
+enum GlobalState<'a> {
+    Pending(&'a Variable, Option<Ty>),
+    InProgress,
+    Done(Ty),
+}
+
+/// A function whose signature is known and whose body is yet to be checked.
+#[derive(Clone)]
+struct PendingFn<'a> {
+    id: FuncId,
+    name: String,
+    params: &'a [Parameter],
+    body: &'a Expr,
+    self_ty: Option<Ty>,
+    span: Span,
+}
+
+/// A literal whose range can only be checked once its type is inferred.
+struct PendingLiteral {
+    ty: Ty,
+    value: LiteralValue,
+    span: Span,
+}
+
+enum LiteralValue {
+    Int { value: u64, negated: bool },
+    Float(f64),
+}
+
+/// State of the function body or global initializer being checked.
+#[derive(Default)]
+struct Frame {
+    /// Return type of the current function; `None` in initializers.
+    ret: Option<Ty>,
+    /// What `Self` means inside an `impl` or derived method.
+    self_ty: Option<Ty>,
+    locals: HashMap<DeclId, TLocal>,
+}
+
+struct Checker<'a> {
+    res: &'a Resolution,
+    out: TProgram,
+    diagnostics: Vec<Diagnostic>,
+    /// Structs, enums and interfaces by name.
+    types: HashMap<String, DeclId>,
+    interfaces: HashMap<DeclId, &'a Interface>,
+    /// Functions declared with `const name : fn...`, including methods.
+    funcs: HashMap<DeclId, FuncId>,
+    /// Functions declared `pub`.
+    public: HashSet<FuncId>,
+    /// Signatures, indexed by `FuncId`.
+    sigs: Vec<FnTy>,
+    pending: Vec<PendingFn<'a>>,
+    bodies: Vec<Option<TFunction>>,
+    /// Methods by owning type and name.
+    methods: HashMap<(DeclId, String), FuncId>,
+    globals: HashMap<DeclId, GlobalState<'a>>,
+    global_values: HashMap<DeclId, TGlobal>,
+    global_order: Vec<DeclId>,
+    frame: Frame,
+    infer: InferCtx,
+    literals: Vec<PendingLiteral>,
+}
+
+/// The span of `range` within the string literal at `span` whose text
+/// between the quotes is `raw`. Falls back to the whole literal when the
+/// two do not line up, as in macro expansions.
+fn literal_span(span: Span, raw: &str, range: &Range<usize>) -> Span {
+    let before = &raw[..range.start];
+    if span.expansion != 0 || span.end - span.start != raw.len() + 2 || before.contains('\n') {
+        return span;
+    }
+    Span {
+        start: span.start + 1 + range.start,
+        end: span.start + 1 + range.end,
+        col: span.col + 1 + before.chars().count(),
+        ..span
+    }
+}
+
+fn ident(token: &Token) -> &str {
+    match &token.kind {
+        TokenType::Ident(name) => name,
+        _ => "",
+    }
+}
+
+/// The payload of a variant being constructed, borrowed from either a
+/// `VariantLiteral` or a qualified call such as `Activity.SwimLaps(8)`.
+enum Payload<'a> {
+    None,
+    Args(&'a [Expr]),
+    Fields(&'a [(Token, Expr)]),
+}
+
+impl<'a> Payload<'a> {
+    fn of(args: &'a VariantArgs) -> Self {
+        match args {
+            VariantArgs::None => Payload::None,
+            VariantArgs::Args(args) => Payload::Args(args),
+            VariantArgs::Fields(fields) => Payload::Fields(fields),
+        }
+    }
+}
+
+fn binop(kind: &TokenType) -> Option<BinOp> {
+    Some(match kind {
+        TokenType::Plus | TokenType::PlusEql => BinOp::Add,
+        TokenType::Minus | TokenType::MinusEql => BinOp::Sub,
+        TokenType::Star | TokenType::StarEql => BinOp::Mul,
+        TokenType::Div | TokenType::DivEql => BinOp::Div,
+        TokenType::DoubleEql => BinOp::Eq,
+        TokenType::BangEql => BinOp::Ne,
+        TokenType::Less => BinOp::Lt,
+        TokenType::LessEql => BinOp::Le,
+        TokenType::Greater => BinOp::Gt,
+        TokenType::GreaterEql => BinOp::Ge,
+        TokenType::AndAnd => BinOp::And,
+        TokenType::OrOr => BinOp::Or,
+        _ => return None,
+    })
+}
+
+impl<'a> Checker<'a> {
+    fn show(&self, ty: &Ty) -> String {
+        DisplayTy {
+            ty: &self.infer.resolve(ty),
+            names: &self.out,
+        }
+        .to_string()
+    }
+
+    fn error(&mut self, diag: Diagnostic) {
+        self.diagnostics.push(diag);
+    }
+
+    fn mismatch(&mut self, span: Span, expected: &Ty, found: &Ty) {
+        let diag = Diagnostic::error("mismatched types")
+            .with_code(codes::MISMATCHED_TYPES)
+            .with_primary(
+                span,
+                format!(
+                    "expected `{}`, found `{}`",
+                    self.show(expected),
+                    self.show(found)
+                ),
+            );
+        self.error(diag);
+    }
+
+    /// Reports `expr` if its type cannot be unified with `expected`. A
+    /// `&mut T` is accepted where a `&T` is expected.
+    fn coerce(&mut self, mut expr: TExpr, expected: &Ty) -> TExpr {
+        if let (Ty::Ref(inner, true), Ty::Ref(_, false)) = (&expr.ty, expected) {
+            expr.ty = Ty::Ref(inner.clone(), false);
+        }
+        if !self.infer.unify(&expr.ty, expected) {
+            self.mismatch(expr.span, expected, &expr.ty);
+        }
+        expr
+    }
+
+    /// Checks `expr` against a known type.
+    fn check(&mut self, expr: &'a Expr, expected: &Ty) -> TExpr {
+        let texpr = self.expr(expr, Some(expected));
+        self.coerce(texpr, expected)
+    }
+
+    // Items
+
+    fn collect_types(&mut self, nodes: &'a [Node]) {
+        let decls = || {
+            nodes.iter().filter_map(|node| match node {
+                Node::Stmt(Stmt::Decl(decl)) => Some(decl.as_ref()),
+                _ => None,
+            })
+        };
+
+        // Names first, so definitions can refer to each other.
+        for decl in decls() {
+            let (name, kind) = match decl {
+                Decl::Struct(s) => (&s.name, SymbolKind::Struct),
+                Decl::Enum(e) => (&e.name, SymbolKind::Enum),
+                Decl::Interface(i) => (&i.name, SymbolKind::Interface),
+                _ => continue,
+            };
+            let Some(id) = self.res.decl_at(name.span) else {
+                continue;
+            };
+            let text = ident(name).to_string();
+            self.types.insert(text.clone(), id);
+            match (kind, decl) {
+                (SymbolKind::Struct, _) => {
+                    self.out.structs.insert(
+                        id,
+                        StructDef {
+                            name: text,
+                            fields: Vec::new(),
+                        },
+                    );
+                }
+                (SymbolKind::Enum, _) => {
+                    self.out.enums.insert(
+                        id,
+                        EnumDef {
+                            name: text,
+                            variants: Vec::new(),
+                        },
+                    );
+                }
+                (_, Decl::Interface(interface)) => {
+                    self.interfaces.insert(id, interface);
+                }
+                _ => {}
+            }
+        }
+
+        for decl in decls() {
+            match decl {
+                Decl::Struct(s) => {
+                    let Some(id) = self.res.decl_at(s.name.span) else {
+                        continue;
+                    };
+                    let fields = self.fields(&s.members);
+                    self.out.structs.get_mut(&id).unwrap().fields = fields;
+                }
+                Decl::Enum(e) => {
+                    let Some(id) = self.res.decl_at(e.name.span) else {
+                        continue;
+                    };
+                    let mut variants: Vec<VariantDef> = Vec::new();
+                    for variant in &e.variants {
+                        let name = ident(&variant.name).to_string();
+                        if let Some(previous) = variants.iter().position(|v| v.name == name) {
+                            let diag = Diagnostic::error(format!(
+                                "variant `{name}` is declared more than once"
+                            ))
+                            .with_code(codes::DUPLICATE_FIELD)
+                            .with_primary(variant.name.span, "declared again here")
+                            .with_secondary(e.variants[previous].name.span, "first declared here");
+                            self.error(diag);
+                            continue;
+                        }
+                        let (shape, fields) = match &variant.payload {
+                            VariantPayload::None => (VariantShape::Unit, Vec::new()),
+                            VariantPayload::Type(ty) => (
+                                VariantShape::Tuple,
+                                vec![FieldDef {
+                                    name: "0".to_string(),
+                                    ty: self.lower_type(ty),
+                                }],
+                            ),
+                            VariantPayload::Touple(types) => (
+                                VariantShape::Tuple,
+                                types
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(i, ty)| FieldDef {
+                                        name: i.to_string(),
+                                        ty: self.lower_type(ty),
+                                    })
+                                    .collect(),
+                            ),
+                            VariantPayload::Struct(members) => {
+                                (VariantShape::Struct, self.fields(members))
+                            }
+                        };
+                        variants.push(VariantDef {
+                            name,
+                            shape,
+                            fields,
+                        });
+                    }
+                    self.out.enums.get_mut(&id).unwrap().variants = variants;
+                }
+                _ => {}
+            }
+        }
+
+        for decl in decls() {
+            self.check_finite(decl);
+        }
+    }
+
+    /// Reports the fields of `decl` that hold its own type by value,
+    /// directly or through other structs and enums, since the type would
+    /// then have no finite size. Such fields become errors, so later passes
+    /// never see the cycle.
+    fn check_finite(&mut self, decl: &'a Decl) {
+        let (name, id) = match decl {
+            Decl::Struct(s) => (&s.name, self.res.decl_at(s.name.span)),
+            Decl::Enum(e) => (&e.name, self.res.decl_at(e.name.span)),
+            _ => return,
+        };
+        let Some(id) = id else {
+            return;
+        };
+        // Fields as (variant, field) positions, with `None` for a struct.
+        let fields: Vec<(Option<usize>, usize, &Ty)> = match decl {
+            Decl::Struct(_) => self.out.structs[&id]
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(f, field)| (None, f, &field.ty))
+                .collect(),
+            _ => self.out.enums[&id]
+                .variants
+                .iter()
+                .enumerate()
+                .flat_map(|(v, variant)| {
+                    variant
+                        .fields
+                        .iter()
+                        .enumerate()
+                        .map(move |(f, field)| (Some(v), f, &field.ty))
+                })
+                .collect(),
+        };
+        let recursive: Vec<(Option<usize>, usize)> = fields
+            .into_iter()
+            .filter(|(_, _, ty)| self.holds(ty, id, &mut HashSet::new()))
+            .map(|(v, f, _)| (v, f))
+            .collect();
+
+        for (variant, field) in recursive {
+            let field_ty = match variant {
+                None => &mut self.out.structs.get_mut(&id).unwrap().fields[field],
+                Some(v) => &mut self.out.enums.get_mut(&id).unwrap().variants[v].fields[field],
+            };
+            let ty = mem::replace(&mut field_ty.ty, Ty::Error);
+            let field_name = field_ty.name.clone();
+            let span = match (decl, variant) {
+                (Decl::Struct(s), _) => s
+                    .members
+                    .iter()
+                    .find(|m| ident(&m.name) == field_name)
+                    .map(|m| m.param_type.span()),
+                (Decl::Enum(e), Some(v)) => {
+                    let variant_name = &self.out.enums[&id].variants[v].name;
+                    e.variants
+                        .iter()
+                        .find(|variant| ident(&variant.name) == variant_name)
+                        .and_then(|variant| match &variant.payload {
+                            VariantPayload::None => None,
+                            VariantPayload::Type(ty) => Some(ty.span()),
+                            VariantPayload::Touple(types) => Some(types[field].span()),
+                            VariantPayload::Struct(members) => members
+                                .iter()
+                                .find(|m| ident(&m.name) == field_name)
+                                .map(|m| m.param_type.span()),
+                        })
+                }
+                _ => None,
+            };
+            let name = ident(name);
+            // Through another struct or enum, that one is what to refer to.
+            let (label, inner) = match ty {
+                Ty::Struct(other) | Ty::Enum(other) if other != id => {
+                    let inner = self.show(&ty);
+                    (
+                        format!("`{name}` contains itself through this `{inner}`"),
+                        inner,
+                    )
+                }
+                _ => (format!("`{name}` contains itself here"), name.to_string()),
+            };
+            let diag = Diagnostic::error(format!("recursive type `{name}` has infinite size"))
+                .with_code(codes::CYCLIC_TYPE)
+                .with_primary(span.unwrap_or(decl.span()), label)
+                .with_help(format!("store a reference instead, such as `&{inner}`"));
+            self.error(diag);
+        }
+    }
+
+    /// Whether a value of type `ty` contains a value of the struct or enum
+    /// `target`, not counting what it refers to.
+    fn holds(&self, ty: &Ty, target: DeclId, seen: &mut HashSet<DeclId>) -> bool {
+        match ty {
+            Ty::Struct(id) | Ty::Enum(id) if *id == target => true,
+            Ty::Struct(id) if seen.insert(*id) => self.out.structs[id]
+                .fields
+                .iter()
+                .any(|field| self.holds(&field.ty, target, seen)),
+            Ty::Enum(id) if seen.insert(*id) => self.out.enums[id]
+                .variants
+                .iter()
+                .flat_map(|variant| &variant.fields)
+                .any(|field| self.holds(&field.ty, target, seen)),
+            Ty::Tuple(elements) => elements.iter().any(|ty| self.holds(ty, target, seen)),
+            _ => false,
+        }
+    }
+
+    fn fields(&mut self, members: &[Parameter]) -> Vec<FieldDef> {
+        let mut fields: Vec<FieldDef> = Vec::new();
+        for member in members {
+            let name = ident(&member.name).to_string();
+            if let Some(previous) = fields.iter().position(|f| f.name == name) {
+                let diag = Diagnostic::error(format!("field `{name}` is declared more than once"))
+                    .with_code(codes::DUPLICATE_FIELD)
+                    .with_primary(member.name.span, "declared again here")
+                    .with_secondary(members[previous].name.span, "first declared here");
+                self.error(diag);
+                continue;
+            }
+            let ty = self.lower_type(&member.param_type);
+            fields.push(FieldDef { name, ty });
+        }
+        fields
+    }
+
+    /// Turns a type annotation into a type.
+    fn lower_type(&mut self, ty: &Type) -> Ty {
+        match ty {
+            Type::Identifier(token) => {
+                let name = ident(token);
+                if let Some(ty) = Ty::primitive(name) {
+                    return ty;
+                }
+                if name == "Self" {
+                    if let Some(ty) = &self.frame.self_ty {
+                        return ty.clone();
+                    }
+                    let diag = Diagnostic::error("`Self` is only available in methods")
+                        .with_code(codes::UNKNOWN_TYPE)
+                        .with_primary(token.span, "not inside an `impl` or interface");
+                    self.error(diag);
+                    return Ty::Error;
+                }
+                match self.types.get(name) {
+                    Some(id) if self.out.structs.contains_key(id) => Ty::Struct(*id),
+                    Some(id) if self.out.enums.contains_key(id) => Ty::Enum(*id),
+                    Some(_) => {
+                        let diag = Diagnostic::error(format!(
+                            "interface `{name}` cannot be used as a type"
+                        ))
+                        .with_code(codes::UNKNOWN_TYPE)
+                        .with_primary(token.span, "not a type")
+                        .with_help(format!("derive it with `Type += {name}{{}};`"));
+                        self.error(diag);
+                        Ty::Error
+                    }
+                    None => {
+                        let diag = Diagnostic::error(format!("cannot find type `{name}`"))
+                            .with_code(codes::UNKNOWN_TYPE)
+                            .with_primary(token.span, "not found");
+                        self.error(diag);
+                        Ty::Error
+                    }
+                }
+            }
+            Type::Touple(elements, _) => {
+                Ty::Tuple(elements.iter().map(|ty| self.lower_type(ty)).collect())
+            }
+            Type::Ref(inner, mutable, _) => Ty::Ref(Box::new(self.lower_type(inner)), *mutable),
+            Type::Fn(params, ret, _) => Ty::Fn(Box::new(FnTy {
+                params: params.iter().map(|ty| self.lower_type(ty)).collect(),
+                ret: self.lower_type(ret),
+            })),
+        }
+    }
+
+    fn signature(&mut self, params: &[Parameter], ret: &Type, self_ty: Option<Ty>) -> FnTy {
+        let outer = mem::replace(&mut self.frame.self_ty, self_ty);
+        let sig = FnTy {
+            params: params
+                .iter()
+                .map(|p| self.lower_type(&p.param_type))
+                .collect(),
+            ret: self.lower_type(ret),
+        };
+        self.frame.self_ty = outer;
+        sig
+    }
+
+    /// Records a function's signature and queues its body.
+    fn register_fn(
+        &mut self,
+        name: String,
+        params: &'a [Parameter],
+        return_type: &Type,
+        body: &'a Expr,
+        self_ty: Option<Ty>,
+        span: Span,
+    ) -> FuncId {
+        let id = FuncId(self.sigs.len() as u32);
+        let sig = self.signature(params, return_type, self_ty.clone());
+        self.sigs.push(sig);
+        self.bodies.push(None);
+        self.pending.push(PendingFn {
+            id,
+            name,
+            params,
+            body,
+            self_ty,
+            span,
+        });
+        id
+    }
+
+    fn collect_functions(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            let Node::Stmt(Stmt::Decl(decl)) = node else {
+                continue;
+            };
+            match decl.as_ref() {
+                Decl::Function(func) => {
+                    let id = self.register_fn(
+                        ident(&func.name).to_string(),
+                        &func.params,
+                        &func.return_type,
+                        &func.body,
+                        None,
+                        func.span,
+                    );
+                    if let Some(decl) = self.res.decl_at(func.name.span) {
+                        self.funcs.insert(decl, id);
+                    }
+                    if func.visibility.is_some() {
+                        self.public.insert(id);
+                    }
+                }
+                Decl::Impl(imp) => self.collect_impl(imp),
+                _ => {}
+            }
+        }
+
+        // Derives last, so they see every method written by hand.
+        for node in nodes {
+            if let Node::Stmt(Stmt::Decl(decl)) = node
+                && let Decl::Derive(derive) = decl.as_ref()
+            {
+                self.collect_derive(derive);
+            }
+        }
+    }
+
+    /// The struct or enum named by `name`, reporting anything else.
+    fn owner_type(&mut self, name: &Token) -> Option<(DeclId, Ty)> {
+        let id = self.types.get(ident(name)).copied();
+        match id {
+            Some(id) if self.out.structs.contains_key(&id) => Some((id, Ty::Struct(id))),
+            Some(id) if self.out.enums.contains_key(&id) => Some((id, Ty::Enum(id))),
+            _ => {
+                let diag = Diagnostic::error(format!("`{}` is not a struct or enum", ident(name)))
+                    .with_code(codes::UNKNOWN_TYPE)
+                    .with_primary(name.span, "expected a struct or enum");
+                self.error(diag);
+                None
+            }
+        }
+    }
+
+    fn collect_impl(&mut self, imp: &'a Impl) {
+        let Some((owner, self_ty)) = self.owner_type(&imp.name) else {
+            return;
+        };
+        let owner_name = ident(&imp.name).to_string();
+
+        for node in &imp.members.nodes {
+            match node {
+                Node::Stmt(Stmt::Decl(decl)) if let Decl::Function(func) = decl.as_ref() => {
+                    let method = ident(&func.name).to_string();
+                    let id = self.register_fn(
+                        format!("{owner_name}.{method}"),
+                        &func.params,
+                        &func.return_type,
+                        &func.body,
+                        Some(self_ty.clone()),
+                        func.span,
+                    );
+                    if let Some(decl) = self.res.decl_at(func.name.span) {
+                        self.funcs.insert(decl, id);
+                    }
+                    if func.visibility.is_some() {
+                        self.public.insert(id);
+                    }
+                    self.methods.insert((owner, method), id);
+                }
+                Node::Error(_) | Node::EOF => {}
+                other => {
+                    let diag = Diagnostic::error("only functions may be declared in an `impl`")
+                        .with_code(codes::MISPLACED_ITEM)
+                        .with_primary(other.span(), "not a function");
+                    self.error(diag);
+                }
+            }
+        }
+    }
+
+    fn collect_derive(&mut self, derive: &'a Derive) {
+        let Some((owner, self_ty)) = self.owner_type(&derive.target) else {
+            return;
+        };
+        let Some(interface) = self
+            .types
+            .get(ident(&derive.interface))
+            .and_then(|id| self.interfaces.get(id))
+            .copied()
+        else {
+            let diag = Diagnostic::error(format!(
+                "`{}` is not an interface",
+                ident(&derive.interface)
+            ))
+            .with_code(codes::UNKNOWN_TYPE)
+            .with_primary(derive.interface.span, "expected an interface");
+            self.error(diag);
+            return;
+        };
+        let owner_name = ident(&derive.target).to_string();
+
+        for method in &interface.methods {
+            let name = ident(&method.name).to_string();
+            let expected =
+                self.signature(&method.params, &method.return_type, Some(self_ty.clone()));
+            match (self.methods.get(&(owner, name.clone())), &method.body) {
+                (Some(&own), _) => {
+                    if self.sigs[own.0 as usize] != expected {
+                        let found = Ty::Fn(Box::new(self.sigs[own.0 as usize].clone()));
+                        let expected = Ty::Fn(Box::new(expected));
+                        let diag = Diagnostic::error(format!(
+                            "method `{name}` does not match its declaration in `{}`",
+                            ident(&interface.name)
+                        ))
+                        .with_code(codes::MISSING_METHOD)
+                        .with_primary(
+                            derive.span,
+                            format!(
+                                "expected `{}`, found `{}`",
+                                self.show(&expected),
+                                self.show(&found)
+                            ),
+                        )
+                        .with_secondary(method.span, "declared here");
+                        self.error(diag);
+                    }
+                }
+                (None, Some(body)) => {
+                    // Each deriving type gets its own copy of the default.
+                    let id = self.register_fn(
+                        format!("{owner_name}.{name}"),
+                        &method.params,
+                        &method.return_type,
+                        body,
+                        Some(self_ty.clone()),
+                        method.span,
+                    );
+                    self.methods.insert((owner, name), id);
+                    if method.visibility.is_some() {
+                        self.public.insert(id);
+                    }
+                }
+                (None, None) => {
+                    let diag = Diagnostic::error(format!(
+                        "`{owner_name}` is missing method `{name}` required by `{}`",
+                        ident(&interface.name)
+                    ))
+                    .with_code(codes::MISSING_METHOD)
+                    .with_primary(derive.span, format!("`{name}` not implemented"))
+                    .with_secondary(method.span, "required here")
+                    .with_help(format!("add it in `{owner_name} += impl {{ ... }};`"));
+                    self.error(diag);
+                }
+            }
+        }
+    }
+
+    fn collect_globals(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            if let Node::Stmt(Stmt::Decl(decl)) = node
+                && let Decl::Variable(var) = decl.as_ref()
+                && let Some(id) = self.res.decl_at(var.name.span)
+            {
+                let hint = var.type_hint.as_ref().map(|ty| self.lower_type(ty));
+                self.globals.insert(id, GlobalState::Pending(var, hint));
+                self.global_order.push(id);
+            }
+        }
+    }
+
+    /// The type of a global, checking its initializer first if the type is
+    /// not written down.
+    fn global_ty(&mut self, id: DeclId, use_span: Span) -> Ty {
+        match self.globals.get(&id) {
+            Some(GlobalState::Done(ty)) => ty.clone(),
+            Some(GlobalState::Pending(_, Some(hint))) => hint.clone(),
+            Some(GlobalState::Pending(_, None)) => self.check_global(id),
+            Some(GlobalState::InProgress) => {
+                let name = &self.res.symbol(id).name;
+                let diag = Diagnostic::error(format!(
+                    "cycle detected when computing the type of `{name}`"
+                ))
+                .with_code(codes::CYCLIC_TYPE)
+                .with_primary(use_span, "used here while its type is still being computed")
+                .with_help(format!("give `{name}` a type annotation"));
+                self.error(diag);
+                Ty::Error
+            }
+            None => Ty::Error,
+        }
+    }
+
+    fn check_global(&mut self, id: DeclId) -> Ty {
+        let (var, hint) = match self.globals.get(&id) {
+            Some(GlobalState::Pending(var, hint)) => (*var, hint.clone()),
+            // Already checked on demand.
+            Some(GlobalState::Done(ty)) => return ty.clone(),
+            _ => return Ty::Error,
+        };
+        self.globals.insert(id, GlobalState::InProgress);
+
+        let outer = mem::take(&mut self.frame);
+        let watermark = self.infer.watermark();
+        let (mut value, ty) = self.initializer(var, hint);
+        let mut frame = mem::replace(&mut self.frame, outer);
+        self.finish_item(watermark, &mut value, &mut frame.locals);
+
+        let ty = self.infer.resolve(&ty);
+        self.global_values.insert(
+            id,
+            TGlobal {
+                id,
+                name: ident(&var.name).to_string(),
+                ty: ty.clone(),
+                value,
+                locals: frame.locals,
+                span: var.span,
+            },
+        );
+        self.globals.insert(id, GlobalState::Done(ty.clone()));
+        ty
+    }
+
+    /// Checks the value of a variable and returns it with the variable's
+    /// type: `hint` if there is one, otherwise that of the value.
+    fn initializer(&mut self, var: &'a Variable, hint: Option<Ty>) -> (TExpr, Ty) {
+        if let Some(hint) = hint {
+            return (self.check(&var.expr, &hint), hint);
+        }
+
+        if matches!(var.expr, Expr::StructLiteral(_) | Expr::Variant(_)) {
+            let name = ident(&var.name);
+            let diag = Diagnostic::error(format!("type annotations needed for `{name}`"))
+                .with_code(codes::CANNOT_INFER)
+                .with_primary(var.name.span, "cannot infer the type of this variable")
+                .with_secondary(var.expr.span(), "this literal needs a known type")
+                .with_help(format!("annotate it: `{name} : Type = ...`"));
+            self.error(diag);
+            let value = self.expr(&var.expr, Some(&Ty::Error));
+            return (value, Ty::Error);
+        }
+
+        let value = self.expr(&var.expr, None);
+        let ty = value.ty.clone();
+        (value, ty)
+    }
+
+    /// Ends inference for an item whose checking started at `watermark`:
+    /// defaults its remaining literals and writes the inferred types into
+    /// `body` and `locals`.
+    fn finish_item(
+        &mut self,
+        watermark: usize,
+        body: &mut TExpr,
+        locals: &mut HashMap<DeclId, TLocal>,
+    ) {
+        self.infer.default_from(watermark);
+        self.infer.zonk(body);
+        for local in locals.values_mut() {
+            local.ty = self.infer.resolve(&local.ty);
+        }
+
+        // Literals of an enclosing item may still be unresolved.
+        for literal in mem::take(&mut self.literals) {
+            match (self.infer.shallow(&literal.ty), &literal.value) {
+                (Ty::Int(int), LiteralValue::Int { value, negated }) => {
+                    self.check_range(*value, int, *negated, literal.span)
+                }
+                (Ty::Float(float), LiteralValue::Float(value)) => {
+                    self.check_float_range(*value, float, literal.span)
+                }
+                (Ty::IntVar(_) | Ty::FloatVar(_), _) => self.literals.push(literal),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_functions(&mut self) {
+        // Bodies may queue nested functions, so the list can grow.
+        let mut i = 0;
+        while i < self.pending.len() {
+            let pending = self.pending[i].clone();
+            i += 1;
+
+            let sig = self.sigs[pending.id.0 as usize].clone();
+            let outer = mem::replace(
+                &mut self.frame,
+                Frame {
+                    ret: Some(sig.ret.clone()),
+                    self_ty: pending.self_ty.clone(),
+                    locals: HashMap::new(),
+                },
+            );
+
+            let mut params = Vec::new();
+            for (param, ty) in pending.params.iter().zip(&sig.params) {
+                if let Some(id) = self.res.decl_at(param.name.span) {
+                    self.frame.locals.insert(
+                        id,
+                        TLocal {
+                            name: ident(&param.name).to_string(),
+                            ty: ty.clone(),
+                        },
+                    );
+                    params.push(id);
+                }
+            }
+            let watermark = self.infer.watermark();
+            let mut body = self.check(pending.body, &sig.ret);
+
+            let mut frame = mem::replace(&mut self.frame, outer);
+            self.finish_item(watermark, &mut body, &mut frame.locals);
+            self.bodies[pending.id.0 as usize] = Some(TFunction {
+                name: pending.name,
+                public: self.public.contains(&pending.id),
+                params,
+                ty: sig,
+                body,
+                locals: frame.locals,
+                span: pending.span,
+            });
+        }
+    }
+
+    fn finish(mut self, nodes: &[Node]) -> (TProgram, Vec<Diagnostic>) {
+        self.out.functions = self.bodies.into_iter().flatten().collect();
+        for id in &self.global_order {
+            if let Some(global) = self.global_values.remove(id) {
+                self.out.globals.push(global);
+            }
+        }
+        self.out.main = nodes.iter().find_map(|node| match node {
+            Node::Stmt(Stmt::Decl(decl)) => match decl.as_ref() {
+                Decl::Function(func) if ident(&func.name) == "main" => self
+                    .res
+                    .decl_at(func.name.span)
+                    .and_then(|id| self.funcs.get(&id).copied()),
+                _ => None,
+            },
+            _ => None,
+        });
+        (self.out, self.diagnostics)
+    }
+
+    // Expressions
+
+    /// Checks `expr`, using `expected` as a hint. Callers that require the
+    /// type use [`Checker::check`] instead.
+    ///
+    /// Types going in and out are resolved as far as inference allows, so
+    /// the rules below can match on them directly.
+    fn expr(&mut self, expr: &'a Expr, expected: Option<&Ty>) -> TExpr {
+        let expected = expected.map(|ty| self.infer.shallow(ty));
+        let mut texpr = self.expr_inner(expr, expected.as_ref());
+        texpr.ty = self.infer.shallow(&texpr.ty);
+        texpr
+    }
+
+    fn expr_inner(&mut self, expr: &'a Expr, expected: Option<&Ty>) -> TExpr {
+        match expr {
+            Expr::Atom(token) => self.atom(token, expected),
+            Expr::BinOp(op) => self.binary(op, expected),
+            Expr::UnaryOp(op) => self.unary(op, expected),
+            Expr::Assign(assign) => self.assign(assign),
+            Expr::Pipe(pipe) => {
+                // `x |> f(a)` is `f(x, a)` and `x |> f` is `f(x)`.
+                let lhs = self.expr(&pipe.lhs, None);
+                match &pipe.rhs {
+                    Expr::Call(call) => self.call(&call.callee, Some(lhs), &call.args, pipe.span),
+                    rhs => self.call(rhs, Some(lhs), &[], pipe.span),
+                }
+            }
+            Expr::Field(field) => self.field(field),
+            Expr::Block(block) => self.block(block, expected),
+            Expr::Call(call) => {
+                let mut texpr = self.call(&call.callee, None, &call.args, call.span);
+                if let Some(annotation) = call.tail {
+                    match &mut texpr.kind {
+                        TExprKind::Call { annotated, .. } => *annotated = true,
+                        TExprKind::Error => {}
+                        _ => {
+                            let diag = Diagnostic::error("`#tail` must annotate a function call")
+                                .with_code(codes::NOT_TAIL_CALL)
+                                .with_primary(call.span, "this constructs a value")
+                                .with_secondary(annotation, "annotation here");
+                            self.error(diag);
+                        }
+                    }
+                }
+                texpr
+            }
+            Expr::StructLiteral(lit) => self.struct_literal(lit, expected),
+            Expr::Switch(switch) => self.switch(switch, expected),
+            Expr::Return(ret) => self.ret(ret),
+            Expr::Variant(variant) => self.variant_literal(variant, expected),
+            // Expansion failed and was reported.
+            Expr::MacroCall(call) => TExpr::error(call.span),
+        }
+    }
+
+    fn atom(&mut self, token: &Token, expected: Option<&Ty>) -> TExpr {
+        match &token.kind {
+            TokenType::Int(lit) => self.int_literal(lit, token.span, expected, false),
+            TokenType::Float(lit) => self.float_literal(lit, token.span, expected),
+            TokenType::String(text) => TExpr {
+                kind: TExprKind::Str(self.unescape(text, token.span)),
+                ty: Ty::Str,
+                span: token.span,
+            },
+            TokenType::Ident(_) => self.name(token),
+            _ => TExpr::error(token.span),
+        }
+    }
+
+    /// Types an integer literal from its suffix or context, defaulting to
+    /// `i32`.
+    fn int_literal(
+        &mut self,
+        lit: &IntLit,
+        span: Span,
+        expected: Option<&Ty>,
+        negated: bool,
+    ) -> TExpr {
+        let Some(value) = lit.value else {
+            let diag = Diagnostic::error("integer literal is too large")
+                .with_code(codes::LITERAL_OUT_OF_RANGE)
+                .with_primary(span, "does not fit in 64 bits");
+            self.error(diag);
+            return TExpr::error(span);
+        };
+
+        let suffix = lit.suffix.as_deref().and_then(Ty::primitive);
+        let ty = match suffix.as_ref().or(expected) {
+            Some(ty @ (Ty::Float(_) | Ty::FloatVar(_))) => {
+                return TExpr {
+                    kind: TExprKind::Float(value as f64),
+                    ty: ty.clone(),
+                    span,
+                };
+            }
+            Some(Ty::Int(int)) => {
+                self.check_range(value, *int, negated, span);
+                Ty::Int(*int)
+            }
+            Some(ty @ Ty::IntVar(_)) => ty.clone(),
+            _ => self.infer.int_var(),
+        };
+        if let Ty::IntVar(_) = ty {
+            self.literals.push(PendingLiteral {
+                ty: ty.clone(),
+                value: LiteralValue::Int { value, negated },
+                span,
+            });
+        }
+
+        TExpr {
+            kind: TExprKind::Int(value),
+            ty,
+            span,
+        }
+    }
+
+    /// Types a float literal from its suffix or context, defaulting to
+    /// `f64`.
+    fn float_literal(&mut self, lit: &FloatLit, span: Span, expected: Option<&Ty>) -> TExpr {
+        let suffix = lit.suffix.as_deref().and_then(Ty::primitive);
+        let ty = match suffix.as_ref().or(expected) {
+            Some(Ty::Float(float)) => {
+                self.check_float_range(lit.value, *float, span);
+                Ty::Float(*float)
+            }
+            Some(ty @ Ty::FloatVar(_)) => ty.clone(),
+            _ => self.infer.float_var(),
+        };
+        if let Ty::FloatVar(_) = ty {
+            self.literals.push(PendingLiteral {
+                ty: ty.clone(),
+                value: LiteralValue::Float(lit.value),
+                span,
+            });
+        }
+
+        TExpr {
+            kind: TExprKind::Float(lit.value),
+            ty,
+            span,
+        }
+    }
+
+    fn check_range(&mut self, value: u64, int: IntTy, negated: bool, span: Span) {
+        // `-128` fits in `i8` even though `128` does not.
+        let max = if negated && int.is_signed() {
+            int.max() + 1
+        } else {
+            int.max()
+        };
+        if value > max || (negated && !int.is_signed() && value > 0) {
+            let diag = Diagnostic::error(format!("literal out of range for `{}`", int.as_str()))
+                .with_code(codes::LITERAL_OUT_OF_RANGE)
+                .with_primary(span, format!("`{}` has no such value", int.as_str()));
+            self.error(diag);
+        }
+    }
+
+    fn check_float_range(&mut self, value: f64, float: FloatTy, span: Span) {
+        if value > float.max() {
+            let diag = Diagnostic::error(format!("literal out of range for `{}`", float.as_str()))
+                .with_code(codes::LITERAL_OUT_OF_RANGE)
+                .with_primary(span, format!("too large for `{}`", float.as_str()));
+            self.error(diag);
+        }
+    }
+
+    /// Decodes `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\'`.
+    fn unescape(&mut self, text: &str, span: Span) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('r') => out.push('\r'),
+                Some('0') => out.push('\0'),
+                Some(c @ ('\\' | '"' | '\'')) => out.push(c),
+                other => {
+                    let seq = other.map_or("\\".to_string(), |c| format!("\\{c}"));
+                    let diag = Diagnostic::error(format!("unknown escape sequence `{seq}`"))
+                        .with_code(codes::INVALID_ESCAPE)
+                        .with_primary(span, "in this string")
+                        .with_help("supported escapes are \\n \\t \\r \\0 \\\\ \\\" and \\'");
+                    self.error(diag);
+                }
+            }
+        }
+        out
+    }
+
+    fn name(&mut self, token: &Token) -> TExpr {
+        let span = token.span;
+        let Some(id) = self.res.use_of(span) else {
+            return TExpr::error(span);
+        };
+        let symbol = self.res.symbol(id);
+        let (kind, ty) = match symbol.kind {
+            SymbolKind::Builtin => match symbol.name.as_str() {
+                "true" => (TExprKind::Bool(true), Ty::Bool),
+                "false" => (TExprKind::Bool(false), Ty::Bool),
+                name => {
+                    let diag = Diagnostic::error(format!("`{name}` can only be called"))
+                        .with_code(codes::NOT_A_VALUE)
+                        .with_primary(span, "not a value")
+                        .with_help(format!("call it directly: `{name}(...)`"));
+                    self.error(diag);
+                    return TExpr::error(span);
+                }
+            },
+            SymbolKind::Variable | SymbolKind::Param | SymbolKind::Capture => {
+                if let Some(local) = self.frame.locals.get(&id) {
+                    (TExprKind::Local(id), local.ty.clone())
+                } else if symbol.global {
+                    (TExprKind::Global(id), self.global_ty(id, span))
+                } else {
+                    let diag = Diagnostic::error(format!(
+                        "cannot use `{}` from an enclosing function",
+                        symbol.name
+                    ))
+                    .with_code(codes::CAPTURED_LOCAL)
+                    .with_primary(span, "captured here")
+                    .with_secondary(symbol.span, "declared here")
+                    .with_note("nested functions cannot capture locals; pass it as a parameter");
+                    self.error(diag);
+                    return TExpr::error(span);
+                }
+            }
+            SymbolKind::Function => match self.funcs.get(&id) {
+                Some(&func) => (
+                    TExprKind::Function(func),
+                    Ty::Fn(Box::new(self.sigs[func.0 as usize].clone())),
+                ),
+                None => return TExpr::error(span),
+            },
+            SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Interface => {
+                let diag =
+                    Diagnostic::error(format!("expected a value, found type `{}`", symbol.name))
+                        .with_code(codes::NOT_A_VALUE)
+                        .with_primary(span, "not a value");
+                self.error(diag);
+                return TExpr::error(span);
+            }
+        };
+        TExpr { kind, ty, span }
+    }
+
+    /// The struct or enum `expr` names, if it is a bare type name.
+    fn type_name(&self, expr: &Expr) -> Option<(DeclId, SymbolKind)> {
+        let Expr::Atom(token) = expr else {
+            return None;
+        };
+        let id = self.res.use_of(token.span)?;
+        match self.res.symbol(id).kind {
+            kind @ (SymbolKind::Struct | SymbolKind::Enum) => Some((id, kind)),
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, op: &'a parser::node::BinOp, expected: Option<&Ty>) -> TExpr {
+        let span = op.span;
+        let Some(bin) = binop(&op.op.kind) else {
+            return TExpr::error(span);
+        };
+
+        let (lhs, rhs, ty) = match bin {
+            BinOp::And | BinOp::Or => {
+                let lhs = self.check(&op.lhs, &Ty::Bool);
+                let rhs = self.check(&op.rhs, &Ty::Bool);
+                (lhs, rhs, Ty::Bool)
+            }
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                let expected = expected.filter(|ty| ty.is_numeric());
+                let (lhs, rhs, ok) = self.operands(bin, &op.lhs, &op.rhs, expected, Ty::is_numeric);
+                let ty = if ok { lhs.ty.clone() } else { Ty::Error };
+                (lhs, rhs, ty)
+            }
+            BinOp::Eq | BinOp::Ne => {
+                let equatable = |ty: &Ty| ty.is_numeric() || matches!(ty, Ty::Bool | Ty::Str);
+                let (lhs, rhs, _) = self.operands(bin, &op.lhs, &op.rhs, None, equatable);
+                (lhs, rhs, Ty::Bool)
+            }
+            _ => {
+                let (lhs, rhs, _) = self.operands(bin, &op.lhs, &op.rhs, None, Ty::is_numeric);
+                (lhs, rhs, Ty::Bool)
+            }
+        };
+
+        TExpr {
+            kind: TExprKind::Binary {
+                op: bin,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            },
+            ty,
+            span,
+        }
+    }
+
+    /// Checks both operands of `op` against each other and reports an
+    /// operand type that `allowed` rejects. Unification lets a literal take
+    /// the type of the other side: `1 + x` is fine when `x` is a `u8`.
+    fn operands(
+        &mut self,
+        op: BinOp,
+        lhs: &'a Expr,
+        rhs: &'a Expr,
+        expected: Option<&Ty>,
+        allowed: impl Fn(&Ty) -> bool,
+    ) -> (TExpr, TExpr, bool) {
+        let lhs = self.expr(lhs, expected);
+        let ok = allowed(&lhs.ty) || lhs.ty.is_bottom();
+        let rhs = if ok {
+            let rhs = self.expr(rhs, Some(&lhs.ty));
+            self.coerce(rhs, &lhs.ty)
+        } else {
+            self.invalid_operand(op, &lhs);
+            self.expr(rhs, None)
+        };
+        (lhs, rhs, ok)
+    }
+
+    fn invalid_operand(&mut self, op: BinOp, operand: &TExpr) {
+        let diag = Diagnostic::error(format!(
+            "cannot apply `{}` to `{}`",
+            op.as_str(),
+            self.show(&operand.ty)
+        ))
+        .with_code(codes::INVALID_OPERAND)
+        .with_primary(
+            operand.span,
+            format!("this is `{}`", self.show(&operand.ty)),
+        );
+        self.error(diag);
+    }
+
+    fn unary(&mut self, op: &'a UnaryOp, expected: Option<&Ty>) -> TExpr {
+        let span = op.span;
+        let (unop, expr, ty) = match op.op.kind {
+            TokenType::Minus => {
+                // A negated literal is range checked as a whole, which
+                // already rejects negative values of unsigned types.
+                let (expr, literal) = match &op.expr {
+                    Expr::Atom(Token {
+                        kind: TokenType::Int(lit),
+                        span,
+                    }) => (self.int_literal(lit, *span, expected, true), true),
+                    operand => (self.expr(operand, expected), false),
+                };
+                let ok = match &expr.ty {
+                    Ty::Int(int) => int.is_signed() || literal,
+                    ty => ty.is_numeric() || ty.is_bottom(),
+                };
+                if !ok {
+                    let diag =
+                        Diagnostic::error(format!("cannot negate `{}`", self.show(&expr.ty)))
+                            .with_code(codes::INVALID_OPERAND)
+                            .with_primary(expr.span, "expected a signed number");
+                    self.error(diag);
+                }
+                let ty = expr.ty.clone();
+                (UnOp::Neg, expr, ty)
+            }
+            TokenType::Bang => (UnOp::Not, self.check(&op.expr, &Ty::Bool), Ty::Bool),
+            TokenType::Amp => {
+                let inner = match expected {
+                    Some(Ty::Ref(inner, _)) => Some(inner.as_ref()),
+                    _ => None,
+                };
+                let expr = self.expr(&op.expr, inner);
+                if op.mutable {
+                    self.writable(&expr, "take `&mut`");
+                }
+                let ty = Ty::Ref(Box::new(expr.ty.clone()), op.mutable);
+                (UnOp::Ref, expr, ty)
+            }
+            TokenType::Star => {
+                let expr = self.expr(&op.expr, None);
+                let ty = match &expr.ty {
+                    Ty::Ref(inner, _) => inner.as_ref().clone(),
+                    ty if ty.is_bottom() => Ty::Error,
+                    ty => {
+                        let diag =
+                            Diagnostic::error(format!("cannot dereference `{}`", self.show(ty)))
+                                .with_code(codes::INVALID_OPERAND)
+                                .with_primary(expr.span, "not a reference");
+                        self.error(diag);
+                        Ty::Error
+                    }
+                };
+                (UnOp::Deref, expr, ty)
+            }
+            _ => return TExpr::error(span),
+        };
+
+        TExpr {
+            kind: TExprKind::Unary {
+                op: unop,
+                expr: Box::new(expr),
+            },
+            ty,
+            span,
+        }
+    }
+
+    fn assign(&mut self, assign: &'a Assign) -> TExpr {
+        let target = self.expr(&assign.target, None);
+        let is_place = matches!(
+            target.kind,
+            TExprKind::Local(_)
+                | TExprKind::Global(_)
+                | TExprKind::Field { .. }
+                | TExprKind::Unary {
+                    op: UnOp::Deref,
+                    ..
+                }
+                | TExprKind::Error
+        );
+        if !is_place {
+            let diag = Diagnostic::error("invalid left-hand side of assignment")
+                .with_code(codes::INVALID_ASSIGN_TARGET)
+                .with_primary(target.span, "cannot assign to this expression");
+            self.error(diag);
+        }
+        self.writable(&target, "assign");
+
+        let op = match assign.op.kind {
+            TokenType::Eql => None,
+            ref kind => binop(kind),
+        };
+        if let Some(op) = op
+            && !target.ty.is_numeric()
+            && !target.ty.is_bottom()
+        {
+            self.invalid_operand(op, &target);
+        }
+        let value = self.check(&assign.value, &target.ty);
+
+        TExpr {
+            kind: TExprKind::Assign {
+                op,
+                target: Box::new(target),
+                value: Box::new(value),
+            },
+            ty: Ty::Void,
+            span: assign.span,
+        }
+    }
+
+    /// Reports `place` if it is reached through a `&` reference, which
+    /// only allows reads. Whether the variable it belongs to may change is
+    /// left to [`crate::mutability`].
+    fn writable(&mut self, place: &TExpr, action: &str) {
+        match &place.kind {
+            TExprKind::Field { base, .. } => self.writable(base, action),
+            TExprKind::Unary {
+                op: UnOp::Deref,
+                expr,
+            } => {
+                let Ty::Ref(inner, false) = &expr.ty else {
+                    return;
+                };
+                let mutable = Ty::Ref(inner.clone(), true);
+                let diag = Diagnostic::error(format!(
+                    "cannot {action} through a `{}`",
+                    self.show(&expr.ty)
+                ))
+                .with_code(codes::WRITE_THROUGH_REF)
+                .with_primary(
+                    expr.span,
+                    "this is a `&` reference, which only allows reads",
+                )
+                .with_help(format!("use a `{}` instead", self.show(&mutable)));
+                self.error(diag);
+            }
+            _ => {}
+        }
+    }
+
+    /// Inserts dereferences until `expr` is not a reference.
+    fn auto_deref(&mut self, mut expr: TExpr) -> TExpr {
+        while let Ty::Ref(inner, _) = &expr.ty {
+            let ty = inner.as_ref().clone();
+            let span = expr.span;
+            expr = TExpr {
+                kind: TExprKind::Unary {
+                    op: UnOp::Deref,
+                    expr: Box::new(expr),
+                },
+                ty,
+                span,
+            };
+        }
+        expr
+    }
+
+    fn field(&mut self, field: &'a Field) -> TExpr {
+        let name = ident(&field.name);
+        match self.type_name(&field.base) {
+            Some((id, SymbolKind::Enum)) => {
+                return self.variant(id, &field.name, field.span, Payload::None, Vec::new());
+            }
+            Some((id, _)) => {
+                // `Person.new` without calling it.
+                return match self.methods.get(&(id, name.to_string())) {
+                    Some(&func) => TExpr {
+                        kind: TExprKind::Function(func),
+                        ty: Ty::Fn(Box::new(self.sigs[func.0 as usize].clone())),
+                        span: field.span,
+                    },
+                    None => {
+                        self.no_member(id, &field.name);
+                        TExpr::error(field.span)
+                    }
+                };
+            }
+            None => {}
+        }
+
+        let base = self.expr(&field.base, None);
+        self.field_access(base, &field.name, field.span)
+    }
+
+    fn field_access(&mut self, base: TExpr, name: &Token, span: Span) -> TExpr {
+        let base = self.auto_deref(base);
+        let text = ident(name);
+        let id = match &base.ty {
+            Ty::Struct(id) => *id,
+            ty if ty.is_bottom() => return TExpr::error(span),
+            Ty::Enum(id) => {
+                let id = *id;
+                self.no_member(id, name);
+                return TExpr::error(span);
+            }
+            ty => {
+                let diag = Diagnostic::error(format!("`{}` has no fields", self.show(ty)))
+                    .with_code(codes::UNKNOWN_FIELD)
+                    .with_primary(name.span, "unknown field");
+                self.error(diag);
+                return TExpr::error(span);
+            }
+        };
+
+        match self.out.structs[&id].field(text) {
+            Some((index, def)) => {
+                let ty = def.ty.clone();
+                TExpr {
+                    kind: TExprKind::Field {
+                        base: Box::new(base),
+                        index,
+                    },
+                    ty,
+                    span,
+                }
+            }
+            None if self.methods.contains_key(&(id, text.to_string())) => {
+                let diag = Diagnostic::error(format!("method `{text}` must be called"))
+                    .with_code(codes::UNKNOWN_FIELD)
+                    .with_primary(name.span, "this is a method")
+                    .with_help(format!("call it: `.{text}(...)`"));
+                self.error(diag);
+                TExpr::error(span)
+            }
+            None => {
+                self.no_member(id, name);
+                TExpr::error(span)
+            }
+        }
+    }
+
+    fn no_member(&mut self, owner: DeclId, name: &Token) {
+        let owner = self.out.type_name(owner).to_string();
+        let diag = Diagnostic::error(format!(
+            "no field, variant or method named `{}` on `{owner}`",
+            ident(name)
+        ))
+        .with_code(codes::UNKNOWN_FIELD)
+        .with_primary(name.span, "unknown member");
+        self.error(diag);
+    }
+
+    /// Checks a call to `printf` against its format string; see
+    /// [`crate::format`].
+    fn printf(&mut self, callee: Span, prefix: Vec<TExpr>, args: &'a [Expr], span: Span) -> TExpr {
+        let void = |args| TExpr {
+            kind: TExprKind::Call {
+                callee: Box::new(TExpr {
+                    kind: TExprKind::Builtin(Builtin::Printf),
+                    ty: Ty::Error,
+                    span: callee,
+                }),
+                args,
+                tail: false,
+                annotated: false,
+            },
+            ty: Ty::Void,
+            span,
+        };
+
+        // The raw text of the literal, to point into it; a piped format
+        // string has already been decoded.
+        let (format, raw, rest) = match (prefix.into_iter().next(), args.split_first()) {
+            (Some(format), _) => (format, None, args),
+            (None, Some((first, rest))) => {
+                let raw = match first {
+                    Expr::Atom(Token {
+                        kind: TokenType::String(text),
+                        ..
+                    }) => Some(text.as_str()),
+                    _ => None,
+                };
+                (self.check(first, &Ty::Str), raw, rest)
+            }
+            (None, None) => {
+                let diag = Diagnostic::error("`printf` takes a format string")
+                    .with_code(codes::ARG_COUNT)
+                    .with_primary(span, "missing format string")
+                    .with_help("for example `printf(\"{d}\\n\", x)`");
+                self.error(diag);
+                return void(Vec::new());
+            }
+        };
+        let mut targs = vec![format];
+        for arg in rest {
+            targs.push(self.expr(arg, None));
+        }
+
+        let TExprKind::Str(decoded) = &targs[0].kind else {
+            if !targs[0].ty.is_bottom() {
+                let diag = Diagnostic::error("format string must be a string literal")
+                    .with_code(codes::INVALID_FORMAT)
+                    .with_primary(targs[0].span, "not a literal")
+                    .with_help("print the value with `printf(\"{s}\", ...)`");
+                self.error(diag);
+            }
+            return void(targs);
+        };
+        let format_span = targs[0].span;
+        let text = raw.unwrap_or(decoded).to_string();
+        let at = |range: &Range<usize>| match raw {
+            Some(raw) => literal_span(format_span, raw, range),
+            None => format_span,
+        };
+
+        let pieces = match format::parse(&text) {
+            Ok(pieces) => pieces,
+            Err(e) => {
+                let diag = Diagnostic::error(e.message)
+                    .with_code(codes::INVALID_FORMAT)
+                    .with_primary(at(&e.range), e.label)
+                    .with_note(
+                        "placeholders are `{s}`, `{d}` and `{f}`; `{{` and `}}` print braces",
+                    );
+                self.error(diag);
+                return void(targs);
+            }
+        };
+
+        let specs: Vec<_> = format::specs(&pieces).collect();
+        let given = targs.len() - 1;
+        if specs.len() != given {
+            let plural = |n: usize, what: &str| match n {
+                1 => format!("1 {what}"),
+                n => format!("{n} {what}s"),
+            };
+            let message = format!(
+                "format string has {} but {} {} given",
+                plural(specs.len(), "placeholder"),
+                plural(given, "argument"),
+                if given == 1 { "was" } else { "were" }
+            );
+            let diag = Diagnostic::error(message).with_code(codes::FORMAT_ARG_COUNT);
+            let diag = match specs.get(given) {
+                Some((_, range)) => diag
+                    .with_primary(at(range), "no argument for this placeholder")
+                    .with_secondary(span, "in this call"),
+                None => diag
+                    .with_primary(
+                        targs[specs.len() + 1].span,
+                        "this argument is never printed",
+                    )
+                    .with_secondary(format_span, "in this format string"),
+            };
+            self.error(diag);
+        }
+
+        for ((spec, range), arg) in specs.iter().zip(&targs[1..]) {
+            let ty = self.infer.shallow(&arg.ty);
+            let fits = match spec {
+                Spec::Str => matches!(ty, Ty::Str),
+                Spec::Int => matches!(ty, Ty::Int(_) | Ty::IntVar(_)),
+                Spec::Float => matches!(ty, Ty::Float(_) | Ty::FloatVar(_)),
+            };
+            if fits || ty.is_bottom() {
+                continue;
+            }
+            let mut diag = Diagnostic::error(format!(
+                "`{}` cannot print a value of type `{}`",
+                spec.as_str(),
+                self.show(&ty)
+            ))
+            .with_code(codes::FORMAT_ARG_TYPE)
+            .with_primary(
+                arg.span,
+                format!("expected {}, found `{}`", spec.expects(), self.show(&ty)),
+            )
+            .with_secondary(at(range), "placeholder for this argument");
+            let better = match ty {
+                Ty::Str => Some(Spec::Str),
+                Ty::Int(_) | Ty::IntVar(_) => Some(Spec::Int),
+                Ty::Float(_) | Ty::FloatVar(_) => Some(Spec::Float),
+                _ => None,
+            };
+            if let Some(better) = better {
+                diag = diag.with_help(format!("use `{}` instead", better.as_str()));
+            }
+            self.error(diag);
+        }
+
+        void(targs)
+    }
+
+    fn call(
+        &mut self,
+        callee: &'a Expr,
+        piped: Option<TExpr>,
+        args: &'a [Expr],
+        span: Span,
+    ) -> TExpr {
+        let prefix: Vec<TExpr> = piped.into_iter().collect();
+
+        // Compiler-provided functions
+        if let Expr::Atom(token) = callee
+            && let Some(id) = self.res.use_of(token.span)
+            && self.res.symbol(id).kind == SymbolKind::Builtin
+            && self.res.symbol(id).name == "printf"
+        {
+            return self.printf(token.span, prefix, args, span);
+        }
+
+        if let Expr::Field(field) = callee {
+            match self.type_name(&field.base) {
+                // `Activity.SwimLaps(8, 50)`
+                Some((id, SymbolKind::Enum)) => {
+                    return self.variant(id, &field.name, span, Payload::Args(args), prefix);
+                }
+                // `Person.new(...)` goes through the generic path below.
+                Some(_) => {}
+                // `value.method(...)`
+                None => {
+                    let base = self.expr(&field.base, None);
+                    let owner = match base.ty.deref_all() {
+                        Ty::Struct(id) | Ty::Enum(id) => Some(*id),
+                        _ => None,
+                    };
+                    let method = owner
+                        .and_then(|id| self.methods.get(&(id, ident(&field.name).to_string())));
+                    let Some(&method) = method else {
+                        let callee = self.field_access(base, &field.name, field.span);
+                        return self.apply(callee, prefix, args, span);
+                    };
+
+                    let sig = self.sigs[method.0 as usize].clone();
+                    let owner_ty = base.ty.deref_all().clone();
+                    let receiver = match sig.params.first() {
+                        Some(Ty::Ref(inner, mutable)) if **inner == owner_ty => {
+                            let base = self.auto_deref(base);
+                            if *mutable {
+                                self.writable(&base, "call a `&mut self` method");
+                            }
+                            let ty = Ty::Ref(Box::new(base.ty.clone()), *mutable);
+                            let span = base.span;
+                            TExpr {
+                                kind: TExprKind::Unary {
+                                    op: UnOp::Ref,
+                                    expr: Box::new(base),
+                                },
+                                ty,
+                                span,
+                            }
+                        }
+                        Some(ty) if *ty == owner_ty => self.auto_deref(base),
+                        _ => {
+                            let diag = Diagnostic::error(format!(
+                                "`{}` does not take `self`",
+                                ident(&field.name)
+                            ))
+                            .with_code(codes::NOT_CALLABLE)
+                            .with_primary(field.name.span, "not a method")
+                            .with_help(format!(
+                                "call it on the type: `{}.{}(...)`",
+                                self.show(&owner_ty),
+                                ident(&field.name)
+                            ));
+                            self.error(diag);
+                            return TExpr::error(span);
+                        }
+                    };
+
+                    let callee = TExpr {
+                        kind: TExprKind::Function(method),
+                        ty: Ty::Fn(Box::new(sig)),
+                        span: field.name.span,
+                    };
+                    let mut all = vec![receiver];
+                    all.extend(prefix);
+                    return self.apply(callee, all, args, span);
+                }
+            }
+        }
+
+        let callee = self.expr(callee, None);
+        self.apply(callee, prefix, args, span)
+    }
+
+    /// Checks a call of `callee` with arguments that were already checked
+    /// (a receiver or piped value) followed by `args`.
+    fn apply(&mut self, callee: TExpr, prefix: Vec<TExpr>, args: &'a [Expr], span: Span) -> TExpr {
+        let sig = match &callee.ty {
+            Ty::Fn(sig) => sig.as_ref().clone(),
+            ty => {
+                if !ty.is_bottom() {
+                    let diag = Diagnostic::error(format!(
+                        "expected a function, found `{}`",
+                        self.show(ty)
+                    ))
+                    .with_code(codes::NOT_CALLABLE)
+                    .with_primary(callee.span, "not callable");
+                    self.error(diag);
+                }
+                for arg in args {
+                    self.expr(arg, None);
+                }
+                return TExpr::error(span);
+            }
+        };
+
+        let supplied = prefix.len() + args.len();
+        if supplied != sig.params.len() {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            let diag = Diagnostic::error(format!(
+                "this function takes {} argument{} but {} {} supplied",
+                sig.params.len(),
+                plural(sig.params.len()),
+                supplied,
+                if supplied == 1 { "was" } else { "were" },
+            ))
+            .with_code(codes::ARG_COUNT)
+            .with_primary(span, "wrong number of arguments");
+            self.error(diag);
+        }
+
+        let mut targs = Vec::new();
+        for (i, arg) in prefix.into_iter().enumerate() {
+            let arg = match sig.params.get(i) {
+                Some(ty) => self.coerce(arg, ty),
+                None => arg,
+            };
+            targs.push(arg);
+        }
+        for arg in args {
+            let arg = match sig.params.get(targs.len()) {
+                Some(ty) => self.check(arg, ty),
+                None => self.expr(arg, None),
+            };
+            targs.push(arg);
+        }
+
+        TExpr {
+            kind: TExprKind::Call {
+                callee: Box::new(callee),
+                args: targs,
+                tail: false,
+                annotated: false,
+            },
+            ty: sig.ret,
+            span,
+        }
+    }
+
+    fn block(&mut self, block: &'a Block, expected: Option<&Ty>) -> TExpr {
+        let mut stmts = Vec::new();
+        let mut tail = None;
+        for node in &block.nodes {
+            match node {
+                Node::Stmt(Stmt::Decl(decl)) => self.local_decl(decl, &mut stmts),
+                Node::Stmt(Stmt::ExprStmt(expr)) => stmts.push(TStmt::Expr(self.expr(expr, None))),
+                Node::Expr(expr) => tail = Some(Box::new(self.expr(expr, expected))),
+                Node::Error(_) | Node::EOF => {}
+            }
+        }
+
+        let ty = match (&tail, stmts.last()) {
+            (Some(tail), _) => tail.ty.clone(),
+            (None, Some(TStmt::Expr(last))) if last.ty == Ty::Never => Ty::Never,
+            _ => Ty::Void,
+        };
+        TExpr {
+            kind: TExprKind::Block(TBlock { stmts, tail }),
+            ty,
+            span: block.span,
+        }
+    }
+
+    fn local_decl(&mut self, decl: &'a Decl, stmts: &mut Vec<TStmt>) {
+        match decl {
+            Decl::Variable(var) => {
+                let hint = var.type_hint.as_ref().map(|ty| self.lower_type(ty));
+                let (value, ty) = self.initializer(var, hint);
+                if let Some(id) = self.res.decl_at(var.name.span) {
+                    self.frame.locals.insert(
+                        id,
+                        TLocal {
+                            name: ident(&var.name).to_string(),
+                            ty,
+                        },
+                    );
+                    stmts.push(TStmt::Let { id, value });
+                }
+            }
+            Decl::Function(func) => {
+                let id = self.register_fn(
+                    ident(&func.name).to_string(),
+                    &func.params,
+                    &func.return_type,
+                    &func.body,
+                    None,
+                    func.span,
+                );
+                if let Some(decl) = self.res.decl_at(func.name.span) {
+                    self.funcs.insert(decl, id);
+                }
+            }
+            Decl::Macro(_) => {}
+            other => {
+                let diag = Diagnostic::error("type declarations must be at the top level")
+                    .with_code(codes::MISPLACED_ITEM)
+                    .with_primary(other.span(), "not allowed inside a block");
+                self.error(diag);
+            }
+        }
+    }
+
+    /// The struct or enum a literal without a type name must have.
+    fn literal_type(&mut self, expected: Option<&Ty>, span: Span, what: &str) -> Option<Ty> {
+        match expected {
+            Some(ty @ (Ty::Struct(_) | Ty::Enum(_))) => Some(ty.clone()),
+            Some(Ty::Error) => None,
+            _ => {
+                let diag = Diagnostic::error(format!("cannot infer the type of this {what}"))
+                    .with_code(codes::CANNOT_INFER)
+                    .with_primary(span, "type must be known here")
+                    .with_help("add a type annotation, e.g. `let p : Person = .{ ... };`");
+                self.error(diag);
+                None
+            }
+        }
+    }
+
+    fn struct_literal(&mut self, lit: &'a StructLiteral, expected: Option<&Ty>) -> TExpr {
+        let id = match self.literal_type(expected, lit.span, "struct literal") {
+            Some(Ty::Struct(id)) => id,
+            Some(ty) => {
+                self.mismatch_literal(lit.span, &ty, "a struct literal");
+                self.skip_inits(&lit.fields);
+                return TExpr::error(lit.span);
+            }
+            None => {
+                self.skip_inits(&lit.fields);
+                return TExpr::error(lit.span);
+            }
+        };
+
+        let def = self.out.structs[&id].clone();
+        let fields = self.field_inits(&def.name, &def.fields, &lit.fields, lit.span);
+        TExpr {
+            kind: TExprKind::Struct { id, fields },
+            ty: Ty::Struct(id),
+            span: lit.span,
+        }
+    }
+
+    fn mismatch_literal(&mut self, span: Span, expected: &Ty, found: &str) {
+        let diag = Diagnostic::error("mismatched types")
+            .with_code(codes::MISMATCHED_TYPES)
+            .with_primary(
+                span,
+                format!("expected `{}`, found {found}", self.show(expected)),
+            );
+        self.error(diag);
+    }
+
+    fn skip_inits(&mut self, inits: &'a [(Token, Expr)]) {
+        for (_, value) in inits {
+            self.expr(value, None);
+        }
+    }
+
+    /// Checks `.name = value` initializers and orders them like `defs`.
+    fn field_inits(
+        &mut self,
+        owner: &str,
+        defs: &[FieldDef],
+        inits: &'a [(Token, Expr)],
+        span: Span,
+    ) -> Vec<TExpr> {
+        let mut values: Vec<Option<TExpr>> = defs.iter().map(|_| None).collect();
+        let mut seen: HashMap<&str, Span> = HashMap::new();
+        for (name, value) in inits {
+            let text = ident(name);
+            let Some(index) = defs.iter().position(|f| f.name == text) else {
+                let diag = Diagnostic::error(format!("`{owner}` has no field named `{text}`"))
+                    .with_code(codes::UNKNOWN_FIELD)
+                    .with_primary(name.span, "unknown field");
+                self.error(diag);
+                self.expr(value, None);
+                continue;
+            };
+            if let Some(first) = seen.insert(text, name.span) {
+                let diag = Diagnostic::error(format!("field `{text}` is initialized twice"))
+                    .with_code(codes::DUPLICATE_FIELD)
+                    .with_primary(name.span, "initialized again here")
+                    .with_secondary(first, "first initialized here");
+                self.error(diag);
+            }
+            values[index] = Some(self.check(value, &defs[index].ty));
+        }
+
+        let missing: Vec<String> = defs
+            .iter()
+            .zip(&values)
+            .filter(|(_, v)| v.is_none())
+            .map(|(f, _)| format!("`{}`", f.name))
+            .collect();
+        if !missing.is_empty() {
+            let diag = Diagnostic::error(format!(
+                "missing field{} {} in initializer of `{owner}`",
+                if missing.len() == 1 { "" } else { "s" },
+                missing.join(", ")
+            ))
+            .with_code(codes::MISSING_FIELD)
+            .with_primary(span, "missing fields");
+            self.error(diag);
+        }
+
+        values
+            .into_iter()
+            .map(|v| v.unwrap_or_else(|| TExpr::error(span)))
+            .collect()
+    }
+
+    fn variant_literal(&mut self, lit: &'a VariantLiteral, expected: Option<&Ty>) -> TExpr {
+        let args = Payload::of(&lit.payload);
+        match self.literal_type(expected, lit.span, "variant") {
+            Some(Ty::Enum(id)) => self.variant(id, &lit.name, lit.span, args, Vec::new()),
+            other => {
+                if let Some(ty) = other {
+                    self.mismatch_literal(lit.span, &ty, "a variant");
+                }
+                match args {
+                    Payload::None => {}
+                    Payload::Args(args) => {
+                        for arg in args {
+                            self.expr(arg, None);
+                        }
+                    }
+                    Payload::Fields(fields) => self.skip_inits(fields),
+                }
+                TExpr::error(lit.span)
+            }
+        }
+    }
+
+    /// Builds variant `name` of enum `id` from its payload.
+    fn variant(
+        &mut self,
+        id: DeclId,
+        name: &Token,
+        span: Span,
+        args: Payload<'a>,
+        prefix: Vec<TExpr>,
+    ) -> TExpr {
+        let def = self.out.enums[&id].clone();
+        let Some((index, variant)) = def.variant(ident(name)) else {
+            self.no_member(id, name);
+            return TExpr::error(span);
+        };
+
+        let shape_error = |this: &mut Self, msg: String, help: String| {
+            let diag = Diagnostic::error(msg)
+                .with_code(codes::MISMATCHED_TYPES)
+                .with_primary(span, "wrong payload")
+                .with_help(help);
+            this.error(diag);
+        };
+
+        let fields = match (&variant.shape, args) {
+            (VariantShape::Unit, Payload::None) => Vec::new(),
+            (VariantShape::Tuple, Payload::Args(args)) => {
+                let supplied = prefix.len() + args.len();
+                if supplied != variant.fields.len() {
+                    let diag = Diagnostic::error(format!(
+                        "variant `{}` takes {} value{} but {} {} supplied",
+                        variant.name,
+                        variant.fields.len(),
+                        if variant.fields.len() == 1 { "" } else { "s" },
+                        supplied,
+                        if supplied == 1 { "was" } else { "were" },
+                    ))
+                    .with_code(codes::ARG_COUNT)
+                    .with_primary(span, "wrong number of values");
+                    self.error(diag);
+                }
+                let mut values = Vec::new();
+                for (i, value) in prefix.into_iter().enumerate() {
+                    values.push(match variant.fields.get(i) {
+                        Some(f) => self.coerce(value, &f.ty),
+                        None => value,
+                    });
+                }
+                for arg in args {
+                    let value = match variant.fields.get(values.len()) {
+                        Some(f) => self.check(arg, &f.ty),
+                        None => self.expr(arg, None),
+                    };
+                    values.push(value);
+                }
+                values.truncate(variant.fields.len());
+                while values.len() < variant.fields.len() {
+                    values.push(TExpr::error(span));
+                }
+                values
+            }
+            (VariantShape::Struct, Payload::Fields(inits)) => {
+                self.field_inits(&variant.name, &variant.fields, inits, span)
+            }
+            (shape, _) => {
+                let usage = match shape {
+                    VariantShape::Unit => format!("`.{}`", variant.name),
+                    VariantShape::Tuple => format!("`.{}(...)`", variant.name),
+                    VariantShape::Struct => format!("`.{}{{ ... }}`", variant.name),
+                };
+                shape_error(
+                    self,
+                    format!("wrong payload for variant `{}`", variant.name),
+                    format!("write it as {usage}"),
+                );
+                return TExpr::error(span);
+            }
+        };
+
+        TExpr {
+            kind: TExprKind::Variant { id, index, fields },
+            ty: Ty::Enum(id),
+            span,
+        }
+    }
+
+    fn switch(&mut self, switch: &'a Switch, expected: Option<&Ty>) -> TExpr {
+        let scrutinee = self.expr(&switch.scrutinee, None);
+        let scrutinee = self.auto_deref(scrutinee);
+        let subject = scrutinee.ty.clone();
+
+        let mut result: Option<Ty> = expected.cloned();
+        let mut arms = Vec::new();
+        let mut covered = HashSet::new();
+        let mut wildcard = false;
+        let mut all_never = !switch.arms.is_empty();
+        for arm in &switch.arms {
+            // Captures the pattern cannot type still need to be locals, so
+            // their uses are not reported again.
+            if let Pattern::Variant {
+                capture: Some(capture),
+                ..
+            } = &arm.pattern
+            {
+                let names = match capture {
+                    Capture::Binding(name) => std::slice::from_ref(name),
+                    Capture::Destructure(names, _) => names.as_slice(),
+                };
+                for name in names {
+                    self.bind(name, &Ty::Error, 0, &mut Vec::new());
+                }
+            }
+            let pattern = self.pattern(&arm.pattern, &subject);
+            match &pattern {
+                TPattern::Wildcard => wildcard = true,
+                TPattern::Variant { index, .. } => {
+                    covered.insert(*index);
+                }
+                TPattern::Bool(b) => {
+                    covered.insert(*b as usize);
+                }
+                _ => {}
+            }
+
+            let body = match &result {
+                Some(ty) => {
+                    let ty = ty.clone();
+                    self.check(&arm.body, &ty)
+                }
+                None => self.expr(&arm.body, None),
+            };
+            if !body.ty.is_bottom() {
+                all_never = false;
+                if result.is_none() {
+                    result = Some(body.ty.clone());
+                }
+            }
+            arms.push(TArm { pattern, body });
+        }
+
+        let ty = match result {
+            Some(ty) => ty,
+            None if all_never => Ty::Never,
+            None => Ty::Void,
+        };
+
+        let exhaustive = wildcard
+            || match &subject {
+                Ty::Enum(id) => covered.len() == self.out.enums[id].variants.len(),
+                Ty::Bool => covered.len() == 2,
+                ty => ty.is_bottom(),
+            };
+        if !exhaustive {
+            if let Ty::Enum(id) = &subject {
+                let missing: Vec<String> = self.out.enums[id]
+                    .variants
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !covered.contains(i))
+                    .map(|(_, v)| format!("`.{}`", v.name))
+                    .collect();
+                let diag = Diagnostic::error(format!(
+                    "non-exhaustive switch: {} not covered",
+                    missing.join(", ")
+                ))
+                .with_code(codes::NON_EXHAUSTIVE)
+                .with_primary(switch.scrutinee.span(), "not every variant is handled")
+                .with_help("add the missing arms or a `_ => ...` arm");
+                self.error(diag);
+            } else if !matches!(ty, Ty::Void | Ty::Never | Ty::Error) {
+                let diag = Diagnostic::error("non-exhaustive switch")
+                    .with_code(codes::NON_EXHAUSTIVE)
+                    .with_primary(
+                        switch.scrutinee.span(),
+                        "a switch used as a value must handle every case",
+                    )
+                    .with_help("add a `_ => ...` arm");
+                self.error(diag);
+            }
+        }
+
+        TExpr {
+            kind: TExprKind::Switch {
+                scrutinee: Box::new(scrutinee),
+                arms,
+            },
+            ty,
+            span: switch.span,
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, subject: &Ty) -> TPattern {
+        let span = pattern.span();
+        let wrong = |this: &mut Self, what: &str| {
+            if !subject.is_bottom() {
+                let diag = Diagnostic::error("mismatched types")
+                    .with_code(codes::MISMATCHED_TYPES)
+                    .with_primary(
+                        span,
+                        format!("expected `{}`, found {what}", this.show(subject)),
+                    );
+                this.error(diag);
+            }
+        };
+
+        match pattern {
+            Pattern::Wildcard(_) => TPattern::Wildcard,
+            Pattern::Literal(token) => match &token.kind {
+                TokenType::Int(lit) => {
+                    if !matches!(subject, Ty::Int(_) | Ty::IntVar(_)) {
+                        wrong(self, "an integer");
+                        return TPattern::Wildcard;
+                    }
+                    let literal = self.int_literal(lit, token.span, Some(subject), false);
+                    let literal = self.coerce(literal, subject);
+                    match literal.kind {
+                        TExprKind::Int(value) => TPattern::Int(value),
+                        _ => TPattern::Wildcard,
+                    }
+                }
+                TokenType::String(text) => {
+                    if *subject != Ty::Str {
+                        wrong(self, "a string");
+                        return TPattern::Wildcard;
+                    }
+                    TPattern::Str(self.unescape(text, token.span))
+                }
+                TokenType::Ident(name) if name == "true" || name == "false" => {
+                    if *subject != Ty::Bool {
+                        wrong(self, "a boolean");
+                        return TPattern::Wildcard;
+                    }
+                    TPattern::Bool(name == "true")
+                }
+                _ => {
+                    wrong(self, "an unsupported pattern");
+                    TPattern::Wildcard
+                }
+            },
+            Pattern::Variant { name, capture, .. } => {
+                let Ty::Enum(id) = subject else {
+                    wrong(self, "a variant");
+                    return TPattern::Wildcard;
+                };
+                let def = self.out.enums[id].clone();
+                let Some((index, variant)) = def.variant(ident(name)) else {
+                    self.no_member(*id, name);
+                    return TPattern::Wildcard;
+                };
+
+                let mut bindings = Vec::new();
+                match capture {
+                    None => {}
+                    Some(Capture::Binding(binding)) => match variant.fields.len() {
+                        1 => self.bind(binding, &variant.fields[0].ty, 0, &mut bindings),
+                        0 => {
+                            let diag = Diagnostic::error(format!(
+                                "`.{}` has no payload to capture",
+                                variant.name
+                            ))
+                            .with_code(codes::MISMATCHED_TYPES)
+                            .with_primary(binding.span, "nothing to capture");
+                            self.error(diag);
+                        }
+                        n => {
+                            let names: Vec<String> = variant
+                                .fields
+                                .iter()
+                                .map(|f| {
+                                    if matches!(variant.shape, VariantShape::Struct) {
+                                        f.name.clone()
+                                    } else {
+                                        format!("_{}", f.name)
+                                    }
+                                })
+                                .collect();
+                            let diag = Diagnostic::error(format!(
+                                "`.{}` carries {n} values",
+                                variant.name
+                            ))
+                            .with_code(codes::MISMATCHED_TYPES)
+                            .with_primary(binding.span, "captures a single value")
+                            .with_help(format!("destructure it: `|{{{}}}|`", names.join(", ")));
+                            self.error(diag);
+                        }
+                    },
+                    Some(Capture::Destructure(names, span)) => match variant.shape {
+                        VariantShape::Struct => {
+                            for name in names {
+                                match variant.fields.iter().position(|f| f.name == ident(name)) {
+                                    Some(i) => {
+                                        self.bind(name, &variant.fields[i].ty, i, &mut bindings)
+                                    }
+                                    None => {
+                                        let diag = Diagnostic::error(format!(
+                                            "`.{}` has no field named `{}`",
+                                            variant.name,
+                                            ident(name)
+                                        ))
+                                        .with_code(codes::UNKNOWN_FIELD)
+                                        .with_primary(name.span, "unknown field");
+                                        self.error(diag);
+                                    }
+                                }
+                            }
+                        }
+                        _ if names.len() == variant.fields.len() => {
+                            // Tuple payloads are destructured by position.
+                            for (i, name) in names.iter().enumerate() {
+                                self.bind(name, &variant.fields[i].ty, i, &mut bindings);
+                            }
+                        }
+                        _ => {
+                            let diag = Diagnostic::error(format!(
+                                "`.{}` carries {} value{}, but {} {} captured",
+                                variant.name,
+                                variant.fields.len(),
+                                if variant.fields.len() == 1 { "" } else { "s" },
+                                names.len(),
+                                if names.len() == 1 { "is" } else { "are" },
+                            ))
+                            .with_code(codes::ARG_COUNT)
+                            .with_primary(*span, "wrong number of captures");
+                            self.error(diag);
+                        }
+                    },
+                }
+                TPattern::Variant { index, bindings }
+            }
+        }
+    }
+
+    fn bind(&mut self, name: &Token, ty: &Ty, field: usize, bindings: &mut Vec<(DeclId, usize)>) {
+        if let Some(id) = self.res.decl_at(name.span) {
+            self.frame.locals.insert(
+                id,
+                TLocal {
+                    name: ident(name).to_string(),
+                    ty: ty.clone(),
+                },
+            );
+            bindings.push((id, field));
+        }
+    }
+
+    fn ret(&mut self, ret: &'a Return) -> TExpr {
+        let Some(expected) = self.frame.ret.clone() else {
+            let diag = Diagnostic::error("`return` outside of a function")
+                .with_code(codes::MISPLACED_ITEM)
+                .with_primary(ret.span, "not inside a function body");
+            self.error(diag);
+            return TExpr::error(ret.span);
+        };
+
+        let value = match &ret.value {
+            Some(value) => Some(Box::new(self.check(value, &expected))),
+            None => {
+                if !matches!(expected, Ty::Void) && !expected.is_bottom() {
+                    self.mismatch(ret.span, &expected, &Ty::Void);
+                }
+                None
+            }
+        };
+        TExpr {
+            kind: TExprKind::Return(value),
+            ty: Ty::Never,
+            span: ret.span,
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! Compile-time evaluation of `const` and `static` initializers.
+//!
+//! The initializer of every `const` or `static` global is evaluated here
+//! and, if that succeeds, replaced by the literal it evaluates to, so that
+//! backends see `Tau` as a number rather than a computation. Evaluation
+//! covers literals, arithmetic and comparisons, struct and enum literals,
+//! blocks, switches, reads of other such globals, and calls to functions
+//! that only do these things.
+//!
+//! Anything only the running program can do stops evaluation, and the
+//! initializer is left to run as before: printing, taking references,
+//! assigning to globals, and reading `mut` globals or globals that are
+//! neither `const` nor `static`, which may have changed by then.
+//!
+//! Errors that running the initializer would certainly hit are reported
+//! instead, at the expression that causes them: integer overflow, which
+//! would otherwise wrap, division by zero, initializers that read each
+//! other, and calls nested deeper than the interpreter allows.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::Span;
+use parser::node::Mutability;
+
+use crate::resolve::{DeclId, Resolution};
+use crate::tast::*;
+use crate::types::{EnumDef, FieldDef, FloatTy, IntTy, StructDef, Ty};
+
+/// Non-tail calls deeper than this are reported, as the interpreter
+/// would at run time, instead of overflowing the compiler's stack.
+const MAX_DEPTH: usize = 10_000;
+
+/// Stack size of the evaluation thread, as for the interpreter: each call
+/// takes a few large Rust frames, and the default is far too small for
+/// `MAX_DEPTH`. Only the pages actually used are committed.
+const STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Likewise for initializers that take more steps than this, so that a
+/// long recursion does not stall compilation.
+const MAX_STEPS: usize = 1_000_000;
+
+/// Evaluates the initializers of the `const` and `static` globals in
+/// `program`, replaces each one that evaluates by its value, and reports
+/// those that would fail at run time.
+pub fn eval_consts(program: &mut TProgram, res: &Resolution) -> Vec<Diagnostic> {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("consteval".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || eval_all(program, res))
+            .expect("failed to spawn the evaluation thread")
+            .join()
+            .expect("constant evaluation panicked")
+    })
+}
+
+fn eval_all(program: &mut TProgram, res: &Resolution) -> Vec<Diagnostic> {
+    let mut eval = Evaluator {
+        program,
+        res,
+        globals: HashMap::new(),
+        evaluating: Vec::new(),
+        depth: 0,
+        steps: 0,
+        diagnostics: Vec::new(),
+    };
+    for global in &program.globals {
+        if is_constant(res, global.id) {
+            eval.init(global.id);
+        }
+    }
+    let Evaluator {
+        globals,
+        diagnostics,
+        ..
+    } = eval;
+
+    for global in &mut program.globals {
+        if let Some(GlobalState::Done(Some(value))) = globals.get(&global.id)
+            && let Some(expr) = literal(
+                value,
+                &global.ty,
+                global.value.span,
+                &program.structs,
+                &program.enums,
+            )
+        {
+            global.value = expr;
+            global.locals.clear();
+        }
+    }
+    diagnostics
+}
+
+/// Whether the global `id` is declared `const` or `static`.
+fn is_constant(res: &Resolution, id: DeclId) -> bool {
+    let symbol = res.symbol(id);
+    symbol.is_static || symbol.mutability == Mutability::Const
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    /// Exact, so that overflow can be detected; always in range for the
+    /// type once an operation completes.
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Void,
+    Function(FuncId),
+    Struct(Vec<Value>),
+    Variant(usize, Vec<Value>),
+}
+
+enum GlobalState {
+    InProgress,
+    /// `None` if the initializer has to run with the program.
+    Done(Option<Value>),
+}
+
+/// Why evaluation stopped before producing a value.
+enum Stop {
+    /// The expression can only be evaluated by the running program.
+    NotConstant,
+    Return(Value),
+    /// A call in tail position, performed by the function being left.
+    TailCall(FuncId, Vec<Value>),
+    Error(Diagnostic),
+}
+
+type Eval<T = Value> = Result<T, Stop>;
+
+/// Locals of one call, or of one global initializer.
+type Frame = HashMap<DeclId, Value>;
+
+struct Evaluator<'a> {
+    program: &'a TProgram,
+    res: &'a Resolution,
+    globals: HashMap<DeclId, GlobalState>,
+    /// The globals whose initializers are running, innermost last.
+    evaluating: Vec<DeclId>,
+    depth: usize,
+    steps: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Evaluator<'_> {
+    /// Evaluates the initializer of `id`, unless that was done already,
+    /// reporting the error it stops with if any.
+    fn init(&mut self, id: DeclId) -> Option<&GlobalState> {
+        if !self.globals.contains_key(&id) {
+            let global = self.program.global(id).expect("unknown global");
+            self.globals.insert(id, GlobalState::InProgress);
+            if self.evaluating.is_empty() {
+                self.steps = 0;
+            }
+            self.evaluating.push(id);
+
+            let value = match self.eval(&global.value, &mut Frame::new()) {
+                Ok(value) | Err(Stop::Return(value)) => Some(value),
+                Err(Stop::NotConstant) => None,
+                Err(Stop::TailCall(..)) => unreachable!("tail call outside a function"),
+                Err(Stop::Error(diag)) => {
+                    self.diagnostics.push(diag);
+                    None
+                }
+            };
+
+            self.evaluating.pop();
+            self.globals.insert(id, GlobalState::Done(value));
+        }
+        self.globals.get(&id)
+    }
+
+    /// The value of the global `id`, read at `span`.
+    fn global(&mut self, id: DeclId, span: Span) -> Eval {
+        if !is_constant(self.res, id) || self.res.symbol(id).mutability == Mutability::Mutable {
+            return Err(Stop::NotConstant);
+        }
+        match self.init(id) {
+            Some(GlobalState::Done(Some(value))) => Ok(value.clone()),
+            // Either not constant, or already reported.
+            Some(GlobalState::Done(None)) | None => Err(Stop::NotConstant),
+            Some(GlobalState::InProgress) => Err(Stop::Error(self.cycle(id, span))),
+        }
+    }
+
+    fn cycle(&self, id: DeclId, span: Span) -> Diagnostic {
+        let name = &self.res.symbol(id).name;
+        let start = self
+            .evaluating
+            .iter()
+            .position(|g| *g == id)
+            .expect("global in progress");
+        let chain: Vec<String> = self.evaluating[start + 1..]
+            .iter()
+            .chain([&id])
+            .map(|g| format!("`{}`", self.res.symbol(*g).name))
+            .collect();
+        let diag = Diagnostic::error(format!("cycle detected when evaluating `{name}`"))
+            .with_code(codes::CONST_CYCLE)
+            .with_primary(
+                span,
+                format!("`{name}` is read here while it is being evaluated"),
+            );
+        let diag = if chain.len() > 1 {
+            diag.with_note(format!("`{name}` needs {}", chain.join(", which needs ")))
+        } else {
+            diag.with_note(format!("the initializer of `{name}` needs its own value"))
+        };
+        diag.with_help("initializers run once, so none of them can depend on itself")
+    }
+
+    /// Stops with an error at `span` in the innermost initializer.
+    fn error(&self, code: &'static str, message: &str, span: Span, label: String) -> Stop {
+        Stop::Error(self.diagnostic(code, message, span, label))
+    }
+
+    /// A diagnostic for an error at `span` in the innermost initializer.
+    fn diagnostic(
+        &self,
+        code: &'static str,
+        message: &str,
+        span: Span,
+        label: String,
+    ) -> Diagnostic {
+        let name = &self
+            .res
+            .symbol(*self.evaluating.last().expect("no initializer"))
+            .name;
+        Diagnostic::error(format!("{message} while evaluating `{name}`"))
+            .with_code(code)
+            .with_primary(span, label)
+            .with_note("`const` and `static` initializers are evaluated at compile time")
+    }
+
+    /// Calls `func` at `span` and runs any tail calls it makes in the same
+    /// loop.
+    fn call(&mut self, func: FuncId, args: Vec<Value>, span: Span) -> Eval {
+        if self.depth == MAX_DEPTH {
+            let diag = self.diagnostic(
+                codes::CONST_STACK_OVERFLOW,
+                "stack overflow",
+                span,
+                format!("more than {MAX_DEPTH} nested calls"),
+            );
+            return Err(Stop::Error(
+                diag.with_help("make the recursive call a tail call"),
+            ));
+        }
+        self.depth += 1;
+
+        let (mut func, mut args) = (func, args);
+        let result = loop {
+            let function = self.program.function(func);
+            let mut frame: Frame = function.params.iter().copied().zip(args).collect();
+            match self.eval(&function.body, &mut frame) {
+                Ok(value) | Err(Stop::Return(value)) => break Ok(value),
+                Err(Stop::TailCall(next, next_args)) => (func, args) = (next, next_args),
+                Err(stop) => break Err(stop),
+            }
+        };
+
+        self.depth -= 1;
+        result
+    }
+
+    fn eval(&mut self, expr: &TExpr, frame: &mut Frame) -> Eval {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(Stop::NotConstant);
+        }
+
+        Ok(match &expr.kind {
+            TExprKind::Int(value) => Value::Int(*value as i128),
+            TExprKind::Float(value) => Value::Float(round(*value, &expr.ty)),
+            TExprKind::Bool(value) => Value::Bool(*value),
+            TExprKind::Str(value) => Value::Str(Rc::from(value.as_str())),
+            TExprKind::Local(id) => frame.get(id).expect("unbound local").clone(),
+            TExprKind::Global(id) => self.global(*id, expr.span)?,
+            TExprKind::Function(func) => Value::Function(*func),
+            TExprKind::Builtin(_) => return Err(Stop::NotConstant),
+            TExprKind::Unary { op, expr: inner } => match (op, self.eval(inner, frame)?) {
+                (UnOp::Neg, Value::Int(v)) => {
+                    self.int(-v, &expr.ty, expr.span, || format!("-{v}"))?
+                }
+                (UnOp::Neg, Value::Float(v)) => Value::Float(-v),
+                (UnOp::Not, Value::Bool(v)) => Value::Bool(!v),
+                _ => return Err(Stop::NotConstant),
+            },
+            TExprKind::Binary { op, lhs, rhs } => self.binary(*op, lhs, rhs, expr, frame)?,
+            TExprKind::Assign { op, target, value } => {
+                let value = self.eval(value, frame)?;
+                let (root, path) = local_place(target).ok_or(Stop::NotConstant)?;
+                let value = match op {
+                    Some(op) => {
+                        let old = field_mut(frame, root, &path).clone();
+                        self.arith(*op, old, value, &target.ty, expr.span)?
+                    }
+                    None => value,
+                };
+                *field_mut(frame, root, &path) = value;
+                Value::Void
+            }
+            TExprKind::Call {
+                callee, args, tail, ..
+            } => {
+                let Value::Function(func) = self.eval(callee, frame)? else {
+                    unreachable!("callee is not a function");
+                };
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.eval(arg, frame)?);
+                }
+                if *tail {
+                    return Err(Stop::TailCall(func, values));
+                }
+                self.call(func, values, expr.span)?
+            }
+            TExprKind::Field { base, index } => match self.eval(base, frame)? {
+                Value::Struct(mut fields) => fields.swap_remove(*index),
+                _ => return Err(Stop::NotConstant),
+            },
+            TExprKind::Struct { fields, .. } => {
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields {
+                    values.push(self.eval(field, frame)?);
+                }
+                Value::Struct(values)
+            }
+            TExprKind::Variant { index, fields, .. } => {
+                let mut values = Vec::with_capacity(fields.len());
+                for field in fields {
+                    values.push(self.eval(field, frame)?);
+                }
+                Value::Variant(*index, values)
+            }
+            TExprKind::Block(block) => {
+                for stmt in &block.stmts {
+                    match stmt {
+                        TStmt::Let { id, value } => {
+                            let value = self.eval(value, frame)?;
+                            frame.insert(*id, value);
+                        }
+                        TStmt::Expr(value) => {
+                            self.eval(value, frame)?;
+                        }
+                    }
+                }
+                match &block.tail {
+                    Some(tail) => self.eval(tail, frame)?,
+                    None => Value::Void,
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => {
+                let value = self.eval(scrutinee, frame)?;
+                for arm in arms {
+                    if matches(&arm.pattern, &value, frame) {
+                        return self.eval(&arm.body, frame);
+                    }
+                }
+                // The program reports this when it runs.
+                return Err(Stop::NotConstant);
+            }
+            TExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, frame)?,
+                    None => Value::Void,
+                };
+                return Err(Stop::Return(value));
+            }
+            TExprKind::Error => unreachable!("programs with errors are not evaluated"),
+        })
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        lhs: &TExpr,
+        rhs: &TExpr,
+        expr: &TExpr,
+        frame: &mut Frame,
+    ) -> Eval {
+        // `&&` and `||` short-circuit.
+        match op {
+            BinOp::And => {
+                return Ok(Value::Bool(
+                    bool(self.eval(lhs, frame)?) && bool(self.eval(rhs, frame)?),
+                ));
+            }
+            BinOp::Or => {
+                return Ok(Value::Bool(
+                    bool(self.eval(lhs, frame)?) || bool(self.eval(rhs, frame)?),
+                ));
+            }
+            _ => {}
+        }
+
+        let a = self.eval(lhs, frame)?;
+        let b = self.eval(rhs, frame)?;
+        if !op.is_comparison() {
+            return self.arith(op, a, b, &expr.ty, expr.span);
+        }
+
+        let ordering = match (&a, &b) {
+            (Value::Int(x), Value::Int(y)) => x.partial_cmp(y),
+            (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+            (Value::Bool(x), Value::Bool(y)) => x.partial_cmp(y),
+            (Value::Str(x), Value::Str(y)) => x.partial_cmp(y),
+            _ => return Err(Stop::NotConstant),
+        };
+        Ok(Value::Bool(op.compare(ordering)))
+    }
+
+    fn arith(&mut self, op: BinOp, a: Value, b: Value, ty: &Ty, span: Span) -> Eval {
+        match (a, b) {
+            (Value::Int(x), Value::Int(y)) => {
+                let value = match op {
+                    BinOp::Add => x + y,
+                    BinOp::Sub => x - y,
+                    BinOp::Mul => x * y,
+                    BinOp::Div if y == 0 => {
+                        return Err(self.error(
+                            codes::CONST_DIVIDE_BY_ZERO,
+                            "division by zero",
+                            span,
+                            format!("`{x} / {y}` divides by zero"),
+                        ));
+                    }
+                    BinOp::Div => x / y,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                self.int(value, ty, span, || format!("{x} {} {y}", op.as_str()))
+            }
+            (Value::Float(x), Value::Float(y)) => {
+                let value = match op {
+                    BinOp::Add => x + y,
+                    BinOp::Sub => x - y,
+                    BinOp::Mul => x * y,
+                    BinOp::Div => x / y,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Value::Float(round(value, ty)))
+            }
+            _ => Err(Stop::NotConstant),
+        }
+    }
+
+    /// Checks that the result of `computation` fits in `ty`.
+    fn int(&self, value: i128, ty: &Ty, span: Span, computation: impl Fn() -> String) -> Eval {
+        let Ty::Int(int) = ty else {
+            return Ok(Value::Int(value));
+        };
+        let (min, max) = range(*int);
+        if value < min || value > max {
+            let int = int.as_str();
+            return Err(self.error(
+                codes::CONST_OVERFLOW,
+                "integer overflow",
+                span,
+                format!(
+                    "`{}` is {value}, which does not fit in `{int}`",
+                    computation()
+                ),
+            ));
+        }
+        Ok(Value::Int(value))
+    }
+}
+
+fn bool(value: Value) -> bool {
+    match value {
+        Value::Bool(value) => value,
+        other => unreachable!("expected a bool, found {other:?}"),
+    }
+}
+
+/// The smallest and largest values of `int`.
+fn range(int: IntTy) -> (i128, i128) {
+    let max = int.max() as i128;
+    if int.is_signed() {
+        (-max - 1, max)
+    } else {
+        (0, max)
+    }
+}
+
+/// Rounds `value` to the precision of `ty`, like the interpreter does.
+fn round(value: f64, ty: &Ty) -> f64 {
+    match ty {
+        Ty::Float(FloatTy::F64) => value,
+        _ => value as f32 as f64,
+    }
+}
+
+/// The local an assignment to `place` writes to, and the fields it goes
+/// through, or `None` if it writes anywhere else.
+fn local_place(place: &TExpr) -> Option<(DeclId, Vec<usize>)> {
+    match &place.kind {
+        TExprKind::Local(id) => Some((*id, Vec::new())),
+        TExprKind::Field { base, index } => {
+            let (id, mut path) = local_place(base)?;
+            path.push(*index);
+            Some((id, path))
+        }
+        _ => None,
+    }
+}
+
+/// The value of `root`, or of the field of it `path` leads to.
+fn field_mut<'f>(frame: &'f mut Frame, root: DeclId, path: &[usize]) -> &'f mut Value {
+    let mut value = frame.get_mut(&root).expect("unbound local");
+    for index in path {
+        let Value::Struct(fields) = value else {
+            unreachable!("field of a non-struct");
+        };
+        value = &mut fields[*index];
+    }
+    value
+}
+
+fn matches(pattern: &TPattern, value: &Value, frame: &mut Frame) -> bool {
+    match (pattern, value) {
+        (TPattern::Wildcard, _) => true,
+        (TPattern::Int(expected), Value::Int(v)) => *expected as i128 == *v,
+        (TPattern::Bool(expected), Value::Bool(v)) => expected == v,
+        (TPattern::Str(expected), Value::Str(v)) => expected.as_str() == &**v,
+        (TPattern::Variant { index, bindings }, Value::Variant(tag, fields)) => {
+            if index != tag {
+                return false;
+            }
+            for (id, field) in bindings {
+                frame.insert(*id, fields[*field].clone());
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The expression the checker would produce for `value` written out as a
+/// literal of type `ty`, or `None` for values no literal can express.
+fn literal(
+    value: &Value,
+    ty: &Ty,
+    span: Span,
+    structs: &HashMap<DeclId, StructDef>,
+    enums: &HashMap<DeclId, EnumDef>,
+) -> Option<TExpr> {
+    let expr = |kind| TExpr {
+        kind,
+        ty: ty.clone(),
+        span,
+    };
+    let negated = |kind| {
+        Some(expr(TExprKind::Unary {
+            op: UnOp::Neg,
+            expr: Box::new(expr(kind)),
+        }))
+    };
+    let fields = |values: &[Value], defs: &[FieldDef]| -> Option<Vec<TExpr>> {
+        values
+            .iter()
+            .zip(defs)
+            .map(|(value, def)| literal(value, &def.ty, span, structs, enums))
+            .collect()
+    };
+
+    match (value, ty) {
+        (Value::Int(v), _) if *v < 0 => negated(TExprKind::Int(v.unsigned_abs() as u64)),
+        (Value::Int(v), _) => Some(expr(TExprKind::Int(*v as u64))),
+        (Value::Float(v), _) if !v.is_finite() => None,
+        (Value::Float(v), _) if v.is_sign_negative() => negated(TExprKind::Float(-v)),
+        (Value::Float(v), _) => Some(expr(TExprKind::Float(*v))),
+        (Value::Bool(v), _) => Some(expr(TExprKind::Bool(*v))),
+        (Value::Str(v), _) => Some(expr(TExprKind::Str(v.to_string()))),
+        (Value::Void, _) => Some(expr(TExprKind::Block(TBlock {
+            stmts: Vec::new(),
+            tail: None,
+        }))),
+        (Value::Function(func), _) => Some(expr(TExprKind::Function(*func))),
+        (Value::Struct(values), Ty::Struct(id)) => Some(expr(TExprKind::Struct {
+            id: *id,
+            fields: fields(values, &structs.get(id)?.fields)?,
+        })),
+        (Value::Variant(index, values), Ty::Enum(id)) => Some(expr(TExprKind::Variant {
+            id: *id,
+            index: *index,
+            fields: fields(values, &enums.get(id)?.variants[*index].fields)?,
+        })),
+        _ => None,
+    }
+}
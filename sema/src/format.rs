@@ -0,0 +1,151 @@
+//! `printf` format strings.
+//!
+//! `printf` is an intrinsic: its first argument must be a string literal,
+//! which the checker parses here so that placeholder and argument mismatches
+//! are compile-time errors. A format string is text with placeholders:
+//!
+//! - `{s}` prints a `str`,
+//! - `{d}` prints any integer type in decimal, unsigned types as unsigned,
+//! - `{f}` prints any float type in fixed-point with six decimals, like C's
+//!   `%f`,
+//!
+//! and `{{` and `}}` print literal braces. Nothing else is printed: there is
+//! no implicit newline.
+//!
+//! In the typed AST, `printf` stays a call to [`Builtin::Printf`] whose
+//! first argument is the decoded format string, which is known to parse.
+//! Every backend lowers it by evaluating the arguments left to right, then
+//! calling [`parse`] again and printing each [`Piece`] in order: literal
+//! text as is and each placeholder as the next argument.
+//!
+//! [`Builtin::Printf`]: crate::tast::Builtin::Printf
+
+use std::ops::Range;
+
+/// What a placeholder prints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spec {
+    Str,
+    Int,
+    Float,
+}
+
+impl Spec {
+    /// The placeholder as written, such as `{d}`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Spec::Str => "{s}",
+            Spec::Int => "{d}",
+            Spec::Float => "{f}",
+        }
+    }
+
+    /// What the argument must be, for diagnostics.
+    pub fn expects(self) -> &'static str {
+        match self {
+            Spec::Str => "a `str`",
+            Spec::Int => "an integer",
+            Spec::Float => "a float",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Piece {
+    /// Text printed as is, with `{{` and `}}` already unescaped.
+    Literal(String),
+    Arg(Spec),
+}
+
+/// A [`Piece`] with the byte range it was parsed from.
+#[derive(Debug, Clone)]
+pub struct Located {
+    pub piece: Piece,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatError {
+    pub message: String,
+    pub label: String,
+    /// Bytes of the format string at fault.
+    pub range: Range<usize>,
+}
+
+/// Splits `format` into literal text and placeholders.
+pub fn parse(format: &str) -> Result<Vec<Located>, FormatError> {
+    let mut pieces: Vec<Located> = Vec::new();
+    let mut literal = String::new();
+    let mut literal_start = 0;
+    let mut chars = format.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '{' if chars.peek().is_some_and(|(_, c)| *c == '{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek().is_some_and(|(_, c)| *c == '}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let Some(close) = format[i..].find('}').map(|end| i + end) else {
+                    return Err(FormatError {
+                        message: "unclosed placeholder in format string".to_string(),
+                        label: "this `{` is never closed".to_string(),
+                        range: i..i + 1,
+                    });
+                };
+                let spec = match &format[i + 1..close] {
+                    "s" => Spec::Str,
+                    "d" => Spec::Int,
+                    "f" => Spec::Float,
+                    other => {
+                        return Err(FormatError {
+                            message: format!("unknown placeholder `{{{other}}}`"),
+                            label: "expected `{s}`, `{d}` or `{f}`".to_string(),
+                            range: i..close + 1,
+                        });
+                    }
+                };
+                if !literal.is_empty() {
+                    pieces.push(Located {
+                        piece: Piece::Literal(std::mem::take(&mut literal)),
+                        range: literal_start..i,
+                    });
+                }
+                pieces.push(Located {
+                    piece: Piece::Arg(spec),
+                    range: i..close + 1,
+                });
+                literal_start = close + 1;
+                while chars.next_if(|(j, _)| *j <= close).is_some() {}
+            }
+            '}' => {
+                return Err(FormatError {
+                    message: "unmatched `}` in format string".to_string(),
+                    label: "write `}}` to print a `}`".to_string(),
+                    range: i..i + 1,
+                });
+            }
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Located {
+            piece: Piece::Literal(literal),
+            range: literal_start..format.len(),
+        });
+    }
+    Ok(pieces)
+}
+
+/// The placeholders of a format string known to parse, in order.
+pub fn specs(pieces: &[Located]) -> impl Iterator<Item = (Spec, &Range<usize>)> {
+    pieces.iter().filter_map(|p| match p.piece {
+        Piece::Arg(spec) => Some((spec, &p.range)),
+        Piece::Literal(_) => None,
+    })
+}
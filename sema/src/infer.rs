@@ -0,0 +1,185 @@
+//! Type inference for numeric literals.
+//!
+//! A literal checked without a concrete expected type gets a fresh type
+//! variable (`Ty::IntVar` or `Ty::FloatVar`) instead of a guess. Later uses
+//! unify the variable with whatever they require, so in
+//!
+//! ```text
+//! let small := 200;
+//! let byte : u8 = small;
+//! ```
+//!
+//! `small` is a `u8`. Variables still unbound once their item is checked
+//! default to `i32` and `f64`.
+//!
+//! Integer variables only ever bind to integer types or other integer
+//! variables, and likewise for floats, so no occurs check is needed.
+
+use crate::tast::*;
+use crate::types::*;
+
+#[derive(Debug, Default)]
+pub struct InferCtx {
+    /// What each variable is bound to, if anything yet.
+    vars: Vec<Option<Ty>>,
+    /// Whether each variable stands for a float type rather than an
+    /// integer type.
+    floats: Vec<bool>,
+}
+
+impl InferCtx {
+    pub fn int_var(&mut self) -> Ty {
+        Ty::IntVar(self.fresh(false))
+    }
+
+    pub fn float_var(&mut self) -> Ty {
+        Ty::FloatVar(self.fresh(true))
+    }
+
+    fn fresh(&mut self, float: bool) -> TyVar {
+        self.vars.push(None);
+        self.floats.push(float);
+        TyVar(self.vars.len() as u32 - 1)
+    }
+
+    /// Number of variables created so far, to later default only those
+    /// created after this point.
+    pub fn watermark(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// Follows bindings until `ty` is not a bound variable.
+    pub fn shallow(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::IntVar(var) | Ty::FloatVar(var) => match &self.vars[var.0 as usize] {
+                Some(value) => self.shallow(value),
+                None => ty.clone(),
+            },
+            ty => ty.clone(),
+        }
+    }
+
+    /// Replaces every bound variable in `ty`, however deep.
+    pub fn resolve(&self, ty: &Ty) -> Ty {
+        match self.shallow(ty) {
+            Ty::Tuple(elements) => Ty::Tuple(elements.iter().map(|ty| self.resolve(ty)).collect()),
+            Ty::Ref(inner, mutable) => Ty::Ref(Box::new(self.resolve(&inner)), mutable),
+            Ty::Fn(func) => Ty::Fn(Box::new(FnTy {
+                params: func.params.iter().map(|ty| self.resolve(ty)).collect(),
+                ret: self.resolve(&func.ret),
+            })),
+            ty => ty,
+        }
+    }
+
+    /// Makes `a` and `b` the same type by binding variables, returning
+    /// whether that was possible. `Never` and `Error` unify with anything.
+    pub fn unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+        match (&a, &b) {
+            _ if a.is_bottom() || b.is_bottom() => true,
+            (Ty::IntVar(x), Ty::IntVar(y)) | (Ty::FloatVar(x), Ty::FloatVar(y)) => {
+                if x != y {
+                    self.bind(*x, b.clone());
+                }
+                true
+            }
+            (Ty::IntVar(var), Ty::Int(_))
+            | (Ty::Int(_), Ty::IntVar(var))
+            | (Ty::FloatVar(var), Ty::Float(_))
+            | (Ty::Float(_), Ty::FloatVar(var)) => {
+                let concrete = if matches!(a, Ty::IntVar(_) | Ty::FloatVar(_)) {
+                    b.clone()
+                } else {
+                    a.clone()
+                };
+                self.bind(*var, concrete);
+                true
+            }
+            (Ty::Ref(a, x), Ty::Ref(b, y)) => x == y && self.unify(a, b),
+            (Ty::Tuple(a), Ty::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.unify(a, b))
+            }
+            (Ty::Fn(a), Ty::Fn(b)) => {
+                a.params.len() == b.params.len()
+                    && a.params
+                        .iter()
+                        .zip(&b.params)
+                        .all(|(a, b)| self.unify(a, b))
+                    && self.unify(&a.ret, &b.ret)
+            }
+            _ => a == b,
+        }
+    }
+
+    fn bind(&mut self, var: TyVar, ty: Ty) {
+        self.vars[var.0 as usize] = Some(ty);
+    }
+
+    /// Binds every variable created since `watermark` that is still
+    /// unbound to its default type.
+    pub fn default_from(&mut self, watermark: usize) {
+        for i in watermark..self.vars.len() {
+            if self.vars[i].is_none() {
+                let default = if self.floats[i] {
+                    Ty::Float(FloatTy::F64)
+                } else {
+                    Ty::Int(IntTy::I32)
+                };
+                self.bind(TyVar(i as u32), default);
+            }
+        }
+    }
+
+    /// Resolves the type of `expr` and everything inside it.
+    pub fn zonk(&self, expr: &mut TExpr) {
+        expr.ty = self.resolve(&expr.ty);
+        match &mut expr.kind {
+            TExprKind::Int(_)
+            | TExprKind::Float(_)
+            | TExprKind::Bool(_)
+            | TExprKind::Str(_)
+            | TExprKind::Local(_)
+            | TExprKind::Global(_)
+            | TExprKind::Function(_)
+            | TExprKind::Builtin(_)
+            | TExprKind::Error => {}
+            TExprKind::Unary { expr, .. } => self.zonk(expr),
+            TExprKind::Binary { lhs, rhs, .. } => {
+                self.zonk(lhs);
+                self.zonk(rhs);
+            }
+            TExprKind::Assign { target, value, .. } => {
+                self.zonk(target);
+                self.zonk(value);
+            }
+            TExprKind::Call { callee, args, .. } => {
+                self.zonk(callee);
+                args.iter_mut().for_each(|arg| self.zonk(arg));
+            }
+            TExprKind::Field { base, .. } => self.zonk(base),
+            TExprKind::Struct { fields, .. } | TExprKind::Variant { fields, .. } => {
+                fields.iter_mut().for_each(|field| self.zonk(field));
+            }
+            TExprKind::Block(block) => {
+                for stmt in &mut block.stmts {
+                    match stmt {
+                        TStmt::Let { value, .. } | TStmt::Expr(value) => self.zonk(value),
+                    }
+                }
+                if let Some(tail) = &mut block.tail {
+                    self.zonk(tail);
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => {
+                self.zonk(scrutinee);
+                arms.iter_mut().for_each(|arm| self.zonk(&mut arm.body));
+            }
+            TExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.zonk(value);
+                }
+            }
+        }
+    }
+}
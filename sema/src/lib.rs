@@ -0,0 +1,12 @@
+//! Semantic analysis: everything that runs on the expanded AST before a
+//! backend sees it.
+
+pub mod check;
+pub mod consteval;
+pub mod format;
+pub mod infer;
+pub mod mutability;
+pub mod resolve;
+pub mod tail;
+pub mod tast;
+pub mod types;
@@ -0,0 +1,285 @@
+//! Mutability and castability.
+//!
+//! Enforces the storage rules from the README on the typed program:
+//!
+//! - Only `mut` and `static mut` bindings may be assigned to. Parameters
+//!   and switch captures are immutable.
+//! - `let` and bare `static` values are immutable but may be cast to `mut`,
+//!   that is, copied or referenced into a `mut` binding.
+//! - `const` and `static const` values may never be cast to `mut`.
+//! - Structs, enums and interfaces must be declared `const` or
+//!   `static const`.
+//! - `&mut` may only be taken of what could be assigned to.
+//!
+//! Writes through a reference are checked by the type checker instead:
+//! only a `&mut` allows them, and `&mut` is never taken of a `&`.
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::{Span, TokenType};
+use parser::node::*;
+
+use crate::resolve::{DeclId, Resolution, SymbolKind};
+use crate::tast::*;
+use crate::types::Ty;
+
+/// Checks the mutability rules for `program`, whose typed form is `typed`.
+pub fn check_mutability(program: &Program, res: &Resolution, typed: &TProgram) -> Vec<Diagnostic> {
+    let mut checker = MutChecker {
+        res,
+        diagnostics: Vec::new(),
+    };
+
+    for node in &program.0 {
+        if let Node::Stmt(Stmt::Decl(decl)) = node {
+            checker.type_decl(decl);
+        }
+    }
+
+    for global in &typed.globals {
+        if res.symbol(global.id).mutability == Mutability::Mutable {
+            checker.cast(global.id, &global.value);
+        }
+        checker.expr(&global.value);
+    }
+    for func in &typed.functions {
+        checker.expr(&func.body);
+    }
+
+    checker.diagnostics
+}
+
+struct MutChecker<'a> {
+    res: &'a Resolution,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// The variable an assignment to `place` writes to, if it is not reached
+/// through a reference.
+fn place_root(place: &TExpr) -> Option<(DeclId, Span)> {
+    match &place.kind {
+        TExprKind::Local(id) | TExprKind::Global(id) => Some((*id, place.span)),
+        TExprKind::Field { base, .. } => place_root(base),
+        _ => None,
+    }
+}
+
+/// What [`MutChecker::write`] checks.
+#[derive(Clone, Copy)]
+enum Write {
+    Assign,
+    MutRef,
+}
+
+/// The variable `value` copies or references, if it reads one directly.
+fn value_root(value: &TExpr) -> Option<(DeclId, Span)> {
+    match &value.kind {
+        TExprKind::Unary {
+            op: UnOp::Ref,
+            expr,
+        } => place_root(expr),
+        _ => place_root(value),
+    }
+}
+
+impl MutChecker<'_> {
+    fn type_decl(&mut self, decl: &Decl) {
+        let (storage, name, what) = match decl {
+            Decl::Struct(s) => (&s.storage, &s.name, "struct"),
+            Decl::Enum(e) => (&e.storage, &e.name, "enum"),
+            Decl::Interface(i) => (&i.storage, &i.name, "interface"),
+            Decl::Macro(m) => (&m.storage, &m.name, "macro"),
+            _ => return,
+        };
+        // Macros are not values, so not even `static` applies to them.
+        let is_macro = matches!(decl, Decl::Macro(_));
+        if storage.mutability() == Mutability::Const && !(is_macro && storage.is_static()) {
+            return;
+        }
+
+        let TokenType::Ident(name) = &name.kind else {
+            return;
+        };
+        let (declared, keyword) = match (storage.is_static(), storage.mutability()) {
+            (true, Mutability::Mutable) => ("static mut", "static const"),
+            (true, Mutability::Const) => ("static const", "const"),
+            (true, _) => ("static", "static const"),
+            (false, Mutability::Mutable) => ("mut", "const"),
+            (false, _) => ("let", "const"),
+        };
+        let (keyword, note) = if is_macro {
+            (
+                "const",
+                "macros are expanded at compile time and cannot change",
+            )
+        } else {
+            (keyword, "types are fixed at compile time and cannot change")
+        };
+        self.diagnostics.push(
+            Diagnostic::error(format!("{what} `{name}` must be declared `const`"))
+                .with_code(codes::NON_CONST_TYPE)
+                .with_primary(storage.span(), format!("declared `{declared}` here"))
+                .with_note(note)
+                .with_help(format!("write `{keyword} {name} : {what} = ...`")),
+        );
+    }
+
+    fn expr(&mut self, expr: &TExpr) {
+        match &expr.kind {
+            TExprKind::Int(_)
+            | TExprKind::Float(_)
+            | TExprKind::Bool(_)
+            | TExprKind::Str(_)
+            | TExprKind::Local(_)
+            | TExprKind::Global(_)
+            | TExprKind::Function(_)
+            | TExprKind::Builtin(_)
+            | TExprKind::Error => {}
+            TExprKind::Unary { op, expr: operand } => {
+                if *op == UnOp::Ref && matches!(expr.ty, Ty::Ref(_, true)) {
+                    self.write(operand, Write::MutRef);
+                }
+                self.expr(operand);
+            }
+            TExprKind::Binary { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            TExprKind::Assign { target, value, .. } => {
+                self.write(target, Write::Assign);
+                if let Some((id, _)) = place_root(target)
+                    && self.res.symbol(id).mutability == Mutability::Mutable
+                {
+                    self.cast(id, value);
+                }
+                self.expr(target);
+                self.expr(value);
+            }
+            TExprKind::Call { callee, args, .. } => {
+                self.expr(callee);
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            TExprKind::Field { base, .. } => self.expr(base),
+            TExprKind::Struct { fields, .. } | TExprKind::Variant { fields, .. } => {
+                fields.iter().for_each(|field| self.expr(field));
+            }
+            TExprKind::Block(block) => {
+                for stmt in &block.stmts {
+                    match stmt {
+                        TStmt::Let { id, value } => {
+                            if self.res.symbol(*id).mutability == Mutability::Mutable {
+                                self.cast(*id, value);
+                            }
+                            self.expr(value);
+                        }
+                        TStmt::Expr(value) => self.expr(value),
+                    }
+                }
+                if let Some(tail) = &block.tail {
+                    self.expr(tail);
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => {
+                self.expr(scrutinee);
+                arms.iter().for_each(|arm| self.expr(&arm.body));
+            }
+            TExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+        }
+    }
+
+    /// Reports assigning to `target`, or taking `&mut` of it, if it is
+    /// not mutable.
+    fn write(&mut self, target: &TExpr, write: Write) {
+        let Some((id, _)) = place_root(target) else {
+            return;
+        };
+        let symbol = self.res.symbol(id);
+        if symbol.mutability == Mutability::Mutable {
+            return;
+        }
+
+        let name = &symbol.name;
+        let (message, code, action) = match write {
+            Write::Assign => (
+                format!("cannot assign to immutable `{name}`"),
+                codes::ASSIGN_TO_IMMUTABLE,
+                "assignment",
+            ),
+            Write::MutRef => (
+                format!("cannot take `&mut` of immutable `{name}`"),
+                codes::MUT_REF_TO_IMMUTABLE,
+                "`&mut`",
+            ),
+        };
+        let label = match (&target.kind, write) {
+            (TExprKind::Local(_) | TExprKind::Global(_), Write::Assign) => {
+                "cannot assign to this".to_string()
+            }
+            (TExprKind::Local(_) | TExprKind::Global(_), Write::MutRef) => {
+                "cannot take `&mut` of this".to_string()
+            }
+            _ => format!("this is part of `{name}`"),
+        };
+        let diag = Diagnostic::error(message)
+            .with_code(code)
+            .with_primary(target.span, label);
+        let diag = match (symbol.kind, symbol.mutability) {
+            (SymbolKind::Param, _) => diag
+                .with_secondary(symbol.span, "parameters are immutable")
+                .with_help(format!(
+                    "copy it into a local first: `mut {name} := {name};`"
+                )),
+            (SymbolKind::Capture, _) => diag
+                .with_secondary(symbol.span, "captures are immutable")
+                .with_help(format!(
+                    "copy it into a local first: `mut {name} := {name};`"
+                )),
+            (_, Mutability::Const) => diag
+                .with_secondary(symbol.span, format!("`{name}` is declared `const`"))
+                .with_note("`const` values can never be cast to `mut`"),
+            _ => {
+                let keyword = if symbol.is_static {
+                    "static mut"
+                } else {
+                    "mut"
+                };
+                diag.with_secondary(symbol.span, format!("`{name}` is immutable"))
+                    .with_help(format!(
+                        "declare it with `{keyword}` to allow {action}: `{keyword} {name} ...`"
+                    ))
+            }
+        };
+        self.diagnostics.push(diag);
+    }
+
+    /// Reports `value` flowing into the mutable `target` if it comes from
+    /// a `const`.
+    fn cast(&mut self, target: DeclId, value: &TExpr) {
+        let Some((source, span)) = value_root(value) else {
+            return;
+        };
+        let symbol = self.res.symbol(source);
+        if symbol.mutability != Mutability::Const {
+            return;
+        }
+
+        let name = &symbol.name;
+        let target = &self.res.symbol(target).name;
+        self.diagnostics.push(
+            Diagnostic::error(format!("cannot cast `const` value `{name}` to `mut`"))
+                .with_code(codes::CONST_TO_MUT)
+                .with_primary(
+                    span,
+                    format!("`{name}` flows into `mut` binding `{target}`"),
+                )
+                .with_secondary(symbol.span, format!("`{name}` is declared `const`"))
+                .with_note("unlike `let`, `const` values can never be cast to `mut`")
+                .with_help(format!(
+                    "declare `{name}` with `let` if it should be castable"
+                )),
+        );
+    }
+}
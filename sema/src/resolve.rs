@@ -0,0 +1,365 @@
+//! Name resolution.
+//!
+//! Binds every identifier used as a value to the declaration it refers to.
+//! Scopes nest for blocks, function bodies, `impl` bodies and switch arms.
+//! Top-level and `impl` declarations are visible to each other regardless
+//! of order, so `static Tau := Pi * 2;` may come before `Pi`; locals are
+//! only visible after their declaration and may shadow each other.
+//!
+//! Type names are not looked up here, only identifiers in expressions.
+
+use std::collections::HashMap;
+
+use diagnostics::{Diagnostic, codes};
+use lexer::token::{Span, Token, TokenType};
+use parser::node::*;
+
+/// Names that are always in scope. Declarations may shadow them.
+pub const BUILTINS: &[&str] = &["printf", "true", "false"];
+
+/// Index of a [`Symbol`] in [`Resolution::symbols`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeclId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Builtin,
+    Variable,
+    Function,
+    Struct,
+    Enum,
+    Interface,
+    Param,
+    /// Bound by a `|x|` or `|{a, b}|` capture in a switch arm.
+    Capture,
+}
+
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Span of the name in the declaration; default for builtins.
+    pub span: Span,
+    pub mutability: Mutability,
+    pub is_static: bool,
+    /// Declared at the top level of the program.
+    pub global: bool,
+}
+
+/// The result of name resolution. Identifiers are keyed by the span of
+/// their token, which is unique even inside macro expansions.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub symbols: Vec<Symbol>,
+    /// Every resolved use of a name.
+    pub uses: HashMap<Span, DeclId>,
+    /// The symbol declared by each name token.
+    pub decls: HashMap<Span, DeclId>,
+}
+
+impl Resolution {
+    pub fn symbol(&self, id: DeclId) -> &Symbol {
+        &self.symbols[id.0 as usize]
+    }
+
+    /// The declaration the identifier at `span` refers to.
+    pub fn use_of(&self, span: Span) -> Option<DeclId> {
+        self.uses.get(&span).copied()
+    }
+
+    /// The symbol declared by the name token at `span`.
+    pub fn decl_at(&self, span: Span) -> Option<DeclId> {
+        self.decls.get(&span).copied()
+    }
+}
+
+struct Resolver {
+    res: Resolution,
+    scopes: Vec<HashMap<String, DeclId>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Resolves every name in `program`.
+pub fn resolve(program: &Program) -> (Resolution, Vec<Diagnostic>) {
+    let mut resolver = Resolver {
+        res: Resolution::default(),
+        scopes: vec![HashMap::new()],
+        diagnostics: Vec::new(),
+    };
+
+    for name in BUILTINS {
+        let id = resolver.add_symbol(Symbol {
+            name: name.to_string(),
+            kind: SymbolKind::Builtin,
+            span: Span::default(),
+            mutability: Mutability::Const,
+            is_static: true,
+            global: true,
+        });
+        resolver.scopes[0].insert(name.to_string(), id);
+    }
+
+    resolver.scopes.push(HashMap::new());
+    resolver.items(&program.0);
+
+    (resolver.res, resolver.diagnostics)
+}
+
+fn name_of(token: &Token) -> Option<&str> {
+    match &token.kind {
+        TokenType::Ident(name) => Some(name),
+        _ => None,
+    }
+}
+
+impl Resolver {
+    fn add_symbol(&mut self, symbol: Symbol) -> DeclId {
+        let id = DeclId(self.res.symbols.len() as u32);
+        self.res.symbols.push(symbol);
+        id
+    }
+
+    fn at_top_level(&self) -> bool {
+        self.scopes.len() == 2
+    }
+
+    /// Declares `name` in the innermost scope. With `unique`, a name already
+    /// declared in that scope is an error instead of being shadowed.
+    fn declare(
+        &mut self,
+        name: &Token,
+        kind: SymbolKind,
+        storage: Option<&Storage>,
+        unique: bool,
+    ) -> Option<DeclId> {
+        let text = name_of(name)?;
+        let scope = self.scopes.last().unwrap();
+        if unique && let Some(&previous) = scope.get(text) {
+            let previous = self.res.symbol(previous).span;
+            self.diagnostics.push(
+                Diagnostic::error(format!("the name `{text}` is defined multiple times"))
+                    .with_code(codes::DUPLICATE_NAME)
+                    .with_primary(name.span, format!("`{text}` redefined here"))
+                    .with_secondary(previous, format!("previous definition of `{text}` here")),
+            );
+            return None;
+        }
+
+        let global = self.at_top_level();
+        let id = self.add_symbol(Symbol {
+            name: text.to_string(),
+            kind,
+            span: name.span,
+            mutability: storage.map_or(Mutability::Immutable, |s| s.mutability()),
+            is_static: storage.is_some_and(|s| s.is_static()),
+            global,
+        });
+        self.scopes.last_mut().unwrap().insert(text.to_string(), id);
+        self.res.decls.insert(name.span, id);
+        Some(id)
+    }
+
+    fn lookup(&mut self, name: &Token) {
+        let Some(text) = name_of(name) else {
+            return;
+        };
+        match self.scopes.iter().rev().find_map(|s| s.get(text)) {
+            Some(&id) => {
+                self.res.uses.insert(name.span, id);
+            }
+            None => {
+                // Names renamed by macro hygiene read as the original name.
+                let shown = text.split('#').next().unwrap_or(text);
+                self.diagnostics.push(
+                    Diagnostic::error(format!("cannot find `{shown}` in this scope"))
+                        .with_code(codes::UNDEFINED_NAME)
+                        .with_primary(name.span, "not found in this scope"),
+                );
+            }
+        }
+    }
+
+    fn with_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// Resolves a list of items that may refer to each other in any order:
+    /// the top level and `impl` bodies.
+    fn items(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            if let Node::Stmt(Stmt::Decl(decl)) = node {
+                self.declare_decl(decl, true);
+            }
+        }
+        for node in nodes {
+            match node {
+                Node::Stmt(Stmt::Decl(decl)) => self.decl(decl),
+                _ => self.node(node),
+            }
+        }
+    }
+
+    fn declare_decl(&mut self, decl: &Decl, unique: bool) {
+        match decl {
+            Decl::Variable(var) => {
+                self.declare(&var.name, SymbolKind::Variable, Some(&var.storage), unique);
+            }
+            Decl::Function(func) => {
+                self.declare(
+                    &func.name,
+                    SymbolKind::Function,
+                    Some(&func.storage),
+                    unique,
+                );
+            }
+            Decl::Struct(s) => {
+                self.declare(&s.name, SymbolKind::Struct, None, unique);
+            }
+            Decl::Enum(e) => {
+                self.declare(&e.name, SymbolKind::Enum, None, unique);
+            }
+            Decl::Interface(i) => {
+                self.declare(&i.name, SymbolKind::Interface, None, unique);
+            }
+            // Macros are gone after expansion; impls and derives add no names.
+            Decl::Macro(_) | Decl::Impl(_) | Decl::Derive(_) => {}
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Stmt(Stmt::Decl(decl)) => {
+                // Functions may call themselves; variables only see earlier
+                // bindings, including any they shadow.
+                match decl.as_ref() {
+                    Decl::Variable(_) => {
+                        self.decl(decl);
+                        self.declare_decl(decl, false);
+                    }
+                    _ => {
+                        self.declare_decl(decl, false);
+                        self.decl(decl);
+                    }
+                }
+            }
+            Node::Stmt(Stmt::ExprStmt(expr)) | Node::Expr(expr) => self.expr(expr),
+            Node::Error(_) | Node::EOF => {}
+        }
+    }
+
+    /// Resolves the contents of a declaration whose name is already bound.
+    fn decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::Variable(var) => self.expr(&var.expr),
+            Decl::Function(func) => self.function(&func.params, &func.body),
+            Decl::Interface(interface) => {
+                for method in &interface.methods {
+                    if let Some(body) = &method.body {
+                        self.function(&method.params, body);
+                    }
+                }
+            }
+            Decl::Impl(imp) => {
+                self.lookup(&imp.name);
+                self.with_scope(|r| r.items(&imp.members.nodes));
+            }
+            Decl::Derive(derive) => {
+                self.lookup(&derive.target);
+                self.lookup(&derive.interface);
+            }
+            Decl::Struct(_) | Decl::Enum(_) | Decl::Macro(_) => {}
+        }
+    }
+
+    fn function(&mut self, params: &[Parameter], body: &Expr) {
+        self.with_scope(|r| {
+            for param in params {
+                r.declare(&param.name, SymbolKind::Param, None, true);
+            }
+            r.expr(body);
+        });
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Atom(token) => self.lookup(token),
+            Expr::BinOp(op) => {
+                self.expr(&op.lhs);
+                self.expr(&op.rhs);
+            }
+            Expr::UnaryOp(op) => self.expr(&op.expr),
+            Expr::Assign(assign) => {
+                self.expr(&assign.target);
+                self.expr(&assign.value);
+            }
+            Expr::Pipe(pipe) => {
+                self.expr(&pipe.lhs);
+                self.expr(&pipe.rhs);
+            }
+            // Field names belong to the type of `base`, not to a scope.
+            Expr::Field(field) => self.expr(&field.base),
+            Expr::Block(block) => self.with_scope(|r| {
+                for node in &block.nodes {
+                    r.node(node);
+                }
+            }),
+            Expr::Call(call) => {
+                self.expr(&call.callee);
+                for arg in &call.args {
+                    self.expr(arg);
+                }
+            }
+            Expr::StructLiteral(lit) => {
+                for (_, value) in &lit.fields {
+                    self.expr(value);
+                }
+            }
+            Expr::Switch(switch) => {
+                self.expr(&switch.scrutinee);
+                for arm in &switch.arms {
+                    self.with_scope(|r| {
+                        if let Pattern::Variant {
+                            capture: Some(capture),
+                            ..
+                        } = &arm.pattern
+                        {
+                            match capture {
+                                Capture::Binding(name) => {
+                                    r.declare(name, SymbolKind::Capture, None, true);
+                                }
+                                Capture::Destructure(names, _) => {
+                                    for name in names {
+                                        r.declare(name, SymbolKind::Capture, None, true);
+                                    }
+                                }
+                            }
+                        }
+                        r.expr(&arm.body);
+                    });
+                }
+            }
+            Expr::Return(ret) => {
+                if let Some(value) = &ret.value {
+                    self.expr(value);
+                }
+            }
+            Expr::Variant(variant) => match &variant.payload {
+                VariantArgs::None => {}
+                VariantArgs::Args(args) => {
+                    for arg in args {
+                        self.expr(arg);
+                    }
+                }
+                VariantArgs::Fields(fields) => {
+                    for (_, value) in fields {
+                        self.expr(value);
+                    }
+                }
+            },
+            // Left behind only when expansion failed, which was reported.
+            Expr::MacroCall(_) => {}
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Tail calls.
+//!
+//! Canary has no loops, so recursion has to run in constant stack space.
+//! This pass marks every call in tail position. Every backend compiles
+//! marked calls of named functions as jumps, whether they call the function
+//! itself or another function that eventually calls back. Calls of function
+//! values are marked too, but only some backends can jump to a callee they
+//! do not know, so those in tail position get a warning.
+//!
+//! A call is in tail position when its result is the result of the
+//! function: the body itself, the tail of a block in tail position, the
+//! body of a switch arm in tail position, or the value of a `return`. A
+//! `void` call that ends a `void` block counts as well. A `#tail` call
+//! anywhere else is an error.
+
+use diagnostics::{Diagnostic, codes};
+
+use crate::tast::*;
+use crate::types::Ty;
+
+/// Marks the tail calls in every function of `program`, reports `#tail`
+/// calls that are not in tail position and warns about tail calls of
+/// function values.
+pub fn mark_tail_calls(program: &mut TProgram) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for func in &mut program.functions {
+        visit(&mut func.body, true, true, &mut diagnostics);
+    }
+    for global in &mut program.globals {
+        visit(&mut global.value, false, false, &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Walks `expr`, which is in tail position if `tail` is set. `in_fn` tells
+/// whether a `return` has a function to return from.
+fn visit(expr: &mut TExpr, tail: bool, in_fn: bool, diagnostics: &mut Vec<Diagnostic>) {
+    match &mut expr.kind {
+        TExprKind::Int(_)
+        | TExprKind::Float(_)
+        | TExprKind::Bool(_)
+        | TExprKind::Str(_)
+        | TExprKind::Local(_)
+        | TExprKind::Global(_)
+        | TExprKind::Function(_)
+        | TExprKind::Builtin(_)
+        | TExprKind::Error => {}
+        TExprKind::Unary { expr, .. } => visit(expr, false, in_fn, diagnostics),
+        TExprKind::Binary { lhs, rhs, .. } => {
+            visit(lhs, false, in_fn, diagnostics);
+            visit(rhs, false, in_fn, diagnostics);
+        }
+        TExprKind::Assign { target, value, .. } => {
+            visit(target, false, in_fn, diagnostics);
+            visit(value, false, in_fn, diagnostics);
+        }
+        TExprKind::Call {
+            callee,
+            args,
+            tail: is_tail,
+            annotated,
+        } => {
+            let builtin = matches!(callee.kind, TExprKind::Builtin(_));
+            *is_tail = tail && !builtin;
+            if *annotated && !*is_tail {
+                let diag = Diagnostic::error("`#tail` call is not in tail position")
+                    .with_code(codes::NOT_TAIL_CALL);
+                let diag = if builtin {
+                    diag.with_primary(expr.span, "this calls a builtin")
+                        .with_note("builtins such as `printf` are never tail-called")
+                } else {
+                    diag.with_primary(expr.span, "its result is used before the function returns")
+                        .with_help("return the call's result directly, e.g. `return f(x);`")
+                };
+                diagnostics.push(diag);
+            }
+            let indirect = !builtin && !matches!(callee.kind, TExprKind::Function(_));
+            if *is_tail && indirect {
+                let diag = Diagnostic::warning("tail call of a function value may use stack space")
+                    .with_code(codes::INDIRECT_TAIL_CALL)
+                    .with_primary(expr.span, "this calls a function value")
+                    .with_note("only tail calls of named functions are always compiled as jumps");
+                diagnostics.push(diag);
+            }
+            visit(callee, false, in_fn, diagnostics);
+            for arg in args {
+                visit(arg, false, in_fn, diagnostics);
+            }
+        }
+        TExprKind::Field { base, .. } => visit(base, false, in_fn, diagnostics),
+        TExprKind::Struct { fields, .. } | TExprKind::Variant { fields, .. } => {
+            for field in fields {
+                visit(field, false, in_fn, diagnostics);
+            }
+        }
+        TExprKind::Block(block) => {
+            let void_tail = tail && block.tail.is_none();
+            let last = block.stmts.len().wrapping_sub(1);
+            for (i, stmt) in block.stmts.iter_mut().enumerate() {
+                match stmt {
+                    TStmt::Let { value, .. } => visit(value, false, in_fn, diagnostics),
+                    TStmt::Expr(value) => {
+                        let tail = void_tail && i == last && value.ty == Ty::Void;
+                        visit(value, tail, in_fn, diagnostics);
+                    }
+                }
+            }
+            if let Some(value) = &mut block.tail {
+                visit(value, tail, in_fn, diagnostics);
+            }
+        }
+        TExprKind::Switch { scrutinee, arms } => {
+            visit(scrutinee, false, in_fn, diagnostics);
+            for arm in arms {
+                visit(&mut arm.body, tail, in_fn, diagnostics);
+            }
+        }
+        TExprKind::Return(value) => {
+            if let Some(value) = value {
+                visit(value, in_fn, in_fn, diagnostics);
+            }
+        }
+    }
+}
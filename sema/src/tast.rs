@@ -0,0 +1,351 @@
+//! The typed AST produced by the checker and consumed by every backend.
+//!
+//! Unlike `parser::node`, everything here is explicit: names are replaced
+//! by the declaration they refer to, fields and variants by their index,
+//! methods by the function they call, `|>` by a call, and every expression
+//! carries its type.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use lexer::token::Span;
+
+use crate::resolve::DeclId;
+use crate::types::*;
+
+/// Index of a [`TFunction`] in [`TProgram::functions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FuncId(pub u32);
+
+#[derive(Debug, Default)]
+pub struct TProgram {
+    pub functions: Vec<TFunction>,
+    /// Top-level variables in source order.
+    pub globals: Vec<TGlobal>,
+    pub structs: HashMap<DeclId, StructDef>,
+    pub enums: HashMap<DeclId, EnumDef>,
+    /// The function called `main`, if any.
+    pub main: Option<FuncId>,
+}
+
+impl TProgram {
+    pub fn function(&self, id: FuncId) -> &TFunction {
+        &self.functions[id.0 as usize]
+    }
+
+    pub fn global(&self, id: DeclId) -> Option<&TGlobal> {
+        self.globals.iter().find(|g| g.id == id)
+    }
+
+    /// Every global, each after the globals its initializer refers to.
+    /// Backends that initialize globals eagerly do it in this order.
+    pub fn init_order(&self) -> Vec<DeclId> {
+        fn visit(
+            program: &TProgram,
+            id: DeclId,
+            seen: &mut HashSet<DeclId>,
+            order: &mut Vec<DeclId>,
+        ) {
+            if !seen.insert(id) {
+                return;
+            }
+            if let Some(global) = program.global(id) {
+                let mut deps = Vec::new();
+                global.value.walk(&mut |e| {
+                    if let TExprKind::Global(dep) = e.kind {
+                        deps.push(dep);
+                    }
+                });
+                for dep in deps {
+                    visit(program, dep, seen, order);
+                }
+            }
+            order.push(id);
+        }
+
+        let mut order = Vec::new();
+        let mut seen = HashSet::new();
+        for global in &self.globals {
+            visit(self, global.id, &mut seen, &mut order);
+        }
+        order
+    }
+}
+
+impl TypeNames for TProgram {
+    fn type_name(&self, id: DeclId) -> &str {
+        self.structs
+            .get(&id)
+            .map(|s| s.name.as_str())
+            .or_else(|| self.enums.get(&id).map(|e| e.name.as_str()))
+            .unwrap_or("?")
+    }
+}
+
+#[derive(Debug)]
+pub struct TLocal {
+    pub name: String,
+    pub ty: Ty,
+}
+
+#[derive(Debug)]
+pub struct TFunction {
+    /// The source name; methods are qualified as `Type.method`.
+    pub name: String,
+    /// Declared `pub`. Other functions may be dropped when nothing calls
+    /// them.
+    pub public: bool,
+    pub params: Vec<DeclId>,
+    pub ty: FnTy,
+    pub body: TExpr,
+    /// Every parameter, local and capture in the body.
+    pub locals: HashMap<DeclId, TLocal>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct TGlobal {
+    pub id: DeclId,
+    pub name: String,
+    pub ty: Ty,
+    pub value: TExpr,
+    /// Locals declared in blocks inside the initializer.
+    pub locals: HashMap<DeclId, TLocal>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct TExpr {
+    pub kind: TExprKind,
+    pub ty: Ty,
+    pub span: Span,
+}
+
+impl TExpr {
+    pub fn error(span: Span) -> Self {
+        Self {
+            kind: TExprKind::Error,
+            ty: Ty::Error,
+            span,
+        }
+    }
+
+    /// Calls `f` on this expression and every expression in it, outermost
+    /// first.
+    pub fn walk(&self, f: &mut impl FnMut(&TExpr)) {
+        f(self);
+        match &self.kind {
+            TExprKind::Unary { expr, .. } => expr.walk(f),
+            TExprKind::Binary { lhs, rhs, .. } => {
+                lhs.walk(f);
+                rhs.walk(f);
+            }
+            TExprKind::Assign { target, value, .. } => {
+                target.walk(f);
+                value.walk(f);
+            }
+            TExprKind::Call { callee, args, .. } => {
+                callee.walk(f);
+                args.iter().for_each(|a| a.walk(f));
+            }
+            TExprKind::Field { base, .. } => base.walk(f),
+            TExprKind::Struct { fields, .. } | TExprKind::Variant { fields, .. } => {
+                fields.iter().for_each(|e| e.walk(f));
+            }
+            TExprKind::Block(block) => {
+                for stmt in &block.stmts {
+                    match stmt {
+                        TStmt::Let { value, .. } | TStmt::Expr(value) => value.walk(f),
+                    }
+                }
+                if let Some(tail) = &block.tail {
+                    tail.walk(f);
+                }
+            }
+            TExprKind::Switch { scrutinee, arms } => {
+                scrutinee.walk(f);
+                arms.iter().for_each(|a| a.body.walk(f));
+            }
+            TExprKind::Return(value) => {
+                if let Some(value) = value {
+                    value.walk(f);
+                }
+            }
+            TExprKind::Int(_)
+            | TExprKind::Float(_)
+            | TExprKind::Bool(_)
+            | TExprKind::Str(_)
+            | TExprKind::Local(_)
+            | TExprKind::Global(_)
+            | TExprKind::Function(_)
+            | TExprKind::Builtin(_)
+            | TExprKind::Error => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        )
+    }
+
+    /// Whether the comparison holds of operands ordered by `ordering`,
+    /// which is `None` if they are unordered.
+    pub fn compare(self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            // Only NaN is unordered, and it equals nothing.
+            (BinOp::Ne, None) => true,
+            (_, None) => false,
+            (BinOp::Eq, Some(o)) => o.is_eq(),
+            (BinOp::Ne, Some(o)) => o.is_ne(),
+            (BinOp::Lt, Some(o)) => o.is_lt(),
+            (BinOp::Le, Some(o)) => o.is_le(),
+            (BinOp::Gt, Some(o)) => o.is_gt(),
+            (BinOp::Ge, Some(o)) => o.is_ge(),
+            _ => unreachable!("{self:?} is not a comparison"),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Ref,
+    Deref,
+}
+
+/// Functions the compiler provides itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Printf,
+}
+
+#[derive(Debug)]
+pub enum TExprKind {
+    Int(u64),
+    Float(f64),
+    Bool(bool),
+    /// With escape sequences already decoded.
+    Str(String),
+    Local(DeclId),
+    Global(DeclId),
+    Function(FuncId),
+    Builtin(Builtin),
+    Unary {
+        op: UnOp,
+        expr: Box<TExpr>,
+    },
+    Binary {
+        op: BinOp,
+        lhs: Box<TExpr>,
+        rhs: Box<TExpr>,
+    },
+    /// `op` is set for compound assignments such as `+=`.
+    Assign {
+        op: Option<BinOp>,
+        target: Box<TExpr>,
+        value: Box<TExpr>,
+    },
+    /// `tail` is set by [`crate::tail`] for calls in tail position, which
+    /// every backend must compile without growing the stack. `annotated`
+    /// records a `#tail` in the source.
+    Call {
+        callee: Box<TExpr>,
+        args: Vec<TExpr>,
+        tail: bool,
+        annotated: bool,
+    },
+    /// Field `index` of the struct `base`; references are dereferenced
+    /// explicitly beforehand.
+    Field {
+        base: Box<TExpr>,
+        index: usize,
+    },
+    /// Fields in declaration order.
+    Struct {
+        id: DeclId,
+        fields: Vec<TExpr>,
+    },
+    /// Payload fields in declaration order.
+    Variant {
+        id: DeclId,
+        index: usize,
+        fields: Vec<TExpr>,
+    },
+    Block(TBlock),
+    Switch {
+        scrutinee: Box<TExpr>,
+        arms: Vec<TArm>,
+    },
+    Return(Option<Box<TExpr>>),
+    /// Only present in programs that failed to check.
+    Error,
+}
+
+#[derive(Debug)]
+pub struct TBlock {
+    pub stmts: Vec<TStmt>,
+    pub tail: Option<Box<TExpr>>,
+}
+
+#[derive(Debug)]
+pub enum TStmt {
+    Let { id: DeclId, value: TExpr },
+    Expr(TExpr),
+}
+
+#[derive(Debug)]
+pub struct TArm {
+    pub pattern: TPattern,
+    pub body: TExpr,
+}
+
+#[derive(Debug)]
+pub enum TPattern {
+    Int(u64),
+    Bool(bool),
+    Str(String),
+    /// Binds the listed payload fields (by index) to new locals.
+    Variant {
+        index: usize,
+        bindings: Vec<(DeclId, usize)>,
+    },
+    Wildcard,
+}
@@ -0,0 +1,266 @@
+//! Canary's semantic types, as opposed to `parser::node::Type`, which is
+//! only the syntax of a type annotation.
+
+use std::fmt::{self, Display};
+
+use crate::resolve::DeclId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntTy {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntTy {
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntTy::I8 | IntTy::I16 | IntTy::I32 | IntTy::I64)
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntTy::I8 | IntTy::U8 => 8,
+            IntTy::I16 | IntTy::U16 => 16,
+            IntTy::I32 | IntTy::U32 => 32,
+            IntTy::I64 | IntTy::U64 => 64,
+        }
+    }
+
+    /// The largest value of this type.
+    pub fn max(self) -> u64 {
+        if self.is_signed() {
+            (1u64 << (self.bits() - 1)) - 1
+        } else {
+            u64::MAX >> (64 - self.bits())
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IntTy::I8 => "i8",
+            IntTy::I16 => "i16",
+            IntTy::I32 => "i32",
+            IntTy::I64 => "i64",
+            IntTy::U8 => "u8",
+            IntTy::U16 => "u16",
+            IntTy::U32 => "u32",
+            IntTy::U64 => "u64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatTy {
+    F16,
+    F32,
+    F64,
+}
+
+impl FloatTy {
+    /// The largest finite value of the type.
+    pub fn max(self) -> f64 {
+        match self {
+            FloatTy::F16 => 65504.0,
+            FloatTy::F32 => f32::MAX as f64,
+            FloatTy::F64 => f64::MAX,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FloatTy::F16 => "f16",
+            FloatTy::F32 => "f32",
+            FloatTy::F64 => "f64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int(IntTy),
+    Float(FloatTy),
+    Bool,
+    Str,
+    Void,
+    /// The type of `return` and other expressions that never produce a
+    /// value. Fits wherever any type is expected.
+    Never,
+    Struct(DeclId),
+    Enum(DeclId),
+    Tuple(Vec<Ty>),
+    /// `&T`, or `&mut T` if the flag is set.
+    Ref(Box<Ty>, bool),
+    Fn(Box<FnTy>),
+    /// An integer literal whose type is not known yet; see
+    /// [`crate::infer`]. Becomes an `Int`, defaulting to `i32`.
+    IntVar(TyVar),
+    /// Likewise for float literals, defaulting to `f64`.
+    FloatVar(TyVar),
+    /// Stands in for the type of an expression that failed to check, so
+    /// one mistake is reported once.
+    Error,
+}
+
+/// Index of an inference variable in [`crate::infer::InferCtx`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TyVar(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FnTy {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+}
+
+impl Ty {
+    /// Maps the name of a primitive type to the type.
+    pub fn primitive(name: &str) -> Option<Ty> {
+        Some(match name {
+            "i8" => Ty::Int(IntTy::I8),
+            "i16" => Ty::Int(IntTy::I16),
+            "i32" => Ty::Int(IntTy::I32),
+            "i64" => Ty::Int(IntTy::I64),
+            "u8" => Ty::Int(IntTy::U8),
+            "u16" => Ty::Int(IntTy::U16),
+            "u32" => Ty::Int(IntTy::U32),
+            "u64" => Ty::Int(IntTy::U64),
+            "f16" => Ty::Float(FloatTy::F16),
+            "f32" => Ty::Float(FloatTy::F32),
+            "f64" => Ty::Float(FloatTy::F64),
+            "bool" => Ty::Bool,
+            "str" => Ty::Str,
+            "void" => Ty::Void,
+            _ => return None,
+        })
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Ty::Int(_) | Ty::Float(_) | Ty::IntVar(_) | Ty::FloatVar(_)
+        )
+    }
+
+    /// Types that never cause a mismatch: they were either already
+    /// reported or have no value.
+    pub fn is_bottom(&self) -> bool {
+        matches!(self, Ty::Never | Ty::Error)
+    }
+
+    /// Strips any number of references, for auto-deref on field access.
+    pub fn deref_all(&self) -> &Ty {
+        match self {
+            Ty::Ref(inner, _) => inner.deref_all(),
+            ty => ty,
+        }
+    }
+}
+
+/// Gives types their source names; `Ty`'s own `Display` can only print ids
+/// for structs and enums.
+pub trait TypeNames {
+    fn type_name(&self, id: DeclId) -> &str;
+}
+
+pub struct DisplayTy<'a, N: TypeNames> {
+    pub ty: &'a Ty,
+    pub names: &'a N,
+}
+
+impl<N: TypeNames> Display for DisplayTy<'_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |ty| DisplayTy {
+            ty,
+            names: self.names,
+        };
+        match self.ty {
+            Ty::Int(int) => write!(f, "{}", int.as_str()),
+            Ty::Float(float) => write!(f, "{}", float.as_str()),
+            Ty::Bool => write!(f, "bool"),
+            Ty::Str => write!(f, "str"),
+            Ty::Void => write!(f, "void"),
+            Ty::Never => write!(f, "never"),
+            Ty::Struct(id) | Ty::Enum(id) => write!(f, "{}", self.names.type_name(*id)),
+            Ty::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", show(element))?;
+                }
+                write!(f, ")")
+            }
+            Ty::Ref(inner, false) => write!(f, "&{}", show(inner)),
+            Ty::Ref(inner, true) => write!(f, "&mut {}", show(inner)),
+            Ty::Fn(func) => {
+                write!(f, "fn(")?;
+                for (i, param) in func.params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", show(param))?;
+                }
+                write!(f, ") -> {}", show(&func.ret))
+            }
+            Ty::IntVar(_) => write!(f, "{{integer}}"),
+            Ty::FloatVar(_) => write!(f, "{{float}}"),
+            Ty::Error => write!(f, "{{error}}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDef {
+    pub name: String,
+    pub ty: Ty,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<FieldDef>,
+}
+
+impl StructDef {
+    pub fn field(&self, name: &str) -> Option<(usize, &FieldDef)> {
+        self.fields.iter().enumerate().find(|(_, f)| f.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum VariantShape {
+    /// `AllDone`
+    Unit,
+    /// `Todo: str` and `SwimLaps: (u8, u8)`; fields are named by position.
+    Tuple,
+    /// `Meditate: struct = { ... }`
+    Struct,
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantDef {
+    pub name: String,
+    pub shape: VariantShape,
+    /// The payload, flattened. Tuple payloads name their fields `0`, `1`...
+    pub fields: Vec<FieldDef>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<VariantDef>,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: &str) -> Option<(usize, &VariantDef)> {
+        self.variants
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.name == name)
+    }
+}
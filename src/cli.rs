@@ -3,13 +3,24 @@
 use std::path::{self, PathBuf};
 
 use anyhow::bail;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use diagnostics::MessageFormat;
+use ir::opt::OptLevel;
 
 #[derive(Parser, Debug)]
 pub struct Cli {
     /// Adds verbose logging
     #[clap(short = 'v', long = "verbose")]
     pub verbose: bool,
+    /// How to print errors and warnings
+    #[clap(
+        long = "message-format",
+        value_enum,
+        global = true,
+        default_value_t = MessageFormatArg::Human
+    )]
+    pub message_format: MessageFormatArg,
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -17,19 +28,115 @@ pub struct Cli {
 impl Cli {
     pub fn get_abs_path(&self) -> anyhow::Result<PathBuf> {
         match &self.command {
-            Command::Run { file } => {
+            Command::Run { file, .. } | Command::Build { file, .. } => {
                 let abs = path::absolute(file)?;
-                Ok(PathBuf::from(abs))
+                Ok(abs)
             }
-            _ => bail!("Can only get absolute path from Run command"),
+            _ => bail!("Can only get absolute path from Run or Build command"),
+        }
+    }
+
+    /// The source file as given on the command line, used in diagnostics.
+    pub fn file_name(&self) -> &str {
+        match &self.command {
+            Command::Run { file, .. } | Command::Build { file, .. } => file,
+            _ => "<unknown>",
         }
     }
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
-    Run { file: String },
+    /// Runs a source file, or a `.cyb` bytecode file in the VM
+    Run {
+        file: String,
+        /// Compiles to bytecode and runs it in the VM instead of interpreting
+        #[clap(long)]
+        vm: bool,
+    },
+    /// Compiles a file for another toolchain
+    Build {
+        file: String,
+        #[clap(long, value_enum)]
+        target: Target,
+        /// Where to write the output, a directory for `jvm`; defaults to next to the file
+        #[clap(short = 'o', long = "output")]
+        output: Option<PathBuf>,
+        /// How much to optimize the IR before generating code
+        #[clap(short = 'O', value_enum, default_value_t = OptLevelArg::O0)]
+        opt_level: OptLevelArg,
+        /// Prints the IR before optimizing and after each pass that changes it
+        #[clap(long)]
+        dump_ir: bool,
+    },
     BuildTests,
     RunTests,
     BuildAndRunTests,
 }
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Target {
+    /// QBE intermediate language (`.ssa`)
+    Qbe,
+    /// Java class files (`.class`), one per class
+    Jvm,
+    /// .NET CIL as ILAsm text (`.il`)
+    Cil,
+    /// C99 source (`.c`), next to the `canary.h` runtime header it includes
+    C,
+    /// Canary bytecode (`.cyb`), run with `canary run`
+    Vm,
+    /// Canary's mid-level IR as text (`.ir`)
+    Ir,
+}
+
+impl Target {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Target::Qbe => "ssa",
+            Target::Jvm => "class",
+            Target::Cil => "il",
+            Target::C => "c",
+            Target::Vm => "cyb",
+            Target::Ir => "ir",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OptLevelArg {
+    /// No optimization
+    #[value(name = "0")]
+    O0,
+    /// Constant folding, dead code elimination and tail recursion to loops
+    #[value(name = "1")]
+    O1,
+    /// Everything in `-O1`, and inlining of small functions
+    #[value(name = "2")]
+    O2,
+}
+
+impl From<OptLevelArg> for OptLevel {
+    fn from(arg: OptLevelArg) -> Self {
+        match arg {
+            OptLevelArg::O0 => OptLevel::O0,
+            OptLevelArg::O1 => OptLevel::O1,
+            OptLevelArg::O2 => OptLevel::O2,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MessageFormatArg {
+    Human,
+    Json,
+}
+
+impl From<MessageFormatArg> for MessageFormat {
+    fn from(arg: MessageFormatArg) -> Self {
+        match arg {
+            MessageFormatArg::Human => MessageFormat::Human,
+            MessageFormatArg::Json => MessageFormat::Json,
+        }
+    }
+}
@@ -4,25 +4,38 @@ mod tester;
 
 use utils::error;
 
-use anyhow::Result;
+use std::process::ExitCode;
+
 use clap::Parser as ClapParser;
-use cli::Cli;
+use cli::{Cli, MessageFormatArg};
+use diagnostics::Aborted;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
     let res = match cli.command {
-        cli::Command::Run { .. } => runner::run_file(&cli),
-        cli::Command::BuildTests => tester::build_tests(cli.verbose),
-        cli::Command::RunTests => tester::run_tests(cli.verbose),
-        cli::Command::BuildAndRunTests => tester::build_and_run_tests(cli.verbose),
+        cli::Command::Run { vm, .. } => runner::run_file(&cli, vm),
+        cli::Command::Build {
+            target,
+            ref output,
+            opt_level,
+            dump_ir,
+            ..
+        } => runner::build_file(&cli, target, output.as_deref(), opt_level.into(), dump_ir),
+        cli::Command::BuildTests => tester::build_tests(cli.verbose).map(|_| 0),
+        cli::Command::RunTests => tester::run_tests(cli.verbose).map(|_| 0),
+        cli::Command::BuildAndRunTests => tester::build_and_run_tests(cli.verbose).map(|_| 0),
     };
 
     match res {
-        Ok(_) => Ok(()),
+        Ok(status) => ExitCode::from(status),
         Err(e) => {
-            error!("{e}");
-            Ok(())
+            // Diagnostics were already emitted; JSON consumers only want those.
+            let json = matches!(cli.message_format, MessageFormatArg::Json);
+            if !(json && e.is::<Aborted>()) {
+                error!("{e}");
+            }
+            ExitCode::FAILURE
         }
     }
 }
@@ -1,29 +1,186 @@
 #![allow(dead_code, unused_imports)]
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, Read};
+use std::path::Path;
 
 use anyhow::{Result, bail};
 
+use diagnostics::{Aborted, Diagnostic, Emitter, SourceMap};
+use ir::opt::OptLevel;
 use lexer::Lexer;
 use parser::Parser;
+use parser::expand::expand;
 use parser::node::*;
+use sema::check::check;
+use sema::consteval::eval_consts;
+use sema::mutability::check_mutability;
+use sema::resolve::resolve;
+use sema::tail::mark_tail_calls;
+use sema::tast::TProgram;
 use utils::*;
 
-use crate::cli::Cli;
+use crate::cli::{Cli, Target};
 
-pub fn run_file(cli: &Cli) -> Result<()> {
+/// Reads the file named on the command line and runs every phase up to
+/// and including constant evaluation, emitting diagnostics as it goes.
+fn compile(cli: &Cli, sources: &mut SourceMap) -> Result<TProgram> {
     let file = File::open(cli.get_abs_path()?)?;
     let mut buffer = String::new();
     BufReader::new(file).read_to_string(&mut buffer)?;
 
-    let lexer = Lexer::new(&buffer);
+    let file_id = sources.add(cli.file_name(), buffer);
+    let emitter = Emitter::new(cli.message_format.into(), sources);
+
+    let lexer = Lexer::with_file_id(sources.content(file_id), file_id);
     let mut parser = Parser::new(lexer);
-    let program = parser.program()?;
+    let (mut program, mut diags) = parser.program();
+    diags.extend(expand(&mut program));
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    let (resolution, diags) = resolve(&program);
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    let (mut typed, diags) = check(&program, &resolution);
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    let diags = check_mutability(&program, &resolution, &typed);
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    let diags = mark_tail_calls(&mut typed);
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    let diags = eval_consts(&mut typed, &resolution);
+    let errors = emitter.emit_all(&diags);
+    if errors > 0 {
+        return Err(Aborted { errors }.into());
+    }
+
+    Ok(typed)
+}
 
-    for node in program.0 {
-        info!("Node: {:?}", node);
+/// Compiles and interprets the file, or runs it in the VM if `vm` is set or
+/// it is a `.cyb` file, returning the program's exit status.
+pub fn run_file(cli: &Cli, vm: bool) -> Result<u8> {
+    let mut sources = SourceMap::new();
+    if cli.file_name().ends_with(".cyb") {
+        let module = match vm::decode(&fs::read(cli.get_abs_path()?)?) {
+            Ok(module) => module,
+            Err(e) => bail!("cannot load {}: {e}", cli.file_name()),
+        };
+        // Spans point into the source, which may have moved or changed
+        // since, so only those that still fit it are shown.
+        if let Ok(content) = fs::read_to_string(&module.source) {
+            sources.add(&module.source, content);
+        }
+        let result = vm::run(&module, &mut std::io::stdout());
+        return report(
+            cli,
+            &sources,
+            result.map_err(|mut diag| {
+                diag.labels.retain(|l| sources.snippet(l.span).is_some());
+                diag
+            }),
+        );
     }
 
-    Ok(())
+    let typed = compile(cli, &mut sources)?;
+    let result = if vm {
+        let module = vm::compile(&ir::lower(&typed, cli.file_name()));
+        vm::run(&module, &mut std::io::stdout())
+    } else {
+        interpreter::run(&typed, &mut std::io::stdout())
+    };
+    report(cli, &sources, result)
+}
+
+fn report(cli: &Cli, sources: &SourceMap, result: Result<i32, Diagnostic>) -> Result<u8> {
+    match result {
+        // Like a process exit status, only the low byte is kept.
+        Ok(status) => Ok(status as u8),
+        Err(diag) => {
+            let emitter = Emitter::new(cli.message_format.into(), sources);
+            let errors = emitter.emit_all(&[diag]);
+            Err(Aborted { errors }.into())
+        }
+    }
+}
+
+/// Compiles the file for `target` and writes the result next to it, or to
+/// `output`, after optimizing the IR at `level`. The IR is printed as each
+/// pass changes it if `dump_ir` is set.
+pub fn build_file(
+    cli: &Cli,
+    target: Target,
+    output: Option<&Path>,
+    level: OptLevel,
+    dump_ir: bool,
+) -> Result<u8> {
+    let mut sources = SourceMap::new();
+    let typed = compile(cli, &mut sources)?;
+    let mut module = ir::lower(&typed, cli.file_name());
+    if dump_ir {
+        print!("{}", ir::opt::dump(&mut module, level));
+    } else {
+        ir::opt::optimize(&mut module, level);
+    }
+
+    let source = Path::new(cli.file_name());
+    let stem = source
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Main");
+    let single = || match output {
+        Some(path) => path.to_path_buf(),
+        None => source.with_extension(target.extension()),
+    };
+    let files = match target {
+        Target::Qbe => vec![(single(), codegen_qbe::emit(&module).into_bytes())],
+        Target::Cil => vec![(single(), codegen_cil::emit(&module, stem).into_bytes())],
+        Target::C => {
+            let path = single();
+            let header = path.with_file_name("canary.h");
+            let code = codegen_c::emit(&module);
+            vec![
+                (path, code.into_bytes()),
+                (header, codegen_c::RUNTIME.as_bytes().to_vec()),
+            ]
+        }
+        Target::Vm => vec![(single(), vm::encode(&vm::compile(&module)))],
+        Target::Ir => vec![(single(), ir::print(&module).into_bytes())],
+        Target::Jvm => {
+            let dir = match output {
+                Some(path) => path.to_path_buf(),
+                None => source.parent().unwrap_or(Path::new("")).to_path_buf(),
+            };
+            fs::create_dir_all(&dir)?;
+            codegen_jvm::emit(&module, stem)
+                .into_iter()
+                .map(|class| {
+                    let file = format!("{}.{}", class.name, target.extension());
+                    (dir.join(file), class.bytes)
+                })
+                .collect()
+        }
+    };
+    for (path, contents) in files {
+        fs::write(&path, contents)?;
+        info!("Wrote {}", path.display());
+    }
+    Ok(0)
 }
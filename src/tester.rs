@@ -4,13 +4,25 @@ use std::{
     fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, BufWriter, Read},
     path::{Path, PathBuf},
+    process::Command,
 };
 
 use anyhow::bail;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use diagnostics::render::render_human;
+use diagnostics::{Diagnostic, SourceMap};
+use ir::opt::OptLevel;
 use parser::Parser;
+use parser::expand::expand;
+use parser::node::Program;
+use sema::check::check;
+use sema::consteval::eval_consts;
+use sema::mutability::check_mutability;
+use sema::resolve::resolve;
+use sema::tail::mark_tail_calls;
+use sema::tast::TProgram;
 
 use lexer::{
     Lexer,
@@ -72,6 +84,633 @@ impl Serialize for TestList {
     }
 }
 
+/// Parses `content` and returns the printed program followed by every
+/// diagnostic rendered without color and, for programs with a `main` that
+/// compile, what running them prints. This is what `expected` is compared to.
+fn test_output(file: &str, content: String) -> Vec<String> {
+    let mut sources = SourceMap::new();
+    let file_id = sources.add(file, content);
+
+    let lexer = Lexer::with_file_id(sources.content(file_id), file_id);
+    let mut parser = Parser::new(lexer);
+    let (mut program, mut diags) = parser.program();
+    diags.extend(expand(&mut program));
+
+    let mut typed = None;
+    if !diags.iter().any(|d| d.is_error()) {
+        let (program, analysis) = analyze(&program);
+        diags.extend(analysis);
+        typed = program;
+    }
+
+    let mut run = None;
+    if let Some(typed) = typed.filter(|t| t.main.is_some()) {
+        let mut stdout = Vec::new();
+        let status = interpreter::run(&typed, &mut stdout);
+        let mut printed = String::from_utf8_lossy(&stdout).into_owned();
+        if !printed.is_empty() && !printed.ends_with('\n') {
+            printed.push('\n');
+        }
+        match status {
+            Ok(status) => printed.push_str(&format!("exit status: {status}")),
+            Err(diag) => diags.push(diag),
+        }
+        run = Some(printed).filter(|p| !p.is_empty());
+    }
+
+    let mut output = vec![format!("{:?}", program)];
+    output.extend(diags.iter().map(|d| render_human(d, &sources, false)));
+    output.extend(run);
+    output
+}
+
+/// Runs the semantic passes on a parsed program. Like the runner, stops at
+/// the first phase that reports errors, keeping the warnings of the phases
+/// before it; the typed program is only returned if there were none.
+fn analyze(program: &Program) -> (Option<TProgram>, Vec<Diagnostic>) {
+    let mut all = Vec::new();
+    let mut phase = |diags: Vec<Diagnostic>| {
+        let failed = diags.iter().any(|d| d.is_error());
+        all.extend(diags);
+        failed
+    };
+    let (resolution, diags) = resolve(program);
+    if phase(diags) {
+        return (None, all);
+    }
+    let (mut typed, diags) = check(program, &resolution);
+    if phase(diags) {
+        return (None, all);
+    }
+    if phase(check_mutability(program, &resolution, &typed)) {
+        return (None, all);
+    }
+    if phase(mark_tail_calls(&mut typed)) {
+        return (None, all);
+    }
+    if phase(eval_consts(&mut typed, &resolution)) {
+        return (None, all);
+    }
+    (Some(typed), all)
+}
+
+/// The programs every backend golden is compiled from.
+const PROGRAMS: &str = "./tests/programs/";
+
+/// A code generator with golden files: each program it lists, from
+/// `tests/programs/`, has its output in `tests/<dir>/`, with the same name
+/// and `extension`.
+struct Backend {
+    dir: &'static str,
+    extension: &'static str,
+    /// The stems of the programs it is tested on.
+    programs: &'static [&'static str],
+    /// Compiles a program, given the file stem, to the golden text.
+    emit: fn(&TProgram, &str) -> String,
+}
+
+const BACKENDS: &[Backend] = &[
+    Backend {
+        dir: "qbe",
+        extension: "ssa",
+        programs: &["aggregates", "basics", "consts", "runtime_error", "tail"],
+        emit: |program, stem| codegen_qbe::emit(&ir::lower(program, &format!("{stem}.cy"))),
+    },
+    Backend {
+        dir: "jvm",
+        extension: "jvm",
+        programs: &["aggregates", "basics", "consts", "references", "tail"],
+        emit: jvm_dump,
+    },
+    Backend {
+        dir: "c",
+        extension: "c",
+        programs: &[
+            "aggregates",
+            "basics",
+            "consts",
+            "interpret",
+            "lowering",
+            "references",
+            "runtime_error",
+            "tail",
+        ],
+        emit: c_golden,
+    },
+    Backend {
+        dir: "c_opt",
+        extension: "c",
+        programs: &["consts", "fold", "inline", "tail"],
+        emit: c_opt_golden,
+    },
+    Backend {
+        dir: "cil",
+        extension: "il",
+        programs: &["aggregates", "basics", "consts", "references", "tail"],
+        emit: |program, stem| codegen_cil::emit(&ir::lower(program, &format!("{stem}.cy")), stem),
+    },
+    Backend {
+        dir: "vm",
+        extension: "vm",
+        programs: &[
+            "aggregates",
+            "basics",
+            "consts",
+            "interpret",
+            "numbers",
+            "references",
+            "runtime_error",
+            "tail",
+        ],
+        emit: vm_golden,
+    },
+    Backend {
+        dir: "ir",
+        extension: "ir",
+        programs: &[
+            "aggregates",
+            "basics",
+            "consts",
+            "interpret",
+            "lowering",
+            "numbers",
+            "references",
+            "runtime_error",
+            "tail",
+        ],
+        emit: ir_golden,
+    },
+    Backend {
+        dir: "opt",
+        extension: "ir",
+        programs: &["consts", "fold", "inline"],
+        emit: opt_golden,
+    },
+];
+
+/// Every class file, checked and disassembled.
+fn jvm_dump(program: &TProgram, stem: &str) -> String {
+    codegen_jvm::emit(&ir::lower(program, &format!("{stem}.cy")), stem)
+        .iter()
+        .map(|class| match codegen_jvm::dump(&class.bytes) {
+            Ok(text) => text,
+            Err(e) => format!("{}: invalid class file: {e}\n", class.name),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The disassembled bytecode, loaded back from its `.cyb` encoding, then
+/// what running it prints. The run must match the interpreter's.
+fn vm_golden(program: &TProgram, stem: &str) -> String {
+    let module = vm::compile(&ir::lower(program, &format!("{stem}.cy")));
+    let module = match vm::decode(&vm::encode(&module)) {
+        Ok(loaded) if loaded == module => loaded,
+        Ok(_) => return "bytecode changed when loaded back\n".to_string(),
+        Err(e) => return format!("invalid bytecode: {e}\n"),
+    };
+    let mut output = vm::dump(&module);
+    if module.main.is_none() {
+        return output;
+    }
+
+    let mut stdout = Vec::new();
+    let from_vm = run_summary(vm::run(&module, &mut stdout), stdout);
+    let from_tree = interpret_summary(program);
+    output.push_str("\n; output:\n");
+    output.push_str(&from_vm);
+    if from_vm != from_tree {
+        output.push_str("\n; the interpreter differs:\n");
+        output.push_str(&from_tree);
+    }
+    output
+}
+
+/// The IR as text, then what running it prints. The module must verify,
+/// read back to the same text, and run like the interpreter.
+fn ir_golden(program: &TProgram, stem: &str) -> String {
+    let module = ir::lower(program, &format!("{stem}.cy"));
+    let mut output = ir::print(&module);
+    check_ir(&module, &mut output);
+    run_ir(&module, program, &mut output);
+    output
+}
+
+/// The IR before optimizing at `-O2` and after each pass that changes it,
+/// then what running the result prints. Each step must verify and read
+/// back, and the result must still run like the interpreter.
+fn opt_golden(program: &TProgram, stem: &str) -> String {
+    let mut module = ir::lower(program, &format!("{stem}.cy"));
+    let mut output = format!("; before optimization\n{}", ir::print(&module));
+    ir::opt::optimize_with(&mut module, OptLevel::O2, |pass, module| {
+        output.push_str(&format!("\n; after {}\n", pass.name));
+        let mut text = ir::print(module);
+        check_ir(module, &mut text);
+        output.push_str(&text);
+    });
+    run_ir(&module, program, &mut output);
+    output
+}
+
+/// Adds a comment to `text`, the printed `module`, if the module is not
+/// well formed or does not read back to the same text.
+fn check_ir(module: &ir::Module, text: &mut String) {
+    if let Err(e) = ir::verify(module) {
+        text.push_str(&format!("\n; invalid IR: {e}\n"));
+    }
+    match ir::parse(text) {
+        Ok(parsed) if ir::print(&parsed) == *text => {}
+        Ok(_) => text.push_str("\n; the IR changed when read back\n"),
+        Err(e) => text.push_str(&format!("\n; the IR does not read back: {e}\n")),
+    }
+}
+
+/// Adds what running `module` prints to `output` as comments, and what
+/// the interpreter prints for `program` if that differs.
+fn run_ir(module: &ir::Module, program: &TProgram, output: &mut String) {
+    if module.main.is_none() {
+        return;
+    }
+    let mut stdout = Vec::new();
+    let from_ir = run_summary(ir::run(module, &mut stdout), stdout);
+    let from_tree = interpret_summary(program);
+    output.push_str("\n; output:\n");
+    output.push_str(
+        &from_ir
+            .lines()
+            .map(|l| format!("; {l}\n"))
+            .collect::<String>(),
+    );
+    if from_ir != from_tree {
+        output.push_str("\n; the interpreter differs:\n");
+        output.push_str(
+            &from_tree
+                .lines()
+                .map(|l| format!("; {l}\n"))
+                .collect::<String>(),
+        );
+    }
+}
+
+/// The generated C. Where `cc` is available, the program is also built and
+/// run, and a comment saying how its run differs from the interpreter's is
+/// added, so the golden only matches if it does not.
+fn c_golden(program: &TProgram, stem: &str) -> String {
+    let module = ir::lower(program, &format!("{stem}.cy"));
+    c_check(&module, program, stem)
+}
+
+/// The C generated from the IR optimized at `-O2`, built and run like
+/// `c_golden`'s.
+fn c_opt_golden(program: &TProgram, stem: &str) -> String {
+    let mut module = ir::lower(program, &format!("{stem}.cy"));
+    ir::opt::optimize(&mut module, OptLevel::O2);
+    c_check(&module, program, stem)
+}
+
+/// The C for `module`, lowered from `program`, with a comment if building
+/// or running it does not go like the interpreter.
+fn c_check(module: &ir::Module, program: &TProgram, stem: &str) -> String {
+    let mut output = codegen_c::emit(module);
+    if program.main.is_none() {
+        return output;
+    }
+    let native = match run_c(&output, stem) {
+        Ok(Some(native)) => native,
+        Ok(None) => return output,
+        Err(e) => {
+            output.push_str(&format!("\n/* cc failed:\n{} */\n", e.replace("*/", "*_/")));
+            return output;
+        }
+    };
+    // A process only gets the low byte of the status.
+    let mut stdout = Vec::new();
+    let result = interpreter::run(program, &mut stdout).map(|status| status & 0xff);
+    let from_tree = run_summary(result, stdout);
+    if native != from_tree {
+        output.push_str(&format!(
+            "\n/* The native run differs from the interpreter's:\n{native}\nexpected:\n{from_tree}*/\n"
+        ));
+    }
+    output
+}
+
+/// Compiles C source with `cc` and runs it, returning what it printed as
+/// `run_summary` does, or `None` if there is no `cc`.
+fn run_c(code: &str, stem: &str) -> Result<Option<String>, String> {
+    let Ok(probe) = Command::new("cc").arg("--version").output() else {
+        return Ok(None);
+    };
+    if !probe.status.success() {
+        return Ok(None);
+    }
+    let dir = std::env::temp_dir().join(format!("canary-c-{}-{stem}", std::process::id()));
+    let result = (|| {
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        fs::write(dir.join("canary.h"), codegen_c::RUNTIME).map_err(|e| e.to_string())?;
+        let source = dir.join(format!("{stem}.c"));
+        fs::write(&source, code).map_err(|e| e.to_string())?;
+        let binary = dir.join(stem);
+        let compiled = Command::new("cc")
+            .args(["-std=c99", "-pedantic", "-O0", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .output()
+            .map_err(|e| e.to_string())?;
+        if !compiled.status.success() {
+            return Err(String::from_utf8_lossy(&compiled.stderr).into_owned());
+        }
+        let run = Command::new(&binary).output().map_err(|e| e.to_string())?;
+        let stderr = String::from_utf8_lossy(&run.stderr);
+        let mut summary = String::from_utf8_lossy(&run.stdout).into_owned();
+        if !summary.is_empty() && !summary.ends_with('\n') {
+            summary.push('\n');
+        }
+        // Runtime errors are reported as `file:line:col: runtime error: ...`.
+        match stderr.find("runtime error: ") {
+            Some(at) => summary.push_str(&stderr[at..]),
+            None => {
+                let status = run.status.code().unwrap_or(-1);
+                summary.push_str(&format!("exit status: {status}\n"));
+            }
+        }
+        Ok(Some(summary))
+    })();
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// What interpreting `program` prints, as `run_summary` does.
+fn interpret_summary(program: &TProgram) -> String {
+    let mut stdout = Vec::new();
+    let result = interpreter::run(program, &mut stdout);
+    run_summary(result, stdout)
+}
+
+/// The output of a run, then its exit status or runtime error.
+fn run_summary(result: Result<i32, Diagnostic>, stdout: Vec<u8>) -> String {
+    let mut summary = String::from_utf8_lossy(&stdout).into_owned();
+    if !summary.is_empty() && !summary.ends_with('\n') {
+        summary.push('\n');
+    }
+    match result {
+        Ok(status) => summary.push_str(&format!("exit status: {status}\n")),
+        Err(diag) => summary.push_str(&format!("{}\n", diag.message)),
+    }
+    summary
+}
+
+/// Compiles `content` with `backend`. Programs that fail to compile produce
+/// their diagnostics instead, so a broken test shows up in the golden file.
+fn backend_output(backend: &Backend, file: &str, content: String) -> String {
+    let mut sources = SourceMap::new();
+    let file_id = sources.add(file, content);
+
+    let lexer = Lexer::with_file_id(sources.content(file_id), file_id);
+    let mut parser = Parser::new(lexer);
+    let (mut program, mut diags) = parser.program();
+    diags.extend(expand(&mut program));
+
+    let mut typed = None;
+    if !diags.iter().any(|d| d.is_error()) {
+        let (program, analysis) = analyze(&program);
+        diags.extend(analysis);
+        typed = program;
+    }
+
+    match typed {
+        Some(typed) => {
+            let stem = Path::new(file).file_stem().and_then(|s| s.to_str());
+            (backend.emit)(&typed, stem.unwrap_or("Main"))
+        }
+        None => diags
+            .iter()
+            .map(|d| render_human(d, &sources, false))
+            .collect(),
+    }
+}
+
+/// The programs a backend is tested on and the golden file of each.
+fn backend_tests(backend: &Backend) -> Vec<(PathBuf, PathBuf)> {
+    let dir = Path::new("./tests/").join(backend.dir);
+    backend
+        .programs
+        .iter()
+        .map(|stem| {
+            let source = Path::new(PROGRAMS).join(stem).with_extension("cy");
+            let golden = dir.join(stem).with_extension(backend.extension);
+            (source, golden)
+        })
+        .collect()
+}
+
+/// Bytecode that no compiler produces, which the VM must reject with an
+/// error, when loading or when running it, rather than panic. The golden
+/// lists the error for each case.
+const MALFORMED: &str = "./tests/vm/malformed.txt";
+
+/// A module whose `main` runs `code`, with a constant pool of an integer
+/// and a string, and a function `pair` of two parameters.
+fn malformed_module(code: Vec<vm::Op>) -> vm::Module {
+    let function = |name: &str, params, code| vm::Function {
+        name: name.to_string(),
+        params,
+        locals: 2,
+        code,
+        spans: Vec::new(),
+    };
+    vm::Module {
+        source: "malformed.cy".to_string(),
+        constants: vec![vm::Constant::Int(1), vm::Constant::Str("one".to_string())],
+        functions: vec![
+            function("main", 0, code),
+            function("pair", 2, vec![vm::Op::Load(0), vm::Op::Return]),
+        ],
+        globals: Vec::new(),
+        main: Some(0),
+    }
+}
+
+/// How the VM handles `bytes`: the error loading or running them, or
+/// what the program returned.
+fn load_and_run(bytes: &[u8]) -> Result<String, String> {
+    std::panic::catch_unwind(|| {
+        let module = vm::decode(bytes).map_err(|e| format!("cannot load: {e}"))?;
+        let mut stdout = Vec::new();
+        match vm::run(&module, &mut stdout) {
+            Ok(status) => Ok(format!("ran, exit status: {status}")),
+            Err(diag) => Err(diag.message),
+        }
+    })
+    .unwrap_or_else(|_| Err("panicked".to_string()))
+}
+
+fn malformed_output() -> String {
+    use vm::{Format, Num, Op};
+    let cases = [
+        ("local out of range", vec![Op::Load(7), Op::Return]),
+        ("constant out of range", vec![Op::Const(9), Op::Return]),
+        ("function out of range", vec![Op::Call(5, 0), Op::Return]),
+        ("jump out of range", vec![Op::Jump(40)]),
+        ("no return at the end", vec![Op::Void]),
+        ("pop of an empty stack", vec![Op::Pop, Op::Void, Op::Return]),
+        ("return of an empty stack", vec![Op::Return]),
+        (
+            "struct of missing fields",
+            vec![Op::Void, Op::Struct(3), Op::Return],
+        ),
+        (
+            "call with too few arguments",
+            vec![Op::Const(0), Op::Call(1, 1), Op::Return],
+        ),
+        (
+            "call of missing arguments",
+            vec![Op::Call(1, 2), Op::Return],
+        ),
+        (
+            "call of an integer",
+            vec![Op::Const(0), Op::CallValue(0), Op::Return],
+        ),
+        (
+            "string plus integer",
+            vec![Op::Const(1), Op::Const(0), Op::Add(Num::I64), Op::Return],
+        ),
+        (
+            "negated bool",
+            vec![Op::True, Op::Neg(Num::I64), Op::Return],
+        ),
+        (
+            "string compared to integer",
+            vec![Op::Const(1), Op::Const(0), Op::Lt(Num::I64), Op::Return],
+        ),
+        (
+            "branch on an integer",
+            vec![Op::Const(0), Op::JumpIfFalse(3), Op::Void, Op::Return],
+        ),
+        (
+            "field of an integer",
+            vec![Op::Const(0), Op::Field(0), Op::Return],
+        ),
+        (
+            "field past the end",
+            vec![Op::Void, Op::Struct(1), Op::Field(3), Op::Return],
+        ),
+        (
+            "tag of a struct",
+            vec![Op::Void, Op::Struct(1), Op::Tag, Op::Return],
+        ),
+        (
+            "dereferenced integer",
+            vec![Op::Const(0), Op::Deref, Op::Return],
+        ),
+        (
+            "store through an integer",
+            vec![
+                Op::Const(0),
+                Op::Const(0),
+                Op::StoreRef,
+                Op::Void,
+                Op::Return,
+            ],
+        ),
+        (
+            "reference to a missing field",
+            vec![Op::Void, Op::Temp, Op::RefField(2), Op::Deref, Op::Return],
+        ),
+        (
+            "unboxed local loaded as a box",
+            vec![Op::LoadBox(0), Op::Return],
+        ),
+        (
+            "integer written as a string",
+            vec![Op::Const(0), Op::Write(Format::Str), Op::Void, Op::Return],
+        ),
+    ];
+    let mut output = String::new();
+    for (name, code) in cases {
+        let result = load_and_run(&vm::encode(&malformed_module(code)));
+        output.push_str(&format!("{name}: {}\n", result.unwrap_or_else(|e| e)));
+    }
+
+    // Every truncation and every flipped byte of a valid module.
+    let bytes = vm::encode(&malformed_module(vec![Op::Const(0), Op::Return]));
+    let truncated = (0..bytes.len())
+        .filter(|&len| load_and_run(&bytes[..len]).is_ok())
+        .count();
+    output.push_str(&format!(
+        "\n{} truncations of {} bytes, {truncated} loaded\n",
+        bytes.len(),
+        bytes.len()
+    ));
+    let (mut ran, mut panicked) = (0, 0);
+    for at in 0..bytes.len() {
+        let mut flipped = bytes.clone();
+        flipped[at] ^= 0xff;
+        match load_and_run(&flipped) {
+            Ok(_) => ran += 1,
+            Err(e) if e == "panicked" => panicked += 1,
+            Err(_) => {}
+        }
+    }
+    output.push_str(&format!(
+        "{} flipped bytes, {ran} ran, {panicked} panicked\n",
+        bytes.len()
+    ));
+    output
+}
+
+fn build_backend_tests() -> anyhow::Result<()> {
+    for backend in BACKENDS {
+        for (source, golden) in backend_tests(backend) {
+            info!("Building {}", source.display());
+            let content = fs::read_to_string(&source)?;
+            let output = backend_output(backend, source.to_str().unwrap_or(""), content);
+            fs::write(golden, output)?;
+        }
+    }
+    info!("Building {MALFORMED}");
+    fs::write(MALFORMED, malformed_output())?;
+    Ok(())
+}
+
+/// Checks every backend golden file. Returns how many passed and how many
+/// there are.
+fn run_backend_tests() -> anyhow::Result<(usize, usize)> {
+    let mut passed = 0;
+    let mut total = 0;
+    for backend in BACKENDS {
+        for (source, golden) in backend_tests(backend) {
+            total += 1;
+            let content = fs::read_to_string(&source)?;
+            let actual = backend_output(backend, source.to_str().unwrap_or(""), content);
+            let expected = fs::read_to_string(&golden).unwrap_or_default();
+            if actual != expected {
+                error!(
+                    "{}: {} output mismatch\n\tExpected:\n{}\n\t     Got:\n{}",
+                    source.display(),
+                    backend.dir,
+                    expected,
+                    actual
+                );
+                continue;
+            }
+            passed += 1;
+            info!("{} passed", golden.display());
+        }
+    }
+
+    total += 1;
+    let actual = malformed_output();
+    let expected = fs::read_to_string(MALFORMED).unwrap_or_default();
+    if actual == expected {
+        passed += 1;
+        info!("{MALFORMED} passed");
+    } else {
+        error!("{MALFORMED}: output mismatch\n\tExpected:\n{expected}\n\t     Got:\n{actual}");
+    }
+    Ok((passed, total))
+}
+
 pub fn build_tests(verbose: bool) -> anyhow::Result<()> {
     let test_dir = Path::new("./tests/");
     let expected_path = test_dir.join("expected.json");
@@ -94,22 +733,14 @@ pub fn build_tests(verbose: bool) -> anyhow::Result<()> {
             let mut test = Test::empty();
             test.file = path.to_str().unwrap_or("").to_string();
 
-            let lexer = Lexer::new(&content);
-            let mut parser = Parser::new(lexer);
-            let program = match parser.program() {
-                Ok(p) => p,
-                Err(e) => {
-                    error!("Test failed, adding to skipped");
-                    if verbose {
-                        context!("{}", e);
-                    }
-                    let mut test = Test::empty();
-                    test.file = path.to_str().unwrap_or("").to_string();
-                    test.skipped = true;
-                    return Ok(test);
-                }
-            };
-            test.expected = vec![format!("{:?}", program)];
+            test.expected = test_output(&test.file, content);
+            if verbose && test.expected.len() > 1 {
+                context!(
+                    "{} produced {} diagnostic(s)",
+                    test.file,
+                    test.expected.len() - 1
+                );
+            }
 
             Ok(test)
         })
@@ -128,16 +759,16 @@ pub fn build_tests(verbose: bool) -> anyhow::Result<()> {
 
     serde_json::to_writer_pretty(&mut writer, &tests)?;
 
-    Ok(())
+    build_backend_tests()
 }
 
 pub fn run_tests(verbose: bool) -> anyhow::Result<()> {
     let expected_file = File::open("./tests/expected.json")?;
     let tests: TestList = serde_json::from_reader(expected_file)?;
 
-    let mut passed: usize = 0;
+    let (mut passed, backend_total) = run_backend_tests()?;
     let mut skipped: usize = 0;
-    let total = tests.tests.len();
+    let total = tests.tests.len() + backend_total;
 
     'tests: for test in &tests.tests {
         if test.skipped {
@@ -154,24 +785,24 @@ pub fn run_tests(verbose: bool) -> anyhow::Result<()> {
         let mut content = String::new();
         BufReader::new(test_file).read_to_string(&mut content)?;
 
-        let lexer = Lexer::new(&content);
-        let mut parser = Parser::new(lexer);
-        let program = match parser.program() {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Error parsing {}: {}", test.file, e);
-                if verbose {
-                    context!("{}", e);
-                }
-                continue 'tests;
-            }
-        };
-        let actual_str = format!("{:?}", program);
+        let actual = test_output(&test.file, content);
 
-        if actual_str != test.expected[0] {
+        if actual.first() != test.expected.first() {
             error!(
                 "{}: AST mismatch\n\tExpected: {}\n\t     Got: {}",
-                test.file, test.expected[0], actual_str
+                test.file,
+                test.expected.first().map_or("", |s| s.as_str()),
+                actual.first().map_or("", |s| s.as_str())
+            );
+            continue 'tests;
+        }
+
+        if actual.get(1..) != test.expected.get(1..) {
+            error!(
+                "{}: diagnostics mismatch\n\tExpected: {:#?}\n\t     Got: {:#?}",
+                test.file,
+                test.expected.get(1..).unwrap_or_default(),
+                actual.get(1..).unwrap_or_default()
             );
             continue 'tests;
         }
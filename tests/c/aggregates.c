@@ -0,0 +1,97 @@
+/* Generated by the Canary compiler from aggregates.cy. */
+#include "canary.h"
+
+typedef struct Point Point;
+typedef struct Line Line;
+typedef struct Shape Shape;
+
+struct Point {
+    int32_t x;
+    int64_t y;
+};
+
+struct Line {
+    Point from;
+    Point to;
+};
+
+struct Shape {
+    int32_t tag;
+    union {
+        struct {
+            Point _0;
+        } Dot;
+        struct {
+            Line _0;
+        } Segment;
+    } as;
+};
+
+static Point Point_new(int32_t, int64_t);
+static void Point_nudge(Point *);
+static int64_t length(Shape);
+static void main_(void);
+
+static Point Point_new(int32_t v0, int64_t v1) {
+    Point v2 = (Point){ .x = v0, .y = v1 };
+    return v2;
+}
+
+static void Point_nudge(Point *v0) {
+    int32_t *v1 = &v0->x;
+    int32_t v3 = (*v1);
+    int32_t v4 = CY_ADD(int32_t, v3, 1);
+    (*v1) = v4;
+    return;
+}
+
+static int64_t length(Shape v0) {
+    int64_t v12;
+    uint32_t v1 = (uint32_t)v0.tag;
+    switch (v1) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    case 2u: goto b3;
+    default: goto b5;
+    }
+b1:;
+    v12 = INT64_C(0);
+    goto b4;
+b2:;
+    Line v4 = v0.as.Segment._0;
+    Point v5 = v4.to;
+    int64_t v6 = v5.y;
+    Point v7 = v4.from;
+    int64_t v8 = v7.y;
+    int64_t v9 = CY_SUB(int64_t, v6, v8);
+    v12 = v9;
+    goto b4;
+b3:;
+    int64_t v11 = CY_NEG(int64_t, INT64_C(1));
+    v12 = v11;
+b4:;
+    return v12;
+b5:;
+    cy_runtime_error("aggregates.cy:26:3", "no switch arm matched");
+}
+
+static void main_(void) {
+    Point slot;
+    Point v3 = Point_new(1, INT64_C(2));
+    slot = v3;
+    Point_nudge(&slot);
+    Point v4 = slot;
+    Point v7 = Point_new(3, INT64_C(40));
+    Line v8 = (Line){ .from = v4, .to = v7 };
+    Shape v9 = (Shape){ .tag = 1, .as.Segment._0 = v8 };
+    Point v10 = slot;
+    int32_t v11 = v10.x;
+    int64_t v12 = length(v9);
+    printf("%" PRId32 " %" PRId64 "\n", v11, v12);
+    return;
+}
+
+int main(void) {
+    main_();
+    return 0;
+}
@@ -0,0 +1,132 @@
+/* Generated by the Canary compiler from basics.cy. */
+#include "canary.h"
+
+static uint8_t Base;
+static unsigned char cy_state_Base;
+static uint8_t Scale;
+static unsigned char cy_state_Scale;
+
+static bool outside(int32_t, int32_t, int32_t);
+static int32_t sign(int32_t);
+static const char *greeting(const char *);
+static int32_t main_(void);
+static uint8_t Base_init(void);
+static uint8_t Scale_init(void);
+static void cy_init_Base(const char *at);
+static void cy_init_Scale(const char *at);
+
+static bool outside(int32_t v0, int32_t v1, int32_t v2) {
+    bool v6;
+    bool v3 = (v0 < v1);
+    if (v3) {
+        v6 = true;
+        goto b2;
+    }
+    bool v5 = (v0 > v2);
+    v6 = v5;
+b2:;
+    return v6;
+}
+
+static int32_t sign(int32_t v0) {
+    int32_t v3;
+    switch (v0) {
+    case 0: goto b1;
+    default: goto b2;
+    }
+b1:;
+    v3 = 0;
+    goto b3;
+b2:;
+    int32_t v2 = ((int32_t)cy_div_i64(v0, v0, "basics.cy:11:10"));
+    v3 = v2;
+b3:;
+    return v3;
+}
+
+static const char *greeting(const char *v0) {
+    const char *v8;
+    bool v2 = (strcmp(v0, "fr") == 0);
+    if (v2) goto b1;
+    goto b2;
+b1:;
+    v8 = "bonjour";
+    goto b5;
+b2:;
+    bool v5 = (strcmp(v0, "de") == 0);
+    if (v5) goto b3;
+    goto b4;
+b3:;
+    v8 = "hallo";
+    goto b5;
+b4:;
+    v8 = "hello";
+b5:;
+    return v8;
+}
+
+static int32_t main_(void) {
+    uint8_t v3;
+    uint64_t v6;
+    int16_t v8;
+    bool v17;
+    bool v24;
+    cy_init_Scale("basics.cy:24:22");
+    uint8_t v1 = Scale;
+    v3 = CY_ADD(uint8_t, v1, 10);
+    v6 = cy_div_u64(UINT64_C(18446744073709551615), UINT64_C(2), "basics.cy:26:20");
+    v8 = CY_NEG(int16_t, -32768);
+    const char *v10 = greeting("en");
+    bool v12 = (strcmp(v10, "hello") == 0);
+    if (v12) goto b1;
+    v17 = false;
+    goto b2;
+b1:;
+    bool v15 = (v6 < UINT64_C(5));
+    bool v16 = (!v15);
+    v17 = v16;
+b2:;
+    if (v17) goto b3;
+    v24 = false;
+    goto b4;
+b3:;
+    bool v22 = outside(5, 0, 10);
+    bool v23 = (!v22);
+    v24 = v23;
+b4:;
+    int16_t v26 = CY_SUB(int16_t, v8, 1);
+    int32_t v28 = CY_NEG(int32_t, 15);
+    int32_t v29 = sign(v28);
+    printf("%" PRIu8 " %" PRIu64 " %" PRId16 " %" PRId32 "\n", v3, v6, v26, v29);
+    const char *v31 = greeting("fr");
+    printf("100%% %s\n", v31);
+    int32_t v33 = sign(7);
+    int32_t v35 = CY_SUB(int32_t, v33, 1);
+    return v35;
+}
+
+static uint8_t Base_init(void) {
+    return 200;
+}
+
+static uint8_t Scale_init(void) {
+    return 250;
+}
+
+static void cy_init_Base(const char *at) {
+    if (cy_begin_init(&cy_state_Base, at)) {
+        Base = Base_init();
+        cy_state_Base = CY_SET;
+    }
+}
+
+static void cy_init_Scale(const char *at) {
+    if (cy_begin_init(&cy_state_Scale, at)) {
+        Scale = Scale_init();
+        cy_state_Scale = CY_SET;
+    }
+}
+
+int main(void) {
+    return (int)main_();
+}
@@ -0,0 +1,283 @@
+/* Generated by the Canary compiler from consts.cy. */
+#include "canary.h"
+
+typedef struct Point Point;
+typedef struct Shape Shape;
+
+struct Point {
+    int32_t x;
+    int32_t y;
+};
+
+struct Shape {
+    int32_t tag;
+    union {
+        struct {
+            int32_t _0;
+        } Square;
+    } as;
+};
+
+static int32_t Width;
+static unsigned char cy_state_Width;
+static int32_t Height;
+static unsigned char cy_state_Height;
+static Point Origin;
+static unsigned char cy_state_Origin;
+static Shape Big;
+static unsigned char cy_state_Big;
+static const char *Name;
+static unsigned char cy_state_Name;
+static bool Wide;
+static unsigned char cy_state_Wide;
+static float Half;
+static unsigned char cy_state_Half;
+static int32_t Counter;
+static unsigned char cy_state_Counter;
+static int32_t Twice;
+static unsigned char cy_state_Twice;
+
+static int32_t fib(int32_t);
+static int32_t fib_from(int32_t, int32_t, int32_t);
+static const char *pick(int32_t);
+static int32_t area(Shape);
+static int32_t main_(void);
+static int32_t Width_init(void);
+static int32_t Height_init(void);
+static Point Origin_init(void);
+static Shape Big_init(void);
+static const char *Name_init(void);
+static bool Wide_init(void);
+static float Half_init(void);
+static int32_t Counter_init(void);
+static int32_t Twice_init(void);
+static void cy_init_Width(const char *at);
+static void cy_init_Height(const char *at);
+static void cy_init_Origin(const char *at);
+static void cy_init_Big(const char *at);
+static void cy_init_Name(const char *at);
+static void cy_init_Wide(const char *at);
+static void cy_init_Half(const char *at);
+static void cy_init_Counter(const char *at);
+static void cy_init_Twice(const char *at);
+
+static int32_t fib(int32_t v0) {
+    return fib_from(v0, 0, 1);
+}
+
+static int32_t fib_from(int32_t v0, int32_t v1, int32_t v2) {
+    int32_t v3;
+b0:;
+    switch (v0) {
+    case 0: goto b1;
+    default: goto b3;
+    }
+b1:;
+    v3 = v1;
+    return v3;
+b3:;
+    int32_t v5 = CY_SUB(int32_t, v0, 1);
+    int32_t v6 = CY_ADD(int32_t, v1, v2);
+    v0 = v5;
+    v1 = v2;
+    v2 = v6;
+    goto b0;
+}
+
+static const char *pick(int32_t v0) {
+    const char *v17;
+    Point slot;
+    Point v3 = (Point){ .x = v0, .y = 0 };
+    slot = v3;
+    Point v5 = slot;
+    int32_t v6 = v5.x;
+    int32_t v8 = ((int32_t)cy_div_i64(v6, 10, "consts.cy:36:9"));
+    slot.y = v8;
+    int32_t v11 = slot.y;
+    int32_t v12 = CY_ADD(int32_t, v11, 1);
+    slot.y = v12;
+    Point v13 = slot;
+    int32_t v14 = v13.y;
+    switch (v14) {
+    case 5: goto b1;
+    default: goto b2;
+    }
+b1:;
+    v17 = "wide";
+    goto b3;
+b2:;
+    v17 = "narrow";
+b3:;
+    return v17;
+}
+
+static int32_t area(Shape v0) {
+    int32_t v5;
+    uint32_t v1 = (uint32_t)v0.tag;
+    switch (v1) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    default: goto b4;
+    }
+b1:;
+    v5 = 0;
+    goto b3;
+b2:;
+    int32_t v3 = v0.as.Square._0;
+    int32_t v4 = CY_MUL(int32_t, v3, v3);
+    v5 = v4;
+b3:;
+    return v5;
+b4:;
+    cy_runtime_error("consts.cy:45:3", "no switch arm matched");
+}
+
+static int32_t main_(void) {
+    bool v29;
+    cy_init_Counter("consts.cy:52:3");
+    Counter = 10;
+    cy_init_Width("consts.cy:53:31");
+    int32_t v3 = Width;
+    cy_init_Height("consts.cy:53:38");
+    int32_t v5 = Height;
+    cy_init_Origin("consts.cy:53:46");
+    Point v7 = Origin;
+    int32_t v8 = v7.x;
+    cy_init_Origin("consts.cy:53:56");
+    Point v10 = Origin;
+    int32_t v11 = v10.y;
+    printf("%" PRId32 " %" PRId32 " %" PRId32 " %" PRId32 "\n", v3, v5, v8, v11);
+    cy_init_Name("consts.cy:54:27");
+    const char *v13 = Name;
+    cy_init_Big("consts.cy:54:38");
+    Shape v15 = Big;
+    int32_t v16 = area(v15);
+    cy_init_Half("consts.cy:54:44");
+    float v18 = Half;
+    printf("%s %" PRId32 " %f\n", v13, v16, v18);
+    cy_init_Twice("consts.cy:55:19");
+    int32_t v20 = Twice;
+    printf("%" PRId32 "\n", v20);
+    cy_init_Wide("consts.cy:56:15");
+    bool v22 = Wide;
+    if (v22) goto b1;
+    v29 = false;
+    goto b2;
+b1:;
+    cy_init_Half("consts.cy:56:25");
+    float v25 = Half;
+    bool v27 = (v25 > 1.0f);
+    bool v28 = (!v27);
+    v29 = v28;
+b2:;
+    return 0;
+}
+
+static int32_t Width_init(void) {
+    return 42;
+}
+
+static int32_t Height_init(void) {
+    return 20;
+}
+
+static Point Origin_init(void) {
+    int32_t v2 = CY_NEG(int32_t, 20);
+    Point v3 = (Point){ .x = 42, .y = v2 };
+    return v3;
+}
+
+static Shape Big_init(void) {
+    Shape v1 = (Shape){ .tag = 1, .as.Square._0 = 6765 };
+    return v1;
+}
+
+static const char *Name_init(void) {
+    return "wide";
+}
+
+static bool Wide_init(void) {
+    return true;
+}
+
+static float Half_init(void) {
+    return 0.33333334f;
+}
+
+static int32_t Counter_init(void) {
+    return 1;
+}
+
+static int32_t Twice_init(void) {
+    cy_init_Counter("consts.cy:21:22");
+    int32_t v1 = Counter;
+    int32_t v3 = CY_MUL(int32_t, v1, 2);
+    return v3;
+}
+
+static void cy_init_Width(const char *at) {
+    if (cy_begin_init(&cy_state_Width, at)) {
+        Width = Width_init();
+        cy_state_Width = CY_SET;
+    }
+}
+
+static void cy_init_Height(const char *at) {
+    if (cy_begin_init(&cy_state_Height, at)) {
+        Height = Height_init();
+        cy_state_Height = CY_SET;
+    }
+}
+
+static void cy_init_Origin(const char *at) {
+    if (cy_begin_init(&cy_state_Origin, at)) {
+        Origin = Origin_init();
+        cy_state_Origin = CY_SET;
+    }
+}
+
+static void cy_init_Big(const char *at) {
+    if (cy_begin_init(&cy_state_Big, at)) {
+        Big = Big_init();
+        cy_state_Big = CY_SET;
+    }
+}
+
+static void cy_init_Name(const char *at) {
+    if (cy_begin_init(&cy_state_Name, at)) {
+        Name = Name_init();
+        cy_state_Name = CY_SET;
+    }
+}
+
+static void cy_init_Wide(const char *at) {
+    if (cy_begin_init(&cy_state_Wide, at)) {
+        Wide = Wide_init();
+        cy_state_Wide = CY_SET;
+    }
+}
+
+static void cy_init_Half(const char *at) {
+    if (cy_begin_init(&cy_state_Half, at)) {
+        Half = Half_init();
+        cy_state_Half = CY_SET;
+    }
+}
+
+static void cy_init_Counter(const char *at) {
+    if (cy_begin_init(&cy_state_Counter, at)) {
+        Counter = Counter_init();
+        cy_state_Counter = CY_SET;
+    }
+}
+
+static void cy_init_Twice(const char *at) {
+    if (cy_begin_init(&cy_state_Twice, at)) {
+        Twice = Twice_init();
+        cy_state_Twice = CY_SET;
+    }
+}
+
+int main(void) {
+    return (int)main_();
+}
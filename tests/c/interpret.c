@@ -0,0 +1,167 @@
+/* Generated by the Canary compiler from interpret.cy. */
+#include "canary.h"
+
+typedef struct Point Point;
+typedef struct Shape Shape;
+
+struct Point {
+    int32_t x;
+    int32_t y;
+};
+
+struct Shape {
+    int32_t tag;
+    union {
+        struct {
+            int32_t _0;
+        } Circle;
+        struct {
+            int32_t _0;
+            int32_t _1;
+        } Rect;
+    } as;
+};
+
+static uint32_t Calls;
+static unsigned char cy_state_Calls;
+
+static uint32_t fib(uint32_t);
+static uint64_t count(uint64_t, uint64_t);
+static const char *describe(Shape);
+static void shift(Point *, int32_t);
+static uint8_t main_(void);
+static uint32_t Calls_init(void);
+static void cy_init_Calls(const char *at);
+
+static uint32_t fib(uint32_t v0) {
+    uint32_t v14;
+    cy_init_Calls("interpret.cy:15:3");
+    uint32_t v3 = Calls;
+    uint32_t v4 = CY_ADD(uint32_t, v3, 1u);
+    Calls = v4;
+    switch (v0) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    default: goto b3;
+    }
+b1:;
+    v14 = 0u;
+    goto b4;
+b2:;
+    v14 = 1u;
+    goto b4;
+b3:;
+    uint32_t v8 = CY_SUB(uint32_t, v0, 1u);
+    uint32_t v9 = fib(v8);
+    uint32_t v11 = CY_SUB(uint32_t, v0, 2u);
+    uint32_t v12 = fib(v11);
+    uint32_t v13 = CY_ADD(uint32_t, v9, v12);
+    v14 = v13;
+b4:;
+    return v14;
+}
+
+static uint64_t count(uint64_t v0, uint64_t v1) {
+    uint64_t v2;
+b0:;
+    switch (v0) {
+    case UINT64_C(0): goto b1;
+    default: goto b3;
+    }
+b1:;
+    v2 = v1;
+    return v2;
+b3:;
+    uint64_t v4 = CY_SUB(uint64_t, v0, UINT64_C(1));
+    uint64_t v5 = CY_ADD(uint64_t, v1, v0);
+    v0 = v4;
+    v1 = v5;
+    goto b0;
+}
+
+static const char *describe(Shape v0) {
+    const char *v9;
+    uint32_t v1 = (uint32_t)v0.tag;
+    switch (v1) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    case 2u: goto b3;
+    default: goto b5;
+    }
+b1:;
+    int32_t v2 = v0.as.Circle._0;
+    printf("circle of radius %" PRId32 "\n", v2);
+    v9 = "round";
+    goto b4;
+b2:;
+    int32_t v4 = v0.as.Rect._0;
+    int32_t v5 = v0.as.Rect._1;
+    int32_t v6 = CY_MUL(int32_t, v4, v5);
+    printf("rect of area %" PRId32 "\n", v6);
+    v9 = "square-ish";
+    goto b4;
+b3:;
+    v9 = "nothing";
+b4:;
+    return v9;
+b5:;
+    cy_runtime_error("interpret.cy:31:3", "no switch arm matched");
+}
+
+static void shift(Point *v0, int32_t v1) {
+    int32_t *v2 = &v0->x;
+    int32_t v3 = (*v2);
+    int32_t v4 = CY_ADD(int32_t, v3, v1);
+    (*v2) = v4;
+    int32_t *v5 = &v0->y;
+    int32_t v6 = (*v5);
+    int32_t v7 = CY_SUB(int32_t, v6, v1);
+    (*v5) = v7;
+    return;
+}
+
+static uint8_t main_(void) {
+    Point slot;
+    uint32_t v2 = fib(15u);
+    cy_init_Calls("interpret.cy:50:51");
+    uint32_t v4 = Calls;
+    printf("fib(15) = %" PRIu32 " in %" PRIu32 " calls\n", v2, v4);
+    uint64_t v7 = count(UINT64_C(1000000), UINT64_C(0));
+    printf("sum to a million = %" PRIu64 "\n", v7);
+    Shape v9 = (Shape){ .tag = 0, .as.Circle._0 = 2 };
+    const char *v10 = describe(v9);
+    printf("%s\n", v10);
+    int32_t v13 = CY_NEG(int32_t, 4);
+    Shape v14 = (Shape){ .tag = 1, .as.Rect._0 = 3, .as.Rect._1 = v13 };
+    const char *v15 = describe(v14);
+    printf("%s\n", v15);
+    Shape v16 = (Shape){ .tag = 2 };
+    const char *v17 = describe(v16);
+    printf("%s\n", v17);
+    Point v20 = (Point){ .x = 1, .y = 2 };
+    slot = v20;
+    shift(&slot, 10);
+    Point v22 = slot;
+    int32_t v23 = v22.x;
+    Point v24 = slot;
+    int32_t v25 = v24.y;
+    printf("p = (%" PRId32 ", %" PRId32 ")\n", v23, v25);
+    uint8_t v28 = CY_ADD(uint8_t, 250, 10);
+    printf("%" PRIu8 " {braces}\n", v28);
+    return 42;
+}
+
+static uint32_t Calls_init(void) {
+    return 0u;
+}
+
+static void cy_init_Calls(const char *at) {
+    if (cy_begin_init(&cy_state_Calls, at)) {
+        Calls = Calls_init();
+        cy_state_Calls = CY_SET;
+    }
+}
+
+int main(void) {
+    return (int)main_();
+}
@@ -0,0 +1,181 @@
+/* Generated by the Canary compiler from lowering.cy. */
+#include "canary.h"
+
+typedef struct Empty Empty;
+typedef struct Color Color;
+
+struct Empty {
+    char unused;
+};
+
+struct Color {
+    int32_t tag;
+};
+
+static int64_t Log;
+static unsigned char cy_state_Log;
+
+static int64_t note(int64_t);
+static int64_t bump(int64_t *);
+static const char *name(Color);
+static int32_t code(const char *);
+static uint64_t ping_group(int, uint64_t, uint64_t);
+static uint64_t ping(uint64_t);
+static uint64_t pong(uint64_t);
+static int32_t main_(void);
+static int64_t Log_init(void);
+static void cy_init_Log(const char *at);
+
+static int64_t note(int64_t v0) {
+    cy_init_Log("lowering.cy:12:3");
+    cy_init_Log("lowering.cy:12:9");
+    int64_t v3 = Log;
+    int64_t v5 = CY_MUL(int64_t, v3, INT64_C(10));
+    int64_t v6 = CY_ADD(int64_t, v5, v0);
+    Log = v6;
+    return v0;
+}
+
+static int64_t bump(int64_t *v0) {
+    int64_t v2 = (*v0);
+    int64_t v3 = CY_ADD(int64_t, v2, INT64_C(100));
+    (*v0) = v3;
+    return INT64_C(1);
+}
+
+static const char *name(Color v0) {
+    const char *v5;
+    uint32_t v1 = (uint32_t)v0.tag;
+    switch (v1) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    default: goto b3;
+    }
+b1:;
+    v5 = "red";
+    goto b4;
+b2:;
+    v5 = "green";
+    goto b4;
+b3:;
+    v5 = "other";
+b4:;
+    return v5;
+}
+
+static int32_t code(const char *v0) {
+    int32_t v8;
+    bool v2 = (strcmp(v0, "one") == 0);
+    if (v2) goto b1;
+    goto b2;
+b1:;
+    v8 = 1;
+    goto b5;
+b2:;
+    bool v5 = (strcmp(v0, "two") == 0);
+    if (v5) goto b3;
+    goto b4;
+b3:;
+    v8 = 2;
+    goto b5;
+b4:;
+    v8 = 0;
+b5:;
+    return v8;
+}
+
+static uint64_t ping_group(int entry, uint64_t v0, uint64_t v0_1) {
+    uint64_t v2;
+    uint64_t v2_1;
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case UINT64_C(0): goto b1;
+    default: goto b3;
+    }
+b1:;
+    v2 = UINT64_C(0);
+    return v2;
+b3:;
+    uint64_t v4 = CY_SUB(uint64_t, v0, UINT64_C(1));
+    v0_1 = v4;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case UINT64_C(0): goto b1_1;
+    default: goto b3_1;
+    }
+b1_1:;
+    v2_1 = UINT64_C(1);
+    return v2_1;
+b3_1:;
+    uint64_t v4_1 = CY_SUB(uint64_t, v0_1, UINT64_C(1));
+    v0 = v4_1;
+    goto b0;
+}
+
+static uint64_t ping(uint64_t v0) {
+    return ping_group(0, v0, 0);
+}
+
+static uint64_t pong(uint64_t v0_1) {
+    return ping_group(1, 0, v0_1);
+}
+
+static int32_t main_(void) {
+    int64_t slot;
+    slot = INT64_C(1);
+    int64_t v2 = slot;
+    int64_t v3 = bump(&slot);
+    int64_t v4 = CY_ADD(int64_t, v2, v3);
+    int64_t v6 = note(INT64_C(1));
+    int64_t v8 = note(INT64_C(2));
+    int64_t v10 = note(INT64_C(3));
+    int64_t v11 = CY_MUL(int64_t, v8, v10);
+    int64_t v12 = CY_ADD(int64_t, v6, v11);
+    int64_t v13 = slot;
+    cy_init_Log("lowering.cy:55:46");
+    int64_t v15 = Log;
+    printf("%" PRId64 " %" PRId64 " %" PRId64 " %" PRId64 "\n", v4, v13, v12, v15);
+    Color v17 = (Color){ .tag = 1 };
+    const char *v18 = name(v17);
+    Color v19 = (Color){ .tag = 2 };
+    const char *v20 = name(v19);
+    int32_t v22 = code("two");
+    printf("%s %s %" PRId32 "\n", v18, v20, v22);
+    uint64_t v24 = ping(UINT64_C(1000001));
+    printf("%" PRIu64 "\n", v24);
+    float v28 = (float)(1.0f / 3.0f);
+    double v29 = (-7.0);
+    double v31 = (v29 / 2.0);
+    printf("%f %f\n", v28, v31);
+    int64_t v34 = note(INT64_C(4));
+    int64_t v35 = note(v34);
+    printf("%" PRId64 "\n", v35);
+    int32_t v37 = CY_NEG(int32_t, INT32_MIN);
+    int8_t v39 = CY_NEG(int8_t, -128);
+    int32_t v41 = CY_SUB(int32_t, v37, 1);
+    int8_t v43 = CY_NEG(int8_t, 1);
+    int8_t v44 = ((int8_t)cy_div_i64(v39, v43, "lowering.cy:69:36"));
+    int32_t v46 = CY_NEG(int32_t, 1);
+    int32_t v47 = ((int32_t)cy_div_i64(v37, v46, "lowering.cy:69:48"));
+    printf("%" PRId32 " %" PRId8 " %" PRId32 "\n", v41, v44, v47);
+    return 0;
+}
+
+static int64_t Log_init(void) {
+    return INT64_C(0);
+}
+
+static void cy_init_Log(const char *at) {
+    if (cy_begin_init(&cy_state_Log, at)) {
+        Log = Log_init();
+        cy_state_Log = CY_SET;
+    }
+}
+
+int main(void) {
+    return (int)main_();
+}
@@ -0,0 +1,191 @@
+/* Generated by the Canary compiler from references.cy. */
+#include "canary.h"
+
+typedef struct Vec2 Vec2;
+typedef struct Body Body;
+typedef struct Event Event;
+
+struct Vec2 {
+    int64_t x;
+    int64_t y;
+};
+
+struct Body {
+    Vec2 pos;
+    uint16_t mass;
+};
+
+struct Event {
+    int32_t tag;
+    union {
+        struct {
+            Body body;
+            int64_t steps;
+        } Moved;
+        struct {
+            const char *_0;
+        } Renamed;
+    } as;
+};
+
+static int64_t Total;
+static unsigned char cy_state_Total;
+
+static void Vec2_bump(Vec2 *, int64_t);
+static void add_to(int64_t *, int64_t);
+static int32_t inc(int32_t);
+static int64_t countdown(int32_t, int64_t *);
+static int64_t handle(Event);
+static int32_t main_(void);
+static int64_t Total_init(void);
+static void cy_init_Total(const char *at);
+
+static void Vec2_bump(Vec2 *v0, int64_t v1) {
+    int64_t *v2 = &v0->x;
+    int64_t v3 = (*v2);
+    int64_t v4 = CY_ADD(int64_t, v3, v1);
+    (*v2) = v4;
+    int64_t *v5 = &v0->y;
+    int64_t v6 = (*v5);
+    int64_t v7 = CY_SUB(int64_t, v6, v1);
+    (*v5) = v7;
+    return;
+}
+
+static void add_to(int64_t *v0, int64_t v1) {
+    int64_t v2 = (*v0);
+    int64_t v3 = CY_ADD(int64_t, v2, v1);
+    (*v0) = v3;
+    return;
+}
+
+static int32_t inc(int32_t v0) {
+    int32_t v2 = CY_ADD(int32_t, v0, 1);
+    return v2;
+}
+
+static int64_t countdown(int32_t v0, int64_t *v1) {
+    int64_t v6;
+b0:;
+    int64_t v3 = (*v1);
+    int64_t v4 = CY_ADD(int64_t, v3, INT64_C(1));
+    (*v1) = v4;
+    switch (v0) {
+    case 0: goto b1;
+    default: goto b3;
+    }
+b1:;
+    int64_t v5 = (*v1);
+    v6 = v5;
+    return v6;
+b3:;
+    int32_t v8 = CY_SUB(int32_t, v0, 1);
+    v0 = v8;
+    goto b0;
+}
+
+static int64_t handle(Event v0) {
+    int64_t v11;
+    uint32_t v1 = (uint32_t)v0.tag;
+    switch (v1) {
+    case 0u: goto b1;
+    case 1u: goto b2;
+    case 2u: goto b3;
+    default: goto b5;
+    }
+b1:;
+    Body v2 = v0.as.Moved.body;
+    int64_t v3 = v0.as.Moved.steps;
+    Vec2 v4 = v2.pos;
+    int64_t v5 = v4.x;
+    int64_t v6 = CY_ADD(int64_t, v5, v3);
+    v11 = v6;
+    goto b4;
+b2:;
+    const char *v7 = v0.as.Renamed._0;
+    printf("renamed to %s\n", v7);
+    v11 = INT64_C(0);
+    goto b4;
+b3:;
+    int64_t v10 = CY_NEG(int64_t, INT64_C(1));
+    v11 = v10;
+b4:;
+    return v11;
+b5:;
+    cy_runtime_error("references.cy:41:3", "no switch arm matched");
+}
+
+static int32_t main_(void) {
+    int64_t slot;
+    Body slot_1;
+    Vec2 slot_2;
+    int64_t slot_3;
+    slot = INT64_C(5);
+    add_to(&slot, INT64_C(10));
+    cy_init_Total("references.cy:54:15");
+    int64_t v7 = slot;
+    add_to(&Total, v7);
+    cy_init_Total("references.cy:55:15");
+    add_to(&Total, INT64_C(1));
+    Vec2 v12 = (Vec2){ .x = INT64_C(1), .y = INT64_C(2) };
+    Body v14 = (Body){ .pos = v12, .mass = 65535 };
+    slot_1 = v14;
+    Vec2_bump(&slot_1.pos, INT64_C(3));
+    Body v17 = slot_1;
+    Vec2 v21 = (Vec2){ .x = INT64_C(100), .y = INT64_C(200) };
+    slot_1.pos = v21;
+    uint16_t v24 = slot_1.mass;
+    uint16_t v25 = CY_ADD(uint16_t, v24, 2);
+    slot_1.mass = v25;
+    Vec2 v26 = slot_1.pos;
+    int64_t v27 = v26.x;
+    Vec2 v28 = v17.pos;
+    int64_t v29 = v28.x;
+    Body v30 = slot_1;
+    uint16_t v31 = v30.mass;
+    cy_init_Total("references.cy:63:66");
+    int64_t v33 = Total;
+    printf("%" PRId64 " %" PRId64 " %" PRIu16 " %" PRId64 "\n", v27, v29, v31, v33);
+    Vec2 v36 = (Vec2){ .x = INT64_C(2), .y = INT64_C(2) };
+    slot_2 = v36;
+    slot_2.y = INT64_C(50);
+    add_to(&slot_2.x, INT64_C(8));
+    Vec2 v41 = slot_2;
+    Vec2 v42 = slot_2;
+    int64_t v43 = v42.x;
+    Vec2 v45 = (Vec2){ .x = v43, .y = INT64_C(7) };
+    slot_2 = v45;
+    int64_t v46 = v41.y;
+    int64_t v47 = slot_2.y;
+    Vec2 v48 = slot_2;
+    int64_t v49 = v48.x;
+    printf("%" PRId64 " %" PRId64 " %" PRId64 "\n", v46, v47, v49);
+    slot_3 = INT64_C(0);
+    int64_t v54 = countdown(100000, &slot_3);
+    int32_t v56 = inc(40);
+    int32_t v57 = inc(v56);
+    printf("%" PRId64 " %" PRId32 " %" PRId64 "\n", v54, v57, INT64_C(0));
+    Event v59 = (Event){ .tag = 0, .as.Moved.body = v17, .as.Moved.steps = INT64_C(250) };
+    int64_t v60 = handle(v59);
+    Event v62 = (Event){ .tag = 1, .as.Renamed._0 = "bob" };
+    int64_t v63 = handle(v62);
+    Event v64 = (Event){ .tag = 2 };
+    int64_t v65 = handle(v64);
+    printf("%" PRId64 " %" PRId64 " %" PRId64 "\n", v60, v63, v65);
+    return 3;
+}
+
+static int64_t Total_init(void) {
+    return INT64_C(0);
+}
+
+static void cy_init_Total(const char *at) {
+    if (cy_begin_init(&cy_state_Total, at)) {
+        Total = Total_init();
+        cy_state_Total = CY_SET;
+    }
+}
+
+int main(void) {
+    return (int)main_();
+}
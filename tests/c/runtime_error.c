@@ -0,0 +1,19 @@
+/* Generated by the Canary compiler from runtime_error.cy. */
+#include "canary.h"
+
+static int32_t divide(int32_t, int32_t);
+static int32_t main_(void);
+
+static int32_t divide(int32_t v0, int32_t v1) {
+    int32_t v2 = ((int32_t)cy_div_i64(v0, v1, "runtime_error.cy:1:46"));
+    return v2;
+}
+
+static int32_t main_(void) {
+    printf("about to divide\n");
+    return divide(1, 0);
+}
+
+int main(void) {
+    return (int)main_();
+}
@@ -0,0 +1,140 @@
+/* Generated by the Canary compiler from tail.cy. */
+#include "canary.h"
+
+typedef struct Pair Pair;
+
+struct Pair {
+    uint32_t a;
+    uint32_t b;
+};
+
+static uint32_t fib(uint32_t, Pair);
+static bool is_even_group(int, uint32_t, uint32_t);
+static bool is_even(uint32_t);
+static bool is_odd(uint32_t);
+static uint32_t ping_group(int, uint32_t, uint32_t, uint32_t, uint32_t);
+static uint32_t ping(uint32_t, uint32_t);
+static uint32_t pong(uint32_t, uint32_t);
+static void main_(void);
+
+static uint32_t fib(uint32_t v0, Pair v1) {
+    uint32_t v3;
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b3;
+    }
+b1:;
+    uint32_t v2 = v1.a;
+    v3 = v2;
+    return v3;
+b3:;
+    uint32_t v5 = CY_SUB(uint32_t, v0, 1u);
+    uint32_t v6 = v1.b;
+    uint32_t v7 = v1.a;
+    uint32_t v8 = v1.b;
+    uint32_t v9 = CY_ADD(uint32_t, v7, v8);
+    Pair v10 = (Pair){ .a = v6, .b = v9 };
+    v0 = v5;
+    v1 = v10;
+    goto b0;
+}
+
+static bool is_even_group(int entry, uint32_t v0, uint32_t v0_1) {
+    bool v2;
+    bool v2_1;
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b3;
+    }
+b1:;
+    v2 = true;
+    return v2;
+b3:;
+    uint32_t v4 = CY_SUB(uint32_t, v0, 1u);
+    v0_1 = v4;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case 0u: goto b1_1;
+    default: goto b3_1;
+    }
+b1_1:;
+    v2_1 = false;
+    return v2_1;
+b3_1:;
+    uint32_t v4_1 = CY_SUB(uint32_t, v0_1, 1u);
+    v0 = v4_1;
+    goto b0;
+}
+
+static bool is_even(uint32_t v0) {
+    return is_even_group(0, v0, 0);
+}
+
+static bool is_odd(uint32_t v0_1) {
+    return is_even_group(1, 0, v0_1);
+}
+
+static uint32_t ping_group(int entry, uint32_t v0, uint32_t v1, uint32_t v0_1, uint32_t v1_1) {
+    uint32_t v2;
+    uint32_t v2_1;
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b3;
+    }
+b1:;
+    v2 = v1;
+    return v2;
+b3:;
+    uint32_t v4 = CY_SUB(uint32_t, v0, 1u);
+    uint32_t v6 = CY_ADD(uint32_t, v1, 1u);
+    v0_1 = v4;
+    v1_1 = v6;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case 0u: goto b1_1;
+    default: goto b3_1;
+    }
+b1_1:;
+    v2_1 = v1_1;
+    return v2_1;
+b3_1:;
+    uint32_t v4_1 = CY_SUB(uint32_t, v0_1, 1u);
+    v0 = v4_1;
+    v1 = v1_1;
+    goto b0;
+}
+
+static uint32_t ping(uint32_t v0, uint32_t v1) {
+    return ping_group(0, v0, v1, 0, 0);
+}
+
+static uint32_t pong(uint32_t v0_1, uint32_t v1_1) {
+    return ping_group(1, 0, 0, v0_1, v1_1);
+}
+
+static void main_(void) {
+    Pair v3 = (Pair){ .a = 0u, .b = 1u };
+    uint32_t v4 = fib(40u, v3);
+    printf("%" PRIu32 "\n", v4);
+    is_even(10u);
+    is_even(1000001u);
+    uint32_t v11 = ping(1000001u, 0u);
+    printf("%" PRIu32 "\n", v11);
+    return;
+}
+
+int main(void) {
+    main_();
+    return 0;
+}
@@ -0,0 +1,38 @@
+/* Generated by the Canary compiler from fold.cy. */
+#include "canary.h"
+
+typedef struct Point Point;
+typedef struct Mode Mode;
+
+struct Point {
+    int32_t x;
+    int32_t y;
+};
+
+struct Mode {
+    int32_t tag;
+    union {
+        struct {
+            int32_t _0;
+        } Slow;
+    } as;
+};
+
+static int32_t exported(int32_t);
+static int32_t main_(void);
+
+static int32_t exported(int32_t v0) {
+    return v0;
+}
+
+static int32_t main_(void) {
+    printf("%" PRId32 " %" PRId32 " %" PRIu8 " %" PRId32 "\n", 10, 20, 4, 6);
+    printf("%s %s\n", "two", "many");
+    printf("%" PRId32 "\n", 40);
+    printf("%" PRId32 " %" PRId32 "\n", 100, 25);
+    return 0;
+}
+
+int main(void) {
+    return (int)main_();
+}
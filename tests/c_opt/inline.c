@@ -0,0 +1,127 @@
+/* Generated by the Canary compiler from inline.cy. */
+#include "canary.h"
+
+typedef struct Point Point;
+
+struct Point {
+    int32_t x;
+    int32_t y;
+};
+
+static void report(const char *, int32_t);
+static int32_t fib(int32_t);
+static bool is_even_group(int, uint32_t, uint32_t);
+static bool is_even(uint32_t);
+static bool is_odd(uint32_t);
+static void main_(void);
+
+static void report(const char *v0, int32_t v1) {
+    printf("%s:\n", v0);
+    printf("  n = %" PRId32 "\n", v1);
+    int32_t v3 = CY_MUL(int32_t, v1, 2);
+    printf("  n * 2 = %" PRId32 "\n", v3);
+    int32_t v4 = CY_MUL(int32_t, v1, v1);
+    printf("  n * n = %" PRId32 "\n", v4);
+    int32_t v5 = CY_NEG(int32_t, v1);
+    printf("  -n = %" PRId32 "\n", v5);
+    int32_t v7 = CY_ADD(int32_t, v1, 1);
+    printf("  n + 1 = %" PRId32 "\n", v7);
+    int32_t v9 = CY_SUB(int32_t, v1, 1);
+    printf("  n - 1 = %" PRId32 "\n", v9);
+    int32_t v11 = ((int32_t)cy_div_i64(v1, 2, "inline.cy:23:29"));
+    printf("  n / 2 = %" PRId32 "\n", v11);
+    return;
+}
+
+static int32_t fib(int32_t v0) {
+    int32_t v10;
+    switch (v0) {
+    case 0: goto b1;
+    case 1: goto b2;
+    default: goto b3;
+    }
+b1:;
+    v10 = 0;
+    goto b4;
+b2:;
+    v10 = 1;
+    goto b4;
+b3:;
+    int32_t v4 = CY_SUB(int32_t, v0, 1);
+    int32_t v5 = fib(v4);
+    int32_t v7 = CY_SUB(int32_t, v0, 2);
+    int32_t v8 = fib(v7);
+    int32_t v9 = CY_ADD(int32_t, v5, v8);
+    v10 = v9;
+b4:;
+    return v10;
+}
+
+static bool is_even_group(int entry, uint32_t v0, uint32_t v0_1) {
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b2;
+    }
+b1:;
+    return true;
+b2:;
+    uint32_t v3 = CY_SUB(uint32_t, v0, 1u);
+    v0_1 = v3;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case 0u: goto b1_1;
+    default: goto b2_1;
+    }
+b1_1:;
+    return false;
+b2_1:;
+    uint32_t v3_1 = CY_SUB(uint32_t, v0_1, 1u);
+    v0 = v3_1;
+    goto b0;
+}
+
+static bool is_even(uint32_t v0) {
+    return is_even_group(0, v0, 0);
+}
+
+static bool is_odd(uint32_t v0_1) {
+    return is_even_group(1, 0, v0_1);
+}
+
+static void main_(void) {
+    int32_t v5;
+    uint32_t v8;
+    uint32_t v9;
+    printf("%" PRId32 " %" PRId32 "\n", 25, 625);
+    report("five", 5);
+    v5 = fib(10);
+    v8 = 100000u;
+    v9 = 0u;
+b1:;
+    switch (v8) {
+    case 0u: goto b2;
+    default: goto b3;
+    }
+b2:;
+    printf("%" PRId32 " %" PRIu32 "\n", v5, v9);
+    is_even(10u);
+    is_odd(7u);
+    printf("%" PRId32 "\n", 13);
+    return;
+b3:;
+    uint32_t v16 = CY_SUB(uint32_t, v8, 1u);
+    uint32_t v17 = CY_ADD(uint32_t, v9, v8);
+    v8 = v16;
+    v9 = v17;
+    goto b1;
+}
+
+int main(void) {
+    main_();
+    return 0;
+}
@@ -0,0 +1,128 @@
+/* Generated by the Canary compiler from tail.cy. */
+#include "canary.h"
+
+typedef struct Pair Pair;
+
+struct Pair {
+    uint32_t a;
+    uint32_t b;
+};
+
+static bool is_even_group(int, uint32_t, uint32_t);
+static bool is_even(uint32_t);
+static bool is_odd(uint32_t);
+static uint32_t ping_group(int, uint32_t, uint32_t, uint32_t, uint32_t);
+static uint32_t ping(uint32_t, uint32_t);
+static uint32_t pong(uint32_t, uint32_t);
+static void main_(void);
+
+static bool is_even_group(int entry, uint32_t v0, uint32_t v0_1) {
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b2;
+    }
+b1:;
+    return true;
+b2:;
+    uint32_t v3 = CY_SUB(uint32_t, v0, 1u);
+    v0_1 = v3;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case 0u: goto b1_1;
+    default: goto b2_1;
+    }
+b1_1:;
+    return false;
+b2_1:;
+    uint32_t v3_1 = CY_SUB(uint32_t, v0_1, 1u);
+    v0 = v3_1;
+    goto b0;
+}
+
+static bool is_even(uint32_t v0) {
+    return is_even_group(0, v0, 0);
+}
+
+static bool is_odd(uint32_t v0_1) {
+    return is_even_group(1, 0, v0_1);
+}
+
+static uint32_t ping_group(int entry, uint32_t v0, uint32_t v1, uint32_t v0_1, uint32_t v1_1) {
+    switch (entry) {
+    case 1: goto b0_1;
+    }
+b0:;
+    switch (v0) {
+    case 0u: goto b1;
+    default: goto b2;
+    }
+b1:;
+    return v1;
+b2:;
+    uint32_t v3 = CY_SUB(uint32_t, v0, 1u);
+    uint32_t v5 = CY_ADD(uint32_t, v1, 1u);
+    v0_1 = v3;
+    v1_1 = v5;
+    goto b0_1;
+b0_1:;
+    switch (v0_1) {
+    case 0u: goto b1_1;
+    default: goto b2_1;
+    }
+b1_1:;
+    return v1_1;
+b2_1:;
+    uint32_t v3_1 = CY_SUB(uint32_t, v0_1, 1u);
+    v0 = v3_1;
+    v1 = v1_1;
+    goto b0;
+}
+
+static uint32_t ping(uint32_t v0, uint32_t v1) {
+    return ping_group(0, v0, v1, 0, 0);
+}
+
+static uint32_t pong(uint32_t v0_1, uint32_t v1_1) {
+    return ping_group(1, 0, 0, v0_1, v1_1);
+}
+
+static void main_(void) {
+    uint32_t v4;
+    Pair v5;
+    Pair v3 = (Pair){ .a = 0u, .b = 1u };
+    v4 = 40u;
+    v5 = v3;
+b1:;
+    switch (v4) {
+    case 0u: goto b2;
+    default: goto b3;
+    }
+b2:;
+    uint32_t v6 = v5.a;
+    printf("%" PRIu32 "\n", v6);
+    is_even(10u);
+    is_even(1000001u);
+    uint32_t v13 = ping(1000001u, 0u);
+    printf("%" PRIu32 "\n", v13);
+    return;
+b3:;
+    uint32_t v15 = CY_SUB(uint32_t, v4, 1u);
+    uint32_t v16 = v5.b;
+    uint32_t v17 = v5.a;
+    uint32_t v18 = v5.b;
+    uint32_t v19 = CY_ADD(uint32_t, v17, v18);
+    Pair v20 = (Pair){ .a = v16, .b = v19 };
+    v4 = v15;
+    v5 = v20;
+    goto b1;
+}
+
+int main(void) {
+    main_();
+    return 0;
+}
@@ -16,7 +16,7 @@ macro_rules! info {
 #[macro_export]
 macro_rules! warning {
     ($( $arg:expr ),+) => {
-        use crate::*;
+        use $crate::*;
         print!("[{}WARNING{}] ", utils::WARNING, utils::RESET);
         println!($( $arg ),+);
     };
@@ -25,7 +25,7 @@ macro_rules! warning {
 #[macro_export]
 macro_rules! error {
     ($( $arg:expr ),+) => {
-        use crate::*;
+        use $crate::*;
         print!("[{}ERROR{}] ", utils::ERROR, utils::RESET);
         println!($( $arg ),+);
     };
@@ -34,7 +34,7 @@ macro_rules! error {
 #[macro_export]
 macro_rules! context {
     ($( $arg:expr ),+) => {
-        use crate::*;
+        use $crate::*;
         print!("[{}CONTEXT{}] ", utils::INFO, utils::RESET);
         println!($( $arg ),+);
     };
@@ -43,7 +43,7 @@ macro_rules! context {
 #[macro_export]
 macro_rules! debug {
     ($( $arg:expr ),+) => {
-        use crate::*;
+        use $crate::*;
         print!("[{}DEBUG{}] ", utils::INFO, utils::RESET);
         println!($( $arg ),+);
     };
@@ -0,0 +1,219 @@
+//! The instruction set and the module it lives in.
+
+use lexer::token::Span;
+
+/// The numeric type an arithmetic or ordering instruction works on.
+/// Integers are 64-bit on the stack and truncated to their type after each
+/// operation; `F32` also stands for `f16`, as in the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Num {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl Num {
+    pub const ALL: [Num; 10] = [
+        Num::I8,
+        Num::I16,
+        Num::I32,
+        Num::I64,
+        Num::U8,
+        Num::U16,
+        Num::U32,
+        Num::U64,
+        Num::F32,
+        Num::F64,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Num::I8 => "i8",
+            Num::I16 => "i16",
+            Num::I32 => "i32",
+            Num::I64 => "i64",
+            Num::U8 => "u8",
+            Num::U16 => "u16",
+            Num::U32 => "u32",
+            Num::U64 => "u64",
+            Num::F32 => "f32",
+            Num::F64 => "f64",
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Num::F32 | Num::F64)
+    }
+}
+
+/// How `write` prints the value on top of the stack; see
+/// [`sema::format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Str,
+    Int,
+    /// The bits of an integer, as unsigned.
+    UInt,
+    Float,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Str, Format::Int, Format::UInt, Format::Float];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Str => "str",
+            Format::Int => "int",
+            Format::UInt => "uint",
+            Format::Float => "float",
+        }
+    }
+}
+
+/// One instruction. Every instruction pops its operands off the stack and
+/// pushes its result, if any. Locals are numbered slots of the current
+/// frame, the first ones holding the arguments. Jump targets are
+/// instruction indices in the same function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes a constant from the pool.
+    Const(u32),
+    True,
+    False,
+    /// Pushes the value of `void` expressions.
+    Void,
+    /// Pushes a function value.
+    Function(u32),
+
+    Load(u16),
+    Store(u16),
+    /// Stores into a new cell in the slot. Locals that are referenced or
+    /// assigned through a field live in cells.
+    Box(u16),
+    LoadBox(u16),
+    StoreBox(u16),
+    /// Pushes a reference to the cell in the slot.
+    RefLocal(u16),
+    /// Globals are initialized on first use; these run the initializer
+    /// first if needed.
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    RefGlobal(u32),
+
+    /// Pops that many fields into a struct.
+    Struct(u16),
+    /// Pops the payload of a variant: its index and field count.
+    Variant(u16, u16),
+    /// Replaces a struct or variant with one of its fields.
+    Field(u16),
+    /// Replaces a variant with its index.
+    Tag,
+    /// Replaces a reference to a struct with a reference to a field.
+    RefField(u16),
+    /// Moves a value into a new cell and pushes a reference to it.
+    Temp,
+    Deref,
+    /// Pops a value, then the reference to store it through.
+    StoreRef,
+
+    Add(Num),
+    Sub(Num),
+    Mul(Num),
+    Div(Num),
+    Neg(Num),
+    Not,
+    Eq,
+    Ne,
+    Lt(Num),
+    Le(Num),
+    Gt(Num),
+    Ge(Num),
+
+    Pop,
+    Dup,
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    /// Calls a function with that many arguments on the stack.
+    Call(u32, u8),
+    /// Replaces the current frame with a call.
+    TailCall(u32, u8),
+    /// Calls the function value below the arguments.
+    CallValue(u8),
+    TailCallValue(u8),
+    Return,
+    /// Reached when no `switch` arm matches.
+    Unreachable,
+
+    /// Prints the value on top of the stack.
+    Write(Format),
+}
+
+impl Op {
+    /// Whether this instruction can fail at run time, and so keeps its
+    /// span.
+    pub fn can_fail(self) -> bool {
+        matches!(
+            self,
+            Op::Div(_)
+                | Op::LoadGlobal(_)
+                | Op::StoreGlobal(_)
+                | Op::RefGlobal(_)
+                | Op::Call(..)
+                | Op::CallValue(_)
+                | Op::Unreachable
+                | Op::Write(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// The source name; methods are qualified as `Type.method`.
+    pub name: String,
+    pub params: u16,
+    /// Slots in a frame, parameters included.
+    pub locals: u16,
+    pub code: Vec<Op>,
+    /// The source of each instruction that can fail, by index, in order.
+    pub spans: Vec<(u32, Span)>,
+}
+
+impl Function {
+    pub fn span(&self, pc: usize) -> Option<Span> {
+        let i = self.spans.binary_search_by_key(&(pc as u32), |(at, _)| *at);
+        i.ok().map(|i| self.spans[i].1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    /// The function computing its value.
+    pub init: u32,
+}
+
+/// A compiled program, as stored in a `.cyb` file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    /// The file the program was compiled from, for diagnostics.
+    pub source: String,
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub main: Option<u32>,
+}
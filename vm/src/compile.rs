@@ -0,0 +1,530 @@
+//! Compiles the IR to bytecode.
+//!
+//! Every SSA value gets a local slot and instructions read their operands
+//! from there, except that constants, functions and globals are pushed
+//! where they are used. A slot of the IR is a local holding a cell. A jump
+//! pushes the arguments of its target and stores them in its parameters,
+//! last first, so they are all assigned at once.
+
+use std::collections::HashMap;
+
+use ir::cfg::reverse_postorder;
+use ir::{BlockId, Callee, Const, Function as IrFunction, InstKind, Target, Terminator, Type};
+use lexer::token::Span;
+use sema::format::{self, Piece, Spec};
+use sema::types::{FloatTy, IntTy};
+
+use crate::bytecode::*;
+
+/// Compiles `module`, which must be well formed.
+pub fn compile(module: &ir::Module) -> Module {
+    let mut out = Module {
+        source: module.source.clone(),
+        ..Module::default()
+    };
+    let mut constants = HashMap::new();
+    for function in &module.functions {
+        let compiled = FnCompiler::new(&mut constants, &mut out, function).compile();
+        out.functions.push(compiled);
+    }
+    out.globals = module
+        .globals
+        .iter()
+        .map(|global| Global {
+            name: global.name.clone(),
+            init: global.init.0,
+        })
+        .collect();
+    out.main = module.main.map(|id| id.0);
+    out
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Int(i64),
+    Float(u64),
+    Str(String),
+}
+
+/// How an operand is pushed.
+#[derive(Clone, Copy)]
+enum Operand {
+    /// From its local.
+    Local(u16),
+    /// A constant or function value, pushed by this instruction.
+    Op(Op),
+    /// A pointer to the cell in a local.
+    Slot(u16),
+    /// A pointer to a global.
+    Global(u32, Span),
+}
+
+struct FnCompiler<'c> {
+    constants: &'c mut HashMap<ConstKey, u32>,
+    module: &'c mut Module,
+    func: &'c IrFunction,
+    code: Vec<Op>,
+    spans: Vec<(u32, Span)>,
+    operands: HashMap<ir::Value, Operand>,
+    /// How many times instructions and terminators read each value.
+    uses: HashMap<ir::Value, usize>,
+    /// The index of the last jump target, which peephole optimizations
+    /// must not cross.
+    target: usize,
+    locals: u16,
+    /// Where each block starts.
+    starts: HashMap<BlockId, u32>,
+    /// Jumps to blocks, patched once every block is placed.
+    jumps: Vec<(usize, BlockId)>,
+}
+
+impl<'c> FnCompiler<'c> {
+    fn new(
+        constants: &'c mut HashMap<ConstKey, u32>,
+        module: &'c mut Module,
+        func: &'c IrFunction,
+    ) -> Self {
+        let mut uses = HashMap::new();
+        for block in &func.blocks {
+            let operands = block.insts.iter().flat_map(|inst| inst.kind.operands());
+            for value in operands.chain(block.term.operands()) {
+                *uses.entry(value).or_insert(0) += 1;
+            }
+            // A switch reads its value once per case.
+            if let Terminator::Switch { value, cases, .. } = &block.term {
+                *uses.get_mut(value).unwrap() += cases.len().saturating_sub(1);
+            }
+        }
+        Self {
+            constants,
+            module,
+            func,
+            code: Vec::new(),
+            spans: Vec::new(),
+            operands: HashMap::new(),
+            uses,
+            target: 0,
+            locals: 0,
+            starts: HashMap::new(),
+            jumps: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> Function {
+        let func = self.func;
+        // The arguments are the first locals.
+        for &param in &func.blocks[0].params {
+            let local = self.new_local();
+            self.operands.insert(param, Operand::Local(local));
+        }
+        let order = reverse_postorder(func);
+        for (i, &block) in order.iter().enumerate() {
+            let start = self.here();
+            self.starts.insert(block, start);
+            for &param in &func.block(block).params {
+                if !self.operands.contains_key(&param) {
+                    let local = self.new_local();
+                    self.operands.insert(param, Operand::Local(local));
+                }
+            }
+            for inst in &func.block(block).insts {
+                self.inst(inst);
+            }
+            self.terminator(&func.block(block).term, order.get(i + 1).copied());
+        }
+        for (at, block) in std::mem::take(&mut self.jumps) {
+            let target = self.starts[&block];
+            match &mut self.code[at] {
+                Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+                op => unreachable!("cannot patch {op:?}"),
+            }
+        }
+        Function {
+            name: func.name.clone(),
+            params: func.blocks[0].params.len() as u16,
+            locals: self.locals,
+            code: self.code,
+            spans: self.spans,
+        }
+    }
+
+    fn new_local(&mut self) -> u16 {
+        self.locals += 1;
+        self.locals - 1
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// Emits an instruction, which reports runtime errors at `span`.
+    fn emit_at(&mut self, op: Op, span: Span) {
+        if op.can_fail() {
+            self.spans.push((self.code.len() as u32, span));
+        }
+        self.code.push(op);
+    }
+
+    /// The index the next instruction will have, as a jump target.
+    fn here(&mut self) -> u32 {
+        self.target = self.code.len();
+        self.code.len() as u32
+    }
+
+    fn used(&self, value: ir::Value) -> bool {
+        self.uses.contains_key(&value)
+    }
+
+    fn jump(&mut self, op: Op, block: BlockId) {
+        let at = self.emit(op);
+        self.jumps.push((at, block));
+    }
+
+    fn constant(&mut self, constant: Constant) -> Op {
+        let key = match &constant {
+            Constant::Int(v) => ConstKey::Int(*v),
+            Constant::Float(v) => ConstKey::Float(v.to_bits()),
+            Constant::Str(s) => ConstKey::Str(s.clone()),
+        };
+        let constants = &mut self.module.constants;
+        Op::Const(*self.constants.entry(key).or_insert_with(|| {
+            constants.push(constant);
+            constants.len() as u32 - 1
+        }))
+    }
+
+    /// Pushes `value`.
+    fn push(&mut self, value: ir::Value) {
+        match self.operands[&value] {
+            // A value read once, right after it is computed, stays on the
+            // stack.
+            Operand::Local(local)
+                if self.uses[&value] == 1
+                    && self.code.len() > self.target
+                    && self.code.last() == Some(&Op::Store(local)) =>
+            {
+                self.code.pop();
+            }
+            Operand::Local(local) => self.code.push(Op::Load(local)),
+            Operand::Op(op) => self.code.push(op),
+            Operand::Slot(local) => self.code.push(Op::RefLocal(local)),
+            Operand::Global(index, span) => self.emit_at(Op::RefGlobal(index), span),
+        }
+    }
+
+    /// Stores the value on the stack as `result`, or drops it if nothing
+    /// reads it.
+    fn define(&mut self, result: Option<ir::Value>) {
+        match result {
+            Some(result) if self.used(result) => {
+                let local = self.new_local();
+                self.operands.insert(result, Operand::Local(local));
+                self.emit(Op::Store(local));
+            }
+            _ => {
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    fn inst(&mut self, inst: &ir::Inst) {
+        let result = inst.result;
+        // What an unused pure instruction computes is never needed.
+        if inst.kind.is_pure() && result.is_none_or(|r| !self.used(r)) {
+            return;
+        }
+        let operand = match &inst.kind {
+            InstKind::Const(Const::Int(v)) => Some(Operand::Op(self.constant(Constant::Int(*v)))),
+            InstKind::Const(Const::Float(v)) => {
+                Some(Operand::Op(self.constant(Constant::Float(*v))))
+            }
+            InstKind::Const(Const::Str(s)) => {
+                Some(Operand::Op(self.constant(Constant::Str(s.clone()))))
+            }
+            InstKind::Const(Const::Bool(true)) => Some(Operand::Op(Op::True)),
+            InstKind::Const(Const::Bool(false)) => Some(Operand::Op(Op::False)),
+            InstKind::Func(id) => Some(Operand::Op(Op::Function(id.0))),
+            InstKind::Slot => {
+                let local = self.new_local();
+                self.emit(Op::Void);
+                self.emit(Op::Box(local));
+                Some(Operand::Slot(local))
+            }
+            InstKind::Global { id, span } => {
+                // Reading it is what initializes it.
+                if result.is_none_or(|r| !self.used(r)) {
+                    self.emit_at(Op::RefGlobal(id.0), *span);
+                    self.emit(Op::Pop);
+                }
+                Some(Operand::Global(id.0, *span))
+            }
+            _ => None,
+        };
+        if let Some(operand) = operand {
+            if let Some(result) = result {
+                self.operands.insert(result, operand);
+            }
+            return;
+        }
+
+        match &inst.kind {
+            InstKind::Unary { op, arg } => {
+                self.push(*arg);
+                match op {
+                    ir::UnOp::Neg => self.emit(Op::Neg(num(self.func.ty(*arg)))),
+                    ir::UnOp::Not => self.emit(Op::Not),
+                };
+            }
+            InstKind::Binary { op, lhs, rhs, span } => {
+                self.push(*lhs);
+                self.push(*rhs);
+                let num = || num(self.func.ty(*lhs));
+                let op = match op {
+                    ir::BinOp::Add => Op::Add(num()),
+                    ir::BinOp::Sub => Op::Sub(num()),
+                    ir::BinOp::Mul => Op::Mul(num()),
+                    ir::BinOp::Div => Op::Div(num()),
+                    ir::BinOp::Eq => Op::Eq,
+                    ir::BinOp::Ne => Op::Ne,
+                    ir::BinOp::Lt => Op::Lt(num()),
+                    ir::BinOp::Le => Op::Le(num()),
+                    ir::BinOp::Gt => Op::Gt(num()),
+                    ir::BinOp::Ge => Op::Ge(num()),
+                };
+                self.emit_at(op, *span);
+            }
+            InstKind::Call { callee, args, span } => {
+                let argc = args.len() as u8;
+                match callee {
+                    Callee::Direct(id) => {
+                        self.push_all(args);
+                        self.emit_at(Op::Call(id.0, argc), *span);
+                    }
+                    Callee::Indirect(value) => {
+                        self.push(*value);
+                        self.push_all(args);
+                        self.emit_at(Op::CallValue(argc), *span);
+                    }
+                }
+            }
+            InstKind::Printf { format, args } => {
+                self.printf(format, args);
+                return;
+            }
+            InstKind::Struct(fields) => {
+                self.push_all(fields);
+                self.emit(Op::Struct(fields.len() as u16));
+            }
+            InstKind::Variant { index, fields } => {
+                self.push_all(fields);
+                self.emit(Op::Variant(*index as u16, fields.len() as u16));
+            }
+            InstKind::Field { base, index } => {
+                self.push(*base);
+                self.emit(Op::Field(*index as u16));
+            }
+            InstKind::Tag(base) => {
+                self.push(*base);
+                self.emit(Op::Tag);
+            }
+            InstKind::Payload { base, field, .. } => {
+                self.push(*base);
+                self.emit(Op::Field(*field as u16));
+            }
+            InstKind::Load(ptr) => match self.operands[ptr] {
+                Operand::Slot(local) => {
+                    self.emit(Op::LoadBox(local));
+                }
+                Operand::Global(index, span) => self.emit_at(Op::LoadGlobal(index), span),
+                _ => {
+                    self.push(*ptr);
+                    self.emit(Op::Deref);
+                }
+            },
+            InstKind::Store { ptr, value } => {
+                match self.operands[ptr] {
+                    Operand::Slot(local) => {
+                        self.push(*value);
+                        self.emit(Op::StoreBox(local));
+                    }
+                    Operand::Global(index, span) => {
+                        self.push(*value);
+                        self.emit_at(Op::StoreGlobal(index), span);
+                    }
+                    _ => {
+                        self.push(*ptr);
+                        self.push(*value);
+                        self.emit(Op::StoreRef);
+                    }
+                }
+                return;
+            }
+            InstKind::FieldPtr { ptr, index } => {
+                self.push(*ptr);
+                self.emit(Op::RefField(*index as u16));
+            }
+            InstKind::Const(_) | InstKind::Func(_) | InstKind::Slot | InstKind::Global { .. } => {
+                unreachable!()
+            }
+        }
+        self.define(result);
+    }
+
+    fn push_all(&mut self, values: &[ir::Value]) {
+        for value in values {
+            self.push(*value);
+        }
+    }
+
+    fn terminator(&mut self, term: &Terminator, next: Option<BlockId>) {
+        match term {
+            Terminator::Jump(target) => self.goto(target, next),
+            Terminator::Branch {
+                cond,
+                then,
+                otherwise,
+            } => {
+                self.push(*cond);
+                if then.args.is_empty() {
+                    self.jump(Op::JumpIfTrue(0), then.block);
+                    self.goto(otherwise, next);
+                } else if otherwise.args.is_empty() {
+                    self.jump(Op::JumpIfFalse(0), otherwise.block);
+                    self.goto(then, next);
+                } else {
+                    let test = self.emit(Op::JumpIfFalse(0));
+                    self.goto(then, None);
+                    self.code[test] = Op::JumpIfFalse(self.here());
+                    self.goto(otherwise, next);
+                }
+            }
+            Terminator::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                for (i, (n, target)) in cases.iter().enumerate() {
+                    self.push(*value);
+                    let constant = self.constant(Constant::Int(*n));
+                    self.emit(constant);
+                    self.emit(Op::Eq);
+                    // The last case falls through to the block after it.
+                    if i == cases.len() - 1
+                        && next == Some(target.block)
+                        && target.args.is_empty()
+                        && default.args.is_empty()
+                    {
+                        self.jump(Op::JumpIfFalse(0), default.block);
+                        return;
+                    }
+                    if target.args.is_empty() {
+                        self.jump(Op::JumpIfTrue(0), target.block);
+                    } else {
+                        let test = self.emit(Op::JumpIfFalse(0));
+                        self.goto(target, None);
+                        self.code[test] = Op::JumpIfFalse(self.here());
+                    }
+                }
+                self.goto(default, next);
+            }
+            Terminator::Return(value) => {
+                match value {
+                    Some(value) => self.push(*value),
+                    None => {
+                        self.emit(Op::Void);
+                    }
+                }
+                self.emit(Op::Return);
+            }
+            Terminator::TailCall { callee, args } => {
+                let argc = args.len() as u8;
+                match callee {
+                    Callee::Direct(id) => {
+                        self.push_all(args);
+                        self.emit(Op::TailCall(id.0, argc));
+                    }
+                    Callee::Indirect(value) => {
+                        self.push(*value);
+                        self.push_all(args);
+                        self.emit(Op::TailCallValue(argc));
+                    }
+                }
+            }
+            // The only trap is a `switch` without a matching arm, which is
+            // what `unreachable` reports.
+            Terminator::Trap { span, .. } => self.emit_at(Op::Unreachable, *span),
+            Terminator::Unreachable => {
+                self.emit(Op::Unreachable);
+            }
+        }
+    }
+
+    /// Passes the arguments of `target` and jumps there, unless it is
+    /// `next`, the block placed after this one.
+    fn goto(&mut self, target: &Target, next: Option<BlockId>) {
+        let params = &self.func.block(target.block).params;
+        self.push_all(&target.args);
+        for param in params.iter().rev() {
+            match self.operands.get(param) {
+                Some(Operand::Local(local)) => {
+                    self.emit(Op::Store(*local));
+                }
+                _ => {
+                    // Placed after this block: allocate its local now.
+                    let local = self.new_local();
+                    self.operands.insert(*param, Operand::Local(local));
+                    self.emit(Op::Store(local));
+                }
+            }
+        }
+        if next != Some(target.block) {
+            self.jump(Op::Jump(0), target.block);
+        }
+    }
+
+    /// Writes each piece of the format string in order.
+    fn printf(&mut self, format: &str, args: &[ir::Value]) {
+        let pieces = format::parse(format).expect("format string was checked");
+        let mut args = args.iter();
+        for located in pieces {
+            let spec = match located.piece {
+                Piece::Literal(text) => {
+                    let constant = self.constant(Constant::Str(text));
+                    self.emit(constant);
+                    self.emit(Op::Write(Format::Str));
+                    continue;
+                }
+                Piece::Arg(spec) => spec,
+            };
+            let arg = *args.next().expect("format arguments were checked");
+            self.push(arg);
+            let format = match (spec, self.func.ty(arg)) {
+                (Spec::Str, _) => Format::Str,
+                (Spec::Int, Type::Int(int)) if !int.is_signed() => Format::UInt,
+                (Spec::Int, _) => Format::Int,
+                (Spec::Float, _) => Format::Float,
+            };
+            self.emit(Op::Write(format));
+        }
+    }
+}
+
+/// The numeric type of an arithmetic or ordering instruction on `ty`.
+fn num(ty: &Type) -> Num {
+    match ty {
+        Type::Int(int) => match int {
+            IntTy::I8 => Num::I8,
+            IntTy::I16 => Num::I16,
+            IntTy::I32 => Num::I32,
+            IntTy::I64 => Num::I64,
+            IntTy::U8 => Num::U8,
+            IntTy::U16 => Num::U16,
+            IntTy::U32 => Num::U32,
+            IntTy::U64 => Num::U64,
+        },
+        Type::Float(FloatTy::F64) => Num::F64,
+        Type::Float(_) => Num::F32,
+        other => unreachable!("arithmetic on {other:?}"),
+    }
+}
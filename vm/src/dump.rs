@@ -0,0 +1,118 @@
+//! A readable listing of a module.
+
+use std::fmt::Write;
+
+use crate::bytecode::*;
+
+pub fn dump(module: &Module) -> String {
+    let mut out = String::new();
+    writeln!(out, "; source: {}", module.source).unwrap();
+
+    if !module.constants.is_empty() {
+        out.push_str("\nconstants:\n");
+        for (i, constant) in module.constants.iter().enumerate() {
+            writeln!(out, "  {i}: {}", show_constant(constant)).unwrap();
+        }
+    }
+
+    if !module.globals.is_empty() {
+        out.push_str("\nglobals:\n");
+        for (i, global) in module.globals.iter().enumerate() {
+            writeln!(out, "  {i}: {} = call {}", global.name, global.init).unwrap();
+        }
+    }
+
+    for (i, function) in module.functions.iter().enumerate() {
+        writeln!(
+            out,
+            "\nfunction {i} {} (params {}, locals {}):",
+            function.name, function.params, function.locals
+        )
+        .unwrap();
+        for (pc, op) in function.code.iter().enumerate() {
+            let text = show_op(*op);
+            match comment(module, *op) {
+                Some(comment) => writeln!(out, "  {pc:4}  {text:<20} ; {comment}").unwrap(),
+                None => writeln!(out, "  {pc:4}  {text}").unwrap(),
+            }
+        }
+    }
+
+    if let Some(main) = module.main {
+        writeln!(out, "\nmain: {main}").unwrap();
+    }
+    out
+}
+
+fn show_constant(constant: &Constant) -> String {
+    match constant {
+        Constant::Int(v) => format!("int {v}"),
+        Constant::Float(v) => format!("float {v:?}"),
+        Constant::Str(s) => format!("str {s:?}"),
+    }
+}
+
+fn show_op(op: Op) -> String {
+    match op {
+        Op::Const(i) => format!("const {i}"),
+        Op::True => "true".to_string(),
+        Op::False => "false".to_string(),
+        Op::Void => "void".to_string(),
+        Op::Function(f) => format!("function {f}"),
+        Op::Load(slot) => format!("load {slot}"),
+        Op::Store(slot) => format!("store {slot}"),
+        Op::Box(slot) => format!("box {slot}"),
+        Op::LoadBox(slot) => format!("load.box {slot}"),
+        Op::StoreBox(slot) => format!("store.box {slot}"),
+        Op::RefLocal(slot) => format!("ref.local {slot}"),
+        Op::LoadGlobal(g) => format!("load.global {g}"),
+        Op::StoreGlobal(g) => format!("store.global {g}"),
+        Op::RefGlobal(g) => format!("ref.global {g}"),
+        Op::Struct(count) => format!("struct {count}"),
+        Op::Variant(index, count) => format!("variant {index} {count}"),
+        Op::Field(index) => format!("field {index}"),
+        Op::Tag => "tag".to_string(),
+        Op::RefField(index) => format!("ref.field {index}"),
+        Op::Temp => "temp".to_string(),
+        Op::Deref => "deref".to_string(),
+        Op::StoreRef => "store.ref".to_string(),
+        Op::Add(num) => format!("add.{}", num.as_str()),
+        Op::Sub(num) => format!("sub.{}", num.as_str()),
+        Op::Mul(num) => format!("mul.{}", num.as_str()),
+        Op::Div(num) => format!("div.{}", num.as_str()),
+        Op::Neg(num) => format!("neg.{}", num.as_str()),
+        Op::Not => "not".to_string(),
+        Op::Eq => "eq".to_string(),
+        Op::Ne => "ne".to_string(),
+        Op::Lt(num) => format!("lt.{}", num.as_str()),
+        Op::Le(num) => format!("le.{}", num.as_str()),
+        Op::Gt(num) => format!("gt.{}", num.as_str()),
+        Op::Ge(num) => format!("ge.{}", num.as_str()),
+        Op::Pop => "pop".to_string(),
+        Op::Dup => "dup".to_string(),
+        Op::Jump(target) => format!("jump {target}"),
+        Op::JumpIfFalse(target) => format!("jump.false {target}"),
+        Op::JumpIfTrue(target) => format!("jump.true {target}"),
+        Op::Call(f, argc) => format!("call {f} {argc}"),
+        Op::TailCall(f, argc) => format!("tail.call {f} {argc}"),
+        Op::CallValue(argc) => format!("call.value {argc}"),
+        Op::TailCallValue(argc) => format!("tail.call.value {argc}"),
+        Op::Return => "return".to_string(),
+        Op::Unreachable => "unreachable".to_string(),
+        Op::Write(format) => format!("write.{}", format.as_str()),
+    }
+}
+
+/// What an index operand refers to.
+fn comment(module: &Module, op: Op) -> Option<String> {
+    match op {
+        Op::Const(i) => Some(show_constant(&module.constants[i as usize])),
+        Op::Function(f) | Op::Call(f, _) | Op::TailCall(f, _) => {
+            Some(module.functions[f as usize].name.clone())
+        }
+        Op::LoadGlobal(g) | Op::StoreGlobal(g) | Op::RefGlobal(g) => {
+            Some(module.globals[g as usize].name.clone())
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,466 @@
+//! The `.cyb` file format.
+//!
+//! All integers are little-endian; strings are a `u32` byte length and
+//! UTF-8. A file is:
+//!
+//! - the magic bytes `CYB\0` and the format [`VERSION`] as a `u16`,
+//! - the source file name,
+//! - the constants: a `u32` count, then each as a tag byte (0 for `i64`,
+//!   1 for `f64` bits, 2 for a string) and its value,
+//! - the functions: a `u32` count, then each as its name, `u16` parameter
+//!   and local counts, a `u32` instruction count and the instructions, a
+//!   `u32` span count and the spans,
+//! - the globals: a `u32` count, then each as its name and the `u32` index
+//!   of its initializer,
+//! - the index of `main` as a `u32`, or `u32::MAX` if there is none.
+//!
+//! An instruction is an opcode byte followed by its operands at their
+//! natural width. A span is its instruction index and the six `u32` fields
+//! of [`Span`]. Loading checks every index, so the machine can trust a
+//! module it was given.
+
+use lexer::token::Span;
+
+use crate::bytecode::*;
+
+pub const MAGIC: [u8; 4] = *b"CYB\0";
+
+/// Bumped whenever the encoding or the meaning of an instruction changes.
+/// Files of any other version are rejected.
+pub const VERSION: u16 = 1;
+
+/// Serializes `module` to the bytes of a `.cyb` file.
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut out = Writer(Vec::new());
+    out.0.extend(MAGIC);
+    out.u16(VERSION);
+    out.str(&module.source);
+
+    out.u32(module.constants.len() as u32);
+    for constant in &module.constants {
+        match constant {
+            Constant::Int(v) => {
+                out.u8(0);
+                out.0.extend(v.to_le_bytes());
+            }
+            Constant::Float(v) => {
+                out.u8(1);
+                out.0.extend(v.to_bits().to_le_bytes());
+            }
+            Constant::Str(s) => {
+                out.u8(2);
+                out.str(s);
+            }
+        }
+    }
+
+    out.u32(module.functions.len() as u32);
+    for function in &module.functions {
+        out.str(&function.name);
+        out.u16(function.params);
+        out.u16(function.locals);
+        out.u32(function.code.len() as u32);
+        for op in &function.code {
+            out.op(*op);
+        }
+        out.u32(function.spans.len() as u32);
+        for (at, span) in &function.spans {
+            out.u32(*at);
+            for field in [span.file_id, span.start, span.end, span.line, span.col] {
+                out.u32(field as u32);
+            }
+            out.u32(span.expansion);
+        }
+    }
+
+    out.u32(module.globals.len() as u32);
+    for global in &module.globals {
+        out.str(&global.name);
+        out.u32(global.init);
+    }
+    out.u32(module.main.unwrap_or(u32::MAX));
+    out.0
+}
+
+/// Loads a `.cyb` file, checking that it is well-formed.
+pub fn decode(bytes: &[u8]) -> Result<Module, String> {
+    let mut input = Reader { bytes, at: 0 };
+    if input.take(4)? != MAGIC {
+        return Err("not a Canary bytecode file".to_string());
+    }
+    let version = input.u16()?;
+    if version != VERSION {
+        return Err(format!(
+            "unsupported bytecode version {version}, expected {VERSION}"
+        ));
+    }
+    let source = input.str()?;
+
+    let mut constants = Vec::new();
+    for _ in 0..input.u32()? {
+        constants.push(match input.u8()? {
+            0 => Constant::Int(i64::from_le_bytes(input.array()?)),
+            1 => Constant::Float(f64::from_bits(u64::from_le_bytes(input.array()?))),
+            2 => Constant::Str(input.str()?),
+            tag => return Err(format!("unknown constant tag {tag}")),
+        });
+    }
+
+    let mut functions = Vec::new();
+    for _ in 0..input.u32()? {
+        let name = input.str()?;
+        let params = input.u16()?;
+        let locals = input.u16()?;
+        let mut code = Vec::new();
+        for _ in 0..input.u32()? {
+            code.push(input.op()?);
+        }
+        let mut spans = Vec::new();
+        for _ in 0..input.u32()? {
+            let at = input.u32()?;
+            let mut fields = [0; 5];
+            for field in &mut fields {
+                *field = input.u32()? as usize;
+            }
+            let [file_id, start, end, line, col] = fields;
+            let expansion = input.u32()?;
+            let span = Span {
+                file_id,
+                start,
+                end,
+                line,
+                col,
+                expansion,
+            };
+            spans.push((at, span));
+        }
+        functions.push(Function {
+            name,
+            params,
+            locals,
+            code,
+            spans,
+        });
+    }
+
+    let mut globals = Vec::new();
+    for _ in 0..input.u32()? {
+        let name = input.str()?;
+        let init = input.u32()?;
+        globals.push(Global { name, init });
+    }
+    let main = match input.u32()? {
+        u32::MAX => None,
+        main => Some(main),
+    };
+    if input.at != bytes.len() {
+        return Err(format!("{} bytes after the module", bytes.len() - input.at));
+    }
+
+    let module = Module {
+        source,
+        constants,
+        functions,
+        globals,
+        main,
+    };
+    verify(&module)?;
+    Ok(module)
+}
+
+/// Checks that every index in `module` refers to something.
+fn verify(module: &Module) -> Result<(), String> {
+    let functions = module.functions.len();
+    let in_range = |index: u32, len: usize, what: &str| match (index as usize) < len {
+        true => Ok(()),
+        false => Err(format!("{what} {index} out of range")),
+    };
+    if let Some(main) = module.main {
+        in_range(main, functions, "main function")?;
+    }
+    for global in &module.globals {
+        in_range(global.init, functions, "initializer of global")?;
+    }
+
+    for function in &module.functions {
+        let context = |e: String| format!("in `{}`: {e}", function.name);
+        if function.params > function.locals {
+            return Err(context("more parameters than locals".to_string()));
+        }
+        let locals = function.locals as usize;
+        let code = function.code.len();
+        for op in &function.code {
+            let checked = match *op {
+                Op::Const(index) => in_range(index, module.constants.len(), "constant"),
+                Op::Function(index) | Op::Call(index, _) | Op::TailCall(index, _) => {
+                    in_range(index, functions, "function")
+                }
+                Op::Load(slot)
+                | Op::Store(slot)
+                | Op::Box(slot)
+                | Op::LoadBox(slot)
+                | Op::StoreBox(slot)
+                | Op::RefLocal(slot) => in_range(slot.into(), locals, "local"),
+                Op::LoadGlobal(index) | Op::StoreGlobal(index) | Op::RefGlobal(index) => {
+                    in_range(index, module.globals.len(), "global")
+                }
+                Op::Jump(target) | Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                    in_range(target, code, "jump target")
+                }
+                _ => Ok(()),
+            };
+            checked.map_err(context)?;
+        }
+        // Control must not run off the end.
+        match function.code.last() {
+            Some(Op::Return | Op::Jump(_) | Op::TailCall(..) | Op::TailCallValue(_))
+            | Some(Op::Unreachable) => {}
+            _ => return Err(context("code does not end in a return".to_string())),
+        }
+        if !function.spans.is_sorted_by_key(|(at, _)| *at)
+            || function.spans.iter().any(|(at, _)| *at as usize >= code)
+        {
+            return Err(context("spans out of order".to_string()));
+        }
+    }
+    Ok(())
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend(value.as_bytes());
+    }
+
+    fn num(&mut self, num: Num) {
+        let index = Num::ALL.iter().position(|n| *n == num).unwrap();
+        self.u8(index as u8);
+    }
+
+    fn op(&mut self, op: Op) {
+        self.u8(opcode(op));
+        match op {
+            Op::Const(index)
+            | Op::Function(index)
+            | Op::LoadGlobal(index)
+            | Op::StoreGlobal(index)
+            | Op::RefGlobal(index)
+            | Op::Jump(index)
+            | Op::JumpIfFalse(index)
+            | Op::JumpIfTrue(index) => self.u32(index),
+            Op::Load(slot)
+            | Op::Store(slot)
+            | Op::Box(slot)
+            | Op::LoadBox(slot)
+            | Op::StoreBox(slot)
+            | Op::RefLocal(slot)
+            | Op::Struct(slot)
+            | Op::Field(slot)
+            | Op::RefField(slot) => self.u16(slot),
+            Op::Variant(index, count) => {
+                self.u16(index);
+                self.u16(count);
+            }
+            Op::Add(num)
+            | Op::Sub(num)
+            | Op::Mul(num)
+            | Op::Div(num)
+            | Op::Neg(num)
+            | Op::Lt(num)
+            | Op::Le(num)
+            | Op::Gt(num)
+            | Op::Ge(num) => self.num(num),
+            Op::Call(func, argc) | Op::TailCall(func, argc) => {
+                self.u32(func);
+                self.u8(argc);
+            }
+            Op::CallValue(argc) | Op::TailCallValue(argc) => self.u8(argc),
+            Op::Write(format) => {
+                let index = Format::ALL.iter().position(|f| *f == format).unwrap();
+                self.u8(index as u8);
+            }
+            Op::True
+            | Op::False
+            | Op::Void
+            | Op::Tag
+            | Op::Temp
+            | Op::Deref
+            | Op::StoreRef
+            | Op::Not
+            | Op::Eq
+            | Op::Ne
+            | Op::Pop
+            | Op::Dup
+            | Op::Return
+            | Op::Unreachable => {}
+        }
+    }
+}
+
+/// The opcode byte of each instruction.
+fn opcode(op: Op) -> u8 {
+    match op {
+        Op::Const(_) => 0x00,
+        Op::True => 0x01,
+        Op::False => 0x02,
+        Op::Void => 0x03,
+        Op::Function(_) => 0x04,
+        Op::Load(_) => 0x10,
+        Op::Store(_) => 0x11,
+        Op::Box(_) => 0x12,
+        Op::LoadBox(_) => 0x13,
+        Op::StoreBox(_) => 0x14,
+        Op::RefLocal(_) => 0x15,
+        Op::LoadGlobal(_) => 0x16,
+        Op::StoreGlobal(_) => 0x17,
+        Op::RefGlobal(_) => 0x18,
+        Op::Struct(_) => 0x20,
+        Op::Variant(..) => 0x21,
+        Op::Field(_) => 0x22,
+        Op::Tag => 0x23,
+        Op::RefField(_) => 0x24,
+        Op::Temp => 0x25,
+        Op::Deref => 0x26,
+        Op::StoreRef => 0x27,
+        Op::Add(_) => 0x30,
+        Op::Sub(_) => 0x31,
+        Op::Mul(_) => 0x32,
+        Op::Div(_) => 0x33,
+        Op::Neg(_) => 0x34,
+        Op::Not => 0x35,
+        Op::Eq => 0x36,
+        Op::Ne => 0x37,
+        Op::Lt(_) => 0x38,
+        Op::Le(_) => 0x39,
+        Op::Gt(_) => 0x3a,
+        Op::Ge(_) => 0x3b,
+        Op::Pop => 0x40,
+        Op::Dup => 0x41,
+        Op::Jump(_) => 0x42,
+        Op::JumpIfFalse(_) => 0x43,
+        Op::JumpIfTrue(_) => 0x44,
+        Op::Call(..) => 0x45,
+        Op::TailCall(..) => 0x46,
+        Op::CallValue(_) => 0x47,
+        Op::TailCallValue(_) => 0x48,
+        Op::Return => 0x49,
+        Op::Unreachable => 0x4a,
+        Op::Write(_) => 0x50,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.at..self.at + len)
+            .ok_or("unexpected end of file")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "string is not UTF-8".to_string())
+    }
+
+    fn num(&mut self) -> Result<Num, String> {
+        let index = self.u8()?;
+        Num::ALL
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("unknown numeric type {index}"))
+    }
+
+    fn op(&mut self) -> Result<Op, String> {
+        Ok(match self.u8()? {
+            0x00 => Op::Const(self.u32()?),
+            0x01 => Op::True,
+            0x02 => Op::False,
+            0x03 => Op::Void,
+            0x04 => Op::Function(self.u32()?),
+            0x10 => Op::Load(self.u16()?),
+            0x11 => Op::Store(self.u16()?),
+            0x12 => Op::Box(self.u16()?),
+            0x13 => Op::LoadBox(self.u16()?),
+            0x14 => Op::StoreBox(self.u16()?),
+            0x15 => Op::RefLocal(self.u16()?),
+            0x16 => Op::LoadGlobal(self.u32()?),
+            0x17 => Op::StoreGlobal(self.u32()?),
+            0x18 => Op::RefGlobal(self.u32()?),
+            0x20 => Op::Struct(self.u16()?),
+            0x21 => Op::Variant(self.u16()?, self.u16()?),
+            0x22 => Op::Field(self.u16()?),
+            0x23 => Op::Tag,
+            0x24 => Op::RefField(self.u16()?),
+            0x25 => Op::Temp,
+            0x26 => Op::Deref,
+            0x27 => Op::StoreRef,
+            0x30 => Op::Add(self.num()?),
+            0x31 => Op::Sub(self.num()?),
+            0x32 => Op::Mul(self.num()?),
+            0x33 => Op::Div(self.num()?),
+            0x34 => Op::Neg(self.num()?),
+            0x35 => Op::Not,
+            0x36 => Op::Eq,
+            0x37 => Op::Ne,
+            0x38 => Op::Lt(self.num()?),
+            0x39 => Op::Le(self.num()?),
+            0x3a => Op::Gt(self.num()?),
+            0x3b => Op::Ge(self.num()?),
+            0x40 => Op::Pop,
+            0x41 => Op::Dup,
+            0x42 => Op::Jump(self.u32()?),
+            0x43 => Op::JumpIfFalse(self.u32()?),
+            0x44 => Op::JumpIfTrue(self.u32()?),
+            0x45 => Op::Call(self.u32()?, self.u8()?),
+            0x46 => Op::TailCall(self.u32()?, self.u8()?),
+            0x47 => Op::CallValue(self.u8()?),
+            0x48 => Op::TailCallValue(self.u8()?),
+            0x49 => Op::Return,
+            0x4a => Op::Unreachable,
+            0x50 => {
+                let index = self.u8()?;
+                let format = Format::ALL.get(index as usize).copied();
+                Op::Write(format.ok_or_else(|| format!("unknown format {index}"))?)
+            }
+            opcode => return Err(format!("unknown opcode {opcode:#04x}")),
+        })
+    }
+}
@@ -0,0 +1,25 @@
+//! Canary's own bytecode and the virtual machine that runs it.
+//!
+//! [`compile`] lowers the IR to a [`Module`] of stack-machine
+//! functions, which [`encode`] writes as a `.cyb` file and [`decode`] loads
+//! back; the format is described in [`file`]. The file starts with a magic
+//! number and a version, and loading rejects any other version, so a stale
+//! file fails cleanly instead of misbehaving.
+//!
+//! [`run`] executes a module with the same semantics as the interpreter:
+//! lazily initialized globals, wrapping integer arithmetic, the same
+//! runtime errors with the same spans, and tail calls that replace the
+//! caller's frame so recursion in tail position runs in constant space.
+
+mod bytecode;
+mod compile;
+mod dump;
+pub mod file;
+mod machine;
+mod value;
+
+pub use bytecode::{Constant, Format, Function, Global, Module, Num, Op};
+pub use compile::compile;
+pub use dump::dump;
+pub use file::{decode, encode};
+pub use machine::run;
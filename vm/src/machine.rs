@@ -0,0 +1,519 @@
+//! The virtual machine.
+//!
+//! One value stack holds every frame: the locals of a call start where its
+//! arguments were pushed, and its operands go on top. Frames live on the
+//! heap, so deep recursion costs no Rust stack; tail calls replace the
+//! current frame, so recursion in tail position runs in constant space.
+//!
+//! [`crate::file::decode`] verifies what can be checked without running
+//! the code, such as indices. Operands of the wrong type and pops below a
+//! frame's own operands can only come from a malformed file, and are
+//! reported as runtime errors rather than trusted.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use diagnostics::{Diagnostic, codes};
+use ir::BinOp;
+
+use crate::bytecode::*;
+use crate::value::{Place, Value, wrap_num};
+
+/// Non-tail calls deeper than this are reported, as in the interpreter.
+const MAX_DEPTH: usize = 10_000;
+
+/// Runs the `main` function of `module`, writing its output to `out`, and
+/// returns its exit status: the value `main` returns, or 0 for `void`.
+pub fn run(module: &Module, out: &mut dyn Write) -> Result<i32, Diagnostic> {
+    let Some(main) = module.main else {
+        return Err(Diagnostic::error("no `main` function")
+            .with_code(codes::NO_MAIN)
+            .with_help("add `const main : fn() -> void = { ... };`"));
+    };
+
+    let constants = module
+        .constants
+        .iter()
+        .map(|constant| match constant {
+            Constant::Int(v) => Value::Int(*v),
+            Constant::Float(v) => Value::Float(*v),
+            Constant::Str(s) => Value::Str(Rc::from(s.as_str())),
+        })
+        .collect();
+    let mut machine = Machine {
+        module,
+        constants,
+        out,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        globals: vec![Global::Uninitialized; module.globals.len()],
+    };
+    machine.enter(main, 0, 0, None)?;
+    let status = match machine.execute()? {
+        Value::Int(status) => status as i32,
+        _ => 0,
+    };
+    machine
+        .out
+        .flush()
+        .map_err(|e| runtime_error(format!("cannot write output: {e}")))?;
+    Ok(status)
+}
+
+fn runtime_error(message: String) -> Diagnostic {
+    Diagnostic::error(format!("runtime error: {message}")).with_code(codes::RUNTIME_ERROR)
+}
+
+#[derive(Clone)]
+enum Global {
+    Uninitialized,
+    InProgress,
+    Done(Place),
+}
+
+struct Frame {
+    func: u32,
+    /// The next instruction.
+    pc: usize,
+    /// Where the locals start.
+    base: usize,
+    /// What the stack is cut back to on return: `base`, or below the
+    /// callee for calls of function values.
+    bottom: usize,
+    /// Where the operands start, above the locals.
+    top: usize,
+    /// The global this frame initializes, which gets the returned value.
+    global: Option<u32>,
+}
+
+struct Machine<'a> {
+    module: &'a Module,
+    constants: Vec<Value>,
+    out: &'a mut dyn Write,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: Vec<Global>,
+}
+
+impl<'a> Machine<'a> {
+    /// A runtime error at the instruction being executed.
+    fn error(&self, message: impl Into<String>) -> Diagnostic {
+        let message = message.into();
+        let diag = runtime_error(message.clone());
+        let frame = self.frames.last().expect("no frame");
+        let function = &self.module.functions[frame.func as usize];
+        match function.span(frame.pc - 1) {
+            Some(span) => diag.with_primary(span, message),
+            None => diag,
+        }
+    }
+
+    /// An error for code that no compiled program contains.
+    fn invalid(&self, message: impl Into<String>) -> Diagnostic {
+        self.error(format!("invalid bytecode: {}", message.into()))
+    }
+
+    /// Starts a call with the top `argc` values as arguments.
+    fn enter(
+        &mut self,
+        func: u32,
+        argc: usize,
+        bottom: usize,
+        global: Option<u32>,
+    ) -> Result<(), Diagnostic> {
+        if global.is_none() && self.frames.len() == MAX_DEPTH {
+            return Err(self
+                .error("stack overflow")
+                .with_note(format!("more than {MAX_DEPTH} nested calls"))
+                .with_help("make the recursive call a tail call"));
+        }
+        let locals = self.module.functions[func as usize].locals as usize;
+        let base = self.stack.len() - argc;
+        let top = base + locals.max(argc);
+        self.stack.resize(top, Value::Void);
+        self.frames.push(Frame {
+            func,
+            pc: 0,
+            base,
+            bottom,
+            top,
+            global,
+        });
+        Ok(())
+    }
+
+    /// Checks that the current frame has `count` operands.
+    fn operands(&self, count: usize) -> Result<(), Diagnostic> {
+        let frame = self.frames.last().expect("no frame");
+        if self.stack.len() - frame.top < count {
+            return Err(self.invalid("stack underflow"));
+        }
+        Ok(())
+    }
+
+    /// Checks that `func` can be called with the top `argc` values.
+    fn arguments(&self, func: u32, argc: usize) -> Result<(), Diagnostic> {
+        self.operands(argc)?;
+        let function = &self.module.functions[func as usize];
+        if function.params as usize != argc {
+            return Err(self.invalid(format!(
+                "`{}` takes {} arguments, not {argc}",
+                function.name, function.params
+            )));
+        }
+        Ok(())
+    }
+
+    /// Replaces the current call with a call with the top `argc` values as
+    /// arguments.
+    fn tail_call(&mut self, func: u32, argc: usize) {
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let frame = self.frames.last_mut().expect("no frame");
+        self.stack.truncate(frame.bottom);
+        let base = self.stack.len();
+        self.stack.extend(args);
+        let locals = self.module.functions[func as usize].locals as usize;
+        let top = base + locals.max(argc);
+        self.stack.resize(top, Value::Void);
+        *frame = Frame {
+            func,
+            pc: 0,
+            base,
+            bottom: base,
+            top,
+            global: frame.global,
+        };
+    }
+
+    /// The storage of a global, or `None` after starting its initializer,
+    /// which runs the current instruction again once it returns.
+    fn global(&mut self, index: u32) -> Result<Option<Place>, Diagnostic> {
+        match &self.globals[index as usize] {
+            Global::Done(place) => Ok(Some(place.clone())),
+            Global::InProgress => Err(self.error("global read during its own initialization")),
+            Global::Uninitialized => {
+                self.globals[index as usize] = Global::InProgress;
+                self.frames.last_mut().expect("no frame").pc -= 1;
+                let init = self.module.globals[index as usize].init;
+                self.enter(init, 0, self.stack.len(), Some(index))?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, Diagnostic> {
+        self.operands(1)?;
+        Ok(self.stack.pop().expect("stack underflow"))
+    }
+
+    /// Pops the top `count` values, the deepest first.
+    fn pop_n(&mut self, count: usize) -> Result<Vec<Value>, Diagnostic> {
+        self.operands(count)?;
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn jump(&mut self, target: u32) {
+        self.frames.last_mut().expect("no frame").pc = target as usize;
+    }
+
+    fn cell(&self, slot: usize) -> Result<&Rc<RefCell<Value>>, Diagnostic> {
+        match &self.stack[slot] {
+            Value::Cell(cell) => Ok(cell),
+            _ => Err(self.invalid("local is not boxed")),
+        }
+    }
+
+    fn get(&self, place: &Place) -> Result<Value, Diagnostic> {
+        place.get().ok_or_else(|| self.invalid("no such field"))
+    }
+
+    fn set(&self, place: &Place, value: Value) -> Result<(), Diagnostic> {
+        place
+            .set(value)
+            .ok_or_else(|| self.invalid("no such field"))
+    }
+
+    /// Runs until the outermost call returns, and returns its value.
+    fn execute(&mut self) -> Result<Value, Diagnostic> {
+        let module = self.module;
+        loop {
+            let frame = self.frames.last_mut().expect("no frame");
+            let op = module.functions[frame.func as usize].code[frame.pc];
+            frame.pc += 1;
+            let base = frame.base;
+
+            match op {
+                Op::Const(index) => self.push(self.constants[index as usize].clone()),
+                Op::True => self.push(Value::Bool(true)),
+                Op::False => self.push(Value::Bool(false)),
+                Op::Void => self.push(Value::Void),
+                Op::Function(func) => self.push(Value::Function(func)),
+
+                Op::Load(slot) => self.push(self.stack[base + slot as usize].clone()),
+                Op::Store(slot) => {
+                    let value = self.pop()?;
+                    self.stack[base + slot as usize] = value;
+                }
+                Op::Box(slot) => {
+                    let value = self.pop()?;
+                    self.stack[base + slot as usize] = Value::Cell(Rc::new(RefCell::new(value)));
+                }
+                Op::LoadBox(slot) => {
+                    let value = self.cell(base + slot as usize)?.borrow().clone();
+                    self.push(value);
+                }
+                Op::StoreBox(slot) => {
+                    let value = self.pop()?;
+                    self.cell(base + slot as usize)?.replace(value);
+                }
+                Op::RefLocal(slot) => {
+                    let cell = self.cell(base + slot as usize)?.clone();
+                    self.push(Value::Ref(Place::of(cell)));
+                }
+                Op::LoadGlobal(index) => {
+                    if let Some(place) = self.global(index)? {
+                        let value = self.get(&place)?;
+                        self.push(value);
+                    }
+                }
+                Op::StoreGlobal(index) => {
+                    if let Some(place) = self.global(index)? {
+                        let value = self.pop()?;
+                        self.set(&place, value)?;
+                    }
+                }
+                Op::RefGlobal(index) => {
+                    if let Some(place) = self.global(index)? {
+                        self.push(Value::Ref(place));
+                    }
+                }
+
+                Op::Struct(count) => {
+                    let fields = self.pop_n(count as usize)?;
+                    self.push(Value::Struct(fields));
+                }
+                Op::Variant(index, count) => {
+                    let fields = self.pop_n(count as usize)?;
+                    self.push(Value::Variant(index, fields));
+                }
+                Op::Field(index) => {
+                    let field = match self.pop()? {
+                        Value::Struct(fields) | Value::Variant(_, fields) => {
+                            fields.into_iter().nth(index as usize)
+                        }
+                        _ => None,
+                    };
+                    let Some(field) = field else {
+                        return Err(self.invalid("no such field"));
+                    };
+                    self.push(field);
+                }
+                Op::Tag => match self.pop()? {
+                    Value::Variant(index, _) => self.push(Value::Int(index as i64)),
+                    _ => return Err(self.invalid("tag of a non-variant")),
+                },
+                Op::RefField(index) => match self.pop()? {
+                    Value::Ref(place) => self.push(Value::Ref(place.field(index))),
+                    _ => return Err(self.invalid("field reference through a non-reference")),
+                },
+                Op::Temp => {
+                    let value = self.pop()?;
+                    self.push(Value::Ref(Place::new(value)));
+                }
+                Op::Deref => match self.pop()? {
+                    Value::Ref(place) => {
+                        let value = self.get(&place)?;
+                        self.push(value);
+                    }
+                    _ => return Err(self.invalid("dereference of a non-reference")),
+                },
+                Op::StoreRef => {
+                    let value = self.pop()?;
+                    match self.pop()? {
+                        Value::Ref(place) => self.set(&place, value)?,
+                        _ => return Err(self.invalid("store through a non-reference")),
+                    }
+                }
+
+                Op::Add(num) | Op::Sub(num) | Op::Mul(num) | Op::Div(num) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let value = self.arith(op, num, a, b)?;
+                    self.push(value);
+                }
+                Op::Neg(num) => {
+                    let value = match self.pop()? {
+                        Value::Int(v) => Value::Int(wrap_num(v.wrapping_neg(), num)),
+                        Value::Float(v) => Value::Float(-v),
+                        _ => return Err(self.invalid("negation of a non-number")),
+                    };
+                    self.push(value);
+                }
+                Op::Not => {
+                    let value = !self.pop_bool()?;
+                    self.push(Value::Bool(value));
+                }
+                Op::Eq | Op::Ne | Op::Lt(_) | Op::Le(_) | Op::Gt(_) | Op::Ge(_) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let Some(result) = compare(op, &a, &b) else {
+                        return Err(self.invalid("comparison of different types"));
+                    };
+                    self.push(Value::Bool(result));
+                }
+
+                Op::Pop => {
+                    self.pop()?;
+                }
+                Op::Dup => {
+                    self.operands(1)?;
+                    let value = self.stack.last().expect("stack underflow").clone();
+                    self.push(value);
+                }
+                Op::Jump(target) => self.jump(target),
+                Op::JumpIfFalse(target) => {
+                    if !self.pop_bool()? {
+                        self.jump(target);
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.pop_bool()? {
+                        self.jump(target);
+                    }
+                }
+                Op::Call(func, argc) => {
+                    self.arguments(func, argc as usize)?;
+                    let bottom = self.stack.len() - argc as usize;
+                    self.enter(func, argc as usize, bottom, None)?;
+                }
+                Op::TailCall(func, argc) => {
+                    self.arguments(func, argc as usize)?;
+                    self.tail_call(func, argc as usize);
+                }
+                Op::CallValue(argc) => {
+                    let func = self.callee(argc as usize)?;
+                    let bottom = self.stack.len() - argc as usize - 1;
+                    self.enter(func, argc as usize, bottom, None)?;
+                }
+                Op::TailCallValue(argc) => {
+                    let func = self.callee(argc as usize)?;
+                    self.stack.remove(self.stack.len() - argc as usize - 1);
+                    self.tail_call(func, argc as usize);
+                }
+                Op::Return => {
+                    let value = self.pop()?;
+                    let frame = self.frames.pop().expect("no frame");
+                    self.stack.truncate(frame.bottom);
+                    if let Some(global) = frame.global {
+                        self.globals[global as usize] = Global::Done(Place::new(value));
+                    } else if self.frames.is_empty() {
+                        return Ok(value);
+                    } else {
+                        self.push(value);
+                    }
+                }
+                Op::Unreachable => return Err(self.error("no switch arm matched")),
+
+                Op::Write(format) => {
+                    let text = match (format, self.pop()?) {
+                        (Format::Str, Value::Str(s)) => s.to_string(),
+                        (Format::Int, Value::Int(v)) => v.to_string(),
+                        (Format::UInt, Value::Int(v)) => (v as u64).to_string(),
+                        (Format::Float, Value::Float(v)) => format!("{v:.6}"),
+                        (format, _) => {
+                            return Err(
+                                self.invalid(format!("cannot write that as {}", format.as_str()))
+                            );
+                        }
+                    };
+                    if let Err(e) = self.out.write_all(text.as_bytes()) {
+                        return Err(self.error(format!("cannot write output: {e}")));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The function value below the top `argc` values, checking that it
+    /// takes that many arguments.
+    fn callee(&self, argc: usize) -> Result<u32, Diagnostic> {
+        self.operands(argc + 1)?;
+        let Value::Function(func) = self.stack[self.stack.len() - argc - 1] else {
+            return Err(self.invalid("call of a non-function"));
+        };
+        self.arguments(func, argc)?;
+        Ok(func)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, Diagnostic> {
+        match self.pop()? {
+            Value::Bool(v) => Ok(v),
+            _ => Err(self.invalid("expected a bool")),
+        }
+    }
+
+    fn arith(&self, op: Op, num: Num, a: Value, b: Value) -> Result<Value, Diagnostic> {
+        match (a, b) {
+            (Value::Int(x), Value::Int(y)) => {
+                let unsigned = matches!(num, Num::U8 | Num::U16 | Num::U32 | Num::U64);
+                let value = match op {
+                    Op::Add(_) => x.wrapping_add(y),
+                    Op::Sub(_) => x.wrapping_sub(y),
+                    Op::Mul(_) => x.wrapping_mul(y),
+                    Op::Div(_) if y == 0 => return Err(self.error("division by zero")),
+                    Op::Div(_) if unsigned => ((x as u64) / (y as u64)) as i64,
+                    Op::Div(_) => x.wrapping_div(y),
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Value::Int(wrap_num(value, num)))
+            }
+            (Value::Float(x), Value::Float(y)) => {
+                let value = match op {
+                    Op::Add(_) => x + y,
+                    Op::Sub(_) => x - y,
+                    Op::Mul(_) => x * y,
+                    Op::Div(_) => x / y,
+                    _ => unreachable!("{op:?} is not arithmetic"),
+                };
+                Ok(Value::Float(match num {
+                    Num::F32 => value as f32 as f64,
+                    _ => value,
+                }))
+            }
+            _ => Err(self.invalid("arithmetic on non-numbers")),
+        }
+    }
+}
+
+/// Evaluates a comparison instruction, or returns `None` if `a` and `b`
+/// cannot be compared.
+fn compare(op: Op, a: &Value, b: &Value) -> Option<bool> {
+    let unsigned = matches!(
+        op,
+        Op::Lt(num) | Op::Le(num) | Op::Gt(num) | Op::Ge(num)
+            if matches!(num, Num::U8 | Num::U16 | Num::U32 | Num::U64)
+    );
+    let ordering = match (a, b) {
+        (Value::Int(x), Value::Int(y)) if unsigned => (*x as u64).partial_cmp(&(*y as u64)),
+        (Value::Int(x), Value::Int(y)) => x.partial_cmp(y),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.partial_cmp(y),
+        (Value::Str(x), Value::Str(y)) => x.partial_cmp(y),
+        _ => return None,
+    };
+    let op = match op {
+        Op::Eq => BinOp::Eq,
+        Op::Ne => BinOp::Ne,
+        Op::Lt(_) => BinOp::Lt,
+        Op::Le(_) => BinOp::Le,
+        Op::Gt(_) => BinOp::Gt,
+        Op::Ge(_) => BinOp::Ge,
+        _ => unreachable!("{op:?} is not a comparison"),
+    };
+    Some(op.compare(ordering))
+}
@@ -0,0 +1,100 @@
+//! Runtime values.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use sema::types::IntTy;
+
+use crate::bytecode::Num;
+
+/// The storage of a boxed local, a global or a temporary. References point
+/// into cells, so `&x` sees later assignments to `x`.
+pub type Cell = Rc<RefCell<Value>>;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// Every integer type, stored as its bits; see [`wrap`].
+    Int(i64),
+    /// Every float type; `f16` and `f32` are rounded on each operation.
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Void,
+    Struct(Vec<Value>),
+    Variant(u16, Vec<Value>),
+    Ref(Place),
+    Function(u32),
+    /// What a slot holding a boxed local contains.
+    Cell(Cell),
+}
+
+/// A location that can be read, written or referenced: a cell, or a field
+/// nested in the struct or variant stored there.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub cell: Cell,
+    pub path: Vec<u16>,
+}
+
+impl Place {
+    pub fn new(value: Value) -> Self {
+        Self::of(Rc::new(RefCell::new(value)))
+    }
+
+    pub fn of(cell: Cell) -> Self {
+        Self {
+            cell,
+            path: Vec::new(),
+        }
+    }
+
+    /// The value stored here, or `None` if the path leads nowhere, which
+    /// only malformed bytecode can cause.
+    pub fn get(&self) -> Option<Value> {
+        let cell = self.cell.borrow();
+        let mut value = &*cell;
+        for &index in &self.path {
+            value = match value {
+                Value::Struct(fields) | Value::Variant(_, fields) => fields.get(index as usize)?,
+                _ => return None,
+            };
+        }
+        Some(value.clone())
+    }
+
+    /// Stores `new` here, returning `None` instead if the path leads
+    /// nowhere.
+    pub fn set(&self, new: Value) -> Option<()> {
+        let mut cell = self.cell.borrow_mut();
+        let mut value = &mut *cell;
+        for &index in &self.path {
+            value = match value {
+                Value::Struct(fields) | Value::Variant(_, fields) => {
+                    fields.get_mut(index as usize)?
+                }
+                _ => return None,
+            };
+        }
+        *value = new;
+        Some(())
+    }
+
+    pub fn field(mut self, index: u16) -> Place {
+        self.path.push(index);
+        self
+    }
+}
+
+/// [`ir::wrap`] for an integer [`Num`].
+pub fn wrap_num(value: i64, num: Num) -> i64 {
+    let ty = match num {
+        Num::I8 => IntTy::I8,
+        Num::I16 => IntTy::I16,
+        Num::I32 => IntTy::I32,
+        Num::U8 => IntTy::U8,
+        Num::U16 => IntTy::U16,
+        Num::U32 => IntTy::U32,
+        _ => return value,
+    };
+    ir::wrap(value, ty)
+}